@@ -1,25 +1,39 @@
 use super::auth_helpers;
+use super::clock::CLOCK_SKEW;
 use super::rule_engine::RuleEngine;
 use super::rule_structs::ObjectRuleInputBuilder;
 use super::rule_structs::RootRuleInputBuilder;
+use super::token_validation::{
+    check_issued_at, check_issuer, decode_claims, ReplayCache, TokenAudience, PROXY_AUDIENCE,
+    USER_AUDIENCE,
+};
 use crate::auth::rule_structs::BundleRuleInputBuilder;
 use crate::auth::rule_structs::PackageObjectRuleInputBuilder;
+use crate::caching::access_cache::{AccessCacheKey, CachedAccess};
 use crate::caching::cache::Cache;
 use crate::helpers::is_method_read;
+use crate::s3_frontend::utils::bucket_name::{key_to_path, strip_duplicated_bucket};
+use crate::structs::check_key_depth;
+use crate::structs::key_segments;
 use crate::structs::AccessKeyPermissions;
 use crate::structs::CheckAccessResult;
 use crate::structs::DbPermissionLevel;
 use crate::structs::Object;
 use crate::structs::ObjectType;
 use crate::structs::ObjectsState;
+use crate::structs::PolicyAction;
 use crate::structs::PubKey;
 use crate::structs::ResourceStates;
 use crate::structs::TypedId;
 use crate::structs::UserState;
+use crate::structs::MAX_HIERARCHY_DEPTH;
+use crate::CONFIG;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Result;
 use aruna_rust_api::api::storage::models::v2::DataClass;
+use aruna_rust_api::api::storage::models::v2::ResourceVariant;
+use chrono::Utc;
 use diesel_ulid::DieselUlid;
 use http::HeaderMap;
 use http::HeaderValue;
@@ -27,7 +41,7 @@ use http::Method;
 use jsonwebtoken::Algorithm;
 use jsonwebtoken::EncodingKey;
 use jsonwebtoken::Header;
-use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use jsonwebtoken::{decode_header, DecodingKey};
 use s3s::auth::Credentials;
 use s3s::path::S3Path;
 use s3s::s3_error;
@@ -35,19 +49,46 @@ use s3s::S3Error;
 use serde::Deserializer;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::ops::Add;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
 use std::time::SystemTime;
 use tracing::error;
 use tracing::trace;
 
+/// User attribute that allows token-scoped credentials (e.g. short-lived instrument
+/// credentials) to create projects via CreateBucket, personal credentials always can
+pub const CREATE_PROJECTS_ATTRIBUTE: &str = "app.aruna-storage.org/create-projects";
+
+/// Whether the credentials may create new projects
+pub fn may_create_projects(
+    key: &AccessKeyPermissions,
+    attributes: &HashMap<String, String>,
+) -> bool {
+    if key.is_service_account {
+        return false;
+    }
+    if let Some(session) = &key.session {
+        // Scoped sessions are restricted to existing resources
+        if session.scope.is_some() {
+            return false;
+        }
+        return session.parent_key == key.user_id.to_string()
+            || attributes
+                .get(CREATE_PROJECTS_ATTRIBUTE)
+                .is_some_and(|value| value == "true");
+    }
+    key.access_key == key.user_id.to_string()
+        || attributes
+            .get(CREATE_PROJECTS_ATTRIBUTE)
+            .is_some_and(|value| value == "true")
+}
+
 pub struct AuthHandler {
     cache: Arc<Cache>,
     self_id: DieselUlid,
     rule_engine: RuleEngine,
     encoding_key: (i32, EncodingKey),
+    replay_cache: ReplayCache,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,12 +97,18 @@ pub(crate) struct ArunaTokenClaims {
     sub: String, // User_ID / DataProxy_ID
     exp: usize,  // Expiration timestamp
     aud: String, // Valid audiences
+    // Issued at, set for all tokens signed by this proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iat: Option<usize>,
     // Token_ID; None if OIDC or DataProxy-DataProxy interaction ?
     #[serde(skip_serializing_if = "Option::is_none")]
     tid: Option<String>,
     // Intent: <endpoint-ulid>_<action>
     #[serde(skip_serializing_if = "Option::is_none")]
     it: Option<Intent>,
+    // Token id of DataProxy-DataProxy tokens, each token can only be used once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jti: Option<String>,
 }
 
 #[repr(u8)]
@@ -75,16 +122,21 @@ pub enum Action {
     DpExchange = 4,
 }
 
-impl From<u8> for Action {
+impl TryFrom<u8> for Action {
+    type Error = anyhow::Error;
+
     #[tracing::instrument(level = "trace", skip(input))]
-    fn from(input: u8) -> Self {
+    fn try_from(input: u8) -> Result<Self> {
         match input {
-            0 => Action::All,
-            1 => Action::CreateSecrets,
-            2 => Action::Impersonate,
-            3 => Action::FetchInfo,
-            4 => Action::DpExchange,
-            _ => panic!("Invalid action"),
+            0 => Ok(Action::All),
+            1 => Ok(Action::CreateSecrets),
+            2 => Ok(Action::Impersonate),
+            3 => Ok(Action::FetchInfo),
+            4 => Ok(Action::DpExchange),
+            _ => {
+                error!(action = input, "Invalid action");
+                bail!("Invalid action: {input}")
+            }
         }
     }
 }
@@ -95,6 +147,31 @@ pub struct Intent {
     pub action: Action,
 }
 
+impl FromStr for Intent {
+    type Err = anyhow::Error;
+
+    /// Parses `<endpoint-ulid>_<action>`, token claims are untrusted input
+    #[tracing::instrument(level = "trace", skip(input))]
+    fn from_str(input: &str) -> Result<Self> {
+        let Some((target, action)) = input.split_once('_') else {
+            error!("Intent without action");
+            bail!("Invalid intent: missing action")
+        };
+        let target = DieselUlid::from_str(target).map_err(|_| {
+            error!("Invalid intent target");
+            anyhow!("Invalid intent: target is not a ULID")
+        })?;
+        let action = u8::from_str(action).map_err(|_| {
+            error!("Invalid intent action");
+            anyhow!("Invalid intent: action is not a number")
+        })?;
+        Ok(Intent {
+            target,
+            action: Action::try_from(action)?,
+        })
+    }
+}
+
 impl Serialize for Intent {
     #[tracing::instrument(level = "trace", skip(self, serializer))]
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -113,15 +190,7 @@ impl<'de> Deserialize<'de> for Intent {
         D: Deserializer<'de>,
     {
         let temp = String::deserialize(deserializer)?;
-        let split = temp.split('_').collect::<Vec<&str>>();
-
-        Ok(Intent {
-            target: DieselUlid::from_str(split[0])
-                .map_err(|_| serde::de::Error::custom("Invalid UUID"))?,
-            action: u8::from_str(split[1])
-                .map_err(|_| serde::de::Error::custom("Invalid Action"))?
-                .into(),
-        })
+        Intent::from_str(&temp).map_err(serde::de::Error::custom)
     }
 }
 
@@ -150,32 +219,21 @@ impl AuthHandler {
             self_id,
             rule_engine: RuleEngine::new()?,
             encoding_key: (encoding_key_serial, encoding_key),
+            replay_cache: ReplayCache::default(),
         })
     }
 
+    pub fn rule_engine(&self) -> &RuleEngine {
+        &self.rule_engine
+    }
+
     #[tracing::instrument(level = "trace", skip(self, token))]
-    pub fn check_permissions(
+    pub async fn check_permissions(
         &self,
         token: &str,
     ) -> Result<(DieselUlid, Option<String>, PubKey), anyhow::Error> {
-        let kid = decode_header(token)?
-            .kid
-            .ok_or_else(|| anyhow!("Unspecified kid"))
-            .map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
-            })?;
-        let (pk, dec_key) = self
-            .cache
-            .get_pubkey(i32::from_str(&kid).map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
-            })?)
-            .map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
-            })?;
-        let claims = self.extract_claims(token, &dec_key)?;
+        let (pk, dec_key) = self.get_decoding_key(token).await?;
+        let claims = self.extract_claims(token, &dec_key, TokenAudience::User)?;
 
         if let Some(it) = claims.it {
             match it.action {
@@ -188,14 +246,7 @@ impl AuthHandler {
                         bail!("Token is not valid for this Dataproxy")
                     }
                 }
-                Action::DpExchange => {
-                    if it.target == self.self_id {
-                        Ok((DieselUlid::from_str(&claims.sub)?, None, pk))
-                    } else {
-                        error!("Token is not valid for this Dataproxy");
-                        bail!("Token is not valid for this Dataproxy")
-                    }
-                }
+                // DataProxy tokens are only accepted by `check_dataproxy_token`
                 _ => {
                     error!("Action not allowed for Dataproxy");
                     bail!("Action not allowed for Dataproxy")
@@ -214,25 +265,116 @@ impl AuthHandler {
         }
     }
 
+    /// Checks a DataProxy-DataProxy token, only tokens with a DpExchange intent
+    /// targeting this proxy, issued and signed by another proxy are accepted.
+    /// Each token can only be used once.
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn check_dataproxy_token(
+        &self,
+        token: &str,
+    ) -> Result<(DieselUlid, PubKey), anyhow::Error> {
+        let (pk, dec_key) = self.get_decoding_key(token).await?;
+        let claims = self.extract_claims(token, &dec_key, TokenAudience::Proxy)?;
+
+        match claims.it {
+            Some(Intent {
+                target,
+                action: Action::DpExchange,
+            }) if target == self.self_id && pk.is_proxy => {}
+            _ => {
+                error!("Token is not a valid DataProxy token for this Dataproxy");
+                bail!("Token is not a valid DataProxy token for this Dataproxy")
+            }
+        }
+        let endpoint_id = check_issuer(&claims.iss, &claims.sub, pk.endpoint_id)?;
+        let jti = claims.jti.as_deref().ok_or_else(|| {
+            error!("DataProxy token without token id");
+            anyhow!("DataProxy token without token id")
+        })?;
+        let now = CLOCK_SKEW
+            .now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        self.replay_cache
+            .check_and_insert(jti, claims.exp as u64, now, self.leeway())?;
+        Ok((endpoint_id, pk))
+    }
+
+    /// Checks a token for administrative proxy operations, only user tokens without a
+    /// restricting intent (or with `Action::All`) of configured proxy admins are accepted
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn check_admin_token(&self, token: &str) -> Result<DieselUlid, anyhow::Error> {
+        let (pk, dec_key) = self.get_decoding_key(token).await?;
+        let claims = self.extract_claims(token, &dec_key, TokenAudience::User)?;
+        if pk.is_proxy {
+            error!("Proxy token is not allowed for admin operations");
+            bail!("Proxy token is not allowed for admin operations")
+        }
+        if !matches!(
+            claims.it,
+            None | Some(Intent {
+                action: Action::All,
+                ..
+            })
+        ) {
+            error!("Token intent does not allow admin operations");
+            bail!("Token intent does not allow admin operations")
+        }
+        let user_id = DieselUlid::from_str(&claims.sub).map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        if !CONFIG.proxy.admin_ids.contains(&user_id) {
+            error!(?user_id, "User is not a proxy admin");
+            bail!("User is not a proxy admin")
+        }
+        Ok(user_id)
+    }
+
+    /// Tokens signed with a key rotated in since the last sync fetch the pubkeys
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    async fn get_decoding_key(&self, token: &str) -> Result<(PubKey, DecodingKey), anyhow::Error> {
+        let kid = decode_header(token)?
+            .kid
+            .ok_or_else(|| anyhow!("Unspecified kid"))
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        let (pk, dec_key) = self
+            .cache
+            .resolve_pubkey(i32::from_str(&kid).map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        Ok((pk, dec_key))
+    }
+
+    /// Leeway for token expiry, the configured leeway widened by the measured clock skew
+    fn leeway(&self) -> u64 {
+        CLOCK_SKEW.leeway_from(CONFIG.proxy.token_leeway)
+    }
+
     #[tracing::instrument(level = "trace", skip(self, token, dec_key))]
     pub(crate) fn extract_claims(
         &self,
         token: &str,
         dec_key: &DecodingKey,
+        audience: TokenAudience,
     ) -> Result<ArunaTokenClaims, anyhow::Error> {
-        let mut validation = Validation::new(Algorithm::EdDSA);
-        validation.set_audience(&["proxy"]);
-
-        let token = decode::<ArunaTokenClaims>(
-            token,
-            dec_key,
-            &validation, //&Validation::new(Algorithm::EdDSA)
-        )
-        .map_err(|e| {
-            tracing::error!(error = ?e, msg = e.to_string());
-            e
-        })?;
-        Ok(token.claims)
+        let leeway = self.leeway();
+        let claims: ArunaTokenClaims = decode_claims(token, dec_key, audience, leeway)?;
+        check_issued_at(
+            claims.iat.map(|iat| iat as u64),
+            CLOCK_SKEW.unix_now(),
+            leeway,
+        )?;
+        Ok(claims)
     }
 
     // ----------------- AUTHORIZATION -----------------
@@ -286,15 +428,13 @@ impl AuthHandler {
                         .build()
                         .map_err(|_| s3_error!(MalformedACLError, "Rule has wrong context"))?,
                 )
-                .map_err(|_| s3_error!(AccessDenied, "Forbidden by rule"))?;
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string(), "Error evaluating rules");
+                    s3_error!(AccessDenied, "Forbidden by rule")
+                })?;
 
-            if !result {
-                return Err(s3_error!(InvalidObjectState, "Forbidden by rule"));
-            }
-            return Ok(CheckAccessResult {
-                user_state: Some(a.clone()).into(),
-                ..Default::default()
-            });
+            result.into_s3_result()?;
+            return Ok(CheckAccessResult::root(Some(a.clone()).into()));
         }
         Err(s3_error!(AccessDenied, "Missing access key"))
     }
@@ -307,14 +447,6 @@ impl AuthHandler {
         creds: Option<&Credentials>,
         headers: &HeaderMap<HeaderValue>,
     ) -> Result<CheckAccessResult, S3Error> {
-        // TODO: Decide how to handle public bucket access
-        // Query the User -> Must exist
-        let (access_key_info, attributes) =
-            self.extract_access_key_perms(creds).await.ok_or_else(|| {
-                error!("No such user");
-                s3_error!(AccessDenied, "No such user")
-            })?;
-
         // Query the project and extract the headers
         let resource_states = self
             .prefix_into_resource_states(
@@ -323,55 +455,89 @@ impl AuthHandler {
             )
             .await?;
 
-        // Extract the permission level from the method READ == "GET" and friends, WRITE == "POST" and friends
-        // Check if the user has the required permissions
-
-        let cors_headers = if resource_states.get_project().is_none() {
-            if Method::GET == *method {
-                return Err(s3_error!(NoSuchBucket, "No such bucket"));
-            } else if access_key_info.is_service_account {
-                return Err(s3_error!(
-                    AccessDenied,
-                    "Service account not allowed to create buckets"
-                ));
+        let mut rule_builder = ObjectRuleInputBuilder::new(&self.rule_engine)
+            .method(method)
+            .headers(headers)
+            .add_resource_states(&resource_states);
+
+        // Query the User -> Anonymous access is only allowed for public projects
+        let (user_state, cors_headers) = match self.extract_access_key_perms(creds).await {
+            Some((access_key_info, attributes)) => {
+                // Extract the permission level from the method READ == "GET" and friends, WRITE == "POST" and friends
+                // Check if the user has the required permissions
+                let cors_headers = if resource_states.get_project().is_none() {
+                    if Method::GET == *method {
+                        return Err(s3_error!(NoSuchBucket, "No such bucket"));
+                    } else if access_key_info.is_service_account {
+                        return Err(s3_error!(
+                            AccessDenied,
+                            "Service account not allowed to create buckets"
+                        ));
+                    } else if Method::PUT == *method
+                        && !may_create_projects(&access_key_info, &attributes)
+                    {
+                        error!("Credentials not allowed to create projects");
+                        return Err(s3_error!(
+                            AccessDenied,
+                            "Credentials not allowed to create buckets"
+                        ));
+                    }
+                    None
+                } else {
+                    resource_states.check_permissions(
+                        &access_key_info,
+                        DbPermissionLevel::from(method),
+                        is_method_read(method),
+                    )?;
+                    self.cache.get_cors_headers(
+                        &resource_states.require_project()?.id,
+                        method,
+                        headers,
+                    )
+                };
+                rule_builder = rule_builder
+                    .user_id(&access_key_info.user_id.to_string())
+                    .attributes(&attributes)
+                    .permissions(&access_key_info.permissions);
+                (Some(access_key_info).into(), cors_headers)
+            }
+            None => {
+                if !is_method_read(method) {
+                    error!("Missing access key");
+                    return Err(s3_error!(AccessDenied, "Missing access key"));
+                }
+                let Some(project) = resource_states.get_project() else {
+                    error!("No such bucket");
+                    return Err(s3_error!(NoSuchBucket, "No such bucket"));
+                };
+                if project.data_class != DataClass::Public {
+                    error!("Missing access key");
+                    return Err(s3_error!(AccessDenied, "Missing access key"));
+                }
+                (
+                    UserState::Anonymous,
+                    self.cache.get_cors_headers(&project.id, method, headers),
+                )
             }
-            None
-        } else {
-            resource_states.check_permissions(
-                &access_key_info,
-                DbPermissionLevel::from(method),
-                is_method_read(method),
-            )?;
-            resource_states
-                .require_project()?
-                .project_get_headers(method, headers)
         };
 
         let result = self
             .rule_engine
-            .evaluate_object(
-                ObjectRuleInputBuilder::new(&self.rule_engine)
-                    .user_id(&access_key_info.user_id.to_string())
-                    .attributes(&attributes)
-                    .method(method)
-                    .permissions(&access_key_info.permissions)
-                    .headers(headers)
-                    .add_resource_states(&resource_states)
-                    .build()
-                    .map_err(|e| {
-                        error!(error = ?e, msg = e.to_string(), "Error in building rule");
-                        s3_error!(MalformedACLError, "Rule has wrong context")
-                    })?,
-            )
-            .map_err(|_| s3_error!(AccessDenied, "Forbidden by rule"))?;
+            .evaluate_object(rule_builder.build().map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), "Error in building rule");
+                s3_error!(MalformedACLError, "Rule has wrong context")
+            })?)
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), "Error evaluating rules");
+                s3_error!(AccessDenied, "Forbidden by rule")
+            })?;
 
-        if !result {
-            return Err(s3_error!(InvalidObjectState, "Forbidden by rule"));
-        }
+        result.into_s3_result()?;
+        self.check_bucket_policy(&resource_states, &user_state, method, "")?;
 
         Ok(CheckAccessResult::new(
-            ObjectsState::new_regular(resource_states, None),
-            Some(access_key_info).into(),
+            ObjectsState::new_bucket(resource_states),
+            user_state,
             cors_headers,
         ))
     }
@@ -390,6 +556,12 @@ impl AuthHandler {
                 if !is_method_read(method) {
                     return Err(s3_error!(MethodNotAllowed, "Method not allowed"));
                 }
+                // Plain ULIDs address a single object directly
+                if !key_name.contains('/') {
+                    return self
+                        .handle_special_objects(key_name, method, creds, headers)
+                        .await;
+                }
                 return self.handle_package_objects(key_name, creds, headers).await;
             }
             "bundles" => {
@@ -401,47 +573,273 @@ impl AuthHandler {
             _ => {}
         }
 
-        let path = format!("{bucket_name}/{key_name}");
-        let prefix: Vec<(String, String)> = auth_helpers::key_into_prefix(&path)?;
-        trace!(?prefix, "Prefix");
-        let resource_states = self.prefix_into_resource_states(&prefix, false).await?;
+        // Reads of the same path with the same credentials are memoized, rules and
+        // CORS headers depend on the request and are evaluated every time
+        let memo_key = is_method_read(method).then(|| {
+            AccessCacheKey::new(
+                creds.map(|c| c.access_key.as_str()),
+                method,
+                bucket_name,
+                key_name,
+            )
+        });
+        let generation = self.cache.access_cache.generation();
+        let cached = memo_key
+            .as_ref()
+            .and_then(|key| self.cache.access_cache.get(key));
+
+        let CachedAccess {
+            resource_states,
+            user,
+        } = match cached {
+            Some(cached) => {
+                trace!("using memoized access check");
+                cached
+            }
+            None => {
+                let access = self
+                    .check_object_access(bucket_name, key_name, method, creds)
+                    .await?;
+                if let Some(memo_key) = memo_key {
+                    self.cache
+                        .access_cache
+                        .insert(memo_key, access.clone(), generation);
+                }
+                access
+            }
+        };
+
+        let cors_headers =
+            self.cache
+                .get_cors_headers(&resource_states.require_project()?.id, method, headers);
+
+        let mut rule_builder = ObjectRuleInputBuilder::new(&self.rule_engine)
+            .method(method)
+            .headers(headers)
+            .key(key_name)
+            .add_resource_states(&resource_states);
+
+        let user_state: UserState = match user {
+            Some((user, attributes)) => {
+                rule_builder = rule_builder
+                    .attributes(&attributes)
+                    .user_id(&user.user_id.to_string())
+                    .permissions(&user.permissions);
+                Some(user).into()
+            }
+            None => UserState::Anonymous,
+        };
+
+        let result = self
+            .rule_engine
+            .evaluate_object(rule_builder.build().map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                s3_error!(MalformedACLError, "Rule has wrong context")
+            })?)
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), "Error evaluating rules");
+                s3_error!(AccessDenied, "Forbidden by rule")
+            })?;
+
+        result.into_s3_result()?;
+        self.check_bucket_policy(&resource_states, &user_state, method, key_name)?;
+
+        let location = if let Some(obj) = resource_states.get_object() {
+            self.cache.lookup_location(&obj.id).await
+        } else {
+            None
+        };
+
+        Ok(CheckAccessResult::new(
+            ObjectsState::new_object(resource_states, location),
+            user_state,
+            cors_headers,
+        ))
+    }
+
+    /// Deny statements of the compiled bucket policy of the project
+    fn check_bucket_policy(
+        &self,
+        resource_states: &ResourceStates,
+        user_state: &UserState,
+        method: &Method,
+        key: &str,
+    ) -> Result<(), S3Error> {
+        let Some(policy) = resource_states
+            .get_project()
+            .and_then(|project| self.cache.get_bucket_policy(&project.id))
+        else {
+            return Ok(());
+        };
+        let action = if is_method_read(method) {
+            PolicyAction::Read
+        } else {
+            PolicyAction::Write
+        };
+        policy.check(user_state.get_user_id().as_ref(), action, key)
+    }
+
+    /// Resolves the path and checks the permissions of the credentials,
+    /// the request independent part of `handle_object`
+    #[tracing::instrument(level = "trace", skip(self, bucket_name, key_name, creds))]
+    async fn check_object_access(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        method: &Method,
+        creds: Option<&Credentials>,
+    ) -> Result<CachedAccess, S3Error> {
+        let resource_states = self.key_into_resource_states(bucket_name, key_name).await?;
 
         if is_method_read(method) {
             // Fail if the object has missing parts
-            resource_states.disallow_missing()?;
+            resource_states.disallow_missing().map_err(|e| {
+                self.duplicated_bucket_hint(bucket_name, key_name)
+                    .unwrap_or(e)
+            })?;
         }
 
         // Fail if the object is partially synced
         resource_states.fail_partial_sync(&self.self_id)?;
 
-        let cors_headers = resource_states
-            .require_project()?
-            .project_get_headers(method, headers);
+        // Query the User
+        let user = self.extract_access_key_perms(creds).await;
+        match &user {
+            Some((user, _)) => resource_states.check_permissions(
+                user,
+                DbPermissionLevel::from(method),
+                is_method_read(method),
+            )?,
+            None => {
+                // Reads of collections and datasets (archives) are checked on the resource itself
+                let resource = match resource_states.get_object() {
+                    Some(object) => object,
+                    None if is_method_read(method) => resource_states
+                        .get_dataset()
+                        .or(resource_states.get_collection())
+                        .ok_or_else(|| {
+                            error!("Object not found");
+                            s3_error!(NoSuchKey, "Object not found")
+                        })?,
+                    None => resource_states.require_object()?,
+                };
+                if resource.data_class != DataClass::Public {
+                    return Err(s3_error!(AccessDenied, "Missing access key"));
+                }
+            }
+        }
+
+        Ok(CachedAccess {
+            resource_states,
+            user,
+        })
+    }
+
+    /// Keys are relative to the bucket, points keys that repeat the bucket name
+    /// to the existing bucket relative key
+    fn duplicated_bucket_hint(&self, bucket_name: &str, key_name: &str) -> Option<S3Error> {
+        let key = strip_duplicated_bucket(bucket_name, key_name)?;
+        self.cache.get_path(&key_to_path(bucket_name, key))?;
+        error!(bucket_name, key_name, "Key repeats the bucket name");
+        Some(s3_error!(
+            NoSuchKey,
+            "Resource not found, keys are relative to the bucket: use key {key} instead of {key_name}"
+        ))
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, key_name, creds, headers))]
+    pub async fn handle_special_objects(
+        &self,
+        key_name: &str,
+        method: &Method,
+        creds: Option<&Credentials>,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<CheckAccessResult, S3Error> {
+        // Extract the object id
+        let object_id = DieselUlid::from_str(key_name).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            s3_error!(NoSuchKey, "No such object")
+        })?;
+        // Query the object and its location
+        let (object, location) = self
+            .cache
+            .get_resource_cloned(&object_id, false)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                s3_error!(NoSuchKey, "No such object")
+            })?;
+        if object.object_type != ObjectType::Object {
+            error!("Resource is not an object");
+            return Err(s3_error!(NoSuchKey, "No such object"));
+        }
+
+        // Reconstruct the hierarchy of the object
+        let mut resource_states = ResourceStates::default();
+        let hierarchy = self
+            .cache
+            .get_single_parent(&object_id)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                s3_error!(NoSuchKey, "No such object")
+            })?;
+        for (id, _) in hierarchy.iter().take(3).flatten() {
+            let (parent, _) = self
+                .cache
+                .get_resource_cloned(id, true)
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    s3_error!(NoSuchKey, "No such object")
+                })?;
+            match parent.object_type {
+                ObjectType::Project => resource_states.set_project(parent),
+                ObjectType::Collection => resource_states.set_collection(parent),
+                ObjectType::Dataset => resource_states.set_dataset(parent),
+                ObjectType::Object => {
+                    error!("Object cannot be a parent");
+                    return Err(s3_error!(InternalError, "Internal Error"));
+                }
+            }
+        }
+        let is_public = object.data_class == DataClass::Public;
+        resource_states.set_object(object);
+
+        // Fail if the object is partially synced
+        resource_states.fail_partial_sync(&self.self_id)?;
+
+        let cors_headers =
+            self.cache
+                .get_cors_headers(&resource_states.require_project()?.id, method, headers);
 
         let mut rule_builder = ObjectRuleInputBuilder::new(&self.rule_engine)
             .method(method)
             .headers(headers)
             .add_resource_states(&resource_states);
 
-        // Query the User
         let user_state: UserState =
             if let Some((user, attributes)) = self.extract_access_key_perms(creds).await {
-                resource_states.check_permissions(
-                    &user,
-                    DbPermissionLevel::from(method),
-                    is_method_read(method),
-                )?;
+                if !is_public {
+                    // Any parent with sufficient permissions grants access
+                    let mut parents = self.get_parents(&object_id).await;
+                    parents.push(TypedId::Object(object_id));
+                    self.check_permission_list(
+                        &parents,
+                        user.permissions.clone(),
+                        DbPermissionLevel::Read,
+                    )
+                    .await?;
+                }
                 rule_builder = rule_builder
                     .attributes(&attributes)
                     .user_id(&user.user_id.to_string())
                     .permissions(&user.permissions);
                 Some(user).into()
+            } else if is_public {
+                UserState::Anonymous
             } else {
-                if resource_states.require_object()?.data_class == DataClass::Public {
-                    UserState::Anonymous
-                } else {
-                    return Err(s3_error!(AccessDenied, "Missing access key"));
-                }
+                return Err(s3_error!(AccessDenied, "Missing access key"));
             };
 
         let result = self
@@ -450,20 +848,15 @@ impl AuthHandler {
                 error!(error = ?e, msg = e.to_string());
                 s3_error!(MalformedACLError, "Rule has wrong context")
             })?)
-            .map_err(|_| s3_error!(AccessDenied, "Forbidden by rule"))?;
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), "Error evaluating rules");
+                s3_error!(AccessDenied, "Forbidden by rule")
+            })?;
 
-        if !result {
-            return Err(s3_error!(InvalidObjectState, "Forbidden by rule"));
-        }
-
-        let location = if let Some(obj) = resource_states.get_object() {
-            self.cache.get_location(&obj.id).await
-        } else {
-            None
-        };
+        result.into_s3_result()?;
 
         Ok(CheckAccessResult::new(
-            ObjectsState::new_regular(resource_states, location),
+            ObjectsState::new_special_object(resource_states, location),
             user_state,
             cors_headers,
         ))
@@ -531,10 +924,12 @@ impl AuthHandler {
                     .build()
                     .map_err(|_| s3_error!(MalformedACLError, "Rule has wrong context"))?,
             )
-            .map_err(|_| s3_error!(AccessDenied, "Forbidden by rule"))?;
-        if !result {
-            return Err(s3_error!(InvalidObjectState, "Forbidden by rule"));
-        }
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), "Error evaluating rules");
+                s3_error!(AccessDenied, "Forbidden by rule")
+            })?;
+
+        result.into_s3_result()?;
 
         Ok(CheckAccessResult::new(objects_state, user, None))
     }
@@ -562,22 +957,72 @@ impl AuthHandler {
             s3_error!(NoSuchKey, "No such object")
         })?;
 
+        // Expired bundles are treated as non-existent
+        if let Some(expires_at) = bundle.expires_at {
+            if expires_at < Utc::now() {
+                error!("Bundle expired");
+                self.cache.delete_bundle(&bundle_id);
+                return Err(s3_error!(NoSuchKey, "No such object"));
+            }
+        }
+
+        // Query all objects referenced by the bundle
+        let mut objects = Vec::with_capacity(bundle.ids.len());
+        for id in bundle.ids.iter() {
+            let (object, _) = self
+                .cache
+                .get_resource_cloned(id, true)
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    s3_error!(NoSuchKey, "No such object")
+                })?;
+            objects.push(object);
+        }
+
         let mut rule_builder = BundleRuleInputBuilder::new(&self.rule_engine)
             .method(&Method::GET)
             .headers(headers)
+            .objects(&objects)
             .bundle(&bundle);
 
-        let object_state = ObjectsState::new_bundle(bundle, path.to_string());
-
         let user = match self.extract_access_key_perms(creds).await {
             Some((user, attributes)) => {
+                // The user needs READ on every non-public object of a credentialed bundle
+                for object in objects.iter() {
+                    if bundle.public || object.data_class == DataClass::Public {
+                        continue;
+                    }
+                    let mut parents = self.get_parents(&object.id).await;
+                    parents.push(TypedId::Object(object.id));
+                    self.check_permission_list(
+                        &parents,
+                        user.permissions.clone(),
+                        DbPermissionLevel::Read,
+                    )
+                    .await?;
+                }
                 rule_builder = rule_builder
+                    .user_id(&user.user_id.to_string())
                     .attributes(&attributes)
                     .permissions(&user.permissions);
                 Some(user).into()
             }
-            None => UserState::Anonymous,
+            None => {
+                // Anonymous access is only possible for public bundles or if all objects are public
+                if !bundle.public
+                    && !objects
+                        .iter()
+                        .all(|object| object.data_class == DataClass::Public)
+                {
+                    error!("Missing access key for non-public bundle");
+                    return Err(s3_error!(AccessDenied, "Missing access key"));
+                }
+                UserState::Anonymous
+            }
         };
+
+        let object_state = ObjectsState::new_bundle(bundle, path.to_string());
         let result = self
             .rule_engine
             .evaluate_bundle(
@@ -585,11 +1030,12 @@ impl AuthHandler {
                     .build()
                     .map_err(|_| s3_error!(MalformedACLError, "Rule has wrong context"))?,
             )
-            .map_err(|_| s3_error!(AccessDenied, "Forbidden by rule"))?;
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), "Error evaluating rules");
+                s3_error!(AccessDenied, "Forbidden by rule")
+            })?;
 
-        if !result {
-            return Err(s3_error!(InvalidObjectState, "Forbidden by rule"));
-        }
+        result.into_s3_result()?;
 
         Ok(CheckAccessResult::new(object_state, user, None))
     }
@@ -619,7 +1065,7 @@ impl AuthHandler {
         headers: &HeaderMap<HeaderValue>,
     ) -> Option<(Object, Option<HashMap<String, String>>)> {
         let project = self.cache.get_full_resource_by_path(bucket).await?;
-        let headers = project.project_get_headers(method, headers);
+        let headers = self.cache.get_cors_headers(&project.id, method, headers);
         Some((project, headers))
     }
 
@@ -629,19 +1075,20 @@ impl AuthHandler {
         user_id: impl Into<String>,
         tid: Option<impl Into<String>>,
     ) -> Result<String, anyhow::Error> {
+        // Corrected by the measured skew so that the server accepts the token
+        let now = CLOCK_SKEW.unix_now();
         let claims = ArunaTokenClaims {
             iss: self.self_id.to_string(),
             sub: user_id.into(),
-            aud: "aruna".to_string(),
-            exp: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .add(Duration::from_secs(15 * 60))
-                .as_secs() as usize,
+            aud: USER_AUDIENCE.to_string(),
+            exp: (now + 15 * 60) as usize,
+            iat: Some(now as usize),
             tid: tid.map(|x| x.into()),
             it: Some(Intent {
                 target: self.self_id,
                 action: Action::Impersonate,
             }),
+            jti: None,
         };
 
         self.sign_token(claims).map_err(|e| {
@@ -652,19 +1099,20 @@ impl AuthHandler {
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub(crate) fn sign_notification_token(&self) -> Result<String, anyhow::Error> {
+        // Corrected by the measured skew so that the server accepts the token
+        let now = CLOCK_SKEW.unix_now();
         let claims = ArunaTokenClaims {
             iss: self.self_id.to_string(),
             sub: self.self_id.to_string(),
-            aud: "aruna".to_string(),
-            exp: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .add(Duration::from_secs(60 * 60 * 24 * 365 * 10))
-                .as_secs() as usize,
+            aud: USER_AUDIENCE.to_string(),
+            exp: (now + 60 * 60 * 24 * 365 * 10) as usize,
+            iat: Some(now as usize),
             tid: None,
             it: Some(Intent {
                 target: self.self_id,
                 action: Action::FetchInfo,
             }),
+            jti: None,
         };
 
         self.sign_token(claims).map_err(|e| {
@@ -678,19 +1126,20 @@ impl AuthHandler {
         &self,
         target_endpoint: DieselUlid,
     ) -> Result<String, anyhow::Error> {
+        // Corrected by the measured skew so that the server accepts the token
+        let now = CLOCK_SKEW.unix_now();
         let claims = ArunaTokenClaims {
             iss: self.self_id.to_string(),
             sub: self.self_id.to_string(),
-            aud: "proxy".to_string(),
-            exp: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .add(Duration::from_secs(15 * 60))
-                .as_secs() as usize,
+            aud: PROXY_AUDIENCE.to_string(),
+            exp: (now + 15 * 60) as usize,
+            iat: Some(now as usize),
             tid: None,
             it: Some(Intent {
                 target: target_endpoint,
                 action: Action::DpExchange,
             }),
+            jti: Some(DieselUlid::generate().to_string()),
         };
 
         self.sign_token(claims).map_err(|e| {
@@ -722,6 +1171,9 @@ impl AuthHandler {
     ) -> Result<ResourceStates, S3Error> {
         let mut resource_states: ResourceStates = ResourceStates::default();
         let len = prefixes.len();
+        if len > MAX_HIERARCHY_DEPTH {
+            check_key_depth(len - 1, MAX_HIERARCHY_DEPTH - 2)?;
+        }
         for (idx, (prefix, name)) in prefixes.iter().enumerate() {
             let Some(obj) = self.cache.get_full_resource_by_path(prefix).await else {
                 resource_states
@@ -753,7 +1205,123 @@ impl AuthHandler {
         }
         resource_states.validate(allow_create).map_err(|e| {
             error!(error = ?e, msg = e.to_string());
-            s3_error!(InternalError, "Internal Error")
+            s3_error!(
+                InvalidArgument,
+                "Path does not map to a valid hierarchy: {e}"
+            )
+        })?;
+        Ok(resource_states)
+    }
+
+    /// Resolves an object key against the existing hierarchy of the bucket
+    ///
+    /// Existing resources define the depth of the key: an existing collection is
+    /// followed by a dataset or the object, an existing dataset always by the object.
+    /// Missing segments become a collection and/or dataset (or, with `flatten_keys`,
+    /// part of the object name) and all segments that do not fit into the hierarchy
+    /// are kept in the object name, e.g. `dataset/sub/dir/file` -> object `sub/dir/file`.
+    /// Directory markers (`dir/`) resolve to the collection or dataset of the directory,
+    /// markers below a dataset are part of object names and resolve to no resource.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn key_into_resource_states(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ResourceStates, S3Error> {
+        let mut resource_states = ResourceStates::default();
+        let Some(project) = self.cache.get_full_resource_by_path(bucket).await else {
+            error!("No such bucket");
+            return Err(s3_error!(NoSuchBucket, "Bucket not found"));
+        };
+        resource_states.set_project(project);
+
+        let (flatten_keys, collapse_deep_keys) = CONFIG
+            .frontend
+            .as_ref()
+            .map(|frontend| (frontend.flatten_keys, frontend.collapse_deep_keys))
+            .unwrap_or((false, true));
+        let (key, marker) = match key.strip_suffix('/') {
+            Some(directory) => (directory, true),
+            None => (key, false),
+        };
+        let segments = key_segments(key)?;
+        // Markers are resolved like the key of an object in the directory
+        let depth = segments.len() + usize::from(marker);
+        let mut prefix = bucket.to_string();
+        // Deepest hierarchy level set so far: 0 = project, 1 = collection, 2 = dataset
+        let mut level = 0;
+        let mut idx = 0;
+        while idx + 1 < depth && level < 2 {
+            let remaining = depth - idx;
+            let path = format!("{prefix}/{}", segments[idx]);
+            match self.cache.get_full_resource_by_path(&path).await {
+                Some(obj) if obj.object_type == ObjectType::Collection && level == 0 => {
+                    resource_states.set_collection(obj);
+                    level = 1;
+                }
+                Some(obj) if obj.object_type == ObjectType::Dataset => {
+                    resource_states.set_dataset(obj);
+                    level = 2;
+                }
+                // Existing objects can not have children, the rest of the key is the object name
+                Some(_) => break,
+                None if flatten_keys => break,
+                None => {
+                    let variant = if level == 0 && remaining > 2 {
+                        level = 1;
+                        ResourceVariant::Collection
+                    } else {
+                        level = 2;
+                        ResourceVariant::Dataset
+                    };
+                    resource_states
+                        .set_missing_variant(segments[idx].to_string(), variant)
+                        .map_err(|e| {
+                            error!(error = ?e, msg = e.to_string());
+                            s3_error!(InternalError, "Internal Error")
+                        })?;
+                }
+            }
+            prefix = path;
+            idx += 1;
+        }
+
+        if marker {
+            if !collapse_deep_keys && !flatten_keys && idx < segments.len() {
+                check_key_depth(depth, idx)?;
+            }
+        } else {
+            if !collapse_deep_keys && !flatten_keys {
+                check_key_depth(segments.len(), idx)?;
+            }
+            let name = segments[idx..].join("/");
+            match self
+                .cache
+                .get_full_resource_by_path(&format!("{prefix}/{name}"))
+                .await
+            {
+                Some(obj) => match obj.object_type {
+                    ObjectType::Object => resource_states.set_object(obj),
+                    ObjectType::Dataset => resource_states.set_dataset(obj),
+                    ObjectType::Collection => resource_states.set_collection(obj),
+                    ObjectType::Project => {}
+                },
+                None => resource_states
+                    .set_missing_variant(name, ResourceVariant::Object)
+                    .map_err(|e| {
+                        error!(error = ?e, msg = e.to_string());
+                        s3_error!(InternalError, "Internal Error")
+                    })?,
+            }
+        }
+        trace!(?resource_states);
+
+        resource_states.validate(false).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            s3_error!(
+                InvalidArgument,
+                "key {key} does not map to a valid hierarchy: {e}"
+            )
         })?;
         Ok(resource_states)
     }
@@ -808,3 +1376,100 @@ impl AuthHandler {
         Ok(objects)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims_with_intent(it: serde_json::Value) -> serde_json::Result<ArunaTokenClaims> {
+        serde_json::from_value(serde_json::json!({
+            "iss": "aruna",
+            "sub": DieselUlid::generate().to_string(),
+            "exp": 0,
+            "aud": USER_AUDIENCE,
+            "it": it,
+        }))
+    }
+
+    #[test]
+    fn test_action_from_u8() {
+        for action in [
+            Action::All,
+            Action::CreateSecrets,
+            Action::Impersonate,
+            Action::FetchInfo,
+            Action::DpExchange,
+        ] {
+            assert_eq!(Action::try_from(action.clone() as u8).unwrap(), action);
+        }
+        for invalid in [5, 42, u8::MAX] {
+            assert!(Action::try_from(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_intent_roundtrip() {
+        let intent = Intent {
+            target: DieselUlid::generate(),
+            action: Action::DpExchange,
+        };
+        let json = serde_json::to_value(&intent).unwrap();
+        assert_eq!(json, format!("{}_4", intent.target));
+        let parsed: Intent = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.target, intent.target);
+        assert_eq!(parsed.action, Action::DpExchange);
+    }
+
+    #[test]
+    fn test_malformed_intents() {
+        let target = DieselUlid::generate();
+        for intent in [
+            String::new(),
+            "_".to_string(),
+            "notanintent".to_string(),
+            target.to_string(),
+            format!("{target}_"),
+            format!("_{}", Action::All as u8),
+            format!("{target}_5"),
+            format!("{target}_255"),
+            format!("{target}_256"),
+            format!("{target}_-1"),
+            format!("{target}_1_2"),
+            format!("{target}__1"),
+            format!("{target}_ 1"),
+            format!("not-a-ulid_1"),
+            "🦀_🦀".to_string(),
+        ] {
+            assert!(Intent::from_str(&intent).is_err(), "{intent}");
+            assert!(
+                claims_with_intent(intent.clone().into()).is_err(),
+                "{intent}"
+            );
+        }
+        // Intents of other types are rejected as well
+        assert!(claims_with_intent(serde_json::json!(4)).is_err());
+        assert!(claims_with_intent(serde_json::json!([target.to_string(), 4])).is_err());
+        assert!(claims_with_intent(serde_json::Value::Null)
+            .unwrap()
+            .it
+            .is_none());
+    }
+
+    #[test]
+    fn test_malformed_intent_in_token() {
+        let key = EncodingKey::from_ed_pem(include_bytes!("../../tests/test.pem")).unwrap();
+        let dec_key = DecodingKey::from_ed_pem(include_bytes!("../../tests/test.pub")).unwrap();
+        let claims = serde_json::json!({
+            "iss": "aruna",
+            "sub": DieselUlid::generate().to_string(),
+            "exp": Utc::now().timestamp() + 600,
+            "aud": USER_AUDIENCE,
+            "it": format!("{}_{}", DieselUlid::generate(), 200),
+        });
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::EdDSA), &claims, &key).unwrap();
+        // Decoding fails instead of panicking
+        assert!(
+            decode_claims::<ArunaTokenClaims>(&token, &dec_key, TokenAudience::User, 60).is_err()
+        );
+    }
+}
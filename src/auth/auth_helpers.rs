@@ -7,23 +7,6 @@ use tracing::error;
 
 use crate::structs::{AccessKeyPermissions, DbPermissionLevel, Object};
 
-/// Creates a list of tuples with the prefix and the object name
-#[tracing::instrument(level = "trace", skip(key))]
-pub(super) fn key_into_prefix(key: &str) -> Result<Vec<(String, String)>, S3Error> {
-    let mut parts = Vec::new();
-    let mut prefix = String::new();
-    for s in key.splitn(4, "/") {
-        prefix.push_str(s);
-        parts.push((prefix.clone(), s.to_string()));
-        prefix.push('/');
-    }
-    if parts.len() > 4 {
-        error!("This should not happen: Detected more than 4 Objects in the path");
-        return Err(s3_error!(InternalError, "Invalid key parsing"));
-    }
-    Ok(parts)
-}
-
 #[tracing::instrument(level = "trace", skip(key_info, resource_id, perm))]
 pub(super) fn check_permissions(
     key_info: &AccessKeyPermissions,
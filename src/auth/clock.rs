@@ -0,0 +1,251 @@
+use crate::metrics::CLOCK_SKEW_SECONDS;
+use crate::scoped_config;
+use chrono::DateTime;
+use dashmap::DashMap;
+use std::ops::Deref;
+use std::time::{Duration, SystemTime};
+use tracing::{info, trace, warn};
+
+/// Default leeway for token validation (same as jsonwebtoken)
+pub const DEFAULT_LEEWAY_SECS: u64 = 60;
+/// Upper bound for the dynamically widened leeway
+pub const MAX_LEEWAY_SECS: u64 = 600;
+/// Skew above this threshold is considered excessive
+pub const SKEW_WARN_THRESHOLD_SECS: u64 = 30;
+/// Origin of the server clock, tokens are signed and validated against it
+pub const ARUNA_ORIGIN: &str = "aruna";
+/// Origin of the storage backend clock, taken from `Date` headers of backend responses
+pub const BACKEND_ORIGIN: &str = "backend";
+/// Origins that issue tokens, only their skew widens the token leeway
+const TOKEN_ISSUER_ORIGINS: &[&str] = &[ARUNA_ORIGIN];
+
+/// Clock skew measured by the dataproxy of the current task
+pub struct CurrentClockSkew;
+
+pub static CLOCK_SKEW: CurrentClockSkew = CurrentClockSkew;
+
+impl Deref for CurrentClockSkew {
+    type Target = ClockSkew;
+
+    fn deref(&self) -> &ClockSkew {
+        &scoped_config::state().clock
+    }
+}
+
+/// Source of the local time, replaceable to simulate drifting clocks
+pub trait ClockSource: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Tracks the measured skew between the local clock and remote peers
+/// (aruna server, storage backend) in seconds (remote - local)
+pub struct ClockSkew {
+    source: Box<dyn ClockSource>,
+    skews: DashMap<String, i64>,
+}
+
+impl ClockSkew {
+    pub fn new(source: Box<dyn ClockSource>) -> Self {
+        ClockSkew {
+            source,
+            skews: DashMap::new(),
+        }
+    }
+
+    /// Records a remote timestamp and updates the measured skew of the origin
+    #[tracing::instrument(level = "trace", skip(self, remote))]
+    pub fn record(&self, remote: SystemTime, origin: &str) {
+        let local = self.source.now();
+        let skew = match remote.duration_since(local) {
+            Ok(ahead) => ahead.as_secs() as i64,
+            Err(behind) => -(behind.duration().as_secs() as i64),
+        };
+        CLOCK_SKEW_SECONDS.with_label_values(&[origin]).set(skew);
+        let previous = self.skews.insert(origin.to_string(), skew);
+        // Backend responses are frequent, only changes are logged
+        if previous == Some(skew) {
+            trace!(clock_skew_seconds = skew, origin);
+        } else if skew.unsigned_abs() > SKEW_WARN_THRESHOLD_SECS {
+            warn!(
+                clock_skew_seconds = skew,
+                origin,
+                "Local clock differs significantly from remote clock, check time synchronization"
+            );
+        } else if previous.is_some_and(|p| p.unsigned_abs() > SKEW_WARN_THRESHOLD_SECS) {
+            info!(
+                clock_skew_seconds = skew,
+                origin, "Clock skew is back to normal"
+            );
+        } else {
+            trace!(clock_skew_seconds = skew, origin);
+        }
+    }
+
+    /// Records the value of a http `Date` header
+    pub fn record_date_header(&self, value: &str, origin: &str) {
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            self.record(SystemTime::from(date), origin);
+        }
+    }
+
+    /// Skew of the server clock, 0 until it is measured
+    pub fn skew_secs(&self) -> i64 {
+        self.origin_skew(ARUNA_ORIGIN).unwrap_or_default()
+    }
+
+    pub fn origin_skew(&self, origin: &str) -> Option<i64> {
+        self.skews.get(origin).map(|skew| *skew)
+    }
+
+    /// Largest absolute skew over all origins
+    fn max_skew_secs(&self) -> u64 {
+        self.skews
+            .iter()
+            .map(|skew| skew.value().unsigned_abs())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Largest absolute skew of the token issuers, the backend clock does not
+    /// affect which tokens are accepted
+    fn max_issuer_skew_secs(&self) -> u64 {
+        TOKEN_ISSUER_ORIGINS
+            .iter()
+            .filter_map(|origin| self.origin_skew(origin))
+            .map(i64::unsigned_abs)
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn is_excessive(&self) -> bool {
+        self.max_skew_secs() > SKEW_WARN_THRESHOLD_SECS
+    }
+
+    /// Origins with excessive skew, reported as not-ready reasons
+    pub fn excessive_origins(&self) -> Vec<(String, i64)> {
+        let mut origins = self
+            .skews
+            .iter()
+            .filter(|skew| skew.value().unsigned_abs() > SKEW_WARN_THRESHOLD_SECS)
+            .map(|skew| (skew.key().clone(), *skew.value()))
+            .collect::<Vec<_>>();
+        origins.sort();
+        origins
+    }
+
+    /// Leeway for token validation widened by the skew of the token issuers (bounded)
+    pub fn leeway(&self) -> u64 {
+        self.leeway_from(DEFAULT_LEEWAY_SECS)
+    }
+
+    /// Like `leeway` with a configured base leeway
+    pub fn leeway_from(&self, base: u64) -> u64 {
+        (base + self.max_issuer_skew_secs()).min(MAX_LEEWAY_SECS.max(base))
+    }
+
+    /// Local time corrected by the measured skew, used for signing tokens
+    pub fn now(&self) -> SystemTime {
+        let now = self.source.now();
+        let skew = self.skew_secs();
+        if skew >= 0 {
+            now + Duration::from_secs(skew as u64)
+        } else {
+            now - Duration::from_secs(skew.unsigned_abs())
+        }
+    }
+
+    /// Corrected unix time in seconds, used for `iat` and `exp` of signed tokens
+    pub fn unix_now(&self) -> u64 {
+        self.now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    /// Local clock at a settable unix time
+    struct MockClock(Arc<AtomicU64>);
+
+    impl ClockSource for MockClock {
+        fn now(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH + Duration::from_secs(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    const REMOTE: u64 = 1_700_000_000;
+
+    fn skew_with_local(local: u64) -> (ClockSkew, Arc<AtomicU64>) {
+        let time = Arc::new(AtomicU64::new(local));
+        (ClockSkew::new(Box::new(MockClock(time.clone()))), time)
+    }
+
+    fn remote() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(REMOTE)
+    }
+
+    #[test]
+    fn test_leeway_is_widened_and_bounded() {
+        let (skew, local) = skew_with_local(REMOTE);
+        assert_eq!(skew.leeway(), DEFAULT_LEEWAY_SECS);
+
+        // Local clock is 2 minutes behind
+        local.store(REMOTE - 120, Ordering::Relaxed);
+        skew.record(remote(), ARUNA_ORIGIN);
+        assert_eq!(skew.skew_secs(), 120);
+        assert_eq!(skew.leeway(), DEFAULT_LEEWAY_SECS + 120);
+        assert_eq!(skew.leeway_from(10), 130);
+        assert_eq!(skew.unix_now(), REMOTE);
+
+        // 20 minutes ahead, bounded by the maximum leeway
+        local.store(REMOTE + 1200, Ordering::Relaxed);
+        skew.record(remote(), ARUNA_ORIGIN);
+        assert_eq!(skew.skew_secs(), -1200);
+        assert_eq!(skew.leeway(), MAX_LEEWAY_SECS);
+        assert_eq!(skew.unix_now(), REMOTE);
+        // A configured leeway above the bound is kept
+        assert_eq!(skew.leeway_from(900), 900);
+    }
+
+    #[test]
+    fn test_excessive_skew_per_origin() {
+        let (skew, local) = skew_with_local(REMOTE);
+        skew.record(remote(), ARUNA_ORIGIN);
+        skew.record(remote(), BACKEND_ORIGIN);
+        assert!(!skew.is_excessive());
+        assert!(skew.excessive_origins().is_empty());
+
+        // The backend clock neither moves the time used for signing nor the token leeway
+        local.store(REMOTE - 300, Ordering::Relaxed);
+        skew.record_date_header("Tue, 14 Nov 2023 22:13:20 GMT", BACKEND_ORIGIN);
+        assert_eq!(skew.origin_skew(BACKEND_ORIGIN), Some(300));
+        assert_eq!(skew.skew_secs(), 0);
+        assert!(skew.is_excessive());
+        assert_eq!(
+            skew.excessive_origins(),
+            vec![(BACKEND_ORIGIN.to_string(), 300)]
+        );
+        assert_eq!(skew.leeway(), DEFAULT_LEEWAY_SECS);
+
+        local.store(REMOTE + 5, Ordering::Relaxed);
+        skew.record(remote(), BACKEND_ORIGIN);
+        assert!(!skew.is_excessive());
+
+        // Skew of the issuer still widens it
+        skew.record(remote() + Duration::from_secs(40), ARUNA_ORIGIN);
+        assert_eq!(skew.leeway(), DEFAULT_LEEWAY_SECS + 35);
+    }
+}
@@ -1,5 +1,7 @@
 pub mod auth;
 pub mod auth_helpers;
+pub mod clock;
 pub mod crypto;
-mod rule_engine;
+pub mod rule_engine;
 mod rule_structs;
+pub mod token_validation;
@@ -1,4 +1,7 @@
+use crate::config::Rule;
 use crate::config::RuleTarget;
+use crate::config::RulesSource;
+use crate::metrics::RULE_DENIALS;
 use crate::CONFIG;
 use anyhow::anyhow;
 use anyhow::Result;
@@ -6,7 +9,10 @@ use cel_interpreter::Context;
 use cel_interpreter::ResolveResult;
 use cel_interpreter::Value;
 use cel_parser::ast::Expression;
-use tracing::error;
+use s3s::{s3_error, S3Error};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
 
 use super::rule_structs::BundleRuleInput;
 use super::rule_structs::ObjectRuleInput;
@@ -29,221 +35,296 @@ impl AddExpression for Expression {
     }
 }
 
-#[allow(dead_code)]
-pub(super) struct RuleEngine {
-    root: Vec<Expression>,
-    obj: Vec<Expression>,
-    bundle: Vec<Expression>,
-    obj_package: Vec<Expression>,
-    repl_in: Vec<Expression>,
-    repl_out: Vec<Expression>,
+const DEFAULT_DENY_MESSAGE: &str = "Forbidden by rule";
+
+#[derive(Debug, Clone)]
+pub struct LoadedRule {
+    pub id: String,
+    pub target: RuleTarget,
+    pub rule: String,
+    pub message: Option<String>,
+    pub dry_run: bool,
+    expression: Expression,
 }
 
-impl RuleEngine {
-    pub fn new() -> Result<Self> {
-        let mut root_expressions = vec![];
-        let mut object_expressions = vec![];
-        let mut bundle_expressions = vec![];
-        let mut object_package_expressions = vec![];
-        let mut replication_inbound_expressions = vec![];
-        let mut replication_outbound_expressions = vec![];
-        for rule in CONFIG.rules.iter() {
-            match rule.target {
-                RuleTarget::ROOT => {
-                    root_expressions.push(
-                        cel_parser::parse(rule.rule.as_str())
-                            .map_err(|e| anyhow!("error parsing rule: {}", e))?,
-                    );
-                }
-                RuleTarget::OBJECT => {
-                    object_expressions.push(
-                        cel_parser::parse(rule.rule.as_str())
-                            .map_err(|e| anyhow!("error parsing rule: {}", e))?,
-                    );
-                }
-                RuleTarget::BUNDLE => {
-                    bundle_expressions.push(
-                        cel_parser::parse(rule.rule.as_str())
-                            .map_err(|e| anyhow!("error parsing rule: {}", e))?,
-                    );
-                }
-                RuleTarget::OBJECTPACKAGE => {
-                    object_package_expressions.push(
-                        cel_parser::parse(rule.rule.as_str())
-                            .map_err(|e| anyhow!("error parsing rule: {}", e))?,
-                    );
-                }
-                RuleTarget::REPLICATIONIN => {
-                    replication_inbound_expressions.push(
-                        cel_parser::parse(rule.rule.as_str())
-                            .map_err(|e| anyhow!("error parsing rule: {}", e))?,
-                    );
-                }
-                RuleTarget::REPLICATIONOUT => {
-                    replication_outbound_expressions.push(
-                        cel_parser::parse(rule.rule.as_str())
-                            .map_err(|e| anyhow!("error parsing rule: {}", e))?,
-                    );
-                }
+impl LoadedRule {
+    fn compile(idx: usize, rule: &Rule) -> Result<Self> {
+        let id = rule
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("{:?}-{idx}", rule.target));
+        let expression = cel_parser::parse(rule.rule.as_str())
+            .map_err(|e| anyhow!("error parsing rule {id}: {}", e))?;
+        Ok(LoadedRule {
+            id,
+            target: rule.target,
+            rule: rule.rule.clone(),
+            message: rule.message.clone(),
+            dry_run: rule.dry_run,
+            expression,
+        })
+    }
+
+    fn compile_all(rules: &[Rule]) -> Result<Vec<Self>> {
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(idx, rule)| LoadedRule::compile(idx, rule))
+            .collect::<Result<Vec<_>>>()?;
+        for (idx, rule) in rules.iter().enumerate() {
+            if rules[..idx].iter().any(|other| other.id == rule.id) {
+                return Err(anyhow!("duplicate rule id: {}", rule.id));
             }
         }
+        Ok(rules)
+    }
+}
+
+/// Result of evaluating all rules of a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleDecision {
+    Allow,
+    Deny { rule_id: String, message: String },
+}
+
+impl RuleDecision {
+    pub fn into_s3_result(self) -> Result<(), S3Error> {
+        match self {
+            RuleDecision::Allow => Ok(()),
+            RuleDecision::Deny { rule_id, message } => {
+                error!(rule_id, message, "Forbidden by rule");
+                Err(s3_error!(InvalidObjectState, "{} ({})", message, rule_id))
+            }
+        }
+    }
+}
+
+pub struct RuleEngine {
+    rules: RwLock<Arc<Vec<LoadedRule>>>,
+}
+
+impl RuleEngine {
+    pub fn new() -> Result<Self> {
         Ok(RuleEngine {
-            root: root_expressions,
-            obj: object_expressions,
-            bundle: bundle_expressions,
-            obj_package: object_package_expressions,
-            repl_in: replication_inbound_expressions,
-            repl_out: replication_outbound_expressions,
+            rules: RwLock::new(Arc::new(LoadedRule::compile_all(&CONFIG.rules)?)),
         })
     }
 
+    /// Reloads all rules from the config file, the loaded rules stay active on errors
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn reload(&self) -> Result<Vec<LoadedRule>> {
+        let source = CONFIG.source.as_ref().ok_or_else(|| {
+            error!(error = "Config was not loaded from a file");
+            anyhow!("Config was not loaded from a file")
+        })?;
+        let rules = LoadedRule::compile_all(&RulesSource::from_file(source)?.rules)?;
+        info!(count = rules.len(), source, "Reloaded rules");
+        *self
+            .rules
+            .write()
+            .map_err(|_| anyhow!("Poisoned rule lock"))? = Arc::new(rules.clone());
+        Ok(rules)
+    }
+
+    pub fn rules(&self) -> Arc<Vec<LoadedRule>> {
+        self.rules
+            .read()
+            .map(|rules| rules.clone())
+            .unwrap_or_default()
+    }
+
+    fn has_target(&self, target: RuleTarget) -> bool {
+        self.rules().iter().any(|rule| rule.target == target)
+    }
+
     pub fn has_root(&self) -> bool {
-        !self.root.is_empty()
+        self.has_target(RuleTarget::ROOT)
     }
 
     pub fn has_object(&self) -> bool {
-        !self.obj.is_empty()
+        self.has_target(RuleTarget::OBJECT)
     }
 
     pub fn has_bundle(&self) -> bool {
-        !self.bundle.is_empty()
+        self.has_target(RuleTarget::BUNDLE)
     }
 
     pub fn has_object_package(&self) -> bool {
-        !self.obj_package.is_empty()
+        self.has_target(RuleTarget::OBJECTPACKAGE)
     }
 
     #[allow(dead_code)]
     pub fn has_replication_in(&self) -> bool {
-        !self.repl_in.is_empty()
+        self.has_target(RuleTarget::REPLICATIONIN)
     }
 
     #[allow(dead_code)]
     pub fn has_replication_out(&self) -> bool {
-        !self.repl_out.is_empty()
+        self.has_target(RuleTarget::REPLICATIONOUT)
     }
 
-    #[tracing::instrument(level = "trace", skip(self, ctx))]
-    pub fn evaluate_root(&self, ctx: RootRuleInput) -> Result<bool> {
-        let mut context = Context::default();
-        context.add_variable("input", ctx).map_err(|e| {
-            error!(error = ?e, "error adding variable");
-            anyhow!("error adding variable: {}", e)
-        })?;
-        let result = Value::resolve_all(&self.root, &context).map_err(|e| {
-            error!(error = ?e, "error running context");
-            anyhow!("error running context: {}", e)
-        })?;
-        match result {
-            Value::List(b) => Ok(b.iter().all(|x| match x {
-                Value::Bool(b) => *b,
-                _ => false,
-            })),
-            _ => Err(anyhow!("expected bool, got {:?}", result)),
+    /// Evaluates the rules of a target in order, the first enforced rule that does not
+    /// resolve to `true` denies the request
+    fn evaluate<T: Serialize>(&self, target: RuleTarget, input: T) -> Result<RuleDecision> {
+        let rules = self.rules();
+        if !rules.iter().any(|rule| rule.target == target) {
+            return Ok(RuleDecision::Allow);
         }
-    }
-
-    pub fn evaluate_object(&self, ctx: ObjectRuleInput) -> Result<bool> {
         let mut context = Context::default();
-        context.add_variable("input", ctx).map_err(|e| {
+        context.add_variable("input", input).map_err(|e| {
             error!(error = ?e, "error adding variable");
             anyhow!("error adding variable: {}", e)
         })?;
-        let result = Value::resolve_all(&self.obj, &context).map_err(|e| {
-            error!(error = ?e, "error running context");
-            anyhow!("error running context: {}", e)
-        })?;
-        match result {
-            Value::List(b) => {
-                let result = b.iter().all(|x| match x {
-                    Value::Bool(b) => *b,
-                    _ => false,
+        for rule in rules.iter().filter(|rule| rule.target == target) {
+            let allowed = match rule.expression.run(&context) {
+                Ok(Value::Bool(allowed)) => allowed,
+                Ok(_) => false,
+                Err(e) if rule.dry_run => {
+                    warn!(rule_id = rule.id, error = ?e, "error running dry-run rule");
+                    continue;
+                }
+                Err(e) => {
+                    error!(rule_id = rule.id, error = ?e, "error running context");
+                    return Err(anyhow!("error running rule {}: {}", rule.id, e));
+                }
+            };
+            if allowed {
+                continue;
+            }
+            let mode = if rule.dry_run { "dry_run" } else { "enforced" };
+            RULE_DENIALS.with_label_values(&[&rule.id, mode]).inc();
+            warn!(
+                target: "access_log",
+                operation = "RuleDenied",
+                rule_id = rule.id,
+                rule_target = ?target,
+                dry_run = rule.dry_run,
+                "Request denied by rule"
+            );
+            if !rule.dry_run {
+                return Ok(RuleDecision::Deny {
+                    rule_id: rule.id.clone(),
+                    message: rule
+                        .message
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_DENY_MESSAGE.to_string()),
                 });
-                Ok(result)
             }
-            _ => Err(anyhow!("expected bool, got {:?}", result)),
         }
+        Ok(RuleDecision::Allow)
     }
 
-    pub fn evaluate_package(&self, ctx: PackageObjectRuleInput) -> Result<bool> {
-        let mut context = Context::default();
-        context.add_variable("input", ctx).map_err(|e| {
-            error!(error = ?e, "error adding variable");
-            anyhow!("error adding variable: {}", e)
-        })?;
-        let result = Value::resolve_all(&self.obj_package, &context).map_err(|e| {
-            error!(error = ?e, "error running context");
-            anyhow!("error running context: {}", e)
-        })?;
-        match result {
-            Value::List(b) => Ok(b.iter().all(|x| match x {
-                Value::Bool(b) => *b,
-                _ => false,
-            })),
-            _ => Err(anyhow!("expected bool, got {:?}", result)),
-        }
+    #[tracing::instrument(level = "trace", skip(self, ctx))]
+    pub fn evaluate_root(&self, ctx: RootRuleInput) -> Result<RuleDecision> {
+        self.evaluate(RuleTarget::ROOT, ctx)
     }
 
-    pub fn evaluate_bundle(&self, ctx: BundleRuleInput) -> Result<bool> {
-        let mut context = Context::default();
-        context.add_variable("input", ctx).map_err(|e| {
-            error!(error = ?e, "error adding variable");
-            anyhow!("error adding variable: {}", e)
-        })?;
-        let result = Value::resolve_all(&self.bundle, &context).map_err(|e| {
-            error!(error = ?e, "error running context");
-            anyhow!("error running context: {}", e)
-        })?;
-        match result {
-            Value::List(b) => Ok(b.iter().all(|x| match x {
-                Value::Bool(b) => *b,
-                _ => false,
-            })),
-            _ => Err(anyhow!("expected bool, got {:?}", result)),
-        }
+    pub fn evaluate_object(&self, ctx: ObjectRuleInput) -> Result<RuleDecision> {
+        self.evaluate(RuleTarget::OBJECT, ctx)
+    }
+
+    pub fn evaluate_package(&self, ctx: PackageObjectRuleInput) -> Result<RuleDecision> {
+        self.evaluate(RuleTarget::OBJECTPACKAGE, ctx)
+    }
+
+    pub fn evaluate_bundle(&self, ctx: BundleRuleInput) -> Result<RuleDecision> {
+        self.evaluate(RuleTarget::BUNDLE, ctx)
     }
 
     pub fn _evaluate_incoming_replication(
         &self,
         ctx: ReplicationIncomingRuleInput,
-    ) -> Result<bool> {
-        let mut context = Context::default();
-        context.add_variable("input", ctx).map_err(|e| {
-            error!(error = ?e, "error adding variable");
-            anyhow!("error adding variable: {}", e)
-        })?;
-        let result = Value::resolve_all(&self.repl_in, &context).map_err(|e| {
-            error!(error = ?e, "error running context");
-            anyhow!("error running context: {}", e)
-        })?;
-        match result {
-            Value::List(b) => Ok(b.iter().all(|x| match x {
-                Value::Bool(b) => *b,
-                _ => false,
-            })),
-            _ => Err(anyhow!("expected bool, got {:?}", result)),
-        }
+    ) -> Result<RuleDecision> {
+        self.evaluate(RuleTarget::REPLICATIONIN, ctx)
     }
 
     pub fn _evaluate_outgoing_replication(
         &self,
         ctx: ReplicationOutgoingRuleInput,
-    ) -> Result<bool> {
-        let mut context = Context::default();
-        context.add_variable("input", ctx).map_err(|e| {
-            error!(error = ?e, "error adding variable");
-            anyhow!("error adding variable: {}", e)
-        })?;
-        let result = Value::resolve_all(&self.repl_out, &context).map_err(|e| {
-            error!(error = ?e, "error running context");
-            anyhow!("error running context: {}", e)
-        })?;
-        match result {
-            Value::List(b) => Ok(b.iter().all(|x| match x {
-                Value::Bool(b) => *b,
-                _ => false,
-            })),
-            _ => Err(anyhow!("expected bool, got {:?}", result)),
+    ) -> Result<RuleDecision> {
+        self.evaluate(RuleTarget::REPLICATIONOUT, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Input {
+        name: String,
+    }
+
+    fn engine(rules: Vec<Rule>) -> RuleEngine {
+        RuleEngine {
+            rules: RwLock::new(Arc::new(LoadedRule::compile_all(&rules).unwrap())),
         }
     }
+
+    fn input(name: &str) -> Input {
+        Input {
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_decisions() {
+        let mut deny = Rule::new(RuleTarget::OBJECT, r#"input.name != "test""#);
+        deny.id = Some("no-test".to_string());
+        deny.message = Some("Projects named test are read-only".to_string());
+        let engine = engine(vec![deny]);
+
+        assert_eq!(
+            engine.evaluate(RuleTarget::OBJECT, input("data")).unwrap(),
+            RuleDecision::Allow
+        );
+        assert_eq!(
+            engine.evaluate(RuleTarget::OBJECT, input("test")).unwrap(),
+            RuleDecision::Deny {
+                rule_id: "no-test".to_string(),
+                message: "Projects named test are read-only".to_string(),
+            }
+        );
+        // Other targets are not affected
+        assert_eq!(
+            engine.evaluate(RuleTarget::BUNDLE, input("test")).unwrap(),
+            RuleDecision::Allow
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut staged = Rule::new(RuleTarget::OBJECT, r#"input.name != "test""#);
+        staged.dry_run = true;
+        let enforced = Rule::new(RuleTarget::OBJECT, r#"input.name != "other""#);
+        let engine = engine(vec![staged, enforced]);
+
+        assert_eq!(
+            engine.evaluate(RuleTarget::OBJECT, input("test")).unwrap(),
+            RuleDecision::Allow
+        );
+        assert_eq!(
+            RULE_DENIALS
+                .with_label_values(&["OBJECT-0", "dry_run"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            engine.evaluate(RuleTarget::OBJECT, input("other")).unwrap(),
+            RuleDecision::Deny {
+                rule_id: "OBJECT-1".to_string(),
+                message: DEFAULT_DENY_MESSAGE.to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let mut first = Rule::new(RuleTarget::ROOT, "true");
+        first.id = Some("same".to_string());
+        let mut second = Rule::new(RuleTarget::OBJECT, "true");
+        second.id = Some("same".to_string());
+        assert!(LoadedRule::compile_all(&[first, second]).is_err());
+    }
 }
@@ -1,4 +1,5 @@
 use super::rule_engine::RuleEngine;
+use crate::s3_frontend::utils::aws_chunked::payload_content_length;
 use crate::structs::Bundle;
 use crate::structs::DbPermissionLevel;
 use crate::structs::Object;
@@ -28,11 +29,27 @@ pub struct ObjectHierarchyRuleInfo {
     pub project: Object,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RequestInfo {
     pub bucket: bool, // Is this request for a bucket or an object
     pub method: String,
     pub headers: HashMap<String, StringOrVec>,
+    /// Declared payload size (x-amz-decoded-content-length for aws-chunked bodies),
+    /// null if unknown
+    pub content_length: Option<i64>,
+    pub content_type: Option<String>,
+    /// Object key without the bucket, null for bucket requests
+    pub key: Option<String>,
+    /// Everything after the first dot of the file name, e.g. `fastq.gz`
+    pub extension: Option<String>,
+}
+
+/// Key-values of the resolved hierarchy by key, later values of a key win
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HierarchyTags {
+    pub project: HashMap<String, String>,
+    pub collection: HashMap<String, String>,
+    pub dataset: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +71,7 @@ pub struct ObjectRuleInput {
     pub user: Option<UserRuleInfo>,
     pub object_hierarchy: ObjectHierarchyRuleInfo,
     pub request: RequestInfo,
+    pub tags: HierarchyTags,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -161,6 +179,7 @@ impl RootRuleInputBuilder {
                 bucket: false,
                 method: self.method,
                 headers: self.headers,
+                ..Default::default()
             },
         })
     }
@@ -174,6 +193,9 @@ pub struct ObjectRuleInputBuilder {
     bucket: bool, // Bucket or Object request
     method: String,
     headers: HashMap<String, StringOrVec>,
+    content_length: Option<i64>,
+    content_type: Option<String>,
+    key: Option<String>,
     object: Option<Object>,
     dataset: Option<Object>,
     collection: Option<Object>,
@@ -228,11 +250,29 @@ impl ObjectRuleInputBuilder {
         self
     }
 
+    /// Also extracts the declared content length and type, the body is never read
     pub fn headers(mut self, headers: &HeaderMap<HeaderValue>) -> Self {
         if self.skip {
             return self;
         }
         self.headers = convert_headers(headers);
+        let content_length = headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        self.content_length = payload_content_length(headers, content_length);
+        self.content_type = headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        self
+    }
+
+    pub fn key(mut self, key: &str) -> Self {
+        if self.skip {
+            return self;
+        }
+        self.key = Some(key.to_string());
         self
     }
 
@@ -328,10 +368,19 @@ impl ObjectRuleInputBuilder {
                 collection: self.collection,
                 project: self.project.ok_or_else(|| anyhow!("project is required"))?,
             },
+            tags: HierarchyTags {
+                project: self.project.as_ref().map(tags).unwrap_or_default(),
+                collection: self.collection.as_ref().map(tags).unwrap_or_default(),
+                dataset: self.dataset.as_ref().map(tags).unwrap_or_default(),
+            },
             request: RequestInfo {
                 bucket: self.bucket,
                 method: self.method,
                 headers: self.headers,
+                content_length: self.content_length,
+                content_type: self.content_type,
+                extension: self.key.as_deref().and_then(extension),
+                key: self.key,
             },
         })
     }
@@ -441,6 +490,7 @@ impl PackageObjectRuleInputBuilder {
                 bucket: false,
                 method: self.method,
                 headers: self.headers,
+                ..Default::default()
             },
         })
     }
@@ -552,6 +602,7 @@ impl BundleRuleInputBuilder {
                 bucket: false,
                 method: self.method,
                 headers: self.headers,
+                ..Default::default()
             },
             bundle: BundleInfo {
                 id: self.bundle.id.to_string(),
@@ -675,6 +726,22 @@ impl ReplicationOutgoingRuleInputBuilder {
 
 // ------ HELPERS -------
 
+fn tags(object: &Object) -> HashMap<String, String> {
+    object
+        .key_values
+        .iter()
+        .map(|kv| (kv.key.clone(), kv.value.clone()))
+        .collect()
+}
+
+fn extension(key: &str) -> Option<String> {
+    let file_name = key.rsplit('/').next()?;
+    file_name
+        .split_once('.')
+        .map(|(_, extension)| extension.to_string())
+        .filter(|extension| !extension.is_empty())
+}
+
 pub fn convert_permissions(
     perm: HashMap<DieselUlid, DbPermissionLevel>,
 ) -> HashMap<String, String> {
@@ -713,3 +780,72 @@ pub fn convert_headers(headers: &HeaderMap<HeaderValue>) -> HashMap<String, Stri
     }
     header_map
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aruna_rust_api::api::storage::models::v2::KeyValue;
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(
+            extension("raw/sample_1.fastq.gz").as_deref(),
+            Some("fastq.gz")
+        );
+        assert_eq!(extension("file.txt").as_deref(), Some("txt"));
+        assert_eq!(extension("raw/README"), None);
+        assert_eq!(extension("raw/trailing."), None);
+    }
+
+    #[test]
+    fn test_object_input() {
+        let project = Object {
+            name: "pilot-project".to_string(),
+            key_values: vec![KeyValue {
+                key: "pilot".to_string(),
+                value: "true".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/gzip"),
+        );
+        let input = ObjectRuleInputBuilder::default()
+            .method(&http::Method::PUT)
+            .headers(&headers)
+            .key("raw/sample.fastq.gz")
+            .project(&project)
+            .unwrap()
+            .build()
+            .unwrap();
+        // Missing lengths do not fail the build
+        assert_eq!(input.request.content_length, None);
+        assert_eq!(
+            input.request.content_type.as_deref(),
+            Some("application/gzip")
+        );
+        assert_eq!(input.request.extension.as_deref(), Some("fastq.gz"));
+        assert_eq!(
+            input.tags.project.get("pilot").map(String::as_str),
+            Some("true")
+        );
+        assert!(input.tags.collection.is_empty());
+
+        headers.insert(
+            http::header::CONTENT_LENGTH,
+            HeaderValue::from_static("2048"),
+        );
+        let input = ObjectRuleInputBuilder::default()
+            .method(&http::Method::PUT)
+            .headers(&headers)
+            .project(&project)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(input.request.content_length, Some(2048));
+        assert_eq!(input.request.key, None);
+    }
+}
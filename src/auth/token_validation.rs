@@ -0,0 +1,213 @@
+//! Validation helpers for tokens of the Aruna server, users and other DataProxies
+use ahash::RandomState;
+use anyhow::{anyhow, bail, Result};
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use std::str::FromStr;
+use tracing::error;
+
+/// Audience of tokens signed by the server (or by proxies on behalf of users)
+pub const USER_AUDIENCE: &str = "aruna";
+/// Audience of DataProxy-DataProxy exchange tokens
+pub const PROXY_AUDIENCE: &str = "proxy";
+
+/// Caller type a token is validated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAudience {
+    /// S3, user and admin requests
+    User,
+    /// DataProxy-DataProxy exchange
+    Proxy,
+}
+
+impl TokenAudience {
+    pub fn expected(&self) -> &'static str {
+        match self {
+            TokenAudience::User => USER_AUDIENCE,
+            TokenAudience::Proxy => PROXY_AUDIENCE,
+        }
+    }
+}
+
+/// Decodes and verifies a token for the expected audience, `leeway` is applied to `exp`
+pub fn decode_claims<T: DeserializeOwned>(
+    token: &str,
+    dec_key: &DecodingKey,
+    audience: TokenAudience,
+    leeway: u64,
+) -> Result<T> {
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_audience(&[audience.expected()]);
+    validation.leeway = leeway;
+    let token = decode::<T>(token, dec_key, &validation).map_err(|e| {
+        error!(error = ?e, msg = e.to_string(), ?audience);
+        e
+    })?;
+    Ok(token.claims)
+}
+
+/// Checks that a DataProxy token was issued by the endpoint its signing key belongs to
+pub fn check_issuer(iss: &str, sub: &str, key_endpoint: Option<DieselUlid>) -> Result<DieselUlid> {
+    let issuer = DieselUlid::from_str(iss).map_err(|_| {
+        error!(iss, "Token issuer is not an endpoint id");
+        anyhow!("Token issuer is not an endpoint id")
+    })?;
+    if iss != sub {
+        error!(iss, sub, "Token issuer and subject differ");
+        bail!("Token issuer and subject differ");
+    }
+    match key_endpoint {
+        Some(endpoint) if endpoint == issuer => Ok(issuer),
+        Some(endpoint) => {
+            error!(%issuer, %endpoint, "Token issuer does not own the signing key");
+            bail!("Token issuer does not own the signing key")
+        }
+        None => {
+            error!(%issuer, "Signing key is not bound to a known endpoint");
+            bail!("Signing key is not bound to a known endpoint")
+        }
+    }
+}
+
+/// Rejects tokens issued in the future, `iat` and `now` are unix seconds
+///
+/// jsonwebtoken does not check `iat`. Tokens issued more than `leeway` seconds ahead
+/// of `now` point to a skewed clock on one of the sides.
+pub fn check_issued_at(iat: Option<u64>, now: u64, leeway: u64) -> Result<()> {
+    match iat {
+        Some(iat) if iat > now.saturating_add(leeway) => {
+            error!(
+                iat,
+                now, leeway, "Token issued in the future, check the clock synchronization"
+            );
+            bail!("Token issued in the future")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Remembers the ids (`jti`) of used DataProxy tokens until they expire
+#[derive(Debug, Default)]
+pub struct ReplayCache {
+    seen: DashMap<String, u64, RandomState>,
+}
+
+impl ReplayCache {
+    /// Fails if the token id was already used, `exp` and `now` are unix seconds
+    pub fn check_and_insert(&self, jti: &str, exp: u64, now: u64, leeway: u64) -> Result<()> {
+        // Tokens are rejected as expired after exp + leeway, the entries are not needed anymore
+        self.seen
+            .retain(|_, expires| expires.saturating_add(leeway) >= now);
+        match self.seen.entry(jti.to_string()) {
+            dashmap::mapref::entry::Entry::Occupied(_) => {
+                error!(jti, "Token was already used");
+                bail!("Token was already used")
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(exp);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde::{Deserialize, Serialize};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Claims {
+        iss: String,
+        sub: String,
+        aud: String,
+        exp: u64,
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn token(aud: &str, exp: u64) -> String {
+        let key = EncodingKey::from_ed_pem(include_bytes!("../../tests/test.pem")).unwrap();
+        let claims = Claims {
+            iss: "issuer".to_string(),
+            sub: "subject".to_string(),
+            aud: aud.to_string(),
+            exp,
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::EdDSA), &claims, &key).unwrap()
+    }
+
+    fn decoding_key() -> DecodingKey {
+        DecodingKey::from_ed_pem(include_bytes!("../../tests/test.pub")).unwrap()
+    }
+
+    #[test]
+    fn test_audience() {
+        let key = decoding_key();
+        let user = token(USER_AUDIENCE, now() + 600);
+        let proxy = token(PROXY_AUDIENCE, now() + 600);
+        assert!(decode_claims::<Claims>(&user, &key, TokenAudience::User, 60).is_ok());
+        assert!(decode_claims::<Claims>(&proxy, &key, TokenAudience::Proxy, 60).is_ok());
+        // Wrong audience
+        assert!(decode_claims::<Claims>(&user, &key, TokenAudience::Proxy, 60).is_err());
+        assert!(decode_claims::<Claims>(&proxy, &key, TokenAudience::User, 60).is_err());
+    }
+
+    #[test]
+    fn test_expiry_and_skew() {
+        let key = decoding_key();
+        // Long expired
+        let expired = token(PROXY_AUDIENCE, now() - 3600);
+        assert!(decode_claims::<Claims>(&expired, &key, TokenAudience::Proxy, 60).is_err());
+        // Expired according to a clock running 2 minutes ahead, tolerated with enough leeway
+        let skewed = token(PROXY_AUDIENCE, now() - 120);
+        assert!(decode_claims::<Claims>(&skewed, &key, TokenAudience::Proxy, 60).is_err());
+        assert!(decode_claims::<Claims>(&skewed, &key, TokenAudience::Proxy, 300).is_ok());
+    }
+
+    #[test]
+    fn test_replay() {
+        let cache = ReplayCache::default();
+        let now = now();
+        assert!(cache.check_and_insert("a", now + 900, now, 60).is_ok());
+        assert!(cache.check_and_insert("a", now + 900, now, 60).is_err());
+        assert!(cache.check_and_insert("b", now + 900, now, 60).is_ok());
+        // Expired entries are dropped
+        assert!(cache
+            .check_and_insert("c", now + 900, now + 2000, 60)
+            .is_ok());
+        assert_eq!(cache.seen.len(), 1);
+    }
+
+    #[test]
+    fn test_issued_at() {
+        let now = now();
+        assert!(check_issued_at(None, now, 60).is_ok());
+        assert!(check_issued_at(Some(now - 600), now, 60).is_ok());
+        assert!(check_issued_at(Some(now + 60), now, 60).is_ok());
+        assert!(check_issued_at(Some(now + 120), now, 60).is_err());
+        // Widened leeway of a measured skew
+        assert!(check_issued_at(Some(now + 120), now, 180).is_ok());
+    }
+
+    #[test]
+    fn test_issuer() {
+        let endpoint = DieselUlid::generate();
+        let id = endpoint.to_string();
+        assert_eq!(check_issuer(&id, &id, Some(endpoint)).unwrap(), endpoint);
+        // Other endpoint, unbound key, subject mismatch and non-endpoint issuer
+        assert!(check_issuer(&id, &id, Some(DieselUlid::generate())).is_err());
+        assert!(check_issuer(&id, &id, None).is_err());
+        assert!(check_issuer(&id, "other", Some(endpoint)).is_err());
+        assert!(check_issuer("aruna", "aruna", Some(endpoint)).is_err());
+    }
+}
@@ -0,0 +1,508 @@
+use crate::caching::cache::Cache;
+use crate::caching::credentials::CredentialRefresher;
+use crate::caching::lifecycle::LifecycleHandler;
+use crate::caching::notification_retry::NotificationRetryHandler;
+use crate::caching::usage::UsageReconciler;
+use crate::config::{self, Config, Rule, RuleTarget};
+use crate::data_backends::disk_cache::DiskCacheBackend;
+use crate::data_backends::filesystem_backend::FSBackend;
+use crate::data_backends::metered::MeteredBackend;
+use crate::data_backends::registry::BackendRegistry;
+use crate::data_backends::{s3_backend::S3Backend, storage_backend::StorageBackend};
+use crate::grpc_api::admin_service::{DataproxyAdminServiceImpl, DataproxyAdminServiceServer};
+use crate::grpc_api::bundler::BundlerServiceImpl;
+use crate::grpc_api::ingestion_service::DataproxyIngestionServiceImpl;
+use crate::grpc_api::session_service::{
+    DataproxySessionServiceImpl, DataproxySessionServiceServer,
+};
+use crate::grpc_api::{
+    proxy_service::DataproxyReplicationServiceImpl, user_service::DataproxyUserServiceImpl,
+};
+use crate::maintenance::footer_repair::FooterRepairHandler;
+use crate::maintenance::key_rotation::KeyRotationHandler;
+use crate::maintenance::restore_poller::RestorePoller;
+use crate::maintenance::scrubber::IntegrityScrubber;
+use crate::maintenance::shard_migration::ShardMigrationHandler;
+use crate::metrics::BACKEND_METRICS;
+use crate::replication::init::InitReplicationHandler;
+use crate::replication::progress::ReplicationProgress;
+use crate::replication::replication_handler::{ReplicationHandler, ReplicationMessage};
+use crate::replication::request::ReplicationRequester;
+use crate::request_id::RequestIdLayer;
+use crate::s3_frontend::s3server::S3Server;
+use crate::scoped_config::{self, ConfigLayer, Scope};
+use crate::shutdown::{release_uploads, wait_for_signal, Shutdown};
+use crate::CONFIG;
+use anyhow::{anyhow, Result};
+use aruna_rust_api::api::dataproxy::services::v2::bundler_service_server::BundlerServiceServer;
+use aruna_rust_api::api::dataproxy::services::v2::dataproxy_ingestion_service_server::DataproxyIngestionServiceServer;
+use aruna_rust_api::api::dataproxy::services::v2::dataproxy_replication_service_server::DataproxyReplicationServiceServer;
+use aruna_rust_api::api::dataproxy::services::v2::dataproxy_user_service_server::DataproxyUserServiceServer;
+use async_channel::{Receiver, Sender};
+use futures::future::{try_join_all, BoxFuture};
+use futures::{FutureExt, TryFutureExt};
+use std::collections::HashMap;
+use std::future::Future;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::try_join;
+use tonic::transport::Server;
+use tower::ServiceBuilder;
+use tracing::{error, info, info_span, trace, Instrument};
+
+/// Name of the main backend in metrics
+const DEFAULT_BACKEND: &str = "default";
+
+async fn init_backend(config: &config::Backend, name: &str) -> Result<Box<dyn StorageBackend>> {
+    let endpoint_id = CONFIG.proxy.endpoint_id.to_string();
+    let backend: Box<dyn StorageBackend> = match config {
+        config::Backend::S3 { .. } => Box::new(S3Backend::new(endpoint_id, config).await?),
+        config::Backend::FileSystem { .. } => Box::new(FSBackend::new(endpoint_id, config).await?),
+    };
+    Ok(Box::new(MeteredBackend::new(
+        backend,
+        name,
+        BACKEND_METRICS.clone(),
+    )))
+}
+
+/// Builder for embedding the dataproxy into other binaries
+#[derive(Default)]
+pub struct DataProxyBuilder {
+    config: Option<Config>,
+    rules: Vec<Rule>,
+    backend: Option<Box<dyn StorageBackend>>,
+    cache: Option<(Arc<Cache>, Receiver<ReplicationMessage>)>,
+}
+
+impl DataProxyBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Config to use, if not set the config is loaded from the `CONFIG` env var (default: config.toml),
+    /// see [`Config::from_env`]
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Adds an additional rule to the rule engine
+    pub fn rule(mut self, target: RuleTarget, rule: impl Into<String>) -> Self {
+        self.rules.push(Rule::new(target, rule));
+        self
+    }
+
+    /// Custom storage backend, if not set the backend is created from the config
+    pub fn backend(mut self, backend: Box<dyn StorageBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Pre-populated cache with the receiver of the replication channel it was created with
+    pub fn cache(mut self, cache: Arc<Cache>, receiver: Receiver<ReplicationMessage>) -> Self {
+        self.cache = Some((cache, receiver));
+        self
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn build(self) -> Result<DataProxy> {
+        let DataProxyBuilder {
+            config,
+            rules,
+            backend,
+            cache,
+        } = self;
+
+        // Every dataproxy runs with its own config, multiple ones can be embedded
+        let mut config = match config {
+            Some(config) => config,
+            None => Config::from_env()?,
+        };
+        config.rules.extend(rules);
+        let scope = scoped_config::init(config)?;
+        scoped_config::scope(scope, Self::build_with(scope, backend, cache)).await
+    }
+
+    async fn build_with(
+        scope: &'static Scope,
+        backend: Option<Box<dyn StorageBackend>>,
+        cache: Option<(Arc<Cache>, Receiver<ReplicationMessage>)>,
+    ) -> Result<DataProxy> {
+        trace!("init storage backend");
+        let backend: Box<dyn StorageBackend> = match backend {
+            Some(backend) => Box::new(MeteredBackend::new(
+                backend,
+                DEFAULT_BACKEND,
+                BACKEND_METRICS.clone(),
+            )),
+            None if CONFIG.backends.is_empty() => {
+                init_backend(&CONFIG.backend, DEFAULT_BACKEND).await?
+            }
+            None => {
+                let mut named = HashMap::new();
+                for (name, config) in &CONFIG.backends {
+                    trace!(backend = name, "init named storage backend");
+                    named.insert(name.clone(), init_backend(config, name).await?);
+                }
+                Box::new(BackendRegistry::new(
+                    init_backend(&CONFIG.backend, DEFAULT_BACKEND).await?,
+                    named,
+                ))
+            }
+        };
+        let backend: Box<dyn StorageBackend> = match &CONFIG.disk_cache {
+            Some(disk_cache) => Box::new(DiskCacheBackend::new(backend, disk_cache.clone()).await?),
+            None => backend,
+        };
+        let storage_backend: Arc<Box<dyn StorageBackend>> = Arc::new(backend);
+
+        trace!("init cache");
+        let (cache, sender, receiver) = match cache {
+            Some((cache, receiver)) => {
+                let sender = cache.sender.clone();
+                (cache, sender, receiver)
+            }
+            None => {
+                let (sender, receiver) = async_channel::bounded(1000);
+                let cache = Cache::new(
+                    CONFIG.proxy.aruna_url.clone(),
+                    CONFIG.persistence.is_some(),
+                    CONFIG.proxy.endpoint_id,
+                    CONFIG
+                        .proxy
+                        .clone()
+                        .private_key
+                        .ok_or_else(|| anyhow!("Private key not set"))?,
+                    CONFIG.proxy.serial,
+                    sender.clone(),
+                    Some(storage_backend.clone()),
+                )
+                .await?;
+                (cache, sender, receiver)
+            }
+        };
+
+        trace!("init s3 server");
+        let mut s3_servers = Vec::new();
+        if let Some(frontend) = &CONFIG.frontend {
+            s3_servers.push(
+                S3Server::new(
+                    &frontend.server,
+                    frontend.hostname.to_string(),
+                    storage_backend.clone(),
+                    cache.clone(),
+                    None,
+                    frontend.tls.clone(),
+                )
+                .await?,
+            );
+            // Additional listeners with restricted policy profiles
+            for listener in frontend.listeners.iter() {
+                s3_servers.push(
+                    S3Server::new(
+                        &listener.server,
+                        listener.hostname.to_string(),
+                        storage_backend.clone(),
+                        cache.clone(),
+                        Some(listener.profile),
+                        listener.tls.clone(),
+                    )
+                    .await?,
+                );
+            }
+        }
+
+        Ok(DataProxy {
+            cache,
+            backend: storage_backend,
+            sender,
+            receiver,
+            s3_servers,
+            shutdown: Shutdown::new(),
+            scope,
+        })
+    }
+}
+
+/// A fully wired dataproxy
+pub struct DataProxy {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+    sender: Sender<ReplicationMessage>,
+    receiver: Receiver<ReplicationMessage>,
+    s3_servers: Vec<S3Server>,
+    shutdown: Shutdown,
+    scope: &'static Scope,
+}
+
+/// Independent tasks of a dataproxy, to be driven by the caller
+pub struct DataProxyTasks {
+    /// S3 frontend listeners, only available if a frontend is configured
+    pub s3: Option<BoxFuture<'static, Result<()>>>,
+    /// gRPC services (replication, user, ingestion and bundler)
+    pub grpc: BoxFuture<'static, Result<()>>,
+    /// Background tasks (replication handler, notification retries, lifecycle expiration,
+    /// usage reconciliation, integrity scrubbing)
+    pub background: BoxFuture<'static, Result<()>>,
+    /// Prometheus metrics listener, only available if configured
+    pub metrics: Option<BoxFuture<'static, Result<()>>>,
+}
+
+impl DataProxy {
+    pub fn cache(&self) -> Arc<Cache> {
+        self.cache.clone()
+    }
+
+    /// Shutdown signal of all tasks, can be triggered by embedding services
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    pub fn backend(&self) -> Arc<Box<dyn StorageBackend>> {
+        self.backend.clone()
+    }
+
+    /// Runs the future with the config of this dataproxy, required for calls of the
+    /// maintenance and replication handlers outside of the dataproxy tasks
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        scoped_config::scope(self.scope, future).await
+    }
+
+    /// Per-object progress of running replications and recently failed replications
+    pub fn replication_progress(&self) -> Arc<ReplicationProgress> {
+        self.cache.replication_progress.clone()
+    }
+
+    /// Number of server notifications waiting for redelivery, should stay close to zero
+    pub fn pending_notifications(&self) -> usize {
+        self.cache.pending_notification_count()
+    }
+
+    /// Handler for InitReplication requests of other proxies
+    pub fn replication_init(&self) -> InitReplicationHandler {
+        InitReplicationHandler::new(self.cache.clone())
+    }
+
+    /// Handler for user initiated replication requests
+    pub fn replication_requests(&self) -> ReplicationRequester {
+        ReplicationRequester::new(self.cache.clone())
+    }
+
+    /// Maintenance handler to rotate the encryption keys of stored locations
+    pub fn key_rotation(&self) -> Arc<KeyRotationHandler> {
+        Arc::new(KeyRotationHandler::new(
+            self.cache.clone(),
+            self.backend.clone(),
+        ))
+    }
+
+    /// Maintenance handler to move stored locations to the configured sharding scheme
+    pub fn shard_migration(&self) -> Arc<ShardMigrationHandler> {
+        Arc::new(ShardMigrationHandler::new(
+            self.cache.clone(),
+            self.backend.clone(),
+        ))
+    }
+
+    /// Splits the dataproxy into its tasks without spawning them, every task runs with
+    /// the config of this dataproxy
+    pub fn into_tasks(self) -> DataProxyTasks {
+        let scope = self.scope;
+        let DataProxyTasks {
+            s3,
+            grpc,
+            background,
+            metrics,
+        } = scoped_config::sync_scope(scope, || self.split());
+        DataProxyTasks {
+            s3: s3.map(|s3| scoped_config::scope(scope, s3).boxed()),
+            grpc: scoped_config::scope(scope, grpc).boxed(),
+            background: scoped_config::scope(scope, background).boxed(),
+            metrics: metrics.map(|metrics| scoped_config::scope(scope, metrics).boxed()),
+        }
+    }
+
+    fn split(self) -> DataProxyTasks {
+        let DataProxy {
+            cache,
+            backend,
+            sender,
+            receiver,
+            s3_servers,
+            shutdown,
+            ..
+        } = self;
+
+        let replication_handler = ReplicationHandler::new(
+            receiver,
+            backend.clone(),
+            CONFIG.proxy.endpoint_id.to_string(),
+            cache.clone(),
+            shutdown.clone(),
+        );
+        let notification_retry = NotificationRetryHandler::new(cache.clone(), shutdown.clone());
+        let lifecycle = LifecycleHandler::new(cache.clone(), shutdown.clone());
+        let usage_reconciler = UsageReconciler::new(cache.clone(), shutdown.clone());
+        let credential_refresher = CredentialRefresher::new(cache.clone(), shutdown.clone());
+        let scrubber = IntegrityScrubber::new(cache.clone(), backend.clone(), shutdown.clone());
+        let restore_poller = RestorePoller::new(cache.clone(), backend.clone(), shutdown.clone());
+        let footer_repair = FooterRepairHandler::new(cache.clone(), backend.clone());
+        let footer_repair_shutdown = shutdown.clone();
+        let background_shutdown = shutdown.clone();
+        let background = async move {
+            try_join!(
+                background_shutdown
+                    .with_grace("replication_handler", replication_handler.run())
+                    .instrument(info_span!("replication_handler_run")),
+                notification_retry
+                    .run()
+                    .instrument(info_span!("notification_retry_run")),
+                lifecycle.run().instrument(info_span!("lifecycle_run")),
+                usage_reconciler
+                    .run()
+                    .instrument(info_span!("usage_reconciler_run")),
+                credential_refresher
+                    .run()
+                    .instrument(info_span!("credential_refresher_run")),
+                scrubber.run().instrument(info_span!("scrubber_run")),
+                restore_poller
+                    .run()
+                    .instrument(info_span!("restore_poller_run")),
+                footer_repair
+                    .run(footer_repair_shutdown)
+                    .instrument(info_span!("footer_repair_run")),
+            )
+            .map(|_| ())
+        }
+        .boxed();
+
+        let grpc_shutdown = shutdown.clone();
+        let grpc = async move {
+            let proxy_grpc_addr = CONFIG.proxy.grpc_server.parse::<SocketAddr>()?;
+            let mut builder = Server::builder()
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(ConfigLayer::current())
+                        .layer(RequestIdLayer),
+                )
+                .add_service(DataproxyReplicationServiceServer::new(
+                    DataproxyReplicationServiceImpl::new(cache.clone(), sender, backend.clone()),
+                ))
+                .add_service(DataproxyUserServiceServer::new(
+                    DataproxyUserServiceImpl::new(cache.clone()),
+                ))
+                .add_service(DataproxyAdminServiceServer::new(
+                    DataproxyAdminServiceImpl::new(cache.clone(), backend.clone()),
+                ))
+                .add_service(DataproxySessionServiceServer::new(
+                    DataproxySessionServiceImpl::new(cache.clone()),
+                ));
+
+            if CONFIG.proxy.enable_ingest {
+                builder = builder.add_service(DataproxyIngestionServiceServer::new(
+                    DataproxyIngestionServiceImpl::new(cache.clone(), backend),
+                ));
+            }
+
+            if let Some(frontend) = &CONFIG.frontend {
+                builder = builder.add_service(BundlerServiceServer::new(BundlerServiceImpl::new(
+                    cache.clone(),
+                    frontend.hostname.to_string(),
+                    true,
+                )));
+            };
+
+            let server = builder.serve_with_shutdown(proxy_grpc_addr, grpc_shutdown.signaled());
+            grpc_shutdown
+                .with_grace("grpc_server", async {
+                    server.await.map_err(|e| {
+                        error!(error = ?e, msg = e.to_string());
+                        anyhow!("an error occurred {e}")
+                    })
+                })
+                .await
+        }
+        .instrument(info_span!("grpc_server_run"))
+        .boxed();
+
+        DataProxyTasks {
+            s3: if s3_servers.is_empty() {
+                None
+            } else {
+                Some(
+                    try_join_all(
+                        s3_servers
+                            .into_iter()
+                            .map(|server| server.run(shutdown.clone())),
+                    )
+                    .map_ok(|_| ())
+                    .boxed(),
+                )
+            },
+            grpc,
+            background,
+            metrics: CONFIG.metrics.as_ref().map(|metrics| {
+                crate::metrics::serve(&metrics.server, shutdown.clone())
+                    .instrument(info_span!("metrics_server_run"))
+                    .boxed()
+            }),
+        }
+    }
+
+    /// Runs all tasks on the current runtime until the S3 or gRPC server stops,
+    /// SIGTERM/SIGINT trigger a graceful shutdown
+    pub async fn run(self) -> Result<()> {
+        let shutdown = self.shutdown();
+        let (cache, backend, scope) = (self.cache(), self.backend(), self.scope);
+        let DataProxyTasks {
+            s3,
+            grpc,
+            background,
+            metrics,
+        } = self.into_tasks();
+
+        let signal_shutdown = shutdown.clone();
+        crate::spawn(async move {
+            match wait_for_signal().await {
+                Ok(()) => signal_shutdown.trigger(),
+                Err(err) => error!(error = ?err, msg = "Unable to listen for shutdown signals"),
+            }
+        });
+
+        let background_handle = crate::spawn(async move {
+            if let Err(err) = background.await {
+                trace!("{err}");
+            };
+        });
+
+        if let Some(metrics) = metrics {
+            crate::spawn(async move {
+                if let Err(err) = metrics.await {
+                    error!(error = ?err, msg = "Metrics server stopped");
+                }
+            });
+        }
+
+        let grpc_server_handle = crate::spawn(grpc);
+        let result = if let Some(s3) = s3 {
+            match try_join!(s3, async { grpc_server_handle.await? }) {
+                Ok(_) => Ok(()),
+                Err(err) => {
+                    error!("{}", err);
+                    Err(err)
+                }
+            }
+        } else {
+            grpc_server_handle.await?
+        };
+
+        if shutdown.is_triggered() {
+            // Let the replication handler finish its current batch
+            let _ = background_handle.await;
+            // No request can complete an upload anymore
+            scoped_config::scope(scope, release_uploads(&cache, &**backend)).await;
+            info!("shutdown complete");
+        }
+        result
+    }
+}
@@ -1,6 +1,10 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
+use super::remote::RemoteObjects;
 use crate::{data_backends::storage_backend::StorageBackend, structs::ObjectLocation};
+use bytes::Bytes;
+use diesel_ulid::DieselUlid;
 use futures_util::TryStreamExt;
 use pithos_lib::helpers::notifications::Message;
 use pithos_lib::{
@@ -8,75 +12,395 @@ use pithos_lib::{
     streamreadwrite::GenericStreamReadWriter,
     transformer::ReadWriter,
     transformers::{
-        async_sender_sink::AsyncSenderSink, decrypt::ChaCha20Dec, gzip_comp::GzipEnc, tar::TarEnc,
-        zstd_decomp::ZstdDec,
+        async_sender_sink::AsyncSenderSink, decrypt::ChaCha20Dec, filter::Filter,
+        gzip_comp::GzipEnc, tar::TarEnc, zip::ZipEnc, zstd_decomp::ZstdDec,
     },
 };
 use s3s::{dto::StreamingBlob, s3_error};
+use serde::Serialize;
 use tokio::pin;
-use tracing::{debug, info_span, trace, Instrument};
+use tracing::{debug, info_span, trace, warn, Instrument};
 
-#[tracing::instrument(level = "trace", skip(path_level_vec, backend))]
+/// Member of a bundle or archive
+#[derive(Debug, Clone, PartialEq)]
+pub enum BundleMember {
+    Directory,
+    /// Object id and location of data stored on this proxy
+    Local(DieselUlid, ObjectLocation),
+    /// Object without local data, fetched from the endpoint that holds it
+    Remote(DieselUlid),
+}
+
+impl BundleMember {
+    fn sort_key(&self) -> Option<DieselUlid> {
+        match self {
+            BundleMember::Directory => None,
+            BundleMember::Local(_, location) => Some(location.id),
+            BundleMember::Remote(id) => Some(*id),
+        }
+    }
+}
+
+/// Packaging of a bundle, selected by the file extension of the bundle name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleFormat {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl BundleFormat {
+    pub const SUPPORTED: &'static str = ".tar.gz, .tar, .zip";
+
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        if filename.ends_with(".tar.gz") {
+            Some(BundleFormat::TarGz)
+        } else if filename.ends_with(".tar") {
+            Some(BundleFormat::Tar)
+        } else if filename.ends_with(".zip") {
+            Some(BundleFormat::Zip)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            BundleFormat::TarGz => "application/gzip",
+            BundleFormat::Tar => "application/x-tar",
+            BundleFormat::Zip => "application/zip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BundleFormat::TarGz => ".tar.gz",
+            BundleFormat::Tar => ".tar",
+            BundleFormat::Zip => ".zip",
+        }
+    }
+
+    /// Archive format requested for a collection or dataset, either by an `archive=<format>`
+    /// query parameter or by an `Accept` header with the content type of the format
+    pub fn from_request(accept: Option<&str>, query: Option<&str>) -> Option<Self> {
+        let from_query = query.and_then(|query| {
+            query
+                .split('&')
+                .find_map(|param| param.strip_prefix("archive="))
+                .and_then(|format| Self::from_filename(&format!(".{format}")))
+        });
+        from_query.or_else(|| {
+            let accept = accept?;
+            [BundleFormat::Tar, BundleFormat::TarGz, BundleFormat::Zip]
+                .into_iter()
+                .find(|format| {
+                    accept.split(',').any(|value| {
+                        value.split(';').next().map(str::trim) == Some(format.content_type())
+                    })
+                })
+        })
+    }
+}
+
+/// Splits a file name into stem and extension (including the dot), dotfiles have no extension
+fn split_extension(name: &str) -> (&str, &str) {
+    let file_start = name.rfind('/').map(|idx| idx + 1).unwrap_or(0);
+    match name[file_start..].rfind('.') {
+        Some(idx) if idx > 0 => name.split_at(file_start + idx),
+        _ => (name, ""),
+    }
+}
+
+/// Archive members in a reproducible order
+///
+/// Names are made relative, duplicate directories are merged and colliding file names
+/// get a `_1`, `_2`, ... suffix in front of the extension (in the order of their
+/// location ids). The returned names are exactly the member names in the archive.
+pub fn prepare_members(members: Vec<(String, BundleMember)>) -> Vec<(String, BundleMember)> {
+    let mut members = members
+        .into_iter()
+        .map(|(name, member)| (name.trim_start_matches('/').to_string(), member))
+        .filter(|(name, _)| !name.is_empty())
+        .collect::<Vec<_>>();
+    members.sort_by(|(a, a_member), (b, b_member)| {
+        a.cmp(b)
+            .then_with(|| a_member.sort_key().cmp(&b_member.sort_key()))
+    });
+
+    let taken = members
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+    let mut used = HashSet::new();
+    let mut result = Vec::with_capacity(members.len());
+    for (name, member) in members {
+        if member == BundleMember::Directory {
+            if used.insert(name.clone()) {
+                result.push((name, member));
+            }
+            continue;
+        }
+        let name = if used.insert(name.clone()) {
+            name
+        } else {
+            let (stem, extension) = split_extension(&name);
+            let mut suffix = 1;
+            loop {
+                let candidate = format!("{stem}_{suffix}{extension}");
+                if !taken.contains(&candidate) && used.insert(candidate.clone()) {
+                    break candidate;
+                }
+                suffix += 1;
+            }
+        };
+        result.push((name, member));
+    }
+    result.sort_by(|(a, _), (b, _)| a.cmp(b));
+    result
+}
+
+/// Members prepended to bundles that are requested with a manifest
+pub const MANIFEST_NAME: &str = "MANIFEST.json";
+pub const CHECKSUMS_NAME: &str = "SHA256SUMS";
+
+/// Bundled object as listed in the manifest, taken from the cached hashes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub object_id: String,
+    pub size: i64,
+    pub sha256: Option<String>,
+}
+
+/// Machine-readable listing of the bundle members, the data itself is not read
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BundleManifest {
+    pub objects: Vec<ManifestEntry>,
+}
+
+impl BundleManifest {
+    /// A manifest is requested with `manifest=true` in the query of the bundle url
+    pub fn requested(query: Option<&str>) -> bool {
+        query.is_some_and(|query| query.split('&').any(|param| param == "manifest=true"))
+    }
+
+    /// Manifest as JSON and checksums in `sha256sum` format, objects without a stored
+    /// hash are only listed in the manifest
+    pub fn to_files(&self) -> anyhow::Result<Vec<(String, Bytes)>> {
+        let manifest = serde_json::to_vec_pretty(self)?;
+        let checksums = self
+            .objects
+            .iter()
+            .filter_map(|entry| {
+                let hash = entry.sha256.as_ref()?;
+                Some(format!("{hash}  {}\n", entry.path))
+            })
+            .collect::<String>();
+        Ok(vec![
+            (MANIFEST_NAME.to_string(), Bytes::from(manifest)),
+            (CHECKSUMS_NAME.to_string(), Bytes::from(checksums)),
+        ])
+    }
+}
+
+/// Name of the note that replaces a member whose data could not be fetched
+pub fn error_note_name(name: &str) -> String {
+    format!("{name}.ERROR.txt")
+}
+
+/// Content of the note that replaces a member encrypted with a customer provided key,
+/// the key is only known to the client that uploaded the object
+fn customer_key_note(name: &str) -> String {
+    format!("Object {name} could not be included: encrypted with a customer provided key\n")
+}
+
+/// Sends the FileContext and data of a stored location
+async fn send_location(
+    name: &str,
+    location: ObjectLocation,
+    backend: &Arc<Box<dyn StorageBackend>>,
+    file_info_sender: &async_channel::Sender<Message>,
+    data_tx: async_channel::Sender<
+        Result<Bytes, Box<dyn std::error::Error + Send + Sync + 'static>>,
+    >,
+) -> anyhow::Result<()> {
+    file_info_sender
+        .send(Message::FileContext(FileContext {
+            file_path: name.to_string(),
+            compressed_size: location.disk_content_len as u64,
+            decompressed_size: location.raw_content_len as u64,
+            compression: location.file_format.is_compressed(),
+            encryption_key: location.file_format.get_encryption_key_as_enc_key(),
+            ..Default::default()
+        }))
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+
+    backend
+        .get_object(location, None, data_tx)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })
+}
+
+/// Sends an uncompressed file that is generated by the proxy
+async fn send_inline(
+    name: String,
+    data: Bytes,
+    file_info_sender: &async_channel::Sender<Message>,
+    data_tx: &async_channel::Sender<
+        Result<Bytes, Box<dyn std::error::Error + Send + Sync + 'static>>,
+    >,
+) -> anyhow::Result<()> {
+    file_info_sender
+        .send(Message::FileContext(FileContext {
+            file_path: name,
+            compressed_size: data.len() as u64,
+            decompressed_size: data.len() as u64,
+            ..Default::default()
+        }))
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+    data_tx.send(Ok(data)).await.map_err(|e| {
+        tracing::error!(error = ?e, msg = e.to_string());
+        anyhow::anyhow!("Unable to send inline file")
+    })
+}
+
+/// Streams the members as archive, `inline_files` (e.g. notes about skipped objects)
+/// are appended after the stored members, the files of a `manifest` are the first members
+///
+/// Remote members are fetched from the endpoint that holds their data, if this fails
+/// (or no `remote` source is given) an error note replaces the member.
+#[tracing::instrument(
+    level = "trace",
+    skip(members, inline_files, backend, remote, manifest)
+)]
 pub async fn get_bundle(
-    path_level_vec: Vec<(String, Option<ObjectLocation>)>,
+    members: Vec<(String, BundleMember)>,
+    inline_files: Vec<(String, Bytes)>,
     backend: Arc<Box<dyn StorageBackend>>,
+    remote: Option<RemoteObjects>,
+    format: BundleFormat,
+    edit_list: Option<Vec<u64>>,
+    manifest: Option<BundleManifest>,
 ) -> Option<StreamingBlob> {
     let (file_info_sender, file_info_receiver) = async_channel::bounded(10);
     let (data_tx, data_sx) = async_channel::bounded(10);
     let (final_sender, final_receiver) = async_channel::bounded(10);
     let final_sender_clone = final_sender.clone();
     let final_receiver_clone = final_receiver.clone();
+    let members = prepare_members(members);
 
-    tokio::spawn(
+    crate::spawn(
         async move {
+            if let Some(manifest) = manifest {
+                for (name, data) in manifest.to_files()? {
+                    send_inline(name, data, &file_info_sender, &data_tx).await?;
+                }
+            }
             let mut counter = 1; // Start with 1 for comparison with len()
-            let len = path_level_vec.len();
-            for (name, loc) in path_level_vec {
-                trace!(object = name, ?loc);
-                let data_tx_clone = data_tx.clone();
-                let file_info_sender_clone = file_info_sender.clone();
-                if let Some(location) = loc {
-                    file_info_sender_clone
-                        .clone()
-                        .send(Message::FileContext(FileContext {
-                            file_path: name.to_string(),
-                            compressed_size: location.disk_content_len as u64,
-                            decompressed_size: location.raw_content_len as u64,
-                            compression: location.file_format.is_compressed(),
-                            encryption_key: location.file_format.get_encryption_key_as_enc_key(),
-                            ..Default::default()
-                        }))
-                        .await
-                        .map_err(|e| {
-                            tracing::error!(error = ?e, msg = e.to_string());
-                            e
-                        })?;
-
-                    backend
-                        .get_object(location.clone(), None, data_tx_clone)
-                        .await
-                        .map_err(|e| {
-                            tracing::error!(error = ?e, msg = e.to_string());
-                            e
-                        })?;
-                } else {
-                    file_info_sender_clone
-                        .clone()
-                        .send(Message::FileContext(FileContext {
-                            file_path: name.to_string(),
-                            is_dir: true,
-                            ..Default::default()
-                        }))
-                        .await
-                        .map_err(|e| {
-                            tracing::error!(error = ?e, msg = e.to_string());
-                            e
-                        })?;
+            let len = members.len();
+            for (name, member) in members {
+                trace!(object = name, ?member);
+                match member {
+                    BundleMember::Local(_, location) if location.is_customer_encrypted() => {
+                        warn!(object = name, "Replacing customer encrypted object with error note");
+                        send_inline(
+                            error_note_name(&name),
+                            Bytes::from(customer_key_note(&name)),
+                            &file_info_sender,
+                            &data_tx,
+                        )
+                        .await?;
+                    }
+                    BundleMember::Local(_, location) => {
+                        send_location(
+                            &name,
+                            location,
+                            &backend,
+                            &file_info_sender,
+                            data_tx.clone(),
+                        )
+                        .await?;
+                    }
+                    BundleMember::Remote(object_id) => {
+                        let staged = match &remote {
+                            Some(remote) => remote.stage(object_id).await,
+                            None => Err(anyhow::anyhow!("Data is not stored on this endpoint")),
+                        };
+                        match staged {
+                            Ok(location) if location.is_customer_encrypted() => {
+                                if let Some(remote) = &remote {
+                                    remote.release(location).await;
+                                }
+                                warn!(object = name, "Replacing customer encrypted object with error note");
+                                send_inline(
+                                    error_note_name(&name),
+                                    Bytes::from(customer_key_note(&name)),
+                                    &file_info_sender,
+                                    &data_tx,
+                                )
+                                .await?;
+                            }
+                            Ok(location) => {
+                                let sent = send_location(
+                                    &name,
+                                    location.clone(),
+                                    &backend,
+                                    &file_info_sender,
+                                    data_tx.clone(),
+                                )
+                                .await;
+                                if let Some(remote) = &remote {
+                                    remote.release(location).await;
+                                }
+                                sent?;
+                            }
+                            Err(e) => {
+                                warn!(error = ?e, object = name, "Replacing remote object with error note");
+                                let note = format!(
+                                    "Object {name} ({object_id}) could not be included: {e}\n"
+                                );
+                                send_inline(
+                                    error_note_name(&name),
+                                    Bytes::from(note),
+                                    &file_info_sender,
+                                    &data_tx,
+                                )
+                                .await?;
+                            }
+                        }
+                    }
+                    BundleMember::Directory => {
+                        file_info_sender
+                            .send(Message::FileContext(FileContext {
+                                file_path: name.to_string(),
+                                is_dir: true,
+                                ..Default::default()
+                            }))
+                            .await
+                            .map_err(|e| {
+                                tracing::error!(error = ?e, msg = e.to_string());
+                                e
+                            })?;
+                    }
                 }
                 counter += 1;
                 trace!("finished file {}/{}", counter, len)
             }
             trace!("Final counter: {}", counter);
+            for (name, data) in inline_files {
+                send_inline(name, data, &file_info_sender, &data_tx).await?;
+            }
 
             Ok::<(), anyhow::Error>(())
         }
@@ -85,7 +409,7 @@ pub async fn get_bundle(
 
     let data_clone = data_sx.clone();
 
-    tokio::spawn(
+    crate::spawn(
         async move {
             pin!(data_clone);
 
@@ -97,9 +421,22 @@ pub async fn get_bundle(
                 tracing::error!(error = ?e, msg = e.to_string());
                 e
             })?)
-            .add_transformer(ZstdDec::new())
-            .add_transformer(TarEnc::new())
-            .add_transformer(GzipEnc::new());
+            .add_transformer(ZstdDec::new());
+
+            aruna_stream_writer = match format {
+                BundleFormat::TarGz => aruna_stream_writer
+                    .add_transformer(TarEnc::new())
+                    .add_transformer(GzipEnc::new()),
+                BundleFormat::Tar => aruna_stream_writer.add_transformer(TarEnc::new()),
+                // Store mode, the zip encoder uses the same FileContext messages
+                BundleFormat::Zip => aruna_stream_writer.add_transformer(ZipEnc::new()),
+            };
+
+            // The bundle is generated on the fly, ranges discard everything before the start
+            if let Some(edit_list) = edit_list {
+                aruna_stream_writer = aruna_stream_writer
+                    .add_transformer(Filter::new_with_edit_list(Some(edit_list)));
+            }
             aruna_stream_writer
                 .add_message_receiver(file_info_receiver.clone())
                 .await
@@ -122,3 +459,260 @@ pub async fn get_bundle(
         s3_error!(InternalError, "Internal processing error")
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_backends::memory_backend::MemoryBackend;
+    use diesel_ulid::DieselUlid;
+    use futures_util::StreamExt;
+
+    /// Memory backend with the key of every local member as its content
+    fn backend(members: &[(String, BundleMember)]) -> Arc<Box<dyn StorageBackend>> {
+        let backend = MemoryBackend::new();
+        for (_, member) in members {
+            if let BundleMember::Local(_, location) = member {
+                backend.insert(location, location.key.clone());
+            }
+        }
+        Arc::new(Box::new(backend))
+    }
+
+    fn location(content: &str) -> BundleMember {
+        BundleMember::Local(
+            DieselUlid::generate(),
+            ObjectLocation {
+                id: DieselUlid::generate(),
+                key: content.to_string(),
+                raw_content_len: content.len() as i64,
+                disk_content_len: content.len() as i64,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Two datasets with a README.txt each, bundled side by side
+    fn colliding_levels() -> Vec<(String, BundleMember)> {
+        vec![
+            ("/sub/".to_string(), BundleMember::Directory),
+            ("/README.txt".to_string(), location("first readme")),
+            ("/sub/a.txt".to_string(), location("a")),
+            ("/b.txt".to_string(), location("b")),
+            ("/README.txt".to_string(), location("second readme")),
+            ("/sub/".to_string(), BundleMember::Directory),
+        ]
+    }
+
+    /// Member names of an uncompressed tar archive
+    fn tar_members(data: &[u8]) -> Vec<String> {
+        let mut members = Vec::new();
+        let mut offset = 0;
+        while offset + 512 <= data.len() {
+            let header = &data[offset..offset + 512];
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            let name = String::from_utf8_lossy(&header[..100])
+                .trim_end_matches('\0')
+                .to_string();
+            let size = String::from_utf8_lossy(&header[124..136]).to_string();
+            let size = u64::from_str_radix(size.trim_matches(|c: char| c == '\0' || c == ' '), 8)
+                .expect("Invalid tar size field");
+            members.push(name);
+            offset += 512 + size.div_ceil(512) as usize * 512;
+        }
+        members
+    }
+
+    #[test]
+    fn members_are_unique_and_sorted() {
+        let members = prepare_members(colliding_levels());
+        let names = members.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["README.txt", "README_1.txt", "b.txt", "sub/", "sub/a.txt"]
+        );
+
+        // The input order does not change the result
+        let levels = colliding_levels();
+        let mut reversed = levels.clone();
+        reversed.reverse();
+        assert_eq!(prepare_members(levels), prepare_members(reversed));
+    }
+
+    #[test]
+    fn suffix_does_not_collide_with_existing_names() {
+        let members = prepare_members(vec![
+            ("/x.txt".to_string(), location("1")),
+            ("/x.txt".to_string(), location("2")),
+            ("/x_1.txt".to_string(), location("3")),
+            ("/.hidden".to_string(), location("4")),
+            ("/.hidden".to_string(), location("5")),
+        ]);
+        let names = members.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![".hidden", ".hidden_1", "x.txt", "x_1.txt", "x_2.txt"]
+        );
+    }
+
+    #[test]
+    fn archive_format_from_request() {
+        assert_eq!(BundleFormat::from_request(None, None), None);
+        assert_eq!(
+            BundleFormat::from_request(None, Some("x-id=GetObject&archive=tar.gz")),
+            Some(BundleFormat::TarGz)
+        );
+        assert_eq!(
+            BundleFormat::from_request(Some("text/html, application/x-tar;q=0.9"), None),
+            Some(BundleFormat::Tar)
+        );
+        // The query parameter takes precedence
+        assert_eq!(
+            BundleFormat::from_request(Some("application/x-tar"), Some("archive=zip")),
+            Some(BundleFormat::Zip)
+        );
+        assert_eq!(
+            BundleFormat::from_request(Some("*/*"), Some("archive=rar")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn tar_members_match_prepared_names() {
+        let mut expected = prepare_members(colliding_levels())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        expected.push("WARNINGS.txt".to_string());
+
+        let members = colliding_levels();
+        let mut body = get_bundle(
+            members.clone(),
+            vec![("WARNINGS.txt".to_string(), Bytes::from_static(b"skipped"))],
+            backend(&members),
+            None,
+            BundleFormat::Tar,
+            None,
+            None,
+        )
+        .await
+        .expect("Missing bundle body");
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.expect("Bundle stream failed"));
+        }
+
+        // Directory entries may or may not keep their trailing slash
+        let members = tar_members(&data)
+            .into_iter()
+            .map(|name| name.trim_end_matches('/').to_string())
+            .collect::<Vec<_>>();
+        let expected = expected
+            .iter()
+            .map(|name| name.trim_end_matches('/').to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(members, expected);
+    }
+
+    #[test]
+    fn manifest_files() {
+        assert!(BundleManifest::requested(Some(
+            "x-id=GetObject&manifest=true"
+        )));
+        assert!(!BundleManifest::requested(Some("manifest=false")));
+        assert!(!BundleManifest::requested(None));
+
+        let manifest = BundleManifest {
+            objects: vec![
+                ManifestEntry {
+                    path: "a.txt".to_string(),
+                    object_id: DieselUlid::generate().to_string(),
+                    size: 1,
+                    sha256: Some("abc".to_string()),
+                },
+                ManifestEntry {
+                    path: "sub/b.txt".to_string(),
+                    object_id: DieselUlid::generate().to_string(),
+                    size: 2,
+                    sha256: None,
+                },
+            ],
+        };
+        let files = manifest.to_files().unwrap();
+        assert_eq!(files[0].0, MANIFEST_NAME);
+        let json: serde_json::Value = serde_json::from_slice(&files[0].1).unwrap();
+        assert_eq!(json["objects"][1]["path"], "sub/b.txt");
+        assert!(json["objects"][1]["sha256"].is_null());
+        // Objects without a hash are left out of the checksum file
+        assert_eq!(
+            files[1],
+            (CHECKSUMS_NAME.to_string(), Bytes::from("abc  a.txt\n"))
+        );
+    }
+
+    #[tokio::test]
+    async fn manifest_is_the_first_member() {
+        let members = vec![("/a.txt".to_string(), location("a"))];
+        let mut body = get_bundle(
+            members.clone(),
+            Vec::new(),
+            backend(&members),
+            None,
+            BundleFormat::Tar,
+            None,
+            Some(BundleManifest {
+                objects: vec![ManifestEntry {
+                    path: "a.txt".to_string(),
+                    object_id: DieselUlid::generate().to_string(),
+                    size: 1,
+                    sha256: Some("abc".to_string()),
+                }],
+            }),
+        )
+        .await
+        .expect("Missing bundle body");
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.expect("Bundle stream failed"));
+        }
+        assert_eq!(
+            tar_members(&data),
+            vec![MANIFEST_NAME, CHECKSUMS_NAME, "a.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unavailable_remote_object_is_replaced_by_note() {
+        let members = vec![
+            ("/a.txt".to_string(), location("a")),
+            (
+                "/remote.txt".to_string(),
+                BundleMember::Remote(DieselUlid::generate()),
+            ),
+            ("/z.txt".to_string(), location("z")),
+        ];
+        let mut body = get_bundle(
+            members.clone(),
+            Vec::new(),
+            backend(&members),
+            None,
+            BundleFormat::Tar,
+            None,
+            None,
+        )
+        .await
+        .expect("Missing bundle body");
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.expect("Bundle stream failed"));
+        }
+
+        // The archive is complete, the remote member is replaced by a note
+        assert_eq!(
+            tar_members(&data),
+            vec!["a.txt", "remote.txt.ERROR.txt", "z.txt"]
+        );
+        assert!(String::from_utf8_lossy(&data).contains("could not be included"));
+    }
+}
@@ -1 +1,2 @@
 pub mod bundle_helper;
+pub mod remote;
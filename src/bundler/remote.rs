@@ -0,0 +1,244 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::replication::checksum::ChecksumAlgorithm;
+use crate::s3_frontend::utils::buffered_s3_sink::BufferedS3Sink;
+use crate::structs::{FileFormat, ObjectLocation};
+use crate::CONFIG;
+use anyhow::{anyhow, bail, Result};
+use aruna_rust_api::api::dataproxy::services::v2::{
+    error_message, pull_replication_request::Message,
+    pull_replication_response::Message as ResponseMessage, Chunk, ChunkAckMessage, Empty,
+    ErrorMessage, InfoAckMessage, InitMessage, ObjectInfo, PullReplicationRequest,
+    PullReplicationResponse,
+};
+use bytes::Bytes;
+use diesel_ulid::DieselUlid;
+use pithos_lib::transformers::footer_extractor::FooterExtractor;
+use pithos_lib::{streamreadwrite::GenericStreamReadWriter, transformer::ReadWriter};
+use std::sync::Arc;
+use tokio::pin;
+use tonic::Streaming;
+use tracing::{error, info_span, trace, Instrument};
+
+type DataSender = async_channel::Sender<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>;
+
+/// Fetches objects of a bundle whose data is stored on another endpoint
+///
+/// The object is pulled over the replication channel into a temporary location on this
+/// proxy, which is then streamed like a local object and deleted afterwards. Pithos
+/// footers are only readable after the whole file was received, so the data can not
+/// be decrypted while it is pulled.
+#[derive(Clone)]
+pub struct RemoteObjects {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+}
+
+impl RemoteObjects {
+    pub fn new(cache: Arc<Cache>, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        Self { cache, backend }
+    }
+
+    /// Pulls the object into a temporary pithos location
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn stage(&self, object_id: DieselUlid) -> Result<ObjectLocation> {
+        let (object, _) = self.cache.get_resource_cloned(&object_id, false).await?;
+        let endpoint_id = object
+            .owning_endpoint(&CONFIG.proxy.endpoint_id)
+            .ok_or_else(|| anyhow!("No endpoint holds the data of object {object_id}"))?;
+        let parents = self.cache.get_single_parent(&object_id).await?;
+        let storage = self.cache.get_storage_policy(&parents);
+        let mut location = self
+            .backend
+            .initialize_location(
+                &object,
+                Some(object.content_len),
+                parents,
+                storage.as_deref(),
+                true,
+            )
+            .await?;
+
+        let (requests, responses, checksum_algorithm) = {
+            let client = self.cache.aruna_client.read().await;
+            let client = client
+                .as_ref()
+                .ok_or_else(|| anyhow!("Aruna server is not available"))?;
+            let init = PullReplicationRequest {
+                message: Some(Message::InitMessage(InitMessage {
+                    dataproxy_id: CONFIG.proxy.endpoint_id.to_string(),
+                    object_ids: vec![object_id.to_string()],
+                })),
+            };
+            client.pull_replication(init, endpoint_id, false).await?
+        };
+
+        let (data_sender, data_receiver) = async_channel::bounded(10);
+        let receiver = crate::spawn(
+            async move {
+                let result = receive_object(
+                    object_id,
+                    &requests,
+                    responses,
+                    checksum_algorithm,
+                    data_sender,
+                )
+                .await;
+                if result.is_err() {
+                    // Best effort, the sending proxy also stops on a closed stream
+                    let _ = requests
+                        .send(PullReplicationRequest {
+                            message: Some(Message::ErrorMessage(ErrorMessage {
+                                error: Some(error_message::Error::Abort(Empty {})),
+                            })),
+                        })
+                        .await;
+                }
+                result
+            }
+            .instrument(info_span!("bundle_remote_receiver")),
+        );
+
+        pin!(data_receiver);
+        let mut awr = GenericStreamReadWriter::new_with_sink(
+            data_receiver,
+            BufferedS3Sink::new(
+                self.backend.clone(),
+                location.clone(),
+                None,
+                None,
+                false,
+                None,
+                false,
+            )
+            .0,
+        );
+        let (extractor, footer_receiver) =
+            FooterExtractor::new(Some(CONFIG.proxy.get_private_key_x25519()?));
+        awr = awr.add_transformer(extractor);
+        let written = awr.process().await;
+        // Unblocks the receiver if staging stopped early
+        drop(awr);
+        data_receiver.close();
+        let raw_size = receiver.await?;
+        let result = match (written, raw_size) {
+            (Ok(_), Ok(raw_size)) => footer_receiver
+                .try_recv()
+                .map_err(|e| anyhow!("Missing footer: {e}"))
+                .and_then(|footer| {
+                    let key = footer
+                        .encryption_keys
+                        .and_then(|keys| keys.keys.first().map(|(key, _)| *key))
+                        .ok_or_else(|| anyhow!("Unable to extract key"))?;
+                    location.file_format = FileFormat::Pithos(key);
+                    location.disk_content_len = footer.eof_metadata.disk_file_size as i64;
+                    location.raw_content_len = raw_size;
+                    Ok(())
+                }),
+            (Err(e), _) => Err(anyhow!(e)),
+            (_, Err(e)) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                trace!(?location, %endpoint_id, "Staged remote object");
+                Ok(location)
+            }
+            Err(e) => {
+                error!(error = ?e, %object_id, %endpoint_id, msg = "Unable to fetch remote object");
+                if let Err(e) = self.backend.delete_object(location).await {
+                    error!(error = ?e, msg = "Unable to delete staged location");
+                }
+                Err(anyhow!(
+                    "Unable to fetch data from endpoint {endpoint_id}: {e}"
+                ))
+            }
+        }
+    }
+
+    /// Removes a staged location after it was streamed
+    pub async fn release(&self, location: ObjectLocation) {
+        if let Err(e) = self.backend.delete_object(location).await {
+            error!(error = ?e, msg = "Unable to delete staged location");
+        }
+    }
+}
+
+/// Receives a single object from the replication stream, returns its raw size.
+/// Chunks are not retried, a failed fetch is reported in the bundle instead.
+async fn receive_object(
+    object_id: DieselUlid,
+    requests: &tokio::sync::mpsc::Sender<PullReplicationRequest>,
+    mut responses: Streaming<PullReplicationResponse>,
+    checksum_algorithm: ChecksumAlgorithm,
+    data_sender: DataSender,
+) -> Result<i64> {
+    let object_id = object_id.to_string();
+    let mut expected_chunks = None;
+    let mut raw_size = 0;
+    let mut received = 0;
+    while let Some(response) = responses.message().await? {
+        match response.message {
+            Some(ResponseMessage::ObjectInfo(ObjectInfo {
+                object_id: id,
+                chunks,
+                raw_size: size,
+                ..
+            })) => {
+                if id != object_id {
+                    bail!("Received unexpected object {id}");
+                }
+                expected_chunks = Some(chunks);
+                raw_size = size;
+                requests
+                    .send(PullReplicationRequest {
+                        message: Some(Message::InfoAckMessage(InfoAckMessage { object_id: id })),
+                    })
+                    .await?;
+            }
+            Some(ResponseMessage::Chunk(Chunk {
+                object_id: id,
+                chunk_idx,
+                data,
+                checksum,
+            })) => {
+                if id != object_id || expected_chunks.is_none() {
+                    bail!("Received unexpected chunk of object {id}");
+                }
+                if chunk_idx != received {
+                    bail!("Received chunk {chunk_idx}, expected {received}");
+                }
+                if !checksum_algorithm.verify(&checksum, &data) {
+                    bail!("Checksum mismatch for chunk {chunk_idx}");
+                }
+                data_sender
+                    .send(Ok(Bytes::from(data)))
+                    .await
+                    .map_err(|_| anyhow!("Staging stopped"))?;
+                requests
+                    .send(PullReplicationRequest {
+                        message: Some(Message::ChunkAckMessage(ChunkAckMessage {
+                            object_id: id,
+                            chunk_idx,
+                        })),
+                    })
+                    .await?;
+                received += 1;
+                if Some(received) == expected_chunks {
+                    requests
+                        .send(PullReplicationRequest {
+                            message: Some(Message::FinishMessage(Empty {})),
+                        })
+                        .await?;
+                    return Ok(raw_size);
+                }
+            }
+            Some(ResponseMessage::FinishMessage(..)) => break,
+            None => bail!("No message provided in PullReplicationResponse"),
+        }
+    }
+    match expected_chunks {
+        Some(chunks) if chunks == received => Ok(raw_size),
+        Some(chunks) => Err(anyhow!("Received {received} of {chunks} chunks")),
+        None => Err(anyhow!("Object was not sent")),
+    }
+}
@@ -0,0 +1,284 @@
+use crate::structs::{AccessKeyPermissions, ResourceStates};
+use ahash::RandomState;
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use http::Method;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccessCacheKey {
+    // None for anonymous requests
+    pub access_key: Option<String>,
+    pub method: Method,
+    pub path: String,
+}
+
+impl AccessCacheKey {
+    pub fn new(access_key: Option<&str>, method: &Method, bucket: &str, key: &str) -> Self {
+        AccessCacheKey {
+            access_key: access_key.map(|k| k.to_string()),
+            method: method.clone(),
+            path: format!("{bucket}/{}", key.trim_start_matches('/')),
+        }
+    }
+}
+
+/// The request independent part of a successful object access check
+#[derive(Debug, Clone)]
+pub struct CachedAccess {
+    pub resource_states: ResourceStates,
+    pub user: Option<(AccessKeyPermissions, HashMap<String, String>)>,
+}
+
+struct Entry {
+    access: CachedAccess,
+    resources: HashSet<DieselUlid>,
+    user_id: Option<DieselUlid>,
+    inserted: Instant,
+}
+
+/// Bounded, time limited memoization of object access checks
+///
+/// Entries are invalidated as soon as the cache receives an update for any resource
+/// in their path (or a parent of an updated resource) or for the permissions of the
+/// user, so revocations are honored on the next request. The ttl only bounds the
+/// staleness of state that is not tracked, e.g. attribute changes of the server.
+///
+/// Every invalidation bumps a generation, checks that started before an invalidation
+/// are not stored as they may have seen the old state.
+pub struct AccessCache {
+    entries: DashMap<AccessCacheKey, Entry, RandomState>,
+    generation: AtomicU64,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl AccessCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        AccessCache {
+            entries: DashMap::default(),
+            generation: AtomicU64::new(0),
+            ttl,
+            capacity,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && !self.ttl.is_zero()
+    }
+
+    /// Current generation, has to be read before the access check is started
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get(&self, key: &AccessCacheKey) -> Option<CachedAccess> {
+        let entry = self.entries.get(key)?;
+        if entry.inserted.elapsed() >= self.ttl {
+            drop(entry);
+            self.entries.remove(key);
+            return None;
+        }
+        Some(entry.access.clone())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, access))]
+    pub fn insert(&self, key: AccessCacheKey, access: CachedAccess, generation: u64) {
+        if !self.is_enabled() || generation != self.generation() {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries
+                .retain(|_, entry| entry.inserted.elapsed() < self.ttl);
+            if self.entries.len() >= self.capacity {
+                return;
+            }
+        }
+        let resources = [
+            access.resource_states.get_project(),
+            access.resource_states.get_collection(),
+            access.resource_states.get_dataset(),
+            access.resource_states.get_object(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|object| object.id)
+        .collect();
+        let user_id = access.user.as_ref().map(|(user, _)| user.user_id);
+        self.entries.insert(
+            key.clone(),
+            Entry {
+                access,
+                resources,
+                user_id,
+                inserted: Instant::now(),
+            },
+        );
+        // An invalidation between the check above and the insert
+        if generation != self.generation() {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Drops all entries whose path contains one of the resources
+    #[tracing::instrument(level = "trace", skip(self, ids))]
+    pub fn invalidate_resources(&self, ids: &[DieselUlid]) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.entries
+            .retain(|_, entry| !ids.iter().any(|id| entry.resources.contains(id)));
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn invalidate_user(&self, user_id: &DieselUlid) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.entries
+            .retain(|_, entry| entry.user_id.as_ref() != Some(user_id));
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn invalidate_access_key(&self, access_key: &str) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.entries
+            .retain(|key, _| key.access_key.as_deref() != Some(access_key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{DbPermissionLevel, Object, ObjectType};
+
+    fn states() -> (ResourceStates, DieselUlid, DieselUlid) {
+        let project = Object {
+            id: DieselUlid::generate(),
+            name: "project".to_string(),
+            object_type: ObjectType::Project,
+            ..Default::default()
+        };
+        let object = Object {
+            id: DieselUlid::generate(),
+            name: "file.txt".to_string(),
+            object_type: ObjectType::Object,
+            ..Default::default()
+        };
+        let (project_id, object_id) = (project.id, object.id);
+        let mut states = ResourceStates::new();
+        states.set_project(project);
+        states.set_object(object);
+        (states, project_id, object_id)
+    }
+
+    fn access(states: ResourceStates, user_id: DieselUlid, project: DieselUlid) -> CachedAccess {
+        let key = AccessKeyPermissions {
+            access_key: user_id.to_string(),
+            user_id,
+            secret: String::new(),
+            is_service_account: false,
+            permissions: HashMap::from([(project, DbPermissionLevel::Read)]),
+            session: None,
+        };
+        CachedAccess {
+            resource_states: states,
+            user: Some((key, HashMap::new())),
+        }
+    }
+
+    #[test]
+    fn test_permission_revocation() {
+        let cache = AccessCache::new(Duration::from_secs(60), 100);
+        let user_id = DieselUlid::generate();
+        let (states, project_id, _) = states();
+        let key = AccessCacheKey::new(
+            Some(&user_id.to_string()),
+            &Method::GET,
+            "project",
+            "file.txt",
+        );
+        cache.insert(
+            key.clone(),
+            access(states.clone(), user_id, project_id),
+            cache.generation(),
+        );
+        assert!(cache.get(&key).is_some());
+
+        // Permission updates of the user
+        cache.invalidate_user(&user_id);
+        assert!(cache.get(&key).is_none());
+
+        // Checks that raced with an invalidation are not stored
+        let generation = cache.generation();
+        cache.invalidate_resources(&[DieselUlid::generate()]);
+        cache.insert(
+            key.clone(),
+            access(states.clone(), user_id, project_id),
+            generation,
+        );
+        assert!(cache.get(&key).is_none());
+
+        // Revoked access keys
+        cache.insert(
+            key.clone(),
+            access(states, user_id, project_id),
+            cache.generation(),
+        );
+        cache.invalidate_access_key(&user_id.to_string());
+        assert!(cache.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_resource_updates() {
+        let cache = AccessCache::new(Duration::from_secs(60), 100);
+        let user_id = DieselUlid::generate();
+        let (states, project_id, object_id) = states();
+        let key = AccessCacheKey::new(None, &Method::GET, "project", "/file.txt");
+        assert_eq!(key.path, "project/file.txt");
+
+        cache.insert(
+            key.clone(),
+            access(states.clone(), user_id, project_id),
+            cache.generation(),
+        );
+        cache.invalidate_resources(&[DieselUlid::generate()]);
+        assert!(cache.get(&key).is_some());
+
+        // e.g. a data class change of the project
+        cache.invalidate_resources(&[project_id]);
+        assert!(cache.get(&key).is_none());
+
+        cache.insert(
+            key.clone(),
+            access(states, user_id, project_id),
+            cache.generation(),
+        );
+        cache.invalidate_resources(&[object_id]);
+        assert!(cache.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let (states, project_id, _) = states();
+        let user_id = DieselUlid::generate();
+
+        let expired = AccessCache::new(Duration::ZERO, 100);
+        assert!(!expired.is_enabled());
+
+        let cache = AccessCache::new(Duration::from_secs(60), 1);
+        let first = AccessCacheKey::new(None, &Method::GET, "project", "a");
+        let second = AccessCacheKey::new(None, &Method::GET, "project", "b");
+        cache.insert(
+            first.clone(),
+            access(states.clone(), user_id, project_id),
+            cache.generation(),
+        );
+        cache.insert(
+            second.clone(),
+            access(states, user_id, project_id),
+            cache.generation(),
+        );
+        assert!(cache.get(&first).is_some());
+        assert!(cache.get(&second).is_none());
+    }
+}
@@ -1,14 +1,28 @@
-use super::grpc_query_handler::GrpcQueryHandler;
+use super::access_cache::AccessCache;
+use super::deletion::{DeleteGuard, ObjectLocks, ReadGuard};
+use super::grpc_query_handler::{is_not_found, is_transient, GrpcQueryHandler};
+use super::policies::ProjectPolicies;
+use super::pubkey_refresh::PubkeyRefresh;
+use super::sync_state::{SyncSnapshot, SyncState};
 use crate::auth::auth::AuthHandler;
+use crate::bundler::bundle_helper::{BundleManifest, BundleMember, ManifestEntry};
 use crate::caching::grpc_query_handler::sort_objects;
+use crate::config::{StoragePolicy, Webhook};
 use crate::data_backends::storage_backend::StorageBackend;
 use crate::database::persistence::delete_parts_by_upload_id;
+use crate::metrics::CACHE_METRICS;
+use crate::replication::progress::ReplicationProgress;
 use crate::replication::replication_handler::ReplicationMessage;
 use crate::s3_frontend::data_handler::DataHandler;
+use crate::s3_frontend::utils::bucket_name::{key_to_path, path_to_key};
 use crate::structs::{
-    AccessKeyPermissions, Bundle, DbPermissionLevel, LocationBinding, ObjectType, TypedId,
-    UploadPart, User,
+    AccessKeyPermissions, BandwidthPolicy, BucketPolicy, BucketUsage, Bundle, CORSConfiguration,
+    CollisionPolicy, ContentChecksum, DbPermissionLevel, LocationBinding, MultipartUploadInfo,
+    ObjectLockPolicy, ObjectType, ObjectUsage, PendingNotification, PendingWebhook, PrefixStats,
+    QuotaPolicy, ReplicationPolicy, SessionInfo, SyncStatus, TypedId, UploadLimitsPolicy,
+    UploadPart, User, ValidationSpec, VersionVariant,
 };
+use crate::CONFIG;
 use crate::{
     database::{database::Database, persistence::WithGenericBytes},
     structs::{Object, ObjectLocation, PubKey},
@@ -16,27 +30,47 @@ use crate::{
 use ahash::RandomState;
 use anyhow::Result;
 use anyhow::{anyhow, bail};
+use aruna_rust_api::api::storage::models::v2::DataClass;
 use aruna_rust_api::api::storage::models::v2::User as GrpcUser;
 use async_channel::Sender;
+use chrono::Utc;
 use crossbeam_skiplist::SkipMap;
 use dashmap::DashMap;
 use diesel_ulid::DieselUlid;
 use jsonwebtoken::DecodingKey;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use s3s::auth::SecretKey;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ops::Deref;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{str::FromStr, sync::Arc};
 use tokio::sync::RwLock;
 use tokio_postgres::GenericClient;
-use tracing::{debug, error, info_span, trace, Instrument};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+/// Result of a forced resync of a cache entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResyncOutcome {
+    Refreshed,
+    /// Does not exist upstream (anymore), the entry was removed
+    Deleted,
+}
 
 pub struct Cache {
     // Map DieselUlid as key and (User, Vec<String>) as value -> Vec<String> is a list of registered access keys -> access_keys
     users: DashMap<DieselUlid, Arc<RwLock<(User, Vec<String>)>>, RandomState>,
     // Permissions Maybe TODO: Arc<RwLock<AccessKeyPermissions>>?
     access_keys: DashMap<String, Arc<RwLock<AccessKeyPermissions>>, RandomState>,
+    // Last validation of an access key against the server
+    key_validations: DashMap<String, Instant, RandomState>,
+    // Access keys that were looked up without success, avoids repeated lookups of invalid keys
+    unknown_keys: DashMap<String, Instant, RandomState>,
+    // Objects whose location was looked up without success, avoids repeated lookups
+    missing_locations: DashMap<DieselUlid, Instant, RandomState>,
+    // Memoized object access checks, invalidated on resource and permission updates
+    pub access_cache: AccessCache,
+    // Orders reads of backend data against deletions
+    pub(crate) object_locks: ObjectLocks,
     // Map with ObjectId as key and Object as value
     resources: DashMap<
         DieselUlid,
@@ -49,6 +83,30 @@ pub struct Cache {
     // Parts sorted by upload_id
     multi_parts: DashMap<String, Vec<UploadPart>>,
 
+    // In-progress multipart uploads by upload_id
+    multipart_uploads: DashMap<String, MultipartUploadInfo, RandomState>,
+
+    // Rollup counters (object count, raw size) per hierarchy node
+    prefix_stats: DashMap<DieselUlid, PrefixStats, RandomState>,
+
+    // Usage contribution per object and the aggregated usage per project
+    object_usage: DashMap<DieselUlid, ObjectUsage, RandomState>,
+    bucket_usage: DashMap<DieselUlid, BucketUsage, RandomState>,
+
+    // Compiled policy artifacts (e.g. cors) per project
+    policies: DashMap<DieselUlid, ProjectPolicies, RandomState>,
+
+    // Progress of running and failed replications
+    pub(crate) replication_progress: Arc<ReplicationProgress>,
+
+    // Server notifications that failed and are retried in the background
+    pending_notifications: DashMap<DieselUlid, PendingNotification, RandomState>,
+    // Webhook deliveries, including the dead letters
+    pending_webhooks: DashMap<DieselUlid, PendingWebhook, RandomState>,
+
+    // Timestamps and errors of the synchronisation with the server
+    pub(crate) sync_state: SyncState,
+
     // Maps with path / key as key and set of all ObjectIds as value
     // /project1/collection1/dataset1 -> ObjectID
     // /project1/collection1/exaset1/object1 -> ObjectID
@@ -66,6 +124,8 @@ pub struct Cache {
     //}
     // Pubkeys; TODO: Expand to endpoint ?
     pubkeys: DashMap<i32, (PubKey, DecodingKey), RandomState>,
+    // Fetches of pubkeys with unknown key ids, e.g. after a key rotation
+    pubkey_refresh: PubkeyRefresh,
 
     // Persistence layer
     persistence: RwLock<Option<Database>>,
@@ -102,11 +162,32 @@ impl Cache {
         let cache = Arc::new(Cache {
             users: DashMap::default(),
             access_keys: DashMap::default(),
+            key_validations: DashMap::default(),
+            unknown_keys: DashMap::default(),
+            missing_locations: DashMap::default(),
+            access_cache: AccessCache::new(
+                Duration::from_secs(CONFIG.proxy.access_cache_ttl),
+                CONFIG.proxy.access_cache_size,
+            ),
+            object_locks: ObjectLocks::default(),
             resources: DashMap::default(),
             bundles: DashMap::default(),
             multi_parts: DashMap::default(),
+            multipart_uploads: DashMap::default(),
+            prefix_stats: DashMap::default(),
+            object_usage: DashMap::default(),
+            bucket_usage: DashMap::default(),
+            policies: DashMap::default(),
+            replication_progress: Arc::new(ReplicationProgress::default()),
+            pending_notifications: DashMap::default(),
+            pending_webhooks: DashMap::default(),
+            sync_state: SyncState::default(),
             paths: SkipMap::new(),
             pubkeys: DashMap::default(),
+            pubkey_refresh: PubkeyRefresh::new(
+                Duration::from_secs(CONFIG.proxy.pubkey_fetch_interval),
+                Duration::from_secs(CONFIG.proxy.credential_negative_ttl),
+            ),
             persistence: RwLock::new(None),
             aruna_client: RwLock::new(None),
             auth: RwLock::new(None),
@@ -141,7 +222,7 @@ impl Cache {
             );
 
             let notifications_handler_clone = notication_handler.clone();
-            tokio::spawn(
+            crate::spawn(
                 async move {
                     notifications_handler_clone
                         .clone()
@@ -152,6 +233,20 @@ impl Cache {
                 .instrument(info_span!("create_notifications_channel")),
             );
 
+            // Compare the local clock with the server clock at startup and periodically
+            let skew_handler_clone = notication_handler.clone();
+            crate::spawn(
+                async move {
+                    loop {
+                        if let Err(e) = skew_handler_clone.check_clock_skew().await {
+                            tracing::error!(error = ?e, msg = e.to_string());
+                        }
+                        tokio::time::sleep(std::time::Duration::from_secs(5 * 60)).await;
+                    }
+                }
+                .instrument(info_span!("check_clock_skew")),
+            );
+
             cache.set_notifications(notication_handler).await;
             debug!("initialized notification handler");
         };
@@ -183,14 +278,10 @@ impl Cache {
     /// Requests a secret key from the cache
     pub async fn get_secret(&self, access_key: &str) -> Result<SecretKey> {
         let secret = self
-            .access_keys
-            .get(access_key)
-            .ok_or_else(|| anyhow!("User not found"))?
-            .value()
-            .read()
+            .get_key_perms(access_key)
             .await
-            .secret
-            .clone();
+            .ok_or_else(|| anyhow!("User not found"))?
+            .secret;
         if secret.is_empty() {
             error!("secret is empty");
             Err(anyhow!("Secret is empty"))
@@ -249,12 +340,30 @@ impl Cache {
             part_vec.push(part);
         }
 
-        for (upload_id, parts) in parts_map {
+        for (upload_id, mut parts) in parts_map {
+            parts.sort_by_key(|part| part.part_number);
             self.multi_parts.insert(upload_id, parts);
         }
 
         debug!("synced parts");
 
+        for upload in MultipartUploadInfo::get_all(&client).await? {
+            self.multipart_uploads
+                .insert(upload.upload_id.clone(), upload);
+        }
+        debug!("synced multipart uploads");
+
+        for notification in PendingNotification::get_all(&client).await? {
+            self.pending_notifications
+                .insert(notification.id, notification);
+        }
+        debug!("synced pending notifications");
+
+        for webhook in PendingWebhook::get_all(&client).await? {
+            self.pending_webhooks.insert(webhook.id, webhook);
+        }
+        debug!("synced pending webhooks");
+
         for object in database_objects {
             let mut location = None;
             if object.object_type == ObjectType::Object {
@@ -276,7 +385,7 @@ impl Cache {
                             ?before_location,
                             "finalizing missing temp location"
                         );
-                        tokio::spawn(
+                        crate::spawn(
                             async move {
                                 DataHandler::finalize_location(
                                     object,
@@ -284,6 +393,7 @@ impl Cache {
                                     backend,
                                     before_location,
                                     None,
+                                    None,
                                 )
                                 .await
                             }
@@ -293,6 +403,8 @@ impl Cache {
                 }
             }
 
+            let initial_size = location.as_ref().map(|l| l.raw_content_len);
+            self.update_policies(&object);
             self.resources.insert(
                 object.id,
                 (
@@ -300,6 +412,11 @@ impl Cache {
                     Arc::new(RwLock::new(location)),
                 ),
             );
+            if let Some(size) = initial_size {
+                self.update_prefix_stats(&object.id, 1, size).await;
+            }
+            CACHE_METRICS.resources.set(self.resources.len() as i64);
+            self.refresh_usage(&object.id).await;
             if let Some(parents) = object.parents {
                 if parents.is_empty() {
                     prefixes.insert(object.id, vec![object.name.clone()]);
@@ -356,11 +473,7 @@ impl Cache {
             user_info.1.push(access_key.to_string());
             token
         };
-        let new_secret = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(30)
-            .map(char::from)
-            .collect::<String>();
+        let new_secret = generate_secret();
 
         let new_access_key = AccessKeyPermissions {
             access_key: access_key.to_string(),
@@ -368,6 +481,7 @@ impl Cache {
             is_service_account: user.read().await.0.is_service_account,
             secret: new_secret.clone(),
             permissions: permissions,
+            session: None,
         };
 
         if let Some(pers) = self.persistence.read().await.as_ref() {
@@ -379,16 +493,137 @@ impl Cache {
         let new_key_access = Arc::new(RwLock::new(new_access_key));
         self.access_keys
             .insert(access_key.to_string(), new_key_access);
+        self.access_cache.invalidate_access_key(access_key);
+        self.key_validations
+            .insert(access_key.to_string(), Instant::now());
+        self.unknown_keys.remove(access_key);
         Ok((access_key.to_string(), new_secret))
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
-    /// Requests a secret key from the cache
+    /// Revokes the credentials of an access key and all sessions derived from it,
+    /// the key is also removed from the persistence to prevent a reload on the next lookup
     pub async fn revoke_secret(&self, access_key: &str) -> Result<()> {
-        self.access_keys.remove(access_key);
+        let keys = self
+            .access_keys
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect::<Vec<_>>();
+        for (key, permissions) in keys {
+            let is_session = permissions
+                .read()
+                .await
+                .session
+                .as_ref()
+                .is_some_and(|session| session.parent_key == access_key);
+            if is_session {
+                self.evict_key(&key).await?;
+            }
+        }
+        self.evict_key(access_key).await?;
         Ok(())
     }
 
+    /// Mints temporary credentials derived from `parent_key`, a scope restricts them to
+    /// a single resource (and its children) with at most the given permission level
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn create_session_key(
+        &self,
+        parent_key: &str,
+        user_id: &DieselUlid,
+        duration: Duration,
+        scope: Option<(DieselUlid, DbPermissionLevel)>,
+    ) -> Result<(String, String, chrono::DateTime<Utc>)> {
+        let user = self
+            .users
+            .get(user_id)
+            .ok_or_else(|| anyhow!("User not found"))?
+            .clone();
+        let (parent_permissions, is_service_account) = {
+            let user = user.read().await;
+            (
+                user.0
+                    .get_key_permissions(parent_key)
+                    .ok_or_else(|| anyhow!("Access key not found"))?,
+                user.0.is_service_account,
+            )
+        };
+        let session = SessionInfo {
+            parent_key: parent_key.to_string(),
+            expires_at: Utc::now() + chrono::Duration::from_std(duration)?,
+            scope,
+        };
+        let permissions = self
+            .session_permissions(parent_permissions, Some(&session))
+            .await
+            .ok_or_else(|| anyhow!("Insufficient permissions for the requested scope"))?;
+
+        let access_key = DieselUlid::generate().to_string();
+        let secret = generate_secret();
+        let expires_at = session.expires_at;
+        let key = AccessKeyPermissions {
+            access_key: access_key.clone(),
+            user_id: *user_id,
+            secret: secret.clone(),
+            is_service_account,
+            permissions,
+            session: Some(session),
+        };
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            key.upsert(persistence.get_client().await?.client()).await?;
+        }
+        self.access_keys
+            .insert(access_key.clone(), Arc::new(RwLock::new(key)));
+        self.key_validations
+            .insert(access_key.clone(), Instant::now());
+        // Removed together with the user
+        user.write().await.1.push(access_key.clone());
+        Ok((access_key, secret, expires_at))
+    }
+
+    /// Permissions of a key derived from the current permissions of the validated key,
+    /// `None` if the minting key no longer holds the scope of the session
+    async fn session_permissions(
+        &self,
+        permissions: HashMap<DieselUlid, DbPermissionLevel>,
+        session: Option<&SessionInfo>,
+    ) -> Option<HashMap<DieselUlid, DbPermissionLevel>> {
+        let Some((scope_id, level)) = session.and_then(|s| s.scope.clone()) else {
+            return Some(permissions);
+        };
+        self.resources.get(&scope_id)?;
+        let chain = std::iter::once(scope_id).chain(
+            self.get_prefixes(&TypedId::Unknown(scope_id), true)
+                .await
+                .into_iter()
+                .map(|(typed_id, _)| typed_id.get_id()),
+        );
+        let effective = chain.filter_map(|id| permissions.get(&id).cloned()).max()?;
+        if effective == DbPermissionLevel::Deny || effective < level {
+            return None;
+        }
+        Some(HashMap::from([(scope_id, level)]))
+    }
+
+    /// Removes expired temporary credentials, returns the number of removed keys
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn purge_expired_sessions(&self) -> Result<usize> {
+        let now = Utc::now();
+        let keys = self
+            .access_keys
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect::<Vec<_>>();
+        let mut purged = 0;
+        for (access_key, key) in keys {
+            if key.read().await.is_expired(now) {
+                self.evict_key(&access_key).await?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
     #[tracing::instrument(level = "trace", skip(self, pks))]
     pub async fn sync_pubkeys(&self, pks: Vec<PubKey>) -> Result<()> {
         for pk in pks.into_iter() {
@@ -414,8 +649,8 @@ impl Cache {
                 pk.upsert(persistence.get_client().await?.client()).await?;
             }
         }
-        trace!("clearing pks in cache");
-        self.pubkeys.clear();
+        // Replaced key by key, tokens stay verifiable during the update
+        let ids = pks.iter().map(|pk| pk.id.into()).collect::<HashSet<i32>>();
         for pk in pks.into_iter() {
             let dec_key = DecodingKey::from_ed_pem(
                 format!(
@@ -426,6 +661,7 @@ impl Cache {
             )?;
             self.pubkeys.insert(pk.id.into(), (pk.clone(), dec_key));
         }
+        self.pubkeys.retain(|id, _| ids.contains(id));
         trace!("updated pks in cache");
         Ok(())
     }
@@ -466,6 +702,67 @@ impl Cache {
         location
     }
 
+    /// Like `get_location` but falls back to the persisted location binding, e.g. when
+    /// the data was written by another instance sharing the database. Unsuccessful
+    /// lookups are cached for `location_negative_ttl` seconds.
+    #[tracing::instrument(level = "trace", skip(self, resource_id))]
+    pub async fn lookup_location(&self, resource_id: &DieselUlid) -> Option<ObjectLocation> {
+        let (_, loc) = self.resources.get(resource_id)?.value().clone();
+        if let Some(location) = loc.read().await.clone() {
+            return Some(location);
+        }
+
+        let negative_ttl = Duration::from_secs(CONFIG.proxy.location_negative_ttl);
+        if let Some(since) = self.missing_locations.get(resource_id).map(|e| *e.value()) {
+            if since.elapsed() < negative_ttl {
+                trace!("location is negatively cached");
+                return None;
+            }
+            self.missing_locations.remove(resource_id);
+        }
+
+        match self.load_location(resource_id).await {
+            Ok(Some(location)) => {
+                let old_size = {
+                    let mut guard = loc.write().await;
+                    if guard.is_some() {
+                        return guard.clone();
+                    }
+                    guard.replace(location.clone()).map(|l| l.raw_content_len)
+                };
+                self.update_prefix_stats_on_change(resource_id, old_size, location.raw_content_len)
+                    .await;
+                self.refresh_usage(resource_id).await;
+                Some(location)
+            }
+            Ok(None) => {
+                debug!(?resource_id, "No location for object");
+                self.missing_locations.insert(*resource_id, Instant::now());
+                None
+            }
+            Err(e) => {
+                error!(error = ?e, msg = e.to_string());
+                self.missing_locations.insert(*resource_id, Instant::now());
+                None
+            }
+        }
+    }
+
+    /// Loads the location bound to an object from the persistence
+    async fn load_location(&self, resource_id: &DieselUlid) -> Result<Option<ObjectLocation>> {
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            let client = persistence.get_client().await?;
+            if let Some(binding) =
+                LocationBinding::get_by_object_id(resource_id, client.client()).await?
+            {
+                return Ok(Some(
+                    ObjectLocation::get(&binding.location_id, client.client()).await?,
+                ));
+            }
+        }
+        Ok(None)
+    }
+
     #[tracing::instrument(level = "trace", skip(self, resource_id, with_intermediates))]
     pub async fn get_prefixes(
         &self,
@@ -629,6 +926,27 @@ impl Cache {
             .clone())
     }
 
+    /// Pubkey of the kid, unknown kids are fetched from the server on demand
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn resolve_pubkey(&self, kid: i32) -> Result<(PubKey, DecodingKey)> {
+        self.pubkey_refresh
+            .resolve(self, kid, || async {
+                let client = self.aruna_client.read().await.clone().ok_or_else(|| {
+                    error!(error = "No aruna client");
+                    anyhow!("No aruna client")
+                })?;
+                Ok::<_, anyhow::Error>(
+                    client
+                        .get_pubkeys()
+                        .await?
+                        .into_iter()
+                        .map(PubKey::from)
+                        .collect(),
+                )
+            })
+            .await
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn upsert_user(self: Arc<Cache>, user: GrpcUser) -> Result<()> {
         let user_id = DieselUlid::from_str(&user.id).map_err(|e| {
@@ -676,6 +994,7 @@ impl Cache {
                     .await?;
             }
         }
+        self.access_cache.invalidate_user(&user_id);
         if let Some(persistence) = self.persistence.read().await.as_ref() {
             proxy_user
                 .upsert(persistence.get_client().await?.client())
@@ -686,6 +1005,7 @@ impl Cache {
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn remove_user(&self, user_id: DieselUlid) -> Result<()> {
+        self.access_cache.invalidate_user(&user_id);
         if let Some((u, v)) = self.users.remove(&user_id) {
             for key in v.read().await.1.iter() {
                 self.access_keys.remove(key.as_str());
@@ -701,9 +1021,147 @@ impl Cache {
         Ok(())
     }
 
+    /// True if the user is already cached with the same permissions and attributes
+    pub async fn is_user_unchanged(&self, user: &User) -> bool {
+        let Some(cached) = self.users.get(&user.user_id).map(|u| u.value().clone()) else {
+            return false;
+        };
+        let unchanged = cached.read().await.0 == *user;
+        unchanged
+    }
+
+    /// True if the resource is already cached in exactly this state
+    pub async fn is_object_unchanged(&self, object: &Object) -> bool {
+        let Some(cached) = self.resources.get(&object.id).map(|r| r.value().0.clone()) else {
+            return false;
+        };
+        let unchanged = *cached.read().await == *object;
+        unchanged
+    }
+
+    /// Removes users that were not part of a completed full sync,
+    /// i.e. that were deleted while the proxy was not connected
+    #[tracing::instrument(level = "trace", skip(self, synced))]
+    pub async fn prune_users(&self, synced: &HashSet<DieselUlid>) -> Result<usize> {
+        let stale = self
+            .users
+            .iter()
+            .map(|u| *u.key())
+            .filter(|id| !synced.contains(id))
+            .collect::<Vec<_>>();
+        for user_id in stale.iter() {
+            debug!(?user_id, "removing user missing from full sync");
+            self.remove_user(*user_id).await?;
+        }
+        Ok(stale.len())
+    }
+
+    /// Removes resources that were not part of a completed full sync, resources
+    /// with pending server notifications are kept as the server may not know them yet
+    #[tracing::instrument(level = "trace", skip(self, synced))]
+    pub async fn prune_resources(&self, synced: &HashSet<DieselUlid>) -> Result<usize> {
+        let candidates = self
+            .resources
+            .iter()
+            .filter(|r| {
+                !synced.contains(r.key()) && !self.pending_notifications.contains_key(r.key())
+            })
+            .map(|r| (*r.key(), r.value().0.clone()))
+            .collect::<Vec<_>>();
+        let mut stale = Vec::new();
+        for (id, object) in candidates {
+            let object_type = object.read().await.object_type.clone();
+            stale.push((id, object_type));
+        }
+        // Children first, paths are derived from the parents
+        stale.sort_by_key(|(_, object_type)| match object_type {
+            ObjectType::Object => 0,
+            ObjectType::Dataset => 1,
+            ObjectType::Collection => 2,
+            ObjectType::Project => 3,
+        });
+        for (id, _) in stale.iter() {
+            debug!(?id, "removing resource missing from full sync");
+            self.delete_object(*id).await?;
+        }
+        Ok(stale.len())
+    }
+
+    /// Applies a completely received full sync. Records are replaced one by one, lookups
+    /// are served from the previous state until their record was updated.
+    #[tracing::instrument(level = "trace", skip(self, sync))]
+    pub async fn apply_full_sync(
+        self: Arc<Cache>,
+        sync: SyncSnapshot,
+        full_resync: bool,
+    ) -> Result<()> {
+        let SyncSnapshot {
+            users,
+            resources,
+            pubkeys,
+        } = sync;
+        if !pubkeys.is_empty() {
+            self.set_pubkeys(pubkeys).await?;
+        }
+
+        let mut synced_users = HashSet::new();
+        let mut updated_users = 0;
+        for u in users {
+            let user = User::try_from(u.clone())?;
+            synced_users.insert(user.user_id);
+            if full_resync || !self.is_user_unchanged(&user).await {
+                updated_users += 1;
+                self.clone().upsert_user(u).await?
+            }
+        }
+
+        let mut synced_resources = HashSet::new();
+        let mut updated_resources = 0;
+        for object in resources {
+            synced_resources.insert(object.id);
+            if full_resync || !self.is_object_unchanged(&object).await {
+                updated_resources += 1;
+                self.upsert_object(object).await?
+            }
+        }
+
+        // Records deleted while the proxy was not connected, an empty sync is
+        // treated as a server side problem and does not wipe the cache
+        let (removed_users, removed_resources) = if synced_resources.is_empty() {
+            warn!("Full sync contained no resources, skipping removal of stale records");
+            (0, 0)
+        } else {
+            (
+                self.prune_users(&synced_users).await?,
+                self.prune_resources(&synced_resources).await?,
+            )
+        };
+        info!(
+            full_resync,
+            updated_users,
+            removed_users,
+            updated_resources,
+            removed_resources,
+            "Reconciled cache with server"
+        );
+        self.sync_state.full_sync_finished();
+        Ok(())
+    }
+
+    /// Number of cached resources, users and pubkeys
+    pub fn sync_counts(&self) -> (usize, usize, usize) {
+        (self.resources.len(), self.users.len(), self.pubkeys.len())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, object))]
-    pub async fn upsert_object(&self, object: Object) -> Result<()> {
+    pub async fn upsert_object(&self, mut object: Object) -> Result<()> {
         trace!(?object, "upserting object");
+        if object.created_at.is_none() {
+            // Local updates (e.g. replicated locations) keep the timestamp of the server
+            if let Some(cached) = self.resources.get(&object.id).map(|o| o.value().0.clone()) {
+                object.created_at = cached.read().await.created_at;
+            }
+        }
         if let Some(persistence) = self.persistence.read().await.as_ref() {
             let mut client = persistence.get_client().await?;
             let transaction = client.transaction().await?;
@@ -711,9 +1169,18 @@ impl Cache {
             object.upsert(transaction_client).await?;
             transaction.commit().await?;
         }
-        let old_name = if let Some(o) = self.resources.get(&object.id) {
-            let (obj, _) = o.value();
-            let mut dash_map_object = obj.try_write()?;
+        self.update_policies(&object);
+        let cached = self.resources.get(&object.id).map(|o| o.value().0.clone());
+        // The rollup counters of a reparented subtree move to the new ancestors
+        let old_ancestors = match &cached {
+            Some(obj) if obj.read().await.parents != object.parents => {
+                Some(self.get_ancestors(&object.id).await)
+            }
+            _ => None,
+        };
+        let old_name = if let Some(obj) = cached {
+            // Waits for concurrent readers, lookups are served while the cache is resynced
+            let mut dash_map_object = obj.write().await;
             let old_name = dash_map_object.name.clone();
             *dash_map_object = object.clone();
             old_name
@@ -725,6 +1192,7 @@ impl Cache {
                     Arc::new(RwLock::new(None)),
                 ),
             );
+            CACHE_METRICS.resources.set(self.resources.len() as i64);
             object.name.to_string()
         };
 
@@ -757,14 +1225,48 @@ impl Cache {
         if prefixes.is_empty() && object.object_type == ObjectType::Project {
             self.paths.insert(object.name.clone(), object.id);
         }
+        if let Some(old_ancestors) = old_ancestors {
+            self.move_prefix_stats(&object.id, &old_ancestors).await;
+        }
+        // The data class may have changed
+        self.refresh_usage(&object.id).await;
 
+        // Parents are included, new siblings or revisions may shadow memoized paths
+        let invalidated = object
+            .parents
+            .iter()
+            .flatten()
+            .map(|parent| parent.get_id())
+            .chain([object.id])
+            .collect::<Vec<_>>();
         if old_name != object.name {
             self.update_object_name(TypedId::from(&object), old_name, object.name)
                 .await?;
         }
+        self.access_cache.invalidate_resources(&invalidated);
         Ok(())
     }
 
+    /// Updates the sync status of an endpoint of a cached object, e.g. while this proxy
+    /// replicates the object and before the server notification arrives
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn set_endpoint_status(
+        &self,
+        object_id: &DieselUlid,
+        endpoint_id: &DieselUlid,
+        status: SyncStatus,
+    ) -> Result<()> {
+        let (mut object, _) = self.get_resource_cloned(object_id, false).await?;
+        let Some(endpoint) = object.endpoints.iter_mut().find(|ep| &ep.id == endpoint_id) else {
+            return Ok(());
+        };
+        if endpoint.status.as_ref() == Some(&status) {
+            return Ok(());
+        }
+        endpoint.status = Some(status);
+        self.upsert_object(object).await
+    }
+
     #[tracing::instrument(level = "trace", skip(self, object_id, new_name))]
     pub async fn update_object_name(
         &self,
@@ -784,9 +1286,68 @@ impl Cache {
         Ok(())
     }
 
+    /// Removes an object with its backend data, objects that are not cached count as
+    /// deleted. See [`super::deletion::delete_object`] for deletions that include the server.
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn delete_object(&self, id: DieselUlid) -> Result<()> {
-        // Remove object and location from database
+        let mut guard = self.lock_deletion(id).await;
+        self.delete_object_locked(id, &mut guard).await
+    }
+
+    /// Waits for running reads of the object data and blocks new ones
+    pub(crate) async fn lock_deletion(&self, id: DieselUlid) -> DeleteGuard {
+        self.object_locks.write(id).await
+    }
+
+    /// Read lock of the object data that is held while the data is streamed, None if
+    /// the object was deleted in the meantime
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn read_object_data(&self, id: &DieselUlid) -> Option<ReadGuard> {
+        let guard = self.object_locks.read(*id).await?;
+        // Removed while the read waited for the deletion
+        self.resources.contains_key(id).then_some(guard)
+    }
+
+    /// Deletes the backend data first, the object is only removed from the cache and
+    /// persistence afterwards. Deleted data is not deleted again by a retry.
+    #[tracing::instrument(level = "trace", skip(self, guard))]
+    pub(crate) async fn delete_object_locked(
+        &self,
+        id: DieselUlid,
+        guard: &mut DeleteGuard,
+    ) -> Result<()> {
+        if !guard.data_deleted() {
+            self.delete_object_data(id).await?;
+            guard.set_data_deleted();
+        }
+        self.remove_object(id).await?;
+        guard.set_removed();
+        Ok(())
+    }
+
+    /// Removes the data of an object from the storage backend
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) async fn delete_object_data(&self, id: DieselUlid) -> Result<()> {
+        let Some(loc) = self.resources.get(&id).map(|r| r.value().1.clone()) else {
+            return Ok(());
+        };
+        let location = loc.read().await.clone();
+        if let (Some(s3_backend), Some(location)) = (&self.backend, location) {
+            s3_backend.delete_object(location).await.map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Removes object and location from persistence and cache
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn remove_object(&self, id: DieselUlid) -> Result<()> {
+        let Some(loc) = self.resources.get(&id).map(|r| r.value().1.clone()) else {
+            trace!(?id, "Resource is already removed");
+            return Ok(());
+        };
         if let Some(persistence) = self.persistence.read().await.as_ref() {
             let mut client = persistence.get_client().await?;
             let transaction = client.transaction().await?;
@@ -798,20 +1359,17 @@ impl Cache {
             transaction.commit().await?;
         }
 
-        // Remove data from storage backend
-        if let Some(s3_backend) = &self.backend {
-            if let Some(resource) = self.resources.get(&id) {
-                let (_, loc) = resource.value();
-                if let Some(location) = loc.read().await.as_ref() {
-                    s3_backend.delete_object(location.clone()).await?;
-                }
-            }
+        let old_size = loc.read().await.as_ref().map(|l| l.raw_content_len);
+        if let Some(size) = old_size {
+            self.update_prefix_stats(&id, -1, -size).await;
         }
-        // Remove object and location from cache
-        let old = self
-            .resources
-            .remove(&id)
-            .ok_or_else(|| anyhow!("Resource not found"))?;
+        self.missing_locations.remove(&id);
+        self.update_usage(&id, None).await;
+        self.policies.remove(&id);
+        let Some(old) = self.resources.remove(&id) else {
+            return Ok(());
+        };
+        CACHE_METRICS.resources.set(self.resources.len() as i64);
         let object = old.1 .0.read().await;
         for p in self
             .get_name_trees(&TypedId::from(object.deref()), object.name.clone(), None)
@@ -820,90 +1378,484 @@ impl Cache {
         {
             self.paths.remove(&p);
         }
+        self.access_cache.invalidate_resources(&[id]);
         Ok(())
     }
 
+    /// Replaces a resource, its location binding and the permissions of the users holding
+    /// permissions on it with the current server state. Entries are replaced in place,
+    /// concurrent requests see either the old or the new state.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn get_key_perms(&self, access_key: &str) -> Option<AccessKeyPermissions> {
-        let result = self.access_keys.get(access_key)?;
-        let result = result.value().read().await.clone();
-        trace!(?result);
-        Some(result)
-    }
+    pub async fn resync_resource(&self, id: &DieselUlid) -> Result<ResyncOutcome> {
+        let handler = self.aruna_client.read().await.clone().ok_or_else(|| {
+            error!(error = "Query handler not available");
+            anyhow!("Query handler not available")
+        })?;
+        let cached = self.resources.get(id).map(|r| r.value().0.clone());
+        let object_type = match cached {
+            Some(object) => Some(object.read().await.object_type.clone()),
+            None => None,
+        };
+        let Some(object) = handler.fetch_resource(id, object_type).await? else {
+            if self.resources.contains_key(id) {
+                debug!(?id, "removing resource missing upstream");
+                self.delete_object(*id).await?;
+            }
+            return Ok(ResyncOutcome::Deleted);
+        };
+        self.upsert_object(object).await?;
+        self.resync_location(id).await?;
+        self.repair_paths(id).await;
 
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn get_resource(
-        &self,
-        resource_id: &DieselUlid,
-    ) -> Result<(Arc<RwLock<Object>>, Arc<RwLock<Option<ObjectLocation>>>)> {
-        let resource = self
-            .resources
-            .get(resource_id)
-            .ok_or_else(|| anyhow!("Resource not found"))?;
-        Ok(resource.value().clone())
+        let mut holders = Vec::new();
+        for user in self
+            .users
+            .iter()
+            .map(|u| u.value().clone())
+            .collect::<Vec<_>>()
+        {
+            let user = user.read().await;
+            if user.0.personal_permissions.contains_key(id)
+                || user.0.tokens.values().any(|perms| perms.contains_key(id))
+            {
+                holders.push(user.0.user_id);
+            }
+        }
+        let cache = self.get_cache().await?;
+        for user_id in holders {
+            match handler.get_user(user_id, String::new()).await {
+                Ok(user) => cache.clone().upsert_user(user).await?,
+                Err(e) if is_not_found(&e) => self.remove_user(user_id).await?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ResyncOutcome::Refreshed)
     }
 
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn get_resource_cloned(
-        &self,
-        resource_id: &DieselUlid,
-        skip_location: bool,
-    ) -> Result<(Object, Option<ObjectLocation>)> {
-        let (obj, loc) = self.get_resource(resource_id).await?;
-        let obj = obj.read().await.clone();
-        let loc = if !skip_location {
-            loc.read().await.clone()
-        } else {
-            None
+    /// Replaces the cached location with the persisted location binding, if any
+    async fn resync_location(&self, id: &DieselUlid) -> Result<()> {
+        self.missing_locations.remove(id);
+        let Some(location) = self.load_location(id).await? else {
+            return Ok(());
         };
-        Ok((obj, loc))
+        let Some(loc) = self.resources.get(id).map(|r| r.value().1.clone()) else {
+            return Ok(());
+        };
+        let old_size = loc
+            .write()
+            .await
+            .replace(location.clone())
+            .map(|l| l.raw_content_len);
+        self.update_prefix_stats_on_change(id, old_size, location.raw_content_len)
+            .await;
+        self.refresh_usage(id).await;
+        Ok(())
     }
 
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub fn get_path(&self, path: &str) -> Option<DieselUlid> {
-        self.paths.get(path).map(|e| e.value().clone())
+    /// Re-derives the paths of a resource and its descendants from the cached hierarchy,
+    /// current paths are inserted before outdated ones are removed
+    async fn repair_paths(&self, id: &DieselUlid) {
+        let mut ids = vec![*id];
+        ids.extend(
+            self.get_suffixes(&TypedId::Unknown(*id), true)
+                .await
+                .into_iter()
+                .map(|(child, _)| child.get_id()),
+        );
+        let mut current: HashMap<DieselUlid, HashSet<String>> = HashMap::new();
+        for id in ids {
+            let Ok((object, _)) = self.get_resource_cloned(&id, false).await else {
+                continue;
+            };
+            let prefixes = self.get_prefixes(&TypedId::Unknown(id), false).await;
+            let paths = if prefixes.is_empty() && object.object_type == ObjectType::Project {
+                HashSet::from([object.name.clone()])
+            } else {
+                prefixes
+                    .into_iter()
+                    .map(|(_, pre)| format!("{pre}/{}", object.name))
+                    .collect()
+            };
+            for path in paths.iter() {
+                self.paths.insert(path.clone(), id);
+            }
+            current.insert(id, paths);
+        }
+        let stale = self
+            .paths
+            .iter()
+            .filter(|e| {
+                current
+                    .get(e.value())
+                    .is_some_and(|paths| !paths.contains(e.key()))
+            })
+            .map(|e| e.key().clone())
+            .collect::<Vec<_>>();
+        for path in stale {
+            debug!(path, "removing outdated path");
+            self.paths.remove(&path);
+        }
     }
 
+    /// Drops the cached permissions of an access key and validates it against the server
     #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn get_user_attributes(
-        &self,
-        resource_id: &DieselUlid,
-    ) -> Option<HashMap<String, String>> {
-        let user_reference = self.users.get(resource_id)?;
-        let user = user_reference.value().read().await;
-        return Some(user.0.attributes.clone());
+    pub async fn resync_access_key(&self, access_key: &str) -> Result<ResyncOutcome> {
+        self.unknown_keys.remove(access_key);
+        self.access_cache.invalidate_access_key(access_key);
+        match self.load_key(access_key).await? {
+            Some(_) => Ok(ResyncOutcome::Refreshed),
+            None => Ok(ResyncOutcome::Deleted),
+        }
     }
 
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub fn get_path_range(&self, bucket_name: &str, skip: &str) -> Vec<(String, DieselUlid)> {
-        let prefix = format!("{}/", bucket_name.to_string());
-
-        self.paths
-            .range(format!("{prefix}{skip}")..=format!("{prefix}~"))
-            .map(|e| {
-                (
-                    e.key()
-                        .strip_prefix(&prefix)
-                        .unwrap_or_default()
-                        .to_string(),
-                    e.value().clone(),
-                )
-            })
-            .collect()
+    /// Owner of a cached access key without waiting for a lock, e.g. before the request
+    /// is authenticated
+    pub fn try_get_key_owner(&self, access_key: &str) -> Option<DieselUlid> {
+        let key = self.access_keys.get(access_key)?.value().clone();
+        let user_id = key.try_read().ok()?.user_id;
+        Some(user_id)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn add_bundle(&self, bundle: Bundle) {
-        self.bundles.insert(bundle.id, bundle);
-        // TODO: Add to persistence layer
+    pub async fn get_key_perms(&self, access_key: &str) -> Option<AccessKeyPermissions> {
+        let cached = self.access_keys.get(access_key).map(|e| e.value().clone());
+        let result = match cached {
+            Some(key) => {
+                CACHE_METRICS.key_lookups.with_label_values(&["hit"]).inc();
+                key.read().await.clone()
+            }
+            None => match self.fetch_key(access_key).await {
+                Some(key) => {
+                    CACHE_METRICS.key_lookups.with_label_values(&["miss"]).inc();
+                    key
+                }
+                None => {
+                    CACHE_METRICS
+                        .key_lookups
+                        .with_label_values(&["unknown"])
+                        .inc();
+                    return None;
+                }
+            },
+        };
+        if result.is_expired(Utc::now()) {
+            debug!(access_key, "Access key is expired");
+            if let Err(e) = self.evict_key(access_key).await {
+                error!(error = ?e, msg = e.to_string());
+            }
+            return None;
+        }
+        trace!(?result);
+        Some(result)
     }
 
+    /// Looks up an access key that is unknown to the cache, e.g. created moments ago by
+    /// another proxy instance that shares the persistence
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn get_bundle(&self, bundle_id: &DieselUlid) -> Option<Bundle> {
-        let result = self.bundles.get(bundle_id).map(|e| e.clone());
-        result
-    }
-
+    async fn fetch_key(&self, access_key: &str) -> Option<AccessKeyPermissions> {
+        let negative_ttl = Duration::from_secs(CONFIG.proxy.credential_negative_ttl);
+        if let Some(since) = self.unknown_keys.get(access_key).map(|e| *e.value()) {
+            if since.elapsed() < negative_ttl {
+                trace!("access key is negatively cached");
+                return None;
+            }
+        }
+        match self.load_key(access_key).await {
+            Ok(Some(key)) => {
+                self.unknown_keys.remove(access_key);
+                Some(key)
+            }
+            Ok(None) => {
+                debug!(access_key, "Unknown access key");
+                self.unknown_keys
+                    .insert(access_key.to_string(), Instant::now());
+                None
+            }
+            Err(e) => {
+                error!(error = ?e, msg = e.to_string());
+                self.unknown_keys
+                    .insert(access_key.to_string(), Instant::now());
+                None
+            }
+        }
+    }
+
+    /// Loads the key from the persistence and validates it against the server
+    async fn load_key(&self, access_key: &str) -> Result<Option<AccessKeyPermissions>> {
+        let stored = match self.persistence.read().await.as_ref() {
+            Some(persistence) => {
+                AccessKeyPermissions::get_opt(
+                    &access_key.to_string(),
+                    persistence.get_client().await?.client(),
+                )
+                .await?
+            }
+            None => None,
+        };
+        // Keys of a proxy without persistence only exist in memory
+        let cached = match self.access_keys.get(access_key).map(|e| e.value().clone()) {
+            Some(key) => Some(key.read().await.clone()),
+            None => None,
+        };
+        let Some(mut key) = stored.or(cached) else {
+            return Ok(None);
+        };
+
+        // The stored permissions may be outdated or the token revoked in the meantime
+        if let Some(handler) = self.aruna_client.read().await.clone() {
+            let user = User::try_from(handler.get_user(key.user_id, String::new()).await?)?;
+            let permissions = match user.get_key_permissions(key.validated_key()) {
+                Some(permissions) => {
+                    self.session_permissions(permissions, key.session.as_ref())
+                        .await
+                }
+                None => None,
+            };
+            let Some(permissions) = permissions else {
+                self.evict_key(access_key).await?;
+                return Ok(None);
+            };
+            key.permissions = permissions;
+            key.is_service_account = user.is_service_account;
+
+            let existing = self.users.get(&user.user_id).map(|e| e.value().clone());
+            match existing {
+                Some(existing) => {
+                    let mut existing = existing.write().await;
+                    existing.0 = user;
+                    if !existing.1.iter().any(|k| k == access_key) {
+                        existing.1.push(access_key.to_string());
+                    }
+                }
+                None => {
+                    self.users.insert(
+                        user.user_id,
+                        Arc::new(RwLock::new((user, vec![access_key.to_string()]))),
+                    );
+                }
+            }
+            self.key_validations
+                .insert(access_key.to_string(), Instant::now());
+        }
+
+        self.access_keys
+            .insert(access_key.to_string(), Arc::new(RwLock::new(key.clone())));
+        self.access_cache.invalidate_user(&key.user_id);
+        Ok(Some(key))
+    }
+
+    /// Re-validates access keys whose last validation is older than the ttl,
+    /// returns the number of evicted (revoked) keys
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn refresh_credentials(&self, ttl: Duration) -> Result<usize> {
+        let negative_ttl = Duration::from_secs(CONFIG.proxy.credential_negative_ttl);
+        self.unknown_keys
+            .retain(|_, since| since.elapsed() < negative_ttl);
+
+        let Some(handler) = self.aruna_client.read().await.clone() else {
+            return Ok(0);
+        };
+
+        // Stale keys grouped by user, one server request per user
+        let keys = self
+            .access_keys
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect::<Vec<_>>();
+        let mut stale: HashMap<DieselUlid, Vec<(String, Arc<RwLock<AccessKeyPermissions>>)>> =
+            HashMap::new();
+        for (access_key, key) in keys {
+            let validated = self
+                .key_validations
+                .get(&access_key)
+                .map(|e| e.value().elapsed() < ttl)
+                .unwrap_or(false);
+            if !validated {
+                let user_id = key.read().await.user_id;
+                stale.entry(user_id).or_default().push((access_key, key));
+            }
+        }
+
+        let mut evicted = 0;
+        for (user_id, keys) in stale {
+            let user = match handler.get_user(user_id, String::new()).await {
+                Ok(user) => Some(User::try_from(user)?),
+                Err(e) if is_transient(&e) => {
+                    // Keys stay valid while the server is unreachable
+                    debug!(?user_id, error = ?e, "Unable to validate credentials");
+                    continue;
+                }
+                Err(e)
+                    if e.downcast_ref::<tonic::Status>().map(|s| s.code())
+                        == Some(tonic::Code::NotFound) =>
+                {
+                    None
+                }
+                Err(e) => {
+                    error!(?user_id, error = ?e, msg = e.to_string());
+                    continue;
+                }
+            };
+
+            for (access_key, key) in keys {
+                // Sessions are validated with the key they were minted from
+                let (validated_key, session) = {
+                    let key = key.read().await;
+                    (key.validated_key().to_string(), key.session.clone())
+                };
+                let permissions = match user
+                    .as_ref()
+                    .and_then(|u| u.get_key_permissions(&validated_key))
+                {
+                    Some(permissions) => {
+                        self.session_permissions(permissions, session.as_ref())
+                            .await
+                    }
+                    None => None,
+                };
+                match permissions {
+                    Some(permissions) => {
+                        let mut key = key.write().await;
+                        if key.permissions != permissions {
+                            key.permissions = permissions;
+                            self.access_cache.invalidate_access_key(&access_key);
+                            if let Some(persistence) = self.persistence.read().await.as_ref() {
+                                key.upsert(persistence.get_client().await?.client()).await?;
+                            }
+                        }
+                        self.key_validations.insert(access_key, Instant::now());
+                    }
+                    None => {
+                        debug!(access_key, "Evicting revoked access key");
+                        self.evict_key(&access_key).await?;
+                        evicted += 1;
+                    }
+                }
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Removes a revoked access key from the cache and the persistence
+    async fn evict_key(&self, access_key: &str) -> Result<()> {
+        self.key_validations.remove(access_key);
+        self.access_cache.invalidate_access_key(access_key);
+        if let Some((_, key)) = self.access_keys.remove(access_key) {
+            let user_id = key.read().await.user_id;
+            let user = self.users.get(&user_id).map(|e| e.value().clone());
+            if let Some(user) = user {
+                user.write().await.1.retain(|k| k != access_key);
+            }
+        }
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            AccessKeyPermissions::delete(
+                &access_key.to_string(),
+                persistence.get_client().await?.client(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_resource(
+        &self,
+        resource_id: &DieselUlid,
+    ) -> Result<(Arc<RwLock<Object>>, Arc<RwLock<Option<ObjectLocation>>>)> {
+        let resource = self
+            .resources
+            .get(resource_id)
+            .ok_or_else(|| anyhow!("Resource not found"))?;
+        Ok(resource.value().clone())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_resource_cloned(
+        &self,
+        resource_id: &DieselUlid,
+        skip_location: bool,
+    ) -> Result<(Object, Option<ObjectLocation>)> {
+        let (obj, loc) = self.get_resource(resource_id).await?;
+        let obj = obj.read().await.clone();
+        let loc = if !skip_location {
+            loc.read().await.clone()
+        } else {
+            None
+        };
+        Ok((obj, loc))
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_path(&self, path: &str) -> Option<DieselUlid> {
+        self.paths.get(path).map(|e| e.value().clone())
+    }
+
+    /// All revisions of an object that are known to the cache (including the object
+    /// itself), newest first
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_revisions(&self, id: &DieselUlid) -> Vec<(Object, Option<ObjectLocation>)> {
+        let mut visited = HashSet::from([*id]);
+        let mut queue = VecDeque::from([*id]);
+        let mut revisions = Vec::new();
+        while let Some(current) = queue.pop_front() {
+            let Ok(revision) = self.get_resource_cloned(&current, false).await else {
+                continue;
+            };
+            for variant in revision.0.versions.iter().flatten() {
+                let (VersionVariant::HasVersion(next) | VersionVariant::IsVersion(next)) = variant;
+                if visited.insert(*next) {
+                    queue.push_back(*next);
+                }
+            }
+            revisions.push(revision);
+        }
+        // Ulids are ordered by their creation time
+        revisions.sort_by(|a, b| b.0.id.cmp(&a.0.id));
+        revisions
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_user_attributes(
+        &self,
+        resource_id: &DieselUlid,
+    ) -> Option<HashMap<String, String>> {
+        let user_reference = self.users.get(resource_id)?;
+        let user = user_reference.value().read().await;
+        return Some(user.0.attributes.clone());
+    }
+
+    /// Whether any cached resource has a path below `path`, e.g. the objects of a dataset
+    pub fn has_paths_below(&self, path: &str) -> bool {
+        let prefix = format!("{path}/");
+        self.paths
+            .range(prefix.clone()..)
+            .next()
+            .is_some_and(|e| e.key().starts_with(&prefix))
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_path_range(&self, bucket_name: &str, skip: &str) -> Vec<(String, DieselUlid)> {
+        // Keys are bucket relative, the project segment is never part of a key
+        self.paths
+            .range(key_to_path(bucket_name, skip)..=key_to_path(bucket_name, "~"))
+            .filter_map(|e| {
+                path_to_key(bucket_name, e.key()).map(|key| (key.to_string(), *e.value()))
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn add_bundle(&self, bundle: Bundle) {
+        self.bundles.insert(bundle.id, bundle);
+        // TODO: Add to persistence layer
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_bundle(&self, bundle_id: &DieselUlid) -> Option<Bundle> {
+        let result = self.bundles.get(bundle_id).map(|e| e.clone());
+        result
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn delete_bundle(&self, bundle_id: &DieselUlid) {
         self.bundles.remove(bundle_id);
@@ -997,44 +1949,544 @@ impl Cache {
         loc
     }
 
+    /// Ids of all cached resources in ascending order
+    pub fn get_resource_ids(&self) -> Vec<DieselUlid> {
+        let mut ids = self.resources.iter().map(|e| *e.key()).collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
     #[tracing::instrument(level = "trace", skip(self, starting_points))]
     pub async fn get_path_levels(
         &self,
         starting_points: &[DieselUlid],
-    ) -> Result<Vec<(String, Option<ObjectLocation>)>> {
+    ) -> Result<Vec<(String, BundleMember)>> {
         let mut results = Vec::new();
         for id in starting_points {
             let suffixes = self.get_suffixes(&TypedId::Unknown(*id), true).await;
             for (id, name) in suffixes {
-                if let TypedId::Object(id) = id {
-                    results.push((name, self.get_location_cloned(&id).await));
-                } else {
-                    results.push((format!("{}/", name), None))
+                match id {
+                    TypedId::Object(id) => results.push((name, self.get_bundle_member(&id).await)),
+                    // The starting point itself is a leaf, e.g. a directly bundled object
+                    TypedId::Unknown(id) if name.is_empty() => {
+                        if let Ok((object, _)) = self.get_resource_cloned(&id, false).await {
+                            if object.object_type == ObjectType::Object {
+                                results.push((
+                                    format!("/{}", object.name),
+                                    self.get_bundle_member(&id).await,
+                                ));
+                            }
+                        }
+                    }
+                    _ => results.push((format!("{}/", name), BundleMember::Directory)),
                 }
             }
         }
         Ok(results)
     }
 
-    #[tracing::instrument(level = "trace", skip(self, user_id))]
-    pub async fn get_personal_projects(&self, user_id: &DieselUlid) -> Result<Vec<Object>> {
-        let mut results = Vec::new();
+    /// Objects without complete local data are fetched from other endpoints
+    async fn get_bundle_member(&self, id: &DieselUlid) -> BundleMember {
+        match self.get_location_cloned(id).await {
+            Some(location) if !location.is_temporary && location.upload_id.is_none() => {
+                BundleMember::Local(*id, location)
+            }
+            _ => BundleMember::Remote(*id),
+        }
+    }
 
-        let user = self
-            .users
-            .get(user_id)
-            .ok_or_else(|| anyhow!("User not found"))?;
-        let user = user.value().read().await;
-
-        for (id, _) in user.0.personal_permissions.iter() {
-            if let Some(project) = self.resources.get(id) {
-                let maybe_project = project.value().0.read().await.clone();
-                if maybe_project.object_type == ObjectType::Project {
-                    results.push(maybe_project);
+    /// Manifest of prepared bundle members (see `prepare_members`), built from the cached
+    /// hashes. Objects without a stored SHA256 are listed without a hash.
+    pub async fn get_bundle_manifest(&self, members: &[(String, BundleMember)]) -> BundleManifest {
+        let mut manifest = BundleManifest::default();
+        for (name, member) in members {
+            let (object_id, location) = match member {
+                BundleMember::Directory => continue,
+                BundleMember::Local(object_id, location) => (*object_id, Some(location.clone())),
+                BundleMember::Remote(object_id) => (*object_id, None),
+            };
+            let object = match self.get_resource_cloned(&object_id, false).await {
+                Ok((object, _)) => Some(object),
+                Err(e) => {
+                    warn!(error = ?e, %object_id, "Bundled object not found for manifest");
+                    None
                 }
+            };
+            let sha256 = object
+                .as_ref()
+                .and_then(|o| o.hashes.get("SHA256"))
+                .or_else(|| location.as_ref().and_then(|l| l.raw_hashes.get("sha256")))
+                .cloned();
+            let size = location
+                .as_ref()
+                .map(|l| l.raw_content_len)
+                .or_else(|| object.as_ref().map(|o| o.content_len))
+                .unwrap_or_default();
+            manifest.objects.push(ManifestEntry {
+                path: name.clone(),
+                object_id: object_id.to_string(),
+                size,
+                sha256,
+            });
+        }
+        manifest
+    }
+
+    /// Archive members below a collection or dataset (see `get_path_levels`)
+    ///
+    /// Objects are only included if they or one of their parents are public or readable
+    /// with the permissions, the names of skipped objects are returned separately.
+    #[tracing::instrument(level = "trace", skip(self, permissions))]
+    pub async fn get_readable_levels(
+        &self,
+        root: &DieselUlid,
+        permissions: &HashMap<DieselUlid, DbPermissionLevel>,
+    ) -> (Vec<(String, BundleMember)>, Vec<String>) {
+        let mut grants: HashMap<DieselUlid, bool> = HashMap::new();
+        let mut levels = Vec::new();
+        let mut skipped = Vec::new();
+        for (typed_id, name) in self.get_suffixes(&TypedId::Unknown(*root), true).await {
+            match typed_id {
+                TypedId::Object(id) => {
+                    let chain = std::iter::once(id).chain(
+                        self.get_prefixes(&TypedId::Object(id), true)
+                            .await
+                            .into_iter()
+                            .map(|(typed_id, _)| typed_id.get_id()),
+                    );
+                    let mut readable = false;
+                    for id in chain {
+                        let granted = match grants.get(&id) {
+                            Some(granted) => *granted,
+                            None => {
+                                let public =
+                                    match self.resources.get(&id).map(|e| e.value().0.clone()) {
+                                        Some(resource) => {
+                                            resource.read().await.data_class == DataClass::Public
+                                        }
+                                        None => false,
+                                    };
+                                let granted = public
+                                    || permissions
+                                        .get(&id)
+                                        .is_some_and(|perm| perm >= &DbPermissionLevel::Read);
+                                grants.insert(id, granted);
+                                granted
+                            }
+                        };
+                        if granted {
+                            readable = true;
+                            break;
+                        }
+                    }
+                    if readable {
+                        levels.push((name, self.get_bundle_member(&id).await));
+                    } else {
+                        skipped.push(name);
+                    }
+                }
+                TypedId::Unknown(_) if name.is_empty() => {}
+                _ => levels.push((format!("{}/", name), BundleMember::Directory)),
             }
         }
-        Ok(results)
+        (levels, skipped)
+    }
+
+    /// All cached projects sorted by name
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_all_projects(&self) -> Vec<Object> {
+        // Root paths are project names, the path index is already sorted
+        let ids = self
+            .paths
+            .iter()
+            .filter(|e| !e.key().contains('/'))
+            .map(|e| *e.value())
+            .collect::<Vec<_>>();
+        let mut projects = Vec::with_capacity(ids.len());
+        for id in ids {
+            let Some(resource) = self.resources.get(&id).map(|r| r.value().0.clone()) else {
+                continue;
+            };
+            let project = resource.read().await.clone();
+            if project.object_type == ObjectType::Project {
+                projects.push(project);
+            }
+        }
+        projects
+    }
+
+    /// Recompiles the policies of a project if its attributes changed
+    #[tracing::instrument(level = "trace", skip(self, object))]
+    fn update_policies(&self, object: &Object) {
+        if object.object_type == ObjectType::Project {
+            self.policies.entry(object.id).or_default().update(object);
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_cors_config(&self, project_id: &DieselUlid) -> Option<Arc<CORSConfiguration>> {
+        self.policies.get(project_id)?.cors.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_lifecycle_config(
+        &self,
+        project_id: &DieselUlid,
+    ) -> Option<Arc<LifecycleConfiguration>> {
+        self.policies.get(project_id)?.lifecycle.get()
+    }
+
+    /// All projects with a lifecycle configuration
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_lifecycle_configs(&self) -> Vec<(DieselUlid, Arc<LifecycleConfiguration>)> {
+        self.policies
+            .iter()
+            .filter_map(|entry| Some((*entry.key(), entry.value().lifecycle.get()?)))
+            .collect()
+    }
+
+    /// Cors headers for a request based on the compiled cors config of the project
+    #[tracing::instrument(level = "trace", skip(self, headers))]
+    pub fn get_cors_headers(
+        &self,
+        project_id: &DieselUlid,
+        method: &http::Method,
+        headers: &http::HeaderMap<http::HeaderValue>,
+    ) -> Option<HashMap<String, String>> {
+        self.get_cors_config(project_id)?
+            .get_headers(method, headers)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_quota(&self, project_id: &DieselUlid) -> Option<Arc<QuotaPolicy>> {
+        self.policies.get(project_id)?.quota.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_bandwidth(&self, project_id: &DieselUlid) -> Option<Arc<BandwidthPolicy>> {
+        self.policies.get(project_id)?.bandwidth.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_upload_limits(&self, project_id: &DieselUlid) -> Option<Arc<UploadLimitsPolicy>> {
+        self.policies.get(project_id)?.upload_limits.get()
+    }
+
+    /// Webhook of the proxy config or the project attribute, attribute webhooks whose
+    /// secret reference is unknown to this proxy are not delivered
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_webhook(&self, project_id: &DieselUlid) -> Option<Arc<Webhook>> {
+        if let Some(webhook) = CONFIG.webhooks.projects.get(project_id) {
+            return Some(Arc::new(webhook.clone()));
+        }
+        let webhook = self.policies.get(project_id)?.webhook.get()?;
+        match webhook.resolve(&CONFIG.webhooks) {
+            Ok(webhook) => Some(Arc::new(webhook)),
+            Err(e) => {
+                error!(error = ?e, msg = e.to_string(), ?project_id, "Invalid project webhook");
+                None
+            }
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_object_lock(&self, project_id: &DieselUlid) -> Option<Arc<ObjectLockPolicy>> {
+        self.policies.get(project_id)?.object_lock.get()
+    }
+
+    /// Storage policy of the project of a new location, consulted by the backends.
+    /// `names` are the hierarchy names as returned by `get_single_parent`.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_storage_policy(
+        &self,
+        names: &[Option<(DieselUlid, String)>; 4],
+    ) -> Option<Arc<StoragePolicy>> {
+        let (project_id, _) = names[0].as_ref()?;
+        self.policies.get(project_id)?.storage.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_cache_control(&self, project_id: &DieselUlid) -> Option<String> {
+        self.policies.get(project_id)?.cache_control.clone()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_bucket_policy(&self, project_id: &DieselUlid) -> Option<Arc<BucketPolicy>> {
+        self.policies.get(project_id)?.bucket_policy.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_validation_spec(&self, project_id: &DieselUlid) -> Option<Arc<ValidationSpec>> {
+        self.policies.get(project_id)?.validation.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_collision_policy(&self, project_id: &DieselUlid) -> Option<Arc<CollisionPolicy>> {
+        self.policies.get(project_id)?.collision.get()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_replication_policy(
+        &self,
+        project_id: &DieselUlid,
+    ) -> Option<Arc<ReplicationPolicy>> {
+        self.policies.get(project_id)?.replication.get()
+    }
+
+    /// Errors of quarantined policies as (attribute key, error)
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_policy_errors(&self, project_id: &DieselUlid) -> Vec<(&'static str, String)> {
+        self.policies
+            .get(project_id)
+            .map(|p| p.errors())
+            .unwrap_or_default()
+    }
+
+    /// Number of policy compilations of a project, only attribute changes recompile
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_policy_compilations(&self, project_id: &DieselUlid) -> usize {
+        self.policies
+            .get(project_id)
+            .map(|p| p.compilations())
+            .unwrap_or_default()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_prefix_stats(&self, id: &DieselUlid) -> Option<PrefixStats> {
+        self.prefix_stats.get(id).map(|e| *e.value())
+    }
+
+    /// Whether the permissions grant read access to the whole subtree of a resource,
+    /// i.e. on the resource itself or on one of its ancestors
+    #[tracing::instrument(level = "trace", skip(self, permissions))]
+    pub async fn grants_subtree_read(
+        &self,
+        id: &DieselUlid,
+        permissions: &HashMap<DieselUlid, DbPermissionLevel>,
+    ) -> bool {
+        let readable = |id: &DieselUlid| {
+            permissions
+                .get(id)
+                .is_some_and(|perm| perm >= &DbPermissionLevel::Read)
+        };
+        readable(id)
+            || self
+                .get_prefixes(&TypedId::Unknown(*id), true)
+                .await
+                .iter()
+                .any(|(typed_id, _)| readable(&typed_id.get_id()))
+    }
+
+    /// Rollup counters of a resource as seen with the permissions, objects count
+    /// themselves. None if the caller can only list a part of the subtree, the counters
+    /// would leak the objects it can not see.
+    #[tracing::instrument(level = "trace", skip(self, permissions))]
+    pub async fn get_visible_prefix_stats(
+        &self,
+        id: &DieselUlid,
+        permissions: &HashMap<DieselUlid, DbPermissionLevel>,
+    ) -> Option<PrefixStats> {
+        if !self.grants_subtree_read(id, permissions).await {
+            return None;
+        }
+        let (object, location) = self.resources.get(id)?.value().clone();
+        if object.read().await.object_type == ObjectType::Object {
+            return Some(
+                location
+                    .read()
+                    .await
+                    .as_ref()
+                    .map(|l| PrefixStats {
+                        object_count: 1,
+                        total_size: l.raw_content_len,
+                    })
+                    .unwrap_or_default(),
+            );
+        }
+        Some(self.get_prefix_stats(id).unwrap_or_default())
+    }
+
+    /// Applies count / size deltas to all ancestors of the resource
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn update_prefix_stats(&self, id: &DieselUlid, count_delta: i64, size_delta: i64) {
+        // Every added or removed location passes through here
+        CACHE_METRICS.locations.add(count_delta);
+        for ancestor in self.get_ancestors(id).await {
+            let mut entry = self.prefix_stats.entry(ancestor).or_default();
+            entry.object_count += count_delta;
+            entry.total_size += size_delta;
+        }
+    }
+
+    /// Ids of all ancestors of the resource over all of its paths
+    async fn get_ancestors(&self, id: &DieselUlid) -> HashSet<DieselUlid> {
+        self.get_prefixes(&TypedId::Unknown(*id), true)
+            .await
+            .into_iter()
+            .map(|(typed_id, _)| typed_id.get_id())
+            .collect()
+    }
+
+    /// Moves the counters of the subtree of a resource whose parents changed from the
+    /// ancestors it left to the ancestors it gained
+    #[tracing::instrument(level = "trace", skip(self, old_ancestors))]
+    async fn move_prefix_stats(&self, id: &DieselUlid, old_ancestors: &HashSet<DieselUlid>) {
+        let Some((object, location)) = self.resources.get(id).map(|e| e.value().clone()) else {
+            return;
+        };
+        let subtree = if object.read().await.object_type == ObjectType::Object {
+            match location.read().await.as_ref() {
+                Some(location) => PrefixStats {
+                    object_count: 1,
+                    total_size: location.raw_content_len,
+                },
+                None => return,
+            }
+        } else {
+            match self.get_prefix_stats(id) {
+                Some(stats) => stats,
+                None => return,
+            }
+        };
+        let new_ancestors = self.get_ancestors(id).await;
+        for ancestor in old_ancestors.difference(&new_ancestors) {
+            let mut entry = self.prefix_stats.entry(*ancestor).or_default();
+            entry.object_count -= subtree.object_count;
+            entry.total_size -= subtree.total_size;
+        }
+        for ancestor in new_ancestors.difference(old_ancestors) {
+            let mut entry = self.prefix_stats.entry(*ancestor).or_default();
+            entry.object_count += subtree.object_count;
+            entry.total_size += subtree.total_size;
+        }
+    }
+
+    /// Usage of a project, maintained incrementally and corrected by `reconcile_usage`
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_bucket_usage(&self, project_id: &DieselUlid) -> BucketUsage {
+        self.bucket_usage
+            .get(project_id)
+            .map(|e| e.value().clone())
+            .unwrap_or_default()
+    }
+
+    /// Current usage contribution of an object, None for non objects and objects without data
+    async fn current_usage(&self, id: &DieselUlid) -> Option<ObjectUsage> {
+        let (object, location) = self.resources.get(id)?.value().clone();
+        let object = object.read().await;
+        if object.object_type != ObjectType::Object {
+            return None;
+        }
+        let location = location.read().await;
+        location.as_ref().map(|l| ObjectUsage {
+            data_class: object.data_class,
+            raw_size: l.raw_content_len,
+            disk_size: l.disk_content_len,
+        })
+    }
+
+    /// Ids of all projects the resource belongs to
+    pub async fn get_projects(&self, id: &DieselUlid) -> HashSet<DieselUlid> {
+        self.get_prefixes(&TypedId::Unknown(*id), true)
+            .await
+            .into_iter()
+            .filter_map(|(typed_id, _)| match typed_id {
+                TypedId::Project(id) => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn refresh_usage(&self, id: &DieselUlid) {
+        let usage = self.current_usage(id).await;
+        self.update_usage(id, usage).await
+    }
+
+    /// Replaces the usage contribution of an object in all of its projects
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn update_usage(&self, id: &DieselUlid, usage: Option<ObjectUsage>) {
+        let old = match usage {
+            Some(usage) => self.object_usage.insert(*id, usage),
+            None => self.object_usage.remove(id).map(|(_, usage)| usage),
+        };
+        if old == usage {
+            return;
+        }
+        for project in self.get_projects(id).await {
+            let mut entry = self.bucket_usage.entry(project).or_default();
+            if let Some(old) = &old {
+                entry.remove(old);
+            }
+            if let Some(usage) = &usage {
+                entry.add(usage);
+            }
+        }
+    }
+
+    /// Recomputes the usage of all projects from the cached resources and replaces
+    /// drifted counters, returns the number of corrected projects
+    ///
+    /// Changes that happen while the pass is running may be overwritten,
+    /// they are corrected by the next pass.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn reconcile_usage(&self) -> usize {
+        let ids = self.resources.iter().map(|e| *e.key()).collect::<Vec<_>>();
+        let mut object_usage = HashMap::new();
+        let mut bucket_usage: HashMap<DieselUlid, BucketUsage> = HashMap::new();
+        for id in ids {
+            let Some(usage) = self.current_usage(&id).await else {
+                continue;
+            };
+            for project in self.get_projects(&id).await {
+                bucket_usage.entry(project).or_default().add(&usage);
+            }
+            object_usage.insert(id, usage);
+        }
+
+        self.object_usage
+            .retain(|id, _| object_usage.contains_key(id));
+        for (id, usage) in object_usage {
+            self.object_usage.insert(id, usage);
+        }
+
+        let mut corrected = 0;
+        self.bucket_usage.retain(|id, usage| {
+            let keep = bucket_usage.contains_key(id);
+            if !keep && usage != &BucketUsage::default() {
+                corrected += 1;
+            }
+            keep
+        });
+        for (id, usage) in bucket_usage {
+            let drifted = self
+                .bucket_usage
+                .get(&id)
+                .map(|e| e.value() != &usage)
+                .unwrap_or(true);
+            if drifted {
+                debug!(project_id = %id, ?usage, "Corrected bucket usage");
+                self.bucket_usage.insert(id, usage);
+                corrected += 1;
+            }
+        }
+        corrected
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn update_prefix_stats_on_change(
+        &self,
+        id: &DieselUlid,
+        old_size: Option<i64>,
+        new_size: i64,
+    ) {
+        match old_size {
+            Some(old_size) if old_size != new_size => {
+                self.update_prefix_stats(id, 0, new_size - old_size).await
+            }
+            Some(_) => {}
+            None => self.update_prefix_stats(id, 1, new_size).await,
+        }
     }
 
     #[tracing::instrument(level = "trace", skip(self, object_id, location))]
@@ -1049,7 +2501,15 @@ impl Cache {
             .ok_or_else(|| anyhow!("Resource not found {}", object_id))?
             .value()
             .clone();
-        *loc.write().await = Some(location.clone());
+        let old_size = loc
+            .write()
+            .await
+            .replace(location.clone())
+            .map(|l| l.raw_content_len);
+        self.missing_locations.remove(&object_id);
+        self.update_prefix_stats_on_change(&object_id, old_size, location.raw_content_len)
+            .await;
+        self.refresh_usage(&object_id).await;
 
         if let Some(persistence) = self.persistence.read().await.as_ref() {
             location
@@ -1069,23 +2529,49 @@ impl Cache {
         Ok(())
     }
 
+    /// Drops the location of an object, e.g. of an upload that was aborted before any
+    /// data was completed
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn remove_location(&self, object_id: DieselUlid) -> Result<()> {
+        let (_, loc) = self
+            .resources
+            .get(&object_id)
+            .ok_or_else(|| anyhow!("Resource not found {}", object_id))?
+            .value()
+            .clone();
+        let Some(location) = loc.write().await.take() else {
+            return Ok(());
+        };
+        self.update_prefix_stats(&object_id, -1, -location.raw_content_len)
+            .await;
+        self.refresh_usage(&object_id).await;
+
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            ObjectLocation::delete(&location.id, persistence.get_client().await?.client()).await?;
+        }
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, object_id, location))]
     pub async fn update_location(
         &self,
         object_id: DieselUlid,
         location: ObjectLocation,
     ) -> Result<()> {
-        let old_location_id = if let Some(resource) = self.resources.get(&object_id) {
+        let (old_location_id, old_size) = if let Some(resource) = self.resources.get(&object_id) {
             let (_, loc) = resource.value();
-            let old_id = {
-                let reference = loc.read().await;
-                reference.as_ref().map(|e| e.id.clone())
-            };
-            *loc.write().await = Some(location.clone());
-            old_id
+            let old = loc.write().await.replace(location.clone());
+            (
+                old.as_ref().map(|e| e.id.clone()),
+                old.map(|e| e.raw_content_len),
+            )
         } else {
             bail!("Resource not found")
         };
+        self.missing_locations.remove(&object_id);
+        self.update_prefix_stats_on_change(&object_id, old_size, location.raw_content_len)
+            .await;
+        self.refresh_usage(&object_id).await;
 
         if let Some(persistence) = self.persistence.read().await.as_ref() {
             location
@@ -1116,7 +2602,16 @@ impl Cache {
 
     #[tracing::instrument(
         level = "trace",
-        skip(self, upload_id, object_id, part_number, raw_size, final_size)
+        skip(
+            self,
+            upload_id,
+            object_id,
+            part_number,
+            raw_size,
+            final_size,
+            etag,
+            md5
+        )
     )]
     pub async fn create_multipart_upload(
         &self,
@@ -1125,6 +2620,9 @@ impl Cache {
         part_number: u64,
         raw_size: u64,
         final_size: u64,
+        etag: String,
+        md5: String,
+        checksum: Option<ContentChecksum>,
     ) -> Result<()> {
         let part = UploadPart {
             id: DieselUlid::generate(),
@@ -1133,6 +2631,9 @@ impl Cache {
             object_id,
             upload_id: upload_id.clone(),
             raw_size,
+            etag,
+            md5,
+            checksum,
         };
         if let Some(persistence) = self.persistence.read().await.as_ref() {
             part.upsert(persistence.get_client().await?.client())
@@ -1151,8 +2652,11 @@ impl Cache {
                 return Err(anyhow!("Failed to create multipart upload"));
             }
 
+            // Sorted by part number, re-uploads of a part are kept in upload order
             let mut entry = entry.or_insert(Vec::new());
-            entry.value_mut().push(part);
+            let parts = entry.value_mut();
+            let idx = parts.partition_point(|p| p.part_number <= part.part_number);
+            parts.insert(idx, part);
             break;
         }
         Ok(())
@@ -1160,13 +2664,20 @@ impl Cache {
 
     #[tracing::instrument(level = "trace", skip(self, upload_id))]
     pub fn get_parts(&self, upload_id: &str) -> Vec<UploadPart> {
-        let mut parts = self
-            .multi_parts
+        self.multi_parts
             .get(upload_id)
             .map(|e| e.value().clone())
-            .unwrap_or_default();
-        parts.sort_by(|a, b| a.part_number.cmp(&b.part_number));
-        parts
+            .unwrap_or_default()
+    }
+
+    /// Runs `f` with the part records of an upload (sorted by part number) without
+    /// cloning them, `f` must not block
+    #[tracing::instrument(level = "trace", skip(self, upload_id, f))]
+    pub fn with_parts<T>(&self, upload_id: &str, f: impl FnOnce(&[UploadPart]) -> T) -> T {
+        match self.multi_parts.get(upload_id) {
+            Some(parts) => f(parts.value()),
+            None => f(&[]),
+        }
     }
 
     #[tracing::instrument(level = "trace", skip(self, upload_id))]
@@ -1182,21 +2693,512 @@ impl Cache {
         Ok(())
     }
 
-    #[tracing::instrument(level = "trace", skip(self, upload_id))]
-    pub async fn delete_part(&self, upload_id: String, part_number: u64) -> Result<()> {
-        let mut entry = self
-            .multi_parts
-            .get_mut(&upload_id)
-            .ok_or_else(|| anyhow!("Upload not found"))?;
-        let value = entry.value_mut();
-        let index = value
+    #[tracing::instrument(level = "trace", skip(self, upload_id, part_numbers))]
+    pub async fn delete_parts(&self, upload_id: String, part_numbers: &[u64]) -> Result<()> {
+        let part_numbers = part_numbers.iter().collect::<HashSet<_>>();
+        let removed = {
+            let mut entry = self
+                .multi_parts
+                .get_mut(&upload_id)
+                .ok_or_else(|| anyhow!("Upload not found"))?;
+            let (removed, kept) = std::mem::take(entry.value_mut())
+                .into_iter()
+                .partition::<Vec<_>, _>(|part| part_numbers.contains(&part.part_number));
+            *entry.value_mut() = kept;
+            removed
+        };
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            let client = persistence.get_client().await?;
+            for part in removed {
+                UploadPart::delete(&part.id, client.client()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tracks an in-progress upload, persisted like its part records
+    #[tracing::instrument(level = "trace", skip(self, upload))]
+    pub async fn add_multipart_upload(&self, upload: MultipartUploadInfo) -> Result<()> {
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            upload
+                .upsert(persistence.get_client().await?.client())
+                .await?;
+        }
+        self.multipart_uploads
+            .insert(upload.upload_id.clone(), upload);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn remove_multipart_upload(&self, upload_id: &str) -> Result<()> {
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            MultipartUploadInfo::delete(
+                &upload_id.to_string(),
+                persistence.get_client().await?.client(),
+            )
+            .await?;
+        }
+        self.multipart_uploads.remove(upload_id);
+        Ok(())
+    }
+
+    /// All in-progress uploads
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn all_multipart_uploads(&self) -> Vec<MultipartUploadInfo> {
+        self.multipart_uploads
+            .iter()
+            .map(|e| e.value().clone())
+            .collect()
+    }
+
+    /// Whether the cache state (locations, uploads, notifications) survives a restart
+    pub async fn is_persistent(&self) -> bool {
+        self.persistence.read().await.is_some()
+    }
+
+    /// Returns all in-progress uploads of a bucket sorted by key and upload_id
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn list_multipart_uploads(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Vec<MultipartUploadInfo> {
+        let mut uploads = self
+            .multipart_uploads
             .iter()
-            .position(|x| x.part_number == part_number)
-            .ok_or_else(|| anyhow!("Part not found"))?;
-        let removed = value.remove(index);
+            .filter(|e| e.bucket == bucket && prefix.map(|p| e.key.starts_with(p)).unwrap_or(true))
+            .map(|e| e.value().clone())
+            .collect::<Vec<_>>();
+        uploads.sort_by(|a, b| (&a.key, &a.upload_id).cmp(&(&b.key, &b.upload_id)));
+        uploads
+    }
+    /// Queues (or replaces) an undelivered server notification, persisted to survive restarts
+    #[tracing::instrument(level = "trace", skip(self, notification))]
+    pub async fn upsert_pending_notification(
+        &self,
+        notification: PendingNotification,
+    ) -> Result<()> {
         if let Some(persistence) = self.persistence.read().await.as_ref() {
-            UploadPart::delete(&removed.id, persistence.get_client().await?.client()).await?;
+            notification
+                .upsert(persistence.get_client().await?.client())
+                .await?;
         }
+        self.pending_notifications
+            .insert(notification.id, notification);
         Ok(())
     }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn remove_pending_notification(&self, id: &DieselUlid) -> Result<()> {
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            PendingNotification::delete(id, persistence.get_client().await?.client()).await?;
+        }
+        self.pending_notifications.remove(id);
+        Ok(())
+    }
+
+    /// Pending notifications whose next attempt is due
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_due_notifications(&self) -> Vec<PendingNotification> {
+        let now = Utc::now();
+        self.pending_notifications
+            .iter()
+            .filter(|e| e.next_attempt <= now)
+            .map(|e| e.value().clone())
+            .collect()
+    }
+
+    /// Number of notifications that still need to be delivered to the server
+    pub fn pending_notification_count(&self) -> usize {
+        self.pending_notifications.len()
+    }
+
+    /// All undelivered notifications, the next due first
+    pub fn get_pending_notifications(&self) -> Vec<PendingNotification> {
+        let mut pending = self
+            .pending_notifications
+            .iter()
+            .map(|e| e.value().clone())
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|n| n.next_attempt);
+        pending
+    }
+
+    /// Replaces an object that was only known locally (its creation was queued) with
+    /// the object created by the server, the location is moved to the new id
+    #[tracing::instrument(level = "trace", skip(self, object))]
+    pub async fn replace_local_object(&self, local_id: DieselUlid, object: Object) -> Result<()> {
+        let location = self.get_location(&local_id).await;
+        // Removes the path of the local object, which is also the path of the new one
+        self.remove_object(local_id).await?;
+        self.upsert_object(object.clone()).await?;
+        if let Some(location) = location {
+            self.add_location_with_binding(object.id, location).await?;
+        }
+        Ok(())
+    }
+
+    /// Queues (or updates) a webhook delivery, persisted to survive restarts
+    #[tracing::instrument(level = "trace", skip(self, webhook))]
+    pub async fn upsert_pending_webhook(&self, webhook: PendingWebhook) -> Result<()> {
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            webhook
+                .upsert(persistence.get_client().await?.client())
+                .await?;
+        }
+        self.pending_webhooks.insert(webhook.id, webhook);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn remove_pending_webhook(&self, id: &DieselUlid) -> Result<()> {
+        if let Some(persistence) = self.persistence.read().await.as_ref() {
+            PendingWebhook::delete(id, persistence.get_client().await?.client()).await?;
+        }
+        self.pending_webhooks.remove(id);
+        Ok(())
+    }
+
+    /// Webhook deliveries whose next attempt is due, dead letters are not retried
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_due_webhooks(&self) -> Vec<PendingWebhook> {
+        let now = Utc::now();
+        self.pending_webhooks
+            .iter()
+            .filter(|e| !e.dead_letter && e.next_attempt <= now)
+            .map(|e| e.value().clone())
+            .collect()
+    }
+
+    /// Undeliverable webhook events, oldest first
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_webhook_dead_letters(&self, project_id: Option<&DieselUlid>) -> Vec<PendingWebhook> {
+        let mut dead_letters = self
+            .pending_webhooks
+            .iter()
+            .filter(|e| e.dead_letter && project_id.map_or(true, |id| e.project_id == *id))
+            .map(|e| e.value().clone())
+            .collect::<Vec<_>>();
+        dead_letters.sort_by_key(|e| e.id);
+        dead_letters
+    }
+
+    /// Schedules dead letters for immediate redelivery with a fresh attempt budget
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn requeue_webhook_dead_letters(
+        &self,
+        project_id: Option<&DieselUlid>,
+    ) -> Result<usize> {
+        let dead_letters = self.get_webhook_dead_letters(project_id);
+        let count = dead_letters.len();
+        for mut webhook in dead_letters {
+            webhook.dead_letter = false;
+            webhook.attempts = 0;
+            webhook.next_attempt = Utc::now();
+            self.upsert_pending_webhook(webhook).await?;
+        }
+        Ok(count)
+    }
+}
+
+/// Random secret for S3 credentials
+fn generate_secret() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(30)
+        .map(char::from)
+        .collect::<String>()
+}
+
+/// Cache without persistence and server connection, the receiver gets the
+/// queued replication messages
+#[cfg(test)]
+pub(crate) async fn test_cache(
+    backend: Option<Arc<Box<dyn StorageBackend>>>,
+) -> (Arc<Cache>, async_channel::Receiver<ReplicationMessage>) {
+    const TEST_KEY: &str = "MC4CAQAwBQYDK2VwBCIEIM/FI+bYw+auSKGyGqeISRIEjofvZV/lbK7QL1wkuCey";
+    let (sender, receiver) = async_channel::unbounded();
+    let cache = Cache::new(
+        None::<String>,
+        false,
+        DieselUlid::generate(),
+        TEST_KEY.to_string(),
+        1,
+        sender,
+        backend,
+    )
+    .await
+    .unwrap();
+    (cache, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::TypedRelation;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_lookups_during_full_sync() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let objects = (0..200)
+            .map(|idx| {
+                Object::initialize_now(
+                    format!("file{idx}"),
+                    ObjectType::Object,
+                    Some(TypedRelation::Project(project.id)),
+                )
+            })
+            .collect::<Vec<_>>();
+        cache.upsert_object(project.clone()).await.unwrap();
+        for object in objects.iter() {
+            cache.upsert_object(object.clone()).await.unwrap();
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let readers = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let stop = stop.clone();
+                let lookups = lookups.clone();
+                tokio::spawn(async move {
+                    while !stop.load(Ordering::Relaxed) {
+                        for idx in 0..200 {
+                            let id = cache
+                                .get_path(&format!("bucket/file{idx}"))
+                                .expect("path missing during sync");
+                            cache
+                                .get_resource_cloned(&id, false)
+                                .await
+                                .expect("resource missing during sync");
+                            lookups.fetch_add(1, Ordering::Relaxed);
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for round in 0..5 {
+            let sync = SyncSnapshot {
+                resources: std::iter::once(project.clone())
+                    .chain(objects.iter().cloned().map(|mut object| {
+                        object.title = format!("round {round}");
+                        object
+                    }))
+                    .collect(),
+                ..Default::default()
+            };
+            cache.clone().apply_full_sync(sync, true).await.unwrap();
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.await.unwrap();
+        }
+
+        assert!(lookups.load(Ordering::Relaxed) > 0);
+        assert!(cache.sync_state.last_full_sync() > 0);
+        assert_eq!(cache.sync_counts(), (201, 0, 0));
+        let (object, _) = cache
+            .get_resource_cloned(&objects[0].id, true)
+            .await
+            .unwrap();
+        assert_eq!(object.title, "round 4");
+    }
+
+    #[tokio::test]
+    async fn test_revoked_credentials_are_denied() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let user_id = DieselUlid::generate();
+        cache
+            .clone()
+            .upsert_user(GrpcUser {
+                id: user_id.to_string(),
+                attributes: Some(Default::default()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let access_key = user_id.to_string();
+
+        // Create and use
+        let (created, secret) = cache
+            .create_or_update_secret(&access_key, &user_id)
+            .await
+            .unwrap();
+        assert_eq!(created, access_key);
+        assert_eq!(
+            cache.get_secret(&access_key).await.unwrap().expose(),
+            secret
+        );
+        let (session_key, _, _) = cache
+            .create_session_key(&access_key, &user_id, Duration::from_secs(600), None)
+            .await
+            .unwrap();
+        assert!(cache.get_key_perms(&session_key).await.is_some());
+
+        // Revoked keys and their sessions are denied on the next lookup
+        cache.revoke_secret(&access_key).await.unwrap();
+        assert!(cache.get_key_perms(&access_key).await.is_none());
+        assert!(cache.get_secret(&access_key).await.is_err());
+        assert!(cache.get_key_perms(&session_key).await.is_none());
+
+        // New credentials replace the revoked ones
+        let (_, new_secret) = cache
+            .create_or_update_secret(&access_key, &user_id)
+            .await
+            .unwrap();
+        assert_ne!(new_secret, secret);
+        assert!(cache.get_key_perms(&access_key).await.is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_prefix_stats_under_churn() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let dataset = Object::initialize_now(
+            "dataset".to_string(),
+            ObjectType::Dataset,
+            Some(TypedRelation::Project(project.id)),
+        );
+        cache.upsert_object(project.clone()).await.unwrap();
+        cache.upsert_object(dataset.clone()).await.unwrap();
+
+        // Every worker creates objects of 10 bytes, grows every second one to 15 bytes
+        // and deletes every third one
+        let workers = (0..4)
+            .map(|worker| {
+                let cache = cache.clone();
+                let dataset_id = dataset.id;
+                tokio::spawn(async move {
+                    for idx in 0..50 {
+                        let object = Object::initialize_now(
+                            format!("file-{worker}-{idx}"),
+                            ObjectType::Object,
+                            Some(TypedRelation::Dataset(dataset_id)),
+                        );
+                        cache.upsert_object(object.clone()).await.unwrap();
+                        let location = ObjectLocation {
+                            id: DieselUlid::generate(),
+                            raw_content_len: 10,
+                            ..Default::default()
+                        };
+                        cache
+                            .add_location_with_binding(object.id, location.clone())
+                            .await
+                            .unwrap();
+                        if idx % 2 == 0 {
+                            let grown = ObjectLocation {
+                                raw_content_len: 15,
+                                ..location
+                            };
+                            cache.update_location(object.id, grown).await.unwrap();
+                        }
+                        if idx % 3 == 0 {
+                            cache.delete_object(object.id).await.unwrap();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.await.unwrap();
+        }
+
+        // Per worker 33 of 50 remain, 16 of them (even) with 15 bytes
+        let expected = PrefixStats {
+            object_count: 4 * 33,
+            total_size: 4 * (17 * 10 + 16 * 15),
+        };
+        assert_eq!(cache.get_prefix_stats(&dataset.id), Some(expected));
+        assert_eq!(cache.get_prefix_stats(&project.id), Some(expected));
+
+        // Only returned with read access on the node or an ancestor
+        let permissions = HashMap::from([(project.id, DbPermissionLevel::Read)]);
+        assert_eq!(
+            cache
+                .get_visible_prefix_stats(&dataset.id, &permissions)
+                .await,
+            Some(expected)
+        );
+        let permissions = HashMap::from([(DieselUlid::generate(), DbPermissionLevel::Admin)]);
+        assert_eq!(
+            cache
+                .get_visible_prefix_stats(&dataset.id, &permissions)
+                .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prefix_stats_follow_reparented_subtrees() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let source = Object::initialize_now(
+            "source".to_string(),
+            ObjectType::Collection,
+            Some(TypedRelation::Project(project.id)),
+        );
+        let target = Object::initialize_now(
+            "target".to_string(),
+            ObjectType::Collection,
+            Some(TypedRelation::Project(project.id)),
+        );
+        let mut dataset = Object::initialize_now(
+            "dataset".to_string(),
+            ObjectType::Dataset,
+            Some(TypedRelation::Collection(source.id)),
+        );
+        for resource in [&project, &source, &target, &dataset] {
+            cache.upsert_object(resource.clone()).await.unwrap();
+        }
+        let mut objects = Vec::new();
+        for (name, size) in [("a", 10), ("b", 20)] {
+            let object = Object::initialize_now(
+                name.to_string(),
+                ObjectType::Object,
+                Some(TypedRelation::Dataset(dataset.id)),
+            );
+            cache.upsert_object(object.clone()).await.unwrap();
+            let location = ObjectLocation {
+                id: DieselUlid::generate(),
+                raw_content_len: size,
+                ..Default::default()
+            };
+            cache
+                .add_location_with_binding(object.id, location)
+                .await
+                .unwrap();
+            objects.push(object);
+        }
+        let stats = |object_count, total_size| {
+            Some(PrefixStats {
+                object_count,
+                total_size,
+            })
+        };
+        assert_eq!(cache.get_prefix_stats(&source.id), stats(2, 30));
+
+        // The dataset moves with its objects, the project keeps its counters
+        dataset.parents = Some(HashSet::from([TypedRelation::Collection(target.id)]));
+        cache.upsert_object(dataset.clone()).await.unwrap();
+        assert_eq!(cache.get_prefix_stats(&source.id), stats(0, 0));
+        assert_eq!(cache.get_prefix_stats(&target.id), stats(2, 30));
+        assert_eq!(cache.get_prefix_stats(&dataset.id), stats(2, 30));
+        assert_eq!(cache.get_prefix_stats(&project.id), stats(2, 30));
+
+        // A single object moves directly below the project
+        let mut object = objects[1].clone();
+        object.parents = Some(HashSet::from([TypedRelation::Project(project.id)]));
+        cache.upsert_object(object).await.unwrap();
+        assert_eq!(cache.get_prefix_stats(&target.id), stats(1, 10));
+        assert_eq!(cache.get_prefix_stats(&dataset.id), stats(1, 10));
+        assert_eq!(cache.get_prefix_stats(&project.id), stats(2, 30));
+    }
 }
@@ -0,0 +1,50 @@
+use super::cache::Cache;
+use crate::shutdown::Shutdown;
+use crate::CONFIG;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, trace};
+
+/// Interval in which cached access keys are checked for expired validations
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Re-validates cached access keys against the server and evicts revoked ones
+///
+/// Keys are validated at most once per `credential_ttl`, revocations therefore take
+/// effect within the ttl instead of after the next restart. Expired session credentials
+/// are removed in the same loop.
+pub struct CredentialRefresher {
+    cache: Arc<Cache>,
+    shutdown: Shutdown,
+}
+
+impl CredentialRefresher {
+    pub fn new(cache: Arc<Cache>, shutdown: Shutdown) -> Self {
+        Self { cache, shutdown }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        let ttl = Duration::from_secs(CONFIG.proxy.credential_ttl);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = self.shutdown.signaled() => {
+                    trace!("credential refresher stopped");
+                    return Ok(());
+                }
+            }
+            match self.cache.refresh_credentials(ttl).await {
+                Ok(0) => trace!("Cached credentials are valid"),
+                Ok(evicted) => info!(evicted, "Evicted revoked credentials"),
+                Err(e) => error!(error = ?e, msg = e.to_string()),
+            }
+            match self.cache.purge_expired_sessions().await {
+                Ok(0) => {}
+                Ok(purged) => info!(purged, "Purged expired session credentials"),
+                Err(e) => error!(error = ?e, msg = e.to_string()),
+            }
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use super::cache::Cache;
+use super::grpc_query_handler::{is_not_found, GrpcQueryHandler};
+use crate::structs::{Object, TypedId};
+use ahash::RandomState;
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use tracing::{debug, error, trace};
+
+/// Server side part of object deletions
+#[async_trait::async_trait]
+pub trait DeleteNotifier: Send + Sync {
+    /// Deletes the object on the server, objects the server does not know (anymore)
+    /// count as deleted so that failed deletions can be retried
+    async fn notify_delete(&self, object_id: DieselUlid, token: &str) -> Result<()>;
+
+    /// Deletes an empty dataset or collection on the server, unknown ones count as deleted
+    async fn notify_delete_parent(&self, parent: TypedId, token: &str) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+impl DeleteNotifier for GrpcQueryHandler {
+    async fn notify_delete(&self, object_id: DieselUlid, token: &str) -> Result<()> {
+        match self.delete_object(object_id, token).await {
+            Err(e) if is_not_found(&e) => {
+                debug!(?object_id, "Object is already deleted on the server");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    async fn notify_delete_parent(&self, parent: TypedId, token: &str) -> Result<()> {
+        let result = match &parent {
+            TypedId::Dataset(id) => self.delete_dataset(*id, token).await,
+            TypedId::Collection(id) => self.delete_collection(*id, token).await,
+            other => bail!("{other:?} is no dataset or collection"),
+        };
+        match result {
+            Err(e) if is_not_found(&e) => {
+                debug!(?parent, "Parent is already deleted on the server");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Deletes an object in the order server, backend data, cache
+///
+/// Running reads of the object finish first, reads started during the deletion wait
+/// for its outcome. A failed step leaves the remaining steps to a retry: before the
+/// data is deleted the object stays readable, afterwards it is hidden until it is
+/// removed from the cache. Objects that are not cached (anymore) count as deleted.
+#[tracing::instrument(level = "trace", skip(cache, notifier, token))]
+pub async fn delete_object(
+    cache: &Cache,
+    notifier: Option<&dyn DeleteNotifier>,
+    object_id: DieselUlid,
+    token: &str,
+) -> Result<()> {
+    let mut guard = cache.lock_deletion(object_id).await;
+    if cache.get_resource(&object_id).await.is_err() {
+        trace!(?object_id, "Object was deleted by a concurrent request");
+        return Ok(());
+    }
+    if let Some(notifier) = notifier {
+        notifier
+            .notify_delete(object_id, token)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+    }
+    cache.delete_object_locked(object_id, &mut guard).await
+}
+
+/// Removes the parents of a deleted object (dataset first, then collection) that were
+/// created automatically for its key and are empty now. Parents created by users and
+/// parents with other children are kept, as are all parents above them.
+#[tracing::instrument(level = "trace", skip(cache, notifier, parents, token))]
+pub async fn remove_empty_parents(
+    cache: &Cache,
+    notifier: Option<&dyn DeleteNotifier>,
+    parents: &[&Object],
+    token: &str,
+) -> Result<()> {
+    for parent in parents {
+        if !parent.dynamic {
+            break;
+        }
+        let typed_id = TypedId::from(*parent);
+        let (paths, _) = cache
+            .get_name_trees(&typed_id, parent.name.clone(), None)
+            .await;
+        if paths.iter().any(|path| cache.has_paths_below(path)) {
+            break;
+        }
+        if let Some(notifier) = notifier {
+            notifier.notify_delete_parent(typed_id, token).await?;
+        }
+        cache.delete_object(parent.id).await?;
+        debug!(id = ?parent.id, "Removed empty auto-created parent");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DataState {
+    Readable,
+    // The backend data is deleted, the object is not removed from the cache yet
+    Deleted,
+    Removed,
+}
+
+type Locks = Arc<DashMap<DieselUlid, Arc<RwLock<DataState>>, RandomState>>;
+
+/// Per object locks that order reads of the backend data against deletions
+///
+/// Reads hold the lock until the data is streamed, deletions wait for running reads
+/// and block new ones. Reads that waited for a deletion see whether the data is still
+/// there, so they either stream the whole object or fail with NoSuchKey up front.
+/// Entries only exist while the lock is held or deleted data is still cached.
+#[derive(Default)]
+pub struct ObjectLocks {
+    locks: Locks,
+}
+
+impl ObjectLocks {
+    fn lock(&self, id: DieselUlid) -> Arc<RwLock<DataState>> {
+        self.locks
+            .entry(id)
+            .or_insert_with(|| Arc::new(RwLock::new(DataState::Readable)))
+            .clone()
+    }
+
+    /// Read lock of the data, None if the data was deleted
+    pub async fn read(&self, id: DieselUlid) -> Option<ReadGuard> {
+        let guard = ReadGuard {
+            guard: Some(self.lock(id).read_owned().await),
+            id,
+            locks: self.locks.clone(),
+        };
+        guard.readable().then_some(guard)
+    }
+
+    pub async fn write(&self, id: DieselUlid) -> DeleteGuard {
+        DeleteGuard {
+            guard: Some(self.lock(id).write_owned().await),
+            id,
+            locks: self.locks.clone(),
+        }
+    }
+}
+
+fn release(locks: &Locks, id: &DieselUlid) {
+    // Deleted data stays marked until the object is removed from the cache
+    locks.remove_if(id, |_, lock| {
+        Arc::strong_count(lock) == 1
+            && lock
+                .try_read()
+                .is_ok_and(|state| *state != DataState::Deleted)
+    });
+}
+
+/// Keeps the data of an object from being deleted
+pub struct ReadGuard {
+    guard: Option<OwnedRwLockReadGuard<DataState>>,
+    id: DieselUlid,
+    locks: Locks,
+}
+
+impl ReadGuard {
+    fn readable(&self) -> bool {
+        self.guard
+            .as_deref()
+            .is_some_and(|state| *state == DataState::Readable)
+    }
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+        release(&self.locks, &self.id);
+    }
+}
+
+/// Exclusive access to the data of an object during its deletion
+pub struct DeleteGuard {
+    guard: Option<OwnedRwLockWriteGuard<DataState>>,
+    id: DieselUlid,
+    locks: Locks,
+}
+
+impl DeleteGuard {
+    fn set(&mut self, state: DataState) {
+        if let Some(guard) = self.guard.as_deref_mut() {
+            *guard = state;
+        }
+    }
+
+    /// Whether the backend data was deleted by this or an earlier deletion
+    pub fn data_deleted(&self) -> bool {
+        self.guard
+            .as_deref()
+            .is_some_and(|state| *state != DataState::Readable)
+    }
+
+    pub fn set_data_deleted(&mut self) {
+        self.set(DataState::Deleted)
+    }
+
+    pub fn set_removed(&mut self) {
+        self.set(DataState::Removed)
+    }
+}
+
+impl Drop for DeleteGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+        release(&self.locks, &self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::data_backends::memory_backend::{BackendOperation, MemoryBackend};
+    use crate::data_backends::storage_backend::StorageBackend;
+    use crate::structs::{Object, ObjectLocation, ObjectType, TypedRelation};
+    use anyhow::bail;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Server that forgets deleted objects, the next notification fails if requested
+    #[derive(Default)]
+    struct StubNotifier {
+        deleted: DashMap<DieselUlid, ()>,
+        deleted_parents: DashMap<DieselUlid, ()>,
+        calls: AtomicUsize,
+        fail: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl DeleteNotifier for StubNotifier {
+        async fn notify_delete(&self, object_id: DieselUlid, _token: &str) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            if self.fail.swap(false, Ordering::Relaxed) {
+                bail!("Server unavailable")
+            }
+            self.deleted.insert(object_id, ());
+            Ok(())
+        }
+
+        async fn notify_delete_parent(&self, parent: TypedId, _token: &str) -> Result<()> {
+            self.deleted_parents.insert(parent.get_id(), ());
+            Ok(())
+        }
+    }
+
+    async fn setup() -> (Arc<Cache>, MemoryBackend, DieselUlid) {
+        let memory = MemoryBackend::new();
+        let backend: Arc<Box<dyn StorageBackend>> = Arc::new(Box::new(memory.clone()));
+        let (cache, _receiver) = test_cache(Some(backend)).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let object = Object::initialize_now(
+            "key".to_string(),
+            ObjectType::Object,
+            Some(TypedRelation::Project(project.id)),
+        );
+        cache.upsert_object(project).await.unwrap();
+        cache.upsert_object(object.clone()).await.unwrap();
+        let location = ObjectLocation {
+            bucket: "bucket".to_string(),
+            key: "key".to_string(),
+            raw_content_len: 10,
+            ..Default::default()
+        };
+        memory.insert(&location, vec![0; 10]);
+        cache
+            .add_location_with_binding(object.id, location)
+            .await
+            .unwrap();
+        (cache, memory, object.id)
+    }
+
+    fn is_cached(cache: &Cache) -> bool {
+        cache.get_path("bucket/key").is_some()
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let (cache, backend, id) = setup().await;
+        let notifier = StubNotifier::default();
+        delete_object(&cache, Some(&notifier), id, "token")
+            .await
+            .unwrap();
+        assert!(notifier.deleted.contains_key(&id));
+        assert_eq!(backend.calls(BackendOperation::Delete), 1);
+        assert_eq!(backend.object_count(), 0);
+        assert!(!is_cached(&cache));
+        assert!(cache.read_object_data(&id).await.is_none());
+        assert!(cache.object_locks.locks.is_empty());
+
+        // Repeated deletions succeed without touching server or backend
+        delete_object(&cache, Some(&notifier), id, "token")
+            .await
+            .unwrap();
+        assert_eq!(notifier.calls.load(Ordering::Relaxed), 1);
+        assert_eq!(backend.calls(BackendOperation::Delete), 1);
+        // The deletion event of the server is no error either
+        cache.delete_object(id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_failure() {
+        let (cache, backend, id) = setup().await;
+        let notifier = StubNotifier::default();
+        notifier.fail.store(true, Ordering::Relaxed);
+        assert!(delete_object(&cache, Some(&notifier), id, "token")
+            .await
+            .is_err());
+        // Nothing is deleted, the object is still readable
+        assert_eq!(backend.calls(BackendOperation::Delete), 0);
+        assert_eq!(backend.object_count(), 1);
+        assert!(is_cached(&cache));
+        assert!(cache.read_object_data(&id).await.is_some());
+
+        delete_object(&cache, Some(&notifier), id, "token")
+            .await
+            .unwrap();
+        assert_eq!(backend.calls(BackendOperation::Delete), 1);
+        assert!(!is_cached(&cache));
+    }
+
+    #[tokio::test]
+    async fn test_backend_failure() {
+        let (cache, backend, id) = setup().await;
+        let notifier = StubNotifier::default();
+        backend.fail_next(BackendOperation::Delete);
+        assert!(delete_object(&cache, Some(&notifier), id, "token")
+            .await
+            .is_err());
+        // Deleted on the server, the data is still there
+        assert!(notifier.deleted.contains_key(&id));
+        assert_eq!(backend.object_count(), 1);
+        assert!(is_cached(&cache));
+        assert!(cache.read_object_data(&id).await.is_some());
+
+        // The retry is not rejected by the server
+        delete_object(&cache, Some(&notifier), id, "token")
+            .await
+            .unwrap();
+        assert_eq!(notifier.calls.load(Ordering::Relaxed), 2);
+        // The failed call and the retry
+        assert_eq!(backend.calls(BackendOperation::Delete), 2);
+        assert_eq!(backend.object_count(), 0);
+        assert!(!is_cached(&cache));
+    }
+
+    #[tokio::test]
+    async fn test_removal_failure() {
+        let (cache, backend, id) = setup().await;
+        // The data is deleted, removing the object from the cache fails
+        let mut guard = cache.lock_deletion(id).await;
+        cache.delete_object_data(id).await.unwrap();
+        guard.set_data_deleted();
+        drop(guard);
+
+        // Hidden until the retry removes it, the data is not deleted twice
+        assert!(is_cached(&cache));
+        assert!(cache.read_object_data(&id).await.is_none());
+        delete_object(&cache, None, id, "token").await.unwrap();
+        assert_eq!(backend.calls(BackendOperation::Delete), 1);
+        assert!(!is_cached(&cache));
+        assert!(cache.object_locks.locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_waits_for_reads() {
+        let (cache, backend, id) = setup().await;
+        let read = cache.read_object_data(&id).await.unwrap();
+
+        let deletion = tokio::spawn({
+            let cache = cache.clone();
+            async move { delete_object(&cache, None, id, "token").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.calls(BackendOperation::Delete), 0);
+
+        // Reads started during the deletion see its outcome
+        let waiting = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.read_object_data(&id).await.is_some() }
+        });
+        drop(read);
+        deletion.await.unwrap().unwrap();
+        assert!(!waiting.await.unwrap());
+        assert_eq!(backend.calls(BackendOperation::Delete), 1);
+        assert!(cache.object_locks.locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_delete_keeps_reads() {
+        let (cache, backend, id) = setup().await;
+        backend.fail_next(BackendOperation::Delete);
+        let mut guard = cache.lock_deletion(id).await;
+        let waiting = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.read_object_data(&id).await.is_some() }
+        });
+        assert!(cache.delete_object_locked(id, &mut guard).await.is_err());
+        drop(guard);
+        // The data is still there, the waiting read streams it completely
+        assert_eq!(backend.object_count(), 1);
+        assert!(waiting.await.unwrap());
+    }
+
+    /// Auto-created collection and dataset of `bucket/col/ds/<name>` with an object for
+    /// every name
+    async fn nested(cache: &Cache, names: &[&str]) -> (Object, Object, Vec<DieselUlid>) {
+        let project = cache.get_path("bucket").unwrap();
+        let collection = Object::initialize_now(
+            "col".to_string(),
+            ObjectType::Collection,
+            Some(TypedRelation::Project(project)),
+        );
+        let dataset = Object::initialize_now(
+            "ds".to_string(),
+            ObjectType::Dataset,
+            Some(TypedRelation::Collection(collection.id)),
+        );
+        cache.upsert_object(collection.clone()).await.unwrap();
+        cache.upsert_object(dataset.clone()).await.unwrap();
+        let mut ids = Vec::new();
+        for name in names {
+            let object = Object::initialize_now(
+                name.to_string(),
+                ObjectType::Object,
+                Some(TypedRelation::Dataset(dataset.id)),
+            );
+            cache.upsert_object(object.clone()).await.unwrap();
+            ids.push(object.id);
+        }
+        (collection, dataset, ids)
+    }
+
+    #[tokio::test]
+    async fn test_remove_empty_parents() {
+        let (cache, _, _) = setup().await;
+        let (collection, dataset, ids) = nested(&cache, &["a", "sub/b"]).await;
+        assert_eq!(cache.get_path("bucket/col/ds/sub/b"), Some(ids[1]));
+        let notifier = StubNotifier::default();
+
+        // The dataset still has an object with a multi segment name
+        delete_object(&cache, Some(&notifier), ids[0], "token")
+            .await
+            .unwrap();
+        remove_empty_parents(&cache, Some(&notifier), &[&dataset, &collection], "token")
+            .await
+            .unwrap();
+        assert!(notifier.deleted_parents.is_empty());
+        assert!(cache.get_path("bucket/col/ds").is_some());
+
+        delete_object(&cache, Some(&notifier), ids[1], "token")
+            .await
+            .unwrap();
+        remove_empty_parents(&cache, Some(&notifier), &[&dataset, &collection], "token")
+            .await
+            .unwrap();
+        assert!(notifier.deleted_parents.contains_key(&dataset.id));
+        assert!(notifier.deleted_parents.contains_key(&collection.id));
+        assert!(cache.get_path("bucket/col/ds").is_none());
+        assert!(cache.get_path("bucket/col").is_none());
+        // The bucket is never removed
+        assert!(cache.get_path("bucket").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_keep_user_created_parents() {
+        let (cache, _, _) = setup().await;
+        let (mut collection, dataset, ids) = nested(&cache, &["a"]).await;
+        collection.dynamic = false;
+        cache.upsert_object(collection.clone()).await.unwrap();
+        let notifier = StubNotifier::default();
+
+        delete_object(&cache, Some(&notifier), ids[0], "token")
+            .await
+            .unwrap();
+        remove_empty_parents(&cache, Some(&notifier), &[&dataset, &collection], "token")
+            .await
+            .unwrap();
+        assert!(notifier.deleted_parents.contains_key(&dataset.id));
+        assert!(!notifier.deleted_parents.contains_key(&collection.id));
+        assert!(cache.get_path("bucket/col").is_some());
+    }
+}
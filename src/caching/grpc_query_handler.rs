@@ -1,9 +1,21 @@
+use crate::auth::clock::{ARUNA_ORIGIN, CLOCK_SKEW};
+use crate::caching::sync_state::SyncSnapshot;
+use crate::replication::checksum::ChecksumAlgorithm;
+use crate::replication::chunk_size::{ChunkSize, MAX_MESSAGE_SIZE};
+use crate::replication::peers::{PeerConnection, PeerSettings};
 use crate::replication::replication_handler::Direction;
 use crate::replication::replication_handler::ReplicationMessage;
+use crate::replication::server_copy;
+use crate::request_id;
 use crate::structs::Object as DPObject;
+use crate::structs::ObjectLocation;
 use crate::structs::ObjectType;
+use crate::structs::PendingNotification;
 use crate::structs::PubKey;
 use crate::structs::TypedRelation;
+use crate::structs::UserState;
+use crate::telemetry;
+use crate::CONFIG;
 use anyhow::anyhow;
 use anyhow::Result;
 use aruna_rust_api::api::dataproxy::services::v2::dataproxy_replication_service_client::DataproxyReplicationServiceClient;
@@ -34,6 +46,7 @@ use aruna_rust_api::api::storage::models::v2::Object;
 use aruna_rust_api::api::storage::models::v2::Project;
 use aruna_rust_api::api::storage::models::v2::Pubkey;
 use aruna_rust_api::api::storage::models::v2::ReplicationStatus;
+use aruna_rust_api::api::storage::models::v2::Status;
 use aruna_rust_api::api::storage::models::v2::User as GrpcUser;
 use aruna_rust_api::api::storage::services::v2::create_dataset_request;
 use aruna_rust_api::api::storage::services::v2::create_object_request;
@@ -44,6 +57,9 @@ use aruna_rust_api::api::storage::services::v2::CreateCollectionRequest;
 use aruna_rust_api::api::storage::services::v2::CreateDatasetRequest;
 use aruna_rust_api::api::storage::services::v2::CreateObjectRequest;
 use aruna_rust_api::api::storage::services::v2::CreateProjectRequest;
+use aruna_rust_api::api::storage::services::v2::DeleteCollectionRequest;
+use aruna_rust_api::api::storage::services::v2::DeleteDatasetRequest;
+use aruna_rust_api::api::storage::services::v2::DeleteObjectRequest;
 use aruna_rust_api::api::storage::services::v2::FinishObjectStagingRequest;
 use aruna_rust_api::api::storage::services::v2::FullSyncEndpointRequest;
 use aruna_rust_api::api::storage::services::v2::GetCollectionRequest;
@@ -83,9 +99,11 @@ use tonic::Streaming;
 use tracing::debug;
 use tracing::error;
 use tracing::trace;
+use tracing::warn;
 use tracing::Instrument;
 
 use super::cache::Cache;
+use super::webhooks;
 
 pub struct GrpcQueryHandler {
     project_service: ProjectServiceClient<Channel>,
@@ -100,6 +118,7 @@ pub struct GrpcQueryHandler {
     cache: Arc<Cache>,
     endpoint_id: String,
     long_lived_token: String,
+    peers: PeerSettings,
 }
 
 impl GrpcQueryHandler {
@@ -176,6 +195,7 @@ impl GrpcQueryHandler {
             cache,
             endpoint_id,
             long_lived_token,
+            peers: PeerSettings::from_config(),
         };
 
         let pks = handler
@@ -202,6 +222,8 @@ impl GrpcQueryHandler {
             e
         })?;
         md.append(key, value);
+        request_id::add_to_metadata(md);
+        telemetry::inject(md);
         Ok(())
     }
 
@@ -231,12 +253,12 @@ impl GrpcQueryHandler {
         Ok(user)
     }
     #[tracing::instrument(level = "trace", skip(self))]
-    async fn get_pubkeys(&self) -> Result<Vec<Pubkey>> {
+    pub(crate) async fn get_pubkeys(&self) -> Result<Vec<Pubkey>> {
         let mut req = Request::new(GetPubkeysRequest {});
 
         Self::add_token_to_md(req.metadata_mut(), &self.long_lived_token)?;
 
-        Ok(self
+        let response = self
             .storage_status_service
             .clone()
             .get_pubkeys(req)
@@ -244,9 +266,25 @@ impl GrpcQueryHandler {
             .map_err(|e| {
                 tracing::error!(error = ?e, msg = e.to_string());
                 e
-            })?
-            .into_inner()
-            .pubkeys)
+            })?;
+
+        // Use the response timestamp of the server to measure clock skew
+        if let Some(date) = response
+            .metadata()
+            .get("date")
+            .and_then(|d| d.to_str().ok())
+        {
+            CLOCK_SKEW.record_date_header(date, ARUNA_ORIGIN);
+        }
+
+        Ok(response.into_inner().pubkeys)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn check_clock_skew(&self) -> Result<()> {
+        // Cheap request, the skew is recorded from the response headers
+        self.get_pubkeys().await?;
+        Ok(())
     }
 
     #[tracing::instrument(level = "trace", skip(self, object, token))]
@@ -433,6 +471,63 @@ impl GrpcQueryHandler {
             })
     }
 
+    /// Fetches the current state of a resource, `None` if it does not exist (anymore).
+    /// Resources of unknown type are requested as every resource type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn fetch_resource(
+        &self,
+        id: &DieselUlid,
+        object_type: Option<ObjectType>,
+    ) -> Result<Option<DPObject>> {
+        let object_types = match object_type {
+            Some(object_type) => vec![object_type],
+            None => vec![
+                ObjectType::Project,
+                ObjectType::Collection,
+                ObjectType::Dataset,
+                ObjectType::Object,
+            ],
+        };
+        for object_type in object_types {
+            let fetched = match object_type {
+                ObjectType::Project => self
+                    .get_project(id, String::new())
+                    .await
+                    .map(DPObject::try_from),
+                ObjectType::Collection => self
+                    .get_collection(id, String::new())
+                    .await
+                    .map(DPObject::try_from),
+                ObjectType::Dataset => self
+                    .get_dataset(id, String::new())
+                    .await
+                    .map(DPObject::try_from),
+                ObjectType::Object => self
+                    .get_object(id, String::new())
+                    .await
+                    .map(DPObject::try_from),
+            };
+            match fetched {
+                Ok(object) => {
+                    let object = object?;
+                    if object.object_status == Status::Deleted {
+                        return Ok(None);
+                    }
+                    return Ok(Some(object));
+                }
+                Err(e) if is_not_found(&e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Fetches the hashes recorded by the server, independent of any other proxy
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_object_hashes(&self, id: &DieselUlid) -> Result<Vec<Hash>> {
+        Ok(self.get_object(id, String::new()).await?.hashes)
+    }
+
     #[tracing::instrument(level = "trace", skip(self, object, token))]
     pub async fn create_object(&self, object: DPObject, token: &str) -> Result<DPObject> {
         trace!(?object, "Creating object");
@@ -463,32 +558,34 @@ impl GrpcQueryHandler {
         Ok(object)
     }
 
-    #[tracing::instrument(level = "trace", skip(self, obj, token))]
+    /// Replaces the label `key` of the project, removes it if no value is provided
+    #[tracing::instrument(level = "trace", skip(self, obj, token, value))]
     pub async fn add_or_replace_key_value_project(
         &self,
         token: &str,
         obj: DPObject,
-        kv: Option<(&str, &str)>,
+        key: &str,
+        value: Option<&str>,
     ) -> Result<()> {
-        let remove_cors = obj
+        let remove_key_values = obj
             .key_values
             .iter()
-            .filter(|e| e.key == "app.aruna-storage.org/cors")
+            .filter(|e| e.key == key)
             .cloned()
             .collect();
 
         let mut req = Request::new(UpdateProjectKeyValuesRequest {
             project_id: obj.id.to_string(),
-            add_key_values: kv
-                .map(|(k, v)| {
+            add_key_values: value
+                .map(|v| {
                     vec![KeyValue {
-                        key: k.to_string(),
+                        key: key.to_string(),
                         value: v.to_string(),
                         variant: KeyValueVariant::Label as i32,
                     }]
                 })
                 .unwrap_or_default(),
-            remove_key_values: remove_cors,
+            remove_key_values,
         });
 
         Self::add_token_to_md(req.metadata_mut(), token)?;
@@ -504,6 +601,113 @@ impl GrpcQueryHandler {
         Ok(())
     }
 
+    /// Replaces the label `key` of an object in place, the object must not be finished yet
+    #[tracing::instrument(level = "trace", skip(self, obj, token, value))]
+    pub async fn add_or_replace_key_value_object(
+        &self,
+        token: &str,
+        obj: DPObject,
+        key: &str,
+        value: &str,
+    ) -> Result<DPObject> {
+        let remove_key_values = obj
+            .key_values
+            .iter()
+            .filter(|e| e.key == key)
+            .cloned()
+            .collect();
+
+        let mut inner_request = UpdateObjectRequest::from(obj);
+        inner_request.add_key_values = vec![KeyValue {
+            key: key.to_string(),
+            value: value.to_string(),
+            variant: KeyValueVariant::Label as i32,
+        }];
+        inner_request.remove_key_values = remove_key_values;
+
+        let mut req = Request::new(inner_request);
+
+        Self::add_token_to_md(req.metadata_mut(), token)?;
+
+        let response = self
+            .object_service
+            .clone()
+            .update_object(req)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?
+            .into_inner();
+
+        let object = DPObject::try_from(response.object.ok_or_else(|| {
+            error!(error = "response does not contain object");
+            anyhow!("response does not contain object")
+        })?)?;
+
+        self.cache.upsert_object(object.clone()).await?;
+
+        Ok(object)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn delete_object(&self, object_id: DieselUlid, token: &str) -> Result<()> {
+        let mut req = Request::new(DeleteObjectRequest {
+            object_id: object_id.to_string(),
+            with_revisions: false,
+        });
+
+        Self::add_token_to_md(req.metadata_mut(), token)?;
+
+        self.object_service
+            .clone()
+            .delete_object(req)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn delete_dataset(&self, dataset_id: DieselUlid, token: &str) -> Result<()> {
+        let mut req = Request::new(DeleteDatasetRequest {
+            dataset_id: dataset_id.to_string(),
+        });
+
+        Self::add_token_to_md(req.metadata_mut(), token)?;
+
+        self.dataset_service
+            .clone()
+            .delete_dataset(req)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn delete_collection(&self, collection_id: DieselUlid, token: &str) -> Result<()> {
+        let mut req = Request::new(DeleteCollectionRequest {
+            collection_id: collection_id.to_string(),
+        });
+
+        Self::add_token_to_md(req.metadata_mut(), token)?;
+
+        self.collection_service
+            .clone()
+            .delete_collection(req)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, object, token, force_update))]
     pub async fn init_object_update(
         &self,
@@ -586,69 +790,79 @@ impl GrpcQueryHandler {
         Ok(object)
     }
 
-    #[tracing::instrument(level = "trace", skip(self, proxy_object, token))]
-    pub async fn create_and_finish(
+    /// Finishes the object staging, if the server is not reachable the notification
+    /// is queued and retried in the background. The data is already stored, so the
+    /// object stays available through the proxy while the notification is pending.
+    #[tracing::instrument(level = "trace", skip(self, object, user_state, token))]
+    pub async fn finish_object_or_queue(
         &self,
-        proxy_object: DPObject,
+        object: DPObject,
         content_len: i64,
+        user_state: &UserState,
         token: &str,
     ) -> Result<DPObject> {
-        trace!(?proxy_object, "Creating and finishing object");
-
-        // Create Object in Aruna Server
-        let mut req = Request::new(CreateObjectRequest::from(proxy_object.clone()));
-
-        Self::add_token_to_md(req.metadata_mut(), token)?;
-
-        let server_object: DPObject = self
-            .object_service
-            .clone()
-            .create_object(req)
+        let notification =
+            PendingNotification::new(object.id, content_len, object.hashes.clone(), user_state);
+        let hashes = object.get_hashes();
+        match self
+            .finish_object(object.id, content_len, hashes, token)
             .await
-            .map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
-            })?
-            .into_inner()
-            .object
-            .ok_or_else(|| {
-                error!(error = "Object missing in CreateObjectResponse");
-                anyhow!("Object missing in CreateObjectResponse")
-            })?
-            .try_into()?;
-
-        let mut req = Request::new(FinishObjectStagingRequest {
-            object_id: server_object.id.to_string(),
-            content_len,
-            hashes: proxy_object.get_hashes(), // Hashes stay the same
-            completed_parts: vec![],
-        });
-
-        Self::add_token_to_md(req.metadata_mut(), token)?;
-
-        let response = self
-            .object_service
-            .clone()
-            .finish_object_staging(req)
-            .await
-            .map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
-            })?
-            .into_inner()
-            .object
-            .ok_or_else(|| {
-                error!(error = "Object missing in FinishObjectResponse");
-                anyhow!("Object missing in FinishObjectResponse")
-            })?;
-
-        // Id of location record should be set to Dataproxy Object id but is set to Server Object id... the fuck?
-        let object = DPObject::try_from(response)?;
-
-        // Persist Object and Location in cache/database
-        self.cache.upsert_object(object.clone()).await?;
+        {
+            Ok(object) => Ok(object),
+            Err(e) if is_transient(&e) => {
+                let Some(mut notification) = notification else {
+                    return Err(e);
+                };
+                warn!(object_id = ?object.id, error = ?e, "Queueing undelivered finish notification");
+                notification.schedule_retry(e.to_string());
+                self.cache.upsert_pending_notification(notification).await?;
+                // Keep the local hashes until the server confirms them
+                self.cache.upsert_object(object.clone()).await?;
+                Ok(object)
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-        Ok(object)
+    /// Creates the object of a new upload. If the server is not reachable the object is
+    /// kept (and can be downloaded) under its local id, it is created and finished in the
+    /// background. Returns None if the creation was queued.
+    #[tracing::instrument(level = "trace", skip(self, object, location, user_state, token))]
+    pub async fn create_object_or_queue(
+        &self,
+        object: DPObject,
+        location: ObjectLocation,
+        user_state: &UserState,
+        token: &str,
+    ) -> Result<Option<DPObject>> {
+        let notification = PendingNotification::new(
+            object.id,
+            location.raw_content_len,
+            object.hashes.clone(),
+            user_state,
+        );
+        match self.create_object(object.clone(), token).await {
+            Ok(mut created) => {
+                // Hashes stay the same
+                created.hashes = object.hashes;
+                Ok(Some(created))
+            }
+            Err(e) if is_transient(&e) => {
+                let Some(mut notification) = notification else {
+                    return Err(e);
+                };
+                warn!(object_id = ?object.id, error = ?e, "Queueing undelivered object creation");
+                self.cache.upsert_object(object.clone()).await?;
+                self.cache
+                    .add_location_with_binding(object.id, location)
+                    .await?;
+                notification.schedule_retry(e.to_string());
+                notification.create = Some(object);
+                self.cache.upsert_pending_notification(notification).await?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -671,49 +885,13 @@ impl GrpcQueryHandler {
 
         let mut inner_stream = stream.into_inner();
 
-        // Fullsync
-        let mut req = Request::new(FullSyncEndpointRequest {});
-        Self::add_token_to_md(req.metadata_mut(), &self.long_lived_token)?;
-        let mut full_sync_stream = self
-            .endpoint_service
-            .clone()
-            .full_sync_endpoint(req)
-            .await
-            .map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
-            })?
-            .into_inner();
-        let mut resources = Vec::new();
-        while let Some(full_sync_message) = full_sync_stream.message().await.map_err(|e| {
-            tracing::error!(error = ?e, msg = e.to_string());
-            e
-        })? {
-            debug!("received full_sync_message");
-            trace!(?full_sync_message);
-            match full_sync_message.target.ok_or_else(|| {
-                error!(error = "Missing target in full_sync");
-                anyhow!("Missing target in full_sync")
-            })? {
-                Target::GenericResource(GenericResource { resource: Some(r) }) => {
-                    resources.push(r);
-                }
-                Target::User(u) => self.cache.clone().upsert_user(u).await?,
-                Target::Pubkey(pk) => {
-                    self.cache.add_pubkey(pk.clone().into()).await?;
-                }
-                _ => (),
-            }
-        }
-
-        sort_resources(&mut resources);
-        for res in resources {
-            let object = DPObject::try_from(res)?;
-            self.cache.upsert_object(object).await?
-        }
+        // The cache was warm-started from the persistence and already serves requests,
+        // the complete server state is still pulled but only records that differ are
+        // rewritten (see `receive_full_sync`)
+        self.full_sync(CONFIG.proxy.full_resync).await?;
 
         let (keep_alive_tx, mut keep_alive_rx) = tokio::sync::mpsc::channel::<()>(1);
-        tokio::spawn(
+        crate::spawn(
             async move {
                 while keep_alive_rx.try_recv().is_ok() {
                     tokio::time::sleep(std::time::Duration::from_secs(60)).await;
@@ -726,11 +904,25 @@ impl GrpcQueryHandler {
         );
 
         debug!("querying events");
-        while let Some(m) = inner_stream.message().await? {
+        while let Some(m) = inner_stream.message().await.map_err(|e| {
+            self.cache
+                .sync_state
+                .error(format!("Event stream failed: {e}"));
+            e
+        })? {
+            // Pings confirm that no events were missed
+            self.cache.sync_state.event_processed();
             if let Some(message) = m.message {
                 debug!(?message, "received event message");
 
-                if let Ok(Some(r)) = self.process_message(message).await {
+                let processed = self.process_message(message).await.map_err(|e| {
+                    error!(error = ?e, msg = "Unable to process event message");
+                    self.cache
+                        .sync_state
+                        .error(format!("Unable to process event: {e}"));
+                    e
+                });
+                if let Ok(Some(r)) = processed {
                     let mut req = Request::new(AcknowledgeMessageBatchRequest { replies: vec![r] });
 
                     Self::add_token_to_md(req.metadata_mut(), &self.long_lived_token)?;
@@ -751,17 +943,89 @@ impl GrpcQueryHandler {
             }
         }
         error!("Stream was closed by sender");
+        self.cache
+            .sync_state
+            .error("Event stream was closed by the server");
         Err(anyhow!("Stream was closed by sender"))
     }
 
+    /// Pulls the complete state of this endpoint from the server. The cache keeps
+    /// serving requests while the sync is received and is only updated afterwards,
+    /// `full_resync` rewrites records that did not change.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn full_sync(&self, full_resync: bool) -> Result<()> {
+        let result = self.receive_full_sync().await;
+        let result = match result {
+            Ok(sync) => self.cache.clone().apply_full_sync(sync, full_resync).await,
+            Err(e) => Err(e),
+        };
+        result.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            self.cache
+                .sync_state
+                .error(format!("Full sync failed: {e}"));
+            e
+        })
+    }
+
+    /// Receives the complete endpoint state. This is not a delta sync: `FullSyncEndpointRequest`
+    /// has no since-timestamp, so every user, resource and pubkey is transferred on each
+    /// startup even if the persisted cache is current. Only the write side is incremental,
+    /// unchanged records are skipped in `Cache::apply_full_sync` and records missing from
+    /// the snapshot are pruned there.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn receive_full_sync(&self) -> Result<SyncSnapshot> {
+        let mut req = Request::new(FullSyncEndpointRequest {});
+        Self::add_token_to_md(req.metadata_mut(), &self.long_lived_token)?;
+        let mut full_sync_stream = self
+            .endpoint_service
+            .clone()
+            .full_sync_endpoint(req)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?
+            .into_inner();
+        let mut resources = Vec::new();
+        let mut sync = SyncSnapshot::default();
+        while let Some(full_sync_message) = full_sync_stream.message().await.map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })? {
+            debug!("received full_sync_message");
+            trace!(?full_sync_message);
+            match full_sync_message.target.ok_or_else(|| {
+                error!(error = "Missing target in full_sync");
+                anyhow!("Missing target in full_sync")
+            })? {
+                Target::GenericResource(GenericResource { resource: Some(r) }) => {
+                    resources.push(r);
+                }
+                Target::User(u) => sync.users.push(u),
+                Target::Pubkey(pk) => sync.pubkeys.push(PubKey::from(pk)),
+                _ => (),
+            }
+        }
+
+        sort_resources(&mut resources);
+        sync.resources = resources
+            .into_iter()
+            .map(DPObject::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(sync)
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn pull_replication(
         &self,
         init_request: PullReplicationRequest,
         endpoint_ulid: DieselUlid,
+        offer_server_copy: bool,
     ) -> Result<(
         Sender<PullReplicationRequest>,
         Streaming<PullReplicationResponse>,
+        ChecksumAlgorithm,
     )> {
         let get_ep_request = Request::new(GetEndpointRequest {
             endpoint: Some(Endpoint::EndpointId(endpoint_ulid.to_string())),
@@ -780,45 +1044,19 @@ impl GrpcQueryHandler {
             error!(error = "No endpoint found in GetEndpointResponse");
             anyhow!("No endpoint found in GetEndpointResponse")
         })?;
-        let config = endpoint
+        let announced = endpoint
             .host_configs
             .iter()
             .find(|config| config.host_variant() == EndpointHostVariant::Grpc)
-            .ok_or_else(|| {
-                error!(error = "No grpc config found for endpoint");
-                anyhow!("No grpc config found for endpoint")
-            })?;
-        let channel = if config.ssl {
-            let proxy_channel = Channel::from_shared(config.url.clone()).map_err(|e| {
-                tracing::error!(error = ?e, msg = e.to_string());
-                e
+            .map(|config| (config.url.as_str(), config.ssl));
+        let channel = PeerConnection::resolve(announced, self.peers.get(&endpoint_ulid))
+            .map_err(|e| anyhow!("Endpoint {endpoint_ulid}: {e}"))?
+            .connect()
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string(), endpoint = %endpoint_ulid);
+                anyhow!("Endpoint {endpoint_ulid}: {e}")
             })?;
-            let tls_config = ClientTlsConfig::new();
-            proxy_channel
-                .tls_config(tls_config)
-                .map_err(|e| {
-                    tracing::error!(error = ?e, msg = e.to_string());
-                    e
-                })?
-                .connect()
-                .await
-                .map_err(|e| {
-                    tracing::error!(error = ?e, msg = e.to_string());
-                    e
-                })?
-        } else {
-            Channel::from_shared(config.url.clone())
-                .map_err(|e| {
-                    tracing::error!(error = ?e, msg = e.to_string());
-                    e
-                })?
-                .connect()
-                .await
-                .map_err(|e| {
-                    tracing::error!(error = ?e, msg = e.to_string());
-                    e
-                })?
-        };
         let token = if let Some(auth) = self.cache.auth.read().await.as_ref() {
             auth.sign_dataproxy_token(endpoint_ulid)?
         } else {
@@ -827,19 +1065,35 @@ impl GrpcQueryHandler {
         };
 
         let dataproxy_service = DataproxyReplicationServiceClient::new(channel.clone())
-            .max_decoding_message_size(1024 * 1024 * 10);
+            .max_decoding_message_size(MAX_MESSAGE_SIZE);
         let (request_stream_sender, request_stream_receiver) = tokio::sync::mpsc::channel(1000);
         let mut req = Request::new(ReceiverStream::new(request_stream_receiver));
         Self::add_token_to_md(req.metadata_mut(), &token)?;
-        let response_stream = dataproxy_service
+        ChecksumAlgorithm::offer_to_metadata(req.metadata_mut());
+        ChunkSize::from_bytes(CONFIG.proxy.replication_chunk_size)
+            .add_to_metadata(req.metadata_mut());
+        if offer_server_copy {
+            server_copy::add_to_metadata(&server_copy::own_identities(), req.metadata_mut());
+        }
+        let response = dataproxy_service
             .clone()
             .pull_replication(req)
             .await
             .map_err(|e| {
                 tracing::error!(error = ?e, msg = e.to_string());
                 e
-            })?
-            .into_inner();
+            })?;
+        // Peers that do not answer the offer are legacy peers (md5 only)
+        let checksum_algorithm = ChecksumAlgorithm::from_metadata(response.metadata());
+        trace!(
+            chunk_size = ChunkSize::from_metadata(response.metadata()).bytes(),
+            "Negotiated replication chunk size"
+        );
+        trace!(
+            shared_backends = ?server_copy::from_metadata(response.metadata()),
+            "Negotiated server side copies"
+        );
+        let response_stream = response.into_inner();
         request_stream_sender
             .send(init_request)
             .await
@@ -847,7 +1101,7 @@ impl GrpcQueryHandler {
                 tracing::error!(error = ?e, msg = e.to_string());
                 e
             })?;
-        Ok((request_stream_sender, response_stream))
+        Ok((request_stream_sender, response_stream, checksum_algorithm))
     }
 
     #[tracing::instrument(level = "trace", skip(self, request))]
@@ -998,9 +1252,13 @@ impl GrpcQueryHandler {
             EventVariant::Deleted => {
                 trace!("deleting object");
                 if let Some(r) = event.resource {
-                    self.cache
-                        .delete_object(DieselUlid::from_str(&r.resource_id)?)
-                        .await?;
+                    let resource_id = DieselUlid::from_str(&r.resource_id)?;
+                    // Keys are only known while the object is still cached
+                    let events = webhooks::removed_events(&self.cache, resource_id).await;
+                    self.cache.delete_object(resource_id).await?;
+                    for (project_id, event) in events {
+                        webhooks::notify(&self.cache, project_id, event).await;
+                    }
                 }
             }
             _ => (),
@@ -1413,3 +1671,27 @@ pub fn sort_objects(res: &mut [DPObject]) {
         (ObjectType::Object, ObjectType::Object) => std::cmp::Ordering::Equal,
     })
 }
+
+/// Errors that are worth retrying later (server unavailable, timeouts, ...)
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<tonic::Status>() {
+        Some(status) => matches!(
+            status.code(),
+            tonic::Code::Unavailable
+                | tonic::Code::DeadlineExceeded
+                | tonic::Code::Unknown
+                | tonic::Code::Aborted
+                | tonic::Code::Cancelled
+                | tonic::Code::ResourceExhausted
+                | tonic::Code::Internal
+        ),
+        None => false,
+    }
+}
+
+/// The server does not know the requested resource
+pub(crate) fn is_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<tonic::Status>()
+        .is_some_and(|status| status.code() == tonic::Code::NotFound)
+}
@@ -0,0 +1,178 @@
+use super::cache::Cache;
+use super::deletion::{self, DeleteNotifier};
+use crate::shutdown::Shutdown;
+use crate::structs::{LifecycleConfiguration, ObjectRetention, ObjectType};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, trace, warn};
+
+/// Interval in which the buckets with lifecycle rules are swept
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Deletes objects that expired according to the lifecycle rules of their bucket
+///
+/// Candidates are collected from the cache first, before every deletion the object
+/// is looked up again so that objects re-uploaded during the sweep are kept.
+pub struct LifecycleHandler {
+    cache: Arc<Cache>,
+    shutdown: Shutdown,
+}
+
+impl LifecycleHandler {
+    pub fn new(cache: Arc<Cache>, shutdown: Shutdown) -> Self {
+        Self { cache, shutdown }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(SWEEP_INTERVAL) => {}
+                _ = self.shutdown.signaled() => {
+                    trace!("lifecycle handler stopped");
+                    return Ok(());
+                }
+            }
+            for (project_id, config) in self.cache.get_lifecycle_configs() {
+                if self.shutdown.is_triggered() {
+                    return Ok(());
+                }
+                if let Err(e) = self.sweep_bucket(project_id, &config).await {
+                    error!(error = ?e, msg = e.to_string());
+                }
+            }
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, config))]
+    async fn sweep_bucket(
+        &self,
+        project_id: DieselUlid,
+        config: &LifecycleConfiguration,
+    ) -> Result<()> {
+        let (project, _) = self.cache.get_resource_cloned(&project_id, true).await?;
+        let now = Utc::now();
+
+        let mut candidates = Vec::new();
+        for (key, id) in self.cache.get_path_range(&project.name, "") {
+            if self.is_expired(config, &project.name, &key, &id, now).await {
+                candidates.push((key, id));
+            }
+        }
+        debug!(
+            bucket = project.name,
+            expired = candidates.len(),
+            "Swept bucket"
+        );
+
+        for (key, id) in candidates {
+            // The object may have been re-uploaded since the candidates were collected
+            if !self
+                .is_expired(config, &project.name, &key, &id, Utc::now())
+                .await
+            {
+                trace!(
+                    bucket = project.name,
+                    key,
+                    "Object changed during sweep, skipping"
+                );
+                continue;
+            }
+            if let Err(e) = self.expire_object(&project.name, &key, id).await {
+                error!(error = ?e, bucket = project.name, key, msg = "Unable to expire object");
+            }
+        }
+        Ok(())
+    }
+
+    /// True if the path still points to this object and the object is expired
+    async fn is_expired(
+        &self,
+        config: &LifecycleConfiguration,
+        bucket: &str,
+        key: &str,
+        id: &DieselUlid,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if self.cache.get_path(&format!("{bucket}/{key}")) != Some(*id) {
+            return false;
+        }
+        let Ok((object, location)) = self.cache.get_resource_cloned(id, false).await else {
+            return false;
+        };
+        if object.object_type != ObjectType::Object {
+            return false;
+        }
+        // Locked objects are kept until their retention expires
+        if ObjectRetention::from_object(&object).is_some_and(|r| r.is_active(now)) {
+            debug!(bucket, key, "Object is locked, skipping expiration");
+            return false;
+        }
+        // Unfinished uploads are not expired
+        if location
+            .as_ref()
+            .map(|l| l.is_temporary || l.upload_id.is_some())
+            .unwrap_or(true)
+        {
+            return false;
+        }
+        let Some(last_modified) = object
+            .get_last_modified()
+            .and_then(|t| DateTime::from_timestamp(t.unix_timestamp(), 0))
+        else {
+            return false;
+        };
+        config.is_expired(key, last_modified, now)
+    }
+
+    async fn expire_object(&self, bucket: &str, key: &str, id: DieselUlid) -> Result<()> {
+        let (object, location) = self.cache.get_resource_cloned(&id, false).await?;
+        if location.as_ref().map(|l| l.ref_count > 1).unwrap_or(false) {
+            warn!(
+                bucket,
+                key, "Location is shared with other objects, skipping"
+            );
+            return Ok(());
+        }
+
+        // The server is notified first, a failed step keeps the object for the next sweep
+        let handler = self.cache.aruna_client.read().await.clone();
+        let token = match &handler {
+            Some(_) => {
+                let user_id = object
+                    .created_by
+                    .ok_or_else(|| anyhow!("Object has no creator"))?;
+                match self.cache.auth.read().await.as_ref() {
+                    Some(auth) => auth
+                        .sign_impersonating_token(user_id.to_string(), None::<String>)
+                        .map_err(|e| {
+                            error!(error = ?e, msg = e.to_string());
+                            e
+                        })?,
+                    None => return Err(anyhow!("Authentication handler not available")),
+                }
+            }
+            None => String::new(),
+        };
+        deletion::delete_object(
+            &self.cache,
+            handler.as_deref().map(|h| h as &dyn DeleteNotifier),
+            id,
+            &token,
+        )
+        .await?;
+        info!(
+            target: "access_log",
+            operation = "DeleteObject",
+            bucket,
+            key,
+            object_id = %id,
+            principal = "lifecycle",
+            "Expired object deleted"
+        );
+        Ok(())
+    }
+}
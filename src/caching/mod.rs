@@ -1,3 +1,13 @@
+pub mod access_cache;
 pub mod cache;
+pub mod credentials;
+pub mod deletion;
 pub mod grpc_query_handler;
+pub mod lifecycle;
+pub mod notification_retry;
+pub mod policies;
+pub mod pubkey_refresh;
+pub mod sync_state;
 pub mod transforms;
+pub mod usage;
+pub mod webhooks;
@@ -0,0 +1,154 @@
+use super::cache::Cache;
+use super::grpc_query_handler::GrpcQueryHandler;
+use super::policies::RETENTION_KEY;
+use super::webhooks;
+use crate::shutdown::Shutdown;
+use crate::structs::PendingNotification;
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, trace, warn};
+
+/// Interval in which due notifications are retried
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Redelivers FinishObjectStaging notifications that could not be sent to the
+/// Aruna server (creating the object first if CreateObject failed as well) and failed
+/// webhook events. Pending notifications are persisted (if a
+/// persistence is configured) and are picked up again after a restart.
+pub struct NotificationRetryHandler {
+    cache: Arc<Cache>,
+    shutdown: Shutdown,
+}
+
+impl NotificationRetryHandler {
+    pub fn new(cache: Arc<Cache>, shutdown: Shutdown) -> Self {
+        Self { cache, shutdown }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+                _ = self.shutdown.signaled() => {
+                    trace!("notification retry handler stopped");
+                    return Ok(());
+                }
+            }
+            for webhook in self.cache.get_due_webhooks() {
+                if let Err(e) = webhooks::deliver(&self.cache, webhook).await {
+                    error!(error = ?e, msg = e.to_string());
+                }
+            }
+            let due = self.cache.get_due_notifications();
+            if due.is_empty() {
+                continue;
+            }
+            debug!(
+                due = due.len(),
+                pending = self.cache.pending_notification_count(),
+                "Retrying server notifications"
+            );
+            for notification in due {
+                if let Err(e) = self.deliver(notification).await {
+                    error!(error = ?e, msg = e.to_string());
+                }
+            }
+        }
+    }
+
+    async fn deliver(&self, mut notification: PendingNotification) -> Result<()> {
+        let Some(handler) = self.cache.aruna_client.read().await.clone() else {
+            return Err(anyhow!("ArunaServer client not available"));
+        };
+        let token = match self.cache.auth.read().await.as_ref() {
+            Some(auth) => auth
+                .sign_impersonating_token(
+                    notification.user_id.to_string(),
+                    notification.access_key.clone(),
+                )
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?,
+            None => return Err(anyhow!("Authentication handler not available")),
+        };
+        if notification.create.is_some() {
+            notification = match self.create(&handler, notification, &token).await? {
+                Some(created) => created,
+                None => return Ok(()),
+            };
+        }
+
+        match handler
+            .finish_object(
+                notification.id,
+                notification.content_len,
+                notification.get_hashes(),
+                &token,
+            )
+            .await
+        {
+            Ok(_) => {
+                debug!(object_id = ?notification.id, "Delivered pending notification");
+                self.cache
+                    .remove_pending_notification(&notification.id)
+                    .await
+            }
+            Err(e) => {
+                warn!(
+                    object_id = ?notification.id,
+                    attempts = notification.attempts,
+                    error = ?e,
+                    "Notification delivery failed"
+                );
+                notification.schedule_retry(e.to_string());
+                self.cache.upsert_pending_notification(notification).await
+            }
+        }
+    }
+
+    /// Creates the object of a queued upload and replaces the local object, returns the
+    /// finish notification of the created object or None if the creation failed again
+    async fn create(
+        &self,
+        handler: &GrpcQueryHandler,
+        mut notification: PendingNotification,
+        token: &str,
+    ) -> Result<Option<PendingNotification>> {
+        let Some(local) = notification.create.clone() else {
+            return Ok(Some(notification));
+        };
+        let mut created = match handler.create_object(local.clone(), token).await {
+            Ok(created) => created,
+            Err(e) => {
+                warn!(
+                    object_id = ?local.id,
+                    attempts = notification.attempts,
+                    error = ?e,
+                    "Object creation failed"
+                );
+                notification.schedule_retry(e.to_string());
+                self.cache.upsert_pending_notification(notification).await?;
+                return Ok(None);
+            }
+        };
+        if let Some(retention) = local.key_values.iter().find(|kv| kv.key == RETENTION_KEY) {
+            created = handler
+                .add_or_replace_key_value_object(token, created, RETENTION_KEY, &retention.value)
+                .await?;
+        }
+        created.hashes = local.hashes;
+        debug!(local_id = ?local.id, object_id = ?created.id, "Created queued object");
+
+        let finish = notification.created(created.id);
+        // The finish is queued first, a restart in between can not lose the object
+        self.cache
+            .upsert_pending_notification(finish.clone())
+            .await?;
+        self.cache.replace_local_object(local.id, created).await?;
+        self.cache.remove_pending_notification(&local.id).await?;
+        Ok(Some(finish))
+    }
+}
@@ -0,0 +1,439 @@
+use crate::config::{ProjectWebhook, StorageOverride, StoragePolicy, DEFAULT_BACKEND};
+use crate::structs::{
+    BandwidthPolicy, BucketPolicy, CORSConfiguration, CollisionPolicy, LifecycleConfiguration,
+    Object, ObjectLockPolicy, ObjectType, QuotaPolicy, ReplicationPolicy, UploadLimitsPolicy,
+    ValidationSpec,
+};
+use crate::CONFIG;
+use aruna_rust_api::api::storage::models::v2::DataClass;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use tracing::{error, trace};
+
+pub const CORS_KEY: &str = "app.aruna-storage.org/cors";
+pub const CACHE_CONTROL_KEY: &str = "app.aruna-storage.org/cache-control";
+pub const LIFECYCLE_KEY: &str = "app.aruna-storage.org/lifecycle";
+pub const STORAGE_KEY: &str = "app.aruna-storage.org/storage";
+pub const QUOTA_KEY: &str = "app.aruna-storage.org/quota";
+pub const OBJECT_LOCK_KEY: &str = "app.aruna-storage.org/object-lock";
+pub const BANDWIDTH_KEY: &str = "app.aruna-storage.org/bandwidth";
+pub const UPLOAD_LIMITS_KEY: &str = "app.aruna-storage.org/upload-limits";
+pub const WEBHOOK_KEY: &str = "app.aruna-storage.org/webhook";
+pub const BUCKET_POLICY_KEY: &str = "app.aruna-storage.org/bucket-policy";
+pub const VALIDATION_KEY: &str = "app.aruna-storage.org/validation";
+pub const COLLISION_KEY: &str = "app.aruna-storage.org/collision";
+pub const REPLICATION_KEY: &str = "app.aruna-storage.org/replication";
+/// Object label, not a project policy
+pub const RETENTION_KEY: &str = "app.aruna-storage.org/retention";
+
+/// Resolves the storage format of a new location of an object in a project
+///
+/// `project` is the storage policy of the project (see `Cache::get_storage_policy`),
+/// `proxy` is the storage policy of the backend the location is created on.
+pub fn resolve_storage(
+    project: Option<&StoragePolicy>,
+    data_class: DataClass,
+    proxy: &StoragePolicy,
+) -> StorageOverride {
+    let proxy = proxy.for_class(data_class);
+    match project {
+        Some(project) => project.for_class(data_class).or(proxy),
+        None => proxy,
+    }
+}
+
+/// Resolves the named backend of a new location, None for the main backend
+pub fn resolve_backend(project: Option<&StoragePolicy>, data_class: DataClass) -> Option<String> {
+    resolve_storage(project, data_class, &CONFIG.backend.storage_policy())
+        .backend
+        .filter(|name| name != DEFAULT_BACKEND)
+}
+
+/// A policy artifact parsed from a project attribute
+///
+/// The artifact is only recompiled if the raw attribute value changes,
+/// invalid values are quarantined and the last valid version is kept.
+#[derive(Debug)]
+pub struct CompiledPolicy<T> {
+    raw: Option<String>,
+    compiled: Option<Arc<T>>,
+    error: Option<String>,
+    // Number of parsed raw values
+    compilations: usize,
+}
+
+impl<T> Default for CompiledPolicy<T> {
+    fn default() -> Self {
+        CompiledPolicy {
+            raw: None,
+            compiled: None,
+            error: None,
+            compilations: 0,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> CompiledPolicy<T> {
+    /// Returns true if the policy was recompiled
+    #[tracing::instrument(level = "trace", skip(self, raw))]
+    pub fn update(&mut self, raw: Option<&str>) -> bool {
+        if self.raw.as_deref() == raw {
+            return false;
+        }
+        self.raw = raw.map(|r| r.to_string());
+        match raw {
+            None => {
+                self.compiled = None;
+                self.error = None;
+            }
+            Some(raw) => {
+                self.compilations += 1;
+                match serde_json::from_str::<T>(raw) {
+                    Ok(compiled) => {
+                        self.compiled = Some(Arc::new(compiled));
+                        self.error = None;
+                    }
+                    Err(e) => {
+                        error!(error = ?e, msg = "Quarantined invalid policy, keeping last valid version");
+                        self.error = Some(e.to_string());
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.compiled.clone()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn compilations(&self) -> usize {
+        self.compilations
+    }
+}
+
+/// All compiled policy artifacts of a project
+#[derive(Debug, Default)]
+pub struct ProjectPolicies {
+    pub cors: CompiledPolicy<CORSConfiguration>,
+    pub lifecycle: CompiledPolicy<LifecycleConfiguration>,
+    pub storage: CompiledPolicy<StoragePolicy>,
+    pub quota: CompiledPolicy<QuotaPolicy>,
+    pub object_lock: CompiledPolicy<ObjectLockPolicy>,
+    pub bandwidth: CompiledPolicy<BandwidthPolicy>,
+    pub upload_limits: CompiledPolicy<UploadLimitsPolicy>,
+    pub webhook: CompiledPolicy<ProjectWebhook>,
+    pub bucket_policy: CompiledPolicy<BucketPolicy>,
+    pub validation: CompiledPolicy<ValidationSpec>,
+    pub collision: CompiledPolicy<CollisionPolicy>,
+    pub replication: CompiledPolicy<ReplicationPolicy>,
+    // Plain header value, used by public listeners
+    pub cache_control: Option<String>,
+}
+
+impl ProjectPolicies {
+    #[tracing::instrument(level = "trace", skip(self, project))]
+    pub fn update(&mut self, project: &Object) {
+        if project.object_type != ObjectType::Project {
+            return;
+        }
+        let raw_value = |key: &str| {
+            project
+                .key_values
+                .iter()
+                .find(|kv| kv.key == key)
+                .map(|kv| kv.value.as_str())
+        };
+        if self.cors.update(raw_value(CORS_KEY)) {
+            trace!(project = ?project.id, "Recompiled cors policy");
+        }
+        if self.lifecycle.update(raw_value(LIFECYCLE_KEY)) {
+            trace!(project = ?project.id, "Recompiled lifecycle policy");
+        }
+        if self.storage.update(raw_value(STORAGE_KEY)) {
+            trace!(project = ?project.id, "Recompiled storage policy");
+        }
+        if self.quota.update(raw_value(QUOTA_KEY)) {
+            trace!(project = ?project.id, "Recompiled quota policy");
+        }
+        if self.object_lock.update(raw_value(OBJECT_LOCK_KEY)) {
+            trace!(project = ?project.id, "Recompiled object lock policy");
+        }
+        if self.bandwidth.update(raw_value(BANDWIDTH_KEY)) {
+            trace!(project = ?project.id, "Recompiled bandwidth policy");
+        }
+        if self.upload_limits.update(raw_value(UPLOAD_LIMITS_KEY)) {
+            trace!(project = ?project.id, "Recompiled upload limits");
+        }
+        if self.webhook.update(raw_value(WEBHOOK_KEY)) {
+            trace!(project = ?project.id, "Recompiled webhook");
+        }
+        if self.bucket_policy.update(raw_value(BUCKET_POLICY_KEY)) {
+            trace!(project = ?project.id, "Recompiled bucket policy");
+        }
+        if self.validation.update(raw_value(VALIDATION_KEY)) {
+            trace!(project = ?project.id, "Recompiled validation spec");
+        }
+        if self.collision.update(raw_value(COLLISION_KEY)) {
+            trace!(project = ?project.id, "Recompiled collision policy");
+        }
+        if self.replication.update(raw_value(REPLICATION_KEY)) {
+            trace!(project = ?project.id, "Recompiled replication policy");
+        }
+        self.cache_control = raw_value(CACHE_CONTROL_KEY).map(|v| v.to_string());
+    }
+
+    /// Errors of quarantined policies as (attribute key, error)
+    pub fn errors(&self) -> Vec<(&'static str, String)> {
+        [
+            (CORS_KEY, self.cors.error()),
+            (LIFECYCLE_KEY, self.lifecycle.error()),
+            (STORAGE_KEY, self.storage.error()),
+            (QUOTA_KEY, self.quota.error()),
+            (OBJECT_LOCK_KEY, self.object_lock.error()),
+            (BANDWIDTH_KEY, self.bandwidth.error()),
+            (UPLOAD_LIMITS_KEY, self.upload_limits.error()),
+            (WEBHOOK_KEY, self.webhook.error()),
+            (BUCKET_POLICY_KEY, self.bucket_policy.error()),
+            (VALIDATION_KEY, self.validation.error()),
+            (COLLISION_KEY, self.collision.error()),
+            (REPLICATION_KEY, self.replication.error()),
+        ]
+        .into_iter()
+        .filter_map(|(key, error)| Some((key, error?.to_string())))
+        .collect()
+    }
+
+    /// Number of parsed attribute values over all policies, reads never parse
+    pub fn compilations(&self) -> usize {
+        self.cors.compilations()
+            + self.lifecycle.compilations()
+            + self.storage.compilations()
+            + self.quota.compilations()
+            + self.object_lock.compilations()
+            + self.bandwidth.compilations()
+            + self.upload_limits.compilations()
+            + self.webhook.compilations()
+            + self.bucket_policy.compilations()
+            + self.validation.compilations()
+            + self.collision.compilations()
+            + self.replication.compilations()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::{test_cache, Cache};
+    use crate::structs::{CollisionMode, PolicyAction};
+    use aruna_rust_api::api::storage::models::v2::{KeyValue, KeyValueVariant};
+    use diesel_ulid::DieselUlid;
+    use s3s::S3ErrorCode;
+
+    async fn cache() -> Arc<Cache> {
+        test_cache(None).await.0
+    }
+
+    fn project(id: DieselUlid, attributes: &[(&str, &str)]) -> Object {
+        Object {
+            id,
+            name: "bucket".to_string(),
+            object_type: ObjectType::Project,
+            key_values: attributes
+                .iter()
+                .map(|(key, value)| KeyValue {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    variant: KeyValueVariant::Label as i32,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    const DENY_ANONYMOUS: &str =
+        r#"{"deny": [{"principals": ["anonymous"], "actions": ["write"], "prefix": "raw/"}]}"#;
+    const DENY_EVERYONE: &str =
+        r#"{"deny": [{"principals": ["*"], "actions": ["read", "write"]}]}"#;
+
+    #[tokio::test]
+    async fn test_recompile_on_attribute_change() {
+        let cache = cache().await;
+        let id = DieselUlid::generate();
+        cache
+            .upsert_object(project(
+                id,
+                &[
+                    (BUCKET_POLICY_KEY, DENY_ANONYMOUS),
+                    (COLLISION_KEY, r#"{"mode": "reject"}"#),
+                    (VALIDATION_KEY, r#"{"extensions": ["csv"]}"#),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(cache.get_policy_compilations(&id), 3);
+        let policy = cache.get_bucket_policy(&id).unwrap();
+        assert!(policy.check(None, PolicyAction::Write, "raw/a").is_err());
+        assert!(policy.check(None, PolicyAction::Read, "raw/a").is_ok());
+        assert_eq!(
+            cache.get_collision_policy(&id).unwrap().mode,
+            CollisionMode::Reject
+        );
+
+        // Notifications without attribute changes do not recompile
+        cache
+            .upsert_object(project(
+                id,
+                &[
+                    (BUCKET_POLICY_KEY, DENY_ANONYMOUS),
+                    (COLLISION_KEY, r#"{"mode": "reject"}"#),
+                    (VALIDATION_KEY, r#"{"extensions": ["csv"]}"#),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(cache.get_policy_compilations(&id), 3);
+
+        // Changed attributes are recompiled, removed ones dropped
+        cache
+            .upsert_object(project(
+                id,
+                &[
+                    (BUCKET_POLICY_KEY, DENY_EVERYONE),
+                    (VALIDATION_KEY, r#"{"extensions": ["csv"]}"#),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(cache.get_policy_compilations(&id), 4);
+        let user_id = DieselUlid::generate();
+        let policy = cache.get_bucket_policy(&id).unwrap();
+        assert!(policy
+            .check(Some(&user_id), PolicyAction::Read, "a")
+            .is_err());
+        assert!(cache.get_collision_policy(&id).is_none());
+        assert!(cache.get_policy_errors(&id).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_keeps_last_valid_policy() {
+        let cache = cache().await;
+        let id = DieselUlid::generate();
+        cache
+            .upsert_object(project(id, &[(BUCKET_POLICY_KEY, DENY_ANONYMOUS)]))
+            .await
+            .unwrap();
+        let valid = cache.get_bucket_policy(&id).unwrap();
+
+        cache
+            .upsert_object(project(id, &[(BUCKET_POLICY_KEY, r#"{"deny": [{"#)]))
+            .await
+            .unwrap();
+        let errors = cache.get_policy_errors(&id);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, BUCKET_POLICY_KEY);
+        // The broken update does not break the project, the old policy is still served
+        let served = cache.get_bucket_policy(&id).unwrap();
+        assert!(Arc::ptr_eq(&valid, &served));
+        assert_eq!(
+            served
+                .check(None, PolicyAction::Write, "raw/a")
+                .unwrap_err()
+                .code(),
+            &S3ErrorCode::AccessDenied
+        );
+
+        // A fixed value replaces the old policy and clears the error
+        cache
+            .upsert_object(project(id, &[(BUCKET_POLICY_KEY, DENY_EVERYONE)]))
+            .await
+            .unwrap();
+        assert!(cache.get_policy_errors(&id).is_empty());
+        assert!(!Arc::ptr_eq(&valid, &cache.get_bucket_policy(&id).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_no_parsing_on_hot_path() {
+        let cache = cache().await;
+        let id = DieselUlid::generate();
+        cache
+            .upsert_object(project(
+                id,
+                &[
+                    (BUCKET_POLICY_KEY, DENY_ANONYMOUS),
+                    (VALIDATION_KEY, r#"{"content_types": ["text/csv"]}"#),
+                    (COLLISION_KEY, r#"{"mode": "overwrite"}"#),
+                    (REPLICATION_KEY, r#"{"allowed_endpoints": []}"#),
+                    (
+                        CORS_KEY,
+                        r#"[{"allowed_methods": ["GET"], "allowed_origins": ["*"], "max_age_seconds": 0}]"#,
+                    ),
+                ],
+            ))
+            .await
+            .unwrap();
+        let warmed_up = cache.get_policy_compilations(&id);
+        assert_eq!(warmed_up, 5);
+        assert!(cache.get_policy_errors(&id).is_empty());
+
+        // Everything a request reads from the compiled policies
+        let headers = http::HeaderMap::new();
+        for _ in 0..1000 {
+            let policy = cache.get_bucket_policy(&id).unwrap();
+            policy.check(None, PolicyAction::Read, "raw/a").unwrap();
+            cache
+                .get_validation_spec(&id)
+                .unwrap()
+                .validate("a.csv", Some("text/csv; charset=utf-8"), None)
+                .unwrap();
+            cache
+                .get_collision_policy(&id)
+                .unwrap()
+                .check("a", true)
+                .unwrap();
+            assert!(cache
+                .get_replication_policy(&id)
+                .unwrap()
+                .allows(&DieselUlid::generate()));
+            cache.get_cors_headers(&id, &http::Method::GET, &headers);
+        }
+        assert_eq!(cache.get_policy_compilations(&id), warmed_up);
+    }
+
+    #[tokio::test]
+    async fn test_storage_policy_is_per_cache() {
+        let (cache, other) = (cache().await, cache().await);
+        let id = DieselUlid::generate();
+        cache
+            .upsert_object(project(
+                id,
+                &[(
+                    STORAGE_KEY,
+                    r#"{"encryption": true, "data_classes": {"public": {"encryption": false}}}"#,
+                )],
+            ))
+            .await
+            .unwrap();
+        let names = [Some((id, "bucket".to_string())), None, None, None];
+        let proxy = StoragePolicy::default();
+
+        let storage = cache.get_storage_policy(&names);
+        let resolved = resolve_storage(storage.as_deref(), DataClass::Private, &proxy);
+        assert_eq!(resolved.encryption, Some(true));
+        let resolved = resolve_storage(storage.as_deref(), DataClass::Public, &proxy);
+        assert_eq!(resolved.encryption, Some(false));
+
+        // Another proxy in the same process does not see the policy
+        let storage = other.get_storage_policy(&names);
+        assert!(storage.is_none());
+        let resolved = resolve_storage(storage.as_deref(), DataClass::Private, &proxy);
+        assert_eq!(resolved.encryption, None);
+
+        cache.delete_object(id).await.unwrap();
+        assert!(cache.get_storage_policy(&names).is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use super::cache::Cache;
+use crate::structs::PubKey;
+use ahash::RandomState;
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use jsonwebtoken::DecodingKey;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{debug, error, info};
+
+/// On demand lookup of pubkeys with an unknown key id
+///
+/// Tokens signed with a key the server rotated in since the last sync would be
+/// rejected until the next announcement or full sync. Unknown key ids trigger a fetch
+/// of the current pubkey set instead. Fetches are serialized and rate-limited, key ids
+/// that are still unknown afterwards are not fetched again for a while so forged
+/// tokens can not hammer the server.
+pub struct PubkeyRefresh {
+    // Time of the last fetch, the lock serializes concurrent fetches
+    last_fetch: Mutex<Option<Instant>>,
+    // Key ids that were unknown to the server
+    unknown_kids: DashMap<i32, Instant, RandomState>,
+    min_interval: Duration,
+    negative_ttl: Duration,
+}
+
+impl PubkeyRefresh {
+    pub fn new(min_interval: Duration, negative_ttl: Duration) -> Self {
+        PubkeyRefresh {
+            last_fetch: Mutex::new(None),
+            unknown_kids: DashMap::default(),
+            min_interval,
+            negative_ttl,
+        }
+    }
+
+    /// Cached pubkey of the kid, unknown kids replace the cached pubkeys with the
+    /// result of `fetch` and are looked up once more
+    #[tracing::instrument(level = "trace", skip(self, cache, fetch))]
+    pub async fn resolve<F, Fut>(
+        &self,
+        cache: &Cache,
+        kid: i32,
+        fetch: F,
+    ) -> Result<(PubKey, DecodingKey)>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<PubKey>>>,
+    {
+        if let Ok(pubkey) = cache.get_pubkey(kid) {
+            return Ok(pubkey);
+        }
+        if let Some(since) = self.unknown_kids.get(&kid).map(|e| *e.value()) {
+            if since.elapsed() < self.negative_ttl {
+                error!(kid, "Pubkey is negatively cached");
+                bail!("Pubkey not found")
+            }
+        }
+
+        let mut last_fetch = self.last_fetch.lock().await;
+        // Fetched while waiting for a concurrent request
+        if let Ok(pubkey) = cache.get_pubkey(kid) {
+            return Ok(pubkey);
+        }
+        if last_fetch.is_some_and(|at| at.elapsed() < self.min_interval) {
+            error!(kid, "Pubkeys were fetched recently, skipping fetch");
+            bail!("Pubkey not found")
+        }
+        *last_fetch = Some(Instant::now());
+        debug!(kid, "Fetching pubkeys for unknown kid");
+        let pubkeys = fetch().await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        cache.set_pubkeys(pubkeys).await?;
+
+        match cache.get_pubkey(kid) {
+            Ok(pubkey) => {
+                info!(kid, "Fetched rotated pubkey");
+                self.unknown_kids.remove(&kid);
+                Ok(pubkey)
+            }
+            Err(e) => {
+                self.unknown_kids.insert(kid, Instant::now());
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::auth::ArunaTokenClaims;
+    use crate::auth::token_validation::{decode_claims, TokenAudience, USER_AUDIENCE};
+    use crate::caching::cache::test_cache;
+    use diesel_ulid::DieselUlid;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const OLD_KID: i32 = 1;
+    const NEW_KID: i32 = 2;
+
+    async fn cache() -> Arc<Cache> {
+        test_cache(None).await.0
+    }
+
+    fn pubkey(id: i32) -> PubKey {
+        let pem = include_str!("../../tests/test.pub");
+        PubKey {
+            id: id as i16,
+            key: pem
+                .lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect(),
+            is_proxy: false,
+            endpoint_id: None,
+        }
+    }
+
+    /// Server side pubkeys after the rotation, counts the fetches
+    async fn fetch(fetches: &AtomicUsize) -> Result<Vec<PubKey>> {
+        fetches.fetch_add(1, Ordering::Relaxed);
+        Ok(vec![pubkey(OLD_KID), pubkey(NEW_KID)])
+    }
+
+    fn token(kid: i32) -> String {
+        let key = EncodingKey::from_ed_pem(include_bytes!("../../tests/test.pem")).unwrap();
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(kid.to_string());
+        let claims = serde_json::json!({
+            "iss": "aruna",
+            "sub": DieselUlid::generate().to_string(),
+            "exp": chrono::Utc::now().timestamp() + 600,
+            "aud": USER_AUDIENCE,
+        });
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rotated_kid_is_fetched() {
+        let cache = cache().await;
+        cache.set_pubkeys(vec![pubkey(OLD_KID)]).await.unwrap();
+        let refresh = PubkeyRefresh::new(Duration::from_secs(60), Duration::from_secs(60));
+        let fetches = AtomicUsize::new(0);
+
+        // Known kids never fetch
+        refresh
+            .resolve(&cache, OLD_KID, || fetch(&fetches))
+            .await
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::Relaxed), 0);
+
+        // The server rotated its key, concurrent requests share one fetch
+        let (first, second) = tokio::join!(
+            refresh.resolve(&cache, NEW_KID, || fetch(&fetches)),
+            refresh.resolve(&cache, NEW_KID, || fetch(&fetches)),
+        );
+        let (pk, dec_key) = first.unwrap();
+        assert!(second.is_ok());
+        assert_eq!(pk.id, NEW_KID as i16);
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        assert!(decode_claims::<ArunaTokenClaims>(
+            &token(NEW_KID),
+            &dec_key,
+            TokenAudience::User,
+            60
+        )
+        .is_ok());
+        // Cached from now on
+        assert!(cache.get_pubkey(NEW_KID).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_forged_kid_fails_fast() {
+        let cache = cache().await;
+        cache.set_pubkeys(vec![pubkey(OLD_KID)]).await.unwrap();
+        let refresh = PubkeyRefresh::new(Duration::from_secs(60), Duration::from_secs(60));
+        let fetches = AtomicUsize::new(0);
+
+        assert!(refresh
+            .resolve(&cache, 999, || fetch(&fetches))
+            .await
+            .is_err());
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // Negatively cached and rate-limited, the server is not asked again
+        for kid in [999, 999, 998, 997] {
+            assert!(refresh
+                .resolve(&cache, kid, || fetch(&fetches))
+                .await
+                .is_err());
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // Keys of the fetch are usable nevertheless
+        assert!(refresh
+            .resolve(&cache, NEW_KID, || fetch(&fetches))
+            .await
+            .is_ok());
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refetch_after_interval() {
+        let cache = cache().await;
+        let refresh = PubkeyRefresh::new(Duration::ZERO, Duration::ZERO);
+        let fetches = AtomicUsize::new(0);
+        for _ in 0..2 {
+            assert!(refresh
+                .resolve(&cache, 999, || fetch(&fetches))
+                .await
+                .is_err());
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::metrics::CACHE_METRICS;
+use crate::structs::{Object, PubKey};
+use aruna_rust_api::api::storage::models::v2::User as GrpcUser;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Number of sync errors that are kept for the status endpoint
+const MAX_SYNC_ERRORS: usize = 100;
+
+/// State of a full sync that was received completely, applied to the cache at once
+#[derive(Debug, Default)]
+pub struct SyncSnapshot {
+    pub users: Vec<GrpcUser>,
+    pub resources: Vec<Object>,
+    pub pubkeys: Vec<PubKey>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncError {
+    pub timestamp: i64,
+    pub message: String,
+}
+
+/// Health of the synchronisation with the Aruna server
+#[derive(Debug, Default)]
+pub struct SyncState {
+    last_full_sync: AtomicI64,
+    last_event: AtomicI64,
+    error_count: AtomicU64,
+    errors: Mutex<VecDeque<SyncError>>,
+    resync_running: AtomicBool,
+}
+
+impl SyncState {
+    pub fn full_sync_finished(&self) {
+        let now = chrono::Utc::now().timestamp();
+        self.last_full_sync.store(now, Ordering::Relaxed);
+        CACHE_METRICS.last_sync.set(now);
+    }
+
+    /// Events and pings, both confirm that no events were missed
+    pub fn event_processed(&self) {
+        let now = chrono::Utc::now().timestamp();
+        self.last_event.store(now, Ordering::Relaxed);
+        CACHE_METRICS.last_sync.set(now);
+    }
+
+    pub fn error(&self, message: impl Into<String>) {
+        self.error_count.fetch_add(1, Ordering::Relaxed);
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        if errors.len() >= MAX_SYNC_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(SyncError {
+            timestamp: chrono::Utc::now().timestamp(),
+            message: message.into(),
+        });
+    }
+
+    /// Unix timestamp of the last completed full sync, 0 if none completed yet
+    pub fn last_full_sync(&self) -> i64 {
+        self.last_full_sync.load(Ordering::Relaxed)
+    }
+
+    /// Unix timestamp of the last event received from the server, 0 if none was received
+    pub fn last_event(&self) -> i64 {
+        self.last_event.load(Ordering::Relaxed)
+    }
+
+    /// All errors since the start of the proxy
+    pub fn error_count(&self) -> u64 {
+        self.error_count.load(Ordering::Relaxed)
+    }
+
+    /// The most recent errors, oldest first
+    pub fn errors(&self) -> Vec<SyncError> {
+        self.errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// False if a forced resync is already running
+    pub fn try_start_resync(&self) -> bool {
+        self.resync_running
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub fn resync_finished(&self) {
+        self.resync_running.store(false, Ordering::Release);
+    }
+
+    pub fn resync_running(&self) -> bool {
+        self.resync_running.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_are_capped() {
+        let state = SyncState::default();
+        for idx in 0..MAX_SYNC_ERRORS + 5 {
+            state.error(format!("error {idx}"));
+        }
+        let errors = state.errors();
+        assert_eq!(state.error_count(), MAX_SYNC_ERRORS as u64 + 5);
+        assert_eq!(errors.len(), MAX_SYNC_ERRORS);
+        assert_eq!(errors[0].message, "error 5");
+    }
+
+    #[test]
+    fn test_single_resync() {
+        let state = SyncState::default();
+        assert!(state.try_start_resync());
+        assert!(!state.try_start_resync());
+        assert!(state.resync_running());
+        state.resync_finished();
+        assert!(state.try_start_resync());
+    }
+}
@@ -0,0 +1,40 @@
+use super::cache::Cache;
+use crate::shutdown::Shutdown;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, trace};
+
+/// Interval in which the bucket usage counters are recomputed
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Periodically corrects drift of the incrementally maintained bucket usage
+pub struct UsageReconciler {
+    cache: Arc<Cache>,
+    shutdown: Shutdown,
+}
+
+impl UsageReconciler {
+    pub fn new(cache: Arc<Cache>, shutdown: Shutdown) -> Self {
+        Self { cache, shutdown }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(RECONCILE_INTERVAL) => {}
+                _ = self.shutdown.signaled() => {
+                    trace!("usage reconciler stopped");
+                    return Ok(());
+                }
+            }
+            let corrected = self.cache.reconcile_usage().await;
+            if corrected > 0 {
+                info!(corrected, "Corrected drifted bucket usage");
+            } else {
+                trace!("Bucket usage is consistent");
+            }
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use super::cache::Cache;
+use crate::config::{is_public_address, Webhook};
+use crate::metrics::WEBHOOK_DELIVERIES;
+use crate::request_id;
+use crate::scoped_config;
+use crate::structs::{Object, ObjectLocation, ObjectType, PendingWebhook, TypedId, WebhookEvent};
+use crate::CONFIG;
+use anyhow::anyhow;
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use diesel_ulid::DieselUlid;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info_span, trace, warn, Instrument};
+
+/// HMAC-SHA256 of the request body with the secret of the webhook, `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-Aruna-Signature";
+
+/// Queued events are left to the retry handler if the first attempt did not finish
+/// within this time (e.g. the proxy stopped)
+const FIRST_ATTEMPT_GRACE: i64 = 60;
+
+/// Webhook clients of a dataproxy, connections are not shared with other dataproxies
+pub struct WebhookClients {
+    /// Only connects to public addresses
+    public: reqwest::Client,
+    /// Client of the hosts in `webhooks.allowed_hosts`
+    allowed_hosts: reqwest::Client,
+}
+
+impl WebhookClients {
+    pub fn new() -> Result<Self> {
+        Ok(WebhookClients {
+            public: client(true)?,
+            allowed_hosts: client(false)?,
+        })
+    }
+}
+
+/// Redirects are never followed, they could point to internal addresses
+fn client(public_only: bool) -> Result<reqwest::Client> {
+    let builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    let builder = if public_only {
+        builder.dns_resolver(Arc::new(PublicResolver))
+    } else {
+        builder
+    };
+    Ok(builder.build()?)
+}
+
+/// Drops non-public addresses of webhook hosts. The check happens when the connection
+/// is established, a changed DNS record can not point a delivery to an internal service.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_address(&addr.ip()))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Event of an object of the current request
+pub fn object_event(
+    event_name: &str,
+    bucket: &str,
+    key: &str,
+    object: &Object,
+    location: Option<&ObjectLocation>,
+) -> WebhookEvent {
+    WebhookEvent {
+        event_name: event_name.to_string(),
+        event_time: Utc::now(),
+        request_id: request_id::current(),
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        size: object.get_raw_size(location),
+        etag: object.get_etag(location),
+        version_id: object.id.to_string(),
+    }
+}
+
+/// `ObjectRemoved:Delete` events for all keys of an object, collected before the
+/// object is removed from the cache
+pub async fn removed_events(
+    cache: &Cache,
+    object_id: DieselUlid,
+) -> Vec<(DieselUlid, WebhookEvent)> {
+    let Ok((object, location)) = cache.get_resource_cloned(&object_id, false).await else {
+        return Vec::new();
+    };
+    if object.object_type != ObjectType::Object {
+        return Vec::new();
+    }
+    let mut events = Vec::new();
+    for (_, prefix) in cache
+        .get_prefixes(&TypedId::Unknown(object_id), false)
+        .await
+    {
+        let path = format!("{prefix}/{}", object.name);
+        let Some((bucket, key)) = path.split_once('/') else {
+            continue;
+        };
+        let Some(project_id) = cache.get_path(bucket) else {
+            continue;
+        };
+        events.push((
+            project_id,
+            object_event(
+                "ObjectRemoved:Delete",
+                bucket,
+                key,
+                &object,
+                location.as_ref(),
+            ),
+        ));
+    }
+    events
+}
+
+/// Queues the event for the webhook of the project and delivers it right away,
+/// projects without a (matching) webhook are skipped
+#[tracing::instrument(level = "trace", skip(cache, event))]
+pub async fn notify(cache: &Arc<Cache>, project_id: DieselUlid, event: WebhookEvent) {
+    let Some(webhook) = cache.get_webhook(&project_id) else {
+        return;
+    };
+    if !webhook.matches(&event.event_name) {
+        trace!(event_name = event.event_name, "Event is not subscribed");
+        return;
+    }
+    let mut pending = PendingWebhook::new(project_id, event);
+    pending.next_attempt += chrono::Duration::seconds(FIRST_ATTEMPT_GRACE);
+    if let Err(e) = cache.upsert_pending_webhook(pending.clone()).await {
+        // Still delivered once, failures are queued again
+        error!(error = ?e, msg = "Unable to queue webhook event");
+    }
+    let cache = cache.clone();
+    crate::spawn(
+        request_id::in_current_request(async move {
+            if let Err(e) = deliver(&cache, pending).await {
+                error!(error = ?e, msg = e.to_string());
+            }
+        })
+        .instrument(info_span!("webhook_delivery")),
+    );
+}
+
+/// Posts a queued event, failures are rescheduled or moved to the dead letters
+#[tracing::instrument(level = "trace", skip(cache, pending))]
+pub async fn deliver(cache: &Cache, mut pending: PendingWebhook) -> Result<()> {
+    let Some(webhook) = cache.get_webhook(&pending.project_id) else {
+        debug!(id = ?pending.id, "Webhook was removed, dropping event");
+        WEBHOOK_DELIVERIES.with_label_values(&["dropped"]).inc();
+        return cache.remove_pending_webhook(&pending.id).await;
+    };
+    let body = serde_json::to_vec(&payload(&pending))?;
+    // Embedded proxies share the clients, the timeout is taken from their config
+    let result = match client_for(&webhook) {
+        Ok(client) => {
+            let mut request = client
+                .post(&webhook.url)
+                .timeout(Duration::from_secs(CONFIG.webhooks.timeout))
+                .header(reqwest::header::CONTENT_TYPE, "application/json");
+            if let Some(secret) = &webhook.secret {
+                request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+            }
+            // Redirects are not followed and count as failed deliveries
+            match request.body(body).send().await {
+                Ok(response) if response.status().is_success() => Ok(()),
+                Ok(response) => Err(format!("Unexpected status {}", response.status())),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    match result {
+        Ok(()) => {
+            debug!(id = ?pending.id, url = webhook.url, "Delivered webhook event");
+            WEBHOOK_DELIVERIES.with_label_values(&["delivered"]).inc();
+            cache.remove_pending_webhook(&pending.id).await
+        }
+        Err(e) => {
+            pending.schedule_retry(e.clone(), CONFIG.webhooks.max_attempts);
+            if pending.dead_letter {
+                error!(
+                    id = ?pending.id,
+                    project_id = ?pending.project_id,
+                    attempts = pending.attempts,
+                    error = %e,
+                    "Webhook delivery failed, moved to dead letters"
+                );
+                WEBHOOK_DELIVERIES.with_label_values(&["dead_letter"]).inc();
+            } else {
+                warn!(
+                    id = ?pending.id,
+                    attempts = pending.attempts,
+                    error = %e,
+                    "Webhook delivery failed"
+                );
+                WEBHOOK_DELIVERIES.with_label_values(&["failed"]).inc();
+            }
+            cache.upsert_pending_webhook(pending).await
+        }
+    }
+}
+
+/// Client for the host of the webhook, addresses in the url itself are not resolved
+/// and checked here
+fn client_for(webhook: &Webhook) -> Result<&'static reqwest::Client> {
+    webhook.validate(&CONFIG.webhooks)?;
+    let url = url::Url::parse(&webhook.url)?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("Webhook url without host"))?;
+    let clients = &scoped_config::state().webhook_clients;
+    Ok(if CONFIG.webhooks.is_allowed_host(host) {
+        &clients.allowed_hosts
+    } else {
+        &clients.public
+    })
+}
+
+/// Body in the shape of S3 event notifications, `eventId` identifies redeliveries
+fn payload(pending: &PendingWebhook) -> serde_json::Value {
+    let event = &pending.event;
+    serde_json::json!({
+        "Records": [{
+            "eventVersion": "2.1",
+            "eventSource": "aruna:s3",
+            "eventTime": event.event_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "eventName": event.event_name,
+            "eventId": pending.id.to_string(),
+            "responseElements": {
+                "x-amz-request-id": event.request_id,
+            },
+            "s3": {
+                "s3SchemaVersion": "1.0",
+                "bucket": {
+                    "name": event.bucket,
+                    "arn": format!("arn:aws:s3:::{}", event.bucket),
+                },
+                "object": {
+                    "key": event.key,
+                    "size": event.size,
+                    "eTag": event.etag.trim_matches('"'),
+                    "versionId": event.version_id,
+                },
+            },
+        }]
+    })
+}
+
+fn signature(secret: &str, body: &[u8]) -> String {
+    // Hmac accepts keys of any length
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProjectWebhook, Webhooks};
+    use std::collections::HashMap;
+
+    fn event(event_name: &str) -> WebhookEvent {
+        WebhookEvent {
+            event_name: event_name.to_string(),
+            event_time: chrono::DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            request_id: Some("req-1".to_string()),
+            bucket: "project".to_string(),
+            key: "coll/file.txt".to_string(),
+            size: 42,
+            etag: "\"abc\"".to_string(),
+            version_id: "01H8KWYY5MTAH1ZGZ71T0W6QAT".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let webhook: Webhook =
+            serde_json::from_str(r#"{"url": "https://hooks.example.org"}"#).unwrap();
+        assert!(webhook.matches("ObjectCreated:Put"));
+        assert!(!webhook.matches("ObjectRemoved:Delete"));
+
+        let webhook = Webhook {
+            events: vec![
+                "s3:ObjectRemoved:*".to_string(),
+                "ObjectCreated:CompleteMultipartUpload".to_string(),
+            ],
+            ..webhook
+        };
+        assert!(webhook.matches("ObjectRemoved:Delete"));
+        assert!(webhook.matches("ObjectCreated:CompleteMultipartUpload"));
+        assert!(!webhook.matches("ObjectCreated:Put"));
+        assert!(webhook.validate(&Webhooks::default()).is_ok());
+        assert!(Webhook {
+            url: "ftp://hooks.example.org".to_string(),
+            ..webhook
+        }
+        .validate(&Webhooks::default())
+        .is_err());
+    }
+
+    #[test]
+    fn test_internal_addresses() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://10.0.0.5/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:192.168.1.1]/hook",
+            "http://100.64.0.1/hook",
+            // NAT64 and 6to4 addresses of 10.0.0.5 and 127.0.0.1
+            "http://[64:ff9b::a00:5]/hook",
+            "http://[2002:7f00:1::1]/hook",
+        ] {
+            let webhook: Webhook =
+                serde_json::from_value(serde_json::json!({ "url": url })).unwrap();
+            assert!(webhook.validate(&Webhooks::default()).is_err(), "{url}");
+        }
+        for url in [
+            "https://93.184.216.34/hook",
+            "https://[64:ff9b::5db8:d822]/hook",
+            "https://[2002:5db8:d822::1]/hook",
+        ] {
+            let webhook: Webhook =
+                serde_json::from_value(serde_json::json!({ "url": url })).unwrap();
+            assert!(webhook.validate(&Webhooks::default()).is_ok(), "{url}");
+        }
+
+        // Internal receivers have to be allowed explicitly
+        let webhooks = Webhooks {
+            allowed_hosts: vec!["10.0.0.5".to_string(), "::1".to_string()],
+            ..Default::default()
+        };
+        for url in ["http://10.0.0.5/hook", "http://[::1]/hook"] {
+            let webhook: Webhook =
+                serde_json::from_value(serde_json::json!({ "url": url })).unwrap();
+            assert!(webhook.validate(&webhooks).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_project_webhook_secret_reference() {
+        // Secrets in project attributes are readable by all members
+        assert!(serde_json::from_str::<ProjectWebhook>(
+            r#"{"url": "https://hooks.example.org", "secret": "inline"}"#
+        )
+        .is_err());
+
+        let project: ProjectWebhook =
+            serde_json::from_str(r#"{"url": "https://hooks.example.org", "secret_ref": "lab"}"#)
+                .unwrap();
+        assert!(project.resolve(&Webhooks::default()).is_err());
+        let webhooks = Webhooks {
+            secrets: HashMap::from_iter([("lab".to_string(), "local".to_string())]),
+            ..Default::default()
+        };
+        let webhook = project.resolve(&webhooks).unwrap();
+        assert_eq!(webhook.secret.as_deref(), Some("local"));
+        assert_eq!(webhook.events, vec!["ObjectCreated:*".to_string()]);
+
+        let project: ProjectWebhook =
+            serde_json::from_str(r#"{"url": "http://192.168.0.10/hook"}"#).unwrap();
+        assert!(project.resolve(&webhooks).is_err());
+    }
+
+    #[test]
+    fn test_payload() {
+        let pending = PendingWebhook::new(DieselUlid::generate(), event("ObjectCreated:Put"));
+        let payload = payload(&pending);
+        let record = &payload["Records"][0];
+        assert_eq!(record["eventName"], "ObjectCreated:Put");
+        assert_eq!(record["eventTime"], "2024-03-01T12:00:00.000Z");
+        assert_eq!(record["eventId"], pending.id.to_string());
+        assert_eq!(record["responseElements"]["x-amz-request-id"], "req-1");
+        assert_eq!(record["s3"]["bucket"]["name"], "project");
+        assert_eq!(record["s3"]["object"]["key"], "coll/file.txt");
+        assert_eq!(record["s3"]["object"]["size"], 42);
+        assert_eq!(record["s3"]["object"]["eTag"], "abc");
+    }
+
+    #[test]
+    fn test_signature() {
+        // Reference value of `echo -n '{"a":1}' | openssl dgst -sha256 -hmac secret`
+        assert_eq!(
+            signature("secret", br#"{"a":1}"#),
+            "sha256=aa9e2e3575f5d7098b6caccd790888c36d5fdb63342a73bada2d6a51747a8494"
+        );
+    }
+
+    #[test]
+    fn test_dead_letter_after_max_attempts() {
+        let mut pending = PendingWebhook::new(DieselUlid::generate(), event("ObjectCreated:Put"));
+        pending.schedule_retry("503".to_string(), 3);
+        pending.schedule_retry("503".to_string(), 3);
+        assert!(!pending.dead_letter);
+        assert!(pending.next_attempt > Utc::now());
+        pending.schedule_retry("503".to_string(), 3);
+        assert!(pending.dead_letter);
+        assert_eq!(pending.last_error.as_deref(), Some("503"));
+    }
+}
@@ -1,24 +1,72 @@
+use crate::replication::chunk_size::MAX_MESSAGE_SIZE;
+use crate::structs::FileFormat;
 use anyhow::{anyhow, bail, Result};
+use aruna_rust_api::api::storage::models::v2::DataClass;
 use base64::engine::general_purpose;
 use base64::Engine;
 use diesel_ulid::DieselUlid;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub proxy: Proxy,
     pub persistence: Option<Persistence>,
     pub frontend: Option<Frontend>,
+    pub metrics: Option<Metrics>,
     pub backend: Backend,
+    /// Additional storage backends by name, new locations are routed to them by
+    /// the `backend` field of storage policies
+    #[serde(default)]
+    pub backends: HashMap<String, Backend>,
+    pub disk_cache: Option<DiskCache>,
+    pub scrubber: Option<Scrubber>,
+    pub footer_repair: Option<FooterRepair>,
+    pub parallel_get: Option<ParallelGet>,
+    /// Export of request traces to an OpenTelemetry collector
+    pub telemetry: Option<Telemetry>,
+    /// Connection settings of replication peers by endpoint id, reloaded from the config
+    /// file when it changes
+    #[serde(default)]
+    pub peers: HashMap<DieselUlid, Peer>,
+    /// Event notifications to external webhooks
+    #[serde(default)]
+    pub webhooks: Webhooks,
     pub rules: Vec<Rule>,
+    /// File the config was loaded from, rules can only be reloaded from a file
+    #[serde(skip)]
+    pub source: Option<String>,
 }
 
 impl Config {
+    /// Loads the config from the file in the `CONFIG` env var (default: config.toml),
+    /// `.env` is read first
+    pub fn from_env() -> Result<Self> {
+        dotenvy::from_filename(".env").ok();
+        let source = dotenvy::var("CONFIG").unwrap_or("config.toml".to_string());
+        let content = std::fs::read_to_string(&source)
+            .map_err(|e| anyhow!("Unable to read config {source}: {e}"))?;
+        let mut config: Config =
+            toml::from_str(&content).map_err(|e| anyhow!("Invalid config {source}: {e}"))?;
+        config.source = Some(source);
+        Ok(config)
+    }
+
     pub fn validate(&mut self) -> Result<()> {
         let Config {
             proxy,
             persistence,
             backend,
+            backends,
+            disk_cache,
+            scrubber,
+            footer_repair,
+            parallel_get,
+            telemetry,
+            peers,
+            webhooks,
             ..
         } = self;
 
@@ -27,8 +75,53 @@ impl Config {
             persistence.validate()?;
         }
         backend.validate()?;
+        for (name, named) in backends.iter_mut() {
+            if name == DEFAULT_BACKEND {
+                bail!("Backend name {DEFAULT_BACKEND} is reserved for the main backend");
+            }
+            named
+                .validate_named()
+                .map_err(|e| anyhow!("Backend {name}: {e}"))?;
+        }
+        let policy = backend.storage_policy();
+        for target in policy.backend_targets() {
+            if target != DEFAULT_BACKEND && !backends.contains_key(target) {
+                bail!("Unknown storage backend: {target}");
+            }
+        }
+        if let Some(disk_cache) = disk_cache {
+            disk_cache.validate()?;
+        }
+        if let Some(scrubber) = scrubber {
+            scrubber.validate()?;
+        }
+        if let Some(footer_repair) = footer_repair {
+            footer_repair.validate()?;
+        }
+        if let Some(parallel_get) = parallel_get {
+            parallel_get.validate()?;
+        }
+        if let Some(telemetry) = telemetry {
+            telemetry.validate()?;
+        }
+        for (id, peer) in peers.iter() {
+            peer.validate().map_err(|e| anyhow!("Peer {id}: {e}"))?;
+        }
+        for (id, webhook) in webhooks.projects.iter() {
+            webhook
+                .validate(webhooks)
+                .map_err(|e| anyhow!("Webhook of {id}: {e}"))?;
+        }
         Ok(())
     }
+
+    /// Storage identity of the main (`None`) or a named backend
+    pub fn backend_identity(&self, name: Option<&str>) -> Option<String> {
+        match name {
+            None => Some(self.backend.identity()),
+            Some(name) => self.backends.get(name).map(Backend::identity),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -42,6 +135,114 @@ pub struct Proxy {
     pub admin_ids: Vec<DieselUlid>,
     pub aruna_url: Option<String>,
     pub grpc_server: String,
+    /// Number of objects processed concurrently per pull replication
+    #[serde(default = "default_replication_concurrency")]
+    pub replication_concurrency: usize,
+    /// Requested chunk size for pulled replication streams in bytes (max 16 MiB)
+    #[serde(default = "default_replication_chunk_size")]
+    pub replication_chunk_size: usize,
+    /// Maximum number of chunk bytes buffered per replication stream
+    #[serde(default = "default_replication_max_inflight_bytes")]
+    pub replication_max_inflight_bytes: usize,
+    /// Copy objects of co-located proxies (same storage backend) on the backend instead
+    /// of streaming them, this shares the stored encryption keys with the peer
+    #[serde(default)]
+    pub replication_server_copy: bool,
+    /// Reject replicas of objects without a sha256 recorded on the server, verifying
+    /// them against the hash announced by the sending proxy is an explicit opt-in
+    #[serde(default = "default_replication_require_server_hash")]
+    pub replication_require_server_hash: bool,
+    /// Seconds in-flight requests and replications get to finish on shutdown
+    #[serde(default = "default_shutdown_grace_period")]
+    pub shutdown_grace_period: u64,
+    /// Seconds after which cached access keys are re-validated against the server
+    #[serde(default = "default_credential_ttl")]
+    pub credential_ttl: u64,
+    /// Seconds an unknown access key or token key id is not looked up again
+    #[serde(default = "default_credential_negative_ttl")]
+    pub credential_negative_ttl: u64,
+    /// Minimum seconds between on demand fetches of the pubkeys for unknown key ids
+    #[serde(default = "default_pubkey_fetch_interval")]
+    pub pubkey_fetch_interval: u64,
+    /// Seconds an object without a local location is not looked up again
+    #[serde(default = "default_location_negative_ttl")]
+    pub location_negative_ttl: u64,
+    /// Seconds successful object access checks are memoized, 0 disables the memoization
+    #[serde(default = "default_access_cache_ttl")]
+    pub access_cache_ttl: u64,
+    /// Maximum number of memoized object access checks
+    #[serde(default = "default_access_cache_size")]
+    pub access_cache_size: usize,
+    /// Rewrite every record during the startup reconciliation, not only changed ones
+    #[serde(default)]
+    pub full_resync: bool,
+    /// Seconds tokens are accepted after their expiry to tolerate clock skew,
+    /// widened by the skew measured against the server
+    #[serde(default = "default_token_leeway")]
+    pub token_leeway: u64,
+    /// Longest lifetime of temporary credentials minted with GetSessionToken in seconds
+    #[serde(default = "default_max_session_duration")]
+    pub max_session_duration: u64,
+}
+
+fn default_replication_concurrency() -> usize {
+    4
+}
+
+fn default_replication_chunk_size() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_replication_max_inflight_bytes() -> usize {
+    256 * 1024 * 1024
+}
+
+fn default_collapse_deep_keys() -> bool {
+    true
+}
+
+fn default_replication_require_server_hash() -> bool {
+    true
+}
+
+fn default_shutdown_grace_period() -> u64 {
+    30
+}
+
+fn default_credential_ttl() -> u64 {
+    60
+}
+
+fn default_credential_negative_ttl() -> u64 {
+    30
+}
+
+fn default_pubkey_fetch_interval() -> u64 {
+    10
+}
+
+fn default_location_negative_ttl() -> u64 {
+    10
+}
+
+fn default_access_cache_ttl() -> u64 {
+    30
+}
+
+fn default_access_cache_size() -> usize {
+    10_000
+}
+
+fn default_max_session_duration() -> u64 {
+    12 * 60 * 60
+}
+
+fn default_token_leeway() -> u64 {
+    crate::auth::clock::DEFAULT_LEEWAY_SECS
+}
+
+fn default_region() -> String {
+    "RegionOne".to_string()
 }
 
 impl Proxy {
@@ -70,6 +271,18 @@ impl Proxy {
             return Err(anyhow::anyhow!("serial must be at least 1"));
         }
 
+        if self.replication_concurrency < 1 {
+            return Err(anyhow::anyhow!(
+                "replication_concurrency must be at least 1"
+            ));
+        }
+
+        if self.replication_max_inflight_bytes < MAX_MESSAGE_SIZE {
+            return Err(anyhow::anyhow!(
+                "replication_max_inflight_bytes must be at least {MAX_MESSAGE_SIZE} bytes"
+            ));
+        }
+
         Ok(())
     }
 
@@ -163,6 +376,581 @@ impl Persistence {
 pub struct Frontend {
     pub server: String,
     pub hostname: String,
+    #[serde(default)]
+    pub listeners: Vec<Listener>,
+    /// Keep missing key segments in the object name instead of creating
+    /// intermediate collections/datasets (e.g. `dir/file` -> object "dir/file")
+    #[serde(default)]
+    pub flatten_keys: bool,
+    /// Keep key segments that exceed the hierarchy depth in the object name
+    /// (e.g. `col/ds/dir/file` -> object "dir/file"), otherwise such keys are rejected
+    #[serde(default = "default_collapse_deep_keys")]
+    pub collapse_deep_keys: bool,
+    /// Request limits per access key (or source ip for anonymous requests)
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// Download bandwidth per access key and project, projects can override it
+    /// with the `app.aruna-storage.org/bandwidth` attribute
+    #[serde(default)]
+    pub bandwidth_limits: BandwidthLimits,
+    /// Maximum sizes of uploads, projects can override them with the
+    /// `app.aruna-storage.org/upload-limits` attribute
+    #[serde(default)]
+    pub upload_limits: UploadLimits,
+    /// Serve `server` via https, certificates are reloaded when the files change
+    #[serde(default)]
+    pub tls: Option<Tls>,
+    /// Also list public projects of other users in ListBuckets
+    #[serde(default)]
+    pub list_public_buckets: bool,
+    /// Skip the write of uploads whose Content-MD5 or x-amz-checksum-sha256 matches the
+    /// stored data of the key, the existing object is returned instead
+    #[serde(default)]
+    pub deduplicate_uploads: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tls {
+    /// PEM encoded certificate chain
+    pub cert: String,
+    /// PEM encoded private key
+    pub key: String,
+    /// PEM encoded CA certificates, clients must present a certificate signed by one of them
+    #[serde(default)]
+    pub client_ca: Option<String>,
+    /// Additional plain http listener (e.g. for health checks)
+    #[serde(default)]
+    pub http_server: Option<String>,
+}
+
+/// Token bucket limits per request class
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimits {
+    #[serde(default = "default_read_limit")]
+    pub reads: RateLimit,
+    #[serde(default = "default_write_limit")]
+    pub writes: RateLimit,
+    #[serde(default = "default_listing_limit")]
+    pub listings: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            reads: default_read_limit(),
+            writes: default_write_limit(),
+            listings: default_listing_limit(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RateLimit {
+    /// Sustained requests per second, 0 disables the limit
+    pub requests_per_second: f64,
+    /// Requests that can be sent at once after an idle period
+    pub burst: f64,
+}
+
+fn default_read_limit() -> RateLimit {
+    RateLimit {
+        requests_per_second: 200.0,
+        burst: 400.0,
+    }
+}
+
+fn default_write_limit() -> RateLimit {
+    RateLimit {
+        requests_per_second: 100.0,
+        burst: 200.0,
+    }
+}
+
+fn default_listing_limit() -> RateLimit {
+    RateLimit {
+        requests_per_second: 20.0,
+        burst: 50.0,
+    }
+}
+
+/// Token bucket limits for response bodies, 0 disables a limit
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct BandwidthLimits {
+    /// Sustained bytes per second of all downloads of an access key
+    #[serde(default)]
+    pub per_key_bytes_per_second: u64,
+    /// Sustained bytes per second of all downloads from a project
+    #[serde(default)]
+    pub per_project_bytes_per_second: u64,
+    /// Bytes that can be sent at once after an idle period
+    #[serde(default = "default_bandwidth_burst")]
+    pub burst_bytes: u64,
+}
+
+impl Default for BandwidthLimits {
+    fn default() -> Self {
+        Self {
+            per_key_bytes_per_second: 0,
+            per_project_bytes_per_second: 0,
+            burst_bytes: default_bandwidth_burst(),
+        }
+    }
+}
+
+fn default_bandwidth_burst() -> u64 {
+    // 8 MiB
+    8 * 1024 * 1024
+}
+
+/// Size limits of request bodies in bytes, 0 disables a limit
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct UploadLimits {
+    /// Largest object that can be uploaded with a single PutObject
+    #[serde(default)]
+    pub max_object_size: u64,
+    /// Largest part of a multipart upload
+    #[serde(default = "default_max_part_size")]
+    pub max_part_size: u64,
+    /// Largest total size of a multipart upload
+    #[serde(default)]
+    pub max_multipart_size: u64,
+    /// Request data buffered in memory per upload: PutObject uploads the data to the
+    /// backend in parts of at most this size, UploadPart bodies have to fit into it
+    #[serde(default)]
+    pub max_inflight_bytes: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self {
+            max_object_size: 0,
+            max_part_size: default_max_part_size(),
+            max_multipart_size: 0,
+            max_inflight_bytes: 0,
+        }
+    }
+}
+
+fn default_max_part_size() -> u64 {
+    // 5 GiB, the S3 part size limit
+    5 * 1024 * 1024 * 1024
+}
+
+/// Separate listener serving Prometheus metrics on `/metrics` and the readiness on `/ready`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Metrics {
+    pub server: String,
+}
+
+/// Local LRU cache for objects read from the storage backend
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiskCache {
+    /// Directory for the cached files, should not be shared with other applications
+    pub path: String,
+    /// Maximum size of all cached files in bytes
+    pub max_size: u64,
+    /// Larger objects are always read from the backend
+    #[serde(default = "default_max_cacheable_size")]
+    pub max_object_size: u64,
+}
+
+fn default_max_cacheable_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl DiskCache {
+    fn validate(&self) -> Result<()> {
+        if self.path.is_empty() {
+            bail!("disk_cache.path cannot be empty")
+        }
+        if self.max_object_size > self.max_size {
+            bail!("disk_cache.max_object_size must not exceed disk_cache.max_size")
+        }
+        Ok(())
+    }
+}
+
+/// Background verification of stored data against the recorded disk hashes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Scrubber {
+    /// Number of locations verified per hour
+    #[serde(default = "default_scrub_objects_per_hour")]
+    pub objects_per_hour: u64,
+    /// Maximum read rate of the scrubber, burst is one second worth of bytes
+    #[serde(default = "default_scrub_bytes_per_sec")]
+    pub bytes_per_sec: u64,
+    /// Pull a good copy from another endpoint if the local data is corrupt
+    #[serde(default = "default_scrub_repair")]
+    pub repair: bool,
+}
+
+fn default_scrub_objects_per_hour() -> u64 {
+    3600
+}
+
+fn default_scrub_bytes_per_sec() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_scrub_repair() -> bool {
+    true
+}
+
+impl Scrubber {
+    fn validate(&self) -> Result<()> {
+        if self.objects_per_hour == 0 {
+            bail!("scrubber.objects_per_hour must be at least 1")
+        }
+        if self.bytes_per_sec == 0 {
+            bail!("scrubber.bytes_per_sec must be at least 1")
+        }
+        Ok(())
+    }
+}
+
+/// Background reconstruction of missing footers and disk metadata of legacy locations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FooterRepair {
+    /// Number of locations checked per hour
+    #[serde(default = "default_footer_repair_objects_per_hour")]
+    pub objects_per_hour: u64,
+}
+
+fn default_footer_repair_objects_per_hour() -> u64 {
+    600
+}
+
+impl FooterRepair {
+    fn validate(&self) -> Result<()> {
+        if self.objects_per_hour == 0 {
+            bail!("footer_repair.objects_per_hour must be at least 1")
+        }
+        Ok(())
+    }
+}
+
+/// Export of request traces via OTLP (gRPC)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Telemetry {
+    /// Collector endpoint, e.g. `http://localhost:4317`
+    pub otlp_endpoint: String,
+    #[serde(default = "default_telemetry_service_name")]
+    pub service_name: String,
+    /// Share of the traces started by this proxy that are exported, traces continued
+    /// from a caller (`traceparent`) follow the decision of the caller
+    #[serde(default = "default_telemetry_sample_rate")]
+    pub sample_rate: f64,
+    /// Exported spans and events (`target=level,...`), request spans are info spans
+    #[serde(default = "default_telemetry_filter")]
+    pub filter: String,
+}
+
+fn default_telemetry_service_name() -> String {
+    "aruna-dataproxy".to_string()
+}
+
+fn default_telemetry_sample_rate() -> f64 {
+    0.01
+}
+
+fn default_telemetry_filter() -> String {
+    [
+        "aos_data_proxy=info",
+        "aos_data_proxy::auth=debug",
+        "aos_data_proxy::caching::cache=trace",
+        "aos_data_proxy::caching::grpc_query_handler=trace",
+        "aos_data_proxy::caching::webhooks=trace",
+        "aos_data_proxy::data_backends=trace",
+    ]
+    .join(",")
+}
+
+impl Telemetry {
+    fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            bail!("telemetry.sample_rate must be between 0.0 and 1.0")
+        }
+        self.filter
+            .parse::<tracing_subscriber::filter::Targets>()
+            .map_err(|e| anyhow!("Invalid telemetry.filter: {e}"))?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Webhooks {
+    /// Failed deliveries are moved to the dead-letter list after this many attempts
+    #[serde(default = "default_webhook_attempts")]
+    pub max_attempts: u32,
+    /// Timeout of a single delivery in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// Webhooks by project id, take precedence over the `app.aruna-storage.org/webhook`
+    /// attribute of the project
+    #[serde(default)]
+    pub projects: HashMap<DieselUlid, Webhook>,
+    /// Hosts (names or addresses of webhook urls) that may resolve to private, loopback
+    /// or link-local addresses, all other webhooks are only delivered to public addresses
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// HMAC secrets by name, referenced by the `secret_ref` of project attribute webhooks
+    #[serde(default)]
+    pub secrets: HashMap<String, String>,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_webhook_attempts(),
+            timeout: default_webhook_timeout(),
+            projects: HashMap::new(),
+            allowed_hosts: Vec::new(),
+            secrets: HashMap::new(),
+        }
+    }
+}
+
+/// Debug output hides the secrets
+impl std::fmt::Debug for Webhooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhooks")
+            .field("max_attempts", &self.max_attempts)
+            .field("timeout", &self.timeout)
+            .field("projects", &self.projects)
+            .field("allowed_hosts", &self.allowed_hosts)
+            .field("secrets", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Webhooks {
+    /// True if the host of a webhook url may resolve to non-public addresses
+    pub fn is_allowed_host(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+}
+
+fn default_webhook_attempts() -> u32 {
+    10
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+/// Receiver of the object events of a project
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    /// Key of the `X-Aruna-Signature` HMAC-SHA256 header, events are not signed without it
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Event names, a trailing `*` matches all events with the prefix
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<String>,
+}
+
+fn default_webhook_events() -> Vec<String> {
+    vec!["ObjectCreated:*".to_string()]
+}
+
+/// Debug output hides the secret
+impl std::fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhook")
+            .field("url", &self.url)
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+impl Webhook {
+    /// Checks the scheme and rejects literal non-public addresses, names are checked
+    /// against the resolved addresses on every delivery
+    pub fn validate(&self, webhooks: &Webhooks) -> Result<()> {
+        let url = url::Url::parse(&self.url)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("Unsupported webhook scheme: {}", url.scheme());
+        }
+        let host = match url.host() {
+            Some(url::Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+            Some(url::Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+            Some(url::Host::Domain(_)) => None,
+            None => bail!("Webhook url without host"),
+        };
+        if let Some(ip) = host {
+            if !is_public_address(&ip)
+                && !url.host_str().is_some_and(|h| webhooks.is_allowed_host(h))
+            {
+                bail!("Webhook address {ip} is not public and not in webhooks.allowed_hosts");
+            }
+        }
+        Ok(())
+    }
+
+    /// Event names with or without the `s3:` prefix of AWS configurations are accepted
+    pub fn matches(&self, event_name: &str) -> bool {
+        self.events.iter().any(|filter| {
+            let filter = filter.strip_prefix("s3:").unwrap_or(filter);
+            match filter.strip_suffix('*') {
+                Some(prefix) => event_name.starts_with(prefix),
+                None => filter == event_name,
+            }
+        })
+    }
+}
+
+/// Webhook of the `app.aruna-storage.org/webhook` project attribute. Attributes are readable
+/// by every project member, so the HMAC secret is referenced by name instead of inlined.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectWebhook {
+    pub url: String,
+    /// Name of the secret in `webhooks.secrets`
+    #[serde(default)]
+    pub secret_ref: Option<String>,
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<String>,
+}
+
+impl ProjectWebhook {
+    /// Webhook with the referenced secret of the local config
+    pub fn resolve(&self, webhooks: &Webhooks) -> Result<Webhook> {
+        let secret = match &self.secret_ref {
+            Some(name) => Some(
+                webhooks
+                    .secrets
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown webhook secret: {name}"))?,
+            ),
+            None => None,
+        };
+        let webhook = Webhook {
+            url: self.url.clone(),
+            secret,
+            events: self.events.clone(),
+        };
+        webhook.validate(webhooks)?;
+        Ok(webhook)
+    }
+}
+
+/// False for loopback, private, link-local, shared, documentation, multicast and
+/// otherwise reserved addresses
+pub fn is_public_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Shared address space (carrier-grade NAT)
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking
+                || (a == 198 && (18..20).contains(&b))
+                // Reserved, including 255.255.255.255
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public_address(&IpAddr::V4(mapped));
+            }
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                is_public_address(&IpAddr::V4(Ipv4Addr::new(a, b, c, d)))
+            };
+            // NAT64 (64:ff9b::/96) embeds the IPv4 address in the last 32 bits
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return embedded(segments[6], segments[7]);
+            }
+            // 6to4 (2002::/16) embeds the IPv4 address in bits 16 to 48
+            if segments[0] == 0x2002 {
+                return embedded(segments[1], segments[2]);
+            }
+            let first = segments[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local
+                || (first & 0xfe00) == 0xfc00
+                // Link-local
+                || (first & 0xffc0) == 0xfe80
+                // Documentation
+                || (first == 0x2001 && segments[1] == 0x0db8))
+        }
+    }
+}
+
+/// Concurrent ranged backend reads for GetObject requests of large objects
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ParallelGet {
+    /// Objects with a smaller stored size are read sequentially
+    #[serde(default = "default_parallel_min_size")]
+    pub min_size: u64,
+    /// Size of the ranges read per backend request
+    #[serde(default = "default_parallel_part_size")]
+    pub part_size: u64,
+    /// Number of ranges read at once, also the number of parts buffered for reordering
+    #[serde(default = "default_parallel_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_parallel_min_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_parallel_part_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_parallel_concurrency() -> usize {
+    4
+}
+
+impl ParallelGet {
+    fn validate(&self) -> Result<()> {
+        if self.part_size == 0 {
+            bail!("parallel_get.part_size must be at least 1")
+        }
+        if self.concurrency < 2 {
+            bail!("parallel_get.concurrency must be at least 2")
+        }
+        Ok(())
+    }
+
+    /// True if a full read of an object with this stored size is split into ranges
+    pub fn applies(&self, disk_size: i64) -> bool {
+        disk_size > 0 && disk_size as u64 >= self.min_size.max(self.part_size + 1)
+    }
+}
+
+/// Additional S3 listener restricted by a policy profile
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Listener {
+    pub server: String,
+    pub hostname: String,
+    pub profile: ListenerProfile,
+    #[serde(default)]
+    pub tls: Option<Tls>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerProfile {
+    /// Anonymous only, read-only access to public resources (e.g. behind a CDN)
+    PublicReadOnly,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -178,6 +966,20 @@ pub enum Backend {
         dropbox_bucket: Option<String>,
         backend_scheme: String,
         tmp: Option<String>,
+        /// Distribution of new locations over buckets / key prefixes
+        #[serde(default)]
+        sharding: ShardingScheme,
+        /// Timeouts and retries of backend requests
+        #[serde(default)]
+        requests: RequestPolicy,
+        #[serde(default = "default_region")]
+        region: String,
+        /// Storage format overrides per data class
+        #[serde(default)]
+        data_classes: HashMap<String, StorageOverride>,
+        /// Content types / extensions that are (not) compressed
+        #[serde(default)]
+        compression_filter: Option<CompressionFilter>,
     },
     FileSystem {
         root_path: String,
@@ -186,18 +988,249 @@ pub enum Backend {
         dropbox_folder: Option<String>,
         backend_scheme: String,
         tmp: Option<String>, // Will default to /tmp
+        /// Storage format overrides per data class
+        #[serde(default)]
+        data_classes: HashMap<String, StorageOverride>,
+        /// Content types / extensions that are (not) compressed
+        #[serde(default)]
+        compression_filter: Option<CompressionFilter>,
     },
 }
 
+/// Timeouts and retries (exponential backoff with full jitter) of backend requests
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RequestPolicy {
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_secs: u64,
+    /// Maximum time until the first byte of a response is received
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout_secs: u64,
+    /// Attempts including the first request, 1 disables retries
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_backoff")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: default_connect_timeout(),
+            read_timeout_secs: default_read_timeout(),
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff(),
+            max_backoff_ms: default_max_backoff(),
+        }
+    }
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    60
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff() -> u64 {
+    100
+}
+
+fn default_max_backoff() -> u64 {
+    5000
+}
+
+/// Distribution of object data over backend buckets or key prefixes
+///
+/// The scheme is recorded on every location, changing it only affects new locations.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(tag = "scheme", rename_all = "kebab-case")]
+pub enum ShardingScheme {
+    /// Buckets and keys as derived from the backend_scheme
+    #[default]
+    Single,
+    /// `<bucket>-<shard>` sub-buckets selected by a hash of the object id
+    HashBucket { shards: u16 },
+    /// `<shard>/<key>` key prefixes selected by a hash of the object id
+    HashPrefix { shards: u16 },
+}
+
+impl ShardingScheme {
+    fn validate(&self) -> Result<()> {
+        match self {
+            Self::HashBucket { shards } | Self::HashPrefix { shards } if *shards == 0 => {
+                bail!("sharding.shards must be at least 1")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the scheme to the bucket and key derived from the backend_scheme
+    pub fn apply(&self, bucket: String, key: String, object_id: &DieselUlid) -> (String, String) {
+        match self {
+            Self::Single => (bucket, key),
+            Self::HashBucket { shards } => (
+                format!("{}-{:x}", bucket, shard_of(object_id, *shards)),
+                key,
+            ),
+            Self::HashPrefix { shards } => (
+                bucket,
+                format!("{:x}/{}", shard_of(object_id, *shards), key),
+            ),
+        }
+    }
+}
+
+/// Stable shard of an object, independent of the object name
+fn shard_of(object_id: &DieselUlid, shards: u16) -> u16 {
+    let digest = Sha256::digest(object_id.to_string().as_bytes());
+    u16::from_be_bytes([digest[0], digest[1]]) % shards.max(1)
+}
+
+/// Name of the main `[backend]`, locations without a backend name are stored there
+pub const DEFAULT_BACKEND: &str = "default";
+
+/// Storage format of new locations, unset fields are inherited from the next
+/// less specific level (project data class > project > proxy data class > proxy)
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct StorageOverride {
+    #[serde(default)]
+    pub encryption: Option<bool>,
+    #[serde(default)]
+    pub compression: Option<bool>,
+    /// Pithos files (with footer) are always encrypted, defaults to true if encrypted
+    #[serde(default)]
+    pub pithos: Option<bool>,
+    /// Named backend of new locations, see `[backends]`
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Content types / extensions that are (not) compressed, replaces less specific filters
+    #[serde(default)]
+    pub compression_filter: Option<CompressionFilter>,
+}
+
+impl StorageOverride {
+    pub fn or(self, fallback: StorageOverride) -> StorageOverride {
+        StorageOverride {
+            encryption: self.encryption.or(fallback.encryption),
+            compression: self.compression.or(fallback.compression),
+            pithos: self.pithos.or(fallback.pithos),
+            backend: self.backend.or(fallback.backend),
+            compression_filter: self.compression_filter.or(fallback.compression_filter),
+        }
+    }
+
+    /// Generates the file format (and a fresh key if encrypted) of a new location
+    pub fn file_format(&self, temp: bool) -> FileFormat {
+        let encryption = self.encryption.unwrap_or_default();
+        if temp {
+            // No pithos and compression for temp
+            return FileFormat::from_bools(false, encryption, false);
+        }
+        FileFormat::from_bools(
+            encryption && self.pithos.unwrap_or(true),
+            encryption,
+            self.compression.unwrap_or_default(),
+        )
+    }
+}
+
+/// Decides per upload whether compression is applied, entries are content types
+/// (`text/*`, `application/json`) or file extensions (`.bam`)
+///
+/// Denied entries are stored uncompressed, allowed entries are compressed even if the
+/// data looks compressed. Everything else is compressed unless the content type or the
+/// first bytes identify already compressed data (gzip / BGZF, zstd, zip, jpeg, ...).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct CompressionFilter {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// Storage format defaults and data class specific overrides
+///
+/// Data classes are referenced by their lowercase name (e.g. `public`).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct StoragePolicy {
+    #[serde(flatten)]
+    pub default: StorageOverride,
+    #[serde(default)]
+    pub data_classes: HashMap<String, StorageOverride>,
+}
+
+impl StoragePolicy {
+    pub fn validate(&self) -> Result<()> {
+        for class in self.data_classes.keys() {
+            if data_class_from_name(class).is_none() {
+                bail!("Unknown data class: {class}");
+            }
+        }
+        Ok(())
+    }
+
+    /// The override for a data class, falling back to the policy default
+    pub fn for_class(&self, data_class: DataClass) -> StorageOverride {
+        self.data_classes
+            .get(data_class_name(data_class))
+            .cloned()
+            .unwrap_or_default()
+            .or(self.default.clone())
+    }
+
+    /// All backend names this policy routes to
+    pub fn backend_targets(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(&self.default)
+            .chain(self.data_classes.values())
+            .filter_map(|o| o.backend.as_deref())
+    }
+}
+
+pub fn data_class_name(data_class: DataClass) -> &'static str {
+    match data_class {
+        DataClass::Unspecified => "unspecified",
+        DataClass::Public => "public",
+        DataClass::Private => "private",
+        DataClass::Workspace => "workspace",
+        DataClass::Confidential => "confidential",
+    }
+}
+
+fn data_class_from_name(name: &str) -> Option<DataClass> {
+    match name {
+        "unspecified" => Some(DataClass::Unspecified),
+        "public" => Some(DataClass::Public),
+        "private" => Some(DataClass::Private),
+        "workspace" => Some(DataClass::Workspace),
+        "confidential" => Some(DataClass::Confidential),
+        _ => None,
+    }
+}
+
 impl Backend {
     fn validate(&mut self) -> Result<()> {
+        self.storage_policy().validate()?;
         match self {
             Self::S3 {
                 access_key,
                 secret_key,
                 host,
+                sharding,
+                requests,
                 ..
             } => {
+                sharding.validate()?;
+                if requests.max_attempts == 0 {
+                    bail!("requests.max_attempts must be at least 1");
+                }
+
                 if let None = host {
                     let env_var = dotenvy::var("AWS_S3_HOST").map_err(|e| {
                         tracing::error!(error = ?e, msg = e.to_string());
@@ -228,6 +1261,26 @@ impl Backend {
         }
     }
 
+    /// Validates an additional backend, these have no environment fallbacks and
+    /// can not route to other backends
+    fn validate_named(&mut self) -> Result<()> {
+        if let Self::S3 {
+            host,
+            access_key,
+            secret_key,
+            ..
+        } = self
+        {
+            if host.is_none() || access_key.is_none() || secret_key.is_none() {
+                bail!("host, access_key and secret_key are required");
+            }
+        }
+        if self.storage_policy().backend_targets().next().is_some() {
+            bail!("Routing to other backends is only supported for the main backend");
+        }
+        self.validate()
+    }
+
     #[allow(dead_code)]
     pub fn get_tmp(&self) -> Option<String> {
         match self {
@@ -251,9 +1304,51 @@ impl Backend {
             Self::FileSystem { compression, .. } => *compression,
         }
     }
+
+    /// Hash of the underlying storage system, equal for proxies sharing the storage
+    pub fn identity(&self) -> String {
+        let storage = match self {
+            Self::S3 { host, .. } => format!(
+                "s3:{}",
+                host.as_deref().unwrap_or_default().trim_end_matches('/')
+            ),
+            Self::FileSystem { root_path, .. } => {
+                format!("fs:{}", root_path.trim_end_matches('/'))
+            }
+        };
+        hex::encode(Sha256::digest(storage.as_bytes()))
+    }
+
+    /// Proxy level storage policy, project policies take precedence
+    pub fn storage_policy(&self) -> StoragePolicy {
+        let (Self::S3 {
+            encryption,
+            compression,
+            data_classes,
+            compression_filter,
+            ..
+        }
+        | Self::FileSystem {
+            encryption,
+            compression,
+            data_classes,
+            compression_filter,
+            ..
+        }) = self;
+        StoragePolicy {
+            default: StorageOverride {
+                encryption: Some(*encryption),
+                compression: Some(*compression),
+                pithos: None,
+                backend: None,
+                compression_filter: compression_filter.clone(),
+            },
+            data_classes: data_classes.clone(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RuleTarget {
     ROOT, // Info
     OBJECT,
@@ -263,8 +1358,106 @@ pub enum RuleTarget {
     REPLICATIONOUT,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rule {
+    /// Identifier used in errors, logs and metrics, defaults to `<TARGET>-<index>`
+    #[serde(default)]
+    pub id: Option<String>,
     pub target: RuleTarget,
     pub rule: String,
+    /// Returned to the client instead of the generic message if the rule denies a request
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Denials are only logged and counted, the request is not rejected
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl Rule {
+    pub fn new(target: RuleTarget, rule: impl Into<String>) -> Self {
+        Rule {
+            id: None,
+            target,
+            rule: rule.into(),
+            message: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// Connection to a replication peer, unset fields fall back to the gRPC host config
+/// the server announces for the endpoint
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Peer {
+    /// gRPC url of the peer, e.g. an internal address
+    pub url: Option<String>,
+    /// Use TLS, defaults to the announced ssl flag (or the scheme of `url`)
+    pub tls: Option<bool>,
+    /// PEM bundle of the CAs that sign the certificate of the peer, system roots otherwise
+    pub ca_bundle: Option<String>,
+    /// Name the certificate is verified against, defaults to the host of the url
+    pub tls_domain: Option<String>,
+    /// HTTP proxy the connection is tunneled through (CONNECT), e.g. "http://proxy:3128"
+    pub proxy: Option<String>,
+}
+
+impl Peer {
+    fn validate(&self) -> Result<()> {
+        if let Some(url) = &self.url {
+            url.parse::<http::Uri>()
+                .map_err(|e| anyhow!("Invalid url {url}: {e}"))?;
+        }
+        if let Some(proxy) = &self.proxy {
+            let uri = proxy
+                .parse::<http::Uri>()
+                .map_err(|e| anyhow!("Invalid proxy {proxy}: {e}"))?;
+            if uri.scheme_str() != Some("http") || uri.host().is_none() {
+                bail!("Invalid proxy {proxy}: only http://<host>:<port> proxies are supported");
+            }
+        }
+        if self.tls == Some(false) && (self.ca_bundle.is_some() || self.tls_domain.is_some()) {
+            bail!("ca_bundle and tls_domain require tls");
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            if !std::path::Path::new(ca_bundle).is_file() {
+                bail!("CA bundle {ca_bundle} not found");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Only the peers of a config file, used to reload them at runtime
+#[derive(Debug, Deserialize)]
+pub struct PeersSource {
+    #[serde(default)]
+    pub peers: HashMap<DieselUlid, Peer>,
+}
+
+impl PeersSource {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read peers from {path}: {e}"))?;
+        let source: Self = toml::from_str(&content)
+            .map_err(|e| anyhow!("Unable to parse peers from {path}: {e}"))?;
+        for (id, peer) in source.peers.iter() {
+            peer.validate().map_err(|e| anyhow!("Peer {id}: {e}"))?;
+        }
+        Ok(source)
+    }
+}
+
+/// Only the rules of a config file, used to reload them at runtime
+#[derive(Debug, Deserialize)]
+pub struct RulesSource {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+impl RulesSource {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read rules from {path}: {e}"))?;
+        toml::from_str(&content).map_err(|e| anyhow!("Unable to parse rules from {path}: {e}"))
+    }
 }
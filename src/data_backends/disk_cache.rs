@@ -0,0 +1,478 @@
+use super::storage_backend::{parse_range, StorageBackend};
+use crate::config::{DiskCache, StoragePolicy};
+use crate::helpers::random_string;
+use crate::metrics::{DISK_CACHE_EVICTIONS, DISK_CACHE_REQUESTS, DISK_CACHE_SIZE};
+use crate::structs::{Object, ObjectLocation, PartETag, RestoreStatus};
+use anyhow::Result;
+use async_channel::{Receiver, Sender};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use diesel_ulid::DieselUlid;
+use digest::Digest;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::{debug, error, trace, warn};
+
+/// Size of the chunks read from cached files
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+struct CacheEntry {
+    version: String,
+    size: u64,
+    last_used: u64,
+}
+
+/// Cached files by name with their access order
+#[derive(Debug, Default)]
+struct LruState {
+    entries: HashMap<String, CacheEntry>,
+    // Access tick -> file name, least recently used first
+    order: BTreeMap<u64, String>,
+    total_size: u64,
+    tick: u64,
+}
+
+impl LruState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Marks the entry as used, false if it is missing or has another version
+    fn touch(&mut self, name: &str, version: &str) -> bool {
+        let tick = self.next_tick();
+        let Some(entry) = self.entries.get_mut(name) else {
+            return false;
+        };
+        if entry.version != version {
+            return false;
+        }
+        self.order.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.order.insert(tick, name.to_string());
+        true
+    }
+
+    fn insert(&mut self, name: String, version: String, size: u64) {
+        self.remove(&name);
+        let tick = self.next_tick();
+        self.order.insert(tick, name.clone());
+        self.entries.insert(
+            name,
+            CacheEntry {
+                version,
+                size,
+                last_used: tick,
+            },
+        );
+        self.total_size += size;
+    }
+
+    fn remove(&mut self, name: &str) -> bool {
+        let Some(entry) = self.entries.remove(name) else {
+            return false;
+        };
+        self.order.remove(&entry.last_used);
+        self.total_size -= entry.size;
+        true
+    }
+
+    /// Removes least recently used entries until the total size fits, returns their names
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total_size > max_size {
+            let Some((_, name)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&name) {
+                self.total_size -= entry.size;
+            }
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+/// Size bounded LRU cache on the local disk in front of another storage backend
+///
+/// Files are cached as stored in the backend (encrypted / compressed), only complete
+/// reads of finished locations populate the cache. Ranges of cached files are served
+/// from disk, writes and deletes of a location invalidate its file.
+#[derive(Debug)]
+pub struct DiskCacheBackend {
+    inner: Box<dyn StorageBackend>,
+    directory: PathBuf,
+    max_size: u64,
+    max_object_size: u64,
+    state: Mutex<LruState>,
+}
+
+impl DiskCacheBackend {
+    #[tracing::instrument(level = "debug", skip(inner))]
+    pub async fn new(inner: Box<dyn StorageBackend>, config: DiskCache) -> Result<Self> {
+        let directory = PathBuf::from(&config.path);
+        tokio::fs::create_dir_all(&directory).await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+
+        // Files of a previous run are not tracked, only files named by the cache are removed
+        let mut files = tokio::fs::read_dir(&directory).await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        while let Some(file) = files.next_entry().await? {
+            if is_cache_file(&file.file_name().to_string_lossy()) {
+                if let Err(e) = tokio::fs::remove_file(file.path()).await {
+                    warn!(error = ?e, msg = "Unable to remove stale cache file");
+                }
+            }
+        }
+        DISK_CACHE_SIZE.set(0);
+
+        Ok(Self {
+            inner,
+            directory,
+            max_size: config.max_size,
+            max_object_size: config.max_object_size,
+            state: Mutex::new(LruState::default()),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, LruState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn file_name(location: &ObjectLocation) -> String {
+        let path = match &location.backend {
+            Some(backend) => format!("{backend}:{}/{}", location.bucket, location.key),
+            None => format!("{}/{}", location.bucket, location.key),
+        };
+        hex::encode(Sha256::digest(path))
+    }
+
+    /// Changes whenever the stored content of the location changes
+    fn version(location: &ObjectLocation) -> String {
+        let hash = location
+            .disk_hash
+            .clone()
+            .or_else(|| location.etag.clone())
+            .unwrap_or_else(|| location.id.to_string());
+        format!("{}:{}", hash, location.disk_content_len)
+    }
+
+    /// Unfinished uploads and large objects are always read from the backend
+    fn is_cacheable(&self, location: &ObjectLocation) -> bool {
+        !location.is_temporary
+            && location.upload_id.is_none()
+            && location.disk_content_len > 0
+            && location.disk_content_len as u64 <= self.max_object_size
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn invalidate(&self, location: &ObjectLocation) {
+        let name = Self::file_name(location);
+        let removed = {
+            let mut state = self.state();
+            let removed = state.remove(&name);
+            DISK_CACHE_SIZE.set(state.total_size as i64);
+            removed
+        };
+        if removed {
+            trace!(bucket = location.bucket, key = location.key, "Invalidated");
+            if let Err(e) = tokio::fs::remove_file(self.directory.join(&name)).await {
+                warn!(error = ?e, msg = "Unable to remove invalidated cache file");
+            }
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn insert(&self, name: String, version: String, size: u64) {
+        let evicted = {
+            let mut state = self.state();
+            state.insert(name, version, size);
+            let evicted = state.evict(self.max_size);
+            DISK_CACHE_SIZE.set(state.total_size as i64);
+            evicted
+        };
+        for name in evicted {
+            DISK_CACHE_EVICTIONS.inc();
+            if let Err(e) = tokio::fs::remove_file(self.directory.join(&name)).await {
+                warn!(error = ?e, msg = "Unable to remove evicted cache file");
+            }
+        }
+    }
+
+    /// Streams the complete object from the backend and writes it to the cache on the way
+    #[tracing::instrument(level = "trace", skip(self, location, sender))]
+    async fn get_and_cache(
+        &self,
+        location: ObjectLocation,
+        name: String,
+        version: String,
+        sender: Sender<Result<bytes::Bytes, Box<dyn std::error::Error + Send + Sync>>>,
+    ) -> Result<()> {
+        let expected_size = location.disk_content_len as u64;
+        let tmp = self
+            .directory
+            .join(format!("{}.{}.tmp", name, random_string(8)));
+        let (tee_sender, tee_receiver) = async_channel::bounded(10);
+
+        let inner = self.inner.get_object(location, None, tee_sender);
+        let tee = async {
+            let mut file = match tokio::fs::File::create(&tmp).await {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    warn!(error = ?e, msg = "Unable to create cache file");
+                    None
+                }
+            };
+            let mut written = 0u64;
+            while let Ok(chunk) = tee_receiver.recv().await {
+                let failed = match (file.as_mut(), &chunk) {
+                    (Some(file), Ok(bytes)) => match file.write_all(bytes).await {
+                        Ok(()) => {
+                            written += bytes.len() as u64;
+                            false
+                        }
+                        Err(e) => {
+                            warn!(error = ?e, msg = "Unable to write cache file");
+                            true
+                        }
+                    },
+                    _ => false,
+                };
+                if failed {
+                    file = None;
+                }
+                sender.send(chunk).await.map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?;
+            }
+            Ok::<_, anyhow::Error>((file, written))
+        };
+        let (inner_result, tee_result) = tokio::join!(inner, tee);
+
+        match (inner_result, tee_result) {
+            (Ok(()), Ok((Some(mut file), written))) if written == expected_size => {
+                file.flush().await?;
+                drop(file);
+                match tokio::fs::rename(&tmp, self.directory.join(&name)).await {
+                    Ok(()) => self.insert(name, version, written).await,
+                    Err(e) => {
+                        warn!(error = ?e, msg = "Unable to store cache file");
+                        let _ = tokio::fs::remove_file(&tmp).await;
+                    }
+                }
+                Ok(())
+            }
+            (inner_result, tee_result) => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                inner_result?;
+                let (_, written) = tee_result?;
+                debug!(written, expected_size, "Incomplete read, not cached");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Cache files are named by a hex encoded sha256, temporary files have a suffix
+fn is_cache_file(name: &str) -> bool {
+    let hash = name.split('.').next().unwrap_or_default();
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[async_trait]
+impl StorageBackend for DiskCacheBackend {
+    #[tracing::instrument(level = "trace", skip(self, recv, location, content_len))]
+    async fn put_object(
+        &self,
+        recv: Receiver<Result<bytes::Bytes>>,
+        location: ObjectLocation,
+        content_len: i64,
+    ) -> Result<()> {
+        self.invalidate(&location).await;
+        self.inner.put_object(recv, location, content_len).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, range, sender))]
+    async fn get_object(
+        &self,
+        location: ObjectLocation,
+        range: Option<String>,
+        sender: Sender<Result<bytes::Bytes, Box<dyn std::error::Error + Send + Sync>>>,
+    ) -> Result<()> {
+        if !self.is_cacheable(&location) {
+            DISK_CACHE_REQUESTS.with_label_values(&["bypass"]).inc();
+            return self.inner.get_object(location, range, sender).await;
+        }
+        let name = Self::file_name(&location);
+        let version = Self::version(&location);
+
+        if self.state().touch(&name, &version) {
+            let section = match &range {
+                Some(range) => parse_range(range, location.disk_content_len as u64),
+                None => Ok((0, location.disk_content_len as u64)),
+            };
+            match (
+                section,
+                tokio::fs::File::open(self.directory.join(&name)).await,
+            ) {
+                (Ok((start, end)), Ok(mut file)) => {
+                    DISK_CACHE_REQUESTS.with_label_values(&["hit"]).inc();
+                    file.seek(SeekFrom::Start(start)).await?;
+                    let mut reader = file.take(end - start);
+                    loop {
+                        let mut buf = BytesMut::with_capacity(READ_CHUNK_SIZE);
+                        if reader.read_buf(&mut buf).await? == 0 {
+                            break;
+                        }
+                        sender.send(Ok(buf.freeze())).await.map_err(|e| {
+                            error!(error = ?e, msg = e.to_string());
+                            e
+                        })?;
+                    }
+                    return Ok(());
+                }
+                (Err(e), _) => {
+                    // e.g. multiple ranges, handled by the backend
+                    debug!(error = ?e, "Range not served from cache");
+                    DISK_CACHE_REQUESTS.with_label_values(&["bypass"]).inc();
+                    return self.inner.get_object(location, range, sender).await;
+                }
+                (_, Err(e)) => {
+                    warn!(error = ?e, msg = "Cached file is missing");
+                    self.invalidate(&location).await;
+                }
+            }
+        }
+
+        DISK_CACHE_REQUESTS.with_label_values(&["miss"]).inc();
+        match range {
+            // Partial reads are not cached
+            Some(range) => self.inner.get_object(location, Some(range), sender).await,
+            None => self.get_and_cache(location, name, version, sender).await,
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn head_object(&self, location: ObjectLocation) -> Result<i64> {
+        self.inner.head_object(location).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn init_multipart_upload(&self, location: ObjectLocation) -> Result<String> {
+        self.inner.init_multipart_upload(location).await
+    }
+
+    #[tracing::instrument(
+        level = "trace",
+        skip(self, recv, location, upload_id, content_len, part_number)
+    )]
+    async fn upload_multi_object(
+        &self,
+        recv: Receiver<Result<bytes::Bytes>>,
+        location: ObjectLocation,
+        upload_id: String,
+        content_len: i64,
+        part_number: i32,
+    ) -> Result<PartETag> {
+        self.inner
+            .upload_multi_object(recv, location, upload_id, content_len, part_number)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, parts, upload_id))]
+    async fn finish_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        parts: Vec<PartETag>,
+        upload_id: String,
+    ) -> Result<()> {
+        self.invalidate(&location).await;
+        self.inner
+            .finish_multipart_upload(location, parts, upload_id)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, upload_id))]
+    async fn abort_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()> {
+        self.inner.abort_multipart_upload(location, upload_id).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, bucket))]
+    async fn create_bucket(&self, bucket: String) -> Result<()> {
+        self.inner.create_bucket(bucket).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn create_location_bucket(&self, location: &ObjectLocation) -> Result<()> {
+        self.inner.create_location_bucket(location).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, source, target))]
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        self.invalidate(target).await;
+        self.inner.copy_object(source, target).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn delete_object(&self, location: ObjectLocation) -> Result<()> {
+        self.invalidate(&location).await;
+        self.inner.delete_object(location).await
+    }
+
+    fn supports_storage_class(&self, location: &ObjectLocation) -> bool {
+        self.inner.supports_storage_class(location)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_object(&self, location: &ObjectLocation, days: i32) -> Result<()> {
+        self.inner.restore_object(location, days).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_status(&self, location: &ObjectLocation) -> Result<Option<RestoreStatus>> {
+        self.inner.restore_status(location).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location(
+        &self,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        self.inner
+            .initialize_location(obj, expected_size, names, storage, temp)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location_on(
+        &self,
+        backend: Option<&str>,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        self.inner
+            .initialize_location_on(backend, obj, expected_size, names, storage, temp)
+            .await
+    }
+}
@@ -8,19 +8,22 @@ use digest::Digest;
 use futures_util::StreamExt;
 use md5::Md5;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::io::SeekFrom;
 use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::pin;
 
+use crate::caching::policies::resolve_storage;
 use crate::helpers::random_string;
-use crate::structs::FileFormat;
 use crate::{
-    config::Backend,
+    config::{Backend, StoragePolicy},
     structs::{Object, ObjectLocation, PartETag},
-    CONFIG,
 };
 
-use super::{location_handler::CompiledVariant, storage_backend::StorageBackend};
+use super::{
+    location_handler::CompiledVariant,
+    storage_backend::{parse_range, StorageBackend},
+};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -29,24 +32,21 @@ pub struct FSBackend {
     pub base_path: String,
     temp: String,
     schema: CompiledVariant,
-    use_pithos: bool,
-    encryption: bool,
-    compression: bool,
     dropbox: Option<String>,
+    storage: StoragePolicy,
 }
 
 impl FSBackend {
-    #[tracing::instrument(level = "debug")]
+    #[tracing::instrument(level = "debug", skip(config))]
     #[allow(dead_code)]
-    pub async fn new(_endpoint_id: String) -> Result<Self> {
+    pub async fn new(_endpoint_id: String, config: &Backend) -> Result<Self> {
         let Backend::FileSystem {
             root_path,
-            encryption,
-            compression,
             dropbox_folder,
             backend_scheme,
             tmp,
-        } = &CONFIG.backend
+            ..
+        } = config
         else {
             return Err(anyhow!("Invalid backend"));
         };
@@ -62,10 +62,8 @@ impl FSBackend {
             temp,
             base_path: root_path.clone(),
             schema: compiled_schema,
-            use_pithos: *encryption || *compression,
-            encryption: *encryption,
-            compression: *compression,
             dropbox: dropbox_folder.clone(),
+            storage: config.storage_policy(),
         };
         Ok(handler)
     }
@@ -127,17 +125,18 @@ impl StorageBackend for FSBackend {
         Ok(())
     }
 
-    // Downloads the given object from the s3 storage
-    // The body is wrapped into an async reader and reads the data in chunks.
+    // Downloads the given object from the filesystem
+    // The file is wrapped into a buffered reader and read in chunks, a single
+    // `bytes=` range limits the read to a section of the file.
     // The chunks are then transferred into the sender.
-    #[tracing::instrument(level = "trace", skip(self, location, _range, sender))]
+    #[tracing::instrument(level = "trace", skip(self, location, range, sender))]
     async fn get_object(
         &self,
         location: ObjectLocation,
-        _range: Option<String>,
+        range: Option<String>,
         sender: Sender<Result<bytes::Bytes, Box<dyn std::error::Error + Send + Sync>>>,
     ) -> Result<()> {
-        let file = tokio::fs::File::open(
+        let mut file = tokio::fs::File::open(
             Path::new(&self.base_path)
                 .join(&location.bucket)
                 .join(&location.key),
@@ -148,10 +147,21 @@ impl StorageBackend for FSBackend {
             e
         })?;
 
-        let mut reader = tokio::io::BufReader::new(file);
+        let len = file.metadata().await?.len();
+        let (start, end) = match &range {
+            Some(range) => parse_range(range, len)?,
+            None => (0, len),
+        };
+        file.seek(SeekFrom::Start(start)).await?;
+
+        let mut reader = tokio::io::BufReader::new(file).take(end - start);
         let mut buf = BytesMut::with_capacity(1024 * 16);
 
-        while reader.read_buf(&mut buf).await.is_ok() {
+        loop {
+            buf.reserve(1024 * 16);
+            if reader.read_buf(&mut buf).await? == 0 {
+                break;
+            }
             sender.send(Ok(buf.split().freeze())).await.map_err(|e| {
                 tracing::error!(error = ?e, msg = e.to_string());
                 e
@@ -304,11 +314,47 @@ impl StorageBackend for FSBackend {
         Ok(())
     }
 
+    #[tracing::instrument(level = "trace", skip(self, _location, upload_id))]
+    async fn abort_multipart_upload(
+        &self,
+        _location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()> {
+        // Remove the temp dir with all uploaded parts
+        tokio::fs::remove_dir_all(Path::new(&self.base_path).join(&upload_id))
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, bucket))]
     async fn create_bucket(&self, bucket: String) -> Result<()> {
         self.check_and_create_bucket(bucket).await
     }
 
+    #[tracing::instrument(level = "trace", skip(self, source, target))]
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        self.check_and_create_bucket(target.bucket.to_string())
+            .await?;
+        tokio::fs::copy(
+            Path::new(&self.base_path)
+                .join(&source.bucket)
+                .join(&source.key),
+            Path::new(&self.base_path)
+                .join(&target.bucket)
+                .join(&target.key),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, location))]
     /// Delete a object from the storage system
     /// # Arguments
@@ -332,11 +378,12 @@ impl StorageBackend for FSBackend {
         obj: &Object,
         expected_size: Option<i64>,
         names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
         temp: bool,
     ) -> Result<ObjectLocation> {
+        let storage = resolve_storage(storage, obj.data_class, &self.storage);
         if temp {
-            // No pithos for temp
-            let file_format = FileFormat::from_bools(false, self.encryption, false);
+            let file_format = storage.file_format(true);
             return Ok(ObjectLocation {
                 id: DieselUlid::generate(),
                 bucket: self.temp.clone(),
@@ -354,8 +401,7 @@ impl StorageBackend for FSBackend {
 
         let (bucket, key) = self.schema.into_names(names);
 
-        let file_format =
-            FileFormat::from_bools(self.use_pithos, self.encryption, self.compression);
+        let file_format = storage.file_format(false);
 
         Ok(ObjectLocation {
             id: DieselUlid::generate(),
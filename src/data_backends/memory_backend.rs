@@ -0,0 +1,504 @@
+use anyhow::{anyhow, bail, Result};
+use async_channel::{Receiver, Sender};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use digest::Digest;
+use futures_util::StreamExt;
+use md5::Md5;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::pin;
+
+use crate::caching::policies::resolve_storage;
+use crate::config::StoragePolicy;
+use crate::helpers::random_string;
+use crate::structs::{Object, ObjectLocation, PartETag};
+
+use super::{
+    location_handler::CompiledVariant,
+    storage_backend::{parse_range, StorageBackend},
+};
+
+/// Size of the chunks reads are sent in
+const READ_CHUNK: usize = 64 * 1024;
+/// Naming scheme of new locations
+const SCHEME: &str = "s3://{{PROJECT_NAME}}/{{OBJECT_NAME}}";
+/// Bucket of temporary locations
+const TEMP_BUCKET: &str = "temp";
+
+/// Operations of a `MemoryBackend`, calls are counted and can be set to fail per operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendOperation {
+    Put,
+    Get,
+    Head,
+    InitMultipart,
+    UploadPart,
+    FinishMultipart,
+    AbortMultipart,
+    CreateBucket,
+    Copy,
+    Delete,
+    InitializeLocation,
+}
+
+/// Data backend keeping all objects in memory, e.g. for tests or dataproxies without
+/// persistent storage
+///
+/// Clones share the stored objects, the call counters and the failure hooks. Locations
+/// follow the scheme `s3://{{PROJECT_NAME}}/{{OBJECT_NAME}}` and are stored unencrypted
+/// and uncompressed.
+#[derive(Debug, Clone)]
+pub struct MemoryBackend {
+    schema: CompiledVariant,
+    storage: StoragePolicy,
+    /// Bandwidth of every read in bytes per second, unlimited if not set
+    read_rate: Option<u64>,
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    objects: DashMap<String, Bytes>,
+    uploads: DashMap<String, BTreeMap<i32, Bytes>>,
+    calls: DashMap<BackendOperation, usize>,
+    /// Indices of the calls that fail by operation
+    failures: DashMap<BackendOperation, Vec<usize>>,
+    reads: AtomicUsize,
+    max_reads: AtomicUsize,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend {
+            schema: CompiledVariant::new(SCHEME).expect("valid memory backend scheme"),
+            storage: StoragePolicy::default(),
+            read_rate: None,
+            state: Arc::new(State::default()),
+        }
+    }
+
+    /// Limits every read to `bytes_per_sec`, e.g. to keep concurrent reads in flight
+    pub fn with_read_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.read_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Stores data at a location without counting a call
+    pub fn insert(&self, location: &ObjectLocation, data: impl Into<Bytes>) {
+        self.state.objects.insert(path(location), data.into());
+    }
+
+    /// Stored data of a location
+    pub fn data(&self, location: &ObjectLocation) -> Option<Bytes> {
+        self.state
+            .objects
+            .get(&path(location))
+            .map(|data| data.value().clone())
+    }
+
+    /// Number of stored objects, uploaded parts are not included
+    pub fn object_count(&self) -> usize {
+        self.state.objects.len()
+    }
+
+    /// Fails the call of the operation with the given index (counted from 0, including
+    /// failed calls). Reads fail after sending their first chunk.
+    pub fn fail_call(&self, operation: BackendOperation, index: usize) {
+        self.state
+            .failures
+            .entry(operation)
+            .or_default()
+            .push(index);
+    }
+
+    /// Fails the next call of the operation
+    pub fn fail_next(&self, operation: BackendOperation) {
+        self.fail_call(operation, self.calls(operation));
+    }
+
+    /// Number of calls of the operation, including failed calls
+    pub fn calls(&self, operation: BackendOperation) -> usize {
+        self.state
+            .calls
+            .get(&operation)
+            .map(|calls| *calls)
+            .unwrap_or_default()
+    }
+
+    /// Number of reads in flight
+    pub fn concurrent_reads(&self) -> usize {
+        self.state.reads.load(Ordering::SeqCst)
+    }
+
+    /// Largest number of reads that were in flight at the same time
+    pub fn max_concurrent_reads(&self) -> usize {
+        self.state.max_reads.load(Ordering::SeqCst)
+    }
+
+    /// Counts the call, true if it should fail
+    fn call(&self, operation: BackendOperation) -> bool {
+        let mut calls = self.state.calls.entry(operation).or_default();
+        let index = *calls;
+        *calls += 1;
+        drop(calls);
+        self.state
+            .failures
+            .get(&operation)
+            .is_some_and(|failures| failures.contains(&index))
+    }
+
+    fn check(&self, operation: BackendOperation) -> Result<()> {
+        if self.call(operation) {
+            bail!("Simulated backend failure: {operation:?}");
+        }
+        Ok(())
+    }
+
+    async fn send_range(
+        &self,
+        location: &ObjectLocation,
+        range: Option<String>,
+        sender: Sender<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>,
+        fail: bool,
+    ) -> Result<()> {
+        let data = self
+            .data(location)
+            .ok_or_else(|| anyhow!("Object not found: {}", path(location)))?;
+        let (start, end) = match &range {
+            Some(range) => parse_range(range, data.len() as u64)?,
+            None => (0, data.len() as u64),
+        };
+        let section = data.slice(start as usize..end as usize);
+        for (idx, chunk) in section.chunks(READ_CHUNK).enumerate() {
+            if idx == 1 && fail {
+                bail!("Simulated backend failure: {:?}", BackendOperation::Get);
+            }
+            if let Some(rate) = self.read_rate {
+                let delay = Duration::from_secs_f64(chunk.len() as f64 / rate as f64);
+                tokio::time::sleep(delay).await;
+            }
+            sender
+                .send(Ok(section.slice_ref(chunk)))
+                .await
+                .map_err(|e| anyhow!(e.to_string()))?;
+        }
+        if fail {
+            bail!("Simulated backend failure: {:?}", BackendOperation::Get);
+        }
+        Ok(())
+    }
+}
+
+fn path(location: &ObjectLocation) -> String {
+    format!("{}/{}", location.bucket, location.key)
+}
+
+async fn collect(recv: Receiver<Result<Bytes>>) -> Result<Bytes> {
+    let mut buf = BytesMut::new();
+    pin!(recv);
+    while let Some(data) = recv.next().await {
+        buf.extend_from_slice(&data?);
+    }
+    Ok(buf.freeze())
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    #[tracing::instrument(level = "trace", skip(self, recv, location, _content_len))]
+    async fn put_object(
+        &self,
+        recv: Receiver<Result<Bytes>>,
+        location: ObjectLocation,
+        _content_len: i64,
+    ) -> Result<()> {
+        self.check(BackendOperation::Put)?;
+        let data = collect(recv).await?;
+        self.insert(&location, data);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, range, sender))]
+    async fn get_object(
+        &self,
+        location: ObjectLocation,
+        range: Option<String>,
+        sender: Sender<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>,
+    ) -> Result<()> {
+        let fail = self.call(BackendOperation::Get);
+        let reads = self.state.reads.fetch_add(1, Ordering::SeqCst) + 1;
+        self.state.max_reads.fetch_max(reads, Ordering::SeqCst);
+        let result = self.send_range(&location, range, sender, fail).await;
+        self.state.reads.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn head_object(&self, location: ObjectLocation) -> Result<i64> {
+        self.check(BackendOperation::Head)?;
+        self.data(&location)
+            .map(|data| data.len() as i64)
+            .ok_or_else(|| anyhow!("Object not found: {}", path(&location)))
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, _location))]
+    async fn init_multipart_upload(&self, _location: ObjectLocation) -> Result<String> {
+        self.check(BackendOperation::InitMultipart)?;
+        let upload_id = random_string(15);
+        self.state
+            .uploads
+            .insert(upload_id.clone(), BTreeMap::new());
+        Ok(upload_id)
+    }
+
+    #[tracing::instrument(
+        level = "trace",
+        skip(self, recv, _location, upload_id, _content_len, part_number)
+    )]
+    async fn upload_multi_object(
+        &self,
+        recv: Receiver<Result<Bytes>>,
+        _location: ObjectLocation,
+        upload_id: String,
+        _content_len: i64,
+        part_number: i32,
+    ) -> Result<PartETag> {
+        self.check(BackendOperation::UploadPart)?;
+        let data = collect(recv).await?;
+        let etag = format!("{:x}", Md5::digest(&data));
+        self.state
+            .uploads
+            .get_mut(&upload_id)
+            .ok_or_else(|| anyhow!("Unknown upload: {upload_id}"))?
+            .insert(part_number, data);
+        Ok(PartETag { part_number, etag })
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, parts, upload_id))]
+    async fn finish_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        parts: Vec<PartETag>,
+        upload_id: String,
+    ) -> Result<()> {
+        self.check(BackendOperation::FinishMultipart)?;
+        let (_, uploaded) = self
+            .state
+            .uploads
+            .remove(&upload_id)
+            .ok_or_else(|| anyhow!("Unknown upload: {upload_id}"))?;
+        let mut data = BytesMut::new();
+        for part in parts {
+            let part = uploaded
+                .get(&part.part_number)
+                .ok_or_else(|| anyhow!("Missing part: {}", part.part_number))?;
+            data.extend_from_slice(part);
+        }
+        self.insert(&location, data.freeze());
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, _location, upload_id))]
+    async fn abort_multipart_upload(
+        &self,
+        _location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()> {
+        self.check(BackendOperation::AbortMultipart)?;
+        self.state.uploads.remove(&upload_id);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, _bucket))]
+    async fn create_bucket(&self, _bucket: String) -> Result<()> {
+        self.check(BackendOperation::CreateBucket)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, source, target))]
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        self.check(BackendOperation::Copy)?;
+        let data = self
+            .data(source)
+            .ok_or_else(|| anyhow!("Object not found: {}", path(source)))?;
+        self.insert(target, data);
+        Ok(())
+    }
+
+    /// Deletes the data of a location, like S3 missing objects are no error
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn delete_object(&self, location: ObjectLocation) -> Result<()> {
+        self.check(BackendOperation::Delete)?;
+        self.state.objects.remove(&path(&location));
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location(
+        &self,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        self.check(BackendOperation::InitializeLocation)?;
+        let storage = resolve_storage(storage, obj.data_class, &self.storage);
+        let (bucket, key) = if temp {
+            (
+                TEMP_BUCKET.to_string(),
+                format!(
+                    "{}{}",
+                    obj.id.to_string().to_ascii_lowercase(),
+                    random_string(3)
+                ),
+            )
+        } else {
+            self.schema.into_names(names)
+        };
+        Ok(ObjectLocation {
+            id: DieselUlid::generate(),
+            bucket,
+            key,
+            file_format: storage.file_format(temp),
+            raw_content_len: expected_size.unwrap_or_default(),
+            is_temporary: temp,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(key: &str) -> ObjectLocation {
+        ObjectLocation {
+            bucket: "bucket".to_string(),
+            key: key.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn read(backend: &MemoryBackend, key: &str, range: Option<&str>) -> Result<Bytes> {
+        let (sender, receiver) = async_channel::unbounded();
+        backend
+            .get_object(location(key), range.map(str::to_string), sender)
+            .await?;
+        let mut data = BytesMut::new();
+        while let Ok(chunk) = receiver.recv().await {
+            data.extend_from_slice(&chunk.map_err(|e| anyhow!(e.to_string()))?);
+        }
+        Ok(data.freeze())
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let backend = MemoryBackend::new();
+        let (sender, receiver) = async_channel::bounded(2);
+        sender
+            .send(Ok(Bytes::from_static(b"hello ")))
+            .await
+            .unwrap();
+        sender.send(Ok(Bytes::from_static(b"world"))).await.unwrap();
+        drop(sender);
+        backend
+            .put_object(receiver, location("file.txt"), 11)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            read(&backend, "file.txt", None).await.unwrap(),
+            "hello world"
+        );
+        assert_eq!(
+            read(&backend, "file.txt", Some("bytes=6-")).await.unwrap(),
+            "world"
+        );
+        assert_eq!(backend.head_object(location("file.txt")).await.unwrap(), 11);
+
+        backend
+            .copy_object(&location("file.txt"), &location("copy.txt"))
+            .await
+            .unwrap();
+        backend.delete_object(location("file.txt")).await.unwrap();
+        assert!(backend.data(&location("file.txt")).is_none());
+        assert_eq!(backend.data(&location("copy.txt")).unwrap(), "hello world");
+        // Repeated deletions succeed
+        backend.delete_object(location("file.txt")).await.unwrap();
+        assert_eq!(backend.calls(BackendOperation::Delete), 2);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let backend = MemoryBackend::new();
+        let upload_id = backend
+            .init_multipart_upload(location("parts.bin"))
+            .await
+            .unwrap();
+        let mut parts = Vec::new();
+        for (part_number, data) in [(2, "second"), (1, "first ")] {
+            let (sender, receiver) = async_channel::bounded(1);
+            sender.send(Ok(Bytes::from(data))).await.unwrap();
+            drop(sender);
+            parts.push(
+                backend
+                    .upload_multi_object(
+                        receiver,
+                        location("parts.bin"),
+                        upload_id.clone(),
+                        data.len() as i64,
+                        part_number,
+                    )
+                    .await
+                    .unwrap(),
+            );
+        }
+        parts.sort_by_key(|part| part.part_number);
+        assert_eq!(parts[0].etag, format!("{:x}", Md5::digest(b"first ")));
+
+        backend
+            .finish_multipart_upload(location("parts.bin"), parts, upload_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.data(&location("parts.bin")).unwrap(),
+            "first second"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failure_hooks() {
+        let backend = MemoryBackend::new();
+        backend.insert(&location("file.bin"), vec![7u8; 3 * READ_CHUNK]);
+
+        backend.fail_next(BackendOperation::Delete);
+        assert!(backend.delete_object(location("file.bin")).await.is_err());
+        assert!(backend.data(&location("file.bin")).is_some());
+
+        // The second read fails after its first chunk
+        backend.fail_call(BackendOperation::Get, 1);
+        assert_eq!(
+            read(&backend, "file.bin", None).await.unwrap().len(),
+            3 * READ_CHUNK
+        );
+        let (sender, receiver) = async_channel::unbounded();
+        assert!(backend
+            .get_object(location("file.bin"), None, sender)
+            .await
+            .is_err());
+        assert_eq!(receiver.len(), 1);
+        assert_eq!(backend.calls(BackendOperation::Get), 2);
+        assert_eq!(backend.concurrent_reads(), 0);
+        assert_eq!(backend.max_concurrent_reads(), 1);
+    }
+}
@@ -0,0 +1,265 @@
+use super::storage_backend::StorageBackend;
+use crate::config::StoragePolicy;
+use crate::metrics::BackendMetrics;
+use crate::structs::{Object, ObjectLocation, PartETag, RestoreStatus};
+use anyhow::Result;
+use async_channel::{Receiver, Sender};
+use async_trait::async_trait;
+use diesel_ulid::DieselUlid;
+use std::time::Instant;
+
+/// Records the duration and failures of all operations of a backend
+///
+/// Labels are limited to the configured backend name and the operation, objects and
+/// buckets are never used as labels.
+#[derive(Debug)]
+pub struct MeteredBackend {
+    inner: Box<dyn StorageBackend>,
+    name: String,
+    metrics: BackendMetrics,
+}
+
+impl MeteredBackend {
+    pub fn new(
+        inner: Box<dyn StorageBackend>,
+        name: impl Into<String>,
+        metrics: BackendMetrics,
+    ) -> Self {
+        MeteredBackend {
+            inner,
+            name: name.into(),
+            metrics,
+        }
+    }
+
+    fn record<T>(&self, operation: &str, started: Instant, result: Result<T>) -> Result<T> {
+        self.metrics
+            .duration
+            .with_label_values(&[&self.name, operation])
+            .observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.metrics
+                .errors
+                .with_label_values(&[&self.name, operation])
+                .inc();
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MeteredBackend {
+    #[tracing::instrument(level = "trace", skip(self, recv, location, content_len))]
+    async fn put_object(
+        &self,
+        recv: Receiver<Result<bytes::Bytes>>,
+        location: ObjectLocation,
+        content_len: i64,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.put_object(recv, location, content_len).await;
+        self.record("put_object", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, range, sender))]
+    async fn get_object(
+        &self,
+        location: ObjectLocation,
+        range: Option<String>,
+        sender: Sender<Result<bytes::Bytes, Box<dyn std::error::Error + Send + Sync>>>,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.get_object(location, range, sender).await;
+        self.record("get_object", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn head_object(&self, location: ObjectLocation) -> Result<i64> {
+        let started = Instant::now();
+        let result = self.inner.head_object(location).await;
+        self.record("head_object", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn init_multipart_upload(&self, location: ObjectLocation) -> Result<String> {
+        let started = Instant::now();
+        let result = self.inner.init_multipart_upload(location).await;
+        self.record("init_multipart_upload", started, result)
+    }
+
+    #[tracing::instrument(
+        level = "trace",
+        skip(self, recv, location, upload_id, content_len, part_number)
+    )]
+    async fn upload_multi_object(
+        &self,
+        recv: Receiver<Result<bytes::Bytes>>,
+        location: ObjectLocation,
+        upload_id: String,
+        content_len: i64,
+        part_number: i32,
+    ) -> Result<PartETag> {
+        let started = Instant::now();
+        let result = self
+            .inner
+            .upload_multi_object(recv, location, upload_id, content_len, part_number)
+            .await;
+        self.record("upload_multi_object", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, parts, upload_id))]
+    async fn finish_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        parts: Vec<PartETag>,
+        upload_id: String,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let result = self
+            .inner
+            .finish_multipart_upload(location, parts, upload_id)
+            .await;
+        self.record("finish_multipart_upload", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, upload_id))]
+    async fn abort_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.abort_multipart_upload(location, upload_id).await;
+        self.record("abort_multipart_upload", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, bucket))]
+    async fn create_bucket(&self, bucket: String) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.create_bucket(bucket).await;
+        self.record("create_bucket", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn create_location_bucket(&self, location: &ObjectLocation) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.create_location_bucket(location).await;
+        self.record("create_bucket", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, source, target))]
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.copy_object(source, target).await;
+        self.record("copy_object", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn delete_object(&self, location: ObjectLocation) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.delete_object(location).await;
+        self.record("delete_object", started, result)
+    }
+
+    fn supports_storage_class(&self, location: &ObjectLocation) -> bool {
+        self.inner.supports_storage_class(location)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_object(&self, location: &ObjectLocation, days: i32) -> Result<()> {
+        let started = Instant::now();
+        let result = self.inner.restore_object(location, days).await;
+        self.record("restore_object", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_status(&self, location: &ObjectLocation) -> Result<Option<RestoreStatus>> {
+        let started = Instant::now();
+        let result = self.inner.restore_status(location).await;
+        self.record("restore_status", started, result)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location(
+        &self,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        self.inner
+            .initialize_location(obj, expected_size, names, storage, temp)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location_on(
+        &self,
+        backend: Option<&str>,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        self.inner
+            .initialize_location_on(backend, obj, expected_size, names, storage, temp)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Backend;
+    use crate::data_backends::filesystem_backend::FSBackend;
+    use prometheus::Registry;
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let root = std::env::temp_dir().join(format!("metered-{}", DieselUlid::generate()));
+        let config = Backend::FileSystem {
+            root_path: root.to_string_lossy().to_string(),
+            encryption: false,
+            compression: false,
+            dropbox_folder: None,
+            backend_scheme: "s3://{{PROJECT_NAME}}/{{OBJECT_NAME}}".to_string(),
+            tmp: None,
+            data_classes: Default::default(),
+            compression_filter: None,
+        };
+        let registry = Registry::new();
+        let metrics = BackendMetrics::new(&registry).unwrap();
+        let backend = MeteredBackend::new(
+            Box::new(FSBackend::new(String::new(), &config).await.unwrap()),
+            "default",
+            metrics.clone(),
+        );
+
+        backend.create_bucket("bucket".to_string()).await.unwrap();
+        let missing = ObjectLocation {
+            bucket: "bucket".to_string(),
+            key: "missing".to_string(),
+            ..Default::default()
+        };
+        assert!(backend.head_object(missing.clone()).await.is_err());
+        assert!(backend.head_object(missing).await.is_err());
+
+        let count = |operation| {
+            metrics
+                .duration
+                .with_label_values(&["default", operation])
+                .get_sample_count()
+        };
+        let errors = |operation| {
+            metrics
+                .errors
+                .with_label_values(&["default", operation])
+                .get()
+        };
+        assert_eq!((count("create_bucket"), errors("create_bucket")), (1, 0));
+        assert_eq!((count("head_object"), errors("head_object")), (2, 2));
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -1,4 +1,9 @@
+pub mod disk_cache;
 pub mod filesystem_backend;
 pub mod location_handler;
+pub mod memory_backend;
+pub mod metered;
+pub mod parallel_get;
+pub mod registry;
 pub mod s3_backend;
 pub mod storage_backend;
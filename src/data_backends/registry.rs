@@ -0,0 +1,207 @@
+use super::storage_backend::StorageBackend;
+use crate::caching::policies::resolve_backend;
+use crate::config::StoragePolicy;
+use crate::structs::{Object, ObjectLocation, PartETag, RestoreStatus};
+use anyhow::{anyhow, Result};
+use async_channel::{Receiver, Sender};
+use async_trait::async_trait;
+use diesel_ulid::DieselUlid;
+use std::collections::HashMap;
+use tracing::{error, trace};
+
+/// Dispatches to the main or a named backend
+///
+/// Existing locations are served by the backend recorded in the location, new locations
+/// are created on the backend resolved from the storage policies (project data class >
+/// project > proxy data class). Locations that reference a backend which is not
+/// configured (anymore) fail instead of being silently read from the main backend.
+#[derive(Debug)]
+pub struct BackendRegistry {
+    default: Box<dyn StorageBackend>,
+    named: HashMap<String, Box<dyn StorageBackend>>,
+}
+
+impl BackendRegistry {
+    pub fn new(
+        default: Box<dyn StorageBackend>,
+        named: HashMap<String, Box<dyn StorageBackend>>,
+    ) -> Self {
+        BackendRegistry { default, named }
+    }
+
+    fn get(&self, name: Option<&str>) -> Result<&dyn StorageBackend> {
+        match name {
+            None => Ok(self.default.as_ref()),
+            Some(name) => self
+                .named
+                .get(name)
+                .map(|backend| backend.as_ref())
+                .ok_or_else(|| {
+                    error!(backend = name, "Unknown storage backend");
+                    anyhow!("Unknown storage backend: {name}")
+                }),
+        }
+    }
+
+    fn for_location(&self, location: &ObjectLocation) -> Result<&dyn StorageBackend> {
+        self.get(location.backend.as_deref())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for BackendRegistry {
+    #[tracing::instrument(level = "trace", skip(self, recv, location, content_len))]
+    async fn put_object(
+        &self,
+        recv: Receiver<Result<bytes::Bytes>>,
+        location: ObjectLocation,
+        content_len: i64,
+    ) -> Result<()> {
+        self.for_location(&location)?
+            .put_object(recv, location, content_len)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, range, sender))]
+    async fn get_object(
+        &self,
+        location: ObjectLocation,
+        range: Option<String>,
+        sender: Sender<Result<bytes::Bytes, Box<dyn std::error::Error + Send + Sync>>>,
+    ) -> Result<()> {
+        self.for_location(&location)?
+            .get_object(location, range, sender)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn head_object(&self, location: ObjectLocation) -> Result<i64> {
+        self.for_location(&location)?.head_object(location).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn init_multipart_upload(&self, location: ObjectLocation) -> Result<String> {
+        self.for_location(&location)?
+            .init_multipart_upload(location)
+            .await
+    }
+
+    #[tracing::instrument(
+        level = "trace",
+        skip(self, recv, location, upload_id, content_len, part_number)
+    )]
+    async fn upload_multi_object(
+        &self,
+        recv: Receiver<Result<bytes::Bytes>>,
+        location: ObjectLocation,
+        upload_id: String,
+        content_len: i64,
+        part_number: i32,
+    ) -> Result<PartETag> {
+        self.for_location(&location)?
+            .upload_multi_object(recv, location, upload_id, content_len, part_number)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, parts, upload_id))]
+    async fn finish_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        parts: Vec<PartETag>,
+        upload_id: String,
+    ) -> Result<()> {
+        self.for_location(&location)?
+            .finish_multipart_upload(location, parts, upload_id)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location, upload_id))]
+    async fn abort_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()> {
+        self.for_location(&location)?
+            .abort_multipart_upload(location, upload_id)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, bucket))]
+    async fn create_bucket(&self, bucket: String) -> Result<()> {
+        self.default.create_bucket(bucket).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn create_location_bucket(&self, location: &ObjectLocation) -> Result<()> {
+        self.for_location(location)?
+            .create_location_bucket(location)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, source, target))]
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        // The source is a location of a co-located proxy, its backend name is not
+        // meaningful here, the copy is done by the backend of the target
+        self.for_location(target)?.copy_object(source, target).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn delete_object(&self, location: ObjectLocation) -> Result<()> {
+        self.for_location(&location)?.delete_object(location).await
+    }
+
+    fn supports_storage_class(&self, location: &ObjectLocation) -> bool {
+        self.for_location(location)
+            .map(|backend| backend.supports_storage_class(location))
+            .unwrap_or(false)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_object(&self, location: &ObjectLocation, days: i32) -> Result<()> {
+        self.for_location(location)?
+            .restore_object(location, days)
+            .await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_status(&self, location: &ObjectLocation) -> Result<Option<RestoreStatus>> {
+        self.for_location(location)?.restore_status(location).await
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location(
+        &self,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        let name = resolve_backend(storage, obj.data_class);
+        trace!(?name, object = ?obj.id, "Resolved backend of new location");
+        let mut location = self
+            .get(name.as_deref())?
+            .initialize_location(obj, expected_size, names, storage, temp)
+            .await?;
+        location.backend = name;
+        Ok(location)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
+    async fn initialize_location_on(
+        &self,
+        backend: Option<&str>,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        let mut location = self
+            .get(backend)?
+            .initialize_location(obj, expected_size, names, storage, temp)
+            .await?;
+        location.backend = backend.map(|name| name.to_string());
+        Ok(location)
+    }
+}
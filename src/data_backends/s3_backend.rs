@@ -1,26 +1,42 @@
 use super::location_handler::CompiledVariant;
 use super::storage_backend::StorageBackend;
-use crate::config::Backend;
+use crate::auth::clock::{BACKEND_ORIGIN, CLOCK_SKEW};
+use crate::caching::policies::resolve_storage;
+use crate::config::{Backend, RequestPolicy, ShardingScheme, StoragePolicy};
+use crate::error::ProxyError;
 use crate::helpers::random_string;
-use crate::structs::FileFormat;
+use crate::metrics::BACKEND_RETRIES;
 use crate::structs::Object;
 use crate::structs::ObjectLocation;
 use crate::structs::PartETag;
-use crate::CONFIG;
+use crate::structs::RestoreStatus;
 use anyhow::anyhow;
 use anyhow::Result;
 use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::interceptors::BeforeDeserializationInterceptorContextRef;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::timeout::TimeoutConfig;
+use aws_sdk_s3::config::{ConfigBag, Credentials, Intercept, RuntimeComponents};
+use aws_sdk_s3::error::{BoxError, SdkError};
 use aws_sdk_s3::primitives::SdkBody;
 use aws_sdk_s3::{
     config::Region,
     primitives::ByteStream,
-    types::{CompletedMultipartUpload, CompletedPart},
+    types::{CompletedMultipartUpload, CompletedPart, RestoreRequest, StorageClass},
     Client,
 };
+use bytes::BytesMut;
+use dashmap::DashSet;
 use diesel_ulid::DieselUlid;
+use futures_util::StreamExt;
 use rand::Rng;
-use tracing::error;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, warn};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -29,24 +45,29 @@ pub struct S3Backend {
     endpoint_id: String,
     temp: String,
     schema: CompiledVariant,
-    use_pithos: bool,
-    encryption: bool,
-    compression: bool,
     dropbox: Option<String>,
+    sharding: ShardingScheme,
+    requests: RequestPolicy,
+    storage: StoragePolicy,
+    // Buckets that are known to exist, avoids a lookup per upload for sharded layouts
+    known_buckets: Arc<DashSet<String>>,
 }
 
 impl S3Backend {
-    #[tracing::instrument]
-    pub async fn new(endpoint_id: String) -> Result<Self> {
+    #[tracing::instrument(skip(config))]
+    pub async fn new(endpoint_id: String, config: &Backend) -> Result<Self> {
         let Backend::S3 {
             tmp,
             backend_scheme,
             host,
-            encryption,
-            compression,
+            access_key,
+            secret_key,
             dropbox_bucket,
+            sharding,
+            requests,
+            region,
             ..
-        } = &CONFIG.backend
+        } = config
         else {
             return Err(anyhow!("Invalid backend"));
         };
@@ -61,10 +82,29 @@ impl S3Backend {
         tracing::debug!("S3 Endpoint: {}", s3_endpoint);
 
         #[allow(deprecated)]
-        let config = aws_config::load_from_env().await;
-        let s3_config = aws_sdk_s3::config::Builder::from(&config)
-            .region(Region::new("RegionOne"))
+        let sdk_config = aws_config::load_from_env().await;
+        let mut s3_config = aws_sdk_s3::config::Builder::from(&sdk_config);
+        if let (Some(access_key), Some(secret_key)) = (access_key, secret_key) {
+            s3_config = s3_config.credentials_provider(Credentials::new(
+                access_key,
+                secret_key,
+                None,
+                None,
+                "dataproxy",
+            ));
+        }
+        let s3_config = s3_config
+            .region(Region::new(region.clone()))
             .endpoint_url(&s3_endpoint)
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .connect_timeout(Duration::from_secs(requests.connect_timeout_secs))
+                    .read_timeout(Duration::from_secs(requests.read_timeout_secs))
+                    .build(),
+            )
+            // Retries are done per operation, see S3Backend::retry
+            .retry_config(RetryConfig::disabled())
+            .interceptor(DateHeaderInterceptor)
             .build();
 
         let s3_client = Client::from_conf(s3_config);
@@ -74,15 +114,38 @@ impl S3Backend {
             endpoint_id,
             temp,
             schema: compiled_schema,
-            use_pithos: *encryption || *compression,
-            encryption: *encryption,
-            compression: *compression,
             dropbox: dropbox_bucket.clone(),
+            sharding: *sharding,
+            requests: *requests,
+            storage: config.storage_policy(),
+            known_buckets: Arc::new(DashSet::new()),
         };
         Ok(handler)
     }
 }
 
+/// Measures the clock skew against the backend from the `Date` header of its responses
+#[derive(Debug)]
+struct DateHeaderInterceptor;
+
+impl Intercept for DateHeaderInterceptor {
+    fn name(&self) -> &'static str {
+        "DateHeaderInterceptor"
+    }
+
+    fn read_before_deserialization(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if let Some(date) = context.response().headers().get("date") {
+            CLOCK_SKEW.record_date_header(date, BACKEND_ORIGIN);
+        }
+        Ok(())
+    }
+}
+
 // Data backend for an S3 based storage.
 #[async_trait]
 impl StorageBackend for S3Backend {
@@ -99,23 +162,29 @@ impl StorageBackend for S3Backend {
         self.check_and_create_bucket(location.bucket.clone())
             .await?;
 
-        let hyper_body = hyper::Body::wrap_stream(recv);
-        let bytestream = ByteStream::from(SdkBody::from_body_0_4(hyper_body));
-
+        // The data is streamed and can only be resent if no chunk was consumed yet
+        let consumed = Arc::new(AtomicBool::new(false));
         match self
-            .s3_client
-            .put_object()
-            .set_bucket(Some(location.bucket))
-            .set_key(Some(location.key))
-            .set_content_length(Some(content_len))
-            .body(bytestream)
-            .send()
+            .retry(
+                "put_object",
+                || !consumed.load(Ordering::Relaxed),
+                || {
+                    self.s3_client
+                        .put_object()
+                        .set_bucket(Some(location.bucket.clone()))
+                        .set_key(Some(location.key.clone()))
+                        .set_content_length(Some(content_len))
+                        .set_storage_class(storage_class(&location))
+                        .body(tracked_body(recv.clone(), consumed.clone()))
+                        .send()
+                },
+            )
             .await
         {
             Ok(_) => {}
             Err(err) => {
                 error!(error = ?err, "Error putting object");
-                return Err(err.into());
+                return Err(backend_error("put_object", &location, err));
             }
         }
 
@@ -132,18 +201,26 @@ impl StorageBackend for S3Backend {
         range: Option<String>,
         sender: Sender<Result<bytes::Bytes, Box<dyn std::error::Error + Send + Sync>>>,
     ) -> Result<()> {
-        let object = self
-            .s3_client
-            .get_object()
-            .set_bucket(Some(location.bucket))
-            .set_key(Some(location.key))
-            .set_range(range);
-
-        let mut object_request = match object.send().await {
+        // Only the initial request is retried, not the already started body stream
+        let mut object_request = match self
+            .retry(
+                "get_object",
+                || true,
+                || {
+                    self.s3_client
+                        .get_object()
+                        .set_bucket(Some(location.bucket.clone()))
+                        .set_key(Some(location.key.clone()))
+                        .set_range(range.clone())
+                        .send()
+                },
+            )
+            .await
+        {
             Ok(value) => value,
             Err(err) => {
                 error!(error = ?err, "Error getting object");
-                return Err(err.into());
+                return Err(backend_error("get_object", &location, err));
             }
         };
 
@@ -166,15 +243,21 @@ impl StorageBackend for S3Backend {
     #[tracing::instrument(level = "trace", skip(self, location))]
     async fn head_object(&self, location: ObjectLocation) -> Result<i64> {
         let object = self
-            .s3_client
-            .head_object()
-            .set_bucket(Some(location.bucket))
-            .set_key(Some(location.key))
-            .send()
+            .retry(
+                "head_object",
+                || true,
+                || {
+                    self.s3_client
+                        .head_object()
+                        .set_bucket(Some(location.bucket.clone()))
+                        .set_key(Some(location.key.clone()))
+                        .send()
+                },
+            )
             .await
             .map_err(|e| {
                 tracing::error!(error = ?e, msg = e.to_string());
-                e
+                backend_error("head_object", &location, e)
             })?;
         Ok(object.content_length().unwrap_or_default())
     }
@@ -188,6 +271,7 @@ impl StorageBackend for S3Backend {
         let multipart = self
             .s3_client
             .create_multipart_upload()
+            .set_storage_class(storage_class(&location))
             .set_bucket(Some(location.bucket))
             .set_key(Some(location.key))
             .send()
@@ -209,23 +293,29 @@ impl StorageBackend for S3Backend {
         content_len: i64,
         part_number: i32,
     ) -> Result<PartETag> {
-        let hyper_body = hyper::Body::wrap_stream(recv);
-        let bytestream = ByteStream::from(SdkBody::from_body_0_4(hyper_body));
+        // Parts are buffered so that they can be resent on transient errors
+        let part = buffer_part(recv, content_len).await?;
 
         let upload = self
-            .s3_client
-            .upload_part()
-            .set_bucket(Some(location.bucket))
-            .set_key(Some(location.key))
-            .set_part_number(Some(part_number))
-            .set_content_length(Some(content_len))
-            .set_upload_id(Some(upload_id))
-            .body(bytestream)
-            .send()
+            .retry(
+                "upload_part",
+                || true,
+                || {
+                    self.s3_client
+                        .upload_part()
+                        .set_bucket(Some(location.bucket.clone()))
+                        .set_key(Some(location.key.clone()))
+                        .set_part_number(Some(part_number))
+                        .set_content_length(Some(content_len))
+                        .set_upload_id(Some(upload_id.clone()))
+                        .body(ByteStream::from(part.clone()))
+                        .send()
+                },
+            )
             .await
             .map_err(|e| {
                 tracing::error!(error = ?e, msg = e.to_string());
-                e
+                backend_error("upload_part", &location, e)
             })?;
 
         return Ok(PartETag {
@@ -278,30 +368,184 @@ impl StorageBackend for S3Backend {
         }
     }
 
+    #[tracing::instrument(level = "trace", skip(self, location, upload_id))]
+    async fn abort_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()> {
+        self.s3_client
+            .abort_multipart_upload()
+            .bucket(location.bucket)
+            .key(location.key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, bucket))]
     async fn create_bucket(&self, bucket: String) -> Result<()> {
         self.check_and_create_bucket(bucket).await
     }
 
+    #[tracing::instrument(level = "trace", skip(self, source, target))]
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        self.check_and_create_bucket(target.bucket.clone()).await?;
+        let copy_source = copy_source(source);
+        let size = if source.disk_content_len > 0 {
+            source.disk_content_len
+        } else {
+            self.head_object(source.clone()).await?
+        };
+
+        if size <= MAX_COPY_SIZE {
+            self.retry(
+                "copy_object",
+                || true,
+                || {
+                    self.s3_client
+                        .copy_object()
+                        .bucket(target.bucket.clone())
+                        .key(target.key.clone())
+                        .copy_source(copy_source.clone())
+                        .set_storage_class(storage_class(target))
+                        .send()
+                },
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+            return Ok(());
+        }
+
+        // Single copies are limited to 5 GiB, larger objects are copied in ranges
+        let upload_id = self.init_multipart_upload(target.clone()).await?;
+        let mut parts = Vec::new();
+        for (idx, start) in (0..size).step_by(COPY_PART_SIZE as usize).enumerate() {
+            let part_number = idx as i32 + 1;
+            let range = format!("bytes={}-{}", start, (start + COPY_PART_SIZE).min(size) - 1);
+            let result = self
+                .retry(
+                    "upload_part_copy",
+                    || true,
+                    || {
+                        self.s3_client
+                            .upload_part_copy()
+                            .bucket(target.bucket.clone())
+                            .key(target.key.clone())
+                            .upload_id(upload_id.clone())
+                            .part_number(part_number)
+                            .copy_source(copy_source.clone())
+                            .copy_source_range(range.clone())
+                            .send()
+                    },
+                )
+                .await;
+            let etag = match result {
+                Ok(output) => output
+                    .copy_part_result
+                    .and_then(|part| part.e_tag)
+                    .ok_or_else(|| anyhow!("Missing etag")),
+                Err(e) => Err(e.into()),
+            };
+            match etag {
+                Ok(etag) => parts.push(PartETag { part_number, etag }),
+                Err(e) => {
+                    tracing::error!(error = ?e, msg = e.to_string());
+                    if let Err(e) = self
+                        .abort_multipart_upload(target.clone(), upload_id.clone())
+                        .await
+                    {
+                        warn!(error = ?e, "Unable to abort multipart copy");
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.finish_multipart_upload(target.clone(), parts, upload_id)
+            .await
+    }
+
     #[tracing::instrument(level = "trace", skip(self, location))]
     /// Delete a object from the storage system
     /// # Arguments
     /// * `location` - The location of the object
     async fn delete_object(&self, location: ObjectLocation) -> Result<()> {
-        self.s3_client
-            .delete_object()
-            .bucket(location.bucket)
-            .key(location.key)
-            .send()
+        self.retry(
+            "delete_object",
+            || true,
+            || {
+                self.s3_client
+                    .delete_object()
+                    .bucket(location.bucket.clone())
+                    .key(location.key.clone())
+                    .send()
+            },
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        Ok(())
+    }
+
+    fn supports_storage_class(&self, _location: &ObjectLocation) -> bool {
+        true
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_object(&self, location: &ObjectLocation, days: i32) -> Result<()> {
+        self.retry(
+            "restore_object",
+            || true,
+            || {
+                self.s3_client
+                    .restore_object()
+                    .bucket(location.bucket.clone())
+                    .key(location.key.clone())
+                    .restore_request(RestoreRequest::builder().days(days).build())
+                    .send()
+            },
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            backend_error("restore_object", location, e)
+        })?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn restore_status(&self, location: &ObjectLocation) -> Result<Option<RestoreStatus>> {
+        let object = self
+            .retry(
+                "head_object",
+                || true,
+                || {
+                    self.s3_client
+                        .head_object()
+                        .set_bucket(Some(location.bucket.clone()))
+                        .set_key(Some(location.key.clone()))
+                        .send()
+                },
+            )
             .await
             .map_err(|e| {
                 tracing::error!(error = ?e, msg = e.to_string());
-                e
+                backend_error("head_object", location, e)
             })?;
-        Ok(())
+        Ok(object.restore().and_then(RestoreStatus::from_header))
     }
 
-    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, temp))]
+    #[tracing::instrument(level = "trace", skip(self, obj, expected_size, names, storage, temp))]
     /// Initialize a new location for a specific object
     /// This takes the object_info into account and creates a new location for the object
     async fn initialize_location(
@@ -309,11 +553,12 @@ impl StorageBackend for S3Backend {
         obj: &Object,
         expected_size: Option<i64>,
         names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
         temp: bool,
     ) -> Result<ObjectLocation> {
+        let storage = resolve_storage(storage, obj.data_class, &self.storage);
         if temp {
-            // No pithos for temp
-            let file_format = FileFormat::from_bools(false, self.encryption, false);
+            let file_format = storage.file_format(true);
             return Ok(ObjectLocation {
                 id: DieselUlid::generate(),
                 bucket: self.temp.clone(),
@@ -330,9 +575,9 @@ impl StorageBackend for S3Backend {
         }
 
         let (bucket, key) = self.schema.into_names(names);
+        let (bucket, key) = self.sharding.apply(bucket, key, &obj.id);
 
-        let file_format =
-            FileFormat::from_bools(self.use_pithos, self.encryption, self.compression);
+        let file_format = storage.file_format(false);
 
         Ok(ObjectLocation {
             id: DieselUlid::generate(),
@@ -340,6 +585,7 @@ impl StorageBackend for S3Backend {
             key,
             file_format,
             raw_content_len: expected_size.unwrap_or_default(),
+            sharding: self.sharding,
             ..Default::default()
         })
     }
@@ -348,6 +594,9 @@ impl StorageBackend for S3Backend {
 impl S3Backend {
     #[tracing::instrument(level = "trace", skip(self, bucket))]
     pub async fn check_and_create_bucket(&self, bucket: String) -> Result<()> {
+        if self.known_buckets.contains(&bucket) {
+            return Ok(());
+        }
         match self
             .s3_client
             .get_bucket_location()
@@ -355,19 +604,202 @@ impl S3Backend {
             .send()
             .await
         {
-            Ok(_) => Ok(()),
-            Err(e1) => match self.s3_client.create_bucket().bucket(bucket).send().await {
-                Ok(_) => Ok(()),
+            Ok(_) => {}
+            Err(e1) => match self
+                .s3_client
+                .create_bucket()
+                .bucket(bucket.clone())
+                .send()
+                .await
+            {
+                Ok(_) => {}
                 Err(err) => {
                     error!(?e1, ?err, "Error creating bucket");
-                    Err(err.into())
+                    return Err(err.into());
                 }
             },
         }
+        self.known_buckets.insert(bucket);
+        Ok(())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn get_random_bucket(&self) -> String {
         format!("{}-{:x}", self.endpoint_id, rand::thread_rng().gen::<u8>()).to_ascii_lowercase()
     }
+
+    /// Sends the request until it succeeds, fails permanently or the attempts are exhausted
+    ///
+    /// Transient errors are retried with exponential backoff and full jitter as long
+    /// as `can_retry` allows it (e.g. no streamed data was consumed yet).
+    async fn retry<T, E, F, Fut>(
+        &self,
+        operation: &'static str,
+        can_retry: impl Fn() -> bool,
+        mut request: F,
+    ) -> Result<T, SdkError<E, HttpResponse>>
+    where
+        E: std::error::Error,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SdkError<E, HttpResponse>>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match request().await {
+                Err(err)
+                    if attempt < self.requests.max_attempts
+                        && is_transient(&err)
+                        && can_retry() =>
+                {
+                    let backoff = self.backoff(attempt);
+                    warn!(
+                        operation,
+                        attempt,
+                        ?backoff,
+                        error = %err,
+                        "Transient backend error, retrying"
+                    );
+                    BACKEND_RETRIES.with_label_values(&[operation]).inc();
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let max = self
+            .requests
+            .initial_backoff_ms
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.requests.max_backoff_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=max))
+    }
+}
+
+/// Largest object that can be copied with a single CopyObject request
+const MAX_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+/// Upper bound of the memory reserved up front for a part, larger parts grow the
+/// buffer as their data arrives
+const MAX_PART_PREALLOCATION: usize = 16 * 1024 * 1024;
+/// Range size of multipart copies
+const COPY_PART_SIZE: i64 = 1024 * 1024 * 1024;
+
+/// Temporary locations are always stored in the default class, the requested
+/// class is applied when the data is finalized
+fn storage_class(location: &ObjectLocation) -> Option<StorageClass> {
+    location
+        .storage_class
+        .as_deref()
+        .filter(|_| !location.is_temporary)
+        .map(StorageClass::from)
+}
+
+/// `bucket/key` with the key percent-encoded as required by the x-amz-copy-source header
+fn copy_source(location: &ObjectLocation) -> String {
+    let mut source = format!("{}/", location.bucket);
+    for byte in location.key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                source.push(byte as char)
+            }
+            _ => source.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    source
+}
+
+/// Timeouts, connection errors, throttling and server errors
+fn is_transient<E>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        SdkError::ServiceError(e) => {
+            let status = e.raw().status().as_u16();
+            status == 429 || status >= 500
+        }
+        _ => false,
+    }
+}
+
+/// Transient errors that outlasted all retries are reported as unavailable backend
+fn backend_error<E>(
+    operation: &str,
+    location: &ObjectLocation,
+    err: SdkError<E, HttpResponse>,
+) -> anyhow::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    if is_transient(&err) {
+        ProxyError::BackendUnavailable(format!(
+            "{operation} ({}/{}): {err}",
+            location.bucket, location.key
+        ))
+        .into()
+    } else {
+        err.into()
+    }
+}
+
+/// Collects the data of a part, the announced length only sizes the initial buffer
+async fn buffer_part(
+    recv: Receiver<Result<bytes::Bytes>>,
+    content_len: i64,
+) -> Result<bytes::Bytes> {
+    let capacity = (content_len.max(0) as usize).min(MAX_PART_PREALLOCATION);
+    let mut buffer = BytesMut::with_capacity(capacity);
+    while let Ok(chunk) = recv.recv().await {
+        buffer.extend_from_slice(&chunk.map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?);
+    }
+    Ok(buffer.freeze())
+}
+
+/// Request body for a single attempt that records if a chunk was taken from the shared receiver
+fn tracked_body(recv: Receiver<Result<bytes::Bytes>>, consumed: Arc<AtomicBool>) -> ByteStream {
+    let stream = recv.inspect(move |_| consumed.store(true, Ordering::Relaxed));
+    ByteStream::from(SdkBody::from_body_0_4(hyper::Body::wrap_stream(stream)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_buffer_part_preallocation() {
+        // A bogus announced length does not reserve memory up front
+        let (sender, recv) = async_channel::bounded(1);
+        sender
+            .send(Ok(bytes::Bytes::from_static(b"part")))
+            .await
+            .unwrap();
+        drop(sender);
+        let part = buffer_part(recv, i64::MAX).await.unwrap();
+        assert_eq!(part.as_ref(), b"part");
+
+        // Parts larger than the preallocation are collected completely
+        let chunk = bytes::Bytes::from(vec![7u8; 1024 * 1024]);
+        let chunks = MAX_PART_PREALLOCATION / chunk.len() + 3;
+        let (sender, recv) = async_channel::unbounded();
+        for _ in 0..chunks {
+            sender.send(Ok(chunk.clone())).await.unwrap();
+        }
+        drop(sender);
+        let part = buffer_part(recv, (chunks * chunk.len()) as i64)
+            .await
+            .unwrap();
+        assert_eq!(part.len(), chunks * chunk.len());
+
+        let (sender, recv) = async_channel::unbounded();
+        sender
+            .send(Err(anyhow!("client disconnected")))
+            .await
+            .unwrap();
+        assert!(buffer_part(recv, 4).await.is_err());
+    }
 }
@@ -1,5 +1,6 @@
-use crate::structs::{Object, ObjectLocation, PartETag};
-use anyhow::Result;
+use crate::config::StoragePolicy;
+use crate::structs::{Object, ObjectLocation, PartETag, RestoreStatus};
+use anyhow::{anyhow, bail, Result};
 use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
 use diesel_ulid::DieselUlid;
@@ -81,24 +82,129 @@ pub trait StorageBackend: Debug + Send + Sync {
         upload_id: String,
     ) -> Result<()>;
 
+    /// Aborts a multipart upload and removes all already uploaded parts
+    /// # Arguments
+    ///
+    /// * `location` - The location of the object
+    /// * `upload_id` - The upload id of the multipart uploads
+    async fn abort_multipart_upload(
+        &self,
+        location: ObjectLocation,
+        upload_id: String,
+    ) -> Result<()>;
+
     /// Creates a bucket or the storage system equivalent
     /// # Arguments
     ///
     /// * `bucket` - Name of the bucket to create
     async fn create_bucket(&self, bucket: String) -> Result<()>;
 
+    /// Creates the bucket of a location on the backend that stores it
+    /// # Arguments
+    ///
+    /// * `location` - The location whose bucket to create
+    async fn create_location_bucket(&self, location: &ObjectLocation) -> Result<()> {
+        self.create_bucket(location.bucket.clone()).await
+    }
+
+    /// Copies the stored data of a location to another location without streaming it
+    /// through the proxy, both locations must be on the same storage system
+    /// # Arguments
+    ///
+    /// * `source` - The location to copy from
+    /// * `target` - The (initialized) location to copy to
+    async fn copy_object(&self, source: &ObjectLocation, target: &ObjectLocation) -> Result<()> {
+        let _ = (source, target);
+        anyhow::bail!("Server side copy is not supported by this backend")
+    }
+
     /// Delete a object from the storage system
     /// # Arguments
     /// * `location` - The location of the object
     async fn delete_object(&self, location: ObjectLocation) -> Result<()>;
 
+    /// Whether the storage class of a location is applied when its data is written
+    /// # Arguments
+    ///
+    /// * `location` - The location that should be stored in its storage class
+    fn supports_storage_class(&self, location: &ObjectLocation) -> bool {
+        let _ = location;
+        false
+    }
+
+    /// Requests a temporary readable copy of data in an archive storage class
+    /// # Arguments
+    ///
+    /// * `location` - The location of the archived object
+    /// * `days` - Number of days the restored copy is kept
+    async fn restore_object(&self, location: &ObjectLocation, days: i32) -> Result<()> {
+        let _ = (location, days);
+        bail!("Restoring archived objects is not supported by this backend")
+    }
+
+    /// Gets the restore status of an archived object, None if no restore was requested
+    /// # Arguments
+    ///
+    /// * `location` - The location of the archived object
+    async fn restore_status(&self, location: &ObjectLocation) -> Result<Option<RestoreStatus>> {
+        let _ = location;
+        bail!("Restoring archived objects is not supported by this backend")
+    }
+
     /// Initialize a new location for a specific object
     /// This takes the object_info into account and creates a new location for the object
+    /// # Arguments
+    ///
+    /// * `storage` - Storage policy of the project, see `Cache::get_storage_policy`
     async fn initialize_location(
         &self,
         obj: &Object,
         expected_size: Option<i64>,
         names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
         temp: bool,
     ) -> Result<ObjectLocation>;
+
+    /// Initialize a new location for a specific object on a named backend instead of
+    /// the backend resolved from the storage policies
+    /// # Arguments
+    ///
+    /// * `backend` - Name of the target backend, None for the main backend
+    async fn initialize_location_on(
+        &self,
+        backend: Option<&str>,
+        obj: &Object,
+        expected_size: Option<i64>,
+        names: [Option<(DieselUlid, String)>; 4],
+        storage: Option<&StoragePolicy>,
+        temp: bool,
+    ) -> Result<ObjectLocation> {
+        match backend {
+            None => {
+                self.initialize_location(obj, expected_size, names, storage, temp)
+                    .await
+            }
+            Some(name) => bail!("Unknown storage backend: {name}"),
+        }
+    }
+}
+
+/// Parses a single `bytes=` range into a start and exclusive end
+pub fn parse_range(range: &str, len: u64) -> Result<(u64, u64)> {
+    let spec = range
+        .trim()
+        .strip_prefix("bytes=")
+        .ok_or_else(|| anyhow!("Invalid range {range}"))?;
+    let (start, end) = spec
+        .split_once('-')
+        .ok_or_else(|| anyhow!("Invalid range {range}"))?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (len.saturating_sub(suffix.parse()?), len),
+        (start, "") => (start.parse()?, len),
+        (start, end) => (start.parse()?, (end.parse::<u64>()? + 1).min(len)),
+    };
+    if start > end {
+        bail!("Unsatisfiable range {range}")
+    }
+    Ok((start, end))
 }
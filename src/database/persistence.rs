@@ -29,6 +29,9 @@ pub enum Table {
     ObjectLocations,
     Permissions,
     Multiparts,
+    MultipartUploads,
+    Notifications,
+    Webhooks,
 }
 
 impl Display for Table {
@@ -41,6 +44,9 @@ impl Display for Table {
             Table::ObjectLocations => write!(f, "object_locations"),
             Table::Permissions => write!(f, "permissions"),
             Table::Multiparts => write!(f, "multiparts"),
+            Table::MultipartUploads => write!(f, "multipart_uploads"),
+            Table::Notifications => write!(f, "notifications"),
+            Table::Webhooks => write!(f, "webhooks"),
         }
     }
 }
@@ -1,7 +1,10 @@
 use diesel_ulid::DieselUlid;
 use postgres_types::Json;
 
-use crate::structs::{AccessKeyPermissions, Object, ObjectLocation, PubKey, UploadPart, User};
+use crate::structs::{
+    AccessKeyPermissions, MultipartUploadInfo, Object, ObjectLocation, PendingNotification,
+    PendingWebhook, PubKey, UploadPart, User,
+};
 
 use super::persistence::{GenericBytes, Table, WithGenericBytes};
 
@@ -168,3 +171,84 @@ impl TryInto<GenericBytes<DieselUlid, Self>> for UploadPart {
         })
     }
 }
+
+impl WithGenericBytes<String, Self> for MultipartUploadInfo {
+    #[tracing::instrument(level = "trace", skip())]
+    fn get_table() -> Table {
+        Table::MultipartUploads
+    }
+}
+
+impl TryFrom<GenericBytes<String, Self>> for MultipartUploadInfo {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    #[tracing::instrument(level = "trace", skip(value))]
+    fn try_from(value: GenericBytes<String, Self>) -> Result<Self, Self::Error> {
+        Ok(value.data.0)
+    }
+}
+
+impl TryInto<GenericBytes<String, Self>> for MultipartUploadInfo {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_into(self) -> Result<GenericBytes<String, Self>, Self::Error> {
+        Ok(GenericBytes {
+            id: self.upload_id.clone(),
+            data: Json(self),
+            table: Self::get_table(),
+        })
+    }
+}
+
+impl WithGenericBytes<DieselUlid, Self> for PendingNotification {
+    #[tracing::instrument(level = "trace", skip())]
+    fn get_table() -> Table {
+        Table::Notifications
+    }
+}
+
+impl TryFrom<GenericBytes<DieselUlid, Self>> for PendingNotification {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    #[tracing::instrument(level = "trace", skip(value))]
+    fn try_from(value: GenericBytes<DieselUlid, Self>) -> Result<Self, Self::Error> {
+        Ok(value.data.0)
+    }
+}
+
+impl TryInto<GenericBytes<DieselUlid, Self>> for PendingNotification {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_into(self) -> Result<GenericBytes<DieselUlid, Self>, Self::Error> {
+        Ok(GenericBytes {
+            id: self.id,
+            data: Json(self),
+            table: Self::get_table(),
+        })
+    }
+}
+
+impl WithGenericBytes<DieselUlid, Self> for PendingWebhook {
+    #[tracing::instrument(level = "trace", skip())]
+    fn get_table() -> Table {
+        Table::Webhooks
+    }
+}
+
+impl TryFrom<GenericBytes<DieselUlid, Self>> for PendingWebhook {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    #[tracing::instrument(level = "trace", skip(value))]
+    fn try_from(value: GenericBytes<DieselUlid, Self>) -> Result<Self, Self::Error> {
+        Ok(value.data.0)
+    }
+}
+
+impl TryInto<GenericBytes<DieselUlid, Self>> for PendingWebhook {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_into(self) -> Result<GenericBytes<DieselUlid, Self>, Self::Error> {
+        Ok(GenericBytes {
+            id: self.id,
+            data: Json(self),
+            table: Self::get_table(),
+        })
+    }
+}
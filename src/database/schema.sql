@@ -37,6 +37,21 @@ CREATE TABLE IF NOT EXISTS multiparts (
     data JSONB NOT NULL -- The actual data
 );
 
+CREATE TABLE IF NOT EXISTS multipart_uploads (
+    id TEXT NOT NULL PRIMARY KEY, -- The upload id
+    data JSONB NOT NULL -- The actual data
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+    id UUID NOT NULL PRIMARY KEY,
+    data JSONB NOT NULL -- The actual data
+);
+
+CREATE TABLE IF NOT EXISTS webhooks (
+    id UUID NOT NULL PRIMARY KEY,
+    data JSONB NOT NULL -- The actual data
+);
+
 CREATE TABLE IF NOT EXISTS permissions (
     id TEXT NOT NULL PRIMARY KEY, 
     data JSONB NOT NULL -- The actual data
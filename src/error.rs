@@ -0,0 +1,306 @@
+//! Typed errors of the data path
+//!
+//! Errors are usually passed around as `anyhow::Error`, a `ProxyError` anywhere in the
+//! error chain (or a `tonic::Status` of the server) decides how the failure is reported
+//! to S3 and gRPC clients. Everything else is reported as internal error.
+use http::StatusCode;
+use s3s::{S3Error, S3ErrorCode};
+use std::fmt::{Display, Formatter};
+use tracing::error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyError {
+    /// The bucket (project) does not exist
+    BucketNotFound(String),
+    /// A collection, dataset or object does not exist
+    NotFound(String),
+    /// The data is not (yet) available on this proxy
+    NotSynced(String),
+    /// The object exists but has no data on any endpoint
+    NotAvailable(String),
+    /// The storage backend failed temporarily
+    BackendUnavailable(String),
+    /// The Aruna server is not reachable
+    NotifierUnavailable(String),
+    AuthFailure(String),
+    Internal(String),
+}
+
+impl ProxyError {
+    pub fn message(&self) -> &str {
+        match self {
+            ProxyError::BucketNotFound(msg)
+            | ProxyError::NotFound(msg)
+            | ProxyError::NotSynced(msg)
+            | ProxyError::NotAvailable(msg)
+            | ProxyError::BackendUnavailable(msg)
+            | ProxyError::NotifierUnavailable(msg)
+            | ProxyError::AuthFailure(msg)
+            | ProxyError::Internal(msg) => msg,
+        }
+    }
+
+    fn with_message(&self, message: String) -> Self {
+        match self {
+            ProxyError::BucketNotFound(_) => ProxyError::BucketNotFound(message),
+            ProxyError::NotFound(_) => ProxyError::NotFound(message),
+            ProxyError::NotSynced(_) => ProxyError::NotSynced(message),
+            ProxyError::NotAvailable(_) => ProxyError::NotAvailable(message),
+            ProxyError::BackendUnavailable(_) => ProxyError::BackendUnavailable(message),
+            ProxyError::NotifierUnavailable(_) => ProxyError::NotifierUnavailable(message),
+            ProxyError::AuthFailure(_) => ProxyError::AuthFailure(message),
+            ProxyError::Internal(_) => ProxyError::Internal(message),
+        }
+    }
+
+    /// Classifies an error, used by `trace_err!`
+    ///
+    /// The operation and resource are logged and prefixed to the message.
+    pub fn traced(
+        error: impl Into<anyhow::Error>,
+        operation: &str,
+        resource: Option<String>,
+    ) -> Self {
+        let error: anyhow::Error = error.into();
+        let classified = ProxyError::from(&error);
+        let context = match &resource {
+            Some(resource) => format!("{operation} ({resource})"),
+            None => operation.to_string(),
+        };
+        error!(
+            error = ?error,
+            operation,
+            resource,
+            kind = classified.kind(),
+            msg = error.to_string()
+        );
+        classified.with_message(format!("{context}: {}", classified.message()))
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ProxyError::BucketNotFound(_) => "bucket_not_found",
+            ProxyError::NotFound(_) => "not_found",
+            ProxyError::NotSynced(_) => "not_synced",
+            ProxyError::NotAvailable(_) => "not_available",
+            ProxyError::BackendUnavailable(_) => "backend_unavailable",
+            ProxyError::NotifierUnavailable(_) => "notifier_unavailable",
+            ProxyError::AuthFailure(_) => "auth_failure",
+            ProxyError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl From<&anyhow::Error> for ProxyError {
+    fn from(error: &anyhow::Error) -> Self {
+        if let Some(proxy_error) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<ProxyError>())
+        {
+            return proxy_error.clone();
+        }
+        if let Some(status) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<tonic::Status>())
+        {
+            let message = status.message().to_string();
+            return match status.code() {
+                tonic::Code::NotFound => ProxyError::NotFound(message),
+                tonic::Code::Unavailable
+                | tonic::Code::DeadlineExceeded
+                | tonic::Code::Cancelled => ProxyError::NotifierUnavailable(message),
+                tonic::Code::Unauthenticated | tonic::Code::PermissionDenied => {
+                    ProxyError::AuthFailure(message)
+                }
+                _ => ProxyError::Internal(message),
+            };
+        }
+        ProxyError::Internal(error.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ProxyError {
+    fn from(error: anyhow::Error) -> Self {
+        ProxyError::from(&error)
+    }
+}
+
+impl From<ProxyError> for S3Error {
+    fn from(error: ProxyError) -> Self {
+        let (code, status) = match &error {
+            ProxyError::BucketNotFound(_) => (S3ErrorCode::NoSuchBucket, StatusCode::NOT_FOUND),
+            ProxyError::NotFound(_) => (S3ErrorCode::NoSuchKey, StatusCode::NOT_FOUND),
+            ProxyError::NotSynced(_) => (
+                S3ErrorCode::Custom("ObjectNotSynced".into()),
+                StatusCode::CONFLICT,
+            ),
+            ProxyError::NotAvailable(_) => (
+                S3ErrorCode::Custom("ObjectNotAvailable".into()),
+                StatusCode::NOT_FOUND,
+            ),
+            ProxyError::BackendUnavailable(_) | ProxyError::NotifierUnavailable(_) => (
+                S3ErrorCode::ServiceUnavailable,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            ProxyError::AuthFailure(_) => (S3ErrorCode::AccessDenied, StatusCode::FORBIDDEN),
+            ProxyError::Internal(_) => (
+                S3ErrorCode::InternalError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        };
+        let mut s3_error = S3Error::with_message(code, error.message().to_string());
+        s3_error.set_status_code(status);
+        s3_error
+    }
+}
+
+impl From<ProxyError> for tonic::Status {
+    fn from(error: ProxyError) -> Self {
+        let message = error.message().to_string();
+        match error {
+            ProxyError::BucketNotFound(_)
+            | ProxyError::NotFound(_)
+            | ProxyError::NotAvailable(_) => tonic::Status::not_found(message),
+            ProxyError::NotSynced(_) => tonic::Status::failed_precondition(message),
+            ProxyError::BackendUnavailable(_) | ProxyError::NotifierUnavailable(_) => {
+                tonic::Status::unavailable(message)
+            }
+            ProxyError::AuthFailure(_) => tonic::Status::permission_denied(message),
+            ProxyError::Internal(_) => tonic::Status::internal(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_s3_mapping() {
+        let cases = [
+            (
+                ProxyError::BucketNotFound("b".to_string()),
+                S3ErrorCode::NoSuchBucket,
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                ProxyError::NotFound("k".to_string()),
+                S3ErrorCode::NoSuchKey,
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                ProxyError::NotSynced("s".to_string()),
+                S3ErrorCode::Custom("ObjectNotSynced".into()),
+                StatusCode::CONFLICT,
+            ),
+            (
+                ProxyError::NotAvailable("o".to_string()),
+                S3ErrorCode::Custom("ObjectNotAvailable".into()),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                ProxyError::BackendUnavailable("b".to_string()),
+                S3ErrorCode::ServiceUnavailable,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ProxyError::NotifierUnavailable("n".to_string()),
+                S3ErrorCode::ServiceUnavailable,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ProxyError::AuthFailure("a".to_string()),
+                S3ErrorCode::AccessDenied,
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                ProxyError::Internal("i".to_string()),
+                S3ErrorCode::InternalError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (error, code, status) in cases {
+            let message = error.message().to_string();
+            let s3_error = S3Error::from(error);
+            assert_eq!(s3_error.code(), &code);
+            assert_eq!(s3_error.status_code(), Some(status));
+            assert_eq!(s3_error.message(), Some(message.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_grpc_mapping() {
+        let cases = [
+            (
+                ProxyError::BucketNotFound(String::new()),
+                tonic::Code::NotFound,
+            ),
+            (ProxyError::NotFound(String::new()), tonic::Code::NotFound),
+            (
+                ProxyError::NotSynced(String::new()),
+                tonic::Code::FailedPrecondition,
+            ),
+            (
+                ProxyError::BackendUnavailable(String::new()),
+                tonic::Code::Unavailable,
+            ),
+            (
+                ProxyError::NotifierUnavailable(String::new()),
+                tonic::Code::Unavailable,
+            ),
+            (
+                ProxyError::AuthFailure(String::new()),
+                tonic::Code::PermissionDenied,
+            ),
+            (ProxyError::Internal(String::new()), tonic::Code::Internal),
+        ];
+        for (error, code) in cases {
+            assert_eq!(tonic::Status::from(error).code(), code);
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        // Typed errors are found anywhere in the chain
+        let error = anyhow::Error::new(ProxyError::BackendUnavailable("slow".to_string()))
+            .context("Unable to put object");
+        assert_eq!(
+            ProxyError::from(error),
+            ProxyError::BackendUnavailable("slow".to_string())
+        );
+        // Server errors
+        let error = anyhow::Error::new(tonic::Status::unavailable("down"));
+        assert_eq!(
+            ProxyError::from(error),
+            ProxyError::NotifierUnavailable("down".to_string())
+        );
+        let error = anyhow::Error::new(tonic::Status::not_found("no parent"));
+        assert_eq!(
+            ProxyError::from(error),
+            ProxyError::NotFound("no parent".to_string())
+        );
+        assert_eq!(
+            ProxyError::from(anyhow!("boom")),
+            ProxyError::Internal("boom".to_string())
+        );
+        // Context is prefixed
+        let traced = ProxyError::traced(
+            tonic::Status::unavailable("down"),
+            "create_collection",
+            Some("bucket/col".to_string()),
+        );
+        assert_eq!(
+            traced,
+            ProxyError::NotifierUnavailable("create_collection (bucket/col): down".to_string())
+        );
+    }
+}
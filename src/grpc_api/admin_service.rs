@@ -0,0 +1,1307 @@
+//! Proxy administration service, not part of the Aruna API definitions.
+//!
+//! Messages and server are written out by hand (see `proto/dataproxy_admin_service.proto`
+//! for clients), the service is only usable with tokens of `proxy.admin_ids`.
+use crate::{
+    auth::{auth_helpers::get_token_from_md, rule_engine::LoadedRule},
+    caching::cache::{Cache, ResyncOutcome},
+    config::DEFAULT_BACKEND,
+    data_backends::storage_backend::StorageBackend,
+    maintenance::backend_migration::{BackendMigrationHandler, BackendMigrationJob},
+    maintenance::footer_repair::{FooterRepairHandler, RepairStatus},
+    maintenance::key_rotation::{KeyRotationHandler, KeyRotationJob},
+    replication::progress::{FailedReplication, ObjectProgress, FAILED_RETENTION_HOURS},
+    replication::replication_handler::Direction,
+    structs::{PendingNotification, PendingWebhook},
+    CONFIG,
+};
+use diesel_ulid::DieselUlid;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, StdError};
+use tracing::{debug, error, info, info_span, Instrument};
+
+const SERVICE_NAME: &str = "aruna.api.dataproxy.admin.v2.DataproxyAdminService";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResyncResourcesRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub resource_ids: Vec<String>,
+    /// `bucket/collection/...` paths, resolved with the current cache state
+    #[prost(string, repeated, tag = "2")]
+    pub paths: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResyncResourcesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub results: Vec<ResyncResult>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlushAccessKeyRequest {
+    #[prost(string, tag = "1")]
+    pub access_key: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlushAccessKeyResponse {
+    #[prost(message, optional, tag = "1")]
+    pub result: Option<ResyncResult>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResyncResult {
+    /// Resource id, path or access key as requested
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(enumeration = "ResyncStatus", tag = "2")]
+    pub status: i32,
+    #[prost(string, tag = "3")]
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ResyncStatus {
+    Unspecified = 0,
+    Refreshed = 1,
+    /// Does not exist upstream (anymore), removed from the cache
+    Deleted = 2,
+    Failed = 3,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListRulesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListRulesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub rules: Vec<RuleInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReloadRulesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReloadRulesResponse {
+    /// Rules active after the reload
+    #[prost(message, repeated, tag = "1")]
+    pub rules: Vec<RuleInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RuleInfo {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    /// ROOT, OBJECT, OBJECTPACKAGE, BUNDLE, REPLICATIONIN or REPLICATIONOUT
+    #[prost(string, tag = "2")]
+    pub target: String,
+    #[prost(string, tag = "3")]
+    pub rule: String,
+    #[prost(string, tag = "4")]
+    pub message: String,
+    #[prost(bool, tag = "5")]
+    pub dry_run: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetSyncStatusRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetSyncStatusResponse {
+    /// Unix timestamp of the last successful full sync, 0 if none completed
+    #[prost(int64, tag = "1")]
+    pub last_full_sync: i64,
+    /// Unix timestamp of the last event or ping of the event stream, 0 if none was received
+    #[prost(int64, tag = "2")]
+    pub last_event: i64,
+    #[prost(uint64, tag = "3")]
+    pub resources: u64,
+    #[prost(uint64, tag = "4")]
+    pub users: u64,
+    #[prost(uint64, tag = "5")]
+    pub pubkeys: u64,
+    /// All sync errors since the start of the proxy
+    #[prost(uint64, tag = "6")]
+    pub error_count: u64,
+    /// The most recent sync errors, oldest first
+    #[prost(message, repeated, tag = "7")]
+    pub errors: Vec<SyncErrorInfo>,
+    #[prost(bool, tag = "8")]
+    pub resync_running: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SyncErrorInfo {
+    #[prost(int64, tag = "1")]
+    pub timestamp: i64,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ForceResyncRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ForceResyncResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MigrateLocationsRequest {
+    /// Project, collection, dataset or object whose locations are moved
+    #[prost(string, tag = "1")]
+    pub resource_id: String,
+    /// Name of a configured backend, `default` for the main backend
+    #[prost(string, tag = "2")]
+    pub backend: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MigrateLocationsResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetMigrationStatusRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetMigrationStatusResponse {
+    /// All migrations since the start of the proxy, oldest first
+    #[prost(message, repeated, tag = "1")]
+    pub migrations: Vec<MigrationInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MigrationInfo {
+    #[prost(string, tag = "1")]
+    pub resource_id: String,
+    #[prost(string, tag = "2")]
+    pub backend: String,
+    #[prost(uint64, tag = "3")]
+    pub total: u64,
+    #[prost(uint64, tag = "4")]
+    pub migrated: u64,
+    /// Already on the target backend, shared or not finalized
+    #[prost(uint64, tag = "5")]
+    pub skipped: u64,
+    #[prost(uint64, tag = "6")]
+    pub failed: u64,
+    #[prost(bool, tag = "7")]
+    pub running: bool,
+    #[prost(int64, tag = "8")]
+    pub started_at: i64,
+    /// Unix timestamp, 0 while running
+    #[prost(int64, tag = "9")]
+    pub finished_at: i64,
+    /// The most recent failures as `<object id>: <error>`
+    #[prost(string, repeated, tag = "10")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RepairLocationRequest {
+    #[prost(string, tag = "1")]
+    pub object_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RepairLocationResponse {
+    /// `repaired`, `rewritten` or `skipped`
+    #[prost(string, tag = "1")]
+    pub status: String,
+    /// Reason if the location was skipped
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListWebhookDeadLettersRequest {
+    /// Only dead letters of this project, empty for all projects
+    #[prost(string, tag = "1")]
+    pub project_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListWebhookDeadLettersResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub dead_letters: Vec<WebhookDeadLetter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RetryWebhookDeadLettersRequest {
+    /// Only dead letters of this project, empty for all projects
+    #[prost(string, tag = "1")]
+    pub project_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RetryWebhookDeadLettersResponse {
+    /// Number of events that are delivered again
+    #[prost(uint64, tag = "1")]
+    pub requeued: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WebhookDeadLetter {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub project_id: String,
+    #[prost(string, tag = "3")]
+    pub event_name: String,
+    #[prost(string, tag = "4")]
+    pub bucket: String,
+    #[prost(string, tag = "5")]
+    pub key: String,
+    #[prost(uint32, tag = "6")]
+    pub attempts: u32,
+    #[prost(string, tag = "7")]
+    pub last_error: String,
+    /// Unix timestamp of the event
+    #[prost(int64, tag = "8")]
+    pub event_time: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetPolicyStatusRequest {
+    #[prost(string, tag = "1")]
+    pub project_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetPolicyStatusResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub errors: Vec<PolicyError>,
+    /// Number of parsed attribute values since the start of the proxy
+    #[prost(uint64, tag = "2")]
+    pub compilations: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PolicyError {
+    /// Attribute key, e.g. app.aruna-storage.org/cors
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(string, tag = "2")]
+    pub error: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RotateEncryptionKeysRequest {
+    /// Project, collection, dataset or object whose locations are re-encrypted
+    #[prost(string, tag = "1")]
+    pub resource_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RotateEncryptionKeysResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetKeyRotationStatusRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetKeyRotationStatusResponse {
+    /// All key rotations since the start of the proxy, oldest first
+    #[prost(message, repeated, tag = "1")]
+    pub rotations: Vec<KeyRotationInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyRotationInfo {
+    #[prost(string, tag = "1")]
+    pub resource_id: String,
+    #[prost(uint64, tag = "2")]
+    pub total: u64,
+    #[prost(uint64, tag = "3")]
+    pub rotated: u64,
+    /// Unencrypted, customer encrypted, pithos, shared or not finalized
+    #[prost(uint64, tag = "4")]
+    pub skipped: u64,
+    #[prost(uint64, tag = "5")]
+    pub failed: u64,
+    #[prost(bool, tag = "6")]
+    pub running: bool,
+    #[prost(int64, tag = "7")]
+    pub started_at: i64,
+    /// Unix timestamp, 0 while running
+    #[prost(int64, tag = "8")]
+    pub finished_at: i64,
+    /// The most recent failures as `<object id>: <error>`
+    #[prost(string, repeated, tag = "9")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetReplicationProgressRequest {
+    /// Permanently failed replications of the last hours, 0 for all retained ones
+    #[prost(int64, tag = "1")]
+    pub failed_hours: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetReplicationProgressResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub running: Vec<ReplicationObjectProgress>,
+    #[prost(message, repeated, tag = "2")]
+    pub failed: Vec<FailedReplicationInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReplicationObjectProgress {
+    #[prost(string, tag = "1")]
+    pub object_id: String,
+    #[prost(string, tag = "2")]
+    pub endpoint_id: String,
+    /// `pull` or `push`
+    #[prost(string, tag = "3")]
+    pub direction: String,
+    /// -1 until the sender announced the object
+    #[prost(int64, tag = "4")]
+    pub expected_chunks: i64,
+    #[prost(int64, tag = "5")]
+    pub received_chunks: i64,
+    #[prost(uint64, tag = "6")]
+    pub bytes_written: u64,
+    #[prost(string, tag = "7")]
+    pub last_error: String,
+    #[prost(int64, tag = "8")]
+    pub updated_at: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FailedReplicationInfo {
+    #[prost(string, tag = "1")]
+    pub object_id: String,
+    #[prost(string, tag = "2")]
+    pub endpoint_id: String,
+    #[prost(string, tag = "3")]
+    pub direction: String,
+    #[prost(string, tag = "4")]
+    pub error: String,
+    #[prost(int64, tag = "5")]
+    pub failed_at: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetPendingNotificationsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetPendingNotificationsResponse {
+    /// Queue depth, should stay close to zero
+    #[prost(uint64, tag = "1")]
+    pub pending: u64,
+    /// Pending notifications, the next due first
+    #[prost(message, repeated, tag = "2")]
+    pub notifications: Vec<PendingNotificationInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PendingNotificationInfo {
+    /// Local id while the object still has to be created
+    #[prost(string, tag = "1")]
+    pub object_id: String,
+    /// CreateObject failed as well
+    #[prost(bool, tag = "2")]
+    pub create: bool,
+    #[prost(uint32, tag = "3")]
+    pub attempts: u32,
+    /// Unix timestamp of the next attempt
+    #[prost(int64, tag = "4")]
+    pub next_attempt: i64,
+    #[prost(string, tag = "5")]
+    pub last_error: String,
+}
+
+impl From<PendingNotification> for PendingNotificationInfo {
+    fn from(notification: PendingNotification) -> Self {
+        PendingNotificationInfo {
+            object_id: notification.id.to_string(),
+            create: notification.create.is_some(),
+            attempts: notification.attempts,
+            next_attempt: notification.next_attempt.timestamp(),
+            last_error: notification.last_error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<PendingWebhook> for WebhookDeadLetter {
+    fn from(webhook: PendingWebhook) -> Self {
+        WebhookDeadLetter {
+            id: webhook.id.to_string(),
+            project_id: webhook.project_id.to_string(),
+            event_name: webhook.event.event_name,
+            bucket: webhook.event.bucket,
+            key: webhook.event.key,
+            attempts: webhook.attempts,
+            last_error: webhook.last_error.unwrap_or_default(),
+            event_time: webhook.event.event_time.timestamp(),
+        }
+    }
+}
+
+/// Optional project filter of the webhook requests
+fn project_filter(project_id: &str) -> Result<Option<DieselUlid>, tonic::Status> {
+    if project_id.is_empty() {
+        return Ok(None);
+    }
+    DieselUlid::from_str(project_id).map(Some).map_err(|e| {
+        error!(error = ?e, msg = e.to_string());
+        tonic::Status::invalid_argument("Invalid project id")
+    })
+}
+
+impl From<BackendMigrationJob> for MigrationInfo {
+    fn from(job: BackendMigrationJob) -> Self {
+        MigrationInfo {
+            resource_id: job.resource_id.to_string(),
+            backend: job.backend.unwrap_or_else(|| DEFAULT_BACKEND.to_string()),
+            total: job.total as u64,
+            migrated: job.migrated as u64,
+            skipped: job.skipped as u64,
+            failed: job.failed as u64,
+            running: job.running,
+            started_at: job.started_at,
+            finished_at: job.finished_at.unwrap_or_default(),
+            errors: job
+                .errors
+                .into_iter()
+                .map(|(id, e)| format!("{id}: {e}"))
+                .collect(),
+        }
+    }
+}
+
+impl From<KeyRotationJob> for KeyRotationInfo {
+    fn from(job: KeyRotationJob) -> Self {
+        KeyRotationInfo {
+            resource_id: job.resource_id.to_string(),
+            total: job.total as u64,
+            rotated: job.rotated as u64,
+            skipped: job.skipped as u64,
+            failed: job.failed as u64,
+            running: job.running,
+            started_at: job.started_at,
+            finished_at: job.finished_at.unwrap_or_default(),
+            errors: job
+                .errors
+                .into_iter()
+                .map(|(id, e)| format!("{id}: {e}"))
+                .collect(),
+        }
+    }
+}
+
+fn direction_name(direction: &Direction) -> String {
+    match direction {
+        Direction::Pull(_) => "pull".to_string(),
+        Direction::Push(_) => "push".to_string(),
+    }
+}
+
+impl From<(DieselUlid, ObjectProgress)> for ReplicationObjectProgress {
+    fn from((object_id, progress): (DieselUlid, ObjectProgress)) -> Self {
+        ReplicationObjectProgress {
+            object_id: object_id.to_string(),
+            endpoint_id: progress.endpoint_id.to_string(),
+            direction: direction_name(&progress.direction),
+            expected_chunks: progress.expected_chunks.unwrap_or(-1),
+            received_chunks: progress.received_chunks,
+            bytes_written: progress.bytes_written,
+            last_error: progress.last_error.unwrap_or_default(),
+            updated_at: progress.updated_at.timestamp(),
+        }
+    }
+}
+
+impl From<(DieselUlid, FailedReplication)> for FailedReplicationInfo {
+    fn from((object_id, failed): (DieselUlid, FailedReplication)) -> Self {
+        FailedReplicationInfo {
+            object_id: object_id.to_string(),
+            endpoint_id: failed.endpoint_id.to_string(),
+            direction: direction_name(&failed.direction),
+            error: failed.error,
+            failed_at: failed.failed_at.timestamp(),
+        }
+    }
+}
+
+/// Target backend of a migration, None for the main backend
+fn migration_target(backend: &str) -> Result<Option<String>, tonic::Status> {
+    match backend {
+        "" | DEFAULT_BACKEND => Ok(None),
+        name if CONFIG.backends.contains_key(name) => Ok(Some(name.to_string())),
+        name => {
+            error!(backend = name, "Unknown storage backend");
+            Err(tonic::Status::invalid_argument(format!(
+                "Unknown storage backend: {name}"
+            )))
+        }
+    }
+}
+
+impl From<&LoadedRule> for RuleInfo {
+    fn from(rule: &LoadedRule) -> Self {
+        RuleInfo {
+            id: rule.id.clone(),
+            target: format!("{:?}", rule.target),
+            rule: rule.rule.clone(),
+            message: rule.message.clone().unwrap_or_default(),
+            dry_run: rule.dry_run,
+        }
+    }
+}
+
+impl ResyncResult {
+    fn new(id: String, result: anyhow::Result<ResyncOutcome>) -> Self {
+        let (status, message) = match result {
+            Ok(ResyncOutcome::Refreshed) => (ResyncStatus::Refreshed, String::new()),
+            Ok(ResyncOutcome::Deleted) => (ResyncStatus::Deleted, String::new()),
+            Err(e) => (ResyncStatus::Failed, e.to_string()),
+        };
+        ResyncResult {
+            id,
+            status: status as i32,
+            message,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DataproxyAdminServiceImpl {
+    pub cache: Arc<Cache>,
+    migrations: Arc<BackendMigrationHandler>,
+    rotations: Arc<KeyRotationHandler>,
+    footer_repair: Arc<FooterRepairHandler>,
+}
+
+impl DataproxyAdminServiceImpl {
+    #[tracing::instrument(level = "trace", skip(cache, backend))]
+    pub fn new(cache: Arc<Cache>, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        Self {
+            migrations: Arc::new(BackendMigrationHandler::new(cache.clone(), backend.clone())),
+            rotations: Arc::new(KeyRotationHandler::new(cache.clone(), backend.clone())),
+            footer_repair: Arc::new(FooterRepairHandler::new(cache.clone(), backend)),
+            cache,
+        }
+    }
+
+    async fn check_admin(&self, md: &tonic::metadata::MetadataMap) -> Result<(), tonic::Status> {
+        self.authenticate_admin(md).await?;
+        if self.cache.aruna_client.read().await.is_none() {
+            error!(error = "Query handler not available");
+            return Err(tonic::Status::unavailable(
+                "Server connection not available",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn authenticate_admin(
+        &self,
+        md: &tonic::metadata::MetadataMap,
+    ) -> Result<(), tonic::Status> {
+        if let Some(a) = self.cache.auth.read().await.as_ref() {
+            let token = get_token_from_md(md).map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::unauthenticated(e.to_string())
+            })?;
+            let user_id = a.check_admin_token(&token).await.map_err(|_| {
+                error!(error = "Unable to authenticate proxy admin");
+                tonic::Status::permission_denied("Invalid permissions")
+            })?;
+            debug!(?user_id, "Authenticated proxy admin");
+        } else {
+            error!(error = "Unable to authenticate user, cache is empty");
+            return Err(tonic::Status::unauthenticated(
+                "Unable to authenticate user",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Forces a refetch of resources, their location and permission holders from the server
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn resync_resources(
+        &self,
+        request: tonic::Request<ResyncResourcesRequest>,
+    ) -> Result<tonic::Response<ResyncResourcesResponse>, tonic::Status> {
+        self.check_admin(request.metadata()).await?;
+        let request = request.into_inner();
+
+        let mut results = Vec::new();
+        for id in request.resource_ids {
+            let result = match DieselUlid::from_str(&id) {
+                Ok(resource_id) => self.cache.resync_resource(&resource_id).await,
+                Err(e) => Err(anyhow::anyhow!("Invalid resource id: {e}")),
+            };
+            results.push(ResyncResult::new(id, result));
+        }
+        for path in request.paths {
+            let result = match self.cache.get_path(path.trim_matches('/')) {
+                Some(resource_id) => self.cache.resync_resource(&resource_id).await,
+                None => Err(anyhow::anyhow!("Unknown path")),
+            };
+            results.push(ResyncResult::new(path, result));
+        }
+        for result in results.iter() {
+            if result.status == ResyncStatus::Failed as i32 {
+                error!(id = result.id, msg = result.message, "Resync failed");
+            }
+        }
+        Ok(tonic::Response::new(ResyncResourcesResponse { results }))
+    }
+
+    /// Drops the cached permissions of an access key and reloads them from the server
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn flush_access_key(
+        &self,
+        request: tonic::Request<FlushAccessKeyRequest>,
+    ) -> Result<tonic::Response<FlushAccessKeyResponse>, tonic::Status> {
+        self.check_admin(request.metadata()).await?;
+        let access_key = request.into_inner().access_key;
+        if access_key.is_empty() {
+            return Err(tonic::Status::invalid_argument("Missing access key"));
+        }
+        let result = self.cache.resync_access_key(&access_key).await;
+        Ok(tonic::Response::new(FlushAccessKeyResponse {
+            result: Some(ResyncResult::new(access_key, result)),
+        }))
+    }
+
+    /// Lists the rules currently evaluated by the rule engine
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn list_rules(
+        &self,
+        request: tonic::Request<ListRulesRequest>,
+    ) -> Result<tonic::Response<ListRulesResponse>, tonic::Status> {
+        self.check_admin(request.metadata()).await?;
+        let rules = match self.cache.auth.read().await.as_ref() {
+            Some(auth) => auth.rule_engine().rules(),
+            None => return Err(tonic::Status::unavailable("Auth handler not available")),
+        };
+        Ok(tonic::Response::new(ListRulesResponse {
+            rules: rules.iter().map(RuleInfo::from).collect(),
+        }))
+    }
+
+    /// Reloads the rules from the config file without restarting the proxy
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn reload_rules(
+        &self,
+        request: tonic::Request<ReloadRulesRequest>,
+    ) -> Result<tonic::Response<ReloadRulesResponse>, tonic::Status> {
+        self.check_admin(request.metadata()).await?;
+        let rules = match self.cache.auth.read().await.as_ref() {
+            Some(auth) => auth.rule_engine().reload().map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::failed_precondition(e.to_string())
+            })?,
+            None => return Err(tonic::Status::unavailable("Auth handler not available")),
+        };
+        Ok(tonic::Response::new(ReloadRulesResponse {
+            rules: rules.iter().map(RuleInfo::from).collect(),
+        }))
+    }
+
+    /// Reports whether the cache is in sync with the server, also available while the
+    /// server connection is down
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_sync_status(
+        &self,
+        request: tonic::Request<GetSyncStatusRequest>,
+    ) -> Result<tonic::Response<GetSyncStatusResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let state = &self.cache.sync_state;
+        let (resources, users, pubkeys) = self.cache.sync_counts();
+        Ok(tonic::Response::new(GetSyncStatusResponse {
+            last_full_sync: state.last_full_sync(),
+            last_event: state.last_event(),
+            resources: resources as u64,
+            users: users as u64,
+            pubkeys: pubkeys as u64,
+            error_count: state.error_count(),
+            errors: state
+                .errors()
+                .into_iter()
+                .map(|e| SyncErrorInfo {
+                    timestamp: e.timestamp,
+                    message: e.message,
+                })
+                .collect(),
+            resync_running: state.resync_running(),
+        }))
+    }
+
+    /// Starts a full sync with the server in the background, requests are served
+    /// from the existing cache until the received state was applied
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn force_resync(
+        &self,
+        request: tonic::Request<ForceResyncRequest>,
+    ) -> Result<tonic::Response<ForceResyncResponse>, tonic::Status> {
+        self.check_admin(request.metadata()).await?;
+        let Some(client) = self.cache.aruna_client.read().await.clone() else {
+            return Err(tonic::Status::unavailable(
+                "Server connection not available",
+            ));
+        };
+        if !self.cache.sync_state.try_start_resync() {
+            error!(error = "Resync already running");
+            return Err(tonic::Status::failed_precondition(
+                "A resync is already running",
+            ));
+        }
+        let cache = self.cache.clone();
+        crate::spawn(
+            async move {
+                match client.full_sync(true).await {
+                    Ok(()) => info!("Forced resync finished"),
+                    Err(e) => error!(error = ?e, msg = "Forced resync failed"),
+                }
+                cache.sync_state.resync_finished();
+            }
+            .instrument(info_span!("force_resync")),
+        );
+        Ok(tonic::Response::new(ForceResyncResponse {}))
+    }
+
+    /// Starts moving the locations below a resource to another storage backend
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn migrate_locations(
+        &self,
+        request: tonic::Request<MigrateLocationsRequest>,
+    ) -> Result<tonic::Response<MigrateLocationsResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let request = request.into_inner();
+        let resource_id = DieselUlid::from_str(&request.resource_id).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::invalid_argument("Invalid resource id")
+        })?;
+        let backend = migration_target(&request.backend)?;
+        if self.cache.get_resource(&resource_id).await.is_err() {
+            error!(?resource_id, "Resource not found");
+            return Err(tonic::Status::not_found("Resource not found"));
+        }
+        self.migrations
+            .start(resource_id, backend.clone())
+            .map_err(|e| tonic::Status::failed_precondition(e.to_string()))?;
+        info!(?resource_id, ?backend, "Started backend migration");
+        Ok(tonic::Response::new(MigrateLocationsResponse {}))
+    }
+
+    /// Progress of the backend migrations
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_migration_status(
+        &self,
+        request: tonic::Request<GetMigrationStatusRequest>,
+    ) -> Result<tonic::Response<GetMigrationStatusResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        Ok(tonic::Response::new(GetMigrationStatusResponse {
+            migrations: self
+                .migrations
+                .jobs()
+                .into_iter()
+                .map(MigrationInfo::from)
+                .collect(),
+        }))
+    }
+
+    /// Reconstructs the footer or disk metadata of a single object, locations that
+    /// are complete already are skipped
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn repair_location(
+        &self,
+        request: tonic::Request<RepairLocationRequest>,
+    ) -> Result<tonic::Response<RepairLocationResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let object_id = DieselUlid::from_str(&request.into_inner().object_id).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::invalid_argument("Invalid object id")
+        })?;
+        let status = self
+            .footer_repair
+            .repair_object(object_id)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::internal(format!("Repair failed: {e}"))
+            })?;
+        info!(?object_id, ?status, "Location repair finished");
+        let message = match &status {
+            RepairStatus::Skipped(reason) => reason.clone(),
+            _ => String::new(),
+        };
+        Ok(tonic::Response::new(RepairLocationResponse {
+            status: status.label().to_string(),
+            message,
+        }))
+    }
+
+    /// Webhook events that were not delivered within `webhooks.max_attempts`
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn list_webhook_dead_letters(
+        &self,
+        request: tonic::Request<ListWebhookDeadLettersRequest>,
+    ) -> Result<tonic::Response<ListWebhookDeadLettersResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let project_id = project_filter(&request.into_inner().project_id)?;
+        Ok(tonic::Response::new(ListWebhookDeadLettersResponse {
+            dead_letters: self
+                .cache
+                .get_webhook_dead_letters(project_id.as_ref())
+                .into_iter()
+                .map(WebhookDeadLetter::from)
+                .collect(),
+        }))
+    }
+
+    /// Queues the dead letters for delivery again, e.g. after the receiver was fixed
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn retry_webhook_dead_letters(
+        &self,
+        request: tonic::Request<RetryWebhookDeadLettersRequest>,
+    ) -> Result<tonic::Response<RetryWebhookDeadLettersResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let project_id = project_filter(&request.into_inner().project_id)?;
+        let requeued = self
+            .cache
+            .requeue_webhook_dead_letters(project_id.as_ref())
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::internal("Unable to requeue dead letters")
+            })?;
+        info!(?project_id, requeued, "Requeued webhook dead letters");
+        Ok(tonic::Response::new(RetryWebhookDeadLettersResponse {
+            requeued: requeued as u64,
+        }))
+    }
+
+    /// Errors of quarantined policy attributes, the last valid versions stay in effect
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_policy_status(
+        &self,
+        request: tonic::Request<GetPolicyStatusRequest>,
+    ) -> Result<tonic::Response<GetPolicyStatusResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let project_id = DieselUlid::from_str(&request.into_inner().project_id).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::invalid_argument("Invalid project id")
+        })?;
+        Ok(tonic::Response::new(GetPolicyStatusResponse {
+            errors: self
+                .cache
+                .get_policy_errors(&project_id)
+                .into_iter()
+                .map(|(key, error)| PolicyError {
+                    key: key.to_string(),
+                    error,
+                })
+                .collect(),
+            compilations: self.cache.get_policy_compilations(&project_id) as u64,
+        }))
+    }
+
+    /// Starts re-encrypting the locations below a resource with new keys
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn rotate_encryption_keys(
+        &self,
+        request: tonic::Request<RotateEncryptionKeysRequest>,
+    ) -> Result<tonic::Response<RotateEncryptionKeysResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let resource_id = DieselUlid::from_str(&request.into_inner().resource_id).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::invalid_argument("Invalid resource id")
+        })?;
+        if self.cache.get_resource(&resource_id).await.is_err() {
+            error!(?resource_id, "Resource not found");
+            return Err(tonic::Status::not_found("Resource not found"));
+        }
+        // Progress is tracked in the job, the per object receiver is not needed
+        self.rotations
+            .rotate(resource_id)
+            .map_err(|e| tonic::Status::failed_precondition(e.to_string()))?;
+        info!(?resource_id, "Started key rotation");
+        Ok(tonic::Response::new(RotateEncryptionKeysResponse {}))
+    }
+
+    /// Objects in the replication queue and recently failed replications
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_replication_progress(
+        &self,
+        request: tonic::Request<GetReplicationProgressRequest>,
+    ) -> Result<tonic::Response<GetReplicationProgressResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let hours = match request.into_inner().failed_hours {
+            hours if hours <= 0 => FAILED_RETENTION_HOURS,
+            hours => hours,
+        };
+        let progress = &self.cache.replication_progress;
+        Ok(tonic::Response::new(GetReplicationProgressResponse {
+            running: progress
+                .get_running()
+                .into_iter()
+                .map(ReplicationObjectProgress::from)
+                .collect(),
+            failed: progress
+                .get_failed(hours)
+                .into_iter()
+                .map(FailedReplicationInfo::from)
+                .collect(),
+        }))
+    }
+
+    /// Progress of the key rotations
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_key_rotation_status(
+        &self,
+        request: tonic::Request<GetKeyRotationStatusRequest>,
+    ) -> Result<tonic::Response<GetKeyRotationStatusResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        Ok(tonic::Response::new(GetKeyRotationStatusResponse {
+            rotations: self
+                .rotations
+                .jobs()
+                .into_iter()
+                .map(KeyRotationInfo::from)
+                .collect(),
+        }))
+    }
+
+    /// Server notifications (and object creations) that wait for redelivery
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_pending_notifications(
+        &self,
+        request: tonic::Request<GetPendingNotificationsRequest>,
+    ) -> Result<tonic::Response<GetPendingNotificationsResponse>, tonic::Status> {
+        self.authenticate_admin(request.metadata()).await?;
+        let notifications = self.cache.get_pending_notifications();
+        Ok(tonic::Response::new(GetPendingNotificationsResponse {
+            pending: notifications.len() as u64,
+            notifications: notifications
+                .into_iter()
+                .map(PendingNotificationInfo::from)
+                .collect(),
+        }))
+    }
+}
+
+/// Routes the admin service methods, equivalent to tonic generated servers
+#[derive(Clone)]
+pub struct DataproxyAdminServiceServer {
+    inner: Arc<DataproxyAdminServiceImpl>,
+}
+
+impl DataproxyAdminServiceServer {
+    pub fn new(inner: DataproxyAdminServiceImpl) -> Self {
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+struct ResyncResourcesSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<ResyncResourcesRequest> for ResyncResourcesSvc {
+    type Response = ResyncResourcesResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<ResyncResourcesRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.resync_resources(request).await })
+    }
+}
+
+struct FlushAccessKeySvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<FlushAccessKeyRequest> for FlushAccessKeySvc {
+    type Response = FlushAccessKeyResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<FlushAccessKeyRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.flush_access_key(request).await })
+    }
+}
+
+struct ListRulesSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<ListRulesRequest> for ListRulesSvc {
+    type Response = ListRulesResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<ListRulesRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.list_rules(request).await })
+    }
+}
+
+struct ReloadRulesSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<ReloadRulesRequest> for ReloadRulesSvc {
+    type Response = ReloadRulesResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<ReloadRulesRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.reload_rules(request).await })
+    }
+}
+
+struct GetSyncStatusSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<GetSyncStatusRequest> for GetSyncStatusSvc {
+    type Response = GetSyncStatusResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetSyncStatusRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_sync_status(request).await })
+    }
+}
+
+struct ForceResyncSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<ForceResyncRequest> for ForceResyncSvc {
+    type Response = ForceResyncResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<ForceResyncRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.force_resync(request).await })
+    }
+}
+
+struct MigrateLocationsSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<MigrateLocationsRequest> for MigrateLocationsSvc {
+    type Response = MigrateLocationsResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<MigrateLocationsRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.migrate_locations(request).await })
+    }
+}
+
+struct GetMigrationStatusSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<GetMigrationStatusRequest> for GetMigrationStatusSvc {
+    type Response = GetMigrationStatusResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetMigrationStatusRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_migration_status(request).await })
+    }
+}
+
+struct RepairLocationSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<RepairLocationRequest> for RepairLocationSvc {
+    type Response = RepairLocationResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<RepairLocationRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.repair_location(request).await })
+    }
+}
+
+struct ListWebhookDeadLettersSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<ListWebhookDeadLettersRequest> for ListWebhookDeadLettersSvc {
+    type Response = ListWebhookDeadLettersResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<ListWebhookDeadLettersRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.list_webhook_dead_letters(request).await })
+    }
+}
+
+struct RetryWebhookDeadLettersSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<RetryWebhookDeadLettersRequest> for RetryWebhookDeadLettersSvc {
+    type Response = RetryWebhookDeadLettersResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<RetryWebhookDeadLettersRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.retry_webhook_dead_letters(request).await })
+    }
+}
+
+struct GetPolicyStatusSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<GetPolicyStatusRequest> for GetPolicyStatusSvc {
+    type Response = GetPolicyStatusResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetPolicyStatusRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_policy_status(request).await })
+    }
+}
+
+struct RotateEncryptionKeysSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<RotateEncryptionKeysRequest> for RotateEncryptionKeysSvc {
+    type Response = RotateEncryptionKeysResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<RotateEncryptionKeysRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.rotate_encryption_keys(request).await })
+    }
+}
+
+struct GetReplicationProgressSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<GetReplicationProgressRequest> for GetReplicationProgressSvc {
+    type Response = GetReplicationProgressResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetReplicationProgressRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_replication_progress(request).await })
+    }
+}
+
+struct GetKeyRotationStatusSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<GetKeyRotationStatusRequest> for GetKeyRotationStatusSvc {
+    type Response = GetKeyRotationStatusResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetKeyRotationStatusRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_key_rotation_status(request).await })
+    }
+}
+
+struct GetPendingNotificationsSvc(Arc<DataproxyAdminServiceImpl>);
+
+impl tonic::server::UnaryService<GetPendingNotificationsRequest> for GetPendingNotificationsSvc {
+    type Response = GetPendingNotificationsResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetPendingNotificationsRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_pending_notifications(request).await })
+    }
+}
+
+impl<B> tower::Service<http::Request<B>> for DataproxyAdminServiceServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let inner = self.inner.clone();
+        let method = req
+            .uri()
+            .path()
+            .strip_prefix(&format!("/{SERVICE_NAME}/"))
+            .map(|m| m.to_string());
+        Box::pin(async move {
+            Ok(match method.as_deref() {
+                Some("ResyncResources") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(ResyncResourcesSvc(inner), req)
+                        .await
+                }
+                Some("FlushAccessKey") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(FlushAccessKeySvc(inner), req)
+                        .await
+                }
+                Some("ListRules") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(ListRulesSvc(inner), req)
+                        .await
+                }
+                Some("ReloadRules") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(ReloadRulesSvc(inner), req)
+                        .await
+                }
+                Some("GetSyncStatus") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetSyncStatusSvc(inner), req)
+                        .await
+                }
+                Some("ForceResync") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(ForceResyncSvc(inner), req)
+                        .await
+                }
+                Some("MigrateLocations") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(MigrateLocationsSvc(inner), req)
+                        .await
+                }
+                Some("GetMigrationStatus") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetMigrationStatusSvc(inner), req)
+                        .await
+                }
+                Some("RepairLocation") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(RepairLocationSvc(inner), req)
+                        .await
+                }
+                Some("ListWebhookDeadLetters") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(ListWebhookDeadLettersSvc(inner), req)
+                        .await
+                }
+                Some("RetryWebhookDeadLetters") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(RetryWebhookDeadLettersSvc(inner), req)
+                        .await
+                }
+                Some("GetPolicyStatus") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetPolicyStatusSvc(inner), req)
+                        .await
+                }
+                Some("RotateEncryptionKeys") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(RotateEncryptionKeysSvc(inner), req)
+                        .await
+                }
+                Some("GetReplicationProgress") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetReplicationProgressSvc(inner), req)
+                        .await
+                }
+                Some("GetKeyRotationStatus") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetKeyRotationStatusSvc(inner), req)
+                        .await
+                }
+                Some("GetPendingNotifications") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetPendingNotificationsSvc(inner), req)
+                        .await
+                }
+                _ => tonic::Status::unimplemented("Unknown method").to_http(),
+            })
+        })
+    }
+}
+
+impl tonic::server::NamedService for DataproxyAdminServiceServer {
+    const NAME: &'static str = SERVICE_NAME;
+}
@@ -1,16 +1,88 @@
 use crate::{
     auth::auth_helpers::get_token_from_md,
+    bundler::bundle_helper::BundleFormat,
     caching::cache::Cache,
     helpers::sign_download_url,
-    structs::{Bundle, DbPermissionLevel},
+    structs::{Bundle, DbPermissionLevel, ObjectType, TypedId},
 };
 use aruna_rust_api::api::dataproxy::services::v2::{
     bundler_service_server::BundlerService, CreateBundleRequest, CreateBundleResponse,
     DeleteBundleRequest, DeleteBundleResponse,
 };
+use chrono::Utc;
 use diesel_ulid::DieselUlid;
 use std::{str::FromStr, sync::Arc};
-use tracing::error;
+use tonic::metadata::MetadataMap;
+use tracing::{debug, error};
+
+/// Bundle options that are not part of the request message, passed as request metadata
+const PATH_KEY: &str = "x-aruna-bundle-path";
+const INCLUDE_KEY: &str = "x-aruna-bundle-include";
+const EXCLUDE_KEY: &str = "x-aruna-bundle-exclude";
+const EXPIRES_IN_KEY: &str = "x-aruna-bundle-expires-in";
+const PUBLIC_KEY: &str = "x-aruna-bundle-public";
+const MANIFEST_KEY: &str = "x-aruna-bundle-manifest";
+
+#[derive(Debug, Default)]
+struct BundleOptions {
+    // Paths (`bucket/collection/...`) of bundled resources, in addition to the resource ids
+    paths: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    // Seconds until the bundle expires, alternative to an absolute expiry
+    expires_in: Option<i64>,
+    public: bool,
+    manifest: bool,
+}
+
+impl BundleOptions {
+    fn from_metadata(md: &MetadataMap) -> Result<Self, tonic::Status> {
+        // Repeated entries and comma separated lists are both accepted
+        let values = |key: &str| {
+            md.get_all(key)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let single = |key: &str| md.get(key).and_then(|v| v.to_str().ok());
+
+        let expires_in = single(EXPIRES_IN_KEY)
+            .map(|v| {
+                v.parse::<i64>().ok().filter(|v| *v > 0).ok_or_else(|| {
+                    error!(error = "Invalid bundle expiry duration");
+                    tonic::Status::invalid_argument(format!(
+                        "{EXPIRES_IN_KEY} must be a positive number of seconds"
+                    ))
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            paths: values(PATH_KEY)
+                .into_iter()
+                .map(|p| p.trim_matches('/').to_string())
+                .collect(),
+            include: values(INCLUDE_KEY)
+                .into_iter()
+                .map(|p| p.trim_start_matches('/').to_string())
+                .collect(),
+            exclude: values(EXCLUDE_KEY)
+                .into_iter()
+                .map(|p| p.trim_start_matches('/').to_string())
+                .collect(),
+            expires_in,
+            public: single(PUBLIC_KEY)
+                .map(|v| v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            manifest: single(MANIFEST_KEY)
+                .map(|v| v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+        })
+    }
+}
 
 pub struct BundlerServiceImpl {
     pub cache: Arc<Cache>,
@@ -43,7 +115,7 @@ impl BundlerService for BundlerServiceImpl {
                 tonic::Status::unauthenticated(e.to_string())
             })?;
             // Check if permissions are valid
-            let (u, tid, pk) = a.check_permissions(&token).map_err(|e| {
+            let (u, tid, pk) = a.check_permissions(&token).await.map_err(|e| {
                 error!(error = ?e, msg = e.to_string());
                 tonic::Status::unauthenticated(format!("Unable to authenticate user"))
             })?;
@@ -60,9 +132,19 @@ impl BundlerService for BundlerServiceImpl {
                 tonic::Status::unauthenticated(format!("Unable to authenticate user"))
             })?;
 
+            let options = BundleOptions::from_metadata(request.metadata())?;
+            debug!(?options);
             let request = request.into_inner();
 
-            let res_ids = request
+            if BundleFormat::from_filename(&request.filename).is_none() {
+                error!(error = "Unsupported bundle format");
+                return Err(tonic::Status::invalid_argument(format!(
+                    "Unsupported bundle format, supported formats: {}",
+                    BundleFormat::SUPPORTED
+                )));
+            }
+
+            let mut res_ids = request
                 .resource_ids
                 .iter()
                 .map(|id| {
@@ -72,15 +154,17 @@ impl BundlerService for BundlerServiceImpl {
                     })
                 })
                 .collect::<Result<Vec<DieselUlid>, tonic::Status>>()?;
-
-            for id in &res_ids {
-                self.cache
-                    .check_access_parents(&permissions, id, DbPermissionLevel::Read)
-                    .await
-                    .map_err(|e| {
-                        error!(error = ?e, msg = e.to_string());
-                        tonic::Status::unauthenticated(format!("Unable to authenticate user"))
-                    })?;
+            for path in &options.paths {
+                res_ids.push(self.cache.get_path(path).ok_or_else(|| {
+                    error!(path, error = "Bundle path not found");
+                    tonic::Status::not_found(format!("Path not found: {path}"))
+                })?);
+            }
+            if res_ids.is_empty() {
+                error!(error = "No resources specified");
+                return Err(tonic::Status::invalid_argument(
+                    "At least one resource id or path must be specified",
+                ));
             }
 
             let bundle_id = DieselUlid::generate();
@@ -88,25 +172,100 @@ impl BundlerService for BundlerServiceImpl {
                 id: bundle_id,
                 owner_access_key: access_key.clone(),
                 ids: res_ids,
-                expires_at: request.expires_at.map(|e| e.into()),
+                expires_at: match (request.expires_at, options.expires_in) {
+                    (Some(expires_at), _) => Some(expires_at.into()),
+                    (None, Some(expires_in)) => {
+                        Some(Utc::now() + chrono::Duration::seconds(expires_in))
+                    }
+                    (None, None) => None,
+                },
                 once: request.once,
+                include: options.include,
+                exclude: options.exclude,
+                public: options.public,
+                manifest: options.manifest,
             };
 
-            self.cache.add_bundle(bundle);
+            // Every selected object is checked before the bundle is registered,
+            // a single unreadable object fails the whole request
+            let mut selected = 0usize;
+            for id in &bundle.ids {
+                self.cache
+                    .check_access_parents(&permissions, id, DbPermissionLevel::Read)
+                    .await
+                    .map_err(|e| {
+                        error!(error = ?e, msg = e.to_string());
+                        tonic::Status::unauthenticated(format!("Unable to authenticate user"))
+                    })?;
+                for (typed_id, path) in self.cache.get_suffixes(&TypedId::Unknown(*id), false).await
+                {
+                    // Empty datasets and collections are leaves as well
+                    let object_id = typed_id.get_id();
+                    let Ok((object, _)) = self.cache.get_resource_cloned(&object_id, true).await
+                    else {
+                        continue;
+                    };
+                    // Directly bundled objects are named like in the archive
+                    let path = if path.is_empty() {
+                        format!("/{}", object.name)
+                    } else {
+                        path
+                    };
+                    if object.object_type != ObjectType::Object || !bundle.selects(&path) {
+                        continue;
+                    }
+                    self.cache
+                        .check_access_parents(&permissions, &object_id, DbPermissionLevel::Read)
+                        .await
+                        .map_err(|e| {
+                            error!(error = ?e, ?object_id, msg = e.to_string());
+                            tonic::Status::permission_denied(format!(
+                                "Missing read permission for {path}"
+                            ))
+                        })?;
+                    if self.cache.get_location(&object_id).await.is_none() {
+                        error!(?object_id, error = "Bundled object has no location");
+                        return Err(tonic::Status::failed_precondition(format!(
+                            "Object has no data on this endpoint: {path}"
+                        )));
+                    }
+                    selected += 1;
+                }
+            }
+            if selected == 0 {
+                error!(error = "Bundle selects no objects");
+                return Err(tonic::Status::invalid_argument(
+                    "The bundle does not contain any objects",
+                ));
+            }
 
-            let response = CreateBundleResponse {
-                bundle_url: sign_download_url(
+            let key = format!("{}/{}", &bundle_id.to_string(), request.filename);
+            let bundle_url = if bundle.public {
+                let endpoint = self
+                    .endpoint_url
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://");
+                let protocol = if self.ssl { "https" } else { "http" };
+                format!("{protocol}://bundles.{endpoint}/{key}")
+            } else {
+                sign_download_url(
                     &access_key,
                     &permissions.secret,
                     self.ssl,
                     "bundles",
-                    &format!("{}/{}", &bundle_id.to_string(), request.filename),
+                    &key,
                     self.endpoint_url.as_str(),
                 )
                 .map_err(|_| {
                     error!(error = "Failed to presign bundle download url");
                     tonic::Status::internal("Failed to presign bundle download url")
-                })?,
+                })?
+            };
+
+            self.cache.add_bundle(bundle);
+
+            let response = CreateBundleResponse {
+                bundle_url,
                 bundle_id: bundle_id.to_string(),
             };
             Ok(tonic::Response::new(response))
@@ -129,7 +288,7 @@ impl BundlerService for BundlerServiceImpl {
                 tonic::Status::unauthenticated(e.to_string())
             })?;
 
-            let (u, tid, pk) = a.check_permissions(&token).map_err(|_| {
+            let (u, tid, pk) = a.check_permissions(&token).await.map_err(|_| {
                 error!(error = "Unable to authenticate user");
                 tonic::Status::unauthenticated("Unable to authenticate user")
             })?;
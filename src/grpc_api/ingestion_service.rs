@@ -40,7 +40,7 @@ impl DataproxyIngestionService for DataproxyIngestionServiceImpl {
                 tonic::Status::unauthenticated(e.to_string())
             })?;
 
-            let (u, _, pk) = a.check_permissions(&token).map_err(|_| {
+            let (u, _, pk) = a.check_permissions(&token).await.map_err(|_| {
                 error!(error = "Unable to authenticate user, check permissions");
                 tonic::Status::unauthenticated("Unable to authenticate user")
             })?;
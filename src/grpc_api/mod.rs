@@ -1,4 +1,6 @@
+pub mod admin_service;
 pub mod bundler;
 pub mod ingestion_service;
 pub mod proxy_service;
+pub mod session_service;
 pub mod user_service;
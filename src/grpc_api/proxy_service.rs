@@ -2,7 +2,14 @@ use crate::{
     auth::auth_helpers::get_token_from_md,
     caching::cache::Cache,
     data_backends::storage_backend::StorageBackend,
-    replication::replication_handler::ReplicationMessage,
+    replication::{
+        checksum::ChecksumAlgorithm,
+        chunk_size::ChunkSize,
+        init::{InitReplicationHandler, InitReplicationInfo},
+        integrity::{CustomerEncryptedData, ExpectedHashes},
+        replication_handler::ReplicationMessage,
+        server_copy::{self, CopySource},
+    },
     s3_frontend::utils::replication_sink::ReplicationSink,
     structs::{Object, ObjectLocation, PubKey},
     CONFIG,
@@ -25,9 +32,9 @@ use aruna_rust_api::api::dataproxy::services::v2::{
     ErrorMessage, RetryChunkMessage,
 };
 use aruna_rust_api::api::dataproxy::services::v2::{
-    pull_replication_request::Message, pull_replication_response, ChunkAckMessage, InfoAckMessage,
-    InitMessage, PullReplicationRequest, PullReplicationResponse, PushReplicationRequest,
-    PushReplicationResponse,
+    pull_replication_request::Message, pull_replication_response, ChunkAckMessage, DataInfos,
+    InfoAckMessage, InitMessage, PullReplicationRequest, PullReplicationResponse,
+    PushReplicationRequest, PushReplicationResponse,
 };
 use async_channel::{Receiver, Sender};
 use dashmap::DashMap;
@@ -40,7 +47,7 @@ use std::{str::FromStr, sync::Arc};
 use tokio::{pin, sync::Mutex};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Streaming;
-use tracing::{error, info_span, trace, Instrument};
+use tracing::{debug, error, info_span, trace, Instrument};
 
 #[derive(Clone)]
 pub struct DataproxyReplicationServiceImpl {
@@ -90,6 +97,15 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
             error!(error = "Token not found");
             tonic::Status::unauthenticated("Token not found")
         })?;
+        // Legacy peers do not offer any algorithm and fall back to md5
+        let checksum_algorithm = ChecksumAlgorithm::from_metadata(&metadata);
+        trace!(checksum_algorithm = checksum_algorithm.as_str());
+        // Legacy peers do not offer a chunk size and receive one block per chunk
+        let chunk_size = ChunkSize::from_metadata(&metadata);
+        trace!(chunk_size = chunk_size.bytes());
+        // Backends shared with the pulling proxy, their objects are copied on the backend
+        let shared_backends = server_copy::negotiate(&server_copy::from_metadata(&metadata));
+        trace!(?shared_backends);
 
         // Sends initial Vec<(object, location)> to sync/ack/stream handlers
         let (object_input_send, object_input_rcv) = async_channel::bounded(5);
@@ -97,8 +113,10 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
         let (object_ack_send, object_ack_rcv) = async_channel::bounded(100);
         // Sends the finished ack hash map to the output handler to compare send/ack
         let (object_sync_send, object_sync_rcv) = async_channel::bounded(1);
-        // Handles output stream
-        let (object_output_send, object_output_rcv) = tokio::sync::mpsc::channel(255);
+        // Handles output stream, sized by bytes to bound the buffered chunks
+        let (object_output_send, object_output_rcv) = tokio::sync::mpsc::channel(
+            chunk_size.channel_capacity(CONFIG.proxy.replication_max_inflight_bytes),
+        );
         // Error and retry handling for ArunaStreamReadWriter
         let (retry_send, retry_rcv) = async_channel::bounded(5);
 
@@ -112,7 +130,7 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
         // Recieving loop
         let proxy_replication_service = self.clone();
         let output_sender = object_output_send.clone();
-        tokio::spawn(async move {
+        crate::spawn(async move {
             while let Ok(message) = request.message().await {
                 trace!(?message);
                 match message {
@@ -188,60 +206,37 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
                                                     .cache
                                                     .get_resource_cloned(&ulid, true)
                                                     .await?;
-                                                if let Some(auth) = proxy_replication_service
-                                                    .cache
-                                                    .auth
-                                                    .read()
-                                                    .await
-                                                    .as_ref()
-                                                {
-                                                    // Returns claims.sub as id -> Can return UserIds or DataproxyIds
-                                                    // -> UserIds cannot be found in object.endpoints, so this should be safe
-                                                    let (dataproxy_id, _, _) = auth
-                                                        .check_permissions(&token)
-                                                        .map_err(|_| {
-                                                            error!(
-                                                                error =
-                                                                    "DataProxy not authenticated"
-                                                            );
-                                                            tonic::Status::unauthenticated(
-                                                                "DataProxy not authenticated",
-                                                            )
-                                                        })?;
-                                                    if !object
-                                                        .endpoints
-                                                        .iter()
-                                                        .any(|ep| ep.id == dataproxy_id)
-                                                    {
-                                                        trace!("Endpoint has no permission to replicate object");
-                                                        output_sender
-                                                                .send(Err(
-                                                                    tonic::Status::unauthenticated(
-                                                                        "Access denied",
-                                                                    )
-                                                                ))
-                                                                .await
-                                                                .map_err(|e| {
-                                                                    tracing::error!(error = ?e, msg = e.to_string());
-                                                                    e
-                                                                })?;
-                                                    } else {
-                                                        object_input_send
-                                                                .send(Ok(vec![(
-                                                                    object,
-                                                                    location
-                                                                        .ok_or_else(|| {
-                                                                            error!("No object location found");
-                                                                            anyhow!(
-                                                                    "No object location found"
-                                                                )})?
-                                                                )]))
-                                                                .await
-                                                                .map_err(|e| {
-                                                                    tracing::error!(error = ?e, msg = e.to_string());
-                                                                    e
-                                                                })?;
-                                                    };
+                                                // The token was verified when the stream was opened,
+                                                // DataProxy tokens can only be used once
+                                                if !object.endpoints.iter().any(|ep| ep.id == id) {
+                                                    trace!("Endpoint has no permission to replicate object");
+                                                    output_sender
+                                                            .send(Err(
+                                                                tonic::Status::unauthenticated(
+                                                                    "Access denied",
+                                                                )
+                                                            ))
+                                                            .await
+                                                            .map_err(|e| {
+                                                                tracing::error!(error = ?e, msg = e.to_string());
+                                                                e
+                                                            })?;
+                                                } else {
+                                                    object_input_send
+                                                            .send(Ok(vec![(
+                                                                object,
+                                                                location
+                                                                    .ok_or_else(|| {
+                                                                        error!("No object location found");
+                                                                        anyhow!(
+                                                                "No object location found"
+                                                            )})?
+                                                            )]))
+                                                            .await
+                                                            .map_err(|e| {
+                                                                tracing::error!(error = ?e, msg = e.to_string());
+                                                                e
+                                                            })?;
                                                 }
                                             }
                                         }
@@ -277,7 +272,7 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
         });
 
         // Ack sync loop
-        tokio::spawn(async move {
+        crate::spawn(async move {
             let mut sync_map: HashSet<AckSync> = HashSet::new();
             while let Ok(ref ack_msg) = object_ack_rcv.recv().await {
                 match ack_msg {
@@ -306,7 +301,8 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
         };
 
         let pubkey = pk;
-        tokio::spawn(async move {
+        let copy_backends = shared_backends.clone();
+        crate::spawn(async move {
             loop {
                 match object_input_rcv.recv().await {
                     // Errors
@@ -345,9 +341,42 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
                             }
 
                             trace!(?object, ?location);
+                            if let Some(source) =
+                                CopySource::from_location(&location, &copy_backends)
+                            {
+                                // The receiver copies the data itself, no chunks are sent
+                                stored_objects.insert(object.id, 0);
+                                object_output_send
+                                    .send(Ok(PullReplicationResponse {
+                                        message: Some(pull_replication_response::Message::ObjectInfo(
+                                            aruna_rust_api::api::dataproxy::services::v2::ObjectInfo {
+                                                object_id: object.id.to_string(),
+                                                chunks: 0,
+                                                compressed_size: location.disk_content_len,
+                                                raw_size: location.raw_content_len,
+                                                extra: source.to_extra(),
+                                            },
+                                        )),
+                                    }))
+                                    .await
+                                    .map_err(|e| {
+                                        tracing::error!(error = ?e, msg = e.to_string());
+                                        e
+                                    })?;
+                                debug!(object_id = %object.id, "Announced object for server side copy");
+                                continue;
+                            }
                             // Need to keep track when to create an object, and when to only update the location
                             // Get chunk size from blocklist
-                            let max_blocks = location.count_blocks() + 1;
+                            // Customer encrypted ciphertext is sent as-is, without a footer
+                            let (max_blocks, extra) =
+                                match CustomerEncryptedData::from_location(&location) {
+                                    Some(data) => (data.chunk_count(chunk_size), data.to_extra()),
+                                    None => (
+                                        chunk_size.chunk_count(location.count_blocks()) + 1,
+                                        ExpectedHashes::from_location(&location).to_extra(),
+                                    ),
+                                };
                             trace!(max_blocks);
                             stored_objects.insert(object.id, max_blocks);
                             // Send ObjectInfo into stream
@@ -359,7 +388,7 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
                                             chunks: max_blocks as i64,
                                             compressed_size: location.disk_content_len,
                                             raw_size: location.raw_content_len,
-                                            extra: None,
+                                            extra,
                                         },
                                     )),
                                 }))
@@ -378,6 +407,8 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
                                     location,
                                     object_output_send.clone(),
                                     retry_rcv.clone(),
+                                    checksum_algorithm,
+                                    chunk_size,
                                 )
                                 .await
                                 .map_err(|e| {
@@ -421,9 +452,12 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
             }
         });
 
-        let grpc_response: tonic::Response<
+        let mut grpc_response: tonic::Response<
             ReceiverStream<std::result::Result<PullReplicationResponse, tonic::Status>>,
         > = tonic::Response::new(ReceiverStream::new(object_output_rcv));
+        checksum_algorithm.add_to_metadata(grpc_response.metadata_mut());
+        chunk_size.add_to_metadata(grpc_response.metadata_mut());
+        server_copy::add_to_metadata(&shared_backends, grpc_response.metadata_mut());
         Ok(grpc_response)
     }
 
@@ -431,19 +465,41 @@ impl DataproxyReplicationService for DataproxyReplicationServiceImpl {
     ///
     /// Status: BETA
     ///
-    /// Provides the necessary url to init replication
-    #[tracing::instrument(level = "trace", skip(self, _request))]
+    /// Provides the necessary url to init replication: objects stored on this proxy
+    /// are pulled from it, partial-synced proxies redirect to the origin of the data
+    /// and objects without any known source are omitted, so that the puller can ask
+    /// a different endpoint instead of failing
+    #[tracing::instrument(level = "trace", skip(self, request))]
     async fn push_replication(
         &self,
-        _request: tonic::Request<PushReplicationRequest>,
+        request: tonic::Request<PushReplicationRequest>,
     ) -> Result<tonic::Response<PushReplicationResponse>, tonic::Status> {
-        // TODO
-        // 1. query permissions
-        // 2. validate endpoint that tries sending these
-        // 3. validate if i need these objects
-        // 4. send message to replication handler with DataInfos
-        error!("InitReplication not implemented");
-        Err(tonic::Status::unimplemented("Currently not implemented"))
+        let token = get_token_from_md(request.metadata()).map_err(|_| {
+            error!(error = "Token not found");
+            tonic::Status::unauthenticated("Token not found")
+        })?;
+        let object_ids = request
+            .into_inner()
+            .object_ids
+            .iter()
+            .map(|id| DieselUlid::from_str(id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::invalid_argument("Invalid object id")
+            })?;
+        let infos = InitReplicationHandler::new(self.cache.clone())
+            .init_replication(&token, object_ids)
+            .await
+            .map_err(|e| tonic::Status::unauthenticated(e.to_string()))?;
+        Ok(tonic::Response::new(PushReplicationResponse {
+            data_infos: Some(DataInfos {
+                data_info: infos
+                    .iter()
+                    .filter_map(InitReplicationInfo::to_data_info)
+                    .collect(),
+            }),
+        }))
     }
 }
 
@@ -454,9 +510,7 @@ impl DataproxyReplicationServiceImpl {
     ) -> Result<(DieselUlid, PubKey), tonic::Status> {
         // 2. check if proxy has permissions to pull everything
         if let Some(auth) = self.cache.auth.read().await.as_ref() {
-            // Returns claims.sub as id -> Can return UserIds or DataproxyIds
-            // -> UserIds cannot be found in object.endpoints, so this should be safe
-            let (dataproxy_id, _, pk) = auth.check_permissions(&token).map_err(|_| {
+            let (dataproxy_id, pk) = auth.check_dataproxy_token(token).await.map_err(|_| {
                 error!(error = "DataProxy not authenticated");
                 tonic::Status::unauthenticated("DataProxy not authenticated")
             })?;
@@ -493,6 +547,17 @@ impl DataproxyReplicationServiceImpl {
                     error!("No location found for object");
                     tonic::Status::not_found("No location found for object")
                 })?;
+                // The replication policies of all projects of the object have to allow the endpoint
+                for project_id in self.cache.get_projects(&id).await {
+                    if let Some(policy) = self.cache.get_replication_policy(&project_id) {
+                        if !policy.allows(&endpoint_id) {
+                            error!(?project_id, ?endpoint_id, "Denied by replication policy");
+                            return Err(tonic::Status::permission_denied(
+                                "Replication to this DataProxy is not allowed by the project",
+                            ));
+                        }
+                    }
+                }
                 objects.push((object.clone(), location.clone()));
                 object_endpoint_map.insert(object.id, object.endpoints.clone());
             }
@@ -518,6 +583,8 @@ impl DataproxyReplicationServiceImpl {
         location: ObjectLocation,
         sender: tokio::sync::mpsc::Sender<Result<PullReplicationResponse, tonic::Status>>,
         error_rcv: Receiver<Option<(i64, String)>>, // contains chunk_idx and object_id
+        checksum_algorithm: ChecksumAlgorithm,
+        chunk_size: ChunkSize,
     ) -> Result<()> {
         dbg!("starting send object");
         // Create channel for get_object
@@ -536,7 +603,7 @@ impl DataproxyReplicationServiceImpl {
         dbg!("cloned location");
         // Spawn get_object
         let backend = self.backend.clone();
-        tokio::spawn(
+        crate::spawn(
             async move {
                 backend
                     .get_object(location_clone, None, object_sender)
@@ -551,22 +618,46 @@ impl DataproxyReplicationServiceImpl {
         dbg!("got object");
 
         // Spawn final part
-        let _ = tokio::spawn(
+        let _ = crate::spawn(
             async move {
                 pin!(object_receiver);
-                let mut asrw = GenericStreamReadWriter::new_with_sink(
-                    // Receive get_object
-                    object_receiver,
-                    // ReplicationSink sends into stream via sender
-                    ReplicationSink::new(
+                let customer_data = CustomerEncryptedData::from_location(&location);
+                let sink = match &customer_data {
+                    Some(data) => ReplicationSink::new(
+                        object_id,
+                        data.blocks(),
+                        sender.clone(),
+                        error_rcv,
+                        checksum_algorithm,
+                        chunk_size,
+                    )
+                    .with_total_size(data.disk_content_len as u64),
+                    None => ReplicationSink::new(
                         object_id,
                         location.count_blocks(),
                         sender.clone(),
                         error_rcv,
+                        checksum_algorithm,
+                        chunk_size,
                     ),
+                };
+                let mut asrw = GenericStreamReadWriter::new_with_sink(
+                    // Receive get_object
+                    object_receiver,
+                    // ReplicationSink sends into stream via sender
+                    sink,
                 );
                 asrw = asrw.add_transformer(DebugTransformer::new("Debug replication"));
 
+                if customer_data.is_some() {
+                    // The key is only known to the client, the ciphertext is sent unchanged
+                    asrw.process().await.map_err(|e| {
+                        tracing::error!(error = ?e, msg = e.to_string());
+                        e
+                    })?;
+                    return Ok::<(), anyhow::Error>(());
+                }
+
                 if let Some(key) = location.get_encryption_key() {
                     // Add decryption transformer
                     if !location.is_pithos() {
@@ -0,0 +1,268 @@
+//! Temporary S3 credentials, not part of the Aruna API definitions.
+//!
+//! Messages and server are written out by hand (see `proto/dataproxy_session_service.proto`
+//! for clients), tokens need the same permissions as for `GetCredentials`.
+use crate::{
+    auth::auth_helpers::get_token_from_md, caching::cache::Cache, structs::DbPermissionLevel,
+    CONFIG,
+};
+use aruna_rust_api::api::storage::models::v2::PermissionLevel;
+use diesel_ulid::DieselUlid;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, StdError};
+use tracing::{error, info};
+
+const SERVICE_NAME: &str = "aruna.api.dataproxy.session.v2.DataproxySessionService";
+
+/// Shortest session that can be requested
+const MIN_SESSION_DURATION: u64 = 60;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetSessionTokenRequest {
+    #[prost(uint64, tag = "1")]
+    pub duration_seconds: u64,
+    /// Project, collection or dataset the credentials are restricted to, unrestricted if empty
+    #[prost(string, tag = "2")]
+    pub resource_id: String,
+    /// Permission level on `resource_id`, required for scoped credentials
+    #[prost(enumeration = "PermissionLevel", tag = "3")]
+    pub permission_level: i32,
+}
+
+/// Debug output hides the secret, responses end up in trace logs
+#[derive(Clone, PartialEq, prost::Message)]
+#[prost(skip_debug)]
+pub struct GetSessionTokenResponse {
+    #[prost(string, tag = "1")]
+    pub access_key: String,
+    #[prost(string, tag = "2")]
+    pub secret_key: String,
+    /// Unix timestamp after which the credentials are rejected
+    #[prost(int64, tag = "3")]
+    pub expires_at: i64,
+}
+
+impl std::fmt::Debug for GetSessionTokenResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GetSessionTokenResponse")
+            .field("access_key", &self.access_key)
+            .field("secret_key", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Validates the requested session length against `proxy.max_session_duration`
+fn session_duration(seconds: u64, max: u64) -> Result<Duration, tonic::Status> {
+    if !(MIN_SESSION_DURATION..=max).contains(&seconds) {
+        error!(seconds, max, error = "Invalid session duration");
+        return Err(tonic::Status::invalid_argument(format!(
+            "Session duration must be between {MIN_SESSION_DURATION} and {max} seconds"
+        )));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+impl GetSessionTokenRequest {
+    fn scope(&self) -> Result<Option<(DieselUlid, DbPermissionLevel)>, tonic::Status> {
+        if self.resource_id.is_empty() {
+            return Ok(None);
+        }
+        let resource_id = DieselUlid::from_str(&self.resource_id).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::invalid_argument("Invalid resource id")
+        })?;
+        let level = DbPermissionLevel::from(self.permission_level());
+        if level < DbPermissionLevel::Read {
+            error!(error = "Missing permission level for scoped session");
+            return Err(tonic::Status::invalid_argument(
+                "Scoped sessions need a permission level",
+            ));
+        }
+        Ok(Some((resource_id, level)))
+    }
+}
+
+#[derive(Clone)]
+pub struct DataproxySessionServiceImpl {
+    pub cache: Arc<Cache>,
+}
+
+impl DataproxySessionServiceImpl {
+    #[tracing::instrument(level = "trace", skip(cache))]
+    pub fn new(cache: Arc<Cache>) -> Self {
+        Self { cache }
+    }
+
+    /// Mints a temporary access key / secret pair derived from the credentials of the token
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    pub async fn get_session_token(
+        &self,
+        request: tonic::Request<GetSessionTokenRequest>,
+    ) -> Result<tonic::Response<GetSessionTokenResponse>, tonic::Status> {
+        let (u, tid) = match self.cache.auth.read().await.as_ref() {
+            Some(a) => {
+                let token = get_token_from_md(request.metadata()).map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    tonic::Status::unauthenticated(e.to_string())
+                })?;
+                let (u, tid, pk) = a.check_permissions(&token).await.map_err(|_| {
+                    error!(error = "Unable to authenticate user");
+                    tonic::Status::unauthenticated("Unable to authenticate user")
+                })?;
+                if pk.is_proxy {
+                    error!(error = "Proxy token is not allowed");
+                    return Err(tonic::Status::unauthenticated("Proxy token is not allowed"));
+                }
+                (u, tid)
+            }
+            None => {
+                error!(error = "Unable to authenticate user, cache is empty");
+                return Err(tonic::Status::unauthenticated(
+                    "Unable to authenticate user",
+                ));
+            }
+        };
+
+        let request = request.into_inner();
+        let duration =
+            session_duration(request.duration_seconds, CONFIG.proxy.max_session_duration)?;
+        let scope = request.scope()?;
+
+        let Some(q_handler) = self.cache.aruna_client.read().await.clone() else {
+            error!("query handler not available");
+            return Err(tonic::Status::unavailable(
+                "Server connection not available",
+            ));
+        };
+        let user = q_handler.get_user(u, "".to_string()).await.map_err(|_| {
+            error!(error = "Unable to authenticate user");
+            tonic::Status::unauthenticated("Unable to authenticate user")
+        })?;
+        let parent_key = tid.unwrap_or_else(|| user.id.to_string());
+        self.cache.clone().upsert_user(user).await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::internal("Unable to update user")
+        })?;
+
+        let (access_key, secret_key, expires_at) = self
+            .cache
+            .create_session_key(&parent_key, &u, duration, scope.clone())
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::permission_denied(e.to_string())
+            })?;
+        info!(access_key, ?scope, %expires_at, "Created session credentials");
+
+        Ok(tonic::Response::new(GetSessionTokenResponse {
+            access_key,
+            secret_key,
+            expires_at: expires_at.timestamp(),
+        }))
+    }
+}
+
+/// Routes the session service methods, equivalent to tonic generated servers
+#[derive(Clone)]
+pub struct DataproxySessionServiceServer {
+    inner: Arc<DataproxySessionServiceImpl>,
+}
+
+impl DataproxySessionServiceServer {
+    pub fn new(inner: DataproxySessionServiceImpl) -> Self {
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+struct GetSessionTokenSvc(Arc<DataproxySessionServiceImpl>);
+
+impl tonic::server::UnaryService<GetSessionTokenRequest> for GetSessionTokenSvc {
+    type Response = GetSessionTokenResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetSessionTokenRequest>) -> Self::Future {
+        let inner = self.0.clone();
+        Box::pin(async move { inner.get_session_token(request).await })
+    }
+}
+
+impl<B> tower::Service<http::Request<B>> for DataproxySessionServiceServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let inner = self.inner.clone();
+        let method = req
+            .uri()
+            .path()
+            .strip_prefix(&format!("/{SERVICE_NAME}/"))
+            .map(|m| m.to_string());
+        Box::pin(async move {
+            Ok(match method.as_deref() {
+                Some("GetSessionToken") => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetSessionTokenSvc(inner), req)
+                        .await
+                }
+                _ => tonic::Status::unimplemented("Unknown method").to_http(),
+            })
+        })
+    }
+}
+
+impl tonic::server::NamedService for DataproxySessionServiceServer {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_duration() {
+        assert!(session_duration(59, 3600).is_err());
+        assert!(session_duration(3601, 3600).is_err());
+        assert_eq!(session_duration(60, 3600).unwrap(), Duration::from_secs(60));
+        assert_eq!(
+            session_duration(3600, 3600).unwrap(),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[test]
+    fn test_scope() {
+        let mut request = GetSessionTokenRequest::default();
+        assert_eq!(request.scope().unwrap(), None);
+
+        let id = DieselUlid::generate();
+        request.resource_id = id.to_string();
+        assert!(request.scope().is_err());
+
+        request.permission_level = PermissionLevel::Append as i32;
+        assert_eq!(
+            request.scope().unwrap(),
+            Some((id, DbPermissionLevel::Append))
+        );
+
+        request.resource_id = "not-an-id".to_string();
+        assert!(request.scope().is_err());
+    }
+}
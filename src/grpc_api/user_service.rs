@@ -1,14 +1,21 @@
 use crate::auth::auth_helpers::get_token_from_md;
 use crate::caching::cache::Cache;
+use crate::replication::progress::RequestState;
+use crate::replication::request::{ReplicationRequester, INSUFFICIENT_PERMISSIONS};
+use crate::structs::SyncStatus;
+use crate::CONFIG;
 use aruna_rust_api::api::dataproxy::services::v2::{
-    dataproxy_user_service_server::DataproxyUserService, CreateOrUpdateCredentialsRequest,
-    CreateOrUpdateCredentialsResponse, GetCredentialsRequest, GetCredentialsResponse,
-    PullReplicaRequest, PullReplicaResponse, PushReplicaRequest, PushReplicaResponse,
-    ReplicationStatusRequest, ReplicationStatusResponse, RevokeCredentialsRequest,
-    RevokeCredentialsResponse,
+    dataproxy_user_service_server::DataproxyUserService, pull_replica_request::Resource,
+    CreateOrUpdateCredentialsRequest, CreateOrUpdateCredentialsResponse, GetCredentialsRequest,
+    GetCredentialsResponse, PullReplicaRequest, PullReplicaResponse, PushReplicaRequest,
+    PushReplicaResponse, ReplicationStatus, ReplicationStatusRequest, ReplicationStatusResponse,
+    RevokeCredentialsRequest, RevokeCredentialsResponse,
 };
+use diesel_ulid::DieselUlid;
+use std::str::FromStr;
 use std::sync::Arc;
-use tracing::error;
+use tonic::metadata::MetadataMap;
+use tracing::{error, info, warn};
 
 pub struct DataproxyUserServiceImpl {
     pub cache: Arc<Cache>,
@@ -19,6 +26,99 @@ impl DataproxyUserServiceImpl {
     pub fn new(cache: Arc<Cache>) -> Self {
         Self { cache }
     }
+
+    /// Checks the aruna-token of the request and refreshes the user from the server,
+    /// returns the user id and the access key the token maps to on this proxy
+    #[tracing::instrument(level = "trace", skip(self, metadata))]
+    async fn authenticate(
+        &self,
+        metadata: &MetadataMap,
+    ) -> Result<(DieselUlid, String), tonic::Status> {
+        let (u, tid) = match self.cache.auth.read().await.as_ref() {
+            Some(a) => {
+                let token = get_token_from_md(metadata).map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    tonic::Status::unauthenticated(e.to_string())
+                })?;
+                let (u, tid, pk) = a.check_permissions(&token).await.map_err(|_| {
+                    error!(error = "Unable to authenticate user");
+                    tonic::Status::unauthenticated("Unable to authenticate user")
+                })?;
+                if pk.is_proxy {
+                    error!(error = "Proxy token is not allowed");
+                    return Err(tonic::Status::unauthenticated("Proxy token is not allowed"));
+                }
+                (u, tid)
+            }
+            None => {
+                error!("authentication handler not available");
+                return Err(tonic::Status::unauthenticated(
+                    "Unable to authenticate user",
+                ));
+            }
+        };
+
+        let Some(q_handler) = self.cache.aruna_client.read().await.clone() else {
+            error!("query handler not available");
+            return Err(tonic::Status::unauthenticated(
+                "Unable to authenticate user",
+            ));
+        };
+        let user = q_handler.get_user(u, "".to_string()).await.map_err(|_| {
+            error!(error = "Unable to authenticate user, get user grpc");
+            tonic::Status::unauthenticated("Unable to authenticate user")
+        })?;
+        let access_key = tid.unwrap_or_else(|| user.id.to_string());
+        // Secrets are derived from the current permissions of the user
+        self.cache.clone().upsert_user(user).await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::internal("Unable to update user")
+        })?;
+        Ok((u, access_key))
+    }
+
+    /// Endpoint the replica is pulled from, preferably one that holds the resource
+    /// completely
+    async fn source_endpoint(&self, resource_id: &DieselUlid) -> Result<DieselUlid, tonic::Status> {
+        let (resource, _) = self
+            .cache
+            .get_resource_cloned(resource_id, true)
+            .await
+            .map_err(|_| {
+                error!(?resource_id, "Resource not found");
+                tonic::Status::not_found("Resource not found")
+            })?;
+        let others = resource
+            .endpoints
+            .iter()
+            .filter(|ep| ep.id != CONFIG.proxy.endpoint_id && ep.status != Some(SyncStatus::Error))
+            .collect::<Vec<_>>();
+        others
+            .iter()
+            .find(|ep| ep.status == Some(SyncStatus::Finished))
+            .or_else(|| others.first())
+            .map(|ep| ep.id)
+            .ok_or_else(|| {
+                error!(?resource_id, "No other endpoint holds the resource");
+                tonic::Status::failed_precondition("No other endpoint holds the resource")
+            })
+    }
+
+    /// Creates (or rotates) the secret of an access key
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn create_secret(
+        &self,
+        access_key: &str,
+        user_id: &DieselUlid,
+    ) -> Result<(String, String), tonic::Status> {
+        self.cache
+            .create_or_update_secret(access_key, user_id)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = "Unable to create or update secret");
+                tonic::Status::unauthenticated("Unable to authenticate user")
+            })
+    }
 }
 
 #[tonic::async_trait]
@@ -29,178 +129,69 @@ impl DataproxyUserService for DataproxyUserServiceImpl {
     /// Status: BETA
     ///
     /// Authorized method that needs a aruna-token to exchange for dataproxy
-    /// specific S3AccessKey and S3SecretKey
-
-    // TODO: UPDATE to two requests one for get and one for create
+    /// specific S3AccessKey and S3SecretKey, credentials are created if absent
     async fn get_credentials(
         &self,
         request: tonic::Request<GetCredentialsRequest>,
     ) -> Result<tonic::Response<GetCredentialsResponse>, tonic::Status> {
-        return if let Some(a) = self.cache.auth.read().await.as_ref() {
-            let token = get_token_from_md(request.metadata()).map_err(|e| {
-                error!(error = ?e, msg = e.to_string());
-                tonic::Status::unauthenticated(e.to_string())
-            })?;
-
-            let (u, tid, pk) = a.check_permissions(&token).map_err(|_| {
-                error!(error = "Unable to authenticate user");
-                tonic::Status::unauthenticated("Unable to authenticate user")
-            })?;
-
-            if pk.is_proxy {
-                error!(error = "Proxy token is not allowed");
-                return Err(tonic::Status::unauthenticated("Proxy token is not allowed"));
-            }
-
-            if let Some(q_handler) = self.cache.aruna_client.read().await.as_ref() {
-                let user = q_handler.get_user(u, "".to_string()).await.map_err(|_| {
-                    error!(error = "Unable to authenticate user");
-                    tonic::Status::unauthenticated("Unable to authenticate user")
-                })?;
+        let (user_id, access_key) = self.authenticate(request.metadata()).await?;
 
-                let access_key = tid.unwrap_or_else(|| user.id.to_string());
-                let secret_key =
-                    self.cache
-                        .clone()
-                        .get_secret(&access_key)
-                        .await
-                        .map_err(|_| {
-                            error!(error = "Unable to authenticate user");
-                            tonic::Status::unauthenticated("Unable to authenticate user")
-                        })?;
-
-                Ok(tonic::Response::new(GetCredentialsResponse {
-                    access_key,
-                    secret_key: secret_key.expose().to_string(),
-                }))
-            } else {
-                error!("query handler not available");
-                Err(tonic::Status::unauthenticated(
-                    "Unable to authenticate user",
-                ))
+        let (access_key, secret_key) = match self.cache.get_secret(&access_key).await {
+            Ok(secret) => (access_key, secret.expose().to_string()),
+            Err(_) => {
+                info!(access_key, "Creating missing credentials");
+                self.create_secret(&access_key, &user_id).await?
             }
-        } else {
-            error!("authentication handler not available");
-            Err(tonic::Status::unauthenticated(
-                "Unable to authenticate user",
-            ))
         };
+
+        Ok(tonic::Response::new(GetCredentialsResponse {
+            access_key,
+            secret_key,
+        }))
     }
 
+    #[tracing::instrument(level = "trace", skip(self, request))]
     /// RevokeCredentials
     ///
     /// Status: BETA
     ///
     /// Authorized method that needs a aruna-token
-    /// Revokes the current credentials
+    /// Revokes the current credentials, S3 requests signed with them fail immediately
     async fn revoke_credentials(
         &self,
         request: tonic::Request<RevokeCredentialsRequest>,
     ) -> std::result::Result<tonic::Response<RevokeCredentialsResponse>, tonic::Status> {
-        return if let Some(a) = self.cache.auth.read().await.as_ref() {
-            let token = get_token_from_md(request.metadata()).map_err(|e| {
-                error!(error = ?e, msg = e.to_string());
-                tonic::Status::unauthenticated(e.to_string())
-            })?;
+        let (_, access_key) = self.authenticate(request.metadata()).await?;
 
-            let (u, tid, pk) = a.check_permissions(&token).map_err(|_| {
-                error!(error = "Unable to authenticate user");
-                tonic::Status::unauthenticated("Unable to authenticate user")
-            })?;
+        self.cache.revoke_secret(&access_key).await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::internal("Unable to revoke credentials")
+        })?;
+        info!(access_key, "Revoked credentials");
 
-            if pk.is_proxy {
-                error!(error = "Proxy token is not allowed");
-                return Err(tonic::Status::unauthenticated("Proxy token is not allowed"));
-            }
-
-            if let Some(q_handler) = self.cache.aruna_client.read().await.as_ref() {
-                let user = q_handler.get_user(u, "".to_string()).await.map_err(|_| {
-                    error!(error = "Unable to authenticate user");
-                    tonic::Status::unauthenticated("Unable to authenticate user")
-                })?;
-
-                let access_key = tid.unwrap_or_else(|| user.id.to_string());
-
-                self.cache.revoke_secret(&access_key).await.map_err(|_| {
-                    error!(error = "Unable to authenticate user");
-                    tonic::Status::unauthenticated("Unable to authenticate user")
-                })?;
-
-                Ok(tonic::Response::new(RevokeCredentialsResponse {}))
-            } else {
-                error!("query handler not available");
-                Err(tonic::Status::unauthenticated(
-                    "Unable to authenticate user",
-                ))
-            }
-        } else {
-            error!("authentication handler not available");
-            Err(tonic::Status::unauthenticated(
-                "Unable to authenticate user",
-            ))
-        };
+        Ok(tonic::Response::new(RevokeCredentialsResponse {}))
     }
 
+    #[tracing::instrument(level = "trace", skip(self, request))]
     /// CreateOrUpdateCredentials
     ///
     /// Status: BETA
     ///
     /// Authorized method that needs a aruna-token to exchange for dataproxy
-    /// specific S3AccessKey and S3SecretKey
+    /// specific S3AccessKey and S3SecretKey, existing secrets are rotated
     async fn create_or_update_credentials(
         &self,
         request: tonic::Request<CreateOrUpdateCredentialsRequest>,
     ) -> std::result::Result<tonic::Response<CreateOrUpdateCredentialsResponse>, tonic::Status>
     {
-        return if let Some(a) = self.cache.auth.read().await.as_ref() {
-            let token = get_token_from_md(request.metadata()).map_err(|e| {
-                error!(error = ?e, msg = e.to_string());
-                tonic::Status::unauthenticated(e.to_string())
-            })?;
+        let (user_id, access_key) = self.authenticate(request.metadata()).await?;
 
-            let (u, tid, pk) = a.check_permissions(&token).map_err(|_| {
-                error!(error = "Unable to authenticate user, check permissions");
-                tonic::Status::unauthenticated("Unable to authenticate user")
-            })?;
-
-            if pk.is_proxy {
-                error!(error = "Proxy token is not allowed");
-                return Err(tonic::Status::unauthenticated("Proxy token is not allowed"));
-            }
-
-            if let Some(q_handler) = self.cache.aruna_client.read().await.as_ref() {
-                let user = q_handler.get_user(u, "".to_string()).await.map_err(|_| {
-                    error!(error = "Unable to authenticate user, get user grpc");
-                    tonic::Status::unauthenticated("Unable to authenticate user")
-                })?;
+        let (access_key, secret_key) = self.create_secret(&access_key, &user_id).await?;
 
-                let access_key = tid.unwrap_or_else(|| user.id.to_string());
-
-                let (access, secret) = self
-                    .cache
-                    .create_or_update_secret(&access_key, &u)
-                    .await
-                    .map_err(|_| {
-                        error!(error = "Unable to authenticate user, create or update secret grpc");
-                        tonic::Status::unauthenticated("Unable to authenticate user")
-                    })?;
-
-                Ok(tonic::Response::new(CreateOrUpdateCredentialsResponse {
-                    access_key: access,
-                    secret_key: secret,
-                }))
-            } else {
-                error!("query handler not available");
-                Err(tonic::Status::unauthenticated(
-                    "Unable to authenticate user",
-                ))
-            }
-        } else {
-            error!("authentication handler not available");
-            Err(tonic::Status::unauthenticated(
-                "Unable to authenticate user",
-            ))
-        };
+        Ok(tonic::Response::new(CreateOrUpdateCredentialsResponse {
+            access_key,
+            secret_key,
+        }))
     }
 
     #[tracing::instrument(level = "trace", skip(self, _request))]
@@ -217,31 +208,115 @@ impl DataproxyUserService for DataproxyUserServiceImpl {
         Err(tonic::Status::unimplemented("Not implemented"))
     }
 
-    #[tracing::instrument(level = "trace", skip(self, _request))]
+    #[tracing::instrument(level = "trace", skip(self, request))]
     /// PullReplica
     ///
     /// Status: BETA
     ///
-    /// Manually request data to be transferred to this data-proxy
+    /// Manually request data to be transferred to this data-proxy, the caller needs
+    /// WRITE permissions on the resource
     async fn pull_replica(
         &self,
-        _request: tonic::Request<PullReplicaRequest>,
+        request: tonic::Request<PullReplicaRequest>,
     ) -> Result<tonic::Response<PullReplicaResponse>, tonic::Status> {
-        error!("PullReplica not implemented");
-        Err(tonic::Status::unimplemented("Not implemented"))
+        let (user_id, _) = self.authenticate(request.metadata()).await?;
+        let token = get_token_from_md(request.metadata()).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::unauthenticated(e.to_string())
+        })?;
+        let resource_id = match request.into_inner().resource {
+            Some(Resource::ResourceId(id)) => DieselUlid::from_str(&id).map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::invalid_argument("Invalid resource id")
+            })?,
+            Some(Resource::S3Path(path)) => self
+                .cache
+                .get_path(path.trim_start_matches("s3://").trim_end_matches('/'))
+                .ok_or_else(|| {
+                    error!(%path, "Path not found");
+                    tonic::Status::not_found("Path not found")
+                })?,
+            None => {
+                error!(error = "No resource provided");
+                return Err(tonic::Status::invalid_argument("No resource provided"));
+            }
+        };
+        let endpoint_id = self.source_endpoint(&resource_id).await?;
+
+        let result = ReplicationRequester::new(self.cache.clone())
+            .request_replication(&token, endpoint_id, vec![resource_id])
+            .await
+            .map_err(|e| tonic::Status::unauthenticated(e.to_string()))?;
+        if result.is_denied() {
+            error!(?resource_id, "Insufficient permissions to pull replica");
+            return Err(tonic::Status::permission_denied(INSUFFICIENT_PERMISSIONS));
+        }
+        for (id, reason) in &result.rejected {
+            warn!(object_id = ?id, %reason, "Object is not replicated");
+        }
+        let objects = result.replicated();
+        if objects.is_empty() {
+            let reasons = result
+                .rejected
+                .iter()
+                .map(|(id, reason)| format!("{id}: {reason}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(tonic::Status::failed_precondition(format!(
+                "Nothing to replicate: {reasons}"
+            )));
+        }
+        let replication_id =
+            self.cache
+                .replication_progress
+                .track_request(user_id, endpoint_id, objects);
+        info!(
+            ?replication_id,
+            ?resource_id,
+            ?endpoint_id,
+            "Requested replica"
+        );
+
+        Ok(tonic::Response::new(PullReplicaResponse {
+            replication_id: replication_id.to_string(),
+        }))
     }
 
-    #[tracing::instrument(level = "trace", skip(self, _request))]
-    /// PullReplica
+    #[tracing::instrument(level = "trace", skip(self, request))]
+    /// ReplicationStatus
     ///
     /// Status: BETA
     ///
     /// Status of the previous replication request
     async fn replication_status(
         &self,
-        _request: tonic::Request<ReplicationStatusRequest>,
+        request: tonic::Request<ReplicationStatusRequest>,
     ) -> Result<tonic::Response<ReplicationStatusResponse>, tonic::Status> {
-        error!("ReplicationStatus not implemented");
-        Err(tonic::Status::unimplemented("Not implemented"))
+        let (user_id, _) = self.authenticate(request.metadata()).await?;
+        let replication_id =
+            DieselUlid::from_str(&request.into_inner().replication_id).map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                tonic::Status::invalid_argument("Invalid replication id")
+            })?;
+        let state = self
+            .cache
+            .replication_progress
+            .request_state(&replication_id, &user_id)
+            .ok_or_else(|| {
+                error!(?replication_id, "Replication not found");
+                tonic::Status::not_found("Replication not found")
+            })?;
+        let status = match state {
+            RequestState::Pending => ReplicationStatus::Pending,
+            RequestState::Running => ReplicationStatus::Running,
+            RequestState::Finished => ReplicationStatus::Finished,
+            RequestState::Error(e) => {
+                error!(?replication_id, error = e, "Replication failed");
+                ReplicationStatus::Error
+            }
+        };
+        Ok(tonic::Response::new(ReplicationStatusResponse {
+            status: status as i32,
+        }))
     }
 }
@@ -148,6 +148,33 @@ pub fn is_method_read(method: &Method) -> bool {
     }
 }
 
+/// Splits a virtual-hosted (`bucket.base_domain/key`) or path style (`/bucket/key`)
+/// request into bucket and key, both are None if not present
+pub fn split_s3_path<B>(
+    req: &http::Request<B>,
+    base_domain: &str,
+) -> (Option<String>, Option<String>) {
+    let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+    let path = req.uri().path().trim_start_matches('/');
+    let host = req
+        .headers()
+        .get(http::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+    // The base domain may or may not contain the port
+    let virtual_bucket = [host, host.split(':').next().unwrap_or(host)]
+        .into_iter()
+        .find_map(|host| host.strip_suffix(base_domain)?.strip_suffix('.'))
+        .and_then(non_empty);
+    match virtual_bucket {
+        Some(bucket) => (Some(bucket), non_empty(path)),
+        None => match path.split_once('/') {
+            Some((bucket, key)) => (non_empty(bucket), non_empty(key)),
+            None => (non_empty(path), None),
+        },
+    }
+}
+
 pub fn random_string(len: usize) -> String {
     use rand::distributions::Alphanumeric;
     use rand::thread_rng;
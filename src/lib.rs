@@ -0,0 +1,95 @@
+//! Aruna DataProxy
+//!
+//! The dataproxy can be used as standalone binary or embedded into other services
+//! via the [`DataProxyBuilder`]. Every dataproxy runs with its own config, multiple
+//! ones can be embedded into the same process:
+//!
+//! ```
+//! use aos_data_proxy::{Config, DataProxyBuilder};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! # let root = std::env::temp_dir().join("dataproxy_doc");
+//! let config: Config = toml::from_str(&format!(
+//!     r#"
+//!     rules = []
+//!
+//!     [proxy]
+//!     endpoint_id = "01H81W0ZMB54YEP5711Q2BK46V"
+//!     private_key = "MC4CAQAwBQYDK2VwBCIEIM/FI+bYw+auSKGyGqeISRIEjofvZV/lbK7QL1wkuCey"
+//!     public_key = "MCowBQYDK2VwAyEAnouQBh4GHPCD/k85VIzPyCdOijVg2qlzt2TELwTMy4c="
+//!     serial = 1337
+//!     enable_ingest = false
+//!     admin_ids = []
+//!     grpc_server = "127.0.0.1:0"
+//!     remote_synced = false
+//!
+//!     [backend.filesystem]
+//!     root_path = "{}"
+//!     encryption = false
+//!     compression = false
+//!     backend_scheme = "s3://{{{{PROJECT_NAME}}}}/{{{{OBJECT_NAME}}}}"
+//!     "#,
+//!     root.display()
+//! ))?;
+//!
+//! # tokio::runtime::Runtime::new()?.block_on(async {
+//! let proxy = DataProxyBuilder::new()
+//!     .config(config)
+//!     // Optional: .backend(Box::new(MyBackend::new()))
+//!     .build()
+//!     .await?;
+//!
+//! // Either run everything at once with `proxy.run()` (stops on SIGTERM/SIGINT) ...
+//! // ... or drive the individual tasks on a caller provided runtime
+//! let shutdown = proxy.shutdown();
+//! let tasks = proxy.into_tasks();
+//! tokio::spawn(tasks.background);
+//! if let Some(s3) = tasks.s3 {
+//!     tokio::spawn(s3);
+//! }
+//! // Stops all tasks gracefully, e.g. when the embedding service shuts down
+//! shutdown.trigger();
+//! tasks.grpc.await?;
+//! # Ok(())
+//! # })
+//! # }
+//! ```
+mod auth;
+mod builder;
+mod bundler;
+mod caching;
+pub(crate) mod config;
+pub(crate) mod data_backends;
+mod database;
+pub mod error;
+mod grpc_api;
+mod helpers;
+#[macro_use]
+mod macros;
+mod maintenance;
+mod metrics;
+mod replication;
+mod request_id;
+mod s3_frontend;
+mod scoped_config;
+mod shutdown;
+pub(crate) mod structs;
+pub mod telemetry;
+
+pub use builder::{DataProxy, DataProxyBuilder, DataProxyTasks};
+pub use caching::cache::Cache;
+pub use config::{Config, RuleTarget, StoragePolicy};
+pub use data_backends::memory_backend::{BackendOperation, MemoryBackend};
+pub use data_backends::storage_backend::StorageBackend;
+pub use maintenance::backend_migration::{BackendMigrationHandler, BackendMigrationJob};
+pub use maintenance::key_rotation::{
+    KeyRotationHandler, KeyRotationJob, RotationProgress, RotationStatus,
+};
+pub use maintenance::shard_migration::{MigrationProgress, MigrationStatus, ShardMigrationHandler};
+pub use replication::init::InitReplicationHandler;
+pub use replication::progress::ReplicationProgress;
+pub use replication::request::ReplicationRequester;
+pub use shutdown::Shutdown;
+pub use structs::{Object, ObjectLocation, ObjectType, PartETag, TypedRelation};
+
+pub(crate) use scoped_config::{spawn, CONFIG};
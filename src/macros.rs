@@ -7,3 +7,15 @@ macro_rules! required {
         }
     };
 }
+
+/// Classifies an error as `ProxyError` with the failed operation and resource,
+/// e.g. `.map_err(trace_err!("create_collection", path))?`
+#[macro_export]
+macro_rules! trace_err {
+    ($operation:expr) => {
+        |e| $crate::error::ProxyError::traced(e, $operation, None)
+    };
+    ($operation:expr, $resource:expr) => {
+        |e| $crate::error::ProxyError::traced(e, $operation, Some($resource.to_string()))
+    };
+}
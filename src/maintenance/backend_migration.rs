@@ -0,0 +1,254 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::maintenance::shard_migration::MigrationStatus;
+use crate::maintenance::{collect_objects, copy_location};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+use tracing::{debug, error, info, info_span, trace, Instrument};
+
+/// Number of failures kept per job for the status
+pub(crate) const MAX_JOB_ERRORS: usize = 20;
+
+/// Progress of a location migration below a resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendMigrationJob {
+    pub resource_id: DieselUlid,
+    /// Target backend, None for the main backend
+    pub backend: Option<String>,
+    pub total: usize,
+    pub migrated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub running: bool,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// Most recent failures as (object id, error)
+    pub errors: Vec<(DieselUlid, String)>,
+}
+
+impl BackendMigrationJob {
+    fn new(resource_id: DieselUlid, backend: Option<String>) -> Self {
+        BackendMigrationJob {
+            resource_id,
+            backend,
+            total: 0,
+            migrated: 0,
+            skipped: 0,
+            failed: 0,
+            running: true,
+            started_at: Utc::now().timestamp(),
+            finished_at: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, object_id: DieselUlid, status: MigrationStatus) {
+        match status {
+            MigrationStatus::Migrated => self.migrated += 1,
+            MigrationStatus::Skipped(_) => self.skipped += 1,
+            MigrationStatus::Failed(e) => {
+                self.failed += 1;
+                if self.errors.len() >= MAX_JOB_ERRORS {
+                    self.errors.remove(0);
+                }
+                self.errors.push((object_id, e));
+            }
+        }
+    }
+}
+
+/// Moves stored locations to another (named) storage backend, e.g. to re-tier
+/// finished projects to cheaper storage
+///
+/// The stored bytes are copied unchanged, encryption keys and footers of the location
+/// stay valid. Reads are served from the old location until the copy is complete and
+/// verified, the location is swapped afterwards and the old backend object is deleted.
+/// Locations that are already on the target backend are skipped, a job can be
+/// restarted after a crash: a crash before the swap leaves the old location untouched,
+/// a crash after it at most leaks the old backend object.
+pub struct BackendMigrationHandler {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+    jobs: DashMap<DieselUlid, BackendMigrationJob>,
+}
+
+impl BackendMigrationHandler {
+    pub fn new(cache: Arc<Cache>, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        Self {
+            cache,
+            backend,
+            jobs: DashMap::new(),
+        }
+    }
+
+    /// Starts the migration of the object or all objects below the given resource,
+    /// fails if a migration of the resource is already running
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn start(self: &Arc<Self>, resource_id: DieselUlid, backend: Option<String>) -> Result<()> {
+        let job = BackendMigrationJob::new(resource_id, backend.clone());
+        match self.jobs.entry(resource_id) {
+            Entry::Occupied(entry) if entry.get().running => {
+                error!(?resource_id, "Migration is already running");
+                bail!("Migration of {resource_id} is already running")
+            }
+            Entry::Occupied(mut entry) => {
+                entry.insert(job);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(job);
+            }
+        }
+        let handler = self.clone();
+        crate::spawn(
+            async move { handler.migrate_all(resource_id, backend).await }
+                .instrument(info_span!("migrate_backend")),
+        );
+        Ok(())
+    }
+
+    /// Status of all jobs since the start of the proxy
+    pub fn jobs(&self) -> Vec<BackendMigrationJob> {
+        let mut jobs = self
+            .jobs
+            .iter()
+            .map(|job| job.value().clone())
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| job.started_at);
+        jobs
+    }
+
+    async fn migrate_all(&self, resource_id: DieselUlid, backend: Option<String>) {
+        let objects = collect_objects(&self.cache, resource_id).await;
+        if let Some(mut job) = self.jobs.get_mut(&resource_id) {
+            job.total = objects.len();
+        }
+        for object_id in objects {
+            let status = match self.migrate_object(object_id, backend.as_deref()).await {
+                Ok(status) => status,
+                Err(e) => {
+                    error!(error = ?e, ?object_id, msg = "Backend migration failed");
+                    MigrationStatus::Failed(e.to_string())
+                }
+            };
+            if let Some(mut job) = self.jobs.get_mut(&resource_id) {
+                job.record(object_id, status);
+            }
+        }
+        if let Some(mut job) = self.jobs.get_mut(&resource_id) {
+            job.running = false;
+            job.finished_at = Some(Utc::now().timestamp());
+            info!(
+                ?resource_id,
+                migrated = job.migrated,
+                skipped = job.skipped,
+                failed = job.failed,
+                "Backend migration finished"
+            );
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn migrate_object(
+        &self,
+        object_id: DieselUlid,
+        backend: Option<&str>,
+    ) -> Result<MigrationStatus> {
+        let Some(old_location) = self.cache.get_location(&object_id).await else {
+            return Ok(MigrationStatus::Skipped("No location".to_string()));
+        };
+        if old_location.backend.as_deref() == backend {
+            return Ok(MigrationStatus::Skipped(
+                "Location is already on the target backend".to_string(),
+            ));
+        }
+        if old_location.is_temporary || old_location.upload_id.is_some() {
+            return Ok(MigrationStatus::Skipped(
+                "Location is not finalized".to_string(),
+            ));
+        }
+        if old_location.ref_count > 1 {
+            return Ok(MigrationStatus::Skipped("Location is shared".to_string()));
+        }
+        if old_location.corrupt {
+            return Ok(MigrationStatus::Skipped("Location is corrupt".to_string()));
+        }
+
+        let (object, _) = self.cache.get_resource_cloned(&object_id, true).await?;
+        let parents = self.cache.get_single_parent(&object_id).await?;
+        let storage = self.cache.get_storage_policy(&parents);
+        let target = self
+            .backend
+            .initialize_location_on(backend, &object, None, parents, storage.as_deref(), false)
+            .await?;
+        if let Err(e) = self.backend.create_location_bucket(&target).await {
+            debug!(error = ?e, msg = "Backing bucket not created, it may already exist");
+        }
+
+        // Only the storage position changes, the stored bytes are kept as they are
+        let mut new_location = old_location.clone();
+        new_location.bucket = target.bucket;
+        new_location.key = target.key;
+        new_location.sharding = target.sharding;
+        new_location.backend = target.backend;
+        new_location.storage_class = target.storage_class;
+        new_location.restore = None;
+
+        trace!(?old_location, ?new_location, "Migrating location");
+
+        if let Err(e) = copy_location(&self.backend, &old_location, &new_location).await {
+            // Remove partially written data, the old location is untouched
+            let _ = self.backend.delete_object(new_location).await;
+            return Err(e);
+        }
+
+        // The object may have been overwritten while its data was copied
+        let current = self.cache.get_location(&object_id).await;
+        if current.as_ref() != Some(&old_location) {
+            let _ = self.backend.delete_object(new_location).await;
+            return Ok(MigrationStatus::Skipped(
+                "Location changed during the migration".to_string(),
+            ));
+        }
+
+        // Same location id -> single upsert of the location row
+        self.cache
+            .update_location(object_id, new_location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+        if let Err(e) = self.backend.delete_object(old_location).await {
+            // Data is already migrated, only the old backend object is leaked
+            error!(error = ?e, ?object_id, msg = "Unable to delete old location");
+        }
+        debug!(?object_id, ?backend, "Migrated location to backend");
+        Ok(MigrationStatus::Migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_record() {
+        let mut job = BackendMigrationJob::new(DieselUlid::generate(), Some("cold".to_string()));
+        let object_id = DieselUlid::generate();
+        job.record(object_id, MigrationStatus::Migrated);
+        job.record(object_id, MigrationStatus::Skipped("shared".to_string()));
+        for idx in 0..MAX_JOB_ERRORS + 5 {
+            job.record(object_id, MigrationStatus::Failed(format!("error {idx}")));
+        }
+        assert_eq!((job.migrated, job.skipped), (1, 1));
+        assert_eq!(job.failed, MAX_JOB_ERRORS + 5);
+        assert_eq!(job.errors.len(), MAX_JOB_ERRORS);
+        assert_eq!(job.errors[0].1, "error 5");
+        assert!(job.running);
+    }
+}
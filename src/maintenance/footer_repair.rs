@@ -0,0 +1,466 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::helpers::random_string;
+use crate::metrics::FOOTER_REPAIRS;
+use crate::s3_frontend::utils::buffered_s3_sink::BufferedS3Sink;
+use crate::shutdown::Shutdown;
+use crate::structs::ObjectLocation;
+use crate::CONFIG;
+use anyhow::{anyhow, bail, Result};
+use bytes::{BufMut, BytesMut};
+use chrono::Utc;
+use diesel_ulid::DieselUlid;
+use futures_util::StreamExt;
+use pithos_lib::helpers::footer_parser::{Footer, FooterParser};
+use pithos_lib::helpers::notifications::Message;
+use pithos_lib::streamreadwrite::GenericStreamReadWriter;
+use pithos_lib::transformer::ReadWriter;
+use pithos_lib::transformers::decrypt::ChaCha20Dec;
+use pithos_lib::transformers::footer::FooterGenerator;
+use pithos_lib::transformers::hashing_transformer::HashingTransformer;
+use pithos_lib::transformers::pithos_comp_enc::PithosTransformer;
+use pithos_lib::transformers::size_probe::SizeProbe;
+use pithos_lib::transformers::zstd_decomp::ZstdDec;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::pin;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+/// The footer of a pithos file is contained in its last two encrypted chunks
+pub const FOOTER_READ_SIZE: usize = (65536 + 28) * 2;
+/// Size of the end-of-file metadata that closes every pithos file
+pub const EOF_METADATA_LEN: u64 = 73;
+
+/// Length of the encrypted data section of a pithos file, without table of contents,
+/// encryption metadata and end-of-file metadata
+pub fn data_section_len(footer: &Footer) -> u64 {
+    footer.eof_metadata.disk_file_size
+        - footer.eof_metadata.toc_len
+        - footer.eof_metadata.encryption_len
+        - EOF_METADATA_LEN
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepairStatus {
+    /// Disk size and hash were reconstructed, the stored data is unchanged
+    Repaired,
+    /// The data was rewritten with a footer
+    Rewritten,
+    Skipped(String),
+}
+
+impl RepairStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RepairStatus::Repaired => "repaired",
+            RepairStatus::Rewritten => "rewritten",
+            RepairStatus::Skipped(_) => "skipped",
+        }
+    }
+}
+
+/// Reads and parses the footer of a pithos location with the key of this proxy
+#[tracing::instrument(level = "trace", skip(backend, location))]
+pub async fn read_footer(
+    backend: &Arc<Box<dyn StorageBackend>>,
+    location: &ObjectLocation,
+) -> Result<Footer> {
+    let (footer_sender, footer_receiver) = async_channel::bounded(1000);
+    pin!(footer_receiver);
+    backend
+        .get_object(
+            location.clone(),
+            Some(format!("bytes=-{FOOTER_READ_SIZE}")),
+            footer_sender,
+        )
+        .await
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+    let mut output = BytesMut::with_capacity(FOOTER_READ_SIZE);
+    while let Ok(Ok(bytes)) = footer_receiver.recv().await {
+        output.put(bytes);
+    }
+
+    let key = CONFIG.proxy.get_private_key_x25519()?;
+    let parser = FooterParser::new(&output)
+        .map_err(|e| {
+            error!(error = ?e, msg = "Invalid footer");
+            e
+        })?
+        .add_recipient(&key)
+        .parse()
+        .map_err(|e| {
+            error!(error = ?e, msg = "Unable to parse footer");
+            e
+        })?;
+    parser.try_into().map_err(|_| {
+        error!(error = "Unable to convert footer");
+        anyhow!("Unable to convert footer")
+    })
+}
+
+/// Reconstructs the footers and disk metadata of locations written before footers
+/// were generated
+///
+/// Locations with a missing disk size or hash are streamed once and the calculated
+/// values are stored, the data itself is not touched. Pithos locations without a
+/// readable footer are decoded and rewritten with a footer into a new backend key,
+/// the location is swapped after the rewritten data was verified against the raw
+/// hash. Locations with a valid footer and complete metadata are skipped, the repair
+/// can be repeated any time. Reads use the repaired location immediately.
+pub struct FooterRepairHandler {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+}
+
+impl FooterRepairHandler {
+    pub fn new(cache: Arc<Cache>, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        Self { cache, backend }
+    }
+
+    /// Walks all locations in id order at `footer_repair.objects_per_hour`
+    #[tracing::instrument(level = "trace", skip(self, shutdown))]
+    pub async fn run(self, shutdown: Shutdown) -> Result<()> {
+        let Some(config) = CONFIG.footer_repair.clone() else {
+            return Ok(());
+        };
+        let interval = Duration::from_secs_f64(3600.0 / config.objects_per_hour as f64);
+        let mut last_id: Option<DieselUlid> = None;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = shutdown.signaled() => {
+                    trace!("footer repair stopped");
+                    return Ok(());
+                }
+            }
+            // The next object after the last checked one, wraps around at the end
+            let Some(object_id) = self
+                .cache
+                .get_resource_ids()
+                .into_iter()
+                .find(|id| last_id.map(|last| *id > last).unwrap_or(true))
+            else {
+                last_id = None;
+                continue;
+            };
+            last_id = Some(object_id);
+
+            let result = tokio::select! {
+                result = self.repair_object(object_id) => result,
+                _ = shutdown.signaled() => {
+                    trace!("footer repair stopped");
+                    return Ok(());
+                }
+            };
+            match result {
+                Ok(RepairStatus::Skipped(reason)) => {
+                    FOOTER_REPAIRS.with_label_values(&["skipped"]).inc();
+                    trace!(?object_id, reason, "Location not repaired");
+                }
+                Ok(status) => {
+                    FOOTER_REPAIRS.with_label_values(&[status.label()]).inc();
+                    info!(?object_id, status = status.label(), "Repaired location");
+                }
+                Err(e) => {
+                    FOOTER_REPAIRS.with_label_values(&["failed"]).inc();
+                    warn!(error = ?e, ?object_id, msg = "Unable to repair location");
+                }
+            }
+        }
+    }
+
+    /// Repairs the location of a single object if it lacks a footer or disk metadata
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn repair_object(&self, object_id: DieselUlid) -> Result<RepairStatus> {
+        let Some(location) = self.cache.get_location(&object_id).await else {
+            return Ok(RepairStatus::Skipped("No location".to_string()));
+        };
+        if location.is_temporary || location.upload_id.is_some() {
+            return Ok(RepairStatus::Skipped(
+                "Location is not finalized".to_string(),
+            ));
+        }
+        if location.corrupt {
+            return Ok(RepairStatus::Skipped("Location is corrupt".to_string()));
+        }
+        if location.is_customer_encrypted() {
+            // Customer encrypted data never has a footer
+            return Ok(RepairStatus::Skipped("Customer provided key".to_string()));
+        }
+        if location.is_archived(Utc::now()) {
+            return Ok(RepairStatus::Skipped("Location is archived".to_string()));
+        }
+        if location.raw_content_len == 0 {
+            // Empty objects are stored without footer
+            return Ok(RepairStatus::Skipped("Empty object".to_string()));
+        }
+
+        if location.is_pithos() {
+            if let Err(e) = read_footer(&self.backend, &location).await {
+                debug!(error = ?e, ?object_id, "No readable footer, rewriting location");
+                return self.rewrite(object_id, location).await;
+            }
+        }
+        if location.disk_hash.is_some() && location.disk_content_len > 0 {
+            return Ok(RepairStatus::Skipped("Location is complete".to_string()));
+        }
+        self.reconstruct(object_id, location).await
+    }
+
+    /// Stores the disk size and hash of the unchanged data
+    async fn reconstruct(
+        &self,
+        object_id: DieselUlid,
+        mut location: ObjectLocation,
+    ) -> Result<RepairStatus> {
+        let (sender, receiver) = async_channel::bounded(10);
+        let backend = self.backend.clone();
+        let source = location.clone();
+        let read = crate::spawn(
+            async move { backend.get_object(source, None, sender).await }
+                .instrument(info_span!("footer_repair_read")),
+        );
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        pin!(receiver);
+        while let Some(chunk) = receiver.next().await {
+            let chunk = chunk.map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                anyhow!("Unable to read location: {e}")
+            })?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+        }
+        read.await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!("Read task failed: {e}")
+        })??;
+
+        // The object may have been overwritten in the meantime
+        if self.cache.get_location(&object_id).await.as_ref() != Some(&location) {
+            return Ok(RepairStatus::Skipped(
+                "Location changed during the repair".to_string(),
+            ));
+        }
+        location.disk_content_len = size as i64;
+        location.disk_hash = Some(hex::encode(hasher.finalize()));
+        location.repaired_at = Some(Utc::now().timestamp());
+        self.cache
+            .update_location(object_id, location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        debug!(?object_id, "Reconstructed disk metadata");
+        Ok(RepairStatus::Repaired)
+    }
+
+    /// Rewrites a pithos location without footer into a new backend key
+    async fn rewrite(
+        &self,
+        object_id: DieselUlid,
+        old_location: ObjectLocation,
+    ) -> Result<RepairStatus> {
+        if old_location.ref_count > 1 {
+            return Ok(RepairStatus::Skipped("Location is shared".to_string()));
+        }
+        let Some(key) = old_location.get_encryption_key() else {
+            return Ok(RepairStatus::Skipped(
+                "Location is not encrypted".to_string(),
+            ));
+        };
+
+        let (object, _) = self.cache.get_resource_cloned(&object_id, true).await?;
+        let parents = self.cache.get_single_parent(&object_id).await?;
+        let storage = self.cache.get_storage_policy(&parents);
+        let target = self
+            .backend
+            .initialize_location_on(
+                old_location.backend.as_deref(),
+                &object,
+                None,
+                parents,
+                storage.as_deref(),
+                false,
+            )
+            .await?;
+
+        // Only the stored bytes change, keys and metadata are kept
+        let mut new_location = old_location.clone();
+        new_location.bucket = target.bucket;
+        new_location.key = target.key;
+        new_location.sharding = target.sharding;
+        // The old data must never be overwritten before the swap
+        if new_location.bucket == old_location.bucket && new_location.key == old_location.key {
+            new_location.key = format!("{}.{}", new_location.key, random_string(8));
+        }
+        if let Err(e) = self.backend.create_location_bucket(&new_location).await {
+            debug!(error = ?e, msg = "Backing bucket not created, it may already exist");
+        }
+
+        trace!(
+            ?old_location,
+            ?new_location,
+            "Rewriting location with footer"
+        );
+
+        let (disk_size, disk_hash) = match self
+            .write_with_footer(&object, &old_location, key, &new_location)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                // Remove partially written data, the old location is untouched
+                let _ = self.backend.delete_object(new_location).await;
+                return Err(e);
+            }
+        };
+
+        // The object may have been overwritten while its data was rewritten
+        if self.cache.get_location(&object_id).await.as_ref() != Some(&old_location) {
+            let _ = self.backend.delete_object(new_location).await;
+            return Ok(RepairStatus::Skipped(
+                "Location changed during the repair".to_string(),
+            ));
+        }
+
+        new_location.disk_content_len = disk_size as i64;
+        new_location.disk_hash = Some(disk_hash);
+        new_location.repaired_at = Some(Utc::now().timestamp());
+
+        // Same location id -> single upsert of the location row
+        self.cache
+            .update_location(object_id, new_location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+        if let Err(e) = self.backend.delete_object(old_location).await {
+            // Data is already rewritten, only the old backend object is leaked
+            error!(error = ?e, ?object_id, msg = "Unable to delete old location");
+        }
+        debug!(?object_id, "Rewrote location with footer");
+        Ok(RepairStatus::Rewritten)
+    }
+
+    /// Decodes the old data and encodes it again with a footer, returns the new disk
+    /// size and hash
+    async fn write_with_footer(
+        &self,
+        object: &crate::structs::Object,
+        old_location: &ObjectLocation,
+        key: [u8; 32],
+        new_location: &ObjectLocation,
+    ) -> Result<(u64, String)> {
+        let ctx = object.get_file_context(
+            Some(new_location.clone()),
+            Some(old_location.disk_content_len),
+        )?;
+        let (data_sender, data_receiver) = async_channel::bounded(10);
+        let backend = self.backend.clone();
+        let target = new_location.clone();
+        let expected_raw_hash = old_location.raw_hashes.get("sha256").cloned();
+        let expected_raw_size = old_location.raw_content_len as u64;
+
+        let handle = crate::spawn(
+            async move {
+                let (message_sender, message_receiver) = async_channel::bounded(10);
+                let (sink, _) =
+                    BufferedS3Sink::new(backend, target, None, None, false, None, false);
+                pin!(data_receiver);
+                let mut asrw = GenericStreamReadWriter::new_with_sink(data_receiver, sink);
+                asrw.add_message_receiver(message_receiver).await?;
+
+                asrw = asrw.add_transformer(ChaCha20Dec::new_with_fixed(key).map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?);
+                asrw = asrw.add_transformer(ZstdDec::new());
+
+                let (raw_sha, raw_sha_recv) =
+                    HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+                asrw = asrw.add_transformer(raw_sha);
+                let (raw_probe, raw_size_recv) = SizeProbe::new();
+                asrw = asrw.add_transformer(raw_probe);
+
+                message_sender.send(Message::FileContext(ctx)).await?;
+                asrw = asrw.add_transformer(PithosTransformer::new());
+                asrw = asrw.add_transformer(FooterGenerator::new(None));
+
+                let (disk_sha, disk_sha_recv) =
+                    HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+                asrw = asrw.add_transformer(disk_sha);
+                let (disk_probe, disk_size_recv) = SizeProbe::new();
+                asrw = asrw.add_transformer(disk_probe);
+
+                asrw.process().await.map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?;
+
+                // Decoding with the wrong assumptions must never replace the data
+                if raw_size_recv.try_recv()? != expected_raw_size {
+                    bail!("Decoded data does not match the raw size");
+                }
+                if let Some(expected) = expected_raw_hash {
+                    if expected != raw_sha_recv.try_recv()? {
+                        bail!("Decoded data does not match the raw hash");
+                    }
+                }
+                Ok::<(u64, String), anyhow::Error>((
+                    disk_size_recv.try_recv()?,
+                    disk_sha_recv.try_recv()?,
+                ))
+            }
+            .instrument(info_span!("rewrite_with_footer")),
+        );
+
+        self.backend
+            .get_object(old_location.clone(), None, data_sender)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+        handle.await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!("Rewrite task failed: {e}")
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_labels() {
+        assert_eq!(RepairStatus::Repaired.label(), "repaired");
+        assert_eq!(RepairStatus::Rewritten.label(), "rewritten");
+        assert_eq!(
+            RepairStatus::Skipped("Location is complete".to_string()).label(),
+            "skipped"
+        );
+    }
+
+    #[test]
+    fn test_repaired_at_defaults() {
+        // Locations persisted before the repair existed
+        let mut persisted = serde_json::to_value(ObjectLocation {
+            repaired_at: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        persisted.as_object_mut().unwrap().remove("repaired_at");
+        let location: ObjectLocation = serde_json::from_value(persisted).unwrap();
+        assert!(location.repaired_at.is_none());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::helpers::random_string;
+use crate::maintenance::backend_migration::MAX_JOB_ERRORS;
+use crate::maintenance::collect_objects;
+use crate::s3_frontend::utils::buffered_s3_sink::BufferedS3Sink;
+use crate::structs::{FileFormat, ObjectLocation};
+use anyhow::{anyhow, bail, Result};
+use async_channel::{Receiver, Sender};
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use pithos_lib::streamreadwrite::GenericStreamReadWriter;
+use pithos_lib::transformer::ReadWriter;
+use pithos_lib::transformers::decrypt::ChaCha20Dec;
+use pithos_lib::transformers::encrypt::ChaCha20Enc;
+use pithos_lib::transformers::hashing_transformer::HashingTransformer;
+use pithos_lib::transformers::size_probe::SizeProbe;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::pin;
+use tracing::{debug, error, info, info_span, trace, Instrument};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationStatus {
+    Rotated,
+    Skipped(String),
+    Failed(String),
+}
+
+/// Progress of a single object during a key rotation
+#[derive(Debug, Clone)]
+pub struct RotationProgress {
+    pub object_id: DieselUlid,
+    pub status: RotationStatus,
+}
+
+/// Progress of a key rotation below a resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRotationJob {
+    pub resource_id: DieselUlid,
+    pub total: usize,
+    pub rotated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub running: bool,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// Most recent failures as (object id, error)
+    pub errors: Vec<(DieselUlid, String)>,
+}
+
+impl KeyRotationJob {
+    fn new(resource_id: DieselUlid) -> Self {
+        KeyRotationJob {
+            resource_id,
+            total: 0,
+            rotated: 0,
+            skipped: 0,
+            failed: 0,
+            running: true,
+            started_at: Utc::now().timestamp(),
+            finished_at: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, object_id: DieselUlid, status: &RotationStatus) {
+        match status {
+            RotationStatus::Rotated => self.rotated += 1,
+            RotationStatus::Skipped(_) => self.skipped += 1,
+            RotationStatus::Failed(e) => {
+                self.failed += 1;
+                if self.errors.len() >= MAX_JOB_ERRORS {
+                    self.errors.remove(0);
+                }
+                self.errors.push((object_id, e.clone()));
+            }
+        }
+    }
+}
+
+/// Re-encrypts stored locations with freshly generated keys
+///
+/// Each location is streamed from the backend through ChaCha20Dec (old key) and
+/// ChaCha20Enc (new key) into a new backend key. Only after the new data is
+/// completely written the location is swapped in the cache/persistence, the old
+/// backend object is deleted afterwards. A crash before the swap leaves the old
+/// location untouched, a crash after it at most leaks the old backend object.
+pub struct KeyRotationHandler {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+    jobs: DashMap<DieselUlid, KeyRotationJob>,
+}
+
+impl KeyRotationHandler {
+    pub fn new(cache: Arc<Cache>, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        Self {
+            cache,
+            backend,
+            jobs: DashMap::new(),
+        }
+    }
+
+    /// Rotates the keys of the object or all objects below the given resource, fails
+    /// if a rotation of the resource is already running
+    ///
+    /// Progress and failures are reported per object via the returned receiver, the
+    /// rotation continues if the receiver is dropped and is tracked in [`Self::jobs`].
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn rotate(self: &Arc<Self>, resource_id: DieselUlid) -> Result<Receiver<RotationProgress>> {
+        let job = KeyRotationJob::new(resource_id);
+        match self.jobs.entry(resource_id) {
+            Entry::Occupied(entry) if entry.get().running => {
+                error!(?resource_id, "Key rotation is already running");
+                bail!("Key rotation of {resource_id} is already running")
+            }
+            Entry::Occupied(mut entry) => {
+                entry.insert(job);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(job);
+            }
+        }
+        let (sender, receiver) = async_channel::bounded(100);
+        let handler = self.clone();
+        crate::spawn(
+            async move { handler.rotate_all(resource_id, sender).await }
+                .instrument(info_span!("rotate_encryption_keys")),
+        );
+        Ok(receiver)
+    }
+
+    /// Status of all rotations since the start of the proxy
+    pub fn jobs(&self) -> Vec<KeyRotationJob> {
+        let mut jobs = self
+            .jobs
+            .iter()
+            .map(|job| job.value().clone())
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| job.started_at);
+        jobs
+    }
+
+    async fn rotate_all(&self, resource_id: DieselUlid, sender: Sender<RotationProgress>) {
+        let objects = collect_objects(&self.cache, resource_id).await;
+        if let Some(mut job) = self.jobs.get_mut(&resource_id) {
+            job.total = objects.len();
+        }
+        for object_id in objects {
+            let status = match self.rotate_object(object_id).await {
+                Ok(status) => status,
+                Err(e) => {
+                    error!(error = ?e, ?object_id, msg = "Key rotation failed");
+                    RotationStatus::Failed(e.to_string())
+                }
+            };
+            if let Some(mut job) = self.jobs.get_mut(&resource_id) {
+                job.record(object_id, &status);
+            }
+            // Nobody listening is fine, the job keeps the progress
+            let _ = sender.send(RotationProgress { object_id, status }).await;
+        }
+        if let Some(mut job) = self.jobs.get_mut(&resource_id) {
+            job.running = false;
+            job.finished_at = Some(Utc::now().timestamp());
+            info!(
+                ?resource_id,
+                rotated = job.rotated,
+                skipped = job.skipped,
+                failed = job.failed,
+                "Key rotation finished"
+            );
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn rotate_object(&self, object_id: DieselUlid) -> Result<RotationStatus> {
+        let Some(old_location) = self.cache.get_location(&object_id).await else {
+            return Ok(RotationStatus::Skipped("No location".to_string()));
+        };
+        if old_location.is_customer_encrypted() {
+            // Only the client knows the key
+            return Ok(RotationStatus::Skipped("Customer provided key".to_string()));
+        }
+        let Some(old_key) = old_location.get_encryption_key() else {
+            return Ok(RotationStatus::Skipped(
+                "Location is not encrypted".to_string(),
+            ));
+        };
+        if old_location.is_pithos() {
+            // The key is part of the pithos footer, rewriting it is not supported yet
+            return Ok(RotationStatus::Skipped(
+                "Pithos locations are not supported".to_string(),
+            ));
+        }
+        if old_location.is_temporary || old_location.upload_id.is_some() {
+            return Ok(RotationStatus::Skipped(
+                "Location is not finalized".to_string(),
+            ));
+        }
+        if old_location.ref_count > 1 {
+            return Ok(RotationStatus::Skipped("Location is shared".to_string()));
+        }
+
+        let (object, _) = self.cache.get_resource_cloned(&object_id, true).await?;
+        let parents = self.cache.get_single_parent(&object_id).await?;
+        let storage = self.cache.get_storage_policy(&parents);
+        let mut new_location = self
+            .backend
+            .initialize_location(&object, None, parents, storage.as_deref(), false)
+            .await?;
+
+        // The old data must never be overwritten before the swap
+        if new_location.bucket == old_location.bucket && new_location.key == old_location.key {
+            new_location.key = format!("{}.{}", new_location.key, random_string(8));
+        }
+
+        let mut new_key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut new_key);
+        new_location.id = old_location.id;
+        new_location.file_format = match old_location.file_format {
+            FileFormat::RawEncryptedCompressed(_) => FileFormat::RawEncryptedCompressed(new_key),
+            _ => FileFormat::RawEncrypted(new_key),
+        };
+        new_location.raw_content_len = old_location.raw_content_len;
+        new_location.ref_count = old_location.ref_count;
+        new_location.content_type = old_location.content_type.clone();
+        new_location.user_metadata = old_location.user_metadata.clone();
+        new_location.raw_hashes = old_location.raw_hashes.clone();
+        new_location.etag = old_location.etag.clone();
+        new_location.checksum = old_location.checksum.clone();
+
+        trace!(?old_location, ?new_location, "Rotating location");
+
+        let (disk_size, disk_hash) = match self
+            .reencrypt(&old_location, old_key, &new_location, new_key)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                // Remove partially written data, the old location is untouched
+                let _ = self.backend.delete_object(new_location).await;
+                return Err(e);
+            }
+        };
+        new_location.disk_content_len = disk_size as i64;
+        new_location.disk_hash = Some(disk_hash);
+
+        // Same location id -> single upsert of the location row
+        self.cache
+            .update_location(object_id, new_location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+        if let Err(e) = self.backend.delete_object(old_location).await {
+            // Data is already rotated, only the old backend object is leaked
+            error!(error = ?e, ?object_id, msg = "Unable to delete old location");
+        }
+        debug!(?object_id, "Rotated encryption key");
+        Ok(RotationStatus::Rotated)
+    }
+
+    /// Streams the old location into the new one, returns the new disk size and hash
+    async fn reencrypt(
+        &self,
+        old_location: &ObjectLocation,
+        old_key: [u8; 32],
+        new_location: &ObjectLocation,
+        new_key: [u8; 32],
+    ) -> Result<(u64, String)> {
+        let (data_sender, data_receiver) = async_channel::bounded(10);
+        let backend = self.backend.clone();
+        let target = new_location.clone();
+        let expected_disk_hash = old_location.disk_hash.clone();
+        // Uncompressed data can be verified against the stored raw hash
+        let expected_raw_hash = (!old_location.is_compressed())
+            .then(|| old_location.raw_hashes.get("sha256").cloned())
+            .flatten();
+
+        let handle = crate::spawn(
+            async move {
+                let (sink, _) =
+                    BufferedS3Sink::new(backend, target, None, None, false, None, false);
+                pin!(data_receiver);
+                let mut asrw = GenericStreamReadWriter::new_with_sink(data_receiver, sink);
+
+                let (old_sha, old_sha_recv) =
+                    HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+                asrw = asrw.add_transformer(old_sha);
+                asrw = asrw.add_transformer(ChaCha20Dec::new_with_fixed(old_key).map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?);
+
+                let (raw_sha, raw_sha_recv) =
+                    HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+                asrw = asrw.add_transformer(raw_sha);
+                asrw = asrw.add_transformer(ChaCha20Enc::new_with_fixed(new_key).map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?);
+
+                let (new_sha, new_sha_recv) =
+                    HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+                asrw = asrw.add_transformer(new_sha);
+                let (size_probe, size_recv) = SizeProbe::new();
+                asrw = asrw.add_transformer(size_probe);
+
+                asrw.process().await.map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    e
+                })?;
+
+                let old_sha = old_sha_recv.try_recv()?;
+                if let Some(expected) = expected_disk_hash {
+                    if expected != old_sha {
+                        bail!("Stored data does not match the disk hash");
+                    }
+                }
+                let raw_sha = raw_sha_recv.try_recv()?;
+                if let Some(expected) = expected_raw_hash {
+                    if expected != raw_sha {
+                        bail!("Decrypted data does not match the raw hash");
+                    }
+                }
+                Ok::<(u64, String), anyhow::Error>((
+                    size_recv.try_recv()?,
+                    new_sha_recv.try_recv()?,
+                ))
+            }
+            .instrument(info_span!("reencrypt_location")),
+        );
+
+        self.backend
+            .get_object(old_location.clone(), None, data_sender)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+        handle.await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!("Re-encryption task failed: {e}")
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_record() {
+        let mut job = KeyRotationJob::new(DieselUlid::generate());
+        let object_id = DieselUlid::generate();
+        job.record(object_id, &RotationStatus::Rotated);
+        job.record(object_id, &RotationStatus::Skipped("shared".to_string()));
+        for idx in 0..MAX_JOB_ERRORS + 1 {
+            job.record(object_id, &RotationStatus::Failed(format!("error {idx}")));
+        }
+        assert_eq!((job.rotated, job.skipped), (1, 1));
+        assert_eq!(job.failed, MAX_JOB_ERRORS + 1);
+        assert_eq!(job.errors.len(), MAX_JOB_ERRORS);
+        assert_eq!(job.errors[0].1, "error 1");
+    }
+}
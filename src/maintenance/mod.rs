@@ -0,0 +1,100 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::s3_frontend::utils::buffered_s3_sink::BufferedS3Sink;
+use crate::structs::{ObjectLocation, TypedId};
+use anyhow::{anyhow, bail, Result};
+use diesel_ulid::DieselUlid;
+use pithos_lib::streamreadwrite::GenericStreamReadWriter;
+use pithos_lib::transformer::ReadWriter;
+use pithos_lib::transformers::hashing_transformer::HashingTransformer;
+use pithos_lib::transformers::size_probe::SizeProbe;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::pin;
+use tracing::{error, info_span, Instrument};
+
+pub mod backend_migration;
+pub mod footer_repair;
+pub mod key_rotation;
+pub mod restore_poller;
+pub mod scrubber;
+pub mod shard_migration;
+
+/// Collects all objects below (and including) the given resource
+pub(crate) async fn collect_objects(cache: &Cache, resource_id: DieselUlid) -> Vec<DieselUlid> {
+    let mut objects = Vec::new();
+    let mut queue = vec![resource_id];
+    while let Some(id) = queue.pop() {
+        let Ok((resource, _)) = cache.get_resource_cloned(&id, true).await else {
+            continue;
+        };
+        match TypedId::from(&resource) {
+            TypedId::Object(id) => objects.push(id),
+            _ => {
+                if let Some(children) = cache.get_children(&id).await {
+                    queue.extend(children.into_iter().map(|(_, child)| child.get_id()));
+                }
+            }
+        }
+    }
+    objects
+}
+
+/// Copies the stored bytes of a location unchanged to another (initialized) location and
+/// verifies them against the disk hash and size, both locations are resolved by `backend`
+pub(crate) async fn copy_location(
+    backend: &Arc<Box<dyn StorageBackend>>,
+    old_location: &ObjectLocation,
+    new_location: &ObjectLocation,
+) -> Result<()> {
+    let (data_sender, data_receiver) = async_channel::bounded(10);
+    let sink_backend = backend.clone();
+    let target = new_location.clone();
+    let expected_hash = old_location.disk_hash.clone();
+    let expected_size = old_location.disk_content_len as u64;
+
+    let handle = crate::spawn(
+        async move {
+            let (sink, _) =
+                BufferedS3Sink::new(sink_backend, target, None, None, false, None, false);
+            let sink = sink.with_expected_size(Some(expected_size));
+            pin!(data_receiver);
+            let mut asrw = GenericStreamReadWriter::new_with_sink(data_receiver, sink);
+
+            let (sha, sha_recv) =
+                HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+            asrw = asrw.add_transformer(sha);
+            let (size_probe, size_recv) = SizeProbe::new();
+            asrw = asrw.add_transformer(size_probe);
+
+            asrw.process().await.map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+            if let Some(expected) = expected_hash {
+                if expected != sha_recv.try_recv()? {
+                    bail!("Copied data does not match the disk hash");
+                }
+            }
+            if expected_size != size_recv.try_recv()? {
+                bail!("Copied data does not match the disk size");
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .instrument(info_span!("copy_location")),
+    );
+
+    backend
+        .get_object(old_location.clone(), None, data_sender)
+        .await
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+
+    handle.await.map_err(|e| {
+        error!(error = ?e, msg = e.to_string());
+        anyhow!("Copy task failed: {e}")
+    })?
+}
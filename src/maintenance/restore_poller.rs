@@ -0,0 +1,87 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::shutdown::Shutdown;
+use crate::structs::ObjectLocation;
+use anyhow::Result;
+use chrono::Utc;
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, trace, warn};
+
+/// Interval between two status checks of all requested restores
+const RESTORE_POLL_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Tracks restores of archived objects requested via RestoreObject
+///
+/// Locations with an ongoing restore are polled at the backend until the restored
+/// copy is available, expired copies are reset so that the object is reported as
+/// archived again.
+pub struct RestorePoller {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+    shutdown: Shutdown,
+}
+
+impl RestorePoller {
+    pub fn new(
+        cache: Arc<Cache>,
+        backend: Arc<Box<dyn StorageBackend>>,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            cache,
+            backend,
+            shutdown,
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(RESTORE_POLL_INTERVAL) => {}
+                _ = self.shutdown.signaled() => {
+                    trace!("restore poller stopped");
+                    return Ok(());
+                }
+            }
+            for (object_id, location) in self.pending_restores().await {
+                if let Err(e) = self.poll(object_id, location).await {
+                    warn!(error = ?e, ?object_id, msg = "Unable to update restore status");
+                }
+            }
+        }
+    }
+
+    /// Locations with an ongoing or expired restore
+    async fn pending_restores(&self) -> Vec<(DieselUlid, ObjectLocation)> {
+        let now = Utc::now();
+        let mut pending = Vec::new();
+        for id in self.cache.get_resource_ids() {
+            if let Some(location) = self.cache.get_location(&id).await {
+                if matches!(&location.restore, Some(restore) if !restore.is_available(now)) {
+                    pending.push((id, location));
+                }
+            }
+        }
+        pending
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn poll(&self, object_id: DieselUlid, mut location: ObjectLocation) -> Result<()> {
+        let status = match &location.restore {
+            Some(restore) if restore.ongoing => self.backend.restore_status(&location).await?,
+            // The restored copy expired
+            _ => None,
+        };
+        if status == location.restore {
+            return Ok(());
+        }
+        if matches!(&status, Some(restore) if !restore.ongoing) {
+            info!(?object_id, "Archived object restored");
+        }
+        location.restore = status;
+        self.cache.update_location(object_id, location).await
+    }
+}
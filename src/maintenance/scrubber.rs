@@ -0,0 +1,323 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::metrics::{
+    SCRUBBER_BYTES, SCRUBBER_LAST_OBJECT, SCRUBBER_LAST_TIMESTAMP, SCRUBBER_OBJECTS,
+};
+use crate::replication::replication_handler::{Direction, ReplicationMessage};
+use crate::shutdown::Shutdown;
+use crate::structs::{ObjectLocation, SyncStatus};
+use crate::CONFIG;
+use anyhow::{anyhow, Result};
+use diesel_ulid::DieselUlid;
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::pin;
+use tracing::{debug, error, info_span, trace, warn, Instrument};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ScrubResult {
+    Ok,
+    Corrupt(String),
+    Skipped,
+}
+
+/// Token bucket on bytes, chunks larger than the bucket are allowed and repaid by waiting
+#[derive(Debug)]
+struct ByteThrottle {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl ByteThrottle {
+    fn new(bytes_per_sec: u64) -> Self {
+        ByteThrottle {
+            rate: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes the bytes, returns how long to wait before reading more
+    fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Periodically verifies stored data against the recorded disk hash and size
+///
+/// Locations are walked in id order at `objects_per_hour`, reads are throttled to
+/// `bytes_per_sec`. Corrupt locations are flagged and, if another endpoint holds a
+/// fully synced copy, replaced by a pull replication.
+pub struct IntegrityScrubber {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+    shutdown: Shutdown,
+}
+
+impl IntegrityScrubber {
+    pub fn new(
+        cache: Arc<Cache>,
+        backend: Arc<Box<dyn StorageBackend>>,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            cache,
+            backend,
+            shutdown,
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        let Some(config) = CONFIG.scrubber.clone() else {
+            return Ok(());
+        };
+        let interval = Duration::from_secs_f64(3600.0 / config.objects_per_hour as f64);
+        let mut throttle = ByteThrottle::new(config.bytes_per_sec);
+        let mut last_id: Option<DieselUlid> = None;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.shutdown.signaled() => {
+                    trace!("integrity scrubber stopped");
+                    return Ok(());
+                }
+            }
+            // The next location after the last scrubbed one, wraps around at the end
+            let Some((object_id, location)) = self.next_location(last_id).await else {
+                last_id = None;
+                continue;
+            };
+            last_id = Some(object_id);
+
+            let result = tokio::select! {
+                result = self.scrub(&location, &mut throttle) => result,
+                _ = self.shutdown.signaled() => {
+                    trace!("integrity scrubber stopped");
+                    return Ok(());
+                }
+            };
+            SCRUBBER_LAST_OBJECT.reset();
+            SCRUBBER_LAST_OBJECT
+                .with_label_values(&[&object_id.to_string()])
+                .set(1);
+            SCRUBBER_LAST_TIMESTAMP.set(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or_default(),
+            );
+            match result {
+                Ok(ScrubResult::Ok) => {
+                    SCRUBBER_OBJECTS.with_label_values(&["ok"]).inc();
+                    trace!(?object_id, "Location is intact");
+                }
+                Ok(ScrubResult::Skipped) => {
+                    SCRUBBER_OBJECTS.with_label_values(&["skipped"]).inc();
+                }
+                Ok(ScrubResult::Corrupt(reason)) => {
+                    SCRUBBER_OBJECTS.with_label_values(&["corrupt"]).inc();
+                    error!(
+                        ?object_id,
+                        location_id = ?location.id,
+                        bucket = location.bucket,
+                        key = location.key,
+                        reason,
+                        "Stored data is corrupt"
+                    );
+                    if let Err(e) = self
+                        .handle_corrupt(object_id, location, config.repair)
+                        .await
+                    {
+                        error!(error = ?e, msg = e.to_string());
+                    }
+                }
+                Err(e) => {
+                    SCRUBBER_OBJECTS.with_label_values(&["error"]).inc();
+                    warn!(error = ?e, ?object_id, msg = "Unable to scrub location");
+                }
+            }
+        }
+    }
+
+    async fn next_location(
+        &self,
+        last_id: Option<DieselUlid>,
+    ) -> Option<(DieselUlid, ObjectLocation)> {
+        for id in self
+            .cache
+            .get_resource_ids()
+            .into_iter()
+            .filter(|id| last_id.map(|last| *id > last).unwrap_or(true))
+        {
+            match self.cache.get_location(&id).await {
+                Some(location) if !location.is_temporary && location.upload_id.is_none() => {
+                    return Some((id, location))
+                }
+                _ => continue,
+            }
+        }
+        None
+    }
+
+    /// Streams the stored data and compares it with the disk hash and size
+    #[tracing::instrument(level = "trace", skip(self, location, throttle))]
+    async fn scrub(
+        &self,
+        location: &ObjectLocation,
+        throttle: &mut ByteThrottle,
+    ) -> Result<ScrubResult> {
+        // Nothing to compare with, corrupt locations wait for their repair
+        if location.corrupt || (location.disk_hash.is_none() && location.disk_content_len <= 0) {
+            return Ok(ScrubResult::Skipped);
+        }
+        // Archived data can not be read without a restore
+        if location.is_archived(chrono::Utc::now()) {
+            return Ok(ScrubResult::Skipped);
+        }
+
+        let (sender, receiver) = async_channel::bounded(10);
+        let backend = self.backend.clone();
+        let source = location.clone();
+        let read = crate::spawn(
+            async move { backend.get_object(source, None, sender).await }
+                .instrument(info_span!("scrub_read")),
+        );
+
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        pin!(receiver);
+        while let Some(chunk) = receiver.next().await {
+            let chunk = chunk.map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                anyhow!("Unable to read location: {e}")
+            })?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+            SCRUBBER_BYTES.inc_by(chunk.len() as u64);
+            let wait = throttle.take(chunk.len(), Instant::now());
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+        }
+        read.await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!("Read task failed: {e}")
+        })??;
+
+        if location.disk_content_len > 0 && size != location.disk_content_len as u64 {
+            return Ok(ScrubResult::Corrupt(format!(
+                "Expected {} bytes, read {size}",
+                location.disk_content_len
+            )));
+        }
+        if let Some(expected) = &location.disk_hash {
+            let calculated = hex::encode(hasher.finalize());
+            if &calculated != expected {
+                return Ok(ScrubResult::Corrupt(format!(
+                    "Expected disk hash {expected}, calculated {calculated}"
+                )));
+            }
+        }
+        Ok(ScrubResult::Ok)
+    }
+
+    /// Flags the location and pulls a good copy from another endpoint if possible
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn handle_corrupt(
+        &self,
+        object_id: DieselUlid,
+        mut location: ObjectLocation,
+        repair: bool,
+    ) -> Result<()> {
+        // Only flag the location if it was not replaced during the scrub
+        if self.cache.get_location(&object_id).await.map(|l| l.id) != Some(location.id) {
+            return Ok(());
+        }
+        location.corrupt = true;
+        self.cache.update_location(object_id, location).await?;
+
+        if !repair {
+            return Ok(());
+        }
+        let (object, _) = self.cache.get_resource_cloned(&object_id, false).await?;
+        let self_id = CONFIG.proxy.endpoint_id;
+        let Some(endpoint_id) = object
+            .endpoints
+            .iter()
+            .find(|ep| ep.id != self_id && ep.status == Some(SyncStatus::Finished))
+            .map(|ep| ep.id)
+        else {
+            warn!(
+                ?object_id,
+                "No other endpoint holds a copy, unable to repair"
+            );
+            return Ok(());
+        };
+        if self
+            .cache
+            .replication_progress
+            .is_queued(endpoint_id, object_id)
+        {
+            return Ok(());
+        }
+        let direction = Direction::Pull(object_id);
+        self.cache
+            .replication_progress
+            .queue(endpoint_id, direction.clone());
+        self.cache
+            .sender
+            .send(ReplicationMessage {
+                direction,
+                endpoint_id,
+            })
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                anyhow!("Replication queue is not available")
+            })?;
+        debug!(
+            ?object_id,
+            ?endpoint_id,
+            "Requested repair of corrupt location"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_throttle() {
+        let start = Instant::now();
+        let mut throttle = ByteThrottle {
+            rate: 1000.0,
+            tokens: 1000.0,
+            last_refill: start,
+        };
+        // One second worth of bytes is available at once
+        assert_eq!(throttle.take(1000, start), Duration::ZERO);
+        // Larger reads are repaid by waiting
+        assert_eq!(throttle.take(500, start), Duration::from_millis(500));
+        // Refilled after the wait
+        let later = start + Duration::from_millis(500);
+        assert_eq!(throttle.take(0, later), Duration::ZERO);
+        // Idle time does not accumulate more than one second worth of bytes
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(throttle.take(1000, idle), Duration::ZERO);
+        assert!(throttle.take(1, idle) > Duration::ZERO);
+    }
+}
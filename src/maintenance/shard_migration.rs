@@ -0,0 +1,141 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::helpers::random_string;
+use crate::maintenance::{collect_objects, copy_location};
+use anyhow::{anyhow, Result};
+use async_channel::{Receiver, Sender};
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+use tracing::{debug, error, info_span, trace, Instrument};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationStatus {
+    Migrated,
+    Skipped(String),
+    Failed(String),
+}
+
+/// Progress of a single object during a shard migration
+#[derive(Debug, Clone)]
+pub struct MigrationProgress {
+    pub object_id: DieselUlid,
+    pub status: MigrationStatus,
+}
+
+/// Moves stored locations to the currently configured sharding scheme
+///
+/// The stored bytes are copied unchanged into a location initialized by the backend
+/// (and thereby with the configured scheme). Reads are served from the old location
+/// until the copy is complete and verified, the location is swapped afterwards and
+/// the old backend object is deleted.
+pub struct ShardMigrationHandler {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+}
+
+impl ShardMigrationHandler {
+    pub fn new(cache: Arc<Cache>, backend: Arc<Box<dyn StorageBackend>>) -> Self {
+        Self { cache, backend }
+    }
+
+    /// Migrates the object or all objects below the given resource (e.g. a project),
+    /// progress and failures are reported per object via the returned receiver
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn migrate(self: Arc<Self>, resource_id: DieselUlid) -> Receiver<MigrationProgress> {
+        let (sender, receiver) = async_channel::bounded(100);
+        crate::spawn(
+            async move {
+                if let Err(e) = self.migrate_all(resource_id, &sender).await {
+                    error!(error = ?e, msg = e.to_string());
+                }
+            }
+            .instrument(info_span!("migrate_shards")),
+        );
+        receiver
+    }
+
+    async fn migrate_all(
+        &self,
+        resource_id: DieselUlid,
+        sender: &Sender<MigrationProgress>,
+    ) -> Result<()> {
+        for object_id in collect_objects(&self.cache, resource_id).await {
+            let status = match self.migrate_object(object_id).await {
+                Ok(status) => status,
+                Err(e) => {
+                    error!(error = ?e, ?object_id, msg = "Shard migration failed");
+                    MigrationStatus::Failed(e.to_string())
+                }
+            };
+            sender
+                .send(MigrationProgress { object_id, status })
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    anyhow!("Progress receiver closed")
+                })?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn migrate_object(&self, object_id: DieselUlid) -> Result<MigrationStatus> {
+        let Some(old_location) = self.cache.get_location(&object_id).await else {
+            return Ok(MigrationStatus::Skipped("No location".to_string()));
+        };
+        if old_location.is_temporary || old_location.upload_id.is_some() {
+            return Ok(MigrationStatus::Skipped(
+                "Location is not finalized".to_string(),
+            ));
+        }
+        if old_location.ref_count > 1 {
+            return Ok(MigrationStatus::Skipped("Location is shared".to_string()));
+        }
+
+        let (object, _) = self.cache.get_resource_cloned(&object_id, true).await?;
+        let parents = self.cache.get_single_parent(&object_id).await?;
+        let storage = self.cache.get_storage_policy(&parents);
+        let target = self
+            .backend
+            .initialize_location(&object, None, parents, storage.as_deref(), false)
+            .await?;
+        if target.sharding == old_location.sharding {
+            return Ok(MigrationStatus::Skipped(
+                "Location already uses the configured scheme".to_string(),
+            ));
+        }
+
+        // Only bucket, key and scheme change, the stored bytes are kept as they are
+        let mut new_location = old_location.clone();
+        new_location.bucket = target.bucket;
+        new_location.key = target.key;
+        new_location.sharding = target.sharding;
+        if new_location.bucket == old_location.bucket && new_location.key == old_location.key {
+            new_location.key = format!("{}.{}", new_location.key, random_string(8));
+        }
+
+        trace!(?old_location, ?new_location, "Migrating location");
+
+        if let Err(e) = copy_location(&self.backend, &old_location, &new_location).await {
+            // Remove partially written data, the old location is untouched
+            let _ = self.backend.delete_object(new_location).await;
+            return Err(e);
+        }
+
+        // Same location id -> single upsert of the location row
+        self.cache
+            .update_location(object_id, new_location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+        if let Err(e) = self.backend.delete_object(old_location).await {
+            // Data is already migrated, only the old backend object is leaked
+            error!(error = ?e, ?object_id, msg = "Unable to delete old location");
+        }
+        debug!(?object_id, "Migrated location");
+        Ok(MigrationStatus::Migrated)
+    }
+}
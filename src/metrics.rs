@@ -0,0 +1,511 @@
+use crate::auth::clock::ClockSkew;
+use crate::scoped_config::{self, ScopedState};
+use crate::shutdown::Shutdown;
+use anyhow::{anyhow, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use tracing::{error, info};
+
+/// Metric of the dataproxy of the current task
+pub struct ScopedMetric<T: 'static>(fn(&'static Metrics) -> &'static T);
+
+impl<T: 'static> Deref for ScopedMetric<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        (self.0)(&scoped_config::state().metrics)
+    }
+}
+
+pub static S3_REQUESTS: ScopedMetric<IntCounterVec> = ScopedMetric(|metrics| &metrics.s3_requests);
+pub static S3_REQUEST_DURATION: ScopedMetric<HistogramVec> =
+    ScopedMetric(|metrics| &metrics.s3_request_duration);
+pub static S3_BYTES_IN: ScopedMetric<IntCounter> = ScopedMetric(|metrics| &metrics.s3_bytes_in);
+pub static S3_BYTES_OUT: ScopedMetric<IntCounter> = ScopedMetric(|metrics| &metrics.s3_bytes_out);
+pub static S3_THROTTLED: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.s3_throttled);
+pub static DISK_CACHE_REQUESTS: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.disk_cache_requests);
+pub static DISK_CACHE_SIZE: ScopedMetric<IntGauge> =
+    ScopedMetric(|metrics| &metrics.disk_cache_size);
+pub static BACKEND_RETRIES: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.backend_retries);
+pub static DISK_CACHE_EVICTIONS: ScopedMetric<IntCounter> =
+    ScopedMetric(|metrics| &metrics.disk_cache_evictions);
+pub static REPLICATED_OBJECTS: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.replicated_objects);
+pub static RULE_DENIALS: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.rule_denials);
+pub static SCRUBBER_OBJECTS: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.scrubber_objects);
+pub static FOOTER_REPAIRS: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.footer_repairs);
+pub static SCRUBBER_BYTES: ScopedMetric<IntCounter> =
+    ScopedMetric(|metrics| &metrics.scrubber_bytes);
+pub static SCRUBBER_LAST_OBJECT: ScopedMetric<IntGaugeVec> =
+    ScopedMetric(|metrics| &metrics.scrubber_last_object);
+pub static SCRUBBER_LAST_TIMESTAMP: ScopedMetric<IntGauge> =
+    ScopedMetric(|metrics| &metrics.scrubber_last_timestamp);
+pub static PARALLEL_GETS: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.parallel_gets);
+pub static DOWNLOAD_BYTES: ScopedMetric<IntCounter> =
+    ScopedMetric(|metrics| &metrics.download_bytes);
+pub static DOWNLOAD_THROTTLED: ScopedMetric<IntCounter> =
+    ScopedMetric(|metrics| &metrics.download_throttled);
+pub static WEBHOOK_DELIVERIES: ScopedMetric<IntCounterVec> =
+    ScopedMetric(|metrics| &metrics.webhook_deliveries);
+pub static CLOCK_SKEW_SECONDS: ScopedMetric<IntGaugeVec> =
+    ScopedMetric(|metrics| &metrics.clock_skew_seconds);
+pub static REPLICATION_METRICS: ScopedMetric<ReplicationMetrics> =
+    ScopedMetric(|metrics| &metrics.replication);
+pub static CACHE_METRICS: ScopedMetric<CacheMetrics> = ScopedMetric(|metrics| &metrics.cache);
+pub static BACKEND_METRICS: ScopedMetric<BackendMetrics> = ScopedMetric(|metrics| &metrics.backend);
+
+/// Metrics of a dataproxy, every dataproxy serves its own registry
+pub struct Metrics {
+    pub registry: Registry,
+    pub s3_requests: IntCounterVec,
+    pub s3_request_duration: HistogramVec,
+    pub s3_bytes_in: IntCounter,
+    pub s3_bytes_out: IntCounter,
+    pub s3_throttled: IntCounterVec,
+    pub disk_cache_requests: IntCounterVec,
+    pub disk_cache_size: IntGauge,
+    pub backend_retries: IntCounterVec,
+    pub disk_cache_evictions: IntCounter,
+    pub replicated_objects: IntCounterVec,
+    pub rule_denials: IntCounterVec,
+    pub scrubber_objects: IntCounterVec,
+    pub footer_repairs: IntCounterVec,
+    pub scrubber_bytes: IntCounter,
+    pub scrubber_last_object: IntGaugeVec,
+    pub scrubber_last_timestamp: IntGauge,
+    pub parallel_gets: IntCounterVec,
+    pub download_bytes: IntCounter,
+    pub download_throttled: IntCounter,
+    pub webhook_deliveries: IntCounterVec,
+    pub clock_skew_seconds: IntGaugeVec,
+    pub replication: ReplicationMetrics,
+    pub cache: CacheMetrics,
+    pub backend: BackendMetrics,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("dataproxy".to_string()), None)?;
+        Ok(Metrics {
+            s3_requests: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "s3_requests_total",
+                    "S3 requests by request class and status"
+                ),
+                &["class", "status"],
+            ))?,
+            s3_request_duration: register_in(&registry, HistogramVec::new(
+                HistogramOpts::new(
+                    "s3_request_duration_seconds",
+                    "Time until the S3 response headers are sent"
+                ),
+                &["class"],
+            ))?,
+            s3_bytes_in: register_in(&registry, IntCounter::new(
+                "s3_received_bytes_total",
+                "Announced request body bytes"
+            ))?,
+            s3_bytes_out: register_in(&registry, IntCounter::new(
+                "s3_sent_bytes_total",
+                "Announced response body bytes"
+            ))?,
+            s3_throttled: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "s3_throttled_total",
+                    "S3 requests rejected by the rate limiter"
+                ),
+                &["class"],
+            ))?,
+            disk_cache_requests: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "disk_cache_requests_total",
+                    "Backend reads by disk cache result (hit, miss, bypass)"
+                ),
+                &["result"],
+            ))?,
+            disk_cache_size: register_in(&registry, IntGauge::new(
+                "disk_cache_size_bytes",
+                "Size of all files in the disk cache"
+            ))?,
+            backend_retries: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "backend_retries_total",
+                    "Retried storage backend requests by operation"
+                ),
+                &["operation"],
+            ))?,
+            disk_cache_evictions: register_in(&registry, IntCounter::new(
+                "disk_cache_evictions_total",
+                "Files evicted from the disk cache"
+            ))?,
+            replicated_objects: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "replicated_objects_total",
+                    "Objects pulled from other proxies by transfer (stream, server_copy, server_copy_fallback, integrity_error)"
+                ),
+                &["transfer"],
+            ))?,
+            rule_denials: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "rule_denials_total",
+                    "Requests denied by rules by rule id and mode (enforced, dry_run)"
+                ),
+                &["rule_id", "mode"],
+            ))?,
+            scrubber_objects: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "scrubber_objects_total",
+                    "Locations checked by the integrity scrubber by result (ok, corrupt, error, skipped)"
+                ),
+                &["result"],
+            ))?,
+            footer_repairs: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "footer_repairs_total",
+                    "Locations checked by the footer repair by result (repaired, rewritten, skipped, failed)"
+                ),
+                &["result"],
+            ))?,
+            scrubber_bytes: register_in(&registry, IntCounter::new(
+                "scrubber_read_bytes_total",
+                "Bytes read from the backend by the integrity scrubber"
+            ))?,
+            scrubber_last_object: register_in(&registry, IntGaugeVec::new(
+                Opts::new(
+                    "scrubber_last_object_info",
+                    "Last object checked by the integrity scrubber, always 1"
+                ),
+                &["object_id"],
+            ))?,
+            scrubber_last_timestamp: register_in(&registry, IntGauge::new(
+                "scrubber_last_timestamp_seconds",
+                "Unix time of the last check of the integrity scrubber"
+            ))?,
+            parallel_gets: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "parallel_gets_total",
+                    "Parallel backend reads by result (completed, fallback)"
+                ),
+                &["result"],
+            ))?,
+            download_bytes: register_in(&registry, IntCounter::new(
+                "s3_download_bytes_total",
+                "Response body bytes of bandwidth limited downloads"
+            ))?,
+            download_throttled: register_in(&registry, IntCounter::new(
+                "s3_download_throttled_milliseconds_total",
+                "Time downloads were delayed by the bandwidth limits"
+            ))?,
+            webhook_deliveries: register_in(&registry, IntCounterVec::new(
+                Opts::new(
+                    "webhook_deliveries_total",
+                    "Webhook delivery attempts by result (delivered, failed, dead_letter, dropped)"
+                ),
+                &["result"],
+            ))?,
+            clock_skew_seconds: register_in(&registry, IntGaugeVec::new(
+                Opts::new(
+                    "clock_skew_seconds",
+                    "Measured skew of remote clocks (aruna, backend) against the local clock, remote - local"
+                ),
+                &["origin"],
+            ))?,
+            replication: ReplicationMetrics::new(&registry)?,
+            cache: CacheMetrics::new(&registry)?,
+            backend: BackendMetrics::new(&registry)?,
+            registry,
+        })
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+/// Replication queue by remote endpoint, the number of endpoints is small and stable
+#[derive(Clone)]
+pub struct ReplicationMetrics {
+    /// Objects in the queue by endpoint and state (queued, in_progress)
+    pub objects: IntGaugeVec,
+    /// Objects that left the queue by endpoint and result (finished, failed)
+    pub completed: IntCounterVec,
+    pub chunk_retries: IntCounterVec,
+    pub bytes: IntCounterVec,
+}
+
+impl ReplicationMetrics {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        Ok(ReplicationMetrics {
+            objects: register_in(
+                registry,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "replication_queue_objects",
+                        "Objects in the replication queue by endpoint and state (queued, in_progress)",
+                    ),
+                    &["endpoint", "state"],
+                ),
+            )?,
+            completed: register_in(
+                registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "replication_completed_total",
+                        "Replications by endpoint and result (finished, failed)",
+                    ),
+                    &["endpoint", "result"],
+                ),
+            )?,
+            chunk_retries: register_in(
+                registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "replication_chunk_retries_total",
+                        "Chunks requested again because they were missing or corrupt",
+                    ),
+                    &["endpoint"],
+                ),
+            )?,
+            bytes: register_in(
+                registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "replication_bytes_total",
+                        "Bytes replicated from other proxies by endpoint",
+                    ),
+                    &["endpoint"],
+                ),
+            )?,
+        })
+    }
+}
+
+impl std::fmt::Debug for ReplicationMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicationMetrics").finish_non_exhaustive()
+    }
+}
+
+/// Size and freshness of the resource cache
+#[derive(Clone)]
+pub struct CacheMetrics {
+    pub resources: IntGauge,
+    pub locations: IntGauge,
+    /// Access key lookups by result (hit, miss, unknown)
+    pub key_lookups: IntCounterVec,
+    /// Unix time of the last full sync or event stream message, the sync age is
+    /// `time() - dataproxy_cache_last_sync_timestamp_seconds`
+    pub last_sync: IntGauge,
+}
+
+impl CacheMetrics {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        Ok(CacheMetrics {
+            resources: register_in(
+                registry,
+                IntGauge::new("cache_resources", "Resources in the cache"),
+            )?,
+            locations: register_in(
+                registry,
+                IntGauge::new("cache_locations", "Cached resources with a data location"),
+            )?,
+            key_lookups: register_in(
+                registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "cache_key_lookups_total",
+                        "Access key permission lookups by result (hit, miss, unknown)",
+                    ),
+                    &["result"],
+                ),
+            )?,
+            last_sync: register_in(
+                registry,
+                IntGauge::new(
+                    "cache_last_sync_timestamp_seconds",
+                    "Unix time of the last message received from the server",
+                ),
+            )?,
+        })
+    }
+}
+
+impl std::fmt::Debug for CacheMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheMetrics").finish_non_exhaustive()
+    }
+}
+
+/// Storage backend requests by configured backend and operation
+#[derive(Clone)]
+pub struct BackendMetrics {
+    pub duration: HistogramVec,
+    pub errors: IntCounterVec,
+}
+
+impl BackendMetrics {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        Ok(BackendMetrics {
+            duration: register_in(
+                registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "backend_operation_duration_seconds",
+                        "Duration of storage backend operations, reads include streaming the data",
+                    ),
+                    &["backend", "operation"],
+                ),
+            )?,
+            errors: register_in(
+                registry,
+                IntCounterVec::new(
+                    Opts::new("backend_errors_total", "Failed storage backend operations"),
+                    &["backend", "operation"],
+                ),
+            )?,
+        })
+    }
+}
+
+impl std::fmt::Debug for BackendMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackendMetrics").finish_non_exhaustive()
+    }
+}
+
+fn register_in<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    collector: prometheus::Result<T>,
+) -> prometheus::Result<T> {
+    let collector = collector?;
+    registry.register(Box::new(collector.clone()))?;
+    Ok(collector)
+}
+
+/// Serves the registry of the current dataproxy in the Prometheus text format on
+/// `/metrics` and its readiness on `/ready`
+#[tracing::instrument(level = "trace", skip(shutdown))]
+pub async fn serve(address: &str, shutdown: Shutdown) -> Result<()> {
+    let address = address.parse::<SocketAddr>().map_err(|e| {
+        error!(error = ?e, msg = e.to_string());
+        e
+    })?;
+    // hyper drives every connection in a task of its own
+    let state = scoped_config::state();
+    let service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+            Ok::<_, Infallible>(handle(req, state))
+        }))
+    });
+    info!("metrics are served at http://{}/metrics", address);
+    Server::try_bind(&address)
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!(e)
+        })?
+        .serve(service)
+        .with_graceful_shutdown(shutdown.signaled())
+        .await
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!(e)
+        })
+}
+
+fn handle(req: Request<Body>, state: &ScopedState) -> Response<Body> {
+    match req.uri().path() {
+        "/metrics" => metrics_response(&state.metrics.registry),
+        "/ready" => readiness_response(&state.clock),
+        _ => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+/// Reasons why the proxy should not receive traffic, empty if it is ready
+pub fn not_ready_reasons(clock: &ClockSkew) -> Vec<String> {
+    clock
+        .excessive_origins()
+        .into_iter()
+        .map(|(origin, skew)| format!("clock_skew: {origin} clock differs by {skew}s"))
+        .collect()
+}
+
+/// 200 if ready, 503 with one not-ready reason per line otherwise
+fn readiness_response(clock: &ClockSkew) -> Response<Body> {
+    let reasons = not_ready_reasons(clock);
+    if reasons.is_empty() {
+        return Response::new(Body::from("ready\n"));
+    }
+    let mut response = Response::new(Body::from(reasons.join("\n") + "\n"));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response
+}
+
+fn metrics_response(registry: &Registry) -> Response<Body> {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&registry.gather(), &mut buffer) {
+        error!(error = ?e, msg = e.to_string());
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        return response;
+    }
+    Response::builder()
+        .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::clock::{ClockSource, ARUNA_ORIGIN};
+    use std::time::{Duration, SystemTime};
+
+    struct FixedClock(SystemTime);
+
+    impl ClockSource for FixedClock {
+        fn now(&self) -> SystemTime {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_readiness_reports_clock_skew() {
+        let local = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = ClockSkew::new(Box::new(FixedClock(local)));
+        assert_eq!(readiness_response(&clock).status(), StatusCode::OK);
+
+        clock.record(local - Duration::from_secs(20 * 60), ARUNA_ORIGIN);
+        assert_eq!(
+            not_ready_reasons(&clock),
+            vec!["clock_skew: aruna clock differs by -1200s".to_string()]
+        );
+        assert_eq!(
+            readiness_response(&clock).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        clock.record(local, ARUNA_ORIGIN);
+        assert_eq!(readiness_response(&clock).status(), StatusCode::OK);
+    }
+}
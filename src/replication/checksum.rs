@@ -0,0 +1,93 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+/// Metadata key used to negotiate the chunk checksum algorithm.
+/// The pulling proxy offers its supported algorithms in the request metadata,
+/// the sending proxy answers with the selected one in the response metadata.
+/// Peers without this key are legacy peers and only speak MD5.
+pub const CHECKSUM_CAPABILITY_KEY: &str = "x-aruna-replication-checksums";
+
+const SHA256_PREFIX: &str = "sha256:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    #[default]
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// All supported algorithms ordered by preference
+    pub fn supported() -> String {
+        [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Md5]
+            .iter()
+            .map(|alg| alg.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Selects the strongest algorithm offered by the peer, MD5 for legacy peers
+    pub fn negotiate(offer: Option<&str>) -> Self {
+        match offer {
+            Some(offer)
+                if offer
+                    .split(',')
+                    .any(|alg| alg.trim().eq_ignore_ascii_case("sha256")) =>
+            {
+                ChecksumAlgorithm::Sha256
+            }
+            _ => ChecksumAlgorithm::Md5,
+        }
+    }
+
+    pub fn from_metadata(metadata: &MetadataMap) -> Self {
+        Self::negotiate(
+            metadata
+                .get(CHECKSUM_CAPABILITY_KEY)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    pub fn offer_to_metadata(metadata: &mut MetadataMap) {
+        if let Ok(value) = MetadataValue::try_from(Self::supported()) {
+            metadata.insert(CHECKSUM_CAPABILITY_KEY, value);
+        }
+    }
+
+    pub fn add_to_metadata(&self, metadata: &mut MetadataMap) {
+        metadata.insert(
+            CHECKSUM_CAPABILITY_KEY,
+            MetadataValue::from_static(self.as_str()),
+        );
+    }
+
+    /// Detects the algorithm of a chunk checksum, legacy checksums are plain MD5 hex
+    pub fn from_checksum(checksum: &str) -> Self {
+        if checksum.starts_with(SHA256_PREFIX) {
+            ChecksumAlgorithm::Sha256
+        } else {
+            ChecksumAlgorithm::Md5
+        }
+    }
+
+    pub fn checksum(&self, data: &[u8]) -> String {
+        match self {
+            ChecksumAlgorithm::Md5 => hex::encode(Md5::digest(data)),
+            ChecksumAlgorithm::Sha256 => {
+                format!("{SHA256_PREFIX}{}", hex::encode(Sha256::digest(data)))
+            }
+        }
+    }
+
+    pub fn verify(&self, checksum: &str, data: &[u8]) -> bool {
+        Self::from_checksum(checksum) == *self && self.checksum(data) == checksum
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+/// Metadata key used to negotiate the replication chunk size.
+/// The pulling proxy offers its configured chunk size in the request metadata,
+/// the sending proxy answers with the (clamped) size it actually uses.
+/// Peers without this key are legacy peers and send one encrypted block per chunk.
+pub const CHUNK_SIZE_KEY: &str = "x-aruna-replication-chunk-size";
+
+/// Size of one encrypted block (data + nonce + mac)
+pub const BLOCK_SIZE: usize = 65536 + 28;
+/// Largest supported chunk size, limits the size of a single gRPC message
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+/// Decoding limit for replication messages, max chunk + message overhead
+pub const MAX_MESSAGE_SIZE: usize = MAX_CHUNK_SIZE + 1024 * 1024;
+
+/// Chunk size in whole encrypted blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSize {
+    blocks: usize,
+}
+
+impl Default for ChunkSize {
+    fn default() -> Self {
+        ChunkSize::LEGACY
+    }
+}
+
+impl ChunkSize {
+    pub const LEGACY: ChunkSize = ChunkSize { blocks: 1 };
+
+    /// Rounds the size down to whole blocks within [BLOCK_SIZE, MAX_CHUNK_SIZE]
+    pub fn from_bytes(bytes: usize) -> Self {
+        ChunkSize {
+            blocks: bytes.clamp(BLOCK_SIZE, MAX_CHUNK_SIZE) / BLOCK_SIZE,
+        }
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.blocks * BLOCK_SIZE
+    }
+
+    /// Number of data chunks needed to transfer the given number of blocks
+    pub fn chunk_count(&self, blocks: usize) -> usize {
+        blocks.div_ceil(self.blocks)
+    }
+
+    /// Channel capacity that keeps at most `max_bytes` of chunks buffered
+    pub fn channel_capacity(&self, max_bytes: usize) -> usize {
+        (max_bytes / self.bytes()).max(1)
+    }
+
+    /// Reads the chunk size from the metadata, legacy peers use one block per chunk
+    pub fn from_metadata(metadata: &MetadataMap) -> Self {
+        metadata
+            .get(CHUNK_SIZE_KEY)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .map(ChunkSize::from_bytes)
+            .unwrap_or(ChunkSize::LEGACY)
+    }
+
+    pub fn add_to_metadata(&self, metadata: &mut MetadataMap) {
+        if let Ok(value) = MetadataValue::try_from(self.bytes().to_string()) {
+            metadata.insert(CHUNK_SIZE_KEY, value);
+        }
+    }
+}
+
+/// Byte budget for the chunks of a replication stream that are received but not yet
+/// written to the backend
+///
+/// When the budget is exhausted the response stream is no longer polled, which
+/// applies backpressure to the sending proxy.
+#[derive(Debug, Clone)]
+pub struct InflightBytes {
+    semaphore: Arc<Semaphore>,
+    max_bytes: usize,
+}
+
+impl InflightBytes {
+    pub fn new(max_bytes: usize) -> Self {
+        let max_bytes = max_bytes.clamp(1, Semaphore::MAX_PERMITS.min(u32::MAX as usize));
+        InflightBytes {
+            semaphore: Arc::new(Semaphore::new(max_bytes)),
+            max_bytes,
+        }
+    }
+
+    /// Waits until the chunk fits into the budget, the bytes are released with the
+    /// permit
+    ///
+    /// A chunk larger than the whole budget waits until all other chunks are
+    /// released and is then buffered alone instead of waiting forever.
+    pub async fn acquire(&self, len: usize) -> Result<OwnedSemaphorePermit> {
+        Ok(self
+            .semaphore
+            .clone()
+            .acquire_many_owned(len.min(self.max_bytes) as u32)
+            .await?)
+    }
+
+    /// Bytes currently buffered
+    pub fn buffered(&self) -> usize {
+        self.max_bytes - self.semaphore.available_permits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_slow_backend_bounded_memory() {
+        let budget = InflightBytes::new(4 * BLOCK_SIZE);
+        let chunk_size = ChunkSize::from_bytes(BLOCK_SIZE);
+        // Unbounded, only the budget limits the buffered chunks
+        let (sender, receiver) = async_channel::unbounded::<(Vec<u8>, OwnedSemaphorePermit)>();
+        let max_buffered = Arc::new(AtomicUsize::new(0));
+
+        // Slow backend, every chunk takes a while to be written
+        let backend = tokio::spawn(async move {
+            let mut written = 0;
+            while let Ok((data, permit)) = receiver.recv().await {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                written += data.len();
+                drop(permit);
+            }
+            written
+        });
+
+        let stream = {
+            let budget = budget.clone();
+            let max_buffered = max_buffered.clone();
+            async move {
+                for _ in 0..50 {
+                    let data = vec![0u8; chunk_size.bytes()];
+                    let permit = budget.acquire(data.len()).await.unwrap();
+                    max_buffered.fetch_max(budget.buffered(), Ordering::Relaxed);
+                    sender.send((data, permit)).await.unwrap();
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), stream)
+            .await
+            .unwrap();
+        assert_eq!(backend.await.unwrap(), 50 * chunk_size.bytes());
+        assert!(max_buffered.load(Ordering::Relaxed) <= 4 * BLOCK_SIZE);
+        assert_eq!(budget.buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_larger_than_budget() {
+        let budget = InflightBytes::new(BLOCK_SIZE);
+        let chunk = ChunkSize::from_bytes(MAX_CHUNK_SIZE).bytes();
+
+        let first = tokio::time::timeout(Duration::from_secs(1), budget.acquire(chunk))
+            .await
+            .expect("Oversized chunk must not wait forever")
+            .unwrap();
+        assert_eq!(budget.buffered(), BLOCK_SIZE);
+        // The next chunk waits for the first one to be written
+        let second = tokio::spawn({
+            let budget = budget.clone();
+            async move { budget.acquire(chunk).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(1), second)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use crate::caching::cache::Cache;
+use crate::structs::{Endpoint, Object, SyncStatus, SyncVariant};
+use crate::CONFIG;
+use anyhow::{anyhow, Result};
+use aruna_rust_api::api::dataproxy::services::v2::DataInfo;
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+use tracing::{error, trace};
+
+/// Where the pulling proxy can fetch the data of an object from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationSource {
+    /// Data is stored on this proxy and can be pulled directly
+    Local {
+        raw_size: i64,
+        compressed_size: i64,
+        // Number of encrypted blocks in the blocklist
+        blocks: usize,
+    },
+    /// This proxy only holds the metadata, the data should be pulled from this endpoint
+    Redirect(DieselUlid),
+    /// Neither this proxy nor any known endpoint holds the data,
+    /// the puller should ask a different endpoint instead of failing
+    Unavailable,
+}
+
+#[derive(Debug, Clone)]
+pub struct InitReplicationInfo {
+    pub object_id: DieselUlid,
+    pub source: ReplicationSource,
+}
+
+impl InitReplicationInfo {
+    /// Data info for the pulling proxy, None for unavailable objects
+    ///
+    /// The url names the endpoint to pull from (`dataproxy://<endpoint id>`), its
+    /// address is resolved via the server. Keys are never shared, sizes and blocks
+    /// are announced again in the ObjectInfo of the PullReplication stream.
+    pub fn to_data_info(&self) -> Option<DataInfo> {
+        let endpoint_id = match self.source {
+            ReplicationSource::Local { .. } => CONFIG.proxy.endpoint_id,
+            ReplicationSource::Redirect(endpoint_id) => endpoint_id,
+            ReplicationSource::Unavailable => return None,
+        };
+        Some(DataInfo {
+            object_id: self.object_id.to_string(),
+            download_url: format!("dataproxy://{endpoint_id}"),
+            ..Default::default()
+        })
+    }
+}
+
+/// Answers InitReplication requests of other proxies, used by partial-synced
+/// proxies to redirect pullers to the origin of the data
+pub struct InitReplicationHandler {
+    cache: Arc<Cache>,
+}
+
+impl InitReplicationHandler {
+    pub fn new(cache: Arc<Cache>) -> Self {
+        Self { cache }
+    }
+
+    /// Returns the replication source of every requested object,
+    /// the token needs a DpExchange intent targeting this proxy
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn init_replication(
+        &self,
+        token: &str,
+        object_ids: Vec<DieselUlid>,
+    ) -> Result<Vec<InitReplicationInfo>> {
+        let requester = match self.cache.auth.read().await.as_ref() {
+            Some(auth) => {
+                let (endpoint_id, _) = auth.check_dataproxy_token(token).await.map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    anyhow!("DataProxy not authenticated")
+                })?;
+                endpoint_id
+            }
+            None => {
+                error!("authentication handler not available");
+                return Err(anyhow!("DataProxy not authenticated"));
+            }
+        };
+        self.sources(requester, object_ids).await
+    }
+
+    /// Replication sources of the objects for an authenticated endpoint
+    async fn sources(
+        &self,
+        endpoint_id: DieselUlid,
+        object_ids: Vec<DieselUlid>,
+    ) -> Result<Vec<InitReplicationInfo>> {
+        let mut infos = Vec::with_capacity(object_ids.len());
+        for object_id in object_ids {
+            let source = match self.cache.get_resource_cloned(&object_id, false).await {
+                Ok((object, location)) => {
+                    // Same check as in PullReplication, the caller must be an endpoint of the object
+                    if !object.endpoints.iter().any(|ep| ep.id == endpoint_id) {
+                        error!(?object_id, "Unauthorized DataProxy request");
+                        return Err(anyhow!(
+                            "DataProxy is not allowed to access requested objects"
+                        ));
+                    }
+                    match location {
+                        Some(location)
+                            if !location.is_temporary && location.upload_id.is_none() =>
+                        {
+                            ReplicationSource::Local {
+                                raw_size: location.raw_content_len,
+                                compressed_size: location.disk_content_len,
+                                blocks: location.count_blocks(),
+                            }
+                        }
+                        _ => Self::find_redirect(&object, endpoint_id)
+                            .map(ReplicationSource::Redirect)
+                            .unwrap_or(ReplicationSource::Unavailable),
+                    }
+                }
+                Err(_) => ReplicationSource::Unavailable,
+            };
+            trace!(?object_id, ?source);
+            infos.push(InitReplicationInfo { object_id, source });
+        }
+        Ok(infos)
+    }
+
+    /// Picks another endpoint that should hold the data,
+    /// full-synced and finished endpoints are preferred
+    fn find_redirect(object: &Object, requester: DieselUlid) -> Option<DieselUlid> {
+        let self_id = CONFIG.proxy.endpoint_id;
+        object
+            .endpoints
+            .iter()
+            .filter(|ep| ep.id != self_id && ep.id != requester)
+            .filter(|ep| !matches!(ep.status, Some(SyncStatus::Error)))
+            .min_by_key(|ep| Self::redirect_rank(ep))
+            .map(|ep| ep.id)
+    }
+
+    fn redirect_rank(endpoint: &Endpoint) -> (bool, bool) {
+        (
+            endpoint.status != Some(SyncStatus::Finished),
+            endpoint.variant != SyncVariant::FullSync,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::structs::{ObjectLocation, ObjectType, TypedRelation};
+
+    fn endpoint(id: DieselUlid, variant: SyncVariant, status: SyncStatus) -> Endpoint {
+        Endpoint {
+            id,
+            variant,
+            status: Some(status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sources() {
+        let (cache, _receiver) = test_cache(None).await;
+        let (requester, partial, origin) = (
+            DieselUlid::generate(),
+            DieselUlid::generate(),
+            DieselUlid::generate(),
+        );
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let object = |name: &str, endpoints: Vec<Endpoint>| Object {
+            endpoints,
+            ..Object::initialize_now(
+                name.to_string(),
+                ObjectType::Object,
+                Some(TypedRelation::Project(project.id)),
+            )
+        };
+        let own = |status| {
+            endpoint(
+                CONFIG.proxy.endpoint_id,
+                SyncVariant::PartialSync(false),
+                status,
+            )
+        };
+        let peer = endpoint(requester, SyncVariant::FullSync, SyncStatus::Waiting);
+        let local = object("local", vec![own(SyncStatus::Finished), peer.clone()]);
+        // Metadata only, the finished full-sync origin is preferred over a partial copy
+        let redirected = object(
+            "redirected",
+            vec![
+                own(SyncStatus::Waiting),
+                peer.clone(),
+                endpoint(
+                    partial,
+                    SyncVariant::PartialSync(false),
+                    SyncStatus::Finished,
+                ),
+                endpoint(origin, SyncVariant::FullSync, SyncStatus::Finished),
+            ],
+        );
+        let unavailable = object(
+            "unavailable",
+            vec![
+                own(SyncStatus::Waiting),
+                peer.clone(),
+                endpoint(origin, SyncVariant::FullSync, SyncStatus::Error),
+            ],
+        );
+        let forbidden = object("forbidden", vec![own(SyncStatus::Finished)]);
+        cache.upsert_object(project).await.unwrap();
+        for object in [&local, &redirected, &unavailable, &forbidden] {
+            cache.upsert_object(object.clone()).await.unwrap();
+        }
+        cache
+            .add_location_with_binding(
+                local.id,
+                ObjectLocation {
+                    raw_content_len: 10,
+                    disk_content_len: 38,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let handler = InitReplicationHandler::new(cache);
+        let unknown = DieselUlid::generate();
+        let infos = handler
+            .sources(
+                requester,
+                vec![local.id, redirected.id, unavailable.id, unknown],
+            )
+            .await
+            .unwrap();
+        let sources = infos
+            .iter()
+            .map(|info| info.source.clone())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            sources[0],
+            ReplicationSource::Local {
+                raw_size: 10,
+                compressed_size: 38,
+                ..
+            }
+        ));
+        assert_eq!(sources[1], ReplicationSource::Redirect(origin));
+        assert_eq!(sources[2], ReplicationSource::Unavailable);
+        assert_eq!(sources[3], ReplicationSource::Unavailable);
+
+        let urls = infos
+            .iter()
+            .map(|info| info.to_data_info().map(|info| info.download_url))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            vec![
+                Some(format!("dataproxy://{}", CONFIG.proxy.endpoint_id)),
+                Some(format!("dataproxy://{origin}")),
+                None,
+                None
+            ]
+        );
+
+        // Endpoints that are not assigned to an object are rejected
+        assert!(handler
+            .sources(requester, vec![forbidden.id])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::replication::chunk_size::{ChunkSize, BLOCK_SIZE};
+use crate::structs::{CustomerKeyHash, ObjectLocation};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Rejected replicas are pulled again with the next batch up to this many times
+pub const MAX_INTEGRITY_RETRIES: u32 = 3;
+
+/// Hashes of a streamed object announced by the sending proxy in the `extra` field
+/// of its ObjectInfo. The pithos footer is rewritten for the receiving proxy, so only
+/// the hash of the raw content is stable between both sides.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedHashes {
+    pub raw_sha256: Option<String>,
+}
+
+impl ExpectedHashes {
+    pub fn from_location(location: &ObjectLocation) -> Self {
+        ExpectedHashes {
+            raw_sha256: location.raw_hashes.get("sha256").cloned(),
+        }
+    }
+
+    pub fn to_extra(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+
+    /// Older proxies send no hashes, a `CopySource` is never parsed as hashes
+    pub fn from_extra(extra: Option<&str>) -> Self {
+        extra
+            .and_then(|extra| serde_json::from_str(extra).ok())
+            .unwrap_or_default()
+    }
+
+    /// Compares the calculated raw hash with the hash recorded by the server and the
+    /// hash announced by the sender, returns a description of the first mismatch
+    ///
+    /// Objects without a server recorded hash (e.g. finished by older proxies) are only
+    /// compared with the announced hash, unless `strict` requires the server hash.
+    pub fn verify(
+        &self,
+        calculated: &str,
+        server_sha256: Option<&str>,
+        strict: bool,
+    ) -> Result<(), String> {
+        match server_sha256 {
+            Some(expected) if expected != calculated => {
+                return Err(format!(
+                    "Replicated data does not match server recorded hash (expected {expected}, calculated {calculated})"
+                ))
+            }
+            Some(_) => {}
+            None if strict => {
+                return Err("Server has no sha256 recorded for the object".to_string())
+            }
+            None => warn!(
+                announced = self.raw_sha256.as_deref(),
+                "Server has no sha256 recorded for the object, falling back to the hash announced by the sending proxy"
+            ),
+        }
+        match &self.raw_sha256 {
+            Some(announced) if announced != calculated => Err(format!(
+                "Replicated data does not match hash announced by the sending proxy (announced {announced}, calculated {calculated})"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Announcement of an object encrypted with a customer provided key (SSE-C). The proxy
+/// cannot decrypt the data, the stored ciphertext is streamed as-is without a pithos
+/// footer and verified by its size and disk hash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomerEncryptedData {
+    pub customer_key: CustomerKeyHash,
+    pub compressed: bool,
+    pub raw_content_len: i64,
+    pub disk_content_len: i64,
+    pub disk_hash: Option<String>,
+}
+
+impl CustomerEncryptedData {
+    pub fn from_location(location: &ObjectLocation) -> Option<Self> {
+        Some(CustomerEncryptedData {
+            customer_key: location.customer_key.clone()?,
+            compressed: location.is_compressed(),
+            raw_content_len: location.raw_content_len,
+            disk_content_len: location.disk_content_len,
+            disk_hash: location.disk_hash.clone(),
+        })
+    }
+
+    pub fn to_extra(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+
+    pub fn from_extra(extra: Option<&str>) -> Option<Self> {
+        serde_json::from_str(extra?).ok()
+    }
+
+    /// Full blocks of the ciphertext, the remainder is sent with the last chunk
+    pub fn blocks(&self) -> usize {
+        self.disk_content_len.max(0) as usize / BLOCK_SIZE
+    }
+
+    pub fn chunk_count(&self, chunk_size: ChunkSize) -> usize {
+        let remainder = self.disk_content_len.max(0) as usize % BLOCK_SIZE;
+        chunk_size.chunk_count(self.blocks()) + usize::from(remainder != 0)
+    }
+
+    /// Compares the stored ciphertext with the announcement, returns a description
+    /// of the first mismatch
+    pub fn verify(&self, disk_len: i64, disk_sha256: &str) -> Result<(), String> {
+        if disk_len != self.disk_content_len {
+            return Err(format!(
+                "Replicated ciphertext size {disk_len} does not match announced size {}",
+                self.disk_content_len
+            ));
+        }
+        match &self.disk_hash {
+            Some(announced) if announced != disk_sha256 => Err(format!(
+                "Replicated ciphertext does not match hash announced by the sending proxy (announced {announced}, calculated {disk_sha256})"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::server_copy::CopySource;
+    use crate::structs::FileFormat;
+
+    #[test]
+    fn test_extra() {
+        let hashes = ExpectedHashes {
+            raw_sha256: Some("abc".to_string()),
+        };
+        assert_eq!(
+            ExpectedHashes::from_extra(hashes.to_extra().as_deref()),
+            hashes
+        );
+        assert_eq!(ExpectedHashes::from_extra(None), ExpectedHashes::default());
+        // Copy sources and hashes are not mistaken for each other
+        let source = CopySource {
+            backend: "s3".to_string(),
+            bucket: "bucket".to_string(),
+            key: "key".to_string(),
+            file_format: FileFormat::Raw,
+            raw_content_len: 1,
+            disk_content_len: 1,
+            disk_hash: None,
+        };
+        assert_eq!(
+            ExpectedHashes::from_extra(source.to_extra().as_deref()),
+            ExpectedHashes::default()
+        );
+        assert!(CopySource::from_extra(hashes.to_extra().as_deref()).is_none());
+    }
+
+    #[test]
+    fn test_verify() {
+        let announced = ExpectedHashes {
+            raw_sha256: Some("abc".to_string()),
+        };
+        assert!(announced.verify("abc", Some("abc"), false).is_ok());
+        assert!(ExpectedHashes::default()
+            .verify("abc", Some("abc"), false)
+            .is_ok());
+        assert!(announced.verify("abc", Some("def"), false).is_err());
+        assert!(announced.verify("def", Some("def"), false).is_err());
+
+        // Without server hash the announced hash is used, strict mode rejects
+        assert!(announced.verify("abc", None, false).is_ok());
+        assert!(announced.verify("def", None, false).is_err());
+        assert!(announced.verify("abc", None, true).is_err());
+    }
+
+    #[test]
+    fn test_customer_encrypted() {
+        let location = ObjectLocation {
+            file_format: FileFormat::customer_encrypted(false),
+            customer_key: Some(CustomerKeyHash::new(&[1u8; 32])),
+            raw_content_len: 10,
+            disk_content_len: (BLOCK_SIZE * 2 + 10) as i64,
+            disk_hash: Some("abc".to_string()),
+            ..Default::default()
+        };
+        assert!(CustomerEncryptedData::from_location(&ObjectLocation::default()).is_none());
+        let data = CustomerEncryptedData::from_location(&location).unwrap();
+        assert_eq!(
+            CustomerEncryptedData::from_extra(data.to_extra().as_deref()),
+            Some(data.clone())
+        );
+        // Never mistaken for hashes, which would extract a footer
+        assert_eq!(
+            ExpectedHashes::from_extra(data.to_extra().as_deref()),
+            ExpectedHashes::default()
+        );
+        let hashes = ExpectedHashes::default();
+        assert!(CustomerEncryptedData::from_extra(hashes.to_extra().as_deref()).is_none());
+
+        assert_eq!(data.blocks(), 2);
+        assert_eq!(data.chunk_count(ChunkSize::LEGACY), 3);
+        assert_eq!(data.chunk_count(ChunkSize::from_bytes(BLOCK_SIZE * 4)), 2);
+
+        assert!(data.verify(location.disk_content_len, "abc").is_ok());
+        assert!(data.verify(location.disk_content_len, "def").is_err());
+        assert!(data.verify(1, "abc").is_err());
+    }
+}
@@ -1 +1,9 @@
+pub mod checksum;
+pub mod chunk_size;
+pub mod init;
+pub mod integrity;
+pub mod peers;
+pub mod progress;
 pub mod replication_handler;
+pub mod request;
+pub mod server_copy;
@@ -0,0 +1,309 @@
+use crate::config::{Peer, PeersSource};
+use crate::CONFIG;
+use anyhow::{anyhow, bail, Result};
+use diesel_ulid::DieselUlid;
+use http::uri::Scheme;
+use http::Uri;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tracing::{error, info};
+
+/// Responses of proxies to CONNECT requests are not expected to be larger
+const MAX_PROXY_RESPONSE: usize = 8192;
+
+/// Peer settings of the config, reloaded when the config file changes
+///
+/// Every connection to a peer checks the modification time of the file, changed settings
+/// are used by the next replication batch. On errors the previous settings stay active.
+pub struct PeerSettings {
+    source: Option<String>,
+    current: RwLock<(Arc<HashMap<DieselUlid, Peer>>, Option<SystemTime>)>,
+}
+
+impl PeerSettings {
+    pub fn new(peers: HashMap<DieselUlid, Peer>, source: Option<String>) -> Self {
+        let modified = source.as_deref().and_then(last_modified);
+        Self {
+            source,
+            current: RwLock::new((Arc::new(peers), modified)),
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(CONFIG.peers.clone(), CONFIG.source.clone())
+    }
+
+    pub fn get(&self, endpoint_id: &DieselUlid) -> Option<Peer> {
+        self.reload_if_changed();
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .0
+            .get(endpoint_id)
+            .cloned()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn reload_if_changed(&self) {
+        let Some(source) = &self.source else {
+            return;
+        };
+        let modified = last_modified(source);
+        if self.current.read().unwrap_or_else(|e| e.into_inner()).1 == modified {
+            return;
+        }
+        match PeersSource::from_file(source) {
+            Ok(PeersSource { peers }) => {
+                info!(count = peers.len(), source, "Reloaded peer settings");
+                *self.current.write().unwrap_or_else(|e| e.into_inner()) =
+                    (Arc::new(peers), modified);
+            }
+            Err(e) => {
+                error!(error = ?e, msg = "Unable to reload peer settings, keeping the previous ones");
+            }
+        }
+    }
+}
+
+fn last_modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Connection to a peer, the configured settings take precedence over the gRPC host
+/// config announced by the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerConnection {
+    pub url: String,
+    pub tls: bool,
+    pub ca_bundle: Option<String>,
+    pub tls_domain: Option<String>,
+    pub proxy: Option<String>,
+}
+
+impl PeerConnection {
+    pub fn resolve(announced: Option<(&str, bool)>, peer: Option<Peer>) -> Result<Self> {
+        let peer = peer.unwrap_or_default();
+        let (url, tls) = match (peer.url, announced) {
+            (Some(url), _) => {
+                let tls = url.starts_with("https");
+                (url, tls)
+            }
+            (None, Some((url, ssl))) => (url.to_string(), ssl),
+            (None, None) => bail!("No grpc config found for endpoint"),
+        };
+        let tls = peer.tls.unwrap_or(tls);
+        Ok(PeerConnection {
+            url: with_scheme(&url, tls)?,
+            tls,
+            ca_bundle: peer.ca_bundle,
+            tls_domain: peer.tls_domain,
+            proxy: peer.proxy,
+        })
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn connect(&self) -> Result<Channel> {
+        let mut endpoint = Endpoint::from_shared(self.url.clone())?;
+        if self.tls {
+            let mut tls = ClientTlsConfig::new();
+            if let Some(ca_bundle) = &self.ca_bundle {
+                let pem = std::fs::read(ca_bundle)
+                    .map_err(|e| anyhow!("Unable to read CA bundle {ca_bundle}: {e}"))?;
+                tls = tls.ca_certificate(Certificate::from_pem(pem));
+            }
+            if let Some(domain) = &self.tls_domain {
+                tls = tls.domain_name(domain);
+            }
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        let channel = match &self.proxy {
+            Some(proxy) => {
+                let proxy = proxy.parse::<Uri>()?;
+                endpoint
+                    .connect_with_connector(tower::service_fn(move |target: Uri| {
+                        connect_via_proxy(proxy.clone(), target)
+                    }))
+                    .await
+            }
+            None => endpoint.connect().await,
+        };
+        channel.map_err(|e| {
+            let via = self
+                .proxy
+                .as_ref()
+                .map(|proxy| format!(" via {proxy}"))
+                .unwrap_or_default();
+            anyhow!(
+                "Unable to connect to {}{via} (tls: {}): {e:?}",
+                self.url,
+                self.tls
+            )
+        })
+    }
+}
+
+/// The scheme decides whether the channel uses TLS
+fn with_scheme(url: &str, tls: bool) -> Result<String> {
+    let mut parts = url
+        .parse::<Uri>()
+        .map_err(|e| anyhow!("Invalid peer url {url}: {e}"))?
+        .into_parts();
+    parts.scheme = Some(if tls { Scheme::HTTPS } else { Scheme::HTTP });
+    if parts.path_and_query.is_none() {
+        parts.path_and_query = Some("/".parse()?);
+    }
+    Ok(Uri::from_parts(parts)?.to_string())
+}
+
+/// Opens a tunnel to the target through an HTTP proxy (CONNECT), TLS is established
+/// by the channel on top of the tunnel
+async fn connect_via_proxy(proxy: Uri, target: Uri) -> io::Result<TcpStream> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let proxy_host = proxy
+        .host()
+        .ok_or_else(|| invalid(format!("Proxy {proxy} has no host")))?;
+    let target_host = target
+        .host()
+        .ok_or_else(|| invalid(format!("Target {target} has no host")))?;
+    let target_port = target
+        .port_u16()
+        .unwrap_or(if target.scheme() == Some(&Scheme::HTTPS) {
+            443
+        } else {
+            80
+        });
+
+    let mut stream = TcpStream::connect((proxy_host, proxy.port_u16().unwrap_or(80))).await?;
+    stream
+        .write_all(
+            format!(
+                "CONNECT {target_host}:{target_port} HTTP/1.1\r\nHost: {target_host}:{target_port}\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    // Read byte-wise, data after the header belongs to the tunnel
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 || response.len() >= MAX_PROXY_RESPONSE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid response of proxy {proxy}"),
+            ));
+        }
+        response.push(byte[0]);
+    }
+    let status = String::from_utf8_lossy(&response);
+    let status = status.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(stream),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Proxy {proxy} refused the tunnel to {target_host}:{target_port}: {status}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_resolve() {
+        // Announced host config
+        let connection = PeerConnection::resolve(Some(("https://peer:50051", true)), None).unwrap();
+        assert_eq!(connection.url, "https://peer:50051/");
+        assert!(connection.tls);
+
+        // Plaintext on a private network
+        let peer = Peer {
+            url: Some("http://10.0.0.5:50051".to_string()),
+            ..Default::default()
+        };
+        let connection =
+            PeerConnection::resolve(Some(("https://peer:50051", true)), Some(peer)).unwrap();
+        assert_eq!(connection.url, "http://10.0.0.5:50051/");
+        assert!(!connection.tls);
+
+        // TLS with an internal CA on the announced url
+        let peer = Peer {
+            tls: Some(true),
+            ca_bundle: Some("/etc/dataproxy/internal-ca.pem".to_string()),
+            ..Default::default()
+        };
+        let connection =
+            PeerConnection::resolve(Some(("http://peer.internal:50051", false)), Some(peer))
+                .unwrap();
+        assert_eq!(connection.url, "https://peer.internal:50051/");
+        assert!(connection.tls);
+        assert_eq!(
+            connection.ca_bundle.as_deref(),
+            Some("/etc/dataproxy/internal-ca.pem")
+        );
+
+        assert!(PeerConnection::resolve(None, None).is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("peers-{}.toml", DieselUlid::generate()));
+        let id = DieselUlid::generate();
+        let write = |url: &str| {
+            std::fs::write(&path, format!("[peers.{id}]\nurl=\"{url}\"\n")).unwrap();
+        };
+        write("http://first:50051");
+        let settings = PeerSettings::new(HashMap::new(), Some(path.to_string_lossy().to_string()));
+        assert!(settings.get(&id).is_none());
+
+        // Force a different modification time
+        settings.current.write().unwrap().1 = None;
+        write("http://second:50051");
+        assert_eq!(
+            settings.get(&id).and_then(|peer| peer.url).as_deref(),
+            Some("http://second:50051")
+        );
+
+        // Invalid files keep the previous settings
+        settings.current.write().unwrap().1 = None;
+        std::fs::write(&path, "[peers.invalid").unwrap();
+        assert!(settings.get(&id).is_some());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_connect_via_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            assert!(request.starts_with(b"CONNECT peer.internal:50051 HTTP/1.1\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunneled")
+                .await
+                .unwrap();
+        });
+
+        let mut stream = connect_via_proxy(proxy, "https://peer.internal:50051".parse().unwrap())
+            .await
+            .unwrap();
+        let mut tunneled = String::new();
+        stream.read_to_string(&mut tunneled).await.unwrap();
+        assert_eq!(tunneled, "tunneled");
+    }
+}
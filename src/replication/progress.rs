@@ -0,0 +1,399 @@
+use crate::metrics::{ReplicationMetrics, REPLICATION_METRICS};
+use crate::replication::replication_handler::Direction;
+use ahash::RandomState;
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use std::sync::Arc;
+
+/// Permanently failed replications are kept for this long
+pub const FAILED_RETENTION_HOURS: i64 = 24;
+
+/// Progress of a single object in the replication queue
+#[derive(Debug, Clone)]
+pub struct ObjectProgress {
+    pub endpoint_id: DieselUlid,
+    pub direction: Direction,
+    pub expected_chunks: Option<i64>,
+    pub received_chunks: i64,
+    pub bytes_written: u64,
+    pub last_error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A replication that failed permanently and needs a user initiated retry
+#[derive(Debug, Clone)]
+pub struct FailedReplication {
+    pub endpoint_id: DieselUlid,
+    pub direction: Direction,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Combined state of the objects of a user initiated replication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestState {
+    /// No object was started yet
+    Pending,
+    Running,
+    Finished,
+    /// At least one object failed permanently, contains the first failure
+    Error(String),
+}
+
+/// Objects of a user initiated replication
+#[derive(Debug, Clone)]
+struct TrackedRequest {
+    user_id: DieselUlid,
+    endpoint_id: DieselUlid,
+    objects: Vec<DieselUlid>,
+    created_at: DateTime<Utc>,
+}
+
+impl ObjectProgress {
+    /// Queued objects become in progress with the first infos or chunk of the sender
+    fn state(&self) -> &'static str {
+        if self.expected_chunks.is_some() || self.received_chunks > 0 {
+            "in_progress"
+        } else {
+            "queued"
+        }
+    }
+}
+
+/// Shared replication progress, written by the replication handler
+/// and read by the replication service
+#[derive(Debug)]
+pub struct ReplicationProgress {
+    // (endpoint_id, object_id) -> progress
+    running: DashMap<(DieselUlid, DieselUlid), ObjectProgress, RandomState>,
+    failed: DashMap<(DieselUlid, DieselUlid), FailedReplication, RandomState>,
+    // replication_id -> requested objects
+    requests: DashMap<DieselUlid, TrackedRequest, RandomState>,
+    metrics: ReplicationMetrics,
+}
+
+impl Default for ReplicationProgress {
+    fn default() -> Self {
+        Self::with_metrics(REPLICATION_METRICS.clone())
+    }
+}
+
+impl ReplicationProgress {
+    pub fn with_metrics(metrics: ReplicationMetrics) -> Self {
+        ReplicationProgress {
+            running: DashMap::default(),
+            failed: DashMap::default(),
+            requests: DashMap::default(),
+            metrics,
+        }
+    }
+
+    pub fn queue(&self, endpoint_id: DieselUlid, direction: Direction) {
+        let object_id = direction.get_id();
+        self.running
+            .entry((endpoint_id, object_id))
+            .or_insert_with(|| {
+                self.gauge(endpoint_id, "queued").inc();
+                ObjectProgress {
+                    endpoint_id,
+                    direction,
+                    expected_chunks: None,
+                    received_chunks: 0,
+                    bytes_written: 0,
+                    last_error: None,
+                    updated_at: Utc::now(),
+                }
+            });
+    }
+
+    pub fn is_queued(&self, endpoint_id: DieselUlid, object_id: DieselUlid) -> bool {
+        self.running.contains_key(&(endpoint_id, object_id))
+    }
+
+    pub fn set_expected_chunks(&self, endpoint_id: DieselUlid, object_id: DieselUlid, chunks: i64) {
+        if let Some(mut progress) = self.running.get_mut(&(endpoint_id, object_id)) {
+            let state = progress.state();
+            progress.expected_chunks = Some(chunks);
+            progress.updated_at = Utc::now();
+            self.transition(endpoint_id, state, progress.state());
+        }
+    }
+
+    pub fn chunk_received(&self, endpoint_id: DieselUlid, object_id: DieselUlid, bytes: u64) {
+        self.bytes_replicated(endpoint_id, bytes);
+        if let Some(mut progress) = self.running.get_mut(&(endpoint_id, object_id)) {
+            let state = progress.state();
+            progress.received_chunks += 1;
+            progress.bytes_written += bytes;
+            progress.updated_at = Utc::now();
+            self.transition(endpoint_id, state, progress.state());
+        }
+    }
+
+    /// Data that was not streamed chunk by chunk, e.g. a server side copy
+    pub fn bytes_replicated(&self, endpoint_id: DieselUlid, bytes: u64) {
+        self.metrics
+            .bytes
+            .with_label_values(&[&endpoint_id.to_string()])
+            .inc_by(bytes);
+    }
+
+    /// Records a chunk that is requested again from the sender
+    pub fn chunk_retried(&self, endpoint_id: DieselUlid, object_id: DieselUlid, error: String) {
+        self.metrics
+            .chunk_retries
+            .with_label_values(&[&endpoint_id.to_string()])
+            .inc();
+        self.set_error(endpoint_id, object_id, error);
+    }
+
+    /// Records a recoverable error (e.g. a retried chunk)
+    pub fn set_error(&self, endpoint_id: DieselUlid, object_id: DieselUlid, error: String) {
+        if let Some(mut progress) = self.running.get_mut(&(endpoint_id, object_id)) {
+            progress.last_error = Some(error);
+            progress.updated_at = Utc::now();
+        }
+    }
+
+    pub fn finish(&self, endpoint_id: DieselUlid, object_id: DieselUlid) {
+        self.dequeue(endpoint_id, object_id, "finished");
+        self.failed.remove(&(endpoint_id, object_id));
+    }
+
+    /// Moves the object from the running to the failed replications
+    pub fn fail(&self, endpoint_id: DieselUlid, object_id: DieselUlid, error: String) {
+        let direction = self
+            .dequeue(endpoint_id, object_id, "failed")
+            .map(|progress| progress.direction)
+            .unwrap_or(Direction::Pull(object_id));
+        self.failed.insert(
+            (endpoint_id, object_id),
+            FailedReplication {
+                endpoint_id,
+                direction,
+                error,
+                failed_at: Utc::now(),
+            },
+        );
+        self.prune();
+    }
+
+    /// Objects currently in the replication queue
+    pub fn get_running(&self) -> Vec<(DieselUlid, ObjectProgress)> {
+        self.running
+            .iter()
+            .map(|entry| (entry.key().1, entry.value().clone()))
+            .collect()
+    }
+
+    /// Objects that failed permanently in the last `hours` hours
+    pub fn get_failed(&self, hours: i64) -> Vec<(DieselUlid, FailedReplication)> {
+        let since = Utc::now() - Duration::hours(hours);
+        self.failed
+            .iter()
+            .filter(|entry| entry.value().failed_at >= since)
+            .map(|entry| (entry.key().1, entry.value().clone()))
+            .collect()
+    }
+
+    /// Tracks the objects of a user initiated replication, returns the replication id
+    pub fn track_request(
+        &self,
+        user_id: DieselUlid,
+        endpoint_id: DieselUlid,
+        objects: Vec<DieselUlid>,
+    ) -> DieselUlid {
+        let since = Utc::now() - Duration::hours(FAILED_RETENTION_HOURS);
+        self.requests
+            .retain(|_, request| request.created_at >= since);
+        let replication_id = DieselUlid::generate();
+        self.requests.insert(
+            replication_id,
+            TrackedRequest {
+                user_id,
+                endpoint_id,
+                objects,
+                created_at: Utc::now(),
+            },
+        );
+        replication_id
+    }
+
+    /// State of a replication requested by the user, None for unknown (or expired)
+    /// replication ids and replications of other users
+    pub fn request_state(
+        &self,
+        replication_id: &DieselUlid,
+        user_id: &DieselUlid,
+    ) -> Option<RequestState> {
+        let request = self
+            .requests
+            .get(replication_id)
+            .filter(|request| request.user_id == *user_id)?
+            .clone();
+        let (mut queued, mut in_progress, mut done) = (0, 0, 0);
+        for object_id in request.objects {
+            let key = (request.endpoint_id, object_id);
+            if let Some(failed) = self.failed.get(&key) {
+                return Some(RequestState::Error(format!(
+                    "{object_id}: {}",
+                    failed.error
+                )));
+            }
+            match self.running.get(&key).map(|progress| progress.state()) {
+                Some("queued") => queued += 1,
+                Some(_) => in_progress += 1,
+                None => done += 1,
+            }
+        }
+        Some(if in_progress > 0 || (queued > 0 && done > 0) {
+            RequestState::Running
+        } else if queued > 0 {
+            RequestState::Pending
+        } else {
+            RequestState::Finished
+        })
+    }
+
+    fn dequeue(
+        &self,
+        endpoint_id: DieselUlid,
+        object_id: DieselUlid,
+        result: &str,
+    ) -> Option<ObjectProgress> {
+        let (_, progress) = self.running.remove(&(endpoint_id, object_id))?;
+        self.gauge(endpoint_id, progress.state()).dec();
+        self.metrics
+            .completed
+            .with_label_values(&[&endpoint_id.to_string(), result])
+            .inc();
+        Some(progress)
+    }
+
+    fn transition(&self, endpoint_id: DieselUlid, from: &str, to: &str) {
+        if from != to {
+            self.gauge(endpoint_id, from).dec();
+            self.gauge(endpoint_id, to).inc();
+        }
+    }
+
+    fn gauge(&self, endpoint_id: DieselUlid, state: &str) -> prometheus::IntGauge {
+        self.metrics
+            .objects
+            .with_label_values(&[&endpoint_id.to_string(), state])
+    }
+
+    fn prune(&self) {
+        let since = Utc::now() - Duration::hours(FAILED_RETENTION_HOURS);
+        self.failed.retain(|_, failed| failed.failed_at >= since);
+    }
+}
+
+/// Progress handle for a single object
+#[derive(Debug, Clone)]
+pub struct ObjectProgressHandle {
+    pub progress: Arc<ReplicationProgress>,
+    pub endpoint_id: DieselUlid,
+    pub object_id: DieselUlid,
+}
+
+impl ObjectProgressHandle {
+    pub fn chunk_received(&self, bytes: u64) {
+        self.progress
+            .chunk_received(self.endpoint_id, self.object_id, bytes);
+    }
+
+    pub fn set_error(&self, error: impl Into<String>) {
+        self.progress
+            .set_error(self.endpoint_id, self.object_id, error.into());
+    }
+
+    pub fn chunk_retried(&self, error: impl Into<String>) {
+        self.progress
+            .chunk_retried(self.endpoint_id, self.object_id, error.into());
+    }
+
+    pub fn bytes_replicated(&self, bytes: u64) {
+        self.progress.bytes_replicated(self.endpoint_id, bytes);
+    }
+
+    pub fn finish(&self) {
+        self.progress.finish(self.endpoint_id, self.object_id);
+    }
+
+    pub fn fail(&self, error: impl Into<String>) {
+        self.progress
+            .fail(self.endpoint_id, self.object_id, error.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    #[test]
+    fn test_metrics() {
+        let registry = Registry::new();
+        let metrics = ReplicationMetrics::new(&registry).unwrap();
+        let progress = ReplicationProgress::with_metrics(metrics.clone());
+        let endpoint = DieselUlid::generate();
+        let label = endpoint.to_string();
+        let gauge = |state| metrics.objects.with_label_values(&[&label, state]).get();
+        let completed = |result| metrics.completed.with_label_values(&[&label, result]).get();
+
+        let (first, second) = (DieselUlid::generate(), DieselUlid::generate());
+        progress.queue(endpoint, Direction::Pull(first));
+        progress.queue(endpoint, Direction::Pull(first));
+        progress.queue(endpoint, Direction::Pull(second));
+        assert_eq!(gauge("queued"), 2);
+
+        progress.set_expected_chunks(endpoint, first, 2);
+        progress.chunk_received(endpoint, first, 10);
+        progress.chunk_retried(endpoint, first, "Missing chunk 1, retrying".to_string());
+        progress.chunk_received(endpoint, first, 5);
+        assert_eq!((gauge("queued"), gauge("in_progress")), (1, 1));
+        assert_eq!(metrics.bytes.with_label_values(&[&label]).get(), 15);
+        assert_eq!(metrics.chunk_retries.with_label_values(&[&label]).get(), 1);
+
+        progress.finish(endpoint, first);
+        progress.fail(endpoint, second, "failed".to_string());
+        // Unknown objects are not counted twice
+        progress.finish(endpoint, first);
+        assert_eq!((gauge("queued"), gauge("in_progress")), (0, 0));
+        assert_eq!((completed("finished"), completed("failed")), (1, 1));
+    }
+
+    #[test]
+    fn test_request_state() {
+        let progress =
+            ReplicationProgress::with_metrics(ReplicationMetrics::new(&Registry::new()).unwrap());
+        let (user, endpoint) = (DieselUlid::generate(), DieselUlid::generate());
+        let (first, second) = (DieselUlid::generate(), DieselUlid::generate());
+        progress.queue(endpoint, Direction::Pull(first));
+        progress.queue(endpoint, Direction::Pull(second));
+        let id = progress.track_request(user, endpoint, vec![first, second]);
+        let state = || progress.request_state(&id, &user);
+
+        assert_eq!(state(), Some(RequestState::Pending));
+        // Other users and unknown ids see nothing
+        assert_eq!(progress.request_state(&id, &DieselUlid::generate()), None);
+        assert_eq!(progress.request_state(&DieselUlid::generate(), &user), None);
+
+        progress.set_expected_chunks(endpoint, first, 1);
+        assert_eq!(state(), Some(RequestState::Running));
+        progress.finish(endpoint, first);
+        assert_eq!(state(), Some(RequestState::Running));
+        progress.fail(endpoint, second, "Checksum mismatch".to_string());
+        assert_eq!(
+            state(),
+            Some(RequestState::Error(format!("{second}: Checksum mismatch")))
+        );
+
+        // A successful retry clears the failure
+        progress.queue(endpoint, Direction::Pull(second));
+        progress.finish(endpoint, second);
+        assert_eq!(state(), Some(RequestState::Finished));
+    }
+}
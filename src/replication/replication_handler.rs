@@ -1,12 +1,24 @@
-use crate::structs::FileFormat;
+use crate::structs::{FileFormat, SyncStatus};
 use crate::CONFIG;
 use crate::{
     caching::cache::Cache, data_backends::storage_backend::StorageBackend,
     s3_frontend::utils::buffered_s3_sink::BufferedS3Sink, structs::ObjectLocation,
 };
+use crate::caching::grpc_query_handler::GrpcQueryHandler;
+use crate::helpers::random_string;
+use crate::maintenance::footer_repair::data_section_len;
+use crate::metrics::REPLICATED_OBJECTS;
+use crate::replication::checksum::ChecksumAlgorithm;
+use crate::replication::chunk_size::InflightBytes;
+use crate::replication::integrity::{CustomerEncryptedData, ExpectedHashes, MAX_INTEGRITY_RETRIES};
+use crate::replication::progress::ObjectProgressHandle;
+use crate::replication::server_copy::CopySource;
+use crate::scoped_config;
+use crate::shutdown::Shutdown;
 use ahash::{HashSet, RandomState};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use aruna_rust_api::api::dataproxy::services::v2::{Empty, ObjectInfo, ReplicationStatus};
+use aruna_rust_api::api::storage::models::v2::Hashalgorithm;
 use aruna_rust_api::api::{
     dataproxy::services::v2::{
         error_message, pull_replication_request::Message,
@@ -16,16 +28,24 @@ use aruna_rust_api::api::{
     storage::services::v2::UpdateReplicationStatusRequest,
 };
 use async_channel::{Receiver, Sender};
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use diesel_ulid::DieselUlid;
-use md5::{Digest, Md5};
+use sha2::{Digest, Sha256};
 use pithos_lib::transformers::footer_extractor::FooterExtractor;
 use pithos_lib::{streamreadwrite::GenericStreamReadWriter, transformer::ReadWriter};
+use pithos_lib::transformers::async_sender_sink::AsyncSenderSink;
+use pithos_lib::transformers::decrypt_with_parts::ChaCha20DecParts;
+use pithos_lib::transformers::encrypt::ChaCha20Enc;
+use pithos_lib::transformers::hashing_transformer::HashingTransformer;
+use pithos_lib::transformers::size_probe::SizeProbe;
+use pithos_lib::transformers::zstd_comp::ZstdEnc;
+use pithos_lib::transformers::zstd_decomp::ZstdDec;
 use std::{str::FromStr, sync::Arc};
 use std::default::Default;
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedSemaphorePermit, RwLock};
 use tokio::pin;
-use tracing::{info_span, trace, Instrument};
+use tokio::task::JoinSet;
+use tracing::{error, info, info_span, trace, warn, Instrument};
 
 pub struct ReplicationMessage {
     pub direction: Direction,
@@ -38,6 +58,14 @@ pub enum Direction {
     Pull(DieselUlid),
 }
 
+impl Direction {
+    pub fn get_id(&self) -> DieselUlid {
+        match self {
+            Direction::Push(id) | Direction::Pull(id) => *id,
+        }
+    }
+}
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub enum RcvSync {
     Info(DieselUlid, i64),  // object_id and how many chunks
@@ -49,6 +77,8 @@ pub struct DataChunk {
     pub chunk_idx: i64,
     pub data: Vec<u8>,
     pub checksum: String,
+    // Released when the chunk is handed to the backend writer
+    pub permit: Option<OwnedSemaphorePermit>,
 }
 
 pub struct ReplicationHandler {
@@ -56,6 +86,11 @@ pub struct ReplicationHandler {
     pub backend: Arc<Box<dyn StorageBackend>>,
     pub cache: Arc<Cache>,
     pub self_id: String,
+    pub shutdown: Shutdown,
+    // Objects whose server side copy failed, they are pulled as streams
+    copy_failed: Arc<DashSet<DieselUlid, RandomState>>,
+    // Rejected replicas by number of retries, see `integrity`
+    integrity_retries: Arc<DashMap<DieselUlid, u32, RandomState>>,
 }
 
 #[derive(Clone, Debug)]
@@ -70,7 +105,18 @@ pub enum ObjectStateStatus {
     NotReceived,
     Infos{
         max_chunks: i64,
-        size: i64
+        size: i64,
+        expected: ExpectedHashes,
+    },
+    // Announced for a server side copy, see `server_copy`
+    Copy {
+        source: CopySource,
+        size: i64,
+    },
+    // Ciphertext encrypted with a customer provided key, stored as-is
+    CustomerEncrypted {
+        max_chunks: i64,
+        data: CustomerEncryptedData,
     },
 }
 
@@ -85,8 +131,40 @@ impl ObjectState {
         }
     }
 
-    pub fn update_state(&mut self, max_chunks:i64, size:i64) {
-        self.state = ObjectStateStatus::Infos { max_chunks , size };
+    pub fn update_state(&mut self, max_chunks:i64, size:i64, expected: ExpectedHashes) {
+        self.state = ObjectStateStatus::Infos { max_chunks , size, expected };
+    }
+
+    pub fn update_copy(&mut self, source: CopySource, size: i64) {
+        self.state = ObjectStateStatus::Copy { source, size };
+    }
+
+    pub fn update_customer_encrypted(&mut self, max_chunks: i64, data: CustomerEncryptedData) {
+        self.state = ObjectStateStatus::CustomerEncrypted { max_chunks, data };
+    }
+
+    pub fn get_customer_data(&self) -> Option<CustomerEncryptedData> {
+        if let ObjectStateStatus::CustomerEncrypted { data, .. } = &self.state {
+            Some(data.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn get_copy_source(&self) -> Option<CopySource> {
+        if let ObjectStateStatus::Copy { source, .. } = &self.state {
+            Some(source.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn get_expected_hashes(&self) -> ExpectedHashes {
+        if let ObjectStateStatus::Infos { expected, .. } = &self.state {
+            expected.clone()
+        } else {
+            ExpectedHashes::default()
+        }
     }
 
     pub fn is_synced(&self) -> bool {
@@ -94,9 +172,13 @@ impl ObjectState {
     }
 
     pub fn get_size(&self) -> Option<i64> {
-        if let ObjectStateStatus::Infos {size, ..} = self.state {
-            Some(size)
-        } else { None }
+        match self.state {
+            ObjectStateStatus::Infos { size, .. } | ObjectStateStatus::Copy { size, .. } => {
+                Some(size)
+            }
+            ObjectStateStatus::CustomerEncrypted { ref data, .. } => Some(data.raw_content_len),
+            ObjectStateStatus::NotReceived => None,
+        }
     }
 
     pub fn get_rcv(&self) -> Receiver<DataChunk> {
@@ -104,14 +186,16 @@ impl ObjectState {
     }
 
     pub fn get_chunks(&self) -> Result<i64> {
-        if let ObjectStateStatus::Infos {max_chunks,.. } = self.state {
-            if max_chunks > 0  {
+        match self.state {
+            ObjectStateStatus::Infos { max_chunks, .. } if max_chunks > 0 => Ok(max_chunks),
+            // Empty ciphertext is announced without chunks
+            ObjectStateStatus::CustomerEncrypted { max_chunks, .. } if max_chunks >= 0 => {
                 Ok(max_chunks)
-            } else {
+            }
+            ObjectStateStatus::Infos { .. } | ObjectStateStatus::CustomerEncrypted { .. } => {
                 Err(anyhow!("Invalid max chunks received"))
             }
-        } else {
-            Err(anyhow!("Not synced"))
+            _ => Err(anyhow!("Not synced")),
         }
     }
 
@@ -122,19 +206,42 @@ impl ObjectState {
 
 type ObjectHandler =
     Arc<DashMap<String, Arc<RwLock<ObjectState>>, RandomState>>;
+
+/// Shared state of all concurrently processed objects of a pull replication
+#[derive(Clone)]
+struct PullContext {
+    cache: Arc<Cache>,
+    backend: Arc<Box<dyn StorageBackend>>,
+    query_handler: Arc<GrpcQueryHandler>,
+    object_handler_map: ObjectHandler,
+    finished_objects: Arc<DashMap<Direction, bool, RandomState>>,
+    request_sdx: tokio::sync::mpsc::Sender<PullReplicationRequest>,
+    sync_sender: Sender<RcvSync>,
+    checksum_algorithm: ChecksumAlgorithm,
+    self_id: String,
+    endpoint_id: DieselUlid,
+    copy_failed: Arc<DashSet<DieselUlid, RandomState>>,
+    integrity_retries: Arc<DashMap<DieselUlid, u32, RandomState>>,
+    // Rejected replicas of this batch, they stay queued for the next batch
+    rejected: Arc<DashSet<DieselUlid, RandomState>>,
+}
 impl ReplicationHandler {
-    #[tracing::instrument(level = "trace", skip(cache, backend, receiver))]
+    #[tracing::instrument(level = "trace", skip(cache, backend, receiver, shutdown))]
     pub fn new(
         receiver: Receiver<ReplicationMessage>,
         backend: Arc<Box<dyn StorageBackend>>,
         self_id: String,
         cache: Arc<Cache>,
+        shutdown: Shutdown,
     ) -> Self {
         Self {
             receiver,
             backend,
             self_id,
             cache,
+            shutdown,
+            copy_failed: Arc::new(DashSet::default()),
+            integrity_retries: Arc::new(DashMap::default()),
         }
     }
 
@@ -147,12 +254,21 @@ impl ReplicationHandler {
         // Push messages into DashMap for further processing
         let queue_clone = queue.clone();
         let receiver = self.receiver.clone();
-        let receive = tokio::spawn(async move {
-            while let Ok(ReplicationMessage {
-                direction,
-                endpoint_id,
-            }) = receiver.recv().await
-            {
+        let progress = self.cache.replication_progress.clone();
+        let shutdown = self.shutdown.clone();
+        let receive = crate::spawn(async move {
+            loop {
+                let ReplicationMessage {
+                    direction,
+                    endpoint_id,
+                } = tokio::select! {
+                    message = receiver.recv() => match message {
+                        Ok(message) => message,
+                        Err(_) => break,
+                    },
+                    _ = shutdown.signaled() => break,
+                };
+                progress.queue(endpoint_id, direction.clone());
                 if queue_clone.contains_key(&endpoint_id) {
                     queue_clone.alter(&endpoint_id, |_, mut objects| {
                         objects.push(direction.clone());
@@ -166,10 +282,18 @@ impl ReplicationHandler {
         });
 
         // Process DashMap entries in batches
-        let process: tokio::task::JoinHandle<Result<()>> = tokio::spawn(async move {
+        let shutdown = self.shutdown.clone();
+        let process: tokio::task::JoinHandle<Result<()>> = crate::spawn(async move {
             loop {
-                // Process batches every 30 seconds
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await; // TODO: set to 30 secs
+                // Process batches every 30 seconds, the current batch is always
+                // finished before a shutdown stops the loop
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {} // TODO: set to 30 secs
+                    _ = shutdown.signaled() => {
+                        trace!("replication handler stopped");
+                        return Ok(());
+                    }
+                }
                 let batch = queue.clone();
 
                 let result = self.process(batch).await.map_err(|e| {
@@ -244,15 +368,46 @@ impl ReplicationHandler {
             };
             if let Some(query_handler) = self.cache.aruna_client.read().await.as_ref() {
                 let endpoint_id = *endpoint.key();
+                // Every pull is a trace of its own, continued by the peer
+                let pull_span = info_span!(
+                    parent: None,
+                    "replication_pull",
+                    endpoint_id = %endpoint_id,
+                    objects = pull.len(),
+                );
                 // This query handler returns a channel for sending messages into the input stream
                 // and the response stream
-                let (request_sender, mut response_stream) = query_handler
-                    .pull_replication(init_request, endpoint_id)
+                // Objects whose copy failed before are streamed by the whole batch
+                let offer_server_copy = !pull.iter().any(|id| self.copy_failed.contains(id));
+                let (request_sender, mut response_stream, checksum_algorithm) = match query_handler
+                    .pull_replication(init_request, endpoint_id, offer_server_copy)
+                    .instrument(pull_span.clone())
                     .await
-                    .map_err(|e| {
-                        tracing::error!(error = ?e, msg = e.to_string());
-                        e
-                    })?;
+                {
+                    Ok(replication) => replication,
+                    Err(e) => {
+                        // Only the batch of this endpoint fails, other peers are still processed
+                        tracing::error!(error = ?e, msg = e.to_string(), endpoint = %endpoint_id);
+                        for object in pull.iter() {
+                            self.cache.replication_progress.set_error(
+                                endpoint_id,
+                                *object,
+                                e.to_string(),
+                            );
+                            if let Err(e) = query_handler
+                                .update_replication_status(UpdateReplicationStatusRequest {
+                                    object_id: object.to_string(),
+                                    endpoint_id: self_id.clone(),
+                                    status: ReplicationStatus::Error as i32,
+                                })
+                                .await
+                            {
+                                tracing::error!(error = ?e, msg = e.to_string());
+                            }
+                        }
+                        continue;
+                    }
+                };
 
                 // This is the init message for object processing
                 let (start_sender, start_receiver) = async_channel::bounded(1);
@@ -265,6 +420,10 @@ impl ReplicationHandler {
                 // This map collects for each object_id a channel for data transmission
                 // TODO: This could be used to make parallel requests later
                 let object_handler_map: ObjectHandler = Arc::new(DashMap::default());
+                // The peer sends the objects in the requested order, they are processed
+                // in the same order so that the buffered chunks always belong to objects
+                // that are currently written
+                let pull_order = pull.iter().map(|o| o.to_string()).collect::<Vec<_>>();
                 for object in pull {
                     query_handler
                         .update_replication_status(UpdateReplicationStatusRequest {
@@ -292,9 +451,14 @@ impl ReplicationHandler {
                 // This is used to handle all requests and responses
                 // to the other data proxy
                 let data_map = object_handler_map.clone();
+                // Limits the chunk bytes buffered in memory, when exhausted the response
+                // stream is no longer polled which applies backpressure to the sender
+                let inflight_bytes =
+                    InflightBytes::new(CONFIG.proxy.replication_max_inflight_bytes);
                 let sync_sender_clone = sync_sender.clone();
                 let request_sender_clone = request_sender.clone();
-                tokio::spawn(async move {
+                let progress = self.cache.replication_progress.clone();
+                crate::spawn(async move {
                     let mut counter = 0;
                     while let Some(response) = response_stream.message().await? {
                         match response.message {
@@ -302,6 +466,7 @@ impl ReplicationHandler {
                                 object_id,
                                 chunks,
                                 raw_size,
+                                extra,
                                 ..
                             })) => {
                                 counter += 1;
@@ -313,7 +478,23 @@ impl ReplicationHandler {
                                 })?;
                                 if let Some(entry) = data_map.get(&object_id) {
                                     let mut guard = entry.write().await;
-                                    guard.update_state(chunks, raw_size);
+                                    if let Some(data) =
+                                        CustomerEncryptedData::from_extra(extra.as_deref())
+                                    {
+                                        guard.update_customer_encrypted(chunks, data);
+                                    } else {
+                                        match CopySource::from_extra(extra.as_deref())
+                                            .filter(|_| chunks == 0)
+                                        {
+                                            Some(source) => guard.update_copy(source, raw_size),
+                                            None => guard.update_state(
+                                                chunks,
+                                                raw_size,
+                                                ExpectedHashes::from_extra(extra.as_deref()),
+                                            ),
+                                        }
+                                    }
+                                    progress.set_expected_chunks(endpoint_id, id, chunks);
                                 } else {
                                     // If no entry is found, abort sync
                                     request_sender_clone
@@ -371,12 +552,14 @@ impl ReplicationHandler {
                                 // If an entry is created inside the object_handler_map ...
                                 if let Some(entry) = data_map.get(&object_id) {
                                     let sender = entry.read().await.get_sdx();
+                                    let permit = inflight_bytes.acquire(data.len()).await?;
                                     // Chunks get processed
                                     let chunk = DataChunk {
                                         object_id: object_id.clone(),
                                         chunk_idx,
                                         data,
                                         checksum,
+                                        permit: Some(permit),
                                     };
                                     sender.send(chunk).await?;
                                     let id = DieselUlid::from_str(&object_id)?;
@@ -439,7 +622,7 @@ impl ReplicationHandler {
                 });
 
                 // Sync handler
-                tokio::spawn(async move {
+                crate::spawn(async move {
                     let mut sync = HashSet::default();
                     // Every InfoMsg and ChunkMsg is stored
                     while let Ok(msg) = sync_receiver.recv().await {
@@ -463,99 +646,61 @@ impl ReplicationHandler {
                 });
 
                 // Process each object
-                let cache = self.cache.clone();
-                let backend = self.backend.clone();
-                let query_handler = query_handler.clone();
-                let request_sdx = request_sender.clone();
                 let finished_objects: Arc<DashMap<Direction, bool, RandomState>> =
                     Arc::new(DashMap::default()); // Syncs if object is already synced
-                let finished_clone = finished_objects.clone();
-                tokio::spawn(async move {
-                    // For now, every entry of the object_handler_map is processed
-                    // consecutively
+                let rejected: Arc<DashSet<DieselUlid, RandomState>> = Arc::new(DashSet::default());
+                let context = PullContext {
+                    cache: self.cache.clone(),
+                    backend: self.backend.clone(),
+                    query_handler: query_handler.clone(),
+                    object_handler_map: object_handler_map.clone(),
+                    finished_objects: finished_objects.clone(),
+                    request_sdx: request_sender.clone(),
+                    sync_sender: sync_sender.clone(),
+                    checksum_algorithm,
+                    self_id: self_id.clone(),
+                    endpoint_id,
+                    copy_failed: self.copy_failed.clone(),
+                    integrity_retries: self.integrity_retries.clone(),
+                    rejected: rejected.clone(),
+                };
+                let request_sdx = request_sender.clone();
+                crate::spawn(async move {
+                    // Up to replication_concurrency objects are processed concurrently,
+                    // each object has its own chunk channel and sends its own status updates
+                    let concurrency = CONFIG.proxy.replication_concurrency;
                     while start_receiver.recv().await.is_ok() {
                         let mut batch_counter = 0;
                         loop {
                             batch_counter += 1;
-                            let mut batch = Vec::new();
-                            for entry in object_handler_map.iter() {
-                                let (key, value) = entry.pair();
-                                batch.push((key.clone(), value.clone()));
-                            }
-                            for (id, object_state) in batch.iter() {
-                                trace!("processing: {}", id);
-                                let object_id = DieselUlid::from_str(&id)?;
-
-                                // The object gets queried
-                                let (object, location) =
-                                    cache.get_resource_cloned(&object_id, false).await?;
-                                trace!(?object);
-                                // If no location is found, a new one is created
-                                let mut location = if location.is_some() {
-                                    // TODO:
-                                    // - Skip if object was already synced
-                                    finished_clone.insert(Direction::Pull(object_id), true);
-                                    object_handler_map.remove(id);
-                                    continue;
-                                } else if !object_state.read().await.is_synced() {
-                                    trace!("skipping object");
-                                    continue;
-                                } else {
-                                    backend
-                                        .initialize_location(
-                                            &object,
-                                            object_state.read().await.get_size(),
-                                            cache.get_single_parent(&object.id).await?,
-                                            false,
-                                        )
-                                        .await
-                                        .map_err(|e| {
-                                            tracing::error!(error = ?e, msg = e.to_string());
-                                            e
-                                        })?
-                                };
-                                trace!("Load into backend");
-                                // Send Chunks get processed
-                                ReplicationHandler::load_into_backend(
-                                    object_state.read().await.get_rcv(),
-                                    request_sdx.clone(),
-                                    sync_sender.clone(),
-                                    &mut location,
-                                    backend.clone(),
-                                    object_state.read().await.get_chunks()?,
-                                )
-                                .await
-                                .map_err(|e| {
-                                    tracing::error!(error = ?e, msg = e.to_string());
-                                    e
-                                })?;
-
-                                trace!("Upsert object");
-                                // TODO: This should probably happen after checking if all chunks were processed
-                                // Sync with cache and db
-                                cache.upsert_object(object.clone()).await?;
-
-                                cache.add_location_with_binding(object.id, location).await?;
-
-                                trace!("Update status");
-                                // Send UpdateStatus to server
-                                query_handler
-                                    .update_replication_status(UpdateReplicationStatusRequest {
-                                        object_id: object.id.to_string(),
-                                        endpoint_id: self_id.clone(),
-                                        status: ReplicationStatus::Finished as i32,
-                                    })
-                                    .await
-                                    .map_err(|e| {
-                                        tracing::error!(error = ?e, msg = e.to_string());
-                                        e
-                                    })?;
-                                {
-                                    trace!("before entry remove");
-                                    object_handler_map.remove(id);
-                                    trace!("after entry remove");
+                            let batch = pull_order
+                                .iter()
+                                .filter_map(|id| {
+                                    object_handler_map
+                                        .get(id)
+                                        .map(|state| (id.clone(), state.value().clone()))
+                                })
+                                .collect::<Vec<_>>();
+                            let mut tasks = JoinSet::new();
+                            for (id, object_state) in batch {
+                                if tasks.len() >= concurrency {
+                                    ReplicationHandler::join_next(&mut tasks).await?;
                                 }
-                                trace!( msg="Removed entry from map", map = ?object_handler_map);
+                                tasks.spawn(scoped_config::in_current_config(
+                                    ReplicationHandler::process_object(
+                                        context.clone(),
+                                        id.clone(),
+                                        object_state,
+                                    )
+                                    .instrument(info_span!(
+                                        parent: &pull_span,
+                                        "process_object",
+                                        object_id = id
+                                    )),
+                                ));
+                            }
+                            while !tasks.is_empty() {
+                                ReplicationHandler::join_next(&mut tasks).await?;
                             }
                             if object_handler_map.is_empty() {
                                 trace!("Object handler map is empty, finishing replication... ");
@@ -608,6 +753,10 @@ impl ReplicationHandler {
                     });
                     // For each object, check if all chunks were processed
                     for (object_id, chunks) in inits {
+                        // Rejected replicas are not finished and pulled again
+                        if rejected.contains(object_id) {
+                            continue;
+                        }
                         let collected = finished
                             .iter()
                             .filter_map(|msg| match msg {
@@ -702,6 +851,346 @@ impl ReplicationHandler {
         trace!(?result);
         Ok(result)
     }
+    /// Waits for the next object task, a failed object aborts the whole batch
+    async fn join_next(tasks: &mut JoinSet<Result<()>>) -> Result<()> {
+        match tasks.join_next().await {
+            Some(Ok(Err(e))) => {
+                tasks.abort_all();
+                Err(e)
+            }
+            Some(Err(e)) => {
+                tracing::error!(error = ?e, msg = e.to_string());
+                tasks.abort_all();
+                Err(anyhow!("Object processing task failed: {e}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Processes a single object, failures are recorded in the replication progress
+    async fn process_object(
+        context: PullContext,
+        id: String,
+        object_state: Arc<RwLock<ObjectState>>,
+    ) -> Result<()> {
+        let object_id = DieselUlid::from_str(&id)?;
+        let progress = ObjectProgressHandle {
+            progress: context.cache.replication_progress.clone(),
+            endpoint_id: context.endpoint_id,
+            object_id,
+        };
+        ReplicationHandler::load_object(context, id, object_state, progress.clone())
+            .await
+            .map_err(|e| {
+                progress.fail(e.to_string());
+                e
+            })
+    }
+
+    /// Loads a single object into the backend, verifies it and reports the
+    /// replication status for this object to the server
+    async fn load_object(
+        context: PullContext,
+        id: String,
+        object_state: Arc<RwLock<ObjectState>>,
+        progress: ObjectProgressHandle,
+    ) -> Result<()> {
+        let PullContext {
+            cache,
+            backend,
+            query_handler,
+            object_handler_map,
+            finished_objects,
+            request_sdx,
+            sync_sender,
+            checksum_algorithm,
+            self_id,
+            endpoint_id,
+            copy_failed,
+            integrity_retries,
+            rejected,
+        } = context;
+        trace!("processing: {}", id);
+        let object_id = DieselUlid::from_str(&id)?;
+
+        // The object gets queried
+        let (mut object, location) = cache.get_resource_cloned(&object_id, false).await?;
+        trace!(?object);
+        // Corrupt locations (see the integrity scrubber) are replaced by the replica
+        let corrupt_location = location.clone().filter(|l| l.corrupt);
+        // If no location is found, a new one is created
+        let mut location = if location.is_some() && corrupt_location.is_none() {
+            // TODO:
+            // - Skip if object was already synced
+            finished_objects.insert(Direction::Pull(object_id), true);
+            object_handler_map.remove(&id);
+            progress.finish();
+            return Ok(());
+        } else if !object_state.read().await.is_synced() {
+            trace!("skipping object");
+            return Ok(());
+        } else {
+            let parents = cache.get_single_parent(&object.id).await?;
+            let storage = cache.get_storage_policy(&parents);
+            backend
+                .initialize_location(
+                    &object,
+                    object_state.read().await.get_size(),
+                    parents,
+                    storage.as_deref(),
+                    false,
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!(error = ?e, msg = e.to_string());
+                    e
+                })?
+        };
+        // Reported as running (see x-aruna-endpoint-status) until the server confirms the replica
+        let own_endpoint = CONFIG.proxy.endpoint_id;
+        if let Some(endpoint) = object.endpoints.iter_mut().find(|ep| ep.id == own_endpoint) {
+            endpoint.status = Some(SyncStatus::Running);
+        }
+        cache
+            .set_endpoint_status(&object_id, &own_endpoint, SyncStatus::Running)
+            .await?;
+        // The expected hash is fetched from the server and not from the
+        // sending proxy, which could provide manipulated data and infos
+        let expected_sha256 = query_handler
+            .get_object_hashes(&object.id)
+            .await?
+            .into_iter()
+            .find(|hash| hash.alg == Hashalgorithm::Sha256 as i32)
+            .map(|hash| hash.hash);
+
+        let copy_source = object_state.read().await.get_copy_source();
+        let transfer = if copy_source.is_some() {
+            "server_copy"
+        } else {
+            "stream"
+        };
+        let (data_len, target) = if let Some(source) = copy_source {
+            trace!("Copy on the shared backend");
+            match ReplicationHandler::copy_from_source(backend.clone(), &source, &mut location)
+                .await
+            {
+                Ok(copied) => copied,
+                Err(e) => {
+                    // The object is pulled again as stream with the next batch
+                    warn!(error = ?e, %object_id, "Server side copy failed, falling back to streaming");
+                    REPLICATED_OBJECTS
+                        .with_label_values(&["server_copy_fallback"])
+                        .inc();
+                    copy_failed.insert(object_id);
+                    let _ = backend.delete_object(location).await;
+                    finished_objects.insert(Direction::Pull(object_id), true);
+                    object_handler_map.remove(&id);
+                    cache
+                        .replication_progress
+                        .queue(endpoint_id, Direction::Pull(object_id));
+                    cache
+                        .sender
+                        .send(ReplicationMessage {
+                            direction: Direction::Pull(object_id),
+                            endpoint_id,
+                        })
+                        .await
+                        .map_err(|e| {
+                            tracing::error!(error = ?e, msg = e.to_string());
+                            anyhow!("Replication queue is not available")
+                        })?;
+                    return Ok(());
+                }
+            }
+        } else {
+            let customer_data = object_state.read().await.get_customer_data();
+            // Replicas are always transmitted as pithos files, if the local storage
+            // policy demands another format the pithos file is staged and rewritten.
+            // Customer encrypted ciphertext can not be rewritten and is stored as-is.
+            let target = if location.is_pithos() || customer_data.is_some() {
+                None
+            } else {
+                let target = location.clone();
+                location.key = format!("{}.{}", location.key, random_string(8));
+                Some(target)
+            };
+
+            trace!("Load into backend");
+            // Send Chunks get processed
+            let data_len = ReplicationHandler::load_into_backend(
+                object_state.read().await.get_rcv(),
+                request_sdx,
+                sync_sender,
+                &mut location,
+                backend.clone(),
+                object_state.read().await.get_chunks()?,
+                checksum_algorithm,
+                progress.clone(),
+                customer_data.as_ref(),
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+            (data_len, target)
+        };
+
+        trace!("Verify replica");
+        // The stored size is checked first, truncated data cannot be decrypted
+        let stored_len = backend.head_object(location.clone()).await.map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        let verified = if stored_len != location.disk_content_len {
+            Err(format!(
+                "Stored size {stored_len} does not match expected disk size {}",
+                location.disk_content_len
+            ))
+        } else if let Some(data) = object_state.read().await.get_customer_data() {
+            // The raw content can not be read without the customer key, only the
+            // ciphertext is compared with the announcement of the sending proxy
+            let disk_hash = location.disk_hash.clone().unwrap_or_default();
+            data.verify(location.disk_content_len, &disk_hash)
+                .map(|_| disk_hash)
+        } else {
+            let calculated_sha256 = ReplicationHandler::calculate_raw_sha256(
+                backend.clone(),
+                location.clone(),
+                data_len,
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+            object_state
+                .read()
+                .await
+                .get_expected_hashes()
+                .verify(
+                    &calculated_sha256,
+                    expected_sha256.as_deref(),
+                    CONFIG.proxy.replication_require_server_hash,
+                )
+                .map(|_| calculated_sha256)
+        };
+        let calculated_sha256 = match verified {
+            Ok(calculated_sha256) => calculated_sha256,
+            Err(reason) => {
+                warn!(
+                    source_endpoint = %endpoint_id,
+                    object_id = %object.id,
+                    reason,
+                    "Replica failed verification, rejecting replica"
+                );
+                REPLICATED_OBJECTS
+                    .with_label_values(&["integrity_error"])
+                    .inc();
+                // The location is never finalized, stored data is discarded
+                backend.delete_object(location).await.map_err(|e| {
+                    tracing::error!(error = ?e, msg = e.to_string());
+                    e
+                })?;
+                cache
+                    .set_endpoint_status(&object_id, &own_endpoint, SyncStatus::Error)
+                    .await?;
+                query_handler
+                    .update_replication_status(UpdateReplicationStatusRequest {
+                        object_id: object.id.to_string(),
+                        endpoint_id: self_id,
+                        status: ReplicationStatus::Error as i32,
+                    })
+                    .await
+                    .map_err(|e| {
+                        tracing::error!(error = ?e, msg = e.to_string());
+                        e
+                    })?;
+                object_handler_map.remove(&id);
+                let retries = {
+                    let mut retries = integrity_retries.entry(object_id).or_insert(0);
+                    *retries += 1;
+                    *retries
+                };
+                if retries <= MAX_INTEGRITY_RETRIES {
+                    // Not reported as finished, the whole object is pulled with the next batch
+                    rejected.insert(object_id);
+                    progress.set_error(format!(
+                        "{reason}, retrying ({retries}/{MAX_INTEGRITY_RETRIES})"
+                    ));
+                } else {
+                    integrity_retries.remove(&object_id);
+                    progress.fail(reason);
+                }
+                return Ok(());
+            }
+        };
+
+        if let Some(target) = target {
+            trace!("Rewrite into local storage format");
+            location = ReplicationHandler::rewrite_location(
+                backend.clone(),
+                location,
+                target,
+                data_len,
+                calculated_sha256,
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        }
+
+        trace!("Upsert object");
+        // TODO: This should probably happen after checking if all chunks were processed
+        // Sync with cache and db
+        cache.upsert_object(object.clone()).await?;
+
+        match corrupt_location {
+            Some(corrupt_location) => {
+                cache.update_location(object.id, location).await?;
+                if corrupt_location.ref_count <= 1 {
+                    if let Err(e) = backend.delete_object(corrupt_location).await {
+                        // The replica is already in place, only the corrupt data is leaked
+                        error!(error = ?e, object_id = %object.id, msg = "Unable to delete corrupt location");
+                    }
+                }
+                info!(object_id = %object.id, "Replaced corrupt location with replica");
+            }
+            None => cache.add_location_with_binding(object.id, location).await?,
+        }
+
+        trace!("Update status");
+        // Send UpdateStatus to server as soon as this object is finished
+        query_handler
+            .update_replication_status(UpdateReplicationStatusRequest {
+                object_id: object.id.to_string(),
+                endpoint_id: self_id,
+                status: ReplicationStatus::Finished as i32,
+            })
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        cache
+            .set_endpoint_status(&object_id, &own_endpoint, SyncStatus::Finished)
+            .await?;
+        copy_failed.remove(&object_id);
+        integrity_retries.remove(&object_id);
+        if transfer == "server_copy" {
+            // Streamed data is counted per chunk
+            progress.bytes_replicated(data_len);
+        }
+        REPLICATED_OBJECTS.with_label_values(&[transfer]).inc();
+        info!(%object_id, source_endpoint = %endpoint_id, transfer, "Replicated object");
+        object_handler_map.remove(&id);
+        progress.finish();
+        trace!(msg = "Removed entry from map", map = ?object_handler_map);
+        Ok(())
+    }
+
     async fn load_into_backend(
         data_receiver: Receiver<DataChunk>,
         stream_sender: tokio::sync::mpsc::Sender<PullReplicationRequest>,
@@ -709,13 +1198,21 @@ impl ReplicationHandler {
         location: &mut ObjectLocation,
         backend: Arc<Box<dyn StorageBackend>>,
         max_chunks: i64,
-    ) -> Result<()> {
+        checksum_algorithm: ChecksumAlgorithm,
+        progress: ObjectProgressHandle,
+        customer_data: Option<&CustomerEncryptedData>,
+    ) -> Result<u64> {
         let mut expected = 0;
         let mut retry_counter = 0;
 
         trace!("Starting chunk processing");
-        let (data_sender, data_stream) = async_channel::bounded(1000);
-        tokio::spawn(
+        // Chunks are buffered in the object channel (accounted by bytes), not here
+        let (data_sender, data_stream) = async_channel::bounded(2);
+        if max_chunks == 0 {
+            // Empty ciphertext, the stream is closed right away
+            drop(data_sender);
+        } else {
+            crate::spawn(
             async move {
                 while let Ok(data) = data_receiver.recv().await {
                     let trace_message = format!(
@@ -728,6 +1225,7 @@ impl ReplicationHandler {
                     );
                     trace!(trace_message);
                     let chunk = bytes::Bytes::from_iter(data.data.into_iter());
+                    let chunk_len = chunk.len();
                     // Check if chunk is missing
                     let idx = data.chunk_idx;
 
@@ -741,6 +1239,7 @@ impl ReplicationHandler {
                             // TODO:
                             // RetryChunk message
                             trace!("MissingChunk: Retry chunk {}", expected);
+                            progress.chunk_retried(format!("Missing chunk {expected}, retrying"));
                             stream_sender
                             .send(PullReplicationRequest {
                                 message: Some(Message::ErrorMessage(
@@ -767,17 +1266,17 @@ impl ReplicationHandler {
                         expected += 1;
                     };
 
-                    // Check checksum of chunk:
-                    let hash = data.checksum;
-                    // - create a Md5 hasher instance
-                    let mut hasher = Md5::new();
-                    // - process input message
-                    hasher.update(&chunk);
-                    // - acquire hash digest in the form of GenericArray,
-                    //   which in this case is equivalent to [u8; 16]
-                    let result = hasher.finalize();
-                    let calculated_hash = hex::encode(result);
-                    if calculated_hash != hash {
+                    // Check checksum of chunk with the negotiated algorithm,
+                    // a downgrade by the sending proxy is treated as a mismatch
+                    if !checksum_algorithm.verify(&data.checksum, &chunk) {
+                        warn!(
+                            object_id = data.object_id,
+                            chunk_idx = data.chunk_idx,
+                            algorithm = checksum_algorithm.as_str(),
+                            received_algorithm =
+                                ChecksumAlgorithm::from_checksum(&data.checksum).as_str(),
+                            "Chunk checksum verification failed"
+                        );
                         if retry_counter > 5 {
                             trace!("Exceeded retries");
                             return Err(anyhow!(
@@ -787,6 +1286,10 @@ impl ReplicationHandler {
                             // TODO:
                             // RetryChunk message
                             trace!("HashError: Retry chunk {}", expected);
+                            progress.chunk_retried(format!(
+                                "Checksum mismatch for chunk {}, retrying",
+                                data.chunk_idx
+                            ));
                             stream_sender
                             .send(PullReplicationRequest {
                                 message: Some(Message::ErrorMessage(
@@ -815,6 +1318,8 @@ impl ReplicationHandler {
                         tracing::error!(error = ?e, msg = e.to_string());
                         e
                     })?;
+                    progress.chunk_received(chunk_len as u64);
+                    drop(data.permit);
 
                     // Message is send to sync
                     sync_sender
@@ -837,7 +1342,8 @@ impl ReplicationHandler {
                 Ok::<(), anyhow::Error>(())
             }
             .instrument(info_span!("replication chunk receiver")),
-        );
+            );
+        }
 
         trace!("Starting ArunaStreamReadWriter task");
         let location_clone = location.clone();
@@ -853,9 +1359,38 @@ impl ReplicationHandler {
                 None,
                 false,
             )
-            .0,
+            .0
+            .with_expected_size(
+                u64::try_from(
+                    customer_data
+                        .map(|data| data.disk_content_len)
+                        .unwrap_or(location_clone.raw_content_len),
+                )
+                .ok(),
+            ),
         );
 
+        if let Some(data) = customer_data {
+            let (disk_sha, disk_sha_recv) =
+                HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+            awr = awr.add_transformer(disk_sha);
+            let (size_probe, size_recv) = SizeProbe::new();
+            awr = awr.add_transformer(size_probe);
+            awr.process().await.map_err(|e| {
+                tracing::error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+
+            // Only a salted hash of the key is stored, see `sse_c`
+            location.file_format = FileFormat::customer_encrypted(data.compressed);
+            location.customer_key = Some(data.customer_key.clone());
+            location.raw_content_len = data.raw_content_len;
+            location.disk_content_len = size_recv.try_recv()? as i64;
+            location.disk_hash = Some(disk_sha_recv.try_recv()?);
+            trace!(location = ?location);
+            return Ok(location.disk_content_len as u64);
+        }
+
         let (extractor, rx) = FooterExtractor::new(Some(CONFIG.proxy.get_private_key_x25519()?));
 
         awr = awr.add_transformer(extractor);
@@ -869,6 +1404,8 @@ impl ReplicationHandler {
             tracing::error!(error = ?e, msg = e.to_string());
             e
         })?;
+        let data_len = data_section_len(&footer);
+
         if let Some(keys) = footer.encryption_keys {
             if let Some((key, _)) = keys.keys.first() {
                 location.file_format = FileFormat::Pithos(*key);
@@ -879,14 +1416,469 @@ impl ReplicationHandler {
             return Err(anyhow!("Unable to extract keys"));
         };
 
-        // TODO:
-        // Fetch calculated hashes
-
         // Put infos into location
         location.disk_content_len = footer.eof_metadata.disk_file_size as i64;
         location.disk_hash = Some(hex::encode(footer.eof_metadata.disk_hash_sha256));
         trace!(location = ?location);
 
-        Ok(())
+        Ok(data_len)
+    }
+
+    /// Copies the data announced by a co-located proxy on the shared backend. The copy
+    /// keeps the stored format and key of the source, if the local storage policy
+    /// demands another format the copy is staged and rewritten like a streamed replica.
+    /// Returns the stored length and the rewrite target.
+    #[tracing::instrument(level = "trace", skip(backend, source, location))]
+    async fn copy_from_source(
+        backend: Arc<Box<dyn StorageBackend>>,
+        source: &CopySource,
+        location: &mut ObjectLocation,
+    ) -> Result<(u64, Option<ObjectLocation>)> {
+        // Pithos files need a footer for this proxy, which only the stream provides
+        if location.is_pithos() {
+            bail!("Local storage policy requires pithos files");
+        }
+        if CONFIG
+            .backend_identity(location.backend.as_deref())
+            .as_ref()
+            != Some(&source.backend)
+        {
+            bail!("Source is not stored on the backend of the new location");
+        }
+        let target = if std::mem::discriminant(&location.file_format)
+            == std::mem::discriminant(&source.file_format)
+        {
+            None
+        } else {
+            let target = location.clone();
+            location.key = format!("{}.{}", location.key, random_string(8));
+            Some(target)
+        };
+        location.file_format = source.file_format.clone();
+        location.raw_content_len = source.raw_content_len;
+        location.disk_content_len = source.disk_content_len;
+        location.disk_hash = source.disk_hash.clone();
+
+        backend.copy_object(&source.location(), location).await?;
+        Ok((source.disk_content_len.max(0) as u64, target))
+    }
+
+    /// Reads the stored replica back and calculates the sha256 of the raw content
+    #[tracing::instrument(level = "trace", skip(backend))]
+    async fn calculate_raw_sha256(
+        backend: Arc<Box<dyn StorageBackend>>,
+        location: ObjectLocation,
+        data_len: u64,
+    ) -> Result<String> {
+        let (data_sender, data_receiver) = async_channel::bounded(255);
+        let (output_sender, output_receiver) = async_channel::bounded(255);
+        let key = location.get_encryption_key();
+        let is_compressed = location.is_compressed();
+
+        let backend_clone = backend.clone();
+        let location_clone = location.clone();
+        crate::spawn(
+            async move {
+                backend_clone
+                    .get_object(
+                        location_clone,
+                        Some(format!("bytes=0-{}", data_len.saturating_sub(1))),
+                        data_sender,
+                    )
+                    .await
+            }
+            .instrument(info_span!("get_object")),
+        );
+
+        // Only the hash is relevant, decrypted data is discarded
+        crate::spawn(async move { while output_receiver.recv().await.is_ok() {} });
+
+        pin!(data_receiver);
+        let mut asrw = GenericStreamReadWriter::new_with_sink(
+            data_receiver,
+            AsyncSenderSink::new(output_sender),
+        );
+        if let Some(key) = key {
+            asrw = asrw.add_transformer(ChaCha20DecParts::new_with_lengths(key, vec![data_len]));
+        }
+        if is_compressed {
+            asrw = asrw.add_transformer(ZstdDec::new());
+        }
+        let (sha_transformer, sha_recv) =
+            HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+        asrw = asrw.add_transformer(sha_transformer);
+
+        asrw.process().await.map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+
+        Ok(sha_recv.try_recv().map_err(|e| {
+            tracing::error!(error = ?e, msg = e.to_string());
+            e
+        })?)
+    }
+
+    /// Rewrites a verified pithos replica into the format of the local storage
+    /// policy, the staged pithos file is deleted afterwards
+    #[tracing::instrument(level = "trace", skip(backend))]
+    async fn rewrite_location(
+        backend: Arc<Box<dyn StorageBackend>>,
+        staged: ObjectLocation,
+        mut target: ObjectLocation,
+        data_len: u64,
+        expected_sha256: String,
+    ) -> Result<ObjectLocation> {
+        let (data_sender, data_receiver) = async_channel::bounded(255);
+        let key = staged.get_encryption_key();
+        let is_compressed = staged.is_compressed();
+
+        let backend_clone = backend.clone();
+        let staged_clone = staged.clone();
+        crate::spawn(
+            async move {
+                backend_clone
+                    .get_object(
+                        staged_clone,
+                        Some(format!("bytes=0-{}", data_len.saturating_sub(1))),
+                        data_sender,
+                    )
+                    .await
+            }
+            .instrument(info_span!("get_object")),
+        );
+
+        let backend_clone = backend.clone();
+        let target_clone = target.clone();
+        let result = async move {
+            let (sink, _) = BufferedS3Sink::new(
+                backend_clone,
+                target_clone.clone(),
+                None,
+                None,
+                false,
+                None,
+                false,
+            );
+            pin!(data_receiver);
+            let mut asrw = GenericStreamReadWriter::new_with_sink(data_receiver, sink);
+            if let Some(key) = key {
+                asrw =
+                    asrw.add_transformer(ChaCha20DecParts::new_with_lengths(key, vec![data_len]));
+            }
+            if is_compressed {
+                asrw = asrw.add_transformer(ZstdDec::new());
+            }
+            let (raw_sha, raw_sha_recv) =
+                HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+            asrw = asrw.add_transformer(raw_sha);
+            if target_clone.is_compressed() {
+                asrw = asrw.add_transformer(ZstdEnc::new());
+            }
+            if let Some(key) = target_clone.get_encryption_key() {
+                asrw = asrw.add_transformer(ChaCha20Enc::new_with_fixed(key)?);
+            }
+            let (disk_sha, disk_sha_recv) =
+                HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
+            asrw = asrw.add_transformer(disk_sha);
+            let (size_probe, size_recv) = SizeProbe::new();
+            asrw = asrw.add_transformer(size_probe);
+
+            asrw.process().await?;
+
+            if raw_sha_recv.try_recv()? != expected_sha256 {
+                return Err(anyhow!("Rewritten data does not match the replicated data"));
+            }
+            Ok::<_, anyhow::Error>((size_recv.try_recv()?, disk_sha_recv.try_recv()?))
+        }
+        .await;
+
+        let (disk_size, disk_hash) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                let _ = backend.delete_object(target).await;
+                let _ = backend.delete_object(staged).await;
+                return Err(e);
+            }
+        };
+        target.disk_content_len = disk_size as i64;
+        target.disk_hash = Some(disk_hash);
+
+        if let Err(e) = backend.delete_object(staged).await {
+            // The replica is complete, only the staged pithos file is leaked
+            warn!(error = ?e, msg = "Unable to delete staged replica");
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::data_backends::memory_backend::{BackendOperation, MemoryBackend};
+    use crate::structs::{Endpoint, Object, ObjectType, SyncVariant, TypedRelation};
+    use aruna_rust_api::api::storage::models::v2::{Hash, Object as GrpcObject};
+    use aruna_rust_api::api::storage::services::v2::{
+        GetObjectRequest, GetObjectResponse, GetPubkeysRequest, GetPubkeysResponse,
+        UpdateReplicationStatusResponse,
+    };
+    use pithos_lib::helpers::notifications::Message as PithosMessage;
+    use pithos_lib::transformers::footer::FooterGenerator;
+    use pithos_lib::transformers::pithos_comp_enc::PithosTransformer;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::{http, BoxFuture};
+
+    /// Aruna server with the recorded sha256 of an object, replication status
+    /// updates are recorded
+    #[derive(Clone)]
+    struct StubServer {
+        sha256: String,
+        statuses: Arc<Mutex<Vec<i32>>>,
+    }
+
+    struct GetPubkeysSvc;
+
+    impl tonic::server::UnaryService<GetPubkeysRequest> for GetPubkeysSvc {
+        type Response = GetPubkeysResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, _request: tonic::Request<GetPubkeysRequest>) -> Self::Future {
+            Box::pin(async { Ok(tonic::Response::new(GetPubkeysResponse { pubkeys: vec![] })) })
+        }
+    }
+
+    struct GetObjectSvc(String);
+
+    impl tonic::server::UnaryService<GetObjectRequest> for GetObjectSvc {
+        type Response = GetObjectResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<GetObjectRequest>) -> Self::Future {
+            let hash = self.0.clone();
+            Box::pin(async move {
+                Ok(tonic::Response::new(GetObjectResponse {
+                    object: Some(GrpcObject {
+                        id: request.into_inner().object_id,
+                        hashes: vec![Hash {
+                            alg: Hashalgorithm::Sha256 as i32,
+                            hash,
+                        }],
+                        ..Default::default()
+                    }),
+                }))
+            })
+        }
+    }
+
+    struct UpdateReplicationStatusSvc(Arc<Mutex<Vec<i32>>>);
+
+    impl tonic::server::UnaryService<UpdateReplicationStatusRequest> for UpdateReplicationStatusSvc {
+        type Response = UpdateReplicationStatusResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(
+            &mut self,
+            request: tonic::Request<UpdateReplicationStatusRequest>,
+        ) -> Self::Future {
+            self.0.lock().unwrap().push(request.into_inner().status);
+            Box::pin(async { Ok(tonic::Response::new(UpdateReplicationStatusResponse {})) })
+        }
+    }
+
+    impl tower::Service<http::Request<hyper::Body>> for StubServer {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<hyper::Body>) -> Self::Future {
+            let stub = self.clone();
+            Box::pin(async move {
+                Ok(match req.uri().path() {
+                    "/aruna.api.storage.services.v2.StorageStatusService/GetPubkeys" => {
+                        tonic::server::Grpc::new(ProstCodec::default())
+                            .unary(GetPubkeysSvc, req)
+                            .await
+                    }
+                    "/aruna.api.storage.services.v2.ObjectService/GetObject" => {
+                        tonic::server::Grpc::new(ProstCodec::default())
+                            .unary(GetObjectSvc(stub.sha256), req)
+                            .await
+                    }
+                    "/aruna.api.storage.services.v2.DataReplicationService/UpdateReplicationStatus" => {
+                        tonic::server::Grpc::new(ProstCodec::default())
+                            .unary(UpdateReplicationStatusSvc(stub.statuses), req)
+                            .await
+                    }
+                    _ => tonic::Status::unimplemented("Not part of the stub").to_http(),
+                })
+            })
+        }
+    }
+
+    async fn start_stub(stub: StubServer) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = hyper::Server::from_tcp(listener)
+            .unwrap()
+            .http2_only(true)
+            .serve(hyper::service::make_service_fn(move |_| {
+                let stub = stub.clone();
+                async move { Ok::<_, Infallible>(stub) }
+            }));
+        tokio::spawn(server);
+        format!("http://{address}")
+    }
+
+    /// Pithos file of the data as transmitted by the sending proxy
+    async fn pithos_file(object: &Object, data: &[u8]) -> Vec<u8> {
+        let location = ObjectLocation {
+            file_format: FileFormat::from_bools(true, false, false),
+            raw_content_len: data.len() as i64,
+            ..Default::default()
+        };
+        let ctx = object
+            .get_file_context(Some(location), Some(data.len() as i64))
+            .unwrap();
+        let (data_sender, data_receiver) = async_channel::unbounded();
+        let (output_sender, output_receiver) = async_channel::unbounded();
+        let (message_sender, message_receiver) = async_channel::bounded(10);
+        data_sender
+            .send(Ok(bytes::Bytes::copy_from_slice(data)))
+            .await
+            .unwrap();
+        drop(data_sender);
+
+        pin!(data_receiver);
+        let mut asrw = GenericStreamReadWriter::new_with_sink(
+            data_receiver,
+            AsyncSenderSink::new(output_sender),
+        );
+        asrw.add_message_receiver(message_receiver).await.unwrap();
+        message_sender
+            .send(PithosMessage::FileContext(ctx))
+            .await
+            .unwrap();
+        asrw = asrw.add_transformer(PithosTransformer::new());
+        asrw = asrw.add_transformer(FooterGenerator::new(None));
+        asrw.process().await.unwrap();
+
+        let mut file = Vec::new();
+        while let Ok(Ok(chunk)) = output_receiver.try_recv() {
+            file.extend_from_slice(&chunk);
+        }
+        file
+    }
+
+    #[tokio::test]
+    async fn test_tampered_replica_is_rejected() {
+        let memory = MemoryBackend::new();
+        let backend: Arc<Box<dyn StorageBackend>> = Arc::new(Box::new(memory.clone()));
+        let (cache, _receiver) = test_cache(Some(backend.clone())).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let mut object = Object::initialize_now(
+            "file".to_string(),
+            ObjectType::Object,
+            Some(TypedRelation::Project(project.id)),
+        );
+        object.endpoints = vec![Endpoint {
+            id: CONFIG.proxy.endpoint_id,
+            variant: SyncVariant::FullSync,
+            status: None,
+        }];
+        cache.upsert_object(project).await.unwrap();
+        cache.upsert_object(object.clone()).await.unwrap();
+
+        // The server recorded the hash of the original data
+        let original = vec![b'a'; 200 * 1024];
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        let server_url = start_stub(StubServer {
+            sha256: hex::encode(Sha256::digest(&original)),
+            statuses: statuses.clone(),
+        })
+        .await;
+        let query_handler = Arc::new(
+            GrpcQueryHandler::new(
+                server_url,
+                cache.clone(),
+                CONFIG.proxy.endpoint_id.to_string(),
+            )
+            .await
+            .unwrap(),
+        );
+
+        // The source replaces the data, the chunk checksums and the announced hash
+        // are calculated over the tampered data
+        let tampered = vec![b'b'; 200 * 1024];
+        let file = pithos_file(&object, &tampered).await;
+        let checksum_algorithm = ChecksumAlgorithm::Sha256;
+        let (chunk_sender, chunk_receiver) = async_channel::unbounded();
+        let chunks = file.chunks(64 * 1024).collect::<Vec<_>>();
+        for (idx, chunk) in chunks.iter().enumerate() {
+            chunk_sender
+                .send(DataChunk {
+                    object_id: object.id.to_string(),
+                    chunk_idx: idx as i64,
+                    data: chunk.to_vec(),
+                    checksum: checksum_algorithm.checksum(chunk),
+                    permit: None,
+                })
+                .await
+                .unwrap();
+        }
+        let mut object_state = ObjectState::new(chunk_sender, chunk_receiver);
+        object_state.update_state(
+            chunks.len() as i64,
+            tampered.len() as i64,
+            ExpectedHashes {
+                raw_sha256: Some(hex::encode(Sha256::digest(&tampered))),
+            },
+        );
+
+        let (request_sdx, _request_rcv) = tokio::sync::mpsc::channel(10);
+        let (sync_sender, _sync_receiver) = async_channel::unbounded();
+        let rejected = Arc::new(DashSet::default());
+        let context = PullContext {
+            cache: cache.clone(),
+            backend,
+            query_handler,
+            object_handler_map: Arc::new(DashMap::default()),
+            finished_objects: Arc::new(DashMap::default()),
+            request_sdx,
+            sync_sender,
+            checksum_algorithm,
+            self_id: CONFIG.proxy.endpoint_id.to_string(),
+            endpoint_id: DieselUlid::generate(),
+            copy_failed: Arc::new(DashSet::default()),
+            integrity_retries: Arc::new(DashMap::default()),
+            rejected: rejected.clone(),
+        };
+        ReplicationHandler::process_object(
+            context,
+            object.id.to_string(),
+            Arc::new(RwLock::new(object_state)),
+        )
+        .await
+        .unwrap();
+
+        // The stored data is deleted and never added to the cache
+        assert!(memory.calls(BackendOperation::Delete) > 0);
+        assert_eq!(memory.object_count(), 0);
+        let (cached, location) = cache.get_resource_cloned(&object.id, false).await.unwrap();
+        assert!(location.is_none());
+        assert_eq!(cached.endpoints[0].status, Some(SyncStatus::Error));
+        assert_eq!(
+            *statuses.lock().unwrap(),
+            vec![ReplicationStatus::Error as i32]
+        );
+        assert!(rejected.contains(&object.id));
     }
 }
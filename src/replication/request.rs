@@ -0,0 +1,371 @@
+use crate::caching::cache::Cache;
+use crate::replication::replication_handler::{Direction, ReplicationMessage};
+use crate::structs::{AccessKeyPermissions, DbPermissionLevel, ObjectType};
+use crate::CONFIG;
+use anyhow::{anyhow, Result};
+use diesel_ulid::DieselUlid;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{error, trace};
+
+/// Rejection reason of resources without WRITE permissions
+pub const INSUFFICIENT_PERMISSIONS: &str = "Insufficient permissions";
+
+/// Result of a user initiated replication request
+#[derive(Debug, Default, Clone)]
+pub struct RequestReplicationResult {
+    pub accepted: Vec<DieselUlid>,
+    pub already_queued: Vec<DieselUlid>,
+    pub rejected: Vec<(DieselUlid, String)>,
+}
+
+impl RequestReplicationResult {
+    /// Objects that will be replicated, newly accepted or already queued
+    pub fn replicated(&self) -> Vec<DieselUlid> {
+        self.accepted
+            .iter()
+            .chain(self.already_queued.iter())
+            .copied()
+            .collect()
+    }
+
+    /// Nothing is replicated because of missing permissions only
+    pub fn is_denied(&self) -> bool {
+        self.accepted.is_empty()
+            && self.already_queued.is_empty()
+            && !self.rejected.is_empty()
+            && self
+                .rejected
+                .iter()
+                .all(|(_, reason)| reason == INSUFFICIENT_PERMISSIONS)
+    }
+}
+
+/// Enqueues objects into the replication handler on behalf of a user
+pub struct ReplicationRequester {
+    cache: Arc<Cache>,
+}
+
+impl ReplicationRequester {
+    pub fn new(cache: Arc<Cache>) -> Self {
+        Self { cache }
+    }
+
+    /// Requests a pull of the given objects (or all objects below the given resources)
+    /// from `endpoint_id`, the caller needs WRITE permissions on every requested resource
+    #[tracing::instrument(level = "trace", skip(self, token))]
+    pub async fn request_replication(
+        &self,
+        token: &str,
+        endpoint_id: DieselUlid,
+        resource_ids: Vec<DieselUlid>,
+    ) -> Result<RequestReplicationResult> {
+        let permissions = self.authenticate(token).await?;
+        Ok(self
+            .request_with_permissions(&permissions, endpoint_id, resource_ids)
+            .await)
+    }
+
+    /// Requests the pull on behalf of an already authenticated access key
+    async fn request_with_permissions(
+        &self,
+        permissions: &AccessKeyPermissions,
+        endpoint_id: DieselUlid,
+        resource_ids: Vec<DieselUlid>,
+    ) -> RequestReplicationResult {
+        let mut result = RequestReplicationResult::default();
+        let mut seen = HashSet::new();
+        for resource_id in resource_ids {
+            if !self
+                .has_permission(permissions, &resource_id, DbPermissionLevel::Write)
+                .await
+            {
+                result
+                    .rejected
+                    .push((resource_id, INSUFFICIENT_PERMISSIONS.to_string()));
+                continue;
+            }
+            for object_id in self.collect_objects(resource_id).await {
+                if !seen.insert(object_id) {
+                    continue;
+                }
+                match self.enqueue(endpoint_id, object_id).await {
+                    Ok(true) => result.accepted.push(object_id),
+                    Ok(false) => result.already_queued.push(object_id),
+                    Err(e) => result.rejected.push((object_id, e.to_string())),
+                }
+            }
+        }
+        trace!(?result);
+        result
+    }
+
+    async fn authenticate(&self, token: &str) -> Result<AccessKeyPermissions> {
+        let (user_id, tid, pk) = match self.cache.auth.read().await.as_ref() {
+            Some(auth) => auth.check_permissions(token).await.map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                anyhow!("Unable to authenticate user")
+            })?,
+            None => {
+                error!("authentication handler not available");
+                return Err(anyhow!("Unable to authenticate user"));
+            }
+        };
+        if pk.is_proxy {
+            error!(error = "Proxy token is not allowed");
+            return Err(anyhow!("Proxy token is not allowed"));
+        }
+        let access_key = tid.unwrap_or_else(|| user_id.to_string());
+        self.cache.get_key_perms(&access_key).await.ok_or_else(|| {
+            error!("Missing permissions for user");
+            anyhow!("Unable to authenticate user")
+        })
+    }
+
+    /// Checks the permission on the resource or any of its ancestors
+    async fn has_permission(
+        &self,
+        permissions: &AccessKeyPermissions,
+        resource_id: &DieselUlid,
+        level: DbPermissionLevel,
+    ) -> bool {
+        let mut queue = vec![*resource_id];
+        let mut visited = HashSet::new();
+        while let Some(id) = queue.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            if permissions
+                .permissions
+                .get(&id)
+                .is_some_and(|perm| *perm >= level)
+            {
+                return true;
+            }
+            if let Some(parents) = self.cache.get_parents(&id).await {
+                queue.extend(parents.into_iter().map(|(_, parent)| parent.get_id()));
+            }
+        }
+        false
+    }
+
+    /// Collects all objects below (and including) the given resource
+    async fn collect_objects(&self, resource_id: DieselUlid) -> Vec<DieselUlid> {
+        let mut objects = Vec::new();
+        let mut queue = vec![resource_id];
+        while let Some(id) = queue.pop() {
+            match self.cache.get_resource_cloned(&id, true).await {
+                Ok((resource, _)) if resource.object_type == ObjectType::Object => objects.push(id),
+                Ok(_) => {
+                    if let Some(children) = self.cache.get_children(&id).await {
+                        queue.extend(children.into_iter().map(|(_, child)| child.get_id()));
+                    }
+                }
+                // Unknown ids are rejected in enqueue
+                Err(_) => objects.push(id),
+            }
+        }
+        objects
+    }
+
+    /// Returns false if the object is already queued for this endpoint
+    async fn enqueue(&self, endpoint_id: DieselUlid, object_id: DieselUlid) -> Result<bool> {
+        let (object, location) = self
+            .cache
+            .get_resource_cloned(&object_id, false)
+            .await
+            .map_err(|_| anyhow!("Object not found"))?;
+        if location.is_some() {
+            return Err(anyhow!("Object is already available on this endpoint"));
+        }
+        let self_id = CONFIG.proxy.endpoint_id;
+        if !object.endpoints.iter().any(|ep| ep.id == self_id) {
+            return Err(anyhow!("Object is not assigned to this endpoint"));
+        }
+        if !object.endpoints.iter().any(|ep| ep.id == endpoint_id) {
+            return Err(anyhow!("Object is not available on the source endpoint"));
+        }
+        if self
+            .cache
+            .replication_progress
+            .is_queued(endpoint_id, object_id)
+        {
+            return Ok(false);
+        }
+        let direction = Direction::Pull(object_id);
+        self.cache
+            .replication_progress
+            .queue(endpoint_id, direction.clone());
+        self.cache
+            .sender
+            .send(ReplicationMessage {
+                direction,
+                endpoint_id,
+            })
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                anyhow!("Replication queue is not available")
+            })?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::structs::{Endpoint, Object, ObjectLocation, SyncVariant, TypedRelation};
+    use async_channel::Receiver;
+    use std::collections::HashMap;
+
+    struct Setup {
+        requester: ReplicationRequester,
+        queue: Receiver<ReplicationMessage>,
+        source: DieselUlid,
+        project: DieselUlid,
+        // Replicated to this proxy, not available yet
+        missing: DieselUlid,
+        // Already stored on this proxy
+        local: DieselUlid,
+        // Not assigned to this proxy
+        foreign: DieselUlid,
+    }
+
+    async fn setup() -> Setup {
+        let (cache, queue) = test_cache(None).await;
+
+        let source = DieselUlid::generate();
+        let endpoint = |id| Endpoint {
+            id,
+            variant: SyncVariant::FullSync,
+            status: None,
+        };
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let object = |name: &str, endpoints: Vec<DieselUlid>| Object {
+            endpoints: endpoints.into_iter().map(endpoint).collect(),
+            ..Object::initialize_now(
+                name.to_string(),
+                ObjectType::Object,
+                Some(TypedRelation::Project(project.id)),
+            )
+        };
+        let missing = object("missing", vec![CONFIG.proxy.endpoint_id, source]);
+        let local = object("local", vec![CONFIG.proxy.endpoint_id, source]);
+        let foreign = object("foreign", vec![source]);
+        cache.upsert_object(project.clone()).await.unwrap();
+        for object in [&missing, &local, &foreign] {
+            cache.upsert_object(object.clone()).await.unwrap();
+        }
+        cache
+            .add_location_with_binding(local.id, ObjectLocation::default())
+            .await
+            .unwrap();
+
+        Setup {
+            requester: ReplicationRequester::new(cache),
+            queue,
+            source,
+            project: project.id,
+            missing: missing.id,
+            local: local.id,
+            foreign: foreign.id,
+        }
+    }
+
+    fn permissions(resources: &[DieselUlid]) -> AccessKeyPermissions {
+        AccessKeyPermissions {
+            access_key: "access_key".to_string(),
+            user_id: DieselUlid::generate(),
+            secret: String::new(),
+            is_service_account: false,
+            permissions: resources
+                .iter()
+                .map(|id| (*id, DbPermissionLevel::Write))
+                .collect::<HashMap<_, _>>(),
+            session: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accepted_and_already_queued() {
+        let setup = setup().await;
+        let permissions = permissions(&[setup.project]);
+
+        let result = setup
+            .requester
+            .request_with_permissions(&permissions, setup.source, vec![setup.missing])
+            .await;
+        assert_eq!(result.accepted, vec![setup.missing]);
+        let queued = setup.queue.try_recv().unwrap();
+        assert_eq!(queued.endpoint_id, setup.source);
+        assert_eq!(queued.direction, Direction::Pull(setup.missing));
+
+        // The second request does not queue the object again
+        let result = setup
+            .requester
+            .request_with_permissions(&permissions, setup.source, vec![setup.missing])
+            .await;
+        assert!(result.accepted.is_empty());
+        assert_eq!(result.already_queued, vec![setup.missing]);
+        assert_eq!(result.replicated(), vec![setup.missing]);
+        assert!(setup.queue.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejected() {
+        let setup = setup().await;
+
+        // WRITE on the project is inherited by its objects
+        let result = setup
+            .requester
+            .request_with_permissions(
+                &permissions(&[setup.project]),
+                setup.source,
+                vec![setup.project],
+            )
+            .await;
+        assert_eq!(result.accepted, vec![setup.missing]);
+        let reason = |id| {
+            result
+                .rejected
+                .iter()
+                .find(|(rejected, _)| *rejected == id)
+                .map(|(_, reason)| reason.as_str())
+        };
+        assert_eq!(
+            reason(setup.local),
+            Some("Object is already available on this endpoint")
+        );
+        assert_eq!(
+            reason(setup.foreign),
+            Some("Object is not assigned to this endpoint")
+        );
+        assert!(!result.is_denied());
+
+        // Unknown source endpoint
+        let result = setup
+            .requester
+            .request_with_permissions(
+                &permissions(&[setup.project]),
+                DieselUlid::generate(),
+                vec![setup.missing],
+            )
+            .await;
+        assert_eq!(
+            result.rejected,
+            vec![(
+                setup.missing,
+                "Object is not available on the source endpoint".to_string()
+            )]
+        );
+
+        // Missing permissions
+        let result = setup
+            .requester
+            .request_with_permissions(&permissions(&[]), setup.source, vec![setup.missing])
+            .await;
+        assert!(result.is_denied());
+        assert!(result.replicated().is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::structs::{FileFormat, ObjectLocation};
+use crate::CONFIG;
+use serde::{Deserialize, Serialize};
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+/// Metadata key used to negotiate server side copies between co-located proxies.
+/// The pulling proxy offers the storage identities of its backends in the request
+/// metadata, the sending proxy answers with the offered identities it shares.
+/// Objects stored on a shared backend are announced with a `CopySource` in the
+/// `extra` field of their ObjectInfo (and no chunks) instead of being streamed.
+/// Peers without this key are not co-located or do not support server side copies.
+pub const SERVER_COPY_KEY: &str = "x-aruna-replication-server-copy";
+
+/// Stored data of an object on the shared backend, including the encryption key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CopySource {
+    pub backend: String,
+    pub bucket: String,
+    pub key: String,
+    pub file_format: FileFormat,
+    pub raw_content_len: i64,
+    pub disk_content_len: i64,
+    pub disk_hash: Option<String>,
+}
+
+impl CopySource {
+    /// Pithos files carry a footer with keys for the storing proxy and are always
+    /// streamed, as are locations that are not complete yet and customer encrypted data
+    pub fn from_location(location: &ObjectLocation, shared: &[String]) -> Option<Self> {
+        if location.is_pithos()
+            || location.is_customer_encrypted()
+            || location.is_temporary
+            || location.upload_id.is_some()
+        {
+            return None;
+        }
+        let backend = CONFIG
+            .backend_identity(location.backend.as_deref())
+            .filter(|identity| shared.contains(identity))?;
+        Some(CopySource {
+            backend,
+            bucket: location.bucket.clone(),
+            key: location.key.clone(),
+            file_format: location.file_format.clone(),
+            raw_content_len: location.raw_content_len,
+            disk_content_len: location.disk_content_len,
+            disk_hash: location.disk_hash.clone(),
+        })
+    }
+
+    pub fn to_extra(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+
+    pub fn from_extra(extra: Option<&str>) -> Option<Self> {
+        serde_json::from_str(extra?).ok()
+    }
+
+    /// The location on the shared backend, only usable as copy source
+    pub fn location(&self) -> ObjectLocation {
+        ObjectLocation {
+            bucket: self.bucket.clone(),
+            key: self.key.clone(),
+            file_format: self.file_format.clone(),
+            raw_content_len: self.raw_content_len,
+            disk_content_len: self.disk_content_len,
+            disk_hash: self.disk_hash.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Storage identities of all backends, empty if server side copies are disabled
+pub fn own_identities() -> Vec<String> {
+    if !CONFIG.proxy.replication_server_copy {
+        return Vec::new();
+    }
+    std::iter::once(None)
+        .chain(CONFIG.backends.keys().map(|name| Some(name.as_str())))
+        .filter_map(|name| CONFIG.backend_identity(name))
+        .collect()
+}
+
+/// Reads the offered or accepted identities, empty for peers without server side copies
+pub fn from_metadata(metadata: &MetadataMap) -> Vec<String> {
+    metadata
+        .get(SERVER_COPY_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .map(|identity| identity.trim().to_string())
+                .filter(|identity| !identity.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Identities of the offer that are shared with this proxy
+pub fn negotiate(offer: &[String]) -> Vec<String> {
+    let own = own_identities();
+    offer
+        .iter()
+        .filter(|identity| own.contains(identity))
+        .cloned()
+        .collect()
+}
+
+pub fn add_to_metadata(identities: &[String], metadata: &mut MetadataMap) {
+    if identities.is_empty() {
+        return;
+    }
+    if let Ok(value) = MetadataValue::try_from(identities.join(",")) {
+        metadata.insert(SERVER_COPY_KEY, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let mut metadata = MetadataMap::new();
+        add_to_metadata(&[], &mut metadata);
+        assert!(metadata.get(SERVER_COPY_KEY).is_none());
+        assert!(from_metadata(&metadata).is_empty());
+
+        let identities = vec!["a1".to_string(), "b2".to_string()];
+        add_to_metadata(&identities, &mut metadata);
+        assert_eq!(from_metadata(&metadata), identities);
+    }
+
+    #[test]
+    fn test_extra_roundtrip() {
+        let source = CopySource {
+            backend: "a1".to_string(),
+            bucket: "bucket".to_string(),
+            key: "key".to_string(),
+            file_format: FileFormat::RawEncryptedCompressed([7; 32]),
+            raw_content_len: 1234,
+            disk_content_len: 567,
+            disk_hash: Some("abc".to_string()),
+        };
+        assert_eq!(
+            CopySource::from_extra(source.to_extra().as_deref()),
+            Some(source)
+        );
+        assert_eq!(CopySource::from_extra(None), None);
+        assert_eq!(CopySource::from_extra(Some("not json")), None);
+    }
+}
@@ -0,0 +1,228 @@
+//! Request ids to correlate S3/gRPC requests, logs and calls to the Aruna server
+use crate::telemetry;
+use bytes::Bytes;
+use diesel_ulid::DieselUlid;
+use futures_core::future::BoxFuture;
+use http::{HeaderMap, HeaderValue};
+use std::future::Future;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tracing::{info, info_span, Instrument};
+
+/// Response header of S3 requests
+pub const S3_REQUEST_ID_HEADER: &str = "x-amz-request-id";
+/// Metadata of gRPC requests and responses, incoming ids are kept
+pub const GRPC_REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longer ids of other systems are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub fn generate() -> String {
+    DieselUlid::generate().to_string()
+}
+
+/// Id of the request the current task is handling
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Runs the future with the request id, e.g. the handling of a request
+pub async fn scope<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// Keeps the request id of the current task for a spawned task
+pub fn in_current_request<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let request_id = current();
+    async move {
+        match request_id {
+            Some(request_id) => REQUEST_ID.scope(request_id, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Forwards the id of the current request to the Aruna server
+pub fn add_to_metadata(md: &mut MetadataMap) {
+    if let Some(value) = current().and_then(|id| AsciiMetadataValue::try_from(id).ok()) {
+        md.insert(GRPC_REQUEST_ID_HEADER, value);
+    }
+}
+
+fn from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(GRPC_REQUEST_ID_HEADER)?
+        .to_str()
+        .ok()
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(|id| id.to_string())
+}
+
+/// Adds the request id to an S3 error body, s3s leaves the `RequestId` element empty
+pub fn add_to_error_body(body: &[u8], request_id: &str) -> Option<Bytes> {
+    let body = std::str::from_utf8(body).ok()?;
+    if body.contains("<RequestId>") {
+        return None;
+    }
+    let end = body.rfind("</Error>")?;
+    Some(Bytes::from(format!(
+        "{}<RequestId>{request_id}</RequestId>{}",
+        &body[..end],
+        &body[end..]
+    )))
+}
+
+/// Emits the access log line of a finished S3 request
+pub fn access_log(request_id: &str, method: &str, path: &str, status: u16, duration: Duration) {
+    info!(
+        target: "access_log",
+        request_id,
+        method,
+        path,
+        status,
+        duration_ms = duration.as_millis() as u64,
+        "S3 request"
+    );
+}
+
+/// Tower layer for the gRPC server: every call gets a request id (the one sent by the
+/// caller, if any), its span and response metadata contain the id
+#[derive(Debug, Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> tower::Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B, ResB> tower::Service<http::Request<B>> for RequestIdService<S>
+where
+    S: tower::Service<http::Request<B>, Response = http::Response<ResB>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let request_id = from_headers(req.headers()).unwrap_or_else(generate);
+        let value = HeaderValue::from_str(&request_id).ok();
+        if let Some(value) = &value {
+            req.headers_mut()
+                .insert(GRPC_REQUEST_ID_HEADER, value.clone());
+        }
+        let span = info_span!("grpc_request", request_id = %request_id, path = req.uri().path());
+        // Calls of peers (e.g. pull replications) continue the trace of the caller
+        telemetry::set_parent(&span, req.headers());
+        // The clone is ready only after poll_ready, the ready service handles this call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(
+            REQUEST_ID.scope(
+                request_id,
+                async move {
+                    let mut resp = inner.call(req).await?;
+                    if let Some(value) = value {
+                        resp.headers_mut().insert(GRPC_REQUEST_ID_HEADER, value);
+                    }
+                    Ok(resp)
+                }
+                .instrument(span),
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects the formatted log output
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_error_body() {
+        let body = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+            <Error><Code>NoSuchKey</Code><Message>Object not found</Message></Error>";
+        let with_id = add_to_error_body(body.as_bytes(), "01ID").unwrap();
+        assert!(std::str::from_utf8(&with_id)
+            .unwrap()
+            .ends_with("<Message>Object not found</Message><RequestId>01ID</RequestId></Error>"));
+        // Bodies with an id and other bodies are not changed
+        assert!(add_to_error_body(&with_id, "02ID").is_none());
+        assert!(add_to_error_body(b"data", "01ID").is_none());
+    }
+
+    #[test]
+    fn test_error_matches_access_log() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+
+        let request_id = generate();
+        let body = "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>";
+        let body = add_to_error_body(body.as_bytes(), &request_id).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            access_log(
+                &request_id,
+                "GET",
+                "/bucket/key",
+                403,
+                Duration::from_millis(3),
+            )
+        });
+
+        let body = std::str::from_utf8(&body).unwrap();
+        let start = body.find("<RequestId>").unwrap() + "<RequestId>".len();
+        let end = body.find("</RequestId>").unwrap();
+        let logged = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains(&format!("request_id=\"{}\"", &body[start..end])));
+        assert!(logged.contains("status=403"));
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(current().is_none());
+        let id = scope("01ID".to_string(), async {
+            tokio::spawn(in_current_request(async { current() }))
+                .await
+                .unwrap()
+        })
+        .await;
+        assert_eq!(id.as_deref(), Some("01ID"));
+    }
+}
@@ -1,20 +1,131 @@
+use super::utils::object_key::validate_key;
 use crate::caching::cache::Cache;
+use crate::config::ListenerProfile;
+use crate::structs::CheckAccessResult;
+use crate::telemetry;
+use http::{HeaderMap, Method};
 use s3s::{
-    auth::{S3Auth, S3AuthContext, SecretKey},
-    s3_error, S3Result,
+    auth::{Credentials, S3Auth, S3AuthContext, SecretKey},
+    path::S3Path,
+    s3_error, S3ErrorCode, S3Result,
 };
 use std::sync::Arc;
-use tracing::debug;
+use tracing::{debug, error};
+
+/// Cache-Control for public listeners if the project does not specify one
+pub const DEFAULT_PUBLIC_CACHE_CONTROL: &str = "public, max-age=300";
+
+fn is_select(query: Option<&str>) -> bool {
+    query.is_some_and(|query| {
+        query
+            .split('&')
+            .any(|param| param == "select" || param.starts_with("select="))
+    })
+}
+
+/// CreateBucket is a plain `PUT /<bucket>`, bucket subresources use a query
+fn is_create_bucket(method: &Method, query: Option<&str>) -> bool {
+    method == Method::PUT && query.map_or(true, |query| query.is_empty())
+}
 
 /// Aruna authprovider
 pub struct AuthProvider {
     cache: Arc<Cache>,
+    profile: Option<ListenerProfile>,
 }
 
 impl AuthProvider {
     #[tracing::instrument(level = "trace", skip(cache))]
-    pub async fn new(cache: Arc<Cache>) -> Self {
-        Self { cache }
+    pub async fn new(cache: Arc<Cache>, profile: Option<ListenerProfile>) -> Self {
+        Self { cache, profile }
+    }
+
+    /// Credentials of an access key, the signature is not verified
+    #[tracing::instrument(level = "trace", skip(self, access_key))]
+    pub async fn credentials(&self, access_key: &str) -> S3Result<Credentials> {
+        Ok(Credentials {
+            access_key: access_key.to_string(),
+            secret_key: self.get_secret_key(access_key).await?,
+        })
+    }
+
+    /// Access decision of a request, None if no auth handler is available
+    #[tracing::instrument(level = "trace", skip(self, credentials, headers))]
+    pub async fn authorize(
+        &self,
+        credentials: Option<&Credentials>,
+        method: &Method,
+        path: &S3Path,
+        query: Option<&str>,
+        headers: &HeaderMap,
+    ) -> S3Result<Option<CheckAccessResult>> {
+        debug!(?path);
+
+        // Malformed keys never reach the cache or the backends
+        if let S3Path::Object { key, .. } = path {
+            validate_key(key)?;
+        }
+
+        // SelectObjectContent (POST ?select) only reads the object
+        let get = Method::GET;
+        let method = if method == Method::POST && is_select(query) {
+            &get
+        } else {
+            method
+        };
+
+        match self.cache.auth.read().await.as_ref() {
+            Some(auth) => {
+                let result = match self.profile {
+                    None => match auth.check_access(credentials, method, path, headers).await {
+                        Ok(result) => result,
+                        Err(e) if *e.code() == S3ErrorCode::AccessDenied => {
+                            // Creating an existing bucket without access to it
+                            if let S3Path::Bucket { bucket } = path {
+                                if is_create_bucket(method, query)
+                                    && self.cache.get_full_resource_by_path(bucket).await.is_some()
+                                {
+                                    error!(%bucket, "Bucket already exists");
+                                    return Err(s3_error!(
+                                        BucketAlreadyExists,
+                                        "The requested bucket name is not available"
+                                    ));
+                                }
+                            }
+                            return Err(e);
+                        }
+                        Err(e) => return Err(e),
+                    },
+                    Some(ListenerProfile::PublicReadOnly) => {
+                        if method != Method::GET && method != Method::HEAD {
+                            error!("Write request on public listener");
+                            return Err(s3_error!(MethodNotAllowed, "Listener is read-only"));
+                        }
+                        // Credentials are ignored, every request is anonymous
+                        let mut result = auth.check_access(None, method, path, headers).await?;
+                        if let Some(states) = result.objects_state.resource_states() {
+                            if let Some(project) = states.get_project() {
+                                let cache_control = self
+                                    .cache
+                                    .get_cache_control(&project.id)
+                                    .unwrap_or_else(|| DEFAULT_PUBLIC_CACHE_CONTROL.to_string());
+                                result
+                                    .headers
+                                    .get_or_insert_with(Default::default)
+                                    .insert("Cache-Control".to_string(), cache_control);
+                            }
+                        }
+                        result
+                    }
+                };
+
+                if let Some(user_id) = result.user_state.get_user_id() {
+                    telemetry::record_user(&user_id);
+                }
+                Ok(Some(result))
+            }
+            None => Ok(None),
+        }
     }
 }
 
@@ -33,18 +144,18 @@ impl S3Auth for AuthProvider {
 
     #[tracing::instrument(level = "trace", skip(self, cx))]
     async fn check_access(&self, cx: &mut S3AuthContext<'_>) -> S3Result<()> {
-        debug!(path = ?cx.s3_path());
-
-        match self.cache.auth.read().await.as_ref() {
-            Some(auth) => {
-                let result = auth
-                    .check_access(cx.credentials(), cx.method(), cx.s3_path(), cx.headers())
-                    .await?;
-
-                cx.extensions_mut().insert(result);
-                Ok(())
-            }
-            None => Ok(()),
+        if let Some(result) = self
+            .authorize(
+                cx.credentials(),
+                cx.method(),
+                cx.s3_path(),
+                cx.uri().query(),
+                cx.headers(),
+            )
+            .await?
+        {
+            cx.extensions_mut().insert(result);
         }
+        Ok(())
     }
 }
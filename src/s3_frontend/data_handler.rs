@@ -1,6 +1,7 @@
 use crate::caching::cache::Cache;
 use crate::data_backends::storage_backend::StorageBackend;
 use crate::s3_frontend::utils::buffered_s3_sink::BufferedS3Sink;
+use crate::structs::FileFormat;
 use crate::structs::Object;
 use crate::structs::ObjectLocation;
 use anyhow::anyhow;
@@ -20,6 +21,7 @@ use pithos_lib::transformers::size_probe::SizeProbe;
 use pithos_lib::transformers::zstd_comp::ZstdEnc;
 use pithos_lib::transformers::zstd_decomp::ZstdDec;
 use sha2::Sha256;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
 use tokio::pin;
@@ -33,9 +35,11 @@ use tracing::Instrument;
 pub struct DataHandler {}
 
 impl DataHandler {
+    /// Rewrites the parts of a completed multipart upload into the final location,
+    /// uploads with a customer provided key (SSE-C) can only be finalized with the key
     #[tracing::instrument(
         level = "trace",
-        skip(object, cache, backend, before_location, path_level)
+        skip(object, cache, backend, before_location, path_level, customer_key)
     )]
     pub async fn finalize_location(
         object: Object,
@@ -43,7 +47,13 @@ impl DataHandler {
         backend: Arc<Box<dyn StorageBackend>>,
         before_location: ObjectLocation,
         path_level: Option<[Option<(DieselUlid, String)>; 4]>,
+        customer_key: Option<[u8; 32]>,
     ) -> Result<()> {
+        if before_location.is_customer_encrypted() && customer_key.is_none() {
+            error!(object_id = %object.id, "Missing customer key to finalize upload");
+            return Err(anyhow!("Missing customer key to finalize upload"));
+        }
+
         let token = if let Some(handler) = cache.auth.read().await.as_ref() {
             let Some(created_by) = object.created_by.clone() else {
                 error!("No created_by found");
@@ -75,9 +85,17 @@ impl DataHandler {
             })?
         };
 
+        let storage = cache.get_storage_policy(&parents);
         let mut new_location = backend
-            .initialize_location(&object, None, parents, false)
+            .initialize_location(&object, None, parents, storage.as_deref(), false)
             .await?;
+        new_location.storage_class = before_location.storage_class.clone();
+        if let Some(customer_key) = customer_key {
+            // Stored with the placeholder key, the key itself is only used for the rewrite
+            new_location.file_format =
+                FileFormat::customer_encrypted(new_location.is_compressed()).with_key(customer_key);
+            new_location.customer_key = before_location.customer_key.clone();
+        }
 
         debug!(?before_location, ?new_location, "Finalizing location");
 
@@ -88,7 +106,7 @@ impl DataHandler {
 
         let (tx_send, tx_receive) = async_channel::bounded(10);
 
-        let clone_key = before_location.get_encryption_key();
+        let clone_key = customer_key.or_else(|| before_location.get_encryption_key());
 
         let before_location = before_location.clone();
         let backend_clone = backend.clone();
@@ -107,7 +125,7 @@ impl DataHandler {
 
         trace!(part_lens = ?part_lens, "Part lengths");
 
-        let aswr_handle = tokio::spawn(
+        let aswr_handle = crate::spawn(
             async move {
                 let (tx, rx) = async_channel::bounded(10);
                 let (sink, _) = BufferedS3Sink::new(
@@ -170,7 +188,7 @@ impl DataHandler {
                     asr = asr.add_transformer(PithosTransformer::new());
                     asr = asr.add_transformer(FooterGenerator::new(None));
                 }
-                
+
                 let (final_sha, final_sha_recv) =
                     HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
 
@@ -178,7 +196,7 @@ impl DataHandler {
 
                 let (disk_size_probe, disk_size_stream) = SizeProbe::new();
                 asr = asr.add_transformer(disk_size_probe);
-                
+
                 asr.process().await.map_err(|e| {
                     error!(error = ?e, msg = e.to_string());
                     e
@@ -231,9 +249,21 @@ impl DataHandler {
                 e
             })?;
 
+        if customer_key.is_some() {
+            new_location.file_format = FileFormat::customer_encrypted(new_location.is_compressed());
+        }
         new_location.disk_content_len = before_size as i64;
         new_location.raw_content_len = after_size as i64;
         new_location.disk_hash = Some(final_sha);
+        new_location.content_type = before_location.content_type.clone();
+        new_location.user_metadata = before_location.user_metadata.clone();
+        // Multipart uploads keep their md5-of-md5s etag
+        new_location.etag = before_location.etag.clone().or_else(|| Some(md5.clone()));
+        new_location.checksum = before_location.checksum.clone();
+        new_location.raw_hashes = HashMap::from_iter([
+            ("sha256".to_string(), sha.clone()),
+            ("md5".to_string(), md5.clone()),
+        ]);
 
         debug!(new_location = ?new_location, "Finished finalizing location");
 
@@ -252,8 +282,9 @@ impl DataHandler {
             // Set id of new location to object id to satisfy FK constraint
             // TODO: Update hashes etc.
 
-            handler.set_object_hashes(&object.id, hashes, &token).await?;
-
+            handler
+                .set_object_hashes(&object.id, hashes, &token)
+                .await?;
 
             cache.update_location(object.id, new_location).await?;
 
@@ -1,11 +1,27 @@
 use super::auth::AuthProvider;
 use super::s3service::ArunaS3Service;
+use super::utils::cors::handle_preflight;
+use super::utils::expect_continue::{expects_continue, UploadPrecheck};
+use super::utils::multipart::{is_complete_multipart_upload, read_complete_body};
+use super::utils::rate_limit::{extract_access_key, Principal, RateLimiter, RequestClass};
+use super::utils::tls::{tls_incoming, ReloadingTlsConfig, RemoteAddr, RELOAD_INTERVAL};
+use super::utils::tree::{handle_tree, tree_path};
 use crate::caching::cache;
+use crate::config::{ListenerProfile, Tls};
 use crate::data_backends::storage_backend::StorageBackend;
-use anyhow::Result;
+use crate::helpers::split_s3_path;
+use crate::metrics::{S3_BYTES_IN, S3_BYTES_OUT, S3_REQUESTS, S3_REQUEST_DURATION, S3_THROTTLED};
+use crate::request_id;
+use crate::scoped_config::{self, Scope};
+use crate::shutdown::Shutdown;
+use crate::telemetry;
+use crate::CONFIG;
+use anyhow::{anyhow, Result};
 use futures_core::future::BoxFuture;
+use futures_util::future::try_join_all;
 use futures_util::FutureExt;
-use http::StatusCode;
+use http::uri::PathAndQuery;
+use http::{HeaderValue, Method, StatusCode};
 use hyper::service::Service;
 use hyper::Server;
 use s3s::service::S3Service;
@@ -16,28 +32,53 @@ use s3s::S3Error;
 use std::convert::Infallible;
 use std::future::ready;
 use std::future::Ready;
+use std::net::IpAddr;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use std::{net::TcpListener, sync::Arc};
 use tracing::error;
 use tracing::info;
 use tracing::info_span;
+use tracing::warn;
 use tracing::Instrument;
 
 pub struct S3Server {
     s3service: S3Service,
+    precheck: Arc<UploadPrecheck>,
     address: String,
+    hostname: String,
+    profile: Option<ListenerProfile>,
+    cache: Arc<cache::Cache>,
+    tls: Option<Arc<ReloadingTlsConfig>>,
+    http_address: Option<String>,
+    scope: &'static Scope,
 }
 
+/// Idle token buckets of the rate limiter are dropped in this interval
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Clone)]
-pub struct WrappingService(SharedS3Service);
+pub struct WrappingService {
+    service: SharedS3Service,
+    precheck: Arc<UploadPrecheck>,
+    profile: Option<ListenerProfile>,
+    cache: Arc<cache::Cache>,
+    hostname: Arc<String>,
+    rate_limiter: Arc<RateLimiter>,
+    remote_ip: Option<IpAddr>,
+    /// Scope of the dataproxy the listener belongs to
+    scope: &'static Scope,
+}
 
 impl S3Server {
     #[tracing::instrument(level = "trace", skip(address, hostname, backend, cache))]
     pub async fn new(
         address: impl Into<String> + Copy,
-        hostname: impl Into<String>,
+        hostname: impl Into<String> + Clone,
         backend: Arc<Box<dyn StorageBackend>>,
         cache: Arc<cache::Cache>,
+        profile: Option<ListenerProfile>,
+        tls: Option<Tls>,
     ) -> Result<Self> {
         let s3service = ArunaS3Service::new(backend, cache.clone())
             .await
@@ -48,31 +89,87 @@ impl S3Server {
 
         let service = {
             let mut b = S3ServiceBuilder::new(s3service);
-            b.set_base_domain(hostname);
-            b.set_auth(AuthProvider::new(cache).await);
+            b.set_base_domain(hostname.clone());
+            b.set_auth(AuthProvider::new(cache.clone(), profile).await);
             b.build()
         };
+        let precheck = Arc::new(UploadPrecheck::new(
+            AuthProvider::new(cache.clone(), profile).await,
+            cache.clone(),
+        ));
+
+        // Loaded here to fail on startup for invalid certificates
+        let http_address = tls.as_ref().and_then(|tls| tls.http_server.clone());
+        let tls = match tls {
+            Some(tls) => Some(Arc::new(ReloadingTlsConfig::new(tls)?)),
+            None => None,
+        };
 
         Ok(Self {
             s3service: service,
+            precheck,
             address: address.into(),
+            hostname: hostname.into(),
+            profile,
+            cache,
+            tls,
+            http_address,
+            scope: scoped_config::current_scope(),
         })
     }
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn run(self) -> Result<()> {
-        // Run server
-        let listener = TcpListener::bind(&self.address).map_err(|e| {
-            error!(error = ?e, msg = e.to_string());
-            tonic::Status::unauthenticated(e.to_string())
-        })?;
-        let server = Server::from_tcp(listener)
-            .map_err(|e| {
-                error!(error = ?e, msg = e.to_string());
-                tonic::Status::unauthenticated(e.to_string())
-            })?
-            .serve(WrappingService(self.s3service.into_shared()).into_make_service());
-        info!("server is running at http(s)://{}/", self.address);
-        Ok(tokio::spawn(server)
+    /// Serves until the shutdown is triggered, in-flight requests are
+    /// drained gracefully within the configured grace period
+    #[tracing::instrument(level = "trace", skip(self, shutdown))]
+    pub async fn run(self, shutdown: Shutdown) -> Result<()> {
+        let rate_limiter = Arc::new(RateLimiter::new(
+            CONFIG
+                .frontend
+                .as_ref()
+                .map(|frontend| frontend.rate_limits.clone())
+                .unwrap_or_default(),
+        ));
+        let prune_limiter = rate_limiter.clone();
+        let prune_shutdown = shutdown.clone();
+        crate::spawn(async move {
+            let mut interval = tokio::time::interval(RATE_LIMIT_PRUNE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => prune_limiter.prune(),
+                    _ = prune_shutdown.signaled() => return,
+                }
+            }
+        });
+        let service = WrappingService {
+            service: self.s3service.into_shared(),
+            precheck: self.precheck,
+            profile: self.profile,
+            cache: self.cache,
+            hostname: Arc::new(self.hostname),
+            rate_limiter,
+            remote_ip: None,
+            scope: self.scope,
+        };
+
+        // Run servers
+        let mut servers = Vec::new();
+        match self.tls {
+            Some(tls) => {
+                servers
+                    .push(serve_tls(&self.address, tls, service.clone(), shutdown.clone()).await?);
+                if let Some(http_address) = &self.http_address {
+                    servers.push(serve_http(http_address, service, shutdown.clone())?);
+                }
+            }
+            None => servers.push(serve_http(&self.address, service, shutdown.clone())?),
+        }
+        let server = async move {
+            shutdown
+                .with_grace("s3_server", async {
+                    try_join_all(servers).await.map(|_| ())
+                })
+                .await
+        };
+        Ok(crate::spawn(server)
             .instrument(info_span!("s3_server_run"))
             .await
             .map_err(|e| {
@@ -86,6 +183,72 @@ impl S3Server {
     }
 }
 
+/// Plain http server on the address
+#[tracing::instrument(level = "trace", skip(service, shutdown))]
+fn serve_http(
+    address: &str,
+    service: WrappingService,
+    shutdown: Shutdown,
+) -> Result<BoxFuture<'static, Result<()>>> {
+    let listener = TcpListener::bind(address).map_err(|e| {
+        error!(error = ?e, msg = e.to_string());
+        tonic::Status::unauthenticated(e.to_string())
+    })?;
+    let server = Server::from_tcp(listener)
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            tonic::Status::unauthenticated(e.to_string())
+        })?
+        .serve(service.into_make_service())
+        .with_graceful_shutdown(shutdown.signaled());
+    info!("server is running at http://{}/", address);
+    Ok(async move {
+        server.await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!(e)
+        })
+    }
+    .boxed())
+}
+
+/// Https server on the address, the certificate is reloaded when the files change
+#[tracing::instrument(level = "trace", skip(tls, service, shutdown))]
+async fn serve_tls(
+    address: &str,
+    tls: Arc<ReloadingTlsConfig>,
+    service: WrappingService,
+    shutdown: Shutdown,
+) -> Result<BoxFuture<'static, Result<()>>> {
+    let listener = tokio::net::TcpListener::bind(address).await.map_err(|e| {
+        error!(error = ?e, msg = e.to_string());
+        e
+    })?;
+
+    let reload_tls = tls.clone();
+    let reload_shutdown = shutdown.clone();
+    crate::spawn(async move {
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => reload_tls.reload_if_changed(),
+                _ = reload_shutdown.signaled() => return,
+            }
+        }
+    });
+
+    let server = Server::builder(tls_incoming(listener, tls, shutdown.clone()))
+        .serve(service.into_make_service())
+        .with_graceful_shutdown(shutdown.signaled());
+    info!("server is running at https://{}/", address);
+    Ok(async move {
+        server.await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            anyhow!(e)
+        })
+    }
+    .boxed())
+}
+
 impl Service<hyper::Request<hyper::Body>> for WrappingService {
     type Response = hyper::Response<Body>;
 
@@ -100,34 +263,240 @@ impl Service<hyper::Request<hyper::Body>> for WrappingService {
 
     #[tracing::instrument(level = "trace", skip(self, req))]
     fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
-        let mut service = self.0.clone();
-        let resp = service.call(req);
-        let res = resp.map(|r| {
-            r.map(|mut r| {
-                if r.headers().contains_key("Transfer-Encoding") {
-                    r.headers_mut().remove("Content-Length");
-                }
+        // hyper drives every connection in a task of its own
+        let scope = self.scope;
+        let resp = scoped_config::sync_scope(scope, || self.handle(req));
+        scoped_config::scope(scope, resp).boxed()
+    }
+}
 
-                // Workaround to return 206 (Partial Content) for range responses
-                if r.headers().contains_key("Content-Range")
-                    && r.headers().contains_key("Accept-Ranges")
-                    && r.status().as_u16() == 200
-                {
-                    let status = r.status_mut();
-                    *status = StatusCode::from_u16(206).unwrap();
-                }
+impl WrappingService {
+    /// Handles the request with its span, request id, metrics and access log
+    fn handle(
+        &mut self,
+        req: hyper::Request<hyper::Body>,
+    ) -> BoxFuture<'static, Result<hyper::Response<Body>, S3Error>> {
+        let started = Instant::now();
+        let class = RequestClass::from_request(&req, &self.hostname);
+        let request_id = request_id::generate();
+        let method = req.method().to_string();
+        let path = req.uri().path().to_string();
+        let (bucket, key) = split_s3_path(&req, &self.hostname);
+        // All spans of the request (incl. backend operations and spawned tasks) are children
+        let span = info_span!(
+            "s3_request",
+            request_id = %request_id,
+            method = %method,
+            path = %path,
+            bucket = bucket.as_deref().unwrap_or_default(),
+            key = key.as_deref().unwrap_or_default(),
+            user_id = tracing::field::Empty,
+            debug_trace = self.is_debug_trace(&req),
+        );
+        let resp = span.in_scope(|| self.dispatch(req, class));
+        let request = request_id::scope(request_id.clone(), async move {
+            let resp = resp.await?;
+            let resp = add_request_id(resp, &request_id).await;
+            record_metrics(class, started, &resp);
+            request_id::access_log(
+                &request_id,
+                &method,
+                &path,
+                resp.status().as_u16(),
+                started.elapsed(),
+            );
+            Ok(resp)
+        });
+        telemetry::scope(span.clone(), request)
+            .instrument(span)
+            .boxed()
+    }
+
+    /// Admins force the export of the request trace with the debug header, the
+    /// signature is verified later on, a forged access key only exports the trace
+    fn is_debug_trace(&self, req: &hyper::Request<hyper::Body>) -> bool {
+        if !telemetry::debug_requested(req.headers()) {
+            return false;
+        }
+        extract_access_key(req)
+            .and_then(|access_key| self.cache.try_get_key_owner(&access_key))
+            .is_some_and(|user_id| CONFIG.proxy.admin_ids.contains(&user_id))
+    }
 
-                r.map(Body::from)
-            })
+    /// Handles the request, metrics and the request id are added by the caller
+    fn dispatch(
+        &mut self,
+        mut req: hyper::Request<hyper::Body>,
+        class: RequestClass,
+    ) -> BoxFuture<'static, Result<hyper::Response<Body>, S3Error>> {
+        let principal = Principal::from_request(&req, self.remote_ip, |access_key| {
+            self.cache.try_get_key_owner(access_key).is_some()
         });
-        res.boxed()
+        if let Err(retry_after) = self.rate_limiter.check(&principal, class) {
+            warn!(
+                principal = %principal.label(),
+                class = class.as_str(),
+                "Request throttled"
+            );
+            S3_THROTTLED.with_label_values(&[class.as_str()]).inc();
+            return ready(Ok(slow_down(retry_after))).boxed();
+        }
+        if let Some(len) = content_length(req.headers()) {
+            S3_BYTES_IN.inc_by(len);
+        }
+
+        // Revisions are immutable, only reads can address a specific version
+        if (req.method() == Method::PUT || req.method() == Method::POST) && has_version_id(&req) {
+            error!("Write request with versionId");
+            return ready(Ok(invalid_version_write())).boxed();
+        }
+
+        // CORS preflights are answered without credentials on every listener
+        if req.method() == Method::OPTIONS {
+            let cache = self.cache.clone();
+            let hostname = self.hostname.clone();
+            return async move { Ok(handle_preflight(cache, &hostname, &req).await) }.boxed();
+        }
+        if let Some(ListenerProfile::PublicReadOnly) = self.profile {
+            if req.method() != Method::GET && req.method() != Method::HEAD {
+                error!("Write request on public listener");
+                let resp = hyper::Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .body(Body::empty())
+                    .map_err(|_| s3s::s3_error!(InternalError, "Unable to build response"));
+                return ready(resp).boxed();
+            }
+            strip_credentials(&mut req);
+        }
+        // Folder tree of the portal, authenticated with aruna tokens instead of signatures,
+        // tokens were already stripped on public listeners
+        if let Some(path) = tree_path(&req, &self.hostname) {
+            let cache = self.cache.clone();
+            return async move { Ok(handle_tree(cache, &path, &req).await) }.boxed();
+        }
+        let mut service = self.service.clone();
+        // Public listeners reject uploads already
+        let precheck =
+            (self.profile.is_none() && expects_continue(&req)).then(|| self.precheck.clone());
+        let hostname = self.hostname.clone();
+        async move {
+            // hyper sends 100 Continue when the body is polled, rejected uploads are
+            // answered before that and the client does not send the body
+            if let Some(precheck) = precheck {
+                if let Err(e) = precheck.check(&req, &hostname).await {
+                    return Ok(early_rejection(&e));
+                }
+            }
+            // s3s deserializes the whole part list, its size is checked upfront
+            if is_complete_multipart_upload(&req) {
+                let (parts, body) = req.into_parts();
+                match read_complete_body(body).await {
+                    Ok(body) => req = hyper::Request::from_parts(parts, hyper::Body::from(body)),
+                    Err(e) => return Ok(early_rejection(&e)),
+                }
+            }
+            let mut r = service.call(req).await?;
+            if r.headers().contains_key("Transfer-Encoding") {
+                r.headers_mut().remove("Content-Length");
+            }
+
+            // Workaround to return 206 (Partial Content) for range responses
+            if r.headers().contains_key("Content-Range")
+                && r.headers().contains_key("Accept-Ranges")
+                && r.status().as_u16() == 200
+            {
+                let status = r.status_mut();
+                *status = StatusCode::from_u16(206).unwrap();
+            }
+
+            Ok(r.map(Body::from))
+        }
+        .boxed()
+    }
+}
+
+/// Removes all credentials (header and presigned query parameters) from a request
+#[tracing::instrument(level = "trace", skip(req))]
+fn strip_credentials(req: &mut hyper::Request<hyper::Body>) {
+    req.headers_mut().remove(hyper::header::AUTHORIZATION);
+    req.headers_mut().remove("x-amz-security-token");
+
+    let Some(query) = req.uri().query() else {
+        return;
+    };
+    let filtered = query
+        .split('&')
+        .filter(|param| {
+            let key = param
+                .split('=')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            !(key.starts_with("x-amz-")
+                || key == "awsaccesskeyid"
+                || key == "signature"
+                || key == "expires")
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let path_and_query = if filtered.is_empty() {
+        req.uri().path().to_string()
+    } else {
+        format!("{}?{}", req.uri().path(), filtered)
+    };
+    let mut parts = req.uri().clone().into_parts();
+    if let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) {
+        parts.path_and_query = Some(path_and_query);
+        if let Ok(uri) = hyper::Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+}
+
+/// Adds the request id header, s3s error bodies get the id as `RequestId`
+async fn add_request_id(
+    mut resp: hyper::Response<Body>,
+    request_id: &str,
+) -> hyper::Response<Body> {
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        resp.headers_mut()
+            .insert(request_id::S3_REQUEST_ID_HEADER, value);
+    }
+    let is_xml = resp
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("xml"))
+        .unwrap_or(false);
+    if !resp.status().is_client_error() && !resp.status().is_server_error() || !is_xml {
+        return resp;
     }
+    let (mut parts, body) = resp.into_parts();
+    // Error bodies are small and already buffered by s3s
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!(error = ?e, msg = "Unable to read error body");
+            return hyper::Response::from_parts(parts, Body::empty());
+        }
+    };
+    let body = match request_id::add_to_error_body(&body, request_id) {
+        Some(with_id) => {
+            parts.headers.insert(
+                hyper::header::CONTENT_LENGTH,
+                HeaderValue::from(with_id.len()),
+            );
+            with_id
+        }
+        None => body,
+    };
+    hyper::Response::from_parts(parts, Body::from(body))
 }
 
 impl AsRef<S3Service> for WrappingService {
     #[tracing::instrument(level = "trace", skip(self))]
     fn as_ref(&self) -> &S3Service {
-        self.0.as_ref()
+        self.service.as_ref()
     }
 }
 
@@ -139,11 +508,91 @@ impl WrappingService {
     }
 }
 
+/// Responds with 503 SlowDown, clients should retry after the given duration
+fn slow_down(retry_after: Duration) -> hyper::Response<Body> {
+    let body = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Error><Code>SlowDown</Code><Message>Please reduce your request rate.</Message></Error>";
+    hyper::Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(hyper::header::CONTENT_TYPE, "application/xml")
+        .header(
+            hyper::header::RETRY_AFTER,
+            retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+        )
+        .body(Body::from(body.to_string()))
+        .unwrap_or_else(|_| hyper::Response::new(Body::empty()))
+}
+
+/// Error response of an upload rejected before its body was read, the connection is
+/// closed since the unread body can not be skipped
+fn early_rejection(err: &S3Error) -> hyper::Response<Body> {
+    let message = err
+        .message()
+        .unwrap_or_default()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Error><Code>{}</Code><Message>{message}</Message></Error>",
+        err.code().as_str()
+    );
+    hyper::Response::builder()
+        .status(err.status_code().unwrap_or(StatusCode::FORBIDDEN))
+        .header(hyper::header::CONTENT_TYPE, "application/xml")
+        .header(hyper::header::CONNECTION, "close")
+        .body(Body::from(body))
+        .unwrap_or_else(|_| hyper::Response::new(Body::empty()))
+}
+
+fn has_version_id(req: &hyper::Request<hyper::Body>) -> bool {
+    req.uri()
+        .query()
+        .map(|query| {
+            query
+                .split('&')
+                .any(|param| param.split('=').next() == Some("versionId"))
+        })
+        .unwrap_or(false)
+}
+
+fn invalid_version_write() -> hyper::Response<Body> {
+    let body = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Error><Code>InvalidArgument</Code><Message>Writes must not specify a versionId.</Message></Error>";
+    hyper::Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(hyper::header::CONTENT_TYPE, "application/xml")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_else(|_| hyper::Response::new(Body::empty()))
+}
+
+fn content_length(headers: &http::HeaderMap) -> Option<u64> {
+    headers
+        .get(hyper::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Streamed responses without Content-Length are not counted in the sent bytes
+fn record_metrics(class: RequestClass, started: Instant, resp: &hyper::Response<Body>) {
+    S3_REQUESTS
+        .with_label_values(&[class.as_str(), resp.status().as_str()])
+        .inc();
+    S3_REQUEST_DURATION
+        .with_label_values(&[class.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    if let Some(len) = content_length(resp.headers()) {
+        S3_BYTES_OUT.inc_by(len);
+    }
+}
+
 #[derive(Clone)]
 pub struct MakeService<S>(S);
 
-impl<T, S: Clone> Service<T> for MakeService<S> {
-    type Response = S;
+impl<'a, C: RemoteAddr> Service<&'a C> for MakeService<WrappingService> {
+    type Response = WrappingService;
 
     type Error = Infallible;
 
@@ -154,8 +603,10 @@ impl<T, S: Clone> Service<T> for MakeService<S> {
         Poll::Ready(Ok(()))
     }
 
-    #[tracing::instrument(level = "trace", skip(self))]
-    fn call(&mut self, _: T) -> Self::Future {
-        ready(Ok(self.0.clone()))
+    #[tracing::instrument(level = "trace", skip(self, conn))]
+    fn call(&mut self, conn: &'a C) -> Self::Future {
+        let mut service = self.0.clone();
+        service.remote_ip = conn.remote_ip();
+        ready(Ok(service))
     }
 }
@@ -1,31 +1,78 @@
 use super::data_handler::DataHandler;
+use super::utils::acl::{project_owner, CannedAcl};
+use super::utils::aws_chunked::{decode_body, is_aws_chunked, payload_content_length};
+use super::utils::bandwidth::BandwidthLimiter;
+use super::utils::bucket_name::{key_to_path, validate_bucket_name};
 use super::utils::buffered_s3_sink::BufferedS3Sink;
-use super::utils::ranges::calculate_ranges;
-use crate::bundler::bundle_helper::get_bundle;
+use super::utils::checksum::{
+    empty_checksum, hex_to_checksum, is_identical_upload, ChecksumTransformer, ContentMd5,
+    RequestedChecksum, Sha256VerifyTransformer, EMPTY_MD5, EMPTY_SHA256,
+};
+use super::utils::compression::{peek_body, should_compress};
+use super::utils::conditional::{Preconditions, WriteLock, WritePreconditions};
+use super::utils::multipart::{merge_parts, CompletedParts};
+use super::utils::object_key::{encode_listed, is_url_encoding, url_encode};
+use super::utils::ranges::{calculate_ranges, RangeNotSatisfiable};
+use super::utils::response_overrides::ResponseOverrides;
+use super::utils::select::SelectExecutor;
+use super::utils::upload_limits::{
+    check_declared, check_quota, limit_body, object_limit, part_limit, project_limits,
+};
+use crate::bundler::bundle_helper::{
+    get_bundle, prepare_members, BundleFormat, BundleManifest, BundleMember,
+};
+use crate::bundler::remote::RemoteObjects;
 use crate::caching::cache::Cache;
+use crate::caching::deletion::{self, DeleteNotifier};
+use crate::caching::policies::{
+    resolve_storage, CORS_KEY, LIFECYCLE_KEY, OBJECT_LOCK_KEY, RETENTION_KEY,
+};
+use crate::caching::webhooks;
+use crate::config::UploadLimits;
+use crate::data_backends::parallel_get::parallel_get_object;
 use crate::data_backends::storage_backend::StorageBackend;
-use crate::s3_frontend::utils::list_objects::list_response;
+use crate::error::ProxyError;
+use crate::maintenance::footer_repair::{data_section_len, read_footer};
+use crate::request_id;
+use crate::s3_frontend::utils::list_buckets::accessible_buckets;
+use crate::s3_frontend::utils::list_objects::{
+    extended_listing, extension_header, last_entry, list_response, marker_start,
+};
+use crate::s3_frontend::utils::sse_c::{unlock_location, CustomerKey};
 use crate::structs::CheckAccessResult;
+use crate::structs::ContentChecksum;
+use crate::structs::ContentChecksumAlgorithm;
+use crate::structs::DbPermissionLevel;
+use crate::structs::FileFormat;
+use crate::structs::LifecycleConfiguration;
+use crate::structs::MultipartUploadInfo;
 use crate::structs::NewOrExistingObject;
 use crate::structs::Object as ProxyObject;
+use crate::structs::ObjectLocation;
+use crate::structs::ObjectLockPolicy;
+use crate::structs::ObjectRetention;
+use crate::structs::ObjectType;
 use crate::structs::ObjectsState;
 use crate::structs::PartETag;
+use crate::structs::RestoreStatus;
 use crate::structs::TypedRelation;
+use crate::structs::UserState;
+use crate::structs::{ARCHIVE_STORAGE_CLASSES, STORAGE_CLASSES};
 use crate::CONFIG;
 use anyhow::Result;
-use aruna_rust_api::api::storage::models::v2::Hash;
-use aruna_rust_api::api::storage::models::v2::Hashalgorithm;
+use aruna_rust_api::api::storage::models::v2::DataClass;
 use aruna_rust_api::api::storage::models::v2::Status;
 use base64::engine::general_purpose;
 use base64::Engine;
-use bytes::BufMut;
-use bytes::BytesMut;
+use bytes::Bytes;
+use dashmap::DashSet;
+use diesel_ulid::DieselUlid;
 use futures_util::TryStreamExt;
+use http::HeaderMap;
 use http::HeaderName;
 use http::HeaderValue;
 use md5::{Digest, Md5};
 use pithos_lib::helpers::footer_parser::Footer;
-use pithos_lib::helpers::footer_parser::FooterParser;
 use pithos_lib::helpers::notifications::Message as PithosMessage;
 use pithos_lib::streamreadwrite::GenericStreamReadWriter;
 use pithos_lib::transformer::ReadWriter;
@@ -47,21 +94,109 @@ use s3s::S3Response;
 use s3s::S3Result;
 use s3s::S3;
 use sha2::Sha256;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::pin;
 use tracing::debug;
 use tracing::error;
+use tracing::info;
 use tracing::info_span;
 use tracing::trace;
 use tracing::warn;
 use tracing::Instrument;
 
+/// Reports the proxy side state of the object data (`x-aruna-object-status`) and the
+/// sync status of this endpoint (`x-aruna-endpoint-status`)
+fn insert_staging_headers(
+    headers: &mut HeaderMap,
+    object: &ProxyObject,
+    location: Option<&ObjectLocation>,
+) {
+    let endpoint_id = &CONFIG.proxy.endpoint_id;
+    headers.insert(
+        HeaderName::from_static("x-aruna-object-status"),
+        HeaderValue::from_static(object.staging_status(location, endpoint_id).as_str()),
+    );
+    headers.insert(
+        HeaderName::from_static("x-aruna-endpoint-status"),
+        HeaderValue::from_static(object.endpoint_status(endpoint_id)),
+    );
+}
+
+/// `Content-Disposition` of generated archives, quotes and path separators are removed
+fn attachment(filename: &str) -> String {
+    let filename = filename.rsplit('/').next().unwrap_or(filename);
+    format!("attachment; filename=\"{}\"", filename.replace('"', ""))
+}
+
+/// Header value of the listing extensions and cors headers
+fn header_value(value: &str) -> S3Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|_| {
+        error!(error = "Unable to parse header value");
+        s3_error!(InternalError, "Unable to parse header value")
+    })
+}
+
+/// Adds the headers of the bucket (e.g. cors) from the access check to a response
+fn insert_access_headers(
+    resp_headers: &mut HeaderMap,
+    headers: Option<HashMap<String, String>>,
+) -> S3Result<()> {
+    for (k, v) in headers.unwrap_or_default() {
+        resp_headers.insert(
+            HeaderName::from_bytes(k.as_bytes()).map_err(|_| {
+                error!(error = "Unable to parse header name");
+                s3_error!(InternalError, "Unable to parse header name")
+            })?,
+            header_value(&v)?,
+        );
+    }
+    Ok(())
+}
+
+/// Listing parameters of ListObjects and ListObjectsV2
+#[derive(Debug)]
+struct ListRequest<'a> {
+    bucket: &'a str,
+    prefix: Option<String>,
+    delimiter: Option<String>,
+    /// First path of the page, the start after key or token (v2) or after the marker (v1)
+    start_at: String,
+    max_keys: Option<i32>,
+    url_encoding: bool,
+    public_only: bool,
+    /// Permissions of the caller, prefix stats are only returned for fully readable prefixes
+    permissions: HashMap<DieselUlid, DbPermissionLevel>,
+}
+
+/// Page of a listing, keys and prefixes are already encoded
+struct BucketListing {
+    contents: Vec<Object>,
+    common_prefixes: Vec<CommonPrefix>,
+    key_count: i32,
+    max_keys: i32,
+    /// Greatest listed key or prefix, not encoded
+    last_entry: Option<String>,
+    next_token: Option<String>,
+    /// Opt-in `x-aruna-*` extensions of the listing
+    headers: HeaderMap,
+}
+
+/// Maximum duration of the backend call completing a multipart upload
+const COMPLETE_MULTIPART_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+/// Idle download bandwidth buckets are dropped in this interval
+const BANDWIDTH_PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub struct ArunaS3Service {
     backend: Arc<Box<dyn StorageBackend>>,
     cache: Arc<Cache>,
+    // Keys with a conditional write in progress
+    conditional_writes: Arc<DashSet<String>>,
+    bandwidth: Arc<BandwidthLimiter>,
 }
 
 impl Debug for ArunaS3Service {
@@ -74,201 +209,358 @@ impl Debug for ArunaS3Service {
 impl ArunaS3Service {
     #[tracing::instrument(level = "trace", skip(backend, cache))]
     pub async fn new(backend: Arc<Box<dyn StorageBackend>>, cache: Arc<Cache>) -> Result<Self> {
+        let bandwidth = Arc::new(BandwidthLimiter::new(
+            CONFIG
+                .frontend
+                .as_ref()
+                .map(|frontend| frontend.bandwidth_limits)
+                .unwrap_or_default(),
+        ));
+        let prune_limiter = Arc::downgrade(&bandwidth);
+        crate::spawn(async move {
+            let mut interval = tokio::time::interval(BANDWIDTH_PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                match prune_limiter.upgrade() {
+                    Some(limiter) => limiter.prune(),
+                    None => return,
+                }
+            }
+        });
         Ok(ArunaS3Service {
             backend: backend.clone(),
             cache,
+            conditional_writes: Arc::new(DashSet::new()),
+            bandwidth,
         })
     }
-}
 
-#[async_trait::async_trait]
-impl S3 for ArunaS3Service {
-    #[tracing::instrument(err)]
-    async fn complete_multipart_upload(
+    /// Applies the download bandwidth limits of the access key and project to a response body
+    fn throttle_body(
         &self,
-        req: S3Request<CompleteMultipartUploadInput>,
-    ) -> S3Result<S3Response<CompleteMultipartUploadOutput>> {
-        let CheckAccessResult {
-            user_state,
-            objects_state,
-            ..
-        } = req
-            .extensions
-            .get::<CheckAccessResult>()
-            .cloned()
-            .ok_or_else(|| {
-                error!(error = "Missing data context");
-                s3_error!(UnexpectedContent, "Missing data context")
-            })?;
+        body: Option<StreamingBlob>,
+        user_state: &UserState,
+        project_id: Option<DieselUlid>,
+    ) -> Option<StreamingBlob> {
+        let project = project_id.map(|id| (id, self.cache.get_bandwidth(&id)));
+        Some(
+            self.bandwidth
+                .throttle(body?, user_state.get_access_key(), project),
+        )
+    }
 
-        let impersonating_token =
-            user_state.sign_impersonating_token(self.cache.auth.read().await.as_ref());
+    /// Upload size limits of the proxy config with the overrides of the project
+    fn upload_limits(&self, project_id: Option<&DieselUlid>) -> UploadLimits {
+        project_limits(&self.cache, project_id)
+    }
 
-        let (object, old_location) = objects_state.extract_object()?;
-        let mut old_location = old_location.ok_or_else(|| {
-            error!(error = "Unable to extract object location");
-            s3_error!(InternalError, "Unable to extract object location")
+    /// Size of a generated bundle, the bundle is generated once and discarded
+    #[tracing::instrument(level = "trace", skip(self, members, manifest))]
+    async fn bundle_len(
+        &self,
+        members: Vec<(String, BundleMember)>,
+        format: BundleFormat,
+        manifest: Option<BundleManifest>,
+    ) -> S3Result<u64> {
+        let mut body = get_bundle(
+            members,
+            Vec::new(),
+            self.backend.clone(),
+            Some(RemoteObjects::new(self.cache.clone(), self.backend.clone())),
+            format,
+            None,
+            manifest,
+        )
+        .await
+        .ok_or_else(|| {
+            error!(error = "Unable to generate bundle");
+            s3_error!(InternalError, "Unable to generate bundle")
         })?;
+        let mut len = 0;
+        while let Some(chunk) = body.try_next().await.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            s3_error!(InternalError, "Unable to generate bundle")
+        })? {
+            len += chunk.len() as u64;
+        }
+        Ok(len)
+    }
 
-        let parts = match req.input.multipart_upload {
-            Some(parts) => parts.parts.ok_or_else(|| {
-                error!(error = "Parts must be specified");
-                s3_error!(InvalidPart, "Parts must be specified")
-            }),
-            None => {
-                error!("parts is none");
-                return Err(s3_error!(InvalidPart, "Parts must be specified"));
-            }
-        }?;
+    /// Decrypted and decompressed data of a location, as streamed by GetObject
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn plaintext(&self, location: &ObjectLocation) -> S3Result<StreamingBlob> {
+        let footer = self.get_footer(location).await?;
+        let parts = self.get_part_lengths(location, footer.as_ref())?;
 
-        let etag_parts = parts
-            .into_iter()
-            .map(|a| {
-                Ok(PartETag {
-                    part_number: a.part_number.ok_or_else(|| {
-                        error!(error = "part_number must be specified");
-                        s3_error!(InvalidPart, "part_number must be specified")
-                    })?,
-                    etag: a.e_tag.ok_or_else(|| {
-                        error!(error = "etag must be specified");
-                        s3_error!(InvalidPart, "etag must be specified")
-                    })?,
-                })
-            })
-            .collect::<Result<Vec<PartETag>, S3Error>>()?;
+        let (sender, receiver) = async_channel::bounded(10);
+        let backend = self.backend.clone();
+        let loc_clone = location.clone();
+        crate::spawn(
+            async move { backend.get_object(loc_clone, None, sender).await }
+                .instrument(info_span!("get_object")),
+        );
 
-        let upload_id = old_location
-            .upload_id
-            .as_ref()
-            .ok_or_else(|| {
-                error!(error = "Upload id must be specified");
-                s3_error!(InvalidPart, "Upload id must be specified")
-            })?
-            .to_string();
-
-        let parts = self.cache.get_parts(&upload_id);
-
-        let mut cumulative_size = 0;
-        let mut disk_size = 0;
-        'outer: for part in parts {
-            for etag in etag_parts.iter() {
-                if part.part_number == etag.part_number as u64 {
-                    cumulative_size += part.raw_size;
-                    disk_size += part.size;
-                    continue 'outer;
+        let (data_send, data_rcv) = async_channel::bounded(10);
+        let decryption_key = location.get_encryption_key();
+        let is_compressed = location.is_compressed();
+        crate::spawn(
+            async move {
+                pin!(receiver);
+                let mut asrw = GenericStreamReadWriter::new_with_sink(
+                    receiver,
+                    AsyncSenderSink::new(data_send),
+                );
+                if let Some(key) = decryption_key {
+                    asrw = asrw.add_transformer(ChaCha20DecParts::new_with_lengths(key, parts));
                 }
-            }
-            self.cache
-                .delete_part(upload_id.to_string(), part.part_number)
-                .await
-                .map_err(|_| {
-                    error!(error = "Unable to delete part");
-                    s3_error!(InternalError, "Unable to delete part")
+                if is_compressed {
+                    asrw = asrw.add_transformer(ZstdDec::new());
+                }
+                asrw.process().await.map_err(|e| {
+                    // Expected if the reader finished early, e.g. a select with LIMIT
+                    debug!(error = ?e, msg = "Plaintext stream closed");
+                    e
                 })?;
-        }
-
-        // Does this object exists (including object id etc)
-        //req.input.multipart_upload.unwrap().
-        self.backend
-            .clone()
-            .finish_multipart_upload(old_location.clone(), etag_parts, upload_id.to_string())
-            .await
-            .map_err(|_| {
-                error!(error = "Unable to finish upload");
-                s3_error!(InternalError, "Unable to finish upload")
-            })?;
+                Ok::<_, anyhow::Error>(())
+            }
+            .instrument(info_span!("plaintext")),
+        );
+        Ok(StreamingBlob::wrap(data_rcv.map_err(|_| {
+            error!(error = "Unable to wrap plaintext stream");
+            s3_error!(InternalError, "Internal processing error")
+        })))
+    }
 
-        let response = CompleteMultipartUploadOutput {
-            e_tag: Some(object.id.to_string()),
-            ..Default::default()
+    /// Validation spec and collision policy of the project, checked before any data is written
+    fn check_ingest(
+        &self,
+        project_id: Option<&DieselUlid>,
+        key: &str,
+        content_type: Option<String>,
+        metadata: Option<&HashMap<String, String>>,
+        object: &NewOrExistingObject,
+    ) -> S3Result<()> {
+        let Some(project_id) = project_id else {
+            return Ok(());
         };
+        if let Some(spec) = self.cache.get_validation_spec(project_id) {
+            spec.validate(key, content_type.as_deref(), metadata)?;
+        }
+        if let Some(collision) = self.cache.get_collision_policy(project_id) {
+            let exists = matches!(
+                object,
+                NewOrExistingObject::Existing(ob) if ob.object_status != Status::Initializing
+            );
+            collision.check(key, exists)?;
+        }
+        Ok(())
+    }
 
-        old_location.disk_content_len = disk_size as i64;
-        old_location.raw_content_len = cumulative_size as i64;
+    /// Strips the aws-chunked framing from a request body, chunk signatures are
+    /// verified with the secret of the requesting access key
+    #[tracing::instrument(level = "trace", skip(self, body, headers, user_state))]
+    async fn decode_request_body(
+        &self,
+        body: StreamingBlob,
+        headers: &HeaderMap,
+        user_state: &UserState,
+    ) -> StreamingBlob {
+        if !is_aws_chunked(headers) {
+            return body;
+        }
+        let secret = match user_state.get_access_key() {
+            Some(access_key) => self
+                .cache
+                .get_key_perms(&access_key)
+                .await
+                .map(|k| k.secret),
+            None => None,
+        };
+        decode_body(body, headers, secret.as_deref())
+    }
 
-        self.cache
-            .update_location(object.id, old_location.clone())
+    /// Resolves a `versionId` to a revision of the requested object, only revisions
+    /// that are known to the cache can be served
+    #[tracing::instrument(level = "trace", skip(self, latest))]
+    async fn get_revision(
+        &self,
+        latest: &ProxyObject,
+        version_id: &str,
+    ) -> S3Result<(ProxyObject, Option<ObjectLocation>)> {
+        let id = DieselUlid::from_str(version_id).map_err(|_| {
+            error!(version_id, error = "Invalid versionId");
+            s3_error!(InvalidArgument, "Invalid versionId")
+        })?;
+        let (revision, location) = self
+            .cache
+            .get_revisions(&latest.id)
             .await
-            .map_err(|_| {
-                error!(error = "Unable to update location");
-                s3_error!(InternalError, "Unable to update location")
+            .into_iter()
+            .find(|(revision, _)| revision.id == id)
+            .ok_or_else(|| {
+                error!(version_id, error = "Version not found");
+                s3_error!(NoSuchVersion, "The specified version does not exist")
             })?;
-
-        if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
-            if let Some(token) = &impersonating_token {
-                // Set id of new location to object id to satisfy FK constraint
-                let _ = handler
-                    .finish_object(object.id, cumulative_size as i64, vec![], token)
-                    .await
-                    .map_err(|_| {
-                        error!(error = "Unable to finish object");
-                        s3_error!(InternalError, "Unable to create object")
-                    })?;
-            }
-        }
-
-        tokio::spawn(DataHandler::finalize_location(
-            object,
-            self.cache.clone(),
-            self.backend.clone(),
-            old_location,
-            Some(objects_state.try_slice()?),
-        ));
-        debug!(?response);
-        Ok(S3Response::new(response))
+        let location = match location {
+            Some(location) => Some(location),
+            None => self.cache.lookup_location(&revision.id).await,
+        };
+        Ok((revision, location))
     }
 
-    #[tracing::instrument(err)]
-    async fn create_bucket(
+    /// Rejects uploads of `additional` bytes that would exceed the quota of a project
+    fn check_quota(
         &self,
-        req: S3Request<CreateBucketInput>,
-    ) -> S3Result<S3Response<CreateBucketOutput>> {
-        let data = req.extensions.get::<CheckAccessResult>().cloned();
-
-        let mut new_object = ProxyObject::from(req.input);
+        project_ids: &[DieselUlid],
+        additional: i64,
+        headers: &HeaderMap,
+        user_state: &UserState,
+    ) -> S3Result<()> {
+        check_quota(&self.cache, project_ids, additional, headers, user_state)
+    }
 
-        if let Some(client) = self.cache.aruna_client.read().await.as_ref() {
-            let CheckAccessResult { user_state, .. } = data.ok_or_else(|| {
-                error!(error = "Missing data context");
-                s3_error!(InternalError, "Internal Error")
-            })?;
+    /// Creates the missing collection and/or dataset of a key on the server, returns the
+    /// deepest created parent. Without client or token nothing is created.
+    async fn create_missing_parents(
+        &self,
+        collection: NewOrExistingObject,
+        dataset: NewOrExistingObject,
+        token: Option<&String>,
+    ) -> S3Result<Option<TypedRelation>> {
+        let client = self.cache.aruna_client.read().await;
+        let (Some(handler), Some(token)) = (client.as_ref(), token) else {
+            return Ok(None);
+        };
 
-            let impersonating_token = user_state
-                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
-                .ok_or_else(|| {
-                    error!(error = "Unauthorized: Impersonating error");
-                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
-                })?;
+        let mut collection_id = None;
+        if let NewOrExistingObject::Missing(collection) = collection {
+            let name = collection.name.clone();
+            let col = handler
+                .create_collection(collection, token)
+                .await
+                .map_err(trace_err!("create_collection", name))?;
+            collection_id = Some(col.id)
+        }
 
-            // TODO: EndpointInfo
-            // -> CreateProject adds matching EndpointId to create project request, and then returns a
-            //    correctly parsed Object where Endpoint infos are added
-            new_object = client
-                .create_project(new_object, &impersonating_token)
+        if let NewOrExistingObject::Missing(mut dataset) = dataset {
+            if let Some(collection_id) = collection_id {
+                dataset.parents = Some(HashSet::from_iter([TypedRelation::Collection(
+                    collection_id,
+                )]));
+            }
+            let name = dataset.name.clone();
+            let dataset = handler
+                .create_dataset(dataset, token)
                 .await
-                .map_err(|_| {
-                    error!(error = "Unable to create project");
-                    s3_error!(InternalError, "[BACKEND] Unable to create project")
-                })?;
+                .map_err(trace_err!("create_dataset", name))?;
+            return Ok(Some(TypedRelation::Dataset(dataset.id)));
         }
-        let output = CreateBucketOutput {
-            location: Some(new_object.name.to_string()),
-        };
+        Ok(collection_id.map(TypedRelation::Collection))
+    }
 
-        self.cache.upsert_object(new_object).await.map_err(|_| {
-            error!(error = "Unable to cache new bucket");
-            s3_error!(InternalError, "Unable to cache new bucket")
-        })?;
+    /// Re-uploads of the stored data (see `deduplicate_uploads`) are not written again,
+    /// the existing object is returned. Staging objects whose finish notification failed
+    /// are still finished, e.g. when a client retries after a notifier error.
+    #[tracing::instrument(level = "trace", skip_all)]
+    async fn deduplicated_upload(
+        &self,
+        req: &S3Request<PutObjectInput>,
+        object: &ProxyObject,
+        content_length: i64,
+        content_md5: Option<&ContentMd5>,
+        requested_checksum: Option<&RequestedChecksum>,
+        user_state: &UserState,
+        impersonating_token: Option<&String>,
+    ) -> S3Result<Option<S3Response<PutObjectOutput>>> {
+        let Some(location) = self.cache.get_location(&object.id).await else {
+            return Ok(None);
+        };
+        if location.is_temporary
+            || location.upload_id.is_some()
+            || location.corrupt
+            || location.is_customer_encrypted()
+            || location.raw_content_len != content_length
+        {
+            return Ok(None);
+        }
+        let stored_md5 = location
+            .raw_hashes
+            .get("md5")
+            .or_else(|| object.hashes.get("MD5"));
+        let stored_sha256 = location
+            .raw_hashes
+            .get("sha256")
+            .or_else(|| object.hashes.get("SHA256"));
+        if !is_identical_upload(
+            content_md5,
+            requested_checksum,
+            stored_md5.map(String::as_str),
+            stored_sha256.map(String::as_str),
+        ) {
+            return Ok(None);
+        }
+        // Changed headers are stored with a new revision
+        if location.content_type != req.input.content_type.as_ref().map(|c| c.to_string())
+            || location.user_metadata != req.input.metadata.clone().unwrap_or_default()
+        {
+            return Ok(None);
+        }
 
+        let mut object = object.clone();
+        if object.object_status == Status::Initializing {
+            if let (Some(handler), Some(token)) = (
+                self.cache.aruna_client.read().await.as_ref(),
+                impersonating_token,
+            ) {
+                object = handler
+                    .finish_object_or_queue(object, location.raw_content_len, user_state, token)
+                    .await
+                    .map_err(trace_err!("finish_object", req.input.key))?;
+            }
+        }
+        info!(object_id = %object.id, key = req.input.key, "Skipped identical re-upload");
+
+        let checksum_sha256 = match requested_checksum {
+            Some(_) => Some(
+                hex_to_checksum(stored_sha256.map(String::as_str).unwrap_or_default()).map_err(
+                    |e| {
+                        error!(error = ?e, msg = e.to_string());
+                        s3_error!(InternalError, "Unable to calculate checksum")
+                    },
+                )?,
+            ),
+            None => None,
+        };
+        let output = PutObjectOutput {
+            e_tag: Some(object.get_etag(Some(&location))),
+            checksum_sha256,
+            version_id: Some(object.id.to_string()),
+            ..Default::default()
+        };
         debug!(?output);
-        Ok(S3Response::new(output))
+        let mut resp = S3Response::new(output);
+        resp.headers.insert(
+            HeaderName::from_static("x-aruna-deduplicated"),
+            HeaderValue::from_static("true"),
+        );
+        Ok(Some(resp))
     }
 
-    #[tracing::instrument(err)]
-    async fn create_multipart_upload(
+    /// Directory markers (empty `dir/` keys, e.g. created by the AWS console) create the
+    /// missing collection and/or dataset of the directory, no data is stored. Markers
+    /// below a dataset are accepted, these directories only exist in object names.
+    async fn put_directory_marker(
         &self,
-        req: S3Request<CreateMultipartUploadInput>,
-    ) -> S3Result<S3Response<CreateMultipartUploadOutput>> {
+        req: &S3Request<PutObjectInput>,
+        content_length: i64,
+    ) -> S3Result<S3Response<PutObjectOutput>> {
+        if content_length != 0 {
+            error!(content_length, "Directory marker with content");
+            return Err(s3_error!(
+                InvalidArgument,
+                "Keys ending with / are directory markers and must be empty"
+            ));
+        }
         let CheckAccessResult {
             objects_state,
             user_state,
@@ -281,29 +573,933 @@ impl S3 for ArunaS3Service {
                 error!(error = "Missing data context");
                 s3_error!(UnexpectedContent, "Missing data context")
             })?;
-
+        let (states, _) = objects_state.require_regular()?;
+        let (collection, dataset) = states.into_directory_marker()?;
+
+        if (matches!(collection, NewOrExistingObject::Missing(_))
+            || matches!(dataset, NewOrExistingObject::Missing(_)))
+            && self.cache.aruna_client.read().await.is_none()
+        {
+            error!("ArunaServer client not available");
+            return Err(ProxyError::NotifierUnavailable(format!(
+                "Unable to create directory {}/{}: ArunaServer client not available",
+                req.input.bucket, req.input.key
+            ))
+            .into());
+        }
         let impersonating_token =
             user_state.sign_impersonating_token(self.cache.auth.read().await.as_ref());
+        self.create_missing_parents(collection, dataset, impersonating_token.as_ref())
+            .await?;
 
-        let (states, _) = objects_state.require_regular()?;
+        let output = PutObjectOutput {
+            e_tag: Some(format!("\"{EMPTY_MD5}\"")),
+            ..Default::default()
+        };
+        debug!(?output);
+        Ok(S3Response::new(output))
+    }
 
-        let (_, collection, dataset, object, location_state) = states.into_new_or_existing()?;
+    /// Rejects overwrites of finished objects with an active retention
+    fn check_object_lock(
+        object: &NewOrExistingObject,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> S3Result<()> {
+        match object {
+            NewOrExistingObject::Existing(ob) if ob.object_status != Status::Initializing => {
+                match ObjectRetention::from_object(ob) {
+                    Some(retention) => retention.check_mutation(&ob.id, now),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
 
-        trace!(?collection, ?dataset, ?object);
+    /// Records the requested storage class on a new location, without a class the data
+    /// is stored in the default class of the backend
+    fn apply_storage_class(
+        &self,
+        class: Option<&StorageClass>,
+        location: &mut ObjectLocation,
+    ) -> S3Result<()> {
+        let Some(class) = class.map(|class| class.as_str()) else {
+            return Ok(());
+        };
+        if !STORAGE_CLASSES.contains(&class) {
+            error!(storage_class = class, "Invalid storage class");
+            return Err(s3_error!(
+                InvalidStorageClass,
+                "Invalid storage class: {}",
+                class
+            ));
+        }
+        if !self.backend.supports_storage_class(location) {
+            // Everything is stored in the default class on these backends
+            if class == StorageClass::STANDARD {
+                return Ok(());
+            }
+            error!(
+                storage_class = class,
+                "Storage class not supported by backend"
+            );
+            return Err(s3_error!(
+                InvalidStorageClass,
+                "Storage class {} is not supported by this backend",
+                class
+            ));
+        }
+        location.storage_class = Some(class.to_string());
+        Ok(())
+    }
 
-        let mut new_object = match &object {
-            NewOrExistingObject::Existing(ob) => {
-                if ob.object_status == Status::Initializing {
-                    trace!("Object is initializing");
-                    ob.clone()
-                } else {
-                    let mut new_revision = if let Some(handler) =
-                        self.cache.aruna_client.read().await.as_ref()
-                    {
-                        if let Some(token) = &impersonating_token {
-                            handler
-                                .init_object_update(ob.clone(), token, true)
-                                .await
+    /// Retention of a new upload, explicit lock headers take precedence over the
+    /// default retention of the project
+    fn requested_retention(
+        &self,
+        input: &PutObjectInput,
+        project: &NewOrExistingObject,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> S3Result<Option<ObjectRetention>> {
+        let requested = ObjectRetention::from_headers(
+            input.object_lock_mode.as_ref().map(|mode| mode.as_str()),
+            input.object_lock_retain_until_date.clone(),
+            now,
+        )?;
+        if requested.is_some() {
+            return Ok(requested);
+        }
+        match project {
+            NewOrExistingObject::Existing(project) => Ok(self
+                .cache
+                .get_object_lock(&project.id)
+                .and_then(|policy| policy.default_retention(now))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Permissions of the access key of the request, empty for anonymous requests
+    async fn key_permissions(
+        &self,
+        user_state: &UserState,
+    ) -> HashMap<DieselUlid, DbPermissionLevel> {
+        match user_state.get_access_key() {
+            Some(access_key) => self
+                .cache
+                .get_key_perms(&access_key)
+                .await
+                .map(|key| key.permissions)
+                .unwrap_or_default(),
+            None => HashMap::default(),
+        }
+    }
+
+    /// Streams all readable objects below a collection or dataset as archive, skipped
+    /// objects are listed in an `ARCHIVE_WARNINGS.txt` member
+    #[tracing::instrument(level = "trace", skip(self, root, user_state, overrides))]
+    async fn get_archive(
+        &self,
+        root: &ProxyObject,
+        project_id: Option<DieselUlid>,
+        format: BundleFormat,
+        user_state: &UserState,
+        overrides: ResponseOverrides,
+    ) -> S3Result<S3Response<GetObjectOutput>> {
+        let permissions = self.key_permissions(user_state).await;
+        let (levels, skipped) = self.cache.get_readable_levels(&root.id, &permissions).await;
+        let mut inline_files = Vec::new();
+        if !skipped.is_empty() {
+            warn!(
+                root = %root.id,
+                skipped = skipped.len(),
+                "Skipped unreadable objects in archive"
+            );
+            let mut warnings =
+                String::from("The following objects were skipped, missing read permissions:\n");
+            for name in skipped {
+                warnings.push_str(name.trim_start_matches('/'));
+                warnings.push('\n');
+            }
+            inline_files.push(("ARCHIVE_WARNINGS.txt".to_string(), Bytes::from(warnings)));
+        }
+
+        let body = get_bundle(
+            levels,
+            inline_files,
+            self.backend.clone(),
+            Some(RemoteObjects::new(self.cache.clone(), self.backend.clone())),
+            format,
+            None,
+            None,
+        )
+        .await;
+        let mut output = GetObjectOutput {
+            body: self.throttle_body(body, user_state, project_id),
+            content_type: format.content_type().parse().ok(),
+            content_disposition: Some(attachment(&format!("{}{}", root.name, format.extension()))),
+            last_modified: None,
+            ..Default::default()
+        };
+        overrides.apply_get(&mut output)?;
+
+        let mut resp = S3Response::new(output);
+        resp.headers.insert(
+            hyper::header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        Ok(resp)
+    }
+
+    /// Fetches and parses the footer of a pithos location (last 2 chunks)
+    #[tracing::instrument(level = "trace", skip(self, location))]
+    async fn get_footer(&self, location: &ObjectLocation) -> S3Result<Option<Footer>> {
+        if !location.is_pithos() {
+            return Ok(None);
+        }
+        let footer = read_footer(&self.backend, location).await.map_err(|e| {
+            // Legacy locations without footer are fixed by the footer repair
+            error!(error = ?e, location_id = ?location.id, msg = "Unable to read footer");
+            s3_error!(InternalError, "Unable to read footer")
+        })?;
+        Ok(Some(footer))
+    }
+
+    /// Lengths of the separately encrypted sections of a location
+    fn get_part_lengths(
+        &self,
+        location: &ObjectLocation,
+        footer: Option<&Footer>,
+    ) -> S3Result<Vec<u64>> {
+        if !location.is_temporary {
+            return Ok(vec![footer
+                .map(data_section_len)
+                .unwrap_or_else(|| location.disk_content_len as u64)]);
+        }
+        let mut part_sizes = Vec::new();
+        let parts = self
+            .cache
+            .get_parts(&location.upload_id.as_ref().ok_or_else(|| {
+                error!(error = "Upload id must be specified");
+                s3_error!(InvalidPart, "Upload id must be specified")
+            })?);
+
+        for parts in parts {
+            let full_chunks = (parts.size / (65536 + 28)) * (65536 + 28);
+            part_sizes.push(full_chunks);
+            if parts.size % (65536 + 28) != 0 {
+                part_sizes.push(parts.size - full_chunks);
+            }
+        }
+        Ok(part_sizes)
+    }
+
+    /// Shared part of ListObjects and ListObjectsV2, opt-in `x-aruna-*` extensions
+    /// of the listing are returned as headers of the page
+    #[tracing::instrument(level = "trace", skip(self, req_headers))]
+    async fn list_bucket(
+        &self,
+        req_headers: &HeaderMap,
+        listing: &ListRequest<'_>,
+    ) -> S3Result<BucketListing> {
+        let ListRequest {
+            bucket,
+            prefix,
+            delimiter,
+            start_at,
+            url_encoding,
+            public_only,
+            ..
+        } = listing;
+        let opted_in =
+            |name: &str| req_headers.get(name).and_then(|v| v.to_str().ok()) == Some("true");
+        let mut headers = HeaderMap::new();
+
+        // Check if bucket exists as root in cache of paths
+        let project_id = match self.cache.get_path(bucket) {
+            Some(id) => id,
+            None => {
+                error!("No bucket found");
+                return Err(s3_error!(NoSuchBucket, "No bucket found"));
+            }
+        };
+
+        // Opt-in storage consumption of the whole bucket, requires read access
+        if !public_only && opted_in("x-aruna-bucket-usage") {
+            let bucket_usage = serde_json::to_string(&self.cache.get_bucket_usage(&project_id))
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    s3_error!(InternalError, "Unable to serialize bucket usage")
+                })?;
+            headers.insert(
+                HeaderName::from_static("x-aruna-bucket-usage"),
+                header_value(&bucket_usage)?,
+            );
+        }
+
+        let max_keys = match listing.max_keys {
+            Some(k) if k < 1000 => k as usize,
+            _ => 1000usize,
+        };
+
+        let (keys, common_prefixes, next_token) = list_response(
+            &self.cache,
+            delimiter,
+            prefix,
+            bucket,
+            start_at,
+            max_keys,
+            *public_only,
+        )
+        .await
+        .map_err(|_| {
+            error!(error = "Keys not found in ListObjects");
+            s3_error!(NoSuchKey, "Keys not found in ListObjects")
+        })?;
+
+        // Opt-in aggregated stats per common prefix, only for prefixes the caller can
+        // read completely. Counts of partially visible prefixes would include objects
+        // the caller can not list, their stats are omitted (as for anonymous listings).
+        if !public_only && opted_in("x-aruna-prefix-stats") {
+            let mut stats = HashMap::new();
+            for common_prefix in common_prefixes.iter() {
+                let path = key_to_path(
+                    bucket,
+                    common_prefix.trim_end_matches(delimiter.as_deref().unwrap_or("/")),
+                );
+                let Some(id) = self.cache.get_path(&path) else {
+                    continue;
+                };
+                if let Some(prefix_stats) = self
+                    .cache
+                    .get_visible_prefix_stats(&id, &listing.permissions)
+                    .await
+                {
+                    // Header values are restricted to ASCII
+                    stats.insert(url_encode(common_prefix), prefix_stats);
+                }
+            }
+            if let Some(value) = extension_header("x-aruna-prefix-stats", &stats) {
+                headers.insert(HeaderName::from_static("x-aruna-prefix-stats"), value);
+            }
+        }
+
+        // Opt-in hierarchy metadata of the listed entries, the XML body stays unchanged
+        if opted_in("x-aruna-extended-listing") {
+            let entries = extended_listing(
+                &self.cache,
+                bucket,
+                delimiter.as_deref(),
+                &keys,
+                &common_prefixes,
+            )
+            .await;
+            if let Some(value) = extension_header("x-aruna-extended-listing", &entries) {
+                headers.insert(HeaderName::from_static("x-aruna-extended-listing"), value);
+            }
+        }
+
+        let last_entry = last_entry(&keys, &common_prefixes);
+        let key_count = (keys.len() + common_prefixes.len()) as i32;
+        let common_prefixes = common_prefixes
+            .into_iter()
+            .map(|e| CommonPrefix {
+                prefix: Some(encode_listed(e, *url_encoding)),
+            })
+            .collect();
+        let contents = keys
+            .into_iter()
+            .map(|e| Object {
+                checksum_algorithm: None,
+                e_tag: Some(e.etag),
+                key: Some(encode_listed(e.key, *url_encoding)),
+                last_modified: e.last_modified.map(Into::into),
+                owner: None,
+                size: Some(e.size),
+                storage_class: Some(
+                    e.storage_class
+                        .map(ObjectStorageClass::from)
+                        .unwrap_or_else(|| {
+                            ObjectStorageClass::from_static(ObjectStorageClass::STANDARD)
+                        }),
+                ),
+                ..Default::default()
+            })
+            .collect();
+
+        Ok(BucketListing {
+            contents,
+            common_prefixes,
+            key_count,
+            max_keys: max_keys.try_into().map_err(|err| {
+                error!(error = ?err, "Conversion failure");
+                s3_error!(InternalError, "[BACKEND] Conversion failure: {}", err)
+            })?,
+            last_entry,
+            next_token,
+            headers,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl S3 for ArunaS3Service {
+    #[tracing::instrument(err)]
+    async fn abort_multipart_upload(
+        &self,
+        req: S3Request<AbortMultipartUploadInput>,
+    ) -> S3Result<S3Response<AbortMultipartUploadOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+
+        let (object, location) = objects_state.extract_object()?;
+        let mut location = match location {
+            Some(location) if location.upload_id.as_ref() == Some(&req.input.upload_id) => location,
+            _ => {
+                error!(error = "No such upload");
+                return Err(s3_error!(NoSuchUpload, "No such upload"));
+            }
+        };
+
+        self.backend
+            .abort_multipart_upload(location.clone(), req.input.upload_id.to_string())
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to abort upload");
+                s3_error!(InternalError, "Unable to abort upload")
+            })?;
+
+        self.cache
+            .delete_parts_by_upload_id(req.input.upload_id.to_string())
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to delete parts");
+                s3_error!(InternalError, "Unable to delete parts")
+            })?;
+
+        self.cache
+            .remove_multipart_upload(&req.input.upload_id)
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to remove upload");
+                s3_error!(InternalError, "Unable to remove upload")
+            })?;
+
+        // Remove the upload id so that a later completion fails
+        location.upload_id = None;
+        self.cache
+            .update_location(object.id, location)
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to update location");
+                s3_error!(InternalError, "Unable to update location")
+            })?;
+
+        let output = AbortMultipartUploadOutput::default();
+        debug!(?output);
+        Ok(S3Response::new(output))
+    }
+
+    #[tracing::instrument(err)]
+    async fn complete_multipart_upload(
+        &self,
+        req: S3Request<CompleteMultipartUploadInput>,
+    ) -> S3Result<S3Response<CompleteMultipartUploadOutput>> {
+        let CheckAccessResult {
+            user_state,
+            objects_state,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+
+        let impersonating_token =
+            user_state.sign_impersonating_token(self.cache.auth.read().await.as_ref());
+
+        let (object, old_location) = objects_state.extract_object()?;
+        let mut old_location = old_location.ok_or_else(|| {
+            error!(error = "Unable to extract object location");
+            s3_error!(InternalError, "Unable to extract object location")
+        })?;
+
+        // Aborted or unknown uploads have no matching upload id in the location
+        let upload_id = match &old_location.upload_id {
+            Some(upload_id) if *upload_id == req.input.upload_id => upload_id.to_string(),
+            _ => {
+                error!(error = "No such upload");
+                return Err(s3_error!(NoSuchUpload, "No such upload"));
+            }
+        };
+        // The parts are rewritten into the final location, which needs the customer key
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        unlock_location(&old_location, customer_key.as_ref())?;
+
+        let parts = match req.input.multipart_upload {
+            Some(parts) => parts.parts.ok_or_else(|| {
+                error!(error = "Parts must be specified");
+                s3_error!(InvalidPart, "Parts must be specified")
+            }),
+            None => {
+                error!("parts is none");
+                return Err(s3_error!(InvalidPart, "Parts must be specified"));
+            }
+        }?;
+        let CompletedParts {
+            backend_parts,
+            unreferenced,
+            raw_size: cumulative_size,
+            disk_size,
+            etag: multipart_etag,
+            checksum,
+        } = self
+            .cache
+            .with_parts(&upload_id, |stored| merge_parts(parts, stored))?;
+
+        // Declared sizes are only checked upfront, the actual size is verified again
+        let project_ids = self
+            .cache
+            .get_projects(&object.id)
+            .await
+            .into_iter()
+            .collect::<Vec<_>>();
+        self.check_quota(
+            &project_ids,
+            cumulative_size as i64,
+            &req.headers,
+            &user_state,
+        )?;
+        // Limits may have been lowered while the parts were uploaded
+        let limits = self.upload_limits(project_ids.first());
+        check_declared(
+            "Multipart upload",
+            cumulative_size,
+            limits.max_multipart_size,
+        )?;
+
+        if !unreferenced.is_empty() {
+            self.cache
+                .delete_parts(upload_id.to_string(), &unreferenced)
+                .await
+                .map_err(|_| {
+                    error!(error = "Unable to delete part");
+                    s3_error!(InternalError, "Unable to delete part")
+                })?;
+        }
+
+        // A stuck backend must not pin the request forever
+        tokio::time::timeout(
+            COMPLETE_MULTIPART_TIMEOUT,
+            self.backend.clone().finish_multipart_upload(
+                old_location.clone(),
+                backend_parts,
+                upload_id.to_string(),
+            ),
+        )
+        .await
+        .map_err(|_| {
+            error!(error = "Timeout while finishing upload");
+            s3_error!(InternalError, "Timeout while finishing upload")
+        })?
+        .map_err(|_| {
+            error!(error = "Unable to finish upload");
+            s3_error!(InternalError, "Unable to finish upload")
+        })?;
+
+        self.cache
+            .remove_multipart_upload(&upload_id)
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to remove upload");
+                s3_error!(InternalError, "Unable to remove upload")
+            })?;
+
+        old_location.disk_content_len = disk_size as i64;
+        old_location.raw_content_len = cumulative_size as i64;
+        old_location.etag = multipart_etag;
+        old_location.checksum = checksum.clone();
+
+        let (checksum_crc32, checksum_crc32c, checksum_sha1, checksum_sha256) = checksum
+            .map(ContentChecksum::into_fields)
+            .unwrap_or_default();
+        let response = CompleteMultipartUploadOutput {
+            e_tag: Some(object.get_etag(Some(&old_location))),
+            checksum_crc32,
+            checksum_crc32c,
+            checksum_sha1,
+            checksum_sha256,
+            ..Default::default()
+        };
+
+        self.cache
+            .update_location(object.id, old_location.clone())
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to update location");
+                s3_error!(InternalError, "Unable to update location")
+            })?;
+
+        if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
+            if let Some(token) = &impersonating_token {
+                // Set id of new location to object id to satisfy FK constraint
+                // Hashes are set by finalize_location once they are calculated
+                let mut finished = object.clone();
+                finished.hashes = HashMap::default();
+                let _ = handler
+                    .finish_object_or_queue(finished, cumulative_size as i64, &user_state, token)
+                    .await
+                    .map_err(trace_err!("finish_object", object.id))?;
+            }
+        }
+
+        if let Some(project_id) = project_ids.first() {
+            let event = webhooks::object_event(
+                "ObjectCreated:CompleteMultipartUpload",
+                &req.input.bucket,
+                &req.input.key,
+                &object,
+                Some(&old_location),
+            );
+            webhooks::notify(&self.cache, *project_id, event).await;
+        }
+
+        crate::spawn(request_id::in_current_request(
+            DataHandler::finalize_location(
+                object,
+                self.cache.clone(),
+                self.backend.clone(),
+                old_location,
+                Some(objects_state.try_slice()?),
+                customer_key.as_ref().map(CustomerKey::key),
+            )
+            .instrument(info_span!("finalize_location")),
+        ));
+        debug!(?response);
+        Ok(S3Response::new(response))
+    }
+
+    #[tracing::instrument(err)]
+    async fn copy_object(
+        &self,
+        req: S3Request<CopyObjectInput>,
+    ) -> S3Result<S3Response<CopyObjectOutput>> {
+        let destination = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+        let CopySource::Bucket {
+            bucket,
+            key,
+            version_id,
+        } = &req.input.copy_source
+        else {
+            error!(error = "Copy from access point");
+            return Err(s3_error!(
+                NotImplemented,
+                "Access points are not supported as copy source"
+            ));
+        };
+
+        // The destination was authorized for the write, the source has to be readable
+        let source = match self.cache.auth.read().await.as_ref() {
+            Some(auth) => {
+                auth.check_access(
+                    req.credentials.as_ref(),
+                    &http::Method::GET,
+                    &s3s::path::S3Path::Object {
+                        bucket: bucket.to_string().into(),
+                        key: key.to_string().into(),
+                    },
+                    &req.headers,
+                )
+                .await?
+            }
+            None => {
+                error!(error = "Auth handler not available");
+                return Err(s3_error!(InternalError, "Auth handler not available"));
+            }
+        };
+        let (object, location) = match version_id.as_deref() {
+            Some(version_id) => {
+                let (latest, _) = source.objects_state.extract_object()?;
+                self.get_revision(&latest, version_id).await?
+            }
+            None => source.objects_state.extract_object()?,
+        };
+        let location =
+            location.ok_or_else(|| object.missing_location(&CONFIG.proxy.endpoint_id))?;
+
+        // Evaluated before the backend is touched
+        Preconditions {
+            if_match: req.input.copy_source_if_match.clone(),
+            if_none_match: req.input.copy_source_if_none_match.clone(),
+            if_modified_since: req.input.copy_source_if_modified_since.clone(),
+            if_unmodified_since: req.input.copy_source_if_unmodified_since.clone(),
+        }
+        .evaluate_copy_source(
+            &object.get_etag(Some(&location)),
+            object.get_last_modified(),
+        )?;
+
+        // The copy is stored like an upload of the plaintext, including limits,
+        // quotas, policies and the storage format of the destination
+        let location = unlock_location(&location, None)?;
+        let replace = req
+            .input
+            .metadata_directive
+            .as_ref()
+            .is_some_and(|directive| directive.as_str() == MetadataDirective::REPLACE);
+        let (content_type, metadata) = if replace {
+            (req.input.content_type.clone(), req.input.metadata.clone())
+        } else {
+            (
+                location
+                    .content_type
+                    .as_ref()
+                    .and_then(|content_type| content_type.parse().ok()),
+                Some(location.user_metadata.clone()).filter(|m| !m.is_empty()),
+            )
+        };
+        let input = PutObjectInput {
+            bucket: req.input.bucket.clone(),
+            key: req.input.key.clone(),
+            body: Some(self.plaintext(&location).await?),
+            content_length: Some(location.raw_content_len),
+            content_type,
+            metadata,
+            storage_class: req.input.storage_class.clone(),
+            ..Default::default()
+        };
+        let mut extensions = http::Extensions::new();
+        extensions.insert(destination);
+        let put = self
+            .put_object(S3Request {
+                input,
+                method: http::Method::PUT,
+                uri: req.uri.clone(),
+                headers: req.headers.clone(),
+                extensions,
+                credentials: req.credentials.clone(),
+                region: req.region.clone(),
+                service: req.service.clone(),
+            })
+            .await?;
+
+        let output = CopyObjectOutput {
+            copy_object_result: Some(CopyObjectResult {
+                e_tag: put.output.e_tag,
+                last_modified: Some(time::OffsetDateTime::now_utc().into()),
+                ..Default::default()
+            }),
+            copy_source_version_id: Some(object.id.to_string()),
+            version_id: put.output.version_id,
+            ..Default::default()
+        };
+        debug!(?output);
+        let mut resp = S3Response::new(output);
+        resp.headers = put.headers;
+        Ok(resp)
+    }
+
+    #[tracing::instrument(err)]
+    async fn create_bucket(
+        &self,
+        req: S3Request<CreateBucketInput>,
+    ) -> S3Result<S3Response<CreateBucketOutput>> {
+        validate_bucket_name(&req.input.bucket).map_err(|e| {
+            error!(
+                bucket = req.input.bucket.as_str(),
+                error = "Invalid bucket name"
+            );
+            e
+        })?;
+
+        let data = req.extensions.get::<CheckAccessResult>().cloned();
+
+        // Auth only lets requests for existing buckets through if the user has access
+        if let Some(ObjectsState::Bucket { states }) = data.as_ref().map(|d| &d.objects_state) {
+            if states.get_project().is_some() {
+                error!(
+                    bucket = req.input.bucket.as_str(),
+                    error = "Bucket already exists"
+                );
+                return Err(s3_error!(
+                    BucketAlreadyOwnedByYou,
+                    "Bucket already exists and is owned by you"
+                ));
+            }
+        }
+
+        let mut new_object = ProxyObject::from(req.input);
+
+        if let Some(client) = self.cache.aruna_client.read().await.as_ref() {
+            let CheckAccessResult { user_state, .. } = data.ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InternalError, "Internal Error")
+            })?;
+
+            // Token-scoped credentials reach this point only if the user allows them to
+            // create projects, the project is then created on behalf of the user
+            let user_state = match user_state {
+                UserState::Token { user_id, .. } => UserState::Personal { user_id },
+                other => other,
+            };
+            let impersonating_token = user_state
+                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+                .ok_or_else(|| {
+                    error!(error = "Unauthorized: Impersonating error");
+                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+                })?;
+
+            // TODO: EndpointInfo
+            // -> CreateProject adds matching EndpointId to create project request, and then returns a
+            //    correctly parsed Object where Endpoint infos are added
+            new_object = client
+                .create_project(new_object, &impersonating_token)
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, msg = e.to_string());
+                    match e.downcast_ref::<tonic::Status>().map(|s| s.code()) {
+                        // Known to the server but not (yet) to this proxy
+                        Some(tonic::Code::AlreadyExists) => s3_error!(
+                            BucketAlreadyExists,
+                            "The requested bucket name is not available"
+                        ),
+                        Some(tonic::Code::InvalidArgument) => {
+                            s3_error!(InvalidBucketName, "Bucket name rejected by the server")
+                        }
+                        Some(tonic::Code::PermissionDenied | tonic::Code::Unauthenticated) => {
+                            s3_error!(AccessDenied, "Not allowed to create projects")
+                        }
+                        _ => s3_error!(InternalError, "[BACKEND] Unable to create project"),
+                    }
+                })?;
+        }
+        let output = CreateBucketOutput {
+            location: Some(new_object.name.to_string()),
+        };
+
+        // Backing bucket for the project, buckets that depend on lower hierarchy levels
+        // are still created on the first write. New projects have no storage policy yet.
+        let names = [
+            Some((new_object.id, new_object.name.clone())),
+            None,
+            None,
+            None,
+        ];
+        match self
+            .backend
+            .initialize_location(&new_object, None, names, None, false)
+            .await
+        {
+            Ok(location) => {
+                if let Err(e) = self.backend.create_location_bucket(&location).await {
+                    error!(error = ?e, msg = e.to_string(), "Unable to create backing bucket");
+                }
+            }
+            Err(e) => error!(error = ?e, msg = e.to_string()),
+        }
+
+        self.cache.upsert_object(new_object).await.map_err(|_| {
+            error!(error = "Unable to cache new bucket");
+            s3_error!(InternalError, "Unable to cache new bucket")
+        })?;
+
+        debug!(?output);
+        Ok(S3Response::new(output))
+    }
+
+    #[tracing::instrument(err)]
+    async fn create_multipart_upload(
+        &self,
+        req: S3Request<CreateMultipartUploadInput>,
+    ) -> S3Result<S3Response<CreateMultipartUploadOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+
+        let impersonating_token =
+            user_state.sign_impersonating_token(self.cache.auth.read().await.as_ref());
+
+        let (states, _) = objects_state.require_regular()?;
+
+        let (project, collection, dataset, object, location_state) =
+            states.into_new_or_existing()?;
+
+        trace!(?collection, ?dataset, ?object);
+
+        if req.input.object_lock_mode.is_some() || req.input.object_lock_retain_until_date.is_some()
+        {
+            error!("Object lock headers on multipart upload");
+            return Err(s3_error!(
+                NotImplemented,
+                "Object lock is only supported for single part uploads"
+            ));
+        }
+        Self::check_object_lock(&object, chrono::Utc::now())?;
+        if let NewOrExistingObject::Existing(project) = &project {
+            self.check_ingest(
+                Some(&project.id),
+                &req.input.key,
+                req.input.content_type.as_ref().map(|c| c.to_string()),
+                req.input.metadata.as_ref(),
+                &object,
+            )?;
+        }
+
+        // The total size is not part of the request, clients may declare it upfront,
+        // undeclared uploads are checked on completion
+        let declared_size = req
+            .headers
+            .get("x-aruna-content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<i64>().ok());
+        if let (NewOrExistingObject::Existing(project), Some(declared_size)) =
+            (&project, declared_size)
+        {
+            self.check_quota(&[project.id], declared_size, &req.headers, &user_state)?;
+            check_declared(
+                "Multipart upload",
+                declared_size.max(0) as u64,
+                self.upload_limits(Some(&project.id)).max_multipart_size,
+            )?;
+        }
+
+        let mut new_object = match &object {
+            NewOrExistingObject::Existing(ob) => {
+                if ob.object_status == Status::Initializing {
+                    trace!("Object is initializing");
+                    ob.clone()
+                } else {
+                    let mut new_revision = if let Some(handler) =
+                        self.cache.aruna_client.read().await.as_ref()
+                    {
+                        if let Some(token) = &impersonating_token {
+                            handler
+                                .init_object_update(ob.clone(), token, true)
+                                .await
                                 .map_err(|_| {
                                     error!(error = "Object update failed");
                                     s3_error!(InternalError, "Object update failed")
@@ -333,107 +1529,845 @@ impl S3 for ArunaS3Service {
             }
         };
 
-        trace!(?new_object);
+        trace!(?new_object);
+
+        let storage = self.cache.get_storage_policy(&location_state);
+        let mut location = self
+            .backend
+            .initialize_location(&new_object, None, location_state, storage.as_deref(), true)
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to create object_location");
+                s3_error!(InternalError, "Unable to create object_location")
+            })?;
+        // Parts are staged in the default class, the class is applied on completion
+        self.apply_storage_class(req.input.storage_class.as_ref(), &mut location)?;
+        // All parts have to be uploaded with the same key, see `upload_part`
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        if let Some(customer_key) = &customer_key {
+            location.file_format = FileFormat::customer_encrypted(false);
+            location.customer_key = Some(customer_key.hash());
+        }
+        trace!(?location);
+
+        let init_response = self
+            .backend
+            .clone()
+            .init_multipart_upload(location.clone())
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to initialize multi-part");
+                s3_error!(InvalidArgument, "Unable to initialize multi-part")
+            })?;
+
+        self.cache
+            .add_multipart_upload(MultipartUploadInfo {
+                upload_id: init_response.to_string(),
+                bucket: req.input.bucket.to_string(),
+                key: req.input.key.to_string(),
+                initiated: chrono::Utc::now(),
+                initiator: user_state.get_user_id(),
+            })
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to track upload");
+                s3_error!(InternalError, "Unable to track upload")
+            })?;
+
+        location.upload_id = Some(init_response.to_string());
+        location.content_type = req.input.content_type.as_ref().map(|c| c.to_string());
+        location.user_metadata = req.input.metadata.clone().unwrap_or_default();
+
+        if let Some(parent) = self
+            .create_missing_parents(collection, dataset, impersonating_token.as_ref())
+            .await?
+        {
+            new_object.parents = Some(HashSet::from_iter([parent]));
+        }
+
+        let mut object_id = new_object.id;
+        if let NewOrExistingObject::Missing(_) = &object {
+            if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
+                if let Some(token) = &impersonating_token {
+                    let server_object = handler
+                        .create_object(new_object.clone(), token)
+                        .await
+                        .map_err(trace_err!("create_object", req.input.key))?;
+                    object_id = server_object.id;
+                }
+            }
+        } else {
+            self.cache.upsert_object(new_object).await.map_err(|_| {
+                error!(error = "Unable to cache new object");
+                s3_error!(InternalError, "Unable to cache new object")
+            })?;
+        }
+
+        self.cache
+            .add_location_with_binding(object_id, location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = "Unable to add location binding to object");
+                s3_error!(InternalError, "Unable to cache new object")
+            })?;
+
+        let output = CreateMultipartUploadOutput {
+            key: Some(req.input.key),
+            bucket: Some(req.input.bucket),
+            upload_id: Some(init_response),
+            ..Default::default()
+        };
+        debug!(?output);
+        let mut resp = S3Response::new(output);
+        if let Some(customer_key) = &customer_key {
+            customer_key.add_to_headers(&mut resp.headers);
+        }
+        Ok(resp)
+    }
+
+    #[tracing::instrument(err)]
+    async fn get_object(
+        &self,
+        req: S3Request<GetObjectInput>,
+    ) -> S3Result<S3Response<GetObjectOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            headers,
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InternalError, "No context found")
+            })?;
+
+        let overrides = ResponseOverrides {
+            cache_control: req.input.response_cache_control.clone(),
+            content_disposition: req.input.response_content_disposition.clone(),
+            content_encoding: req.input.response_content_encoding.clone(),
+            content_language: req.input.response_content_language.clone(),
+            content_type: req.input.response_content_type.clone(),
+            expires: req.input.response_expires.clone(),
+        };
+        overrides.validate(&user_state)?;
+
+        if let ObjectsState::Bundle { bundle, filename } = objects_state {
+            let format = BundleFormat::from_filename(&filename).ok_or_else(|| {
+                error!(error = "Unsupported bundle format");
+                s3_error!(
+                    InvalidArgument,
+                    "Unsupported bundle format, supported formats: {}",
+                    BundleFormat::SUPPORTED
+                )
+            })?;
+
+            let levels = bundle.filter_levels(
+                self.cache
+                    .get_path_levels(bundle.ids.as_slice())
+                    .await
+                    .map_err(|_| {
+                        error!(error = "Unable to get path levels");
+                        s3_error!(InternalError, "Unable to get path levels")
+                    })?,
+            );
+
+            // Prepared here already, the manifest lists the final member names
+            let levels = prepare_members(levels);
+            let manifest = if bundle.manifest || BundleManifest::requested(req.uri.query()) {
+                Some(self.cache.get_bundle_manifest(&levels).await)
+            } else {
+                None
+            };
+
+            // Only single ranges with known start are supported, the size of the
+            // generated bundle is not known in advance
+            let (edit_list, content_range) = match req.input.range {
+                None => (None, None),
+                Some(Range::Int { first, last }) => match last {
+                    Some(last) if last < first => {
+                        error!(error = "Invalid range");
+                        return Err(s3_error!(InvalidRange, "Invalid range"));
+                    }
+                    Some(last) => (
+                        Some(vec![first, last - first + 1]),
+                        Some(format!("bytes {}-{}/*", first, last)),
+                    ),
+                    None => {
+                        // Content-Range requires the last byte, open ranges (resumed
+                        // downloads) are resolved by generating the bundle once
+                        let len = self
+                            .bundle_len(levels.clone(), format, manifest.clone())
+                            .await?;
+                        if first >= len {
+                            error!(first, len, error = "Range start beyond bundle size");
+                            return Err(s3_error!(InvalidRange, "Invalid range"));
+                        }
+                        (
+                            Some(vec![first, len - first]),
+                            Some(format!("bytes {}-{}/{}", first, len - 1, len)),
+                        )
+                    }
+                },
+                Some(Range::Suffix { .. }) => {
+                    error!(error = "Suffix ranges are not supported for bundles");
+                    return Err(s3_error!(
+                        InvalidRange,
+                        "Suffix ranges are not supported for bundles"
+                    ));
+                }
+            };
+
+            let body = get_bundle(
+                levels,
+                Vec::new(),
+                self.backend.clone(),
+                Some(RemoteObjects::new(self.cache.clone(), self.backend.clone())),
+                format,
+                edit_list,
+                manifest,
+            )
+            .await;
+
+            // Bundles can span projects, only the access key limit applies
+            let content_type_override = overrides.content_type.is_some();
+            let mut output = GetObjectOutput {
+                body: self.throttle_body(body, &user_state, None),
+                accept_ranges: Some("bytes".to_string()),
+                content_range,
+                content_disposition: Some(attachment(&filename)),
+                last_modified: None,
+                e_tag: Some(format!("-{}", bundle.id)),
+                ..Default::default()
+            };
+            overrides.apply_get(&mut output)?;
+            let mut resp = S3Response::new(output);
+
+            resp.headers.insert(
+                hyper::header::TRANSFER_ENCODING,
+                HeaderValue::from_static("chunked"),
+            );
+
+            if !content_type_override {
+                resp.headers.insert(
+                    hyper::header::CONTENT_TYPE,
+                    HeaderValue::from_static(format.content_type()),
+                );
+            }
+
+            return Ok(resp);
+        }
+
+        let (states, location) = match objects_state {
+            ObjectsState::Object { states, location }
+            | ObjectsState::SpecialObject { states, location } => (states, location),
+            ObjectsState::Root | ObjectsState::Bucket { .. } | ObjectsState::Bundle { .. } => {
+                error!(error = "GetObject without object path");
+                return Err(s3_error!(InvalidRequest, "Invalid object path"));
+            }
+            ObjectsState::Objects { .. } => {
+                error!(error = "GetObject of object package");
+                return Err(s3_error!(
+                    NotImplemented,
+                    "Object packages are not supported"
+                ));
+            }
+        };
+
+        // Collections and datasets can be downloaded as archive
+        if states.get_object().is_none() {
+            let accept = req
+                .headers
+                .get(hyper::header::ACCEPT)
+                .and_then(|v| v.to_str().ok());
+            if let Some(format) = BundleFormat::from_request(accept, req.uri.query()) {
+                let root = states
+                    .get_dataset()
+                    .or(states.get_collection())
+                    .ok_or_else(|| {
+                        error!("Object not found");
+                        s3_error!(NoSuchKey, "Object not found")
+                    })?;
+                let mut resp = self
+                    .get_archive(
+                        root,
+                        states.get_project().map(|p| p.id),
+                        format,
+                        &user_state,
+                        overrides,
+                    )
+                    .await?;
+                if let Some(headers) = headers {
+                    for (k, v) in headers {
+                        resp.headers.insert(
+                            HeaderName::from_bytes(k.as_bytes()).map_err(|_| {
+                                s3_error!(InternalError, "Unable to parse header name")
+                            })?,
+                            HeaderValue::from_str(&v).map_err(|_| {
+                                s3_error!(InternalError, "Unable to parse header value")
+                            })?,
+                        );
+                    }
+                }
+                return Ok(resp);
+            }
+        }
+
+        let (object, location) = match &req.input.version_id {
+            Some(version_id) => {
+                self.get_revision(states.require_object()?, version_id)
+                    .await?
+            }
+            None => (states.require_object()?.clone(), location),
+        };
+        let location =
+            location.ok_or_else(|| object.missing_location(&CONFIG.proxy.endpoint_id))?;
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        let location = unlock_location(&location, customer_key.as_ref())?;
+        if location.is_archived(chrono::Utc::now()) {
+            error!(object_id = ?object.id, "Object is archived");
+            return Err(s3_error!(
+                InvalidObjectState,
+                "The operation is not valid for the object's storage class"
+            ));
+        }
+        let mut content_length = location.raw_content_len;
+
+        let (sender, receiver) = async_channel::bounded(10);
+
+        // Evaluate preconditions before the backend is touched
+        Preconditions {
+            if_match: req.input.if_match.clone(),
+            if_none_match: req.input.if_none_match.clone(),
+            if_modified_since: req.input.if_modified_since.clone(),
+            if_unmodified_since: req.input.if_unmodified_since.clone(),
+        }
+        .evaluate(
+            &object.get_etag(Some(&location)),
+            object.get_last_modified(),
+        )?;
+
+        // Opt-in verification of the streamed plaintext against the stored hash
+        let verify_sha256 = match req
+            .headers
+            .get("x-aruna-verify-hash")
+            .map(|v| v.to_str().unwrap_or_default())
+        {
+            None => None,
+            Some(alg) if alg.eq_ignore_ascii_case("sha256") => {
+                if req.input.range.is_some() {
+                    error!(error = "Hash verification requested for a range");
+                    return Err(s3_error!(
+                        InvalidRequest,
+                        "Hash verification is not supported for range requests"
+                    ));
+                }
+                Some(
+                    location
+                        .raw_hashes
+                        .get("sha256")
+                        .or_else(|| object.hashes.get("SHA256"))
+                        .cloned()
+                        .ok_or_else(|| {
+                            error!(error = "No stored SHA256 hash");
+                            s3_error!(InvalidRequest, "No stored SHA256 hash for this object")
+                        })?,
+                )
+            }
+            Some(alg) => {
+                error!(alg, error = "Unsupported hash verification algorithm");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Unsupported x-aruna-verify-hash algorithm, supported: sha256"
+                ));
+            }
+        };
+
+        let footer = if location.raw_content_len == 0 {
+            None
+        } else {
+            self.get_footer(&location).await?
+        };
+        let parts = self.get_part_lengths(&location, footer.as_ref())?;
 
-        let mut location = self
-            .backend
-            .initialize_location(&new_object, None, location_state, true)
-            .await
-            .map_err(|_| {
-                error!(error = "Unable to create object_location");
-                s3_error!(InternalError, "Unable to create object_location")
-            })?;
-        trace!(?location);
+        trace!("calculating ranges");
+        let (query_ranges, edit_list, actual_range) =
+            match calculate_ranges(req.input.range, content_length as u64, footer, &location) {
+                Ok((query_ranges, edit_list, _, actual_range)) => {
+                    (query_ranges, edit_list, actual_range)
+                }
+                Err(err) if err.is::<RangeNotSatisfiable>() => {
+                    error!(error = ?err, "Invalid range");
+                    return Err(s3_error!(InvalidRange, "Requested range not satisfiable"));
+                }
+                Err(err) => {
+                    error!(error = ?err, "Unable to calculate ranges");
+                    return Err(s3_error!(InternalError, "Unable to calculate ranges"));
+                }
+            };
 
-        let init_response = self
-            .backend
-            .clone()
-            .init_multipart_upload(location.clone())
+        let (accept_ranges, content_range) = if let Some(query_range) = actual_range {
+            content_length = (query_range.to - query_range.from) as i64;
+            (
+                Some("bytes".to_string()),
+                Some(format!(
+                    "bytes {}-{}/{}",
+                    query_range.from, query_range.to, content_length
+                )),
+            )
+        } else {
+            (None, None)
+        };
+
+        trace!(?edit_list);
+
+        // Full reads of large objects are split into concurrent range requests,
+        // client ranges always read sequentially
+        let parallel = CONFIG.parallel_get.as_ref().filter(|config| {
+            query_ranges.is_none()
+                && edit_list.is_none()
+                && config.applies(location.disk_content_len)
+        });
+
+        let decryption_key = location.get_encryption_key();
+        let content_type = location
+            .content_type
+            .as_ref()
+            .and_then(|c| c.parse().ok())
+            .or_else(|| mime_guess::from_path(object.name.as_str()).first());
+        let metadata = location.get_metadata();
+        let e_tag = object.get_etag(Some(&location));
+        let last_modified = object.get_last_modified();
+        let verified_hash = verify_sha256.as_ref().map(|hash| format!("sha256={hash}"));
+
+        // Held until the data is read, deletions of the object wait for it
+        let read_guard = self
+            .cache
+            .read_object_data(&object.id)
             .await
-            .map_err(|_| {
-                error!(error = "Unable to initialize multi-part");
-                s3_error!(InvalidArgument, "Unable to initialize multi-part")
+            .ok_or_else(|| {
+                error!(object_id = ?object.id, "Object was deleted");
+                s3_error!(NoSuchKey, "Object not found")
             })?;
 
-        location.upload_id = Some(init_response.to_string());
-
-        let mut collection_id = None;
-        if let NewOrExistingObject::Missing(collection) = collection {
-            if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
-                if let Some(token) = &impersonating_token {
-                    let col = handler
-                        .create_collection(collection, token)
-                        .await
-                        .map_err(|_| {
-                            error!(error = "Unable to create collection");
-                            s3_error!(InternalError, "Unable to create collection")
-                        })?;
-                    collection_id = Some(col.id)
+        let (final_send, final_rcv) = async_channel::bounded(100);
+        if location.raw_content_len == 0 {
+            // Empty objects are not read from the backend, the body ends immediately
+            drop(final_send);
+        } else {
+            // Spawn get_object to fetch bytes from storage storage
+            let backend = self.backend.clone();
+            let loc_clone = location.clone();
+            trace!(
+                ?loc_clone,
+                ?query_ranges,
+                parallel = parallel.is_some(),
+                "spawning get_object"
+            );
+            crate::spawn(
+                async move {
+                    let _read_guard = read_guard;
+                    match parallel {
+                        Some(config) => {
+                            parallel_get_object(backend, loc_clone, config, sender).await
+                        }
+                        None => backend.get_object(loc_clone, query_ranges, sender).await,
+                    }
                 }
-            }
-        }
+                .instrument(info_span!("get_object")),
+            );
+            trace!(parts = ?parts);
+            // Spawn final part
+            crate::spawn(
+                async move {
+                    pin!(receiver);
+                    let mut asrw = GenericStreamReadWriter::new_with_sink(
+                        receiver,
+                        AsyncSenderSink::new(final_send),
+                    );
+
+                    if let Some(key) = decryption_key {
+                        asrw = asrw.add_transformer(ChaCha20DecParts::new_with_lengths(key, parts));
+                    }
 
-        let mut dataset_id = None;
-        if let NewOrExistingObject::Missing(mut dataset) = dataset {
-            if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
-                if let Some(token) = &impersonating_token {
-                    if let Some(collection_id) = collection_id {
-                        dataset.parents = Some(HashSet::from_iter([TypedRelation::Collection(
-                            collection_id,
-                        )]));
+                    if location.is_compressed() {
+                        asrw = asrw.add_transformer(ZstdDec::new());
                     }
-                    let dataset = handler.create_dataset(dataset, token).await.map_err(|_| {
-                        error!(error = "Unable to create dataset");
-                        s3_error!(InternalError, "Unable to create dataset")
+
+                    if let Some(edit_list) = edit_list {
+                        asrw = asrw.add_transformer(Filter::new_with_edit_list(Some(edit_list)));
+                    };
+
+                    if let Some(expected) = verify_sha256 {
+                        asrw = asrw.add_transformer(Sha256VerifyTransformer::new(expected));
+                    }
+
+                    asrw.process().await.map_err(|e| {
+                        error!(error = ?e, msg = "Unable to process final part");
+                        s3_error!(InternalError, "Internal notifier error")
                     })?;
-                    dataset_id = Some(dataset.id);
+
+                    Ok::<_, anyhow::Error>(())
                 }
+                .instrument(info_span!("query_data")),
+            );
+        }
+
+        let body = self.throttle_body(
+            Some(StreamingBlob::wrap(final_rcv.map_err(|_| {
+                error!(error = "Unable to wrap final_rcv");
+                s3_error!(InternalError, "Internal processing error")
+            }))),
+            &user_state,
+            states.get_project().map(|p| p.id),
+        );
+
+        let mut output = GetObjectOutput {
+            body,
+            accept_ranges,
+            content_range,
+            content_length: Some(content_length),
+            content_type,
+            metadata,
+            last_modified: last_modified.map(Into::into),
+            e_tag: Some(e_tag),
+            version_id: Some(object.id.to_string()),
+            ..Default::default()
+        };
+        overrides.apply_get(&mut output)?;
+        debug!(?output);
+
+        let mut resp = S3Response::new(output);
+        insert_staging_headers(&mut resp.headers, &object, Some(&location));
+        if let Some(verified_hash) = verified_hash {
+            resp.headers.insert(
+                HeaderName::from_static("x-aruna-verified-hash"),
+                HeaderValue::from_str(&verified_hash).map_err(|_| {
+                    error!(error = "Unable to parse header value");
+                    s3_error!(InternalError, "Unable to parse header value")
+                })?,
+            );
+        }
+        if let Some(headers) = headers {
+            for (k, v) in headers {
+                resp.headers.insert(
+                    HeaderName::from_bytes(k.as_bytes())
+                        .map_err(|_| s3_error!(InternalError, "Unable to parse header name"))?,
+                    HeaderValue::from_str(&v)
+                        .map_err(|_| s3_error!(InternalError, "Unable to parse header value"))?,
+                );
             }
         }
+        if let Some(customer_key) = &customer_key {
+            customer_key.add_to_headers(&mut resp.headers);
+        }
+        Ok(resp)
+    }
 
-        if let Some(dataset_id) = dataset_id {
-            new_object.parents = Some(HashSet::from_iter([TypedRelation::Dataset(dataset_id)]));
-        } else {
-            if let Some(collection_id) = collection_id {
-                new_object.parents = Some(HashSet::from_iter([TypedRelation::Collection(
-                    collection_id,
-                )]));
+    #[tracing::instrument(err)]
+    async fn head_object(
+        &self,
+        req: S3Request<HeadObjectInput>,
+    ) -> S3Result<S3Response<HeadObjectOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            headers,
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "No context found");
+                s3_error!(InternalError, "No context found")
+            })?;
+
+        let overrides = ResponseOverrides {
+            cache_control: req.input.response_cache_control.clone(),
+            content_disposition: req.input.response_content_disposition.clone(),
+            content_encoding: req.input.response_content_encoding.clone(),
+            content_language: req.input.response_content_language.clone(),
+            content_type: req.input.response_content_type.clone(),
+            expires: req.input.response_expires.clone(),
+        };
+        overrides.validate(&user_state)?;
+
+        if let ObjectsState::Bundle { bundle, filename } = objects_state {
+            let mut output = HeadObjectOutput {
+                content_length: None,
+                content_disposition: Some(attachment(&filename)),
+                content_type: BundleFormat::from_filename(&filename)
+                    .and_then(|format| format.content_type().parse().ok()),
+                last_modified: Some(
+                    time::OffsetDateTime::from_unix_timestamp(
+                        (bundle.id.timestamp() / 1000) as i64,
+                    )
+                    .map_err(|_| {
+                        error!(error = "Unable to parse timestamp");
+                        s3_error!(InternalError, "Unable to parse timestamp")
+                    })?
+                    .into(),
+                ),
+                e_tag: Some(format!("-{}", bundle.id)),
+                ..Default::default()
+            };
+            overrides.apply_head(&mut output)?;
+            return Ok(S3Response::new(output));
+        }
+
+        let (object, location) = match &req.input.version_id {
+            Some(version_id) => {
+                let (latest, _) = objects_state.extract_object()?;
+                self.get_revision(&latest, version_id).await?
             }
+            None => objects_state.extract_object()?,
+        };
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        if let Some(location) = &location {
+            unlock_location(location, customer_key.as_ref())?;
         }
 
-        let mut object_id = new_object.id;
-        if let NewOrExistingObject::Missing(_) = &object {
-            if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
-                if let Some(token) = &impersonating_token {
-                    let server_object = handler
-                        .create_object(new_object.clone(), token)
-                        .await
-                        .map_err(|_| {
-                            error!(error = "Unable to create object");
-                            s3_error!(InternalError, "Unable to create object")
-                        })?;
-                    object_id = server_object.id;
-                }
+        let e_tag = object.get_etag(location.as_ref());
+        let last_modified = object.get_last_modified();
+        Preconditions {
+            if_match: req.input.if_match.clone(),
+            if_none_match: req.input.if_none_match.clone(),
+            if_modified_since: req.input.if_modified_since.clone(),
+            if_unmodified_since: req.input.if_unmodified_since.clone(),
+        }
+        .evaluate(&e_tag, last_modified)?;
+
+        let content_len = object.get_raw_size(location.as_ref());
+
+        let mime = location
+            .as_ref()
+            .and_then(|l| l.content_type.as_ref())
+            .and_then(|c| c.parse().ok())
+            .or_else(|| mime_guess::from_path(object.name.as_str()).first());
+
+        let mut output = HeadObjectOutput {
+            content_length: Some(content_len),
+            metadata: location.as_ref().and_then(|l| l.get_metadata()),
+            last_modified: Some(
+                last_modified
+                    .ok_or_else(|| {
+                        error!(error = "Unable to parse timestamp");
+                        s3_error!(InternalError, "Unable to parse timestamp")
+                    })?
+                    .into(),
+            ),
+            e_tag: Some(e_tag),
+            content_type: mime,
+            version_id: Some(object.id.to_string()),
+            // Objects in the default class are reported without a class
+            storage_class: location
+                .as_ref()
+                .and_then(|l| l.storage_class.as_deref())
+                .filter(|class| *class != StorageClass::STANDARD)
+                .map(|class| StorageClass::from(class.to_string())),
+            restore: location
+                .as_ref()
+                .and_then(|l| l.restore.as_ref())
+                .map(|restore| restore.to_header()),
+            ..Default::default()
+        };
+        overrides.apply_head(&mut output)?;
+
+        debug!(?output);
+        debug!(?headers);
+
+        let mut resp = S3Response::new(output);
+        insert_staging_headers(&mut resp.headers, &object, location.as_ref());
+        if let Some(headers) = headers {
+            for (k, v) in headers {
+                resp.headers.insert(
+                    HeaderName::from_bytes(k.as_bytes()).map_err(|_| {
+                        error!(error = "Unable to parse header name");
+                        s3_error!(InternalError, "Unable to parse header name")
+                    })?,
+                    HeaderValue::from_str(&v).map_err(|_| {
+                        error!(error = "Unable to parse header value");
+                        s3_error!(InternalError, "Unable to parse header value")
+                    })?,
+                );
             }
-        } else {
-            self.cache.upsert_object(new_object).await.map_err(|_| {
-                error!(error = "Unable to cache new object");
-                s3_error!(InternalError, "Unable to cache new object")
+        }
+        if let Some(customer_key) = &customer_key {
+            customer_key.add_to_headers(&mut resp.headers);
+        }
+
+        Ok(resp)
+    }
+
+    #[tracing::instrument(err)]
+    async fn get_object_attributes(
+        &self,
+        req: S3Request<GetObjectAttributesInput>,
+    ) -> S3Result<S3Response<GetObjectAttributesOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
             })?;
+
+        let (object, location) = objects_state.extract_object()?;
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        if let Some(location) = &location {
+            unlock_location(location, customer_key.as_ref())?;
+        }
+        let requested = req
+            .input
+            .object_attributes
+            .iter()
+            .map(|attribute| attribute.as_str().to_string())
+            .collect::<HashSet<_>>();
+
+        let mut output = GetObjectAttributesOutput {
+            last_modified: object.get_last_modified().map(|t| t.into()),
+            version_id: Some(object.id.to_string()),
+            ..Default::default()
+        };
+        if requested.contains(ObjectAttributes::ETAG) {
+            output.e_tag = Some(
+                object
+                    .get_etag(location.as_ref())
+                    .trim_matches('"')
+                    .to_string(),
+            );
+        }
+        if requested.contains(ObjectAttributes::OBJECT_SIZE) {
+            output.object_size = Some(object.get_raw_size(location.as_ref()));
+        }
+        if requested.contains(ObjectAttributes::STORAGE_CLASS) {
+            output.storage_class = Some(
+                location
+                    .as_ref()
+                    .and_then(|l| l.storage_class.clone())
+                    .map(StorageClass::from)
+                    .unwrap_or_else(|| StorageClass::from_static(StorageClass::STANDARD)),
+            );
+        }
+        if requested.contains(ObjectAttributes::CHECKSUM) {
+            output.checksum = location
+                .as_ref()
+                .and_then(|l| l.checksum.clone())
+                .map(|checksum| {
+                    let (checksum_crc32, checksum_crc32c, checksum_sha1, checksum_sha256) =
+                        checksum.into_fields();
+                    Checksum {
+                        checksum_crc32,
+                        checksum_crc32c,
+                        checksum_sha1,
+                        checksum_sha256,
+                    }
+                });
         }
+        debug!(?output);
+        let mut resp = S3Response::new(output);
+        if let Some(customer_key) = &customer_key {
+            customer_key.add_to_headers(&mut resp.headers);
+        }
+        Ok(resp)
+    }
 
-        self.cache
-            .add_location_with_binding(object_id, location)
-            .await
-            .map_err(|e| {
-                error!(error = ?e, msg = "Unable to add location binding to object");
-                s3_error!(InternalError, "Unable to cache new object")
+    #[tracing::instrument(err)]
+    async fn list_multipart_uploads(
+        &self,
+        req: S3Request<ListMultipartUploadsInput>,
+    ) -> S3Result<S3Response<ListMultipartUploadsOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
             })?;
 
-        let output = CreateMultipartUploadOutput {
-            key: Some(req.input.key),
+        // Only users with WRITE on the bucket may see its uploads
+        let states = objects_state.require_bucket()?;
+        let project = states.require_project()?;
+        let access_key = user_state.get_access_key().ok_or_else(|| {
+            error!(error = "Missing access key");
+            s3_error!(AccessDenied, "Missing access key")
+        })?;
+        let key_perms = self.cache.get_key_perms(&access_key).await.ok_or_else(|| {
+            error!(error = "Unknown access key");
+            s3_error!(AccessDenied, "Access Denied")
+        })?;
+        match key_perms.permissions.get(&project.id) {
+            Some(perm) if *perm >= DbPermissionLevel::Write => {}
+            _ => {
+                error!(error = "Insufficient permissions");
+                return Err(s3_error!(AccessDenied, "Access Denied"));
+            }
+        }
+
+        let max_uploads = match req.input.max_uploads {
+            Some(m) if m > 0 && m < 1000 => m as usize,
+            _ => 1000usize,
+        };
+        let url_encoding = is_url_encoding(req.input.encoding_type.as_ref());
+        let key_marker = req.input.key_marker.clone().unwrap_or_default();
+        let upload_id_marker = req.input.upload_id_marker.clone().unwrap_or_default();
+
+        let mut uploads = self
+            .cache
+            .list_multipart_uploads(&req.input.bucket, req.input.prefix.as_deref())
+            .into_iter()
+            .filter(|upload| {
+                upload.key > key_marker
+                    || (upload.key == key_marker
+                        && !upload_id_marker.is_empty()
+                        && upload.upload_id > upload_id_marker)
+            })
+            .collect::<Vec<_>>();
+        let is_truncated = uploads.len() > max_uploads;
+        uploads.truncate(max_uploads);
+        let (next_key_marker, next_upload_id_marker) = match (is_truncated, uploads.last()) {
+            (true, Some(last)) => (Some(last.key.clone()), Some(last.upload_id.clone())),
+            _ => (None, None),
+        };
+
+        let output = ListMultipartUploadsOutput {
             bucket: Some(req.input.bucket),
-            upload_id: Some(init_response),
+            encoding_type: req.input.encoding_type,
+            is_truncated: Some(is_truncated),
+            key_marker: req.input.key_marker.map(|k| encode_listed(k, url_encoding)),
+            max_uploads: Some(max_uploads as i32),
+            next_key_marker: next_key_marker.map(|k| encode_listed(k, url_encoding)),
+            next_upload_id_marker,
+            prefix: req.input.prefix.map(|p| encode_listed(p, url_encoding)),
+            upload_id_marker: req.input.upload_id_marker,
+            uploads: Some(
+                uploads
+                    .into_iter()
+                    .map(|upload| MultipartUpload {
+                        initiated: Some(s3s::dto::Timestamp::from(
+                            time::OffsetDateTime::from_unix_timestamp(upload.initiated.timestamp())
+                                .unwrap_or_else(|_| {
+                                    error!(error = "Unable to parse timestamp");
+                                    time::OffsetDateTime::now_utc()
+                                }),
+                        )),
+                        initiator: upload.initiator.map(|id| Initiator {
+                            id: Some(id.to_string()),
+                            ..Default::default()
+                        }),
+                        key: Some(encode_listed(upload.key, url_encoding)),
+                        upload_id: Some(upload.upload_id),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
             ..Default::default()
         };
         debug!(?output);
@@ -441,12 +2375,12 @@ impl S3 for ArunaS3Service {
     }
 
     #[tracing::instrument(err)]
-    async fn get_object(
+    async fn list_objects(
         &self,
-        req: S3Request<GetObjectInput>,
-    ) -> S3Result<S3Response<GetObjectOutput>> {
+        req: S3Request<ListObjectsInput>,
+    ) -> S3Result<S3Response<ListObjectsOutput>> {
         let CheckAccessResult {
-            objects_state,
+            user_state,
             headers,
             ..
         } = req
@@ -454,588 +2388,942 @@ impl S3 for ArunaS3Service {
             .get::<CheckAccessResult>()
             .cloned()
             .ok_or_else(|| {
-                error!(error = "Missing data context");
+                error!(error = "No context found");
                 s3_error!(InternalError, "No context found")
             })?;
+        let url_encoding = is_url_encoding(req.input.encoding_type.as_ref());
+        let marker = req.input.marker.unwrap_or_default();
+        let listing = ListRequest {
+            bucket: &req.input.bucket,
+            prefix: req.input.prefix.filter(|prefix| !prefix.is_empty()),
+            start_at: marker_start(&marker, req.input.delimiter.as_deref()),
+            delimiter: req.input.delimiter,
+            max_keys: req.input.max_keys,
+            url_encoding,
+            public_only: matches!(user_state, UserState::Anonymous),
+            permissions: self.key_permissions(&user_state).await,
+        };
+        let page = self.list_bucket(&req.headers, &listing).await?;
+
+        // S3 only returns the next marker with a delimiter, it is returned for all
+        // truncated pages to spare clients the fallback to the last key
+        let next_marker = page
+            .next_token
+            .is_some()
+            .then_some(page.last_entry)
+            .flatten()
+            .map(|m| encode_listed(m, url_encoding));
+        let result = ListObjectsOutput {
+            common_prefixes: Some(page.common_prefixes),
+            contents: Some(page.contents),
+            delimiter: listing.delimiter.map(|d| encode_listed(d, url_encoding)),
+            encoding_type: req.input.encoding_type,
+            is_truncated: Some(next_marker.is_some()),
+            marker: Some(encode_listed(marker, url_encoding)),
+            max_keys: Some(page.max_keys),
+            name: Some(listing.bucket.to_string()),
+            next_marker,
+            prefix: listing.prefix.map(|p| encode_listed(p, url_encoding)),
+            ..Default::default()
+        };
+        debug!(?result);
 
-        if let ObjectsState::Bundle { bundle, .. } = objects_state {
-            let levels = self
-                .cache
-                .get_path_levels(bundle.ids.as_slice())
-                .await
-                .map_err(|_| {
-                    error!(error = "Unable to get path levels");
-                    s3_error!(InternalError, "Unable to get path levels")
-                })?;
-
-            let body = get_bundle(levels, self.backend.clone()).await;
+        let mut resp = S3Response::new(result);
+        resp.headers.extend(page.headers);
+        insert_access_headers(&mut resp.headers, headers)?;
+        Ok(resp)
+    }
 
-            let mut resp = S3Response::new(GetObjectOutput {
-                body,
-                last_modified: None,
-                e_tag: Some(format!("-{}", bundle.id)),
-                ..Default::default()
-            });
+    #[tracing::instrument(err)]
+    async fn list_objects_v2(
+        &self,
+        req: S3Request<ListObjectsV2Input>,
+    ) -> S3Result<S3Response<ListObjectsV2Output>> {
+        let CheckAccessResult {
+            user_state,
+            headers,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "No context found");
+                s3_error!(InternalError, "No context found")
+            })?;
+        let url_encoding = is_url_encoding(req.input.encoding_type.as_ref());
 
-            resp.headers.insert(
-                hyper::header::TRANSFER_ENCODING,
-                HeaderValue::from_static("chunked"),
-            );
+        // Process continuation token from request
+        let continuation_token = match req.input.continuation_token {
+            Some(t) => {
+                let decoded_token = general_purpose::STANDARD_NO_PAD.decode(t).map_err(|_| {
+                    error!(error = "Invalid continuation token");
+                    s3_error!(InvalidToken, "Invalid continuation token")
+                })?;
+                let decoded_token = std::str::from_utf8(&decoded_token)
+                    .map_err(|_| {
+                        error!(error = "Invalid continuation token");
+                        s3_error!(InvalidToken, "Invalid continuation token")
+                    })?
+                    .to_string();
+                Some(decoded_token)
+            }
+            None => None,
+        };
 
-            resp.headers.insert(
-                hyper::header::CONTENT_TYPE,
-                HeaderValue::from_static("application/octet-stream"),
-            );
+        // Filter all objects from cache which have the project as root
+        let start_after = match (req.input.start_after, continuation_token.clone()) {
+            (Some(_), Some(ct)) => ct,
+            (None, Some(ct)) => ct,
+            (Some(s), None) => s,
+            _ => "".to_string(),
+        };
 
-            return Ok(resp);
-        }
+        let listing = ListRequest {
+            bucket: &req.input.bucket,
+            prefix: req.input.prefix.filter(|prefix| !prefix.is_empty()),
+            delimiter: req.input.delimiter,
+            start_at: start_after,
+            max_keys: req.input.max_keys,
+            url_encoding,
+            public_only: matches!(user_state, UserState::Anonymous),
+            permissions: self.key_permissions(&user_state).await,
+        };
+        let page = self.list_bucket(&req.headers, &listing).await?;
 
-        let ObjectsState::Regular { states, location } = objects_state else {
-            return Err(s3_error!(
-                InternalError,
-                "Invalid object state, missing location"
-            ));
+        let result = ListObjectsV2Output {
+            key_count: Some(page.key_count),
+            common_prefixes: Some(page.common_prefixes),
+            contents: Some(page.contents),
+            continuation_token,
+            delimiter: listing.delimiter.map(|d| encode_listed(d, url_encoding)),
+            encoding_type: req.input.encoding_type,
+            is_truncated: Some(page.next_token.is_some()),
+            max_keys: Some(page.max_keys),
+            name: Some(listing.bucket.to_string()),
+            next_continuation_token: page.next_token,
+            prefix: listing.prefix.map(|p| encode_listed(p, url_encoding)),
+            start_after: Some(encode_listed(listing.start_at, url_encoding)),
+            ..Default::default()
         };
+        debug!(?result);
 
-        let location = location.ok_or_else(|| {
-            error!(error = "Unable to get resource");
-            s3_error!(NoSuchKey, "Object not found")
-        })?;
-        let mut content_length = location.raw_content_len;
+        let mut resp = S3Response::new(result);
+        resp.headers.extend(page.headers);
+        insert_access_headers(&mut resp.headers, headers)?;
+        Ok(resp)
+    }
 
-        let (sender, receiver) = async_channel::bounded(10);
-        let object = states.require_object()?;
+    #[tracing::instrument(err)]
+    async fn list_object_versions(
+        &self,
+        req: S3Request<ListObjectVersionsInput>,
+    ) -> S3Result<S3Response<ListObjectVersionsOutput>> {
+        let CheckAccessResult { user_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "No context found");
+                s3_error!(InternalError, "No context found")
+            })?;
+        // Anonymous requests only see public revisions
+        let public_only = matches!(user_state, UserState::Anonymous);
+        let ListObjectVersionsInput {
+            bucket,
+            delimiter,
+            encoding_type,
+            key_marker,
+            max_keys,
+            prefix,
+            ..
+        } = req.input;
+        let url_encoding = is_url_encoding(encoding_type.as_ref());
 
-        // Gets 128 kb chunks (last 2)
+        if self.cache.get_path(&bucket).is_none() {
+            error!("No bucket found");
+            return Err(s3_error!(NoSuchBucket, "No bucket found"));
+        }
 
-        let footer: Option<Footer> = if location.is_pithos() {
-            let (footer_sender, footer_receiver) = async_channel::bounded(1000);
-            pin!(footer_receiver);
-            self.backend
-                .get_object(
-                    location.clone(),
-                    Some(format!("bytes=-{}", (65536 + 28) * 2)),
-                    footer_sender,
-                )
+        let max_keys = match max_keys {
+            Some(k) if k > 0 && k < 1000 => k as usize,
+            _ => 1000usize,
+        };
+        let key_prefix = prefix.clone().unwrap_or_default();
+
+        // Pages always end at a key boundary, all revisions of a key are returned together
+        let mut versions = Vec::new();
+        let mut common_prefixes = BTreeSet::new();
+        let mut last_key = None;
+        let mut next_key_marker = None;
+        for (key, id) in self.cache.get_path_range(&bucket, &key_prefix) {
+            // Paths are sorted, no later path can match the prefix
+            let Some(stripped) = key.strip_prefix(&key_prefix) else {
+                break;
+            };
+            if key_marker
+                .as_ref()
+                .map(|marker| key.as_str() <= marker.as_str())
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            if let Some(delimiter) = &delimiter {
+                if let Some((common_prefix, _)) = stripped.split_once(delimiter.as_str()) {
+                    common_prefixes.insert(format!("{key_prefix}{common_prefix}{delimiter}"));
+                    continue;
+                }
+            }
+
+            let revisions = self
+                .cache
+                .get_revisions(&id)
                 .await
-                .map_err(|_| {
-                    error!(error = "Unable to get encryption_footer");
-                    s3_error!(InternalError, "Unable to get encryption_footer")
-                })?;
-            let mut output = BytesMut::with_capacity((65536 + 28) * 2);
-            while let Ok(Ok(bytes)) = footer_receiver.recv().await {
-                output.put(bytes);
+                .into_iter()
+                .filter(|(revision, _)| {
+                    revision.object_type == ObjectType::Object
+                        && (!public_only || revision.data_class == DataClass::Public)
+                })
+                .collect::<Vec<_>>();
+            if revisions.is_empty() {
+                continue;
+            }
+            if !versions.is_empty() && versions.len() + revisions.len() > max_keys {
+                next_key_marker = last_key.clone();
+                break;
+            }
+            for (revision, location) in revisions {
+                versions.push(ObjectVersion {
+                    e_tag: Some(revision.get_etag(location.as_ref())),
+                    is_latest: Some(revision.id == id),
+                    key: Some(encode_listed(key.clone(), url_encoding)),
+                    last_modified: revision.get_last_modified().map(Into::into),
+                    size: Some(revision.get_raw_size(location.as_ref())),
+                    version_id: Some(revision.id.to_string()),
+                    ..Default::default()
+                });
             }
+            last_key = Some(key);
+        }
 
-            let mut parser = FooterParser::new(&output).unwrap();
+        let result = ListObjectVersionsOutput {
+            common_prefixes: Some(
+                common_prefixes
+                    .into_iter()
+                    .map(|prefix| CommonPrefix {
+                        prefix: Some(encode_listed(prefix, url_encoding)),
+                    })
+                    .collect(),
+            ),
+            delimiter: delimiter.map(|d| encode_listed(d, url_encoding)),
+            encoding_type,
+            is_truncated: Some(next_key_marker.is_some()),
+            key_marker: key_marker.map(|k| encode_listed(k, url_encoding)),
+            max_keys: Some(max_keys as i32),
+            name: Some(bucket),
+            next_key_marker: next_key_marker.map(|k| encode_listed(k, url_encoding)),
+            prefix: prefix.map(|p| encode_listed(p, url_encoding)),
+            versions: Some(versions),
+            ..Default::default()
+        };
+        debug!(?result);
+        Ok(S3Response::new(result))
+    }
 
-            let key = CONFIG.proxy.clone().get_private_key_x25519().map_err(|e| {
-                error!(?e, error = "Unable to get private key");
-                s3_error!(InternalError, "Unable to get private key")
-            })?;
-            parser = parser.add_recipient(&key);
-            parser = parser.parse().map_err(|e| {
-                error!(error = ?e, msg = "Unable to parse footer");
-                s3_error!(InternalError, "Unable to parse footer")
+    #[tracing::instrument(err)]
+    async fn list_parts(
+        &self,
+        req: S3Request<ListPartsInput>,
+    ) -> S3Result<S3Response<ListPartsOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
             })?;
 
-            Some(parser.try_into().map_err(|_| {
-                error!(error = "Unable to convert footer");
-                s3_error!(InternalError, "Unable to convert footer")
-            })?)
-        } else {
-            None
+        let (_, location) = objects_state.extract_object()?;
+        match location {
+            Some(location) if location.upload_id.as_ref() == Some(&req.input.upload_id) => {}
+            _ => {
+                error!(error = "No such upload");
+                return Err(s3_error!(NoSuchUpload, "No such upload"));
+            }
         };
 
-        let parts = if location.is_temporary {
-            let mut part_sizes = Vec::new();
-            let parts = self
-                .cache
-                .get_parts(&location.upload_id.as_ref().ok_or_else(|| {
-                    error!(error = "Upload id must be specified");
-                    s3_error!(InvalidPart, "Upload id must be specified")
-                })?);
-
-            for parts in parts {
-                let full_chunks = (parts.size / (65536 + 28)) * (65536 + 28);
-                part_sizes.push(full_chunks);
-                if parts.size % (65536 + 28) != 0 {
-                    part_sizes.push(parts.size - full_chunks);
-                }
-            }
-            part_sizes
-        } else {
-            vec![footer
-                .as_ref()
-                .map(|f| {
-                    f.eof_metadata.disk_file_size
-                        - f.eof_metadata.toc_len
-                        - f.eof_metadata.encryption_len
-                        - 73
-                })
-                .unwrap_or_else(|| location.disk_content_len as u64)]
+        let max_parts = match req.input.max_parts {
+            Some(m) if m > 0 && m < 1000 => m as usize,
+            _ => 1000usize,
+        };
+        let marker = match &req.input.part_number_marker {
+            Some(marker) => marker.parse::<u64>().map_err(|_| {
+                error!(error = "Invalid part number marker");
+                s3_error!(InvalidArgument, "Invalid part number marker")
+            })?,
+            None => 0,
         };
 
-        trace!("calculating ranges");
-        let (query_ranges, edit_list, actual_range) =
-            match calculate_ranges(req.input.range, content_length as u64, footer, &location) {
-                Ok((query_ranges, edit_list, _, actual_range)) => {
-                    (query_ranges, edit_list, actual_range)
-                }
-                Err(err) => {
-                    error!(error = ?err, "Unable to calculate ranges");
-                    return Err(s3_error!(InternalError, "Unable to calculate ranges"));
-                }
-            };
+        let mut parts = self
+            .cache
+            .get_parts(&req.input.upload_id)
+            .into_iter()
+            .filter(|part| part.part_number > marker)
+            .collect::<Vec<_>>();
+        let is_truncated = parts.len() > max_parts;
+        parts.truncate(max_parts);
+        let next_part_number_marker = parts.last().map(|part| part.part_number.to_string());
 
-        let (accept_ranges, content_range) = if let Some(query_range) = actual_range {
-            content_length = (query_range.to - query_range.from) as i64;
-            (
-                Some("bytes".to_string()),
-                Some(format!(
-                    "bytes {}-{}/{}",
-                    query_range.from, query_range.to, content_length
-                )),
-            )
-        } else {
-            (None, None)
+        let output = ListPartsOutput {
+            bucket: Some(req.input.bucket),
+            key: Some(req.input.key),
+            upload_id: Some(req.input.upload_id),
+            is_truncated: Some(is_truncated),
+            max_parts: Some(max_parts as i32),
+            part_number_marker: req.input.part_number_marker,
+            next_part_number_marker,
+            parts: Some(
+                parts
+                    .into_iter()
+                    .map(|part| Part {
+                        e_tag: Some(if part.md5.is_empty() {
+                            part.etag
+                        } else {
+                            format!("\"{}\"", part.md5)
+                        }),
+                        part_number: Some(part.part_number as i32),
+                        size: Some(part.raw_size as i64),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
         };
+        debug!(?output);
+        Ok(S3Response::new(output))
+    }
 
-        trace!(?edit_list);
-
-        // Spawn get_object to fetch bytes from storage storage
-        let backend = self.backend.clone();
-        let loc_clone = location.clone();
-        trace!(?loc_clone, ?query_ranges, "spawning get_object");
-        tokio::spawn(
-            async move { backend.get_object(loc_clone, query_ranges, sender).await }
-                .instrument(info_span!("get_object")),
+    #[tracing::instrument(err)]
+    async fn put_bucket_cors(
+        &self,
+        req: S3Request<PutBucketCorsInput>,
+    ) -> S3Result<S3Response<PutBucketCorsOutput>> {
+        let config = crate::structs::CORSConfiguration(
+            req.input
+                .cors_configuration
+                .cors_rules
+                .into_iter()
+                .map(CORSRule::into)
+                .collect(),
         );
-        let (final_send, final_rcv) = async_channel::bounded(100);
+        for rule in config.0.iter() {
+            if rule.allowed_origins.is_empty() || rule.allowed_methods.is_empty() {
+                error!(?rule, "Invalid cors rule");
+                return Err(s3_error!(
+                    MalformedXML,
+                    "CORS rules require at least one allowed origin and method"
+                ));
+            }
+            if let Some(method) = rule
+                .allowed_methods
+                .iter()
+                .find(|m| !matches!(m.as_str(), "GET" | "PUT" | "POST" | "DELETE" | "HEAD"))
+            {
+                error!(method, "Unsupported cors method");
+                return Err(s3_error!(
+                    InvalidRequest,
+                    "Found unsupported HTTP method in CORS config"
+                ));
+            }
+        }
 
-        let decryption_key = location.get_encryption_key();
+        let data = req.extensions.get::<CheckAccessResult>().cloned();
 
-        trace!(parts = ?parts);
-        // Spawn final part
-        tokio::spawn(
-            async move {
-                pin!(receiver);
-                let mut asrw = GenericStreamReadWriter::new_with_sink(
-                    receiver,
-                    AsyncSenderSink::new(final_send),
-                );
+        if let Some(client) = self.cache.aruna_client.read().await.as_ref() {
+            let CheckAccessResult {
+                objects_state,
+                user_state,
+                ..
+            } = data.ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InvalidObjectState, "Missing CheckAccess extension")
+            })?;
+
+            let object = objects_state.require_bucket()?;
+            let bucket_obj = object.require_project()?;
+
+            let token = user_state
+                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+                .ok_or_else(|| {
+                    error!(error = "Unauthorized: Impersonating error");
+                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+                })?;
+
+            client
+                .add_or_replace_key_value_project(
+                    &token,
+                    bucket_obj.clone(),
+                    CORS_KEY,
+                    Some(&serde_json::to_string(&config).map_err(|_| {
+                        error!(error = "Unable to serialize cors configuration");
+                        s3_error!(InvalidArgument, "Unable to serialize cors configuration")
+                    })?),
+                )
+                .await
+                .map_err(|_| {
+                    error!(error = "Unable to update KeyValues");
+                    s3_error!(InternalError, "Unable to update KeyValues")
+                })?;
+        }
+        Ok(S3Response::new(PutBucketCorsOutput::default()))
+    }
 
-                if let Some(key) = decryption_key {
-                    asrw = asrw
-                        .add_transformer(ChaCha20DecParts::new_with_lengths(key, parts));
-                }
+    #[tracing::instrument(err)]
+    async fn delete_bucket_cors(
+        &self,
+        req: S3Request<DeleteBucketCorsInput>,
+    ) -> S3Result<S3Response<DeleteBucketCorsOutput>> {
+        let data = req.extensions.get::<CheckAccessResult>().cloned();
 
-                if location.is_compressed() {
-                    asrw = asrw.add_transformer(ZstdDec::new());
-                }
+        if let Some(client) = self.cache.aruna_client.read().await.as_ref() {
+            let CheckAccessResult {
+                objects_state,
+                user_state,
+                ..
+            } = data.ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InternalError, "Internal Error")
+            })?;
 
-                if let Some(edit_list) = edit_list {
-                    asrw = asrw.add_transformer(Filter::new_with_edit_list(Some(edit_list)));
-                };
+            let object = objects_state.require_bucket()?;
+            let bucket_obj = object.require_project()?;
 
-                asrw.process().await.map_err(|e| {
-                    error!(error = ?e, msg = "Unable to process final part");
-                    s3_error!(InternalError, "Internal notifier error")
+            let token = user_state
+                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+                .ok_or_else(|| {
+                    error!(error = "Unauthorized: Impersonating error");
+                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
                 })?;
 
-                Ok::<_, anyhow::Error>(())
-            }
-            .instrument(info_span!("query_data")),
-        );
+            client
+                .add_or_replace_key_value_project(&token, bucket_obj.clone(), CORS_KEY, None)
+                .await
+                .map_err(|_| {
+                    error!(error = "Unable to update KeyValues");
+                    s3_error!(InternalError, "Unable to update KeyValues")
+                })?;
+        }
+        Ok(S3Response::new(DeleteBucketCorsOutput::default()))
+    }
 
-        let body = Some(StreamingBlob::wrap(final_rcv.map_err(|_| {
-            error!(error = "Unable to wrap final_rcv");
-            s3_error!(InternalError, "Internal processing error")
-        })));
+    #[tracing::instrument(err)]
+    async fn get_bucket_location(
+        &self,
+        _req: S3Request<GetBucketLocationInput>,
+    ) -> S3Result<S3Response<GetBucketLocationOutput>> {
+        return Ok(S3Response::new(GetBucketLocationOutput {
+            // TODO: Return proxy location / id -> Not possible restricted set of allowed locations
+            location_constraint: None,
+        }));
+    }
 
-        let output = GetObjectOutput {
-            body,
-            accept_ranges,
-            content_range,
-            content_length: Some(content_length),
-            last_modified: None,
-            e_tag: Some(format!("-{}", object.id)),
-            version_id: None,
-            ..Default::default()
-        };
-        debug!(?output);
+    #[tracing::instrument(err)]
+    async fn get_bucket_cors(
+        &self,
+        req: S3Request<GetBucketCorsInput>,
+    ) -> S3Result<S3Response<GetBucketCorsOutput>> {
+        let data = req.extensions.get::<CheckAccessResult>().cloned();
 
-        let mut resp = S3Response::new(output);
-        if let Some(headers) = headers {
-            for (k, v) in headers {
-                resp.headers.insert(
-                    HeaderName::from_bytes(k.as_bytes())
-                        .map_err(|_| s3_error!(InternalError, "Unable to parse header name"))?,
-                    HeaderValue::from_str(&v)
-                        .map_err(|_| s3_error!(InternalError, "Unable to parse header value"))?,
-                );
-            }
+        let CheckAccessResult { objects_state, .. } = data.ok_or_else(|| {
+            error!(error = "Missing data context");
+            s3_error!(InvalidObjectState, "Missing CheckAccess extension")
+        })?;
+
+        let object = objects_state.require_bucket()?;
+        let bucket_obj = object.require_project()?;
+
+        if let Some(cors) = self.cache.get_cors_config(&bucket_obj.id) {
+            return Ok(S3Response::new(cors.as_ref().clone().into()));
         }
-        Ok(resp)
+        Ok(S3Response::new(GetBucketCorsOutput::default()))
     }
 
     #[tracing::instrument(err)]
-    async fn head_object(
+    async fn put_bucket_lifecycle_configuration(
         &self,
-        req: S3Request<HeadObjectInput>,
-    ) -> S3Result<S3Response<HeadObjectOutput>> {
+        req: S3Request<PutBucketLifecycleConfigurationInput>,
+    ) -> S3Result<S3Response<PutBucketLifecycleConfigurationOutput>> {
+        let config = LifecycleConfiguration::try_from(
+            req.input.lifecycle_configuration.ok_or_else(|| {
+                error!(error = "Missing lifecycle configuration");
+                s3_error!(MalformedXML, "Missing lifecycle configuration")
+            })?,
+        )?;
+
         let CheckAccessResult {
             objects_state,
-            headers,
+            user_state,
             ..
         } = req
             .extensions
             .get::<CheckAccessResult>()
             .cloned()
             .ok_or_else(|| {
-                error!(error = "No context found");
-                s3_error!(InternalError, "No context found")
+                error!(error = "Missing data context");
+                s3_error!(InvalidObjectState, "Missing CheckAccess extension")
             })?;
 
-        if let ObjectsState::Bundle { bundle, .. } = objects_state {
-            return Ok(S3Response::new(HeadObjectOutput {
-                content_length: None,
-                last_modified: Some(
-                    time::OffsetDateTime::from_unix_timestamp(
-                        (bundle.id.timestamp() / 1000) as i64,
-                    )
-                    .map_err(|_| {
-                        error!(error = "Unable to parse timestamp");
-                        s3_error!(InternalError, "Unable to parse timestamp")
-                    })?
-                    .into(),
-                ),
-                e_tag: Some(format!("-{}", bundle.id)),
-                ..Default::default()
-            }));
-        }
-
-        let (object, location) = objects_state.extract_object()?;
-
-        let content_len = location.map(|l| l.raw_content_len).unwrap_or_default();
+        let object = objects_state.require_bucket()?;
+        let bucket_obj = object.require_project()?;
 
-        let mime = mime_guess::from_path(object.name.as_str()).first();
+        let token = user_state
+            .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+            .ok_or_else(|| {
+                error!(error = "Unauthorized: Impersonating error");
+                s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+            })?;
 
-        let output = HeadObjectOutput {
-            content_length: Some(content_len),
-            last_modified: Some(
-                time::OffsetDateTime::from_unix_timestamp((object.id.timestamp() / 1000) as i64)
-                    .map_err(|e| {
-                        error!(error = ?e, msg = "Unable to parse timestamp");
-                        s3_error!(InternalError, "Unable to parse timestamp")
-                    })?
-                    .into(),
-            ),
-            e_tag: Some(object.id.to_string()),
-            content_type: mime,
-            ..Default::default()
+        let Some(client) = self.cache.aruna_client.read().await.clone() else {
+            error!("ArunaServer client not available");
+            return Err(s3_error!(InternalError, "ArunaServer client not available"));
         };
+        client
+            .add_or_replace_key_value_project(
+                &token,
+                bucket_obj.clone(),
+                LIFECYCLE_KEY,
+                Some(&serde_json::to_string(&config).map_err(|_| {
+                    error!(error = "Unable to serialize lifecycle configuration");
+                    s3_error!(
+                        InvalidArgument,
+                        "Unable to serialize lifecycle configuration"
+                    )
+                })?),
+            )
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to update KeyValues");
+                s3_error!(InternalError, "Unable to update KeyValues")
+            })?;
+        Ok(S3Response::new(
+            PutBucketLifecycleConfigurationOutput::default(),
+        ))
+    }
 
-        debug!(?output);
-        debug!(?headers);
+    #[tracing::instrument(err)]
+    async fn get_bucket_lifecycle_configuration(
+        &self,
+        req: S3Request<GetBucketLifecycleConfigurationInput>,
+    ) -> S3Result<S3Response<GetBucketLifecycleConfigurationOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InvalidObjectState, "Missing CheckAccess extension")
+            })?;
 
-        let mut resp = S3Response::new(output);
-        if let Some(headers) = headers {
-            for (k, v) in headers {
-                resp.headers.insert(
-                    HeaderName::from_bytes(k.as_bytes()).map_err(|_| {
-                        error!(error = "Unable to parse header name");
-                        s3_error!(InternalError, "Unable to parse header name")
-                    })?,
-                    HeaderValue::from_str(&v).map_err(|_| {
-                        error!(error = "Unable to parse header value");
-                        s3_error!(InternalError, "Unable to parse header value")
-                    })?,
-                );
-            }
-        }
+        let object = objects_state.require_bucket()?;
+        let bucket_obj = object.require_project()?;
 
-        Ok(resp)
+        match self.cache.get_lifecycle_config(&bucket_obj.id) {
+            Some(config) => Ok(S3Response::new(config.as_ref().clone().into())),
+            None => Err(s3_error!(
+                NoSuchLifecycleConfiguration,
+                "The lifecycle configuration does not exist"
+            )),
+        }
     }
 
     #[tracing::instrument(err)]
-    async fn list_objects_v2(
+    async fn delete_object(
         &self,
-        req: S3Request<ListObjectsV2Input>,
-    ) -> S3Result<S3Response<ListObjectsV2Output>> {
-        let CheckAccessResult { headers, .. } = req
+        req: S3Request<DeleteObjectInput>,
+    ) -> S3Result<S3Response<DeleteObjectOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            headers,
+        } = req
             .extensions
             .get::<CheckAccessResult>()
             .cloned()
             .ok_or_else(|| {
-                error!(error = "No context found");
-                s3_error!(InternalError, "No context found")
+                error!(error = "Missing data context");
+                s3_error!(InternalError, "Internal Error")
             })?;
-        // Fetch the project name, delimiter and prefix from the request
-        let project_name = &req.input.bucket;
-        let delimiter = req.input.delimiter;
-        let prefix = req.input.prefix.filter(|prefix| !prefix.is_empty());
 
-        // Check if bucket exists as root in cache of paths
-        match self.cache.get_path(project_name.as_str()) {
-            Some(_) => {}
-            None => {
-                error!("No bucket found");
-                return Err(s3_error!(NoSuchBucket, "No bucket found"));
+        let states = match objects_state {
+            ObjectsState::Object { states, .. } | ObjectsState::SpecialObject { states, .. } => {
+                states
             }
-        };
-
-        // Process continuation token from request
-        let continuation_token = match req.input.continuation_token {
-            Some(t) => {
-                let decoded_token = general_purpose::STANDARD_NO_PAD.decode(t).map_err(|_| {
-                    error!(error = "Invalid continuation token");
-                    s3_error!(InvalidToken, "Invalid continuation token")
-                })?;
-                let decoded_token = std::str::from_utf8(&decoded_token)
-                    .map_err(|_| {
-                        error!(error = "Invalid continuation token");
-                        s3_error!(InvalidToken, "Invalid continuation token")
-                    })?
-                    .to_string();
-                Some(decoded_token)
+            _ => {
+                error!(error = "DeleteObject without object path");
+                return Err(s3_error!(InvalidRequest, "Invalid object path"));
             }
-            None => None,
         };
-
-        // Filter all objects from cache which have the project as root
-        let start_after = match (req.input.start_after, continuation_token.clone()) {
-            (Some(_), Some(ct)) => ct,
-            (None, Some(ct)) => ct,
-            (Some(s), None) => s,
-            _ => "".to_string(),
+        // Deleting a missing key succeeds like on AWS, e.g. for retried deletions
+        let Some(object) = states.get_object() else {
+            debug!(
+                key = req.input.key,
+                "Object does not exist, nothing to delete"
+            );
+            return Ok(S3Response::new(DeleteObjectOutput::default()));
         };
+        if req
+            .input
+            .version_id
+            .as_ref()
+            .is_some_and(|version_id| *version_id != object.id.to_string())
+        {
+            error!(error = "Deleting revisions is not supported");
+            return Err(s3_error!(
+                NotImplemented,
+                "Deleting revisions is not supported"
+            ));
+        }
+        if let Some(retention) = ObjectRetention::from_object(object) {
+            retention.check_mutation(&object.id, chrono::Utc::now())?;
+        }
 
-        let max_keys = match req.input.max_keys {
-            Some(k) if k < 1000 => k as usize,
-            _ => 1000usize,
+        let client = self.cache.aruna_client.read().await.clone();
+        let token = match &client {
+            Some(_) => user_state
+                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+                .ok_or_else(|| {
+                    error!(error = "Unauthorized: Impersonating error");
+                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+                })?,
+            None => String::new(),
         };
 
-        let (keys, common_prefixes, new_continuation_token) = list_response(
+        // Keys are only known while the object is still cached
+        let events = webhooks::removed_events(&self.cache, object.id).await;
+        deletion::delete_object(
             &self.cache,
-            &delimiter,
-            &prefix,
-            &project_name,
-            &start_after,
-            max_keys,
+            client.as_deref().map(|c| c as &dyn DeleteNotifier),
+            object.id,
+            &token,
         )
         .await
-        .map_err(|_| {
-            error!(error = "Keys not found in ListObjectsV2");
-            s3_error!(NoSuchKey, "Keys not found in ListObjectsV2")
+        .map_err(|e| {
+            error!(error = ?e, msg = "Unable to delete object");
+            s3_error!(InternalError, "Unable to delete object, please retry")
         })?;
-
-        let key_count = (keys.len() + common_prefixes.len()) as i32;
-        let common_prefixes = Some(
-            common_prefixes
-                .into_iter()
-                .map(|e| CommonPrefix { prefix: Some(e) })
-                .collect(),
-        );
-        let contents = Some(
-            keys.into_iter()
-                .map(|e| Object {
-                    checksum_algorithm: None,
-                    e_tag: Some(e.etag.to_string()),
-                    key: Some(e.key),
-                    last_modified: e.created_at.map(|t| {
-                        s3s::dto::Timestamp::from(
-                            time::OffsetDateTime::from_unix_timestamp(t.and_utc().timestamp())
-                                .unwrap_or_else(|_| {
-                                    error!(error = "Unable to parse timestamp");
-                                    time::OffsetDateTime::now_utc()
-                                }),
-                        )
-                    }),
-                    owner: None,
-                    size: Some(e.size),
-                    ..Default::default()
-                })
-                .collect(),
-        );
-
-        let result = ListObjectsV2Output {
-            common_prefixes,
-            contents,
-            continuation_token,
-            delimiter,
-            encoding_type: None,
-            is_truncated: Some(new_continuation_token.is_some()),
-            key_count: Some(key_count),
-            max_keys: Some(max_keys.try_into().map_err(|err| {
-                error!(error = ?err, "Conversion failure");
-                s3_error!(InternalError, "[BACKEND] Conversion failure: {}", err)
-            })?),
-            name: Some(project_name.clone()),
-            next_continuation_token: new_continuation_token,
-            prefix,
-            start_after: Some(start_after),
-            ..Default::default()
-        };
-        debug!(?result);
-
-        let mut resp = S3Response::new(result);
-
-        if let Some(headers) = headers {
-            for (k, v) in headers {
-                resp.headers.insert(
-                    HeaderName::from_bytes(k.as_bytes()).map_err(|_| {
-                        error!(error = "Unable to parse header name");
-                        s3_error!(InternalError, "Unable to parse header name")
-                    })?,
-                    HeaderValue::from_str(&v).map_err(|_| {
-                        error!(error = "Unable to parse header value");
-                        s3_error!(InternalError, "Unable to parse header value")
-                    })?,
-                );
-            }
+        // The object is deleted, a failed cleanup only leaves an empty dataset behind
+        let parents = [states.get_dataset(), states.get_collection()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if let Err(e) = deletion::remove_empty_parents(
+            &self.cache,
+            client.as_deref().map(|c| c as &dyn DeleteNotifier),
+            &parents,
+            &token,
+        )
+        .await
+        {
+            error!(error = ?e, msg = "Unable to remove empty parents");
+        }
+        for (project_id, event) in events {
+            webhooks::notify(&self.cache, project_id, event).await;
         }
 
+        let mut resp = S3Response::new(DeleteObjectOutput::default());
+        insert_access_headers(&mut resp.headers, headers)?;
         Ok(resp)
     }
 
     #[tracing::instrument(err)]
-    async fn put_bucket_cors(
+    async fn delete_bucket_lifecycle(
         &self,
-        req: S3Request<PutBucketCorsInput>,
-    ) -> S3Result<S3Response<PutBucketCorsOutput>> {
-        let config = crate::structs::CORSConfiguration(
-            req.input
-                .cors_configuration
-                .cors_rules
-                .into_iter()
-                .map(CORSRule::into)
-                .collect(),
-        );
+        req: S3Request<DeleteBucketLifecycleInput>,
+    ) -> S3Result<S3Response<DeleteBucketLifecycleOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InternalError, "Internal Error")
+            })?;
 
-        let data = req.extensions.get::<CheckAccessResult>().cloned();
+        let object = objects_state.require_bucket()?;
+        let bucket_obj = object.require_project()?;
 
-        if let Some(client) = self.cache.aruna_client.read().await.as_ref() {
-            let CheckAccessResult {
-                objects_state,
-                user_state,
-                ..
-            } = data.ok_or_else(|| {
+        let token = user_state
+            .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+            .ok_or_else(|| {
+                error!(error = "Unauthorized: Impersonating error");
+                s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+            })?;
+
+        let Some(client) = self.cache.aruna_client.read().await.clone() else {
+            error!("ArunaServer client not available");
+            return Err(s3_error!(InternalError, "ArunaServer client not available"));
+        };
+        client
+            .add_or_replace_key_value_project(&token, bucket_obj.clone(), LIFECYCLE_KEY, None)
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to update KeyValues");
+                s3_error!(InternalError, "Unable to update KeyValues")
+            })?;
+        Ok(S3Response::new(DeleteBucketLifecycleOutput::default()))
+    }
+
+    #[tracing::instrument(err)]
+    async fn put_object_lock_configuration(
+        &self,
+        req: S3Request<PutObjectLockConfigurationInput>,
+    ) -> S3Result<S3Response<PutObjectLockConfigurationOutput>> {
+        let config = req.input.object_lock_configuration.ok_or_else(|| {
+            error!(error = "Missing object lock configuration");
+            s3_error!(MalformedXML, "Missing object lock configuration")
+        })?;
+        // Without a rule only the default retention is removed
+        let policy = match config.rule {
+            Some(_) => Some(ObjectLockPolicy::try_from(config)?),
+            None => None,
+        };
+
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
                 error!(error = "Missing data context");
                 s3_error!(InvalidObjectState, "Missing CheckAccess extension")
             })?;
 
-            let (object, _) = objects_state.require_regular()?;
-            let bucket_obj = object.require_project()?;
+        let object = objects_state.require_bucket()?;
+        let bucket_obj = object.require_project()?;
 
-            let token = user_state
-                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
-                .ok_or_else(|| {
-                    error!(error = "Unauthorized: Impersonating error");
-                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
-                })?;
+        let token = user_state
+            .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+            .ok_or_else(|| {
+                error!(error = "Unauthorized: Impersonating error");
+                s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+            })?;
 
-            client
-                .add_or_replace_key_value_project(
-                    &token,
-                    bucket_obj.clone(),
-                    Some((
-                        "app.aruna-storage.org/cors",
-                        &serde_json::to_string(&config).map_err(|_| {
-                            error!(error = "Unable to serialize cors configuration");
-                            s3_error!(InvalidArgument, "Unable to serialize cors configuration")
-                        })?,
-                    )),
-                )
-                .await
-                .map_err(|_| {
-                    error!(error = "Unable to update KeyValues");
-                    s3_error!(InternalError, "Unable to update KeyValues")
-                })?;
-        }
-        Ok(S3Response::new(PutBucketCorsOutput::default()))
+        let Some(client) = self.cache.aruna_client.read().await.clone() else {
+            error!("ArunaServer client not available");
+            return Err(s3_error!(InternalError, "ArunaServer client not available"));
+        };
+        let value = policy
+            .map(|policy| {
+                serde_json::to_string(&policy).map_err(|_| {
+                    error!(error = "Unable to serialize object lock configuration");
+                    s3_error!(
+                        InvalidArgument,
+                        "Unable to serialize object lock configuration"
+                    )
+                })
+            })
+            .transpose()?;
+        client
+            .add_or_replace_key_value_project(
+                &token,
+                bucket_obj.clone(),
+                OBJECT_LOCK_KEY,
+                value.as_deref(),
+            )
+            .await
+            .map_err(|_| {
+                error!(error = "Unable to update KeyValues");
+                s3_error!(InternalError, "Unable to update KeyValues")
+            })?;
+        Ok(S3Response::new(PutObjectLockConfigurationOutput::default()))
     }
 
     #[tracing::instrument(err)]
-    async fn delete_bucket_cors(
+    async fn get_object_lock_configuration(
         &self,
-        req: S3Request<DeleteBucketCorsInput>,
-    ) -> S3Result<S3Response<DeleteBucketCorsOutput>> {
-        let data = req.extensions.get::<CheckAccessResult>().cloned();
-
-        if let Some(client) = self.cache.aruna_client.read().await.as_ref() {
-            let CheckAccessResult {
-                objects_state,
-                user_state,
-                ..
-            } = data.ok_or_else(|| {
+        req: S3Request<GetObjectLockConfigurationInput>,
+    ) -> S3Result<S3Response<GetObjectLockConfigurationOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
                 error!(error = "Missing data context");
-                s3_error!(InternalError, "Internal Error")
+                s3_error!(InvalidObjectState, "Missing CheckAccess extension")
             })?;
 
-            let (object, _) = objects_state.require_regular()?;
-            let bucket_obj = object.require_project()?;
+        let object = objects_state.require_bucket()?;
+        let bucket_obj = object.require_project()?;
 
-            let token = user_state
-                .sign_impersonating_token(self.cache.auth.read().await.as_ref())
-                .ok_or_else(|| {
-                    error!(error = "Unauthorized: Impersonating error");
-                    s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
-                })?;
+        match self.cache.get_object_lock(&bucket_obj.id) {
+            Some(policy) => Ok(S3Response::new(GetObjectLockConfigurationOutput {
+                object_lock_configuration: Some(policy.as_ref().clone().into()),
+            })),
+            None => Err(s3_error!(
+                ObjectLockConfigurationNotFoundError,
+                "The object lock configuration does not exist"
+            )),
+        }
+    }
 
-            client
-                .add_or_replace_key_value_project(&token, bucket_obj.clone(), None)
-                .await
-                .map_err(|_| {
-                    error!(error = "Unable to update KeyValues");
-                    s3_error!(InternalError, "Unable to update KeyValues")
-                })?;
+    #[tracing::instrument(err)]
+    async fn get_object_retention(
+        &self,
+        req: S3Request<GetObjectRetentionInput>,
+    ) -> S3Result<S3Response<GetObjectRetentionOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+
+        let (object, _) = match &req.input.version_id {
+            Some(version_id) => {
+                let (latest, _) = objects_state.extract_object()?;
+                self.get_revision(&latest, version_id).await?
+            }
+            None => objects_state.extract_object()?,
+        };
+
+        match ObjectRetention::from_object(&object) {
+            Some(retention) => Ok(S3Response::new(retention.into())),
+            None => {
+                error!(object_id = ?object.id, "Object has no retention");
+                Err(s3_error!(
+                    NoSuchObjectLockConfiguration,
+                    "The object does not have a retention"
+                ))
+            }
         }
-        Ok(S3Response::new(DeleteBucketCorsOutput::default()))
     }
 
     #[tracing::instrument(err)]
-    async fn get_bucket_location(
+    async fn get_object_acl(
         &self,
-        _req: S3Request<GetBucketLocationInput>,
-    ) -> S3Result<S3Response<GetBucketLocationOutput>> {
-        return Ok(S3Response::new(GetBucketLocationOutput {
-            // TODO: Return proxy location / id -> Not possible restricted set of allowed locations
-            location_constraint: None,
-        }));
+        req: S3Request<GetObjectAclInput>,
+    ) -> S3Result<S3Response<GetObjectAclOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+
+        let (object, _) = match &req.input.version_id {
+            Some(version_id) => {
+                let (latest, _) = objects_state.extract_object()?;
+                self.get_revision(&latest, version_id).await?
+            }
+            None => objects_state.extract_object()?,
+        };
+        let (states, _) = objects_state.require_regular()?;
+        let owner = project_owner(states.require_project()?);
+
+        // The ACL is derived from the data class, it can not be stored independently
+        let acl = CannedAcl::from_data_class(object.data_class);
+        Ok(S3Response::new(GetObjectAclOutput {
+            grants: Some(acl.grants(&owner)),
+            owner: Some(owner),
+            ..Default::default()
+        }))
     }
 
     #[tracing::instrument(err)]
-    async fn get_bucket_cors(
+    async fn put_object_acl(
         &self,
-        req: S3Request<GetBucketCorsInput>,
-    ) -> S3Result<S3Response<GetBucketCorsOutput>> {
-        let data = req.extensions.get::<CheckAccessResult>().cloned();
+        req: S3Request<PutObjectAclInput>,
+    ) -> S3Result<S3Response<PutObjectAclOutput>> {
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            ..
+        } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
 
-        let CheckAccessResult { objects_state, .. } = data.ok_or_else(|| {
-            error!(error = "Missing data context");
-            s3_error!(InvalidObjectState, "Missing CheckAccess extension")
-        })?;
+        let (object, _) = match &req.input.version_id {
+            Some(version_id) => {
+                let (latest, _) = objects_state.extract_object()?;
+                self.get_revision(&latest, version_id).await?
+            }
+            None => objects_state.extract_object()?,
+        };
+        let (states, _) = objects_state.require_regular()?;
+        let project = states.require_project()?;
 
-        let (object, _) = objects_state.require_regular()?;
-        let bucket_obj = object.require_project()?;
+        // A data class change affects who can read the object, like other project settings
+        // it requires ADMIN on the project
+        let access_key = user_state.get_access_key().ok_or_else(|| {
+            error!(error = "Missing access key");
+            s3_error!(AccessDenied, "Missing access key")
+        })?;
+        let is_admin = self
+            .cache
+            .get_key_perms(&access_key)
+            .await
+            .and_then(|key| key.permissions.get(&project.id).cloned())
+            .is_some_and(|perm| perm >= DbPermissionLevel::Admin);
+        if !is_admin {
+            error!(project_id = ?project.id, "ACL change requires ADMIN on the project");
+            return Err(s3_error!(
+                AccessDenied,
+                "Changing the ACL requires ADMIN permissions on the bucket"
+            ));
+        }
 
-        let cors = bucket_obj
-            .key_values
-            .clone()
-            .into_iter()
-            .find(|kv| kv.key == "app.aruna-storage.org/cors")
-            .map(|kv| kv.value);
-
-        if let Some(cors) = cors {
-            let cors: crate::structs::CORSConfiguration =
-                serde_json::from_str(&cors).map_err(|_| {
-                    error!(error = "Unable to deserialize cors from JSON");
-                    s3_error!(InvalidObjectState, "Unable to deserialize cors from JSON")
-                })?;
-            return Ok(S3Response::new(cors.into()));
+        let acl = CannedAcl::from_request(&req.input, &project_owner(project))?;
+        let data_class = acl.apply(object.data_class);
+        if data_class == object.data_class {
+            return Ok(S3Response::new(PutObjectAclOutput::default()));
         }
-        Ok(S3Response::new(GetBucketCorsOutput::default()))
+        let mut updated = object.clone();
+        updated.data_class = data_class;
+
+        match self.cache.aruna_client.read().await.as_ref() {
+            Some(client) => {
+                let token = user_state
+                    .sign_impersonating_token(self.cache.auth.read().await.as_ref())
+                    .ok_or_else(|| {
+                        error!(error = "Unauthorized: Impersonating error");
+                        s3_error!(NotSignedUp, "Unauthorized: Impersonating error")
+                    })?;
+                // Updates the cache with the server response, anonymous access checks
+                // of the object are invalidated with it
+                client
+                    .init_object_update(updated, &token, false)
+                    .await
+                    .map_err(|e| {
+                        error!(error = ?e, msg = e.to_string());
+                        s3_error!(
+                            InvalidRequest,
+                            "Data class change was rejected by the server"
+                        )
+                    })?;
+            }
+            None => self.cache.upsert_object(updated).await.map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                s3_error!(InternalError, "Unable to update object")
+            })?,
+        }
+        Ok(S3Response::new(PutObjectAclOutput::default()))
     }
 
     #[tracing::instrument(err)]
@@ -1050,45 +3338,38 @@ impl S3 for ArunaS3Service {
             s3_error!(InvalidObjectState, "Missing CheckAccess extension")
         })?;
 
-        match user_state.get_user_id() {
-            Some(user_id) => {
-                let mut buckets = Vec::new();
-                for o in self
-                    .cache
-                    .get_personal_projects(&user_id)
-                    .await
-                    .map_err(|_| {
-                        error!(error = "Unable to get personal projects");
-                        s3_error!(InternalError, "Unable to get personal projects")
-                    })?
-                {
-                    buckets.push(Bucket {
-                        creation_date: o.created_at.map(|t| {
-                            s3s::dto::Timestamp::from(
-                                time::OffsetDateTime::from_unix_timestamp(t.and_utc().timestamp())
-                                    .unwrap(),
-                            )
-                        }),
-                        name: Some(o.name),
-                    });
-                }
-                let bs = if buckets.is_empty() {
-                    None
-                } else {
-                    Some(buckets)
-                };
-                return Ok(S3Response::new(ListBucketsOutput {
-                    buckets: bs,
-                    owner: Some(Owner {
-                        display_name: None,
-                        id: Some(user_id.to_string()),
-                    }),
-                }));
-            }
-            None => {
-                return Err(s3_error!(InvalidAccessKeyId, "Invalid access key / user"));
-            }
-        }
+        let (Some(user_id), Some(access_key)) =
+            (user_state.get_user_id(), user_state.get_access_key())
+        else {
+            error!(error = "Missing user for ListBuckets");
+            return Err(s3_error!(InvalidAccessKeyId, "Invalid access key / user"));
+        };
+        let permissions = self
+            .cache
+            .get_key_perms(&access_key)
+            .await
+            .ok_or_else(|| {
+                error!(error = "Unable to get access key permissions");
+                s3_error!(InvalidAccessKeyId, "Invalid access key / user")
+            })?
+            .permissions;
+        let include_public = CONFIG
+            .frontend
+            .as_ref()
+            .is_some_and(|f| f.list_public_buckets);
+        let buckets = accessible_buckets(
+            self.cache.get_all_projects().await,
+            &permissions,
+            include_public,
+        );
+
+        Ok(S3Response::new(ListBucketsOutput {
+            buckets: (!buckets.is_empty()).then_some(buckets),
+            owner: Some(Owner {
+                display_name: None,
+                id: Some(user_id.to_string()),
+            }),
+        }))
     }
 
     #[tracing::instrument(err, skip(self, req))]
@@ -1096,16 +3377,19 @@ impl S3 for ArunaS3Service {
         &self,
         req: S3Request<PutObjectInput>,
     ) -> S3Result<S3Response<PutObjectOutput>> {
-        match req.input.content_length {
-            Some(0) | None => {
-                error!("Missing or invalid (0) content-length");
-                return Err(s3_error!(
-                    MissingContentLength,
-                    "Missing or invalid (0) content-length"
-                ));
-            }
-            _ => {}
+        let content_length = payload_content_length(&req.headers, req.input.content_length);
+        let Some(content_length) = content_length else {
+            error!("Missing or invalid content-length");
+            return Err(s3_error!(
+                MissingContentLength,
+                "Missing or invalid content-length"
+            ));
         };
+        if req.input.key.ends_with('/') {
+            return self.put_directory_marker(&req, content_length).await;
+        }
+        let empty = content_length == 0;
+        let content_length = Some(content_length);
 
         let CheckAccessResult {
             objects_state,
@@ -1120,12 +3404,129 @@ impl S3 for ArunaS3Service {
                 s3_error!(UnexpectedContent, "Missing data context")
             })?;
 
-        let impersonating_token =
-            user_state.sign_impersonating_token(self.cache.auth.read().await.as_ref());
+        let impersonating_token =
+            user_state.sign_impersonating_token(self.cache.auth.read().await.as_ref());
+
+        let (states, _) = objects_state.require_regular()?;
+
+        let (project, collection, dataset, object, location_state) =
+            states.into_new_or_existing()?;
+        let project_id = location_state[0].as_ref().map(|(id, _)| *id);
+        let project_storage = self.cache.get_storage_policy(&location_state);
+
+        // Size limits are enforced before anything is initialized
+        let limits = self.upload_limits(project_id.as_ref());
+        let max_size = object_limit(&limits);
+        check_declared(
+            "Object",
+            content_length.unwrap_or_default() as u64,
+            max_size,
+        )?;
+
+        // Missing parents can only be created by the server, fail before any data is written
+        if (matches!(collection, NewOrExistingObject::Missing(_))
+            || matches!(dataset, NewOrExistingObject::Missing(_)))
+            && self.cache.aruna_client.read().await.is_none()
+        {
+            error!("ArunaServer client not available");
+            return Err(ProxyError::NotifierUnavailable(format!(
+                "Unable to create parents of {}/{}: ArunaServer client not available",
+                req.input.bucket, req.input.key
+            ))
+            .into());
+        }
+
+        // Locked objects can not be overwritten, not even by admins
+        let now = chrono::Utc::now();
+        Self::check_object_lock(&object, now)?;
+        self.check_ingest(
+            project_id.as_ref(),
+            &req.input.key,
+            req.input.content_type.as_ref().map(|c| c.to_string()),
+            req.input.metadata.as_ref(),
+            &object,
+        )?;
+        let retention = self.requested_retention(&req.input, &project, now)?;
+        if retention.is_some() && self.cache.aruna_client.read().await.is_none() {
+            error!("ArunaServer client not available");
+            return Err(ProxyError::NotifierUnavailable(format!(
+                "Unable to store retention of {}/{}: ArunaServer client not available",
+                req.input.bucket, req.input.key
+            ))
+            .into());
+        }
+
+        // Quotas are enforced before any data is written
+        if let NewOrExistingObject::Existing(project) = &project {
+            self.check_quota(
+                &[project.id],
+                content_length.unwrap_or_default(),
+                &req.headers,
+                &user_state,
+            )?;
+        }
+
+        // Compare-and-swap style writes, the key stays locked until the new revision is finished
+        let write_preconditions = WritePreconditions::from_headers(&req.headers);
+        let _write_lock = if write_preconditions.is_empty() {
+            None
+        } else {
+            let lock = WriteLock::acquire(
+                &self.conditional_writes,
+                format!("{}/{}", req.input.bucket, req.input.key),
+            )
+            .ok_or_else(|| {
+                error!(error = "Concurrent conditional write");
+                s3_error!(
+                    PreconditionFailed,
+                    "Another conditional write of this key is in progress"
+                )
+            })?;
+            let current_etag = match &object {
+                NewOrExistingObject::Existing(ob) if ob.object_status != Status::Initializing => {
+                    let location = self.cache.get_location(&ob.id).await;
+                    Some(ob.get_etag(location.as_ref()))
+                }
+                _ => None,
+            };
+            write_preconditions.evaluate(current_etag.as_deref())?;
+            Some(lock)
+        };
+
+        let content_md5 = ContentMd5::from_input(req.input.content_md5.as_ref())?;
+        let requested_checksum = RequestedChecksum::from_input(
+            req.input.checksum_algorithm.as_ref(),
+            req.input.checksum_crc32.as_ref(),
+            req.input.checksum_crc32c.as_ref(),
+            req.input.checksum_sha1.as_ref(),
+            req.input.checksum_sha256.as_ref(),
+        )?;
 
-        let (states, _) = objects_state.require_regular()?;
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
 
-        let (_, collection, dataset, object, location_state) = states.into_new_or_existing()?;
+        let deduplicate = CONFIG
+            .frontend
+            .as_ref()
+            .is_some_and(|frontend| frontend.deduplicate_uploads);
+        if let NewOrExistingObject::Existing(existing) = &object {
+            // Identical data with another customer key is not identical on disk
+            if deduplicate && retention.is_none() && customer_key.is_none() {
+                if let Some(resp) = self
+                    .deduplicated_upload(
+                        &req,
+                        existing,
+                        content_length.unwrap_or_default(),
+                        content_md5.as_ref(),
+                        requested_checksum.as_ref(),
+                        &user_state,
+                        impersonating_token.as_ref(),
+                    )
+                    .await?
+                {
+                    return Ok(resp);
+                }
+            }
+        }
 
         let (mut new_object, was_init) = match object {
             NewOrExistingObject::Existing(ob) => {
@@ -1133,25 +3534,24 @@ impl S3 for ArunaS3Service {
                     trace!("Object is initializing");
                     (ob, true)
                 } else {
-                    let mut new_revision = if let Some(handler) =
-                        self.cache.aruna_client.read().await.as_ref()
-                    {
-                        if let Some(token) = &impersonating_token {
-                            handler
-                                .init_object_update(ob, token, true)
-                                .await
-                                .map_err(|_| {
-                                    error!(error = "Object update failed");
-                                    s3_error!(InternalError, "Object update failed")
-                                })?
+                    let mut new_revision =
+                        if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
+                            if let Some(token) = &impersonating_token {
+                                handler
+                                    .init_object_update(ob, token, true)
+                                    .await
+                                    .map_err(trace_err!("init_object_update", req.input.key))?
+                            } else {
+                                error!("missing impersonating token");
+                                return Err(s3_error!(InternalError, "Token creation failed"));
+                            }
                         } else {
-                            error!("missing impersonating token");
-                            return Err(s3_error!(InternalError, "Token creation failed"));
-                        }
-                    } else {
-                        error!("ArunaServer client not available");
-                        return Err(s3_error!(InternalError, "ArunaServer client not available"));
-                    };
+                            error!("ArunaServer client not available");
+                            return Err(ProxyError::NotifierUnavailable(
+                                "ArunaServer client not available".to_string(),
+                            )
+                            .into());
+                        };
                     new_revision.hashes = HashMap::default();
                     new_revision.synced = false;
                     new_revision.children = None;
@@ -1172,12 +3572,28 @@ impl S3 for ArunaS3Service {
 
         let mut location = self
             .backend
-            .initialize_location(&new_object, req.input.content_length, location_state, false)
+            .initialize_location(
+                &new_object,
+                content_length,
+                location_state,
+                project_storage.as_deref(),
+                false,
+            )
             .await
             .map_err(|_| {
                 error!(error = "Unable to create object_location");
                 s3_error!(InternalError, "Unable to create object_location")
             })?;
+        self.apply_storage_class(req.input.storage_class.as_ref(), &mut location)?;
+        if let Some(customer_key) = &customer_key {
+            // Pithos footers would contain the key, only its salted hash is stored
+            location.file_format = FileFormat::customer_encrypted(location.is_compressed());
+            location.customer_key = Some(customer_key.hash());
+        }
+        if empty {
+            // Nothing to encrypt or compress, empty objects have no footer
+            location.file_format = FileFormat::Raw;
+        }
         trace!(?location);
 
         trace!("Initialized data location");
@@ -1191,9 +3607,52 @@ impl S3 for ArunaS3Service {
         let (final_sha_trans, final_sha_recv) =
             HashingTransformer::new_with_backchannel(Sha256::new(), "sha256".to_string());
         let (final_size_trans, final_size_recv) = SizeProbe::new();
+        // SHA256 checksums are derived from the initial sha256 hash
+        let (checksum_trans, checksum_recv) = match &requested_checksum {
+            Some(requested) if requested.algorithm != ContentChecksumAlgorithm::Sha256 => {
+                let (trans, recv) = ChecksumTransformer::new_with_backchannel(requested.algorithm);
+                (Some(trans), Some(recv))
+            }
+            _ => (None, None),
+        };
 
         match req.input.body {
+            _ if empty => {
+                let (tx, rx) = async_channel::bounded(1);
+                drop(tx);
+                self.backend
+                    .put_object(rx, location.clone(), 0)
+                    .await
+                    .map_err(trace_err!("write_object", location.id))?;
+            }
             Some(data) => {
+                let data = self
+                    .decode_request_body(data, &req.headers, &user_state)
+                    .await;
+                // Chunked uploads can exceed the declared length
+                let (data, body_limit) = limit_body(data, max_size);
+                // Already compressed data is stored as is, pithos compresses chunk wise
+                let data = if location.is_compressed() && !location.is_pithos() {
+                    let (head, data) = peek_body(data).await;
+                    let storage = resolve_storage(
+                        project_storage.as_deref(),
+                        new_object.data_class,
+                        &CONFIG.backend.storage_policy(),
+                    );
+                    let content_type = req.input.content_type.as_ref().map(|c| c.to_string());
+                    if !should_compress(
+                        storage.compression_filter.as_ref(),
+                        &req.input.key,
+                        content_type.as_deref(),
+                        &head,
+                    ) {
+                        debug!(key = req.input.key, "Storing upload without compression");
+                        location.file_format = location.file_format.without_compression();
+                    }
+                    data
+                } else {
+                    data
+                };
                 let (tx, rx) = async_channel::bounded(10);
 
                 let mut awr = GenericStreamReadWriter::new_with_sink(
@@ -1207,7 +3666,9 @@ impl S3 for ArunaS3Service {
                         None,
                         false,
                     )
-                    .0,
+                    .0
+                    .with_expected_size(content_length.and_then(|len| u64::try_from(len).ok()))
+                    .with_max_part_size(limits.max_inflight_bytes),
                 );
 
                 awr.add_message_receiver(rx).await.map_err(|_| {
@@ -1218,13 +3679,20 @@ impl S3 for ArunaS3Service {
                 awr = awr.add_transformer(initial_sha_trans);
                 awr = awr.add_transformer(initial_md5_trans);
                 awr = awr.add_transformer(initial_size_trans);
+                if let Some(checksum_trans) = checksum_trans {
+                    awr = awr.add_transformer(checksum_trans);
+                }
 
                 if location.is_compressed() && !location.is_pithos() {
                     trace!("adding zstd decompressor");
                     awr = awr.add_transformer(ZstdEnc::new());
                 }
 
-                if let Some(enc_key) = &location.get_encryption_key() {
+                let enc_key = match &customer_key {
+                    Some(customer_key) => Some(customer_key.key()),
+                    None => location.get_encryption_key(),
+                };
+                if let Some(enc_key) = &enc_key {
                     if !location.is_pithos() {
                         awr = awr.add_transformer(ChaCha20Enc::new_with_fixed(*enc_key).map_err(
                             |_| {
@@ -1240,7 +3708,7 @@ impl S3 for ArunaS3Service {
 
                 if location.is_pithos() {
                     let ctx = new_object
-                        .get_file_context(Some(location.clone()), req.input.content_length)
+                        .get_file_context(Some(location.clone()), content_length)
                         .map_err(|_| {
                             error!(error = "Unable to get file context");
                             s3_error!(InternalError, "Unable to get file context")
@@ -1257,10 +3725,12 @@ impl S3 for ArunaS3Service {
                 awr = awr.add_transformer(final_sha_trans);
                 awr = awr.add_transformer(final_size_trans);
 
-                awr.process().await.map_err(|_| {
-                    error!(error = "Internal data transformer processing error");
-                    s3_error!(InternalError, "Internal data transformer processing error")
-                })?;
+                // The sink aborts its backend multipart upload if the body is rejected
+                let processed = awr.process().await;
+                if processed.is_err() {
+                    body_limit.check()?;
+                }
+                processed.map_err(trace_err!("write_object", location.id))?;
             }
             None => {
                 error!("Empty body is not allowed");
@@ -1268,72 +3738,94 @@ impl S3 for ArunaS3Service {
             }
         }
 
-        let mut collection_id = None;
-        if let NewOrExistingObject::Missing(collection) = collection {
-            if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
-                if let Some(token) = &impersonating_token {
-                    let col = handler
-                        .create_collection(collection, token)
-                        .await
-                        .map_err(|_| {
-                            error!(error = "Unable to create collection");
-                            s3_error!(InternalError, "Unable to create collection")
-                        })?;
-                    collection_id = Some(col.id)
-                }
-            }
+        if let Some(parent) = self
+            .create_missing_parents(collection, dataset, impersonating_token.as_ref())
+            .await?
+        {
+            new_object.parents = Some(HashSet::from_iter([parent]));
         }
 
-        let mut dataset_id = None;
-        if let NewOrExistingObject::Missing(mut dataset) = dataset {
-            if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
-                if let Some(token) = &impersonating_token {
-                    if let Some(collection_id) = collection_id {
-                        dataset.parents = Some(HashSet::from_iter([TypedRelation::Collection(
-                            collection_id,
-                        )]));
-                    }
-                    let dataset = handler.create_dataset(dataset, token).await.map_err(|_| {
-                        error!(error = "Unable to create dataset");
-                        s3_error!(InternalError, "Unable to create dataset")
-                    })?;
-                    dataset_id = Some(dataset.id);
+        // Fetch calculated hashes
+        trace!("fetching hashes");
+        // Empty uploads are not passed through the transformers
+        let (md5_initial, sha_initial, sha_final, initial_size, final_size): (
+            String,
+            String,
+            String,
+            u64,
+            u64,
+        ) = if empty {
+            (
+                EMPTY_MD5.to_string(),
+                EMPTY_SHA256.to_string(),
+                EMPTY_SHA256.to_string(),
+                0,
+                0,
+            )
+        } else {
+            (
+                initial_md5_recv.try_recv().map_err(|_| {
+                    error!(error = "Unable to md5 hash initial data");
+                    s3_error!(InternalError, "Unable to md5 hash initial data")
+                })?,
+                initial_sha_recv.try_recv().map_err(|_| {
+                    error!(error = "Unable to sha hash initial data");
+                    s3_error!(InternalError, "Unable to sha hash initial data")
+                })?,
+                final_sha_recv.try_recv().map_err(|_| {
+                    error!(error = "Unable to sha hash final data");
+                    s3_error!(InternalError, "Unable to sha hash final data")
+                })?,
+                initial_size_recv.try_recv().map_err(|_| {
+                    error!(error = "Unable to get size");
+                    s3_error!(InternalError, "Unable to get size")
+                })?,
+                final_size_recv.try_recv().map_err(|_| {
+                    error!(error = "Unable to get size");
+                    s3_error!(InternalError, "Unable to get size")
+                })?,
+            )
+        };
+        let md5_initial = Some(md5_initial);
+        let sha_initial = Some(sha_initial);
+        if let Some(content_md5) = &content_md5 {
+            if let Err(err) = content_md5.verify(md5_initial.as_deref().unwrap_or_default()) {
+                // The data was already written, remove it again
+                if let Err(e) = self.backend.delete_object(location.clone()).await {
+                    error!(error = ?e, msg = "Unable to delete rejected upload");
                 }
+                return Err(err);
             }
         }
 
-        if let Some(dataset_id) = dataset_id {
-            new_object.parents = Some(HashSet::from_iter([TypedRelation::Dataset(dataset_id)]));
-        } else {
-            if let Some(collection_id) = collection_id {
-                new_object.parents = Some(HashSet::from_iter([TypedRelation::Collection(
-                    collection_id,
-                )]));
+        let checksum = match &requested_checksum {
+            Some(requested) => {
+                let calculated = match &checksum_recv {
+                    Some(_) if empty => empty_checksum(requested.algorithm),
+                    Some(recv) => recv.try_recv().map_err(|_| {
+                        error!(error = "Unable to calculate checksum");
+                        s3_error!(InternalError, "Unable to calculate checksum")
+                    })?,
+                    None => hex_to_checksum(sha_initial.as_deref().unwrap_or_default()).map_err(
+                        |e| {
+                            error!(error = ?e, msg = e.to_string());
+                            s3_error!(InternalError, "Unable to calculate checksum")
+                        },
+                    )?,
+                };
+                match requested.verify(&calculated) {
+                    Ok(checksum) => Some(checksum),
+                    Err(err) => {
+                        // The data was already written, remove it again
+                        if let Err(e) = self.backend.delete_object(location.clone()).await {
+                            error!(error = ?e, msg = "Unable to delete rejected upload");
+                        }
+                        return Err(err);
+                    }
+                }
             }
-        }
-
-        // Fetch calculated hashes
-        trace!("fetching hashes");
-        let md5_initial = Some(initial_md5_recv.try_recv().map_err(|_| {
-            error!(error = "Unable to md5 hash initial data");
-            s3_error!(InternalError, "Unable to md5 hash initial data")
-        })?);
-        let sha_initial = Some(initial_sha_recv.try_recv().map_err(|_| {
-            error!(error = "Unable to sha hash initial data");
-            s3_error!(InternalError, "Unable to sha hash initial data")
-        })?);
-        let sha_final: String = final_sha_recv.try_recv().map_err(|_| {
-            error!(error = "Unable to sha hash final data");
-            s3_error!(InternalError, "Unable to sha hash final data")
-        })?;
-        let initial_size: u64 = initial_size_recv.try_recv().map_err(|_| {
-            error!(error = "Unable to get size");
-            s3_error!(InternalError, "Unable to get size")
-        })?;
-        let final_size: u64 = final_size_recv.try_recv().map_err(|_| {
-            error!(error = "Unable to get size");
-            s3_error!(InternalError, "Unable to get size")
-        })?;
+            None => None,
+        };
 
         new_object.hashes = vec![
             (
@@ -1354,71 +3846,330 @@ impl S3 for ArunaS3Service {
         .into_iter()
         .collect::<HashMap<String, String>>();
 
-        let hashes = vec![
-            Hash {
-                alg: Hashalgorithm::Sha256.into(),
-                hash: sha_initial.clone().ok_or_else(|| {
-                    error!(error = "Unable to get sha hash initial data");
-                    s3_error!(InternalError, "Unable to get sha hash initial data")
-                })?,
-            },
-            Hash {
-                alg: Hashalgorithm::Md5.into(),
-                hash: md5_initial.clone().ok_or_else(|| {
-                    error!(error = "Unable to get md5 hash initial data");
-                    s3_error!(InternalError, "Unable to get md5 hash initial data")
-                })?,
-            },
-        ];
-
         location.raw_content_len = initial_size as i64;
         location.disk_content_len = final_size as i64;
         location.disk_hash = Some(sha_final.clone());
+        location.content_type = req.input.content_type.as_ref().map(|c| c.to_string());
+        location.user_metadata = req.input.metadata.clone().unwrap_or_default();
+        // Missing hashes are omitted, not stored (and returned as metadata) empty
+        location.raw_hashes = [
+            ("sha256".to_string(), sha_initial.clone()),
+            ("md5".to_string(), md5_initial.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(name, hash)| Some((name, hash?)))
+        .collect();
+        location.etag = md5_initial.clone();
+        location.checksum = checksum.clone();
 
         trace!("finishing object");
+        let mut location_bound = false;
         if let Some(handler) = self.cache.aruna_client.read().await.as_ref() {
             if let Some(token) = &impersonating_token {
-                if was_init {
-                    new_object = handler
-                        .finish_object(
-                            new_object.id,
-                            location.raw_content_len,
-                            hashes.clone(),
+                let mut queued = false;
+                if !was_init {
+                    // Kept with the local object in case its creation is queued
+                    if let Some(retention) = &retention {
+                        new_object.key_values.push(retention.to_label()?);
+                    }
+                    match handler
+                        .create_object_or_queue(
+                            new_object.clone(),
+                            location.clone(),
+                            &user_state,
                             token,
                         )
                         .await
-                        .map_err(|_| {
-                            error!(error = "Unable to finish object");
-                            s3_error!(InternalError, "Unable to finish object")
-                        })?;
-                } else {
-                    new_object = handler
-                        .create_and_finish(new_object.clone(), location.raw_content_len, token)
+                    {
+                        Ok(Some(created)) => new_object = created,
+                        // The data is stored, the object is created in the background
+                        Ok(None) => {
+                            queued = true;
+                            location_bound = true;
+                        }
+                        Err(e) => {
+                            return Err(
+                                match ProxyError::traced(
+                                    e,
+                                    "create_object",
+                                    Some(req.input.key.clone()),
+                                ) {
+                                    // Rejected by the server, e.g. invalid names
+                                    ProxyError::Internal(msg) => {
+                                        s3_error!(InvalidObjectState, "{}", msg)
+                                    }
+                                    other => other.into(),
+                                },
+                            );
+                        }
+                    }
+                }
+                if !queued {
+                    // The retention is stored before the object is finished, the staging
+                    // object is updated in place
+                    if let Some(retention) = &retention {
+                        let hashes = new_object.hashes.clone();
+                        new_object = handler
+                            .add_or_replace_key_value_object(
+                                token,
+                                new_object,
+                                RETENTION_KEY,
+                                &retention.to_json()?,
+                            )
+                            .await
+                            .map_err(trace_err!("store_retention", req.input.key))?;
+                        new_object.hashes = hashes;
+                    }
+                    // The data is bound before the server is notified, until then the
+                    // object is reported as staging instead of missing
+                    self.cache
+                        .add_location_with_binding(new_object.id, location.clone())
                         .await
                         .map_err(|e| {
-                            error!(error = ?e, "Unable to create and finish object");
-                            s3_error!(InvalidObjectState, "{}", e.to_string())
+                            error!(error = ?e, msg = "Unable to add location with binding");
+                            s3_error!(InternalError, "Unable to add location with binding")
                         })?;
+                    location_bound = true;
+                    new_object = handler
+                        .finish_object_or_queue(
+                            new_object,
+                            location.raw_content_len,
+                            &user_state,
+                            token,
+                        )
+                        .await
+                        .map_err(trace_err!("finish_object", req.input.key))?;
                 }
             }
         }
 
-        self.cache
-            .add_location_with_binding(new_object.id, location)
-            .await
-            .map_err(|e| {
-                error!(error = ?e, msg = "Unable to add location with binding");
-                s3_error!(InternalError, "Unable to add location with binding")
-            })?;
+        let event = webhooks::object_event(
+            "ObjectCreated:Put",
+            &req.input.bucket,
+            &req.input.key,
+            &new_object,
+            Some(&location),
+        );
+        if !location_bound {
+            self.cache
+                .add_location_with_binding(new_object.id, location)
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, msg = "Unable to add location with binding");
+                    s3_error!(InternalError, "Unable to add location with binding")
+                })?;
+        }
 
+        let (checksum_crc32, checksum_crc32c, checksum_sha1, checksum_sha256) = checksum
+            .map(ContentChecksum::into_fields)
+            .unwrap_or_default();
         let output = PutObjectOutput {
-            e_tag: md5_initial,
-            checksum_sha256: sha_initial,
+            e_tag: md5_initial.map(|md5| format!("\"{md5}\"")),
+            checksum_crc32,
+            checksum_crc32c,
+            checksum_sha1,
+            checksum_sha256,
             version_id: Some(new_object.id.to_string()),
             ..Default::default()
         };
+        if let Some(project_id) = project_id {
+            webhooks::notify(&self.cache, project_id, event).await;
+        }
         debug!(?output);
-        Ok(S3Response::new(output))
+        let mut resp = S3Response::new(output);
+        if let Some(customer_key) = &customer_key {
+            customer_key.add_to_headers(&mut resp.headers);
+        }
+        Ok(resp)
+    }
+
+    #[tracing::instrument(err)]
+    async fn restore_object(
+        &self,
+        req: S3Request<RestoreObjectInput>,
+    ) -> S3Result<S3Response<RestoreObjectOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(UnexpectedContent, "Missing data context")
+            })?;
+
+        let (object, location) = match &req.input.version_id {
+            Some(version_id) => {
+                let (latest, _) = objects_state.extract_object()?;
+                self.get_revision(&latest, version_id).await?
+            }
+            None => objects_state.extract_object()?,
+        };
+        let mut location =
+            location.ok_or_else(|| object.missing_location(&CONFIG.proxy.endpoint_id))?;
+
+        if !location
+            .storage_class
+            .as_deref()
+            .map(|class| ARCHIVE_STORAGE_CLASSES.contains(&class))
+            .unwrap_or(false)
+        {
+            error!(object_id = ?object.id, "Object is not archived");
+            return Err(s3_error!(
+                InvalidObjectState,
+                "Restore is not allowed for the object's storage class"
+            ));
+        }
+        if location
+            .restore
+            .as_ref()
+            .map(|r| r.ongoing)
+            .unwrap_or(false)
+        {
+            error!(object_id = ?object.id, "Restore already in progress");
+            return Err(s3_error!(
+                RestoreAlreadyInProgress,
+                "Object restore is already in progress"
+            ));
+        }
+        let days = req
+            .input
+            .restore_request
+            .as_ref()
+            .and_then(|request| request.days)
+            .filter(|days| *days > 0)
+            .ok_or_else(|| {
+                error!(error = "Missing restore days");
+                s3_error!(
+                    InvalidArgument,
+                    "Restore request requires a positive number of days"
+                )
+            })?;
+
+        self.backend
+            .restore_object(&location, days)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                s3_error!(InternalError, "Unable to restore object")
+            })?;
+        // Updated by the restore poller until the restored copy is available
+        location.restore = Some(
+            self.backend
+                .restore_status(&location)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or(RestoreStatus {
+                    ongoing: true,
+                    expiry: None,
+                }),
+        );
+        self.cache
+            .update_location(object.id, location)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, msg = "Unable to update location");
+                s3_error!(InternalError, "Unable to update location")
+            })?;
+
+        Ok(S3Response::new(RestoreObjectOutput::default()))
+    }
+
+    #[tracing::instrument(err)]
+    async fn select_object_content(
+        &self,
+        req: S3Request<SelectObjectContentInput>,
+    ) -> S3Result<S3Response<SelectObjectContentOutput>> {
+        let CheckAccessResult { objects_state, .. } = req
+            .extensions
+            .get::<CheckAccessResult>()
+            .cloned()
+            .ok_or_else(|| {
+                error!(error = "Missing data context");
+                s3_error!(InternalError, "No context found")
+            })?;
+
+        let (_, _, location) = match objects_state {
+            ObjectsState::Objects { .. } | ObjectsState::Bundle { .. } => {
+                error!(error = "Select on bundle");
+                return Err(s3_error!(
+                    NotImplemented,
+                    "SelectObjectContent is not supported for bundles"
+                ));
+            }
+            objects_state => objects_state.require_data(&CONFIG.proxy.endpoint_id)?,
+        };
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        let location = unlock_location(&location, customer_key.as_ref())?;
+
+        // The query is validated before the backend is touched
+        let mut executor = SelectExecutor::new(&req.input.request).map_err(|e| {
+            error!(error = ?e, msg = "Unsupported select request");
+            S3Error::from(e)
+        })?;
+
+        // Select runs on the plaintext
+        let mut data = self.plaintext(&location).await?;
+
+        let (event_send, event_rcv) = async_channel::bounded(10);
+        crate::spawn(
+            async move {
+                let result = async {
+                    while let Some(chunk) = data.try_next().await.map_err(|e| {
+                        error!(error = ?e, msg = "Unable to read object data");
+                        s3_error!(InternalError, "Unable to read object data")
+                    })? {
+                        let records = executor.process(&chunk)?;
+                        if !records.is_empty() {
+                            event_send
+                                .send(Ok(SelectObjectContentEvent::Records(RecordsEvent {
+                                    payload: Some(records.into()),
+                                })))
+                                .await
+                                .map_err(|_| s3_error!(InternalError, "Select stream closed"))?;
+                        }
+                        if executor.is_done() {
+                            break;
+                        }
+                    }
+                    let records = executor.finish()?;
+                    if !records.is_empty() {
+                        event_send
+                            .send(Ok(SelectObjectContentEvent::Records(RecordsEvent {
+                                payload: Some(records.into()),
+                            })))
+                            .await
+                            .map_err(|_| s3_error!(InternalError, "Select stream closed"))?;
+                    }
+                    let stats = Stats {
+                        bytes_processed: Some(executor.bytes_scanned as i64),
+                        bytes_returned: Some(executor.bytes_returned as i64),
+                        bytes_scanned: Some(executor.bytes_scanned as i64),
+                    };
+                    event_send
+                        .send(Ok(SelectObjectContentEvent::Stats(StatsEvent {
+                            details: Some(stats),
+                        })))
+                        .await
+                        .map_err(|_| s3_error!(InternalError, "Select stream closed"))?;
+                    event_send
+                        .send(Ok(SelectObjectContentEvent::End(EndEvent::default())))
+                        .await
+                        .map_err(|_| s3_error!(InternalError, "Select stream closed"))?;
+                    Ok::<_, S3Error>(())
+                }
+                .await;
+                if let Err(e) = result {
+                    error!(error = ?e, msg = "Select failed");
+                    let _ = event_send.send(Err(e)).await;
+                }
+            }
+            .instrument(info_span!("select_object_content")),
+        );
+
+        Ok(S3Response::new(SelectObjectContentOutput {
+            payload: Some(SelectObjectContentEventStream::new(event_rcv)),
+        }))
     }
 
     #[tracing::instrument(err)]
@@ -1426,7 +4177,7 @@ impl S3 for ArunaS3Service {
         &self,
         req: S3Request<UploadPartInput>,
     ) -> S3Result<S3Response<UploadPartOutput>> {
-        match req.input.content_length {
+        let declared = match payload_content_length(&req.headers, req.input.content_length) {
             Some(0) | None => {
                 error!("Missing or invalid (0) content-length");
                 return Err(s3_error!(
@@ -1439,10 +4190,15 @@ impl S3 for ArunaS3Service {
                     error!("Content-Length exceeds 5GB");
                     return Err(s3_error!(EntityTooLarge, "Content-Length larger than 5Gib"));
                 }
+                bytes as u64
             }
         };
 
-        let CheckAccessResult { objects_state, .. } = req
+        let CheckAccessResult {
+            objects_state,
+            user_state,
+            ..
+        } = req
             .extensions
             .get::<CheckAccessResult>()
             .cloned()
@@ -1454,6 +4210,7 @@ impl S3 for ArunaS3Service {
         // If the object exists and the signatures match -> Skip the download
 
         let (object, location) = objects_state.require_regular()?;
+        let project_id = object.get_project().map(|p| p.id);
         let object = object.require_object()?;
 
         let location = location.ok_or_else(|| {
@@ -1461,7 +4218,39 @@ impl S3 for ArunaS3Service {
             s3_error!(NoSuchKey, "Object not found")
         })?;
 
-        let etag = match req.input.body {
+        // Size limits are enforced before the part is buffered
+        let limits = self.upload_limits(project_id.as_ref());
+        let max_part = part_limit(&limits);
+        check_declared("Part", declared, max_part)?;
+        if limits.max_multipart_size > 0 {
+            // Re-uploaded parts are stored multiple times, the latest one counts
+            let uploaded = self
+                .cache
+                .get_parts(location.upload_id.as_deref().unwrap_or_default())
+                .into_iter()
+                .filter(|part| part.part_number != req.input.part_number as u64)
+                .map(|part| (part.part_number, part.raw_size))
+                .collect::<HashMap<_, _>>();
+            check_declared(
+                "Multipart upload",
+                uploaded.values().sum::<u64>() + declared,
+                limits.max_multipart_size,
+            )?;
+        }
+
+        let content_md5 = ContentMd5::from_input(req.input.content_md5.as_ref())?;
+        let requested_checksum = RequestedChecksum::from_input(
+            req.input.checksum_algorithm.as_ref(),
+            req.input.checksum_crc32.as_ref(),
+            req.input.checksum_crc32c.as_ref(),
+            req.input.checksum_sha1.as_ref(),
+            req.input.checksum_sha256.as_ref(),
+        )?;
+        // Parts of customer encrypted uploads need the key of CreateMultipartUpload
+        let customer_key = CustomerKey::from_headers(&req.headers)?;
+        let enc_key = unlock_location(&location, customer_key.as_ref())?.get_encryption_key();
+
+        let (etag, checksum) = match req.input.body {
             Some(data) => {
                 trace!("streaming data to backend");
 
@@ -1475,14 +4264,31 @@ impl S3 for ArunaS3Service {
                     true,
                 );
 
+                let data = self
+                    .decode_request_body(data, &req.headers, &user_state)
+                    .await;
+                let (data, body_limit) = limit_body(data, max_part);
                 let mut awr = GenericStreamReadWriter::new_with_sink(data, sink);
 
                 let (before_probe, before_receiver) = SizeProbe::new();
                 awr = awr.add_transformer(before_probe);
 
+                let (md5_trans, md5_recv) =
+                    HashingTransformer::new_with_backchannel(Md5::new(), "md5".to_string());
+                awr = awr.add_transformer(md5_trans);
+
+                let checksum_recv = if let Some(requested) = &requested_checksum {
+                    let (checksum_trans, checksum_recv) =
+                        ChecksumTransformer::new_with_backchannel(requested.algorithm);
+                    awr = awr.add_transformer(checksum_trans);
+                    Some(checksum_recv)
+                } else {
+                    None
+                };
+
                 let (after_probe, after_receiver) = SizeProbe::new();
 
-                if let Some(enc_key) = &location.get_encryption_key() {
+                if let Some(enc_key) = &enc_key {
                     trace!("adding chacha20 encryption");
                     awr = awr.add_transformer(ChaCha20Enc::new_with_fixed(*enc_key).map_err(
                         |_| {
@@ -1494,7 +4300,12 @@ impl S3 for ArunaS3Service {
 
                 awr = awr.add_transformer(after_probe);
 
-                awr.process().await.map_err(|_| {
+                // Nothing is uploaded before the whole part is buffered
+                let processed = awr.process().await;
+                if processed.is_err() {
+                    body_limit.check()?;
+                }
+                processed.map_err(|_| {
                     error!(error = "Internal data transformer processing error");
                     s3_error!(InternalError, "Internal data transformer processing error")
                 })?;
@@ -1509,6 +4320,35 @@ impl S3 for ArunaS3Service {
                     s3_error!(InternalError, "Unable to get size")
                 })?;
 
+                let md5 = md5_recv.try_recv().map_err(|_| {
+                    error!(error = "Unable to md5 hash part data");
+                    s3_error!(InternalError, "Unable to md5 hash part data")
+                })?;
+
+                // Rejected parts are not registered and overwritten by the next upload
+                if let Some(content_md5) = &content_md5 {
+                    content_md5.verify(&md5)?;
+                }
+                let checksum = match (&requested_checksum, checksum_recv) {
+                    (Some(requested), Some(recv)) => {
+                        Some(requested.verify(&recv.try_recv().map_err(|_| {
+                            error!(error = "Unable to calculate checksum");
+                            s3_error!(InternalError, "Unable to calculate checksum")
+                        })?)?)
+                    }
+                    _ => None,
+                };
+
+                let etag = if let Some(r) = receiver {
+                    r.recv().await.map_err(|_| {
+                        error!(error = "Unable to query etag");
+                        s3_error!(InternalError, "Unable to query etag")
+                    })?
+                } else {
+                    error!("receiver is none");
+                    return Err(s3_error!(InternalError, "receiver is none"));
+                };
+
                 self.cache
                     .create_multipart_upload(
                         location.upload_id.ok_or_else(|| {
@@ -1519,6 +4359,9 @@ impl S3 for ArunaS3Service {
                         req.input.part_number as u64,
                         before_size,
                         after_size,
+                        format!("-{}", etag),
+                        md5.clone(),
+                        checksum.clone(),
                     )
                     .await
                     .map_err(|_| {
@@ -1526,15 +4369,7 @@ impl S3 for ArunaS3Service {
                         s3_error!(InternalError, "Unable to create multipart upload")
                     })?;
 
-                if let Some(r) = receiver {
-                    r.recv().await.map_err(|_| {
-                        error!(error = "Unable to query etag");
-                        s3_error!(InternalError, "Unable to query etag")
-                    })?
-                } else {
-                    error!("receiver is none");
-                    return Err(s3_error!(InternalError, "receiver is none"));
-                }
+                (md5, checksum)
             }
             None => {
                 error!("empty body is not allowed");
@@ -1542,11 +4377,22 @@ impl S3 for ArunaS3Service {
             }
         };
 
+        let (checksum_crc32, checksum_crc32c, checksum_sha1, checksum_sha256) = checksum
+            .map(ContentChecksum::into_fields)
+            .unwrap_or_default();
         let output = UploadPartOutput {
-            e_tag: Some(format!("-{}", etag)),
+            e_tag: Some(format!("\"{etag}\"")),
+            checksum_crc32,
+            checksum_crc32c,
+            checksum_sha1,
+            checksum_sha256,
             ..Default::default()
         };
         debug!(?output);
-        Ok(S3Response::new(output))
+        let mut resp = S3Response::new(output);
+        if let Some(customer_key) = &customer_key {
+            customer_key.add_to_headers(&mut resp.headers);
+        }
+        Ok(resp)
     }
 }
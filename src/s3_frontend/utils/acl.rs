@@ -0,0 +1,260 @@
+use crate::structs::Object;
+use aruna_rust_api::api::storage::models::v2::DataClass;
+use s3s::dto::{
+    AccessControlPolicy, Grant, Grantee, Grants, ObjectCannedACL, Owner, Permission,
+    PutObjectAclInput, Type,
+};
+use s3s::{s3_error, S3Error};
+use tracing::error;
+
+/// Group of all (anonymous) users
+pub const ALL_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+
+/// The canned ACLs that can be expressed with Aruna data classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CannedAcl {
+    Private,
+    PublicRead,
+}
+
+impl CannedAcl {
+    /// Only public resources can be read anonymously, all other data classes are private
+    pub fn from_data_class(data_class: DataClass) -> Self {
+        match data_class {
+            DataClass::Public => CannedAcl::PublicRead,
+            _ => CannedAcl::Private,
+        }
+    }
+
+    /// Data class after applying the ACL, non public data classes are kept for `private`
+    pub fn apply(&self, data_class: DataClass) -> DataClass {
+        match (self, data_class) {
+            (CannedAcl::PublicRead, _) => DataClass::Public,
+            (CannedAcl::Private, DataClass::Public) => DataClass::Private,
+            (CannedAcl::Private, data_class) => data_class,
+        }
+    }
+
+    pub fn grants(&self, owner: &Owner) -> Grants {
+        let mut grants = vec![Grant {
+            grantee: Some(Grantee {
+                display_name: owner.display_name.clone(),
+                email_address: None,
+                id: owner.id.clone(),
+                type_: Type::from_static(Type::CANONICAL_USER),
+                uri: None,
+            }),
+            permission: Some(Permission::from_static(Permission::FULL_CONTROL)),
+        }];
+        if *self == CannedAcl::PublicRead {
+            grants.push(Grant {
+                grantee: Some(Grantee {
+                    display_name: None,
+                    email_address: None,
+                    id: None,
+                    type_: Type::from_static(Type::GROUP),
+                    uri: Some(ALL_USERS_URI.to_string()),
+                }),
+                permission: Some(Permission::from_static(Permission::READ)),
+            });
+        }
+        grants
+    }
+
+    /// Parses a PutObjectAcl request, grants are only accepted if they are equivalent
+    /// to one of the supported canned ACLs
+    pub fn from_request(input: &PutObjectAclInput, owner: &Owner) -> Result<Self, S3Error> {
+        let grant_headers = [
+            &input.grant_full_control,
+            &input.grant_read,
+            &input.grant_read_acp,
+            &input.grant_write,
+            &input.grant_write_acp,
+        ]
+        .iter()
+        .any(|header| header.is_some());
+        Self::from_parts(
+            input.acl.as_ref(),
+            input.access_control_policy.as_ref(),
+            grant_headers,
+            owner,
+        )
+    }
+
+    fn from_parts(
+        acl: Option<&ObjectCannedACL>,
+        policy: Option<&AccessControlPolicy>,
+        grant_headers: bool,
+        owner: &Owner,
+    ) -> Result<Self, S3Error> {
+        if grant_headers {
+            error!("Grant headers are not supported");
+            return Err(s3_error!(
+                InvalidRequest,
+                "Grant headers are not supported, use the canned ACLs private or public-read"
+            ));
+        }
+        match (acl, policy) {
+            (Some(acl), None) => match acl.as_str() {
+                ObjectCannedACL::PRIVATE => Ok(CannedAcl::Private),
+                ObjectCannedACL::PUBLIC_READ => Ok(CannedAcl::PublicRead),
+                other => {
+                    error!(acl = other, "Unsupported canned ACL");
+                    Err(s3_error!(
+                        InvalidRequest,
+                        "Unsupported canned ACL {other}, only private and public-read are supported"
+                    ))
+                }
+            },
+            (None, Some(policy)) => Self::from_policy(policy, owner),
+            (Some(_), Some(_)) => {
+                error!("Canned ACL and access control policy");
+                Err(s3_error!(
+                    InvalidRequest,
+                    "Specify either a canned ACL or an access control policy"
+                ))
+            }
+            (None, None) => {
+                error!("Missing ACL");
+                Err(s3_error!(InvalidRequest, "Missing canned ACL"))
+            }
+        }
+    }
+
+    fn from_policy(policy: &AccessControlPolicy, owner: &Owner) -> Result<Self, S3Error> {
+        let mut acl = CannedAcl::Private;
+        let mut owner_granted = false;
+        for grant in policy.grants.iter().flatten() {
+            let (Some(grantee), Some(permission)) = (&grant.grantee, &grant.permission) else {
+                error!(?grant, "Incomplete grant");
+                return Err(s3_error!(
+                    InvalidRequest,
+                    "Grants require a grantee and permission"
+                ));
+            };
+            match (grantee.type_.as_str(), permission.as_str()) {
+                (Type::CANONICAL_USER, Permission::FULL_CONTROL) if grantee.id == owner.id => {
+                    owner_granted = true
+                }
+                (Type::GROUP, Permission::READ)
+                    if grantee.uri.as_deref() == Some(ALL_USERS_URI) =>
+                {
+                    acl = CannedAcl::PublicRead
+                }
+                _ => {
+                    error!(?grant, "Unsupported grant");
+                    return Err(s3_error!(
+                        InvalidRequest,
+                        "Only the grants of the canned ACLs private and public-read are supported"
+                    ));
+                }
+            }
+        }
+        if !owner_granted {
+            error!("Owner grant missing");
+            return Err(s3_error!(
+                InvalidRequest,
+                "The owner must keep FULL_CONTROL of the object"
+            ));
+        }
+        Ok(acl)
+    }
+}
+
+/// The owner of all resources in a project
+pub fn project_owner(project: &Object) -> Owner {
+    Owner {
+        display_name: Some(project.name.clone()),
+        id: Some(project.created_by.unwrap_or(project.id).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use s3s::S3ErrorCode;
+
+    fn owner() -> Owner {
+        Owner {
+            display_name: Some("project".to_string()),
+            id: Some("owner".to_string()),
+        }
+    }
+
+    fn parse(acl: Option<&str>, policy: Option<AccessControlPolicy>) -> Result<CannedAcl, S3Error> {
+        let acl = acl.map(|acl| ObjectCannedACL::from(acl.to_string()));
+        CannedAcl::from_parts(acl.as_ref(), policy.as_ref(), false, &owner())
+    }
+
+    #[test]
+    fn test_data_class() {
+        assert_eq!(
+            CannedAcl::from_data_class(DataClass::Public),
+            CannedAcl::PublicRead
+        );
+        assert_eq!(
+            CannedAcl::from_data_class(DataClass::Confidential),
+            CannedAcl::Private
+        );
+        assert_eq!(
+            CannedAcl::Private.apply(DataClass::Public),
+            DataClass::Private
+        );
+        assert_eq!(
+            CannedAcl::Private.apply(DataClass::Workspace),
+            DataClass::Workspace
+        );
+        assert_eq!(
+            CannedAcl::PublicRead.apply(DataClass::Private),
+            DataClass::Public
+        );
+    }
+
+    #[test]
+    fn test_from_request() {
+        let owner = owner();
+        assert_eq!(
+            parse(Some("public-read"), None).unwrap(),
+            CannedAcl::PublicRead
+        );
+        assert_eq!(parse(Some("private"), None).unwrap(), CannedAcl::Private);
+        assert!(parse(Some("public-read-write"), None).is_err());
+        assert!(parse(None, None).is_err());
+        let private = ObjectCannedACL::from_static(ObjectCannedACL::PRIVATE);
+        let err = CannedAcl::from_parts(Some(&private), None, true, &owner).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidRequest);
+
+        // Policies returned by GetObjectAcl are accepted
+        for acl in [CannedAcl::Private, CannedAcl::PublicRead] {
+            let policy = AccessControlPolicy {
+                grants: Some(acl.grants(&owner)),
+                owner: Some(owner.clone()),
+            };
+            assert_eq!(parse(None, Some(policy)).unwrap(), acl);
+        }
+        // Other grantees and permissions are rejected
+        let mut grants = CannedAcl::Private.grants(&owner);
+        grants.push(Grant {
+            grantee: Some(Grantee {
+                display_name: None,
+                email_address: Some("someone@example.com".to_string()),
+                id: None,
+                type_: Type::from_static(Type::AMAZON_CUSTOMER_BY_EMAIL),
+                uri: None,
+            }),
+            permission: Some(Permission::from_static(Permission::WRITE)),
+        });
+        let policy = AccessControlPolicy {
+            grants: Some(grants),
+            owner: Some(owner.clone()),
+        };
+        let err = parse(None, Some(policy)).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidRequest);
+        // The owner can not be removed
+        let policy = AccessControlPolicy {
+            grants: Some(vec![]),
+            owner: Some(owner.clone()),
+        };
+        assert!(parse(None, Some(policy)).is_err());
+    }
+}
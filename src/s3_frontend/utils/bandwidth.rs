@@ -0,0 +1,272 @@
+use crate::config::BandwidthLimits;
+use crate::metrics::{DOWNLOAD_BYTES, DOWNLOAD_THROTTLED};
+use crate::structs::BandwidthPolicy;
+use bytes::Bytes;
+use dashmap::DashMap;
+use diesel_ulid::DieselUlid;
+use futures::{stream, StreamExt, TryStreamExt};
+use s3s::dto::StreamingBlob;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Buckets that were not used for this long are full again and can be dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Chunks are throttled in slices of this size to keep the transfer rate smooth
+const SLICE_SIZE: usize = 64 * 1024;
+
+/// Byte budget that refills with the configured rate
+///
+/// Senders may overdraw the budget and wait until the debt is refilled, concurrent
+/// downloads sharing a bucket queue up behind each other this way.
+#[derive(Debug)]
+struct ByteBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl ByteBucket {
+    fn new(burst: u64, now: Instant) -> Self {
+        ByteBucket {
+            tokens: burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Takes `bytes`, returns how long the sender has to wait before sending them
+    fn take(&mut self, bytes: u64, rate: u64, burst: u64, now: Instant) -> Duration {
+        let rate = rate as f64;
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.last_refill = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+/// Limits of a single download
+#[derive(Debug, Clone)]
+struct Throttle {
+    access_key: Option<String>,
+    project_id: Option<DieselUlid>,
+    limits: BandwidthLimits,
+}
+
+/// Download bandwidth limiter per access key and per project
+///
+/// Anonymous downloads are only limited by the project limit.
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    limits: BandwidthLimits,
+    keys: DashMap<String, ByteBucket>,
+    projects: DashMap<DieselUlid, ByteBucket>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self {
+            limits,
+            keys: DashMap::new(),
+            projects: DashMap::new(),
+        }
+    }
+
+    /// Limits of the proxy config with the overrides of the project
+    fn limits(&self, policy: Option<&BandwidthPolicy>) -> BandwidthLimits {
+        match policy {
+            Some(policy) => policy.apply(self.limits),
+            None => self.limits,
+        }
+    }
+
+    /// Time to wait before `bytes` can be sent, the longer wait of both buckets
+    fn wait(&self, throttle: &Throttle, bytes: u64, now: Instant) -> Duration {
+        let limits = &throttle.limits;
+        let burst = limits.burst_bytes.max(1);
+        let mut wait = Duration::ZERO;
+        if let (Some(access_key), rate @ 1..) =
+            (&throttle.access_key, limits.per_key_bytes_per_second)
+        {
+            let mut bucket = self
+                .keys
+                .entry(access_key.clone())
+                .or_insert_with(|| ByteBucket::new(burst, now));
+            wait = wait.max(bucket.take(bytes, rate, burst, now));
+        }
+        if let (Some(project_id), rate @ 1..) =
+            (throttle.project_id, limits.per_project_bytes_per_second)
+        {
+            let mut bucket = self
+                .projects
+                .entry(project_id)
+                .or_insert_with(|| ByteBucket::new(burst, now));
+            wait = wait.max(bucket.take(bytes, rate, burst, now));
+        }
+        wait
+    }
+
+    /// Delays the chunks of a response body according to the limits of the access key
+    /// and project, bodies without an active limit are returned as is
+    pub fn throttle(
+        self: &Arc<Self>,
+        body: StreamingBlob,
+        access_key: Option<String>,
+        project: Option<(DieselUlid, Option<Arc<BandwidthPolicy>>)>,
+    ) -> StreamingBlob {
+        let (project_id, policy) = match project {
+            Some((project_id, policy)) => (Some(project_id), policy),
+            None => (None, None),
+        };
+        let limits = self.limits(policy.as_deref());
+        let key_limited = access_key.is_some() && limits.per_key_bytes_per_second > 0;
+        let project_limited = project_id.is_some() && limits.per_project_bytes_per_second > 0;
+        if !key_limited && !project_limited {
+            return body;
+        }
+
+        let throttle = Throttle {
+            access_key,
+            project_id,
+            limits,
+        };
+        let limiter = self.clone();
+        let stream = body
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            .map_ok(|chunk| stream::iter(slices(chunk).into_iter().map(Ok)))
+            .try_flatten()
+            .then(move |chunk| {
+                let limiter = limiter.clone();
+                let throttle = throttle.clone();
+                async move {
+                    if let Ok(chunk) = &chunk {
+                        let wait = limiter.wait(&throttle, chunk.len() as u64, Instant::now());
+                        if !wait.is_zero() {
+                            DOWNLOAD_THROTTLED.inc_by(wait.as_millis() as u64);
+                            tokio::time::sleep(wait).await;
+                        }
+                        DOWNLOAD_BYTES.inc_by(chunk.len() as u64);
+                    }
+                    chunk
+                }
+            });
+        StreamingBlob::wrap(stream)
+    }
+
+    /// Removes buckets that were idle long enough to be full again
+    pub fn prune(&self) {
+        self.keys
+            .retain(|_, bucket| bucket.last_refill.elapsed() < IDLE_TIMEOUT);
+        self.projects
+            .retain(|_, bucket| bucket.last_refill.elapsed() < IDLE_TIMEOUT);
+    }
+}
+
+fn slices(chunk: Bytes) -> Vec<Bytes> {
+    (0..chunk.len())
+        .step_by(SLICE_SIZE)
+        .map(|start| chunk.slice(start..(start + SLICE_SIZE).min(chunk.len())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(per_key: u64, per_project: u64) -> BandwidthLimits {
+        BandwidthLimits {
+            per_key_bytes_per_second: per_key,
+            per_project_bytes_per_second: per_project,
+            burst_bytes: 1000,
+        }
+    }
+
+    #[test]
+    fn test_byte_bucket() {
+        let now = Instant::now();
+        let mut bucket = ByteBucket::new(1000, now);
+        // Burst is sent without delay
+        assert_eq!(bucket.take(1000, 100, 1000, now), Duration::ZERO);
+        // Debt is refilled with the rate
+        assert_eq!(bucket.take(100, 100, 1000, now), Duration::from_secs(1));
+        assert_eq!(bucket.take(100, 100, 1000, now), Duration::from_secs(2));
+        // Refill never exceeds the burst
+        let later = now + Duration::from_secs(60);
+        assert_eq!(bucket.take(1000, 100, 1000, later), Duration::ZERO);
+        assert_eq!(
+            bucket.take(50, 100, 1000, later),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn test_shared_buckets() {
+        let limiter = BandwidthLimiter::new(limits(100, 200));
+        let project_id = DieselUlid::generate();
+        let now = Instant::now();
+        let first = Throttle {
+            access_key: Some("a".to_string()),
+            project_id: Some(project_id),
+            limits: limiter.limits(None),
+        };
+        let second = Throttle {
+            access_key: Some("b".to_string()),
+            ..first.clone()
+        };
+        assert_eq!(limiter.wait(&first, 1000, now), Duration::ZERO);
+        // Key limit of a is exhausted
+        assert_eq!(limiter.wait(&first, 100, now), Duration::from_secs(1));
+        // b has its own key budget but shares the exhausted project budget
+        assert_eq!(limiter.wait(&second, 100, now), Duration::from_secs(1));
+
+        let anonymous = Throttle {
+            access_key: None,
+            ..first
+        };
+        assert_eq!(
+            limiter.wait(&anonymous, 200, now),
+            Duration::from_secs_f64(2.0)
+        );
+    }
+
+    #[test]
+    fn test_project_overrides() {
+        let limiter = BandwidthLimiter::new(limits(100, 0));
+        assert_eq!(limiter.limits(None), limits(100, 0));
+        let policy = BandwidthPolicy {
+            per_key_bytes_per_second: Some(0),
+            per_project_bytes_per_second: Some(500),
+        };
+        assert_eq!(limiter.limits(Some(&policy)), limits(0, 500));
+        assert_eq!(
+            limiter.limits(Some(&BandwidthPolicy::default())),
+            limits(100, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttled_body() {
+        let limiter = Arc::new(BandwidthLimiter::new(limits(1_000_000, 0)));
+        let data = vec![1u8; SLICE_SIZE + 10];
+        let body = StreamingBlob::wrap(stream::iter([Ok::<_, std::io::Error>(Bytes::from(
+            data.clone(),
+        ))]));
+        let body = limiter.throttle(body, Some("a".to_string()), None);
+        let chunks = body.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn test_slices() {
+        let chunk = Bytes::from(vec![0u8; SLICE_SIZE * 2 + 1]);
+        let sizes = slices(chunk).iter().map(|s| s.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![SLICE_SIZE, SLICE_SIZE, 1]);
+        assert!(slices(Bytes::new()).is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+use s3s::{s3_error, S3Error};
+
+/// Validates a bucket name for CreateBucket.
+///
+/// A bucket is an Aruna project, so the name has to satisfy both rule sets:
+/// - Aruna: 3 to 63 characters, lowercase letters, digits and hyphens only
+/// - S3: starts and ends with a letter or digit, no reserved prefixes (`xn--`, `sthree-`)
+///   and no reserved suffixes (`-s3alias`, `--ol-s3`)
+///
+/// Names like `-project-` or `xn--data` are valid Aruna project names but are rejected
+/// here because S3 clients cannot address them. Dots are valid in S3 but not in Aruna.
+pub fn validate_bucket_name(name: &str) -> Result<(), S3Error> {
+    if !(3..=63).contains(&name.len()) {
+        return Err(s3_error!(
+            InvalidBucketName,
+            "Bucket names must be between 3 and 63 characters long"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(s3_error!(
+            InvalidBucketName,
+            "Bucket names may only contain lowercase letters, digits and hyphens"
+        ));
+    }
+    if name.starts_with('-') || name.ends_with('-') {
+        return Err(s3_error!(
+            InvalidBucketName,
+            "Bucket names must start and end with a letter or digit"
+        ));
+    }
+    if ["xn--", "sthree-"].iter().any(|p| name.starts_with(p))
+        || ["-s3alias", "--ol-s3"].iter().any(|s| name.ends_with(s))
+    {
+        return Err(s3_error!(
+            InvalidBucketName,
+            "Bucket name uses a prefix or suffix reserved by S3"
+        ));
+    }
+    Ok(())
+}
+
+/// Cache path of a bucket relative key, the bucket is always the project and keys
+/// never repeat the project name
+pub fn key_to_path(bucket: &str, key: &str) -> String {
+    format!("{bucket}/{key}")
+}
+
+/// Bucket relative key of a cache path, inverse of `key_to_path`
+pub fn path_to_key<'a>(bucket: &str, path: &'a str) -> Option<&'a str> {
+    path.strip_prefix(bucket)?.strip_prefix('/')
+}
+
+/// Remainder of keys that repeat the bucket name (`s3://bucket/bucket/...`)
+pub fn strip_duplicated_bucket<'a>(bucket: &str, key: &'a str) -> Option<&'a str> {
+    path_to_key(bucket, key).filter(|key| !key.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_path_round_trip() {
+        for key in ["a.txt", "coll/a.txt", "coll/ds/a.txt", "coll/ds/sub/a.txt"] {
+            let path = key_to_path("my-project", key);
+            assert_eq!(path, format!("my-project/{key}"));
+            assert_eq!(path_to_key("my-project", &path), Some(key));
+        }
+        assert_eq!(path_to_key("my-project", "other-project/a.txt"), None);
+        // The bucket name has to be a complete segment
+        assert_eq!(path_to_key("my-project", "my-project2/a.txt"), None);
+    }
+
+    #[test]
+    fn test_strip_duplicated_bucket() {
+        assert_eq!(
+            strip_duplicated_bucket("my-project", "my-project/coll/a.txt"),
+            Some("coll/a.txt")
+        );
+        assert_eq!(strip_duplicated_bucket("my-project", "coll/a.txt"), None);
+        assert_eq!(strip_duplicated_bucket("my-project", "my-project/"), None);
+        assert_eq!(strip_duplicated_bucket("my-project", "my-project"), None);
+    }
+
+    #[test]
+    fn test_validate_bucket_name() {
+        assert!(validate_bucket_name("my-project-01").is_ok());
+        assert!(validate_bucket_name("abc").is_ok());
+
+        // Invalid for both
+        assert!(validate_bucket_name("ab").is_err());
+        assert!(validate_bucket_name(&"a".repeat(64)).is_err());
+        assert!(validate_bucket_name("My-Project").is_err());
+        assert!(validate_bucket_name("under_score").is_err());
+        // Valid S3 but not Aruna
+        assert!(validate_bucket_name("my.project").is_err());
+        // Valid Aruna but not S3
+        assert!(validate_bucket_name("-project").is_err());
+        assert!(validate_bucket_name("project-").is_err());
+        assert!(validate_bucket_name("xn--project").is_err());
+        assert!(validate_bucket_name("project-s3alias").is_err());
+    }
+}
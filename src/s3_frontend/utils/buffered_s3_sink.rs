@@ -1,4 +1,5 @@
 use crate::data_backends::storage_backend::StorageBackend;
+use crate::scoped_config;
 use crate::structs::{ObjectLocation, PartETag};
 use anyhow::{anyhow, Result};
 use async_channel::{Receiver, Sender, TryRecvError};
@@ -8,6 +9,68 @@ use pithos_lib::transformer::{Sink, Transformer, TransformerType};
 use std::sync::Arc;
 use tracing::{debug, error, info_span, trace, Instrument};
 
+const MIB: u64 = 1024 * 1024;
+/// Minimum size of every part except the last one
+const MIN_PART_SIZE: u64 = 5 * MIB;
+/// Maximum size of a single part
+const MAX_PART_SIZE: u64 = 5 * 1024 * MIB;
+/// Parts planned for uploads of known size, leaves headroom below the 10,000 parts limit
+/// for the encryption and footer overhead of the stored data
+const PLANNED_PARTS: u64 = 9_500;
+/// Uploads of unknown size double their part size after every this many parts
+const PARTS_PER_STEP: i32 = 1_000;
+
+/// Chooses the size of multipart upload parts
+///
+/// With a known size all parts are `ceil(size / 9500)` rounded up to full MiB (at least
+/// 5 MiB). Otherwise parts start at 5 MiB and double every 1000 parts, which allows
+/// almost 5 TiB (the S3 object size limit) within 10,000 parts.
+#[derive(Debug, Clone, Copy)]
+pub struct PartSizer {
+    expected_size: Option<u64>,
+    max_part_size: u64,
+}
+
+impl Default for PartSizer {
+    fn default() -> Self {
+        PartSizer::new(None)
+    }
+}
+
+impl PartSizer {
+    pub fn new(expected_size: Option<u64>) -> Self {
+        PartSizer {
+            expected_size,
+            max_part_size: MAX_PART_SIZE,
+        }
+    }
+
+    /// Caps the part size (and the buffered data) at `max` bytes, at least 5 MiB
+    pub fn with_max_part_size(mut self, max: u64) -> Self {
+        self.max_part_size = max.clamp(MIN_PART_SIZE, MAX_PART_SIZE);
+        self
+    }
+
+    /// Largest upload that fits into the planned parts
+    pub fn max_object_size(&self) -> u64 {
+        self.max_part_size * PLANNED_PARTS
+    }
+
+    /// Size after which the buffered data is uploaded as the given (1-based) part
+    pub fn part_size(&self, part_number: i32) -> u64 {
+        match self.expected_size {
+            Some(size) => size
+                .div_ceil(PLANNED_PARTS)
+                .next_multiple_of(MIB)
+                .clamp(MIN_PART_SIZE, self.max_part_size),
+            None => {
+                let step = ((part_number.max(1) - 1) / PARTS_PER_STEP).min(10) as u32;
+                (MIN_PART_SIZE << step).min(self.max_part_size)
+            }
+        }
+    }
+}
+
 pub struct BufferedS3Sink {
     backend: Arc<Box<dyn StorageBackend>>,
     buffer: BytesMut,
@@ -21,6 +84,9 @@ pub struct BufferedS3Sink {
     notifier: Option<Arc<Notifier>>,
     msg_receiver: Option<Receiver<Message>>,
     idx: Option<usize>,
+    part_sizer: PartSizer,
+    /// Multipart upload initialized by the sink itself, aborted if the upload fails
+    owned_upload: bool,
 }
 
 impl Sink for BufferedS3Sink {}
@@ -70,10 +136,54 @@ impl BufferedS3Sink {
                 notifier: None,
                 msg_receiver: None,
                 idx: None,
+                part_sizer: PartSizer::default(),
+                owned_upload: false,
             },
             sx,
         )
     }
+
+    /// Sizes the parts of multipart uploads for the expected (raw) size of the data
+    pub fn with_expected_size(mut self, expected_size: Option<u64>) -> Self {
+        self.part_sizer = PartSizer::new(expected_size.filter(|size| *size > 0));
+        self
+    }
+
+    /// Bounds the buffered data, 0 keeps the default part sizes
+    pub fn with_max_part_size(mut self, max: u64) -> Self {
+        if max > 0 {
+            self.part_sizer = self.part_sizer.with_max_part_size(max);
+        }
+        self
+    }
+}
+
+impl Drop for BufferedS3Sink {
+    fn drop(&mut self) {
+        if !self.owned_upload {
+            return;
+        }
+        // Failed or rejected uploads must not leave an open multipart upload behind
+        let (Some(upload_id), Ok(handle)) =
+            (self.upload_id.take(), tokio::runtime::Handle::try_current())
+        else {
+            return;
+        };
+        let backend = self.backend.clone();
+        let location = self.target_location.clone();
+        handle.spawn(scoped_config::in_current_config(
+            async move {
+                match backend
+                    .abort_multipart_upload(location, upload_id.clone())
+                    .await
+                {
+                    Ok(_) => debug!(upload_id, "Aborted unfinished multipart upload"),
+                    Err(e) => error!(error = ?e, upload_id, msg = "Unable to abort upload"),
+                }
+            }
+            .instrument(info_span!("abort_multipart_spawn")),
+        ));
+    }
 }
 
 impl BufferedS3Sink {
@@ -107,6 +217,7 @@ impl BufferedS3Sink {
                 .init_multipart_upload(self.target_location.clone())
                 .await?,
         );
+        self.owned_upload = true;
         debug!("Initialized multipart");
         Ok(())
     }
@@ -129,7 +240,7 @@ impl BufferedS3Sink {
                 e
             })?;
 
-        tokio::spawn(
+        crate::spawn(
             async move {
                 backend_clone
                     .put_object(receiver, location_clone, expected_len)
@@ -166,7 +277,7 @@ impl BufferedS3Sink {
                 e
             })?;
 
-        let tag = tokio::spawn(
+        let tag = crate::spawn(
             async move {
                 backend_clone
                     .upload_multi_object(receiver, location_clone, up_id, expected_len, pnumber)
@@ -205,6 +316,7 @@ impl BufferedS3Sink {
                 tracing::error!(error = ?e, msg = e.to_string());
                 e
             })?;
+        self.owned_upload = false;
         debug!(up_id, "finished multipart");
         Ok(())
     }
@@ -247,9 +359,10 @@ impl Transformer for BufferedS3Sink {
             }
             Ok(())
         } else {
-            if self.buffer.len() > 5242880 {
-                trace!("exceeds 5 Mib -> upload multi part");
-                // 5 Mib -> initialize multipart
+            let part_size = self.part_sizer.part_size(self.part_number.unwrap_or(1));
+            if self.buffer.len() as u64 >= part_size {
+                trace!(part_size, "exceeds part size -> upload multi part");
+                // First full part -> initialize multipart
                 if self.upload_id.is_none() {
                     self.initialize_multipart().await?;
                 }
@@ -284,3 +397,67 @@ impl Transformer for BufferedS3Sink {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of parts the sink uploads for a stream of equally sized chunks
+    fn simulate(sizer: PartSizer, total: u64, chunk: u64) -> i32 {
+        let (mut part_number, mut buffered, mut sent) = (1, 0, 0);
+        while sent < total {
+            let len = chunk.min(total - sent);
+            sent += len;
+            buffered += len;
+            if buffered >= sizer.part_size(part_number) {
+                assert!(buffered <= MAX_PART_SIZE + chunk);
+                buffered = 0;
+                part_number += 1;
+            }
+        }
+        if buffered > 0 {
+            part_number += 1;
+        }
+        part_number - 1
+    }
+
+    #[test]
+    fn test_known_size() {
+        assert_eq!(PartSizer::new(Some(1)).part_size(1), MIN_PART_SIZE);
+        // 60 GB -> ceil(60e9 / 9500) = 6315790 bytes -> 7 MiB
+        let sizer = PartSizer::new(Some(60_000_000_000));
+        assert_eq!(sizer.part_size(1), 7 * MIB);
+        assert_eq!(sizer.part_size(9000), 7 * MIB);
+        assert!(simulate(sizer, 60_000_000_000, 64 * 1024) <= 9_500);
+        // Up to 1% overhead of the stored data still fits
+        assert!(simulate(sizer, 60_600_000_000, 64 * 1024) <= 10_000);
+    }
+
+    #[test]
+    fn test_max_part_size() {
+        let sizer = PartSizer::new(None).with_max_part_size(8 * MIB);
+        assert_eq!(sizer.part_size(1), MIN_PART_SIZE);
+        assert_eq!(sizer.part_size(5000), 8 * MIB);
+        assert_eq!(sizer.max_object_size(), 8 * MIB * PLANNED_PARTS);
+        let sizer = PartSizer::new(Some(60_000_000_000)).with_max_part_size(MIB);
+        assert_eq!(sizer.part_size(1), MIN_PART_SIZE);
+        assert_eq!(
+            PartSizer::default().max_object_size(),
+            MAX_PART_SIZE * PLANNED_PARTS
+        );
+    }
+
+    #[test]
+    fn test_unknown_size() {
+        let sizer = PartSizer::new(None);
+        assert_eq!(sizer.part_size(1), MIN_PART_SIZE);
+        assert_eq!(sizer.part_size(1000), MIN_PART_SIZE);
+        assert_eq!(sizer.part_size(1001), 2 * MIN_PART_SIZE);
+        assert_eq!(sizer.part_size(100_000), MAX_PART_SIZE);
+        // A 60 GB stream of unknown size in 1 MiB chunks
+        let parts = simulate(sizer, 60 * 1024 * 1024 * 1024, MIB);
+        assert!(parts < 10_000, "{parts} parts");
+        // Multiple TiB are still reachable
+        assert!(simulate(sizer, 4 * 1024 * 1024 * MIB, MIB) <= 10_000);
+    }
+}
@@ -0,0 +1,550 @@
+use crate::structs::{ContentChecksum, ContentChecksumAlgorithm};
+use anyhow::{anyhow, Result};
+use async_channel::{Receiver, Sender, TryRecvError};
+use base64::engine::general_purpose;
+use base64::Engine;
+use bytes::BytesMut;
+use digest::Digest;
+use pithos_lib::helpers::notifications::{Message, Notifier};
+use pithos_lib::transformer::{Transformer, TransformerType};
+use s3s::dto::ChecksumAlgorithm;
+use s3s::{s3_error, S3Result};
+use std::sync::Arc;
+use tracing::error;
+
+/// Checksum requested by the client via x-amz-checksum-* or x-amz-sdk-checksum-algorithm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestedChecksum {
+    pub algorithm: ContentChecksumAlgorithm,
+    /// Base64 encoded value to verify, None if the checksum should only be calculated
+    pub expected: Option<String>,
+}
+
+impl RequestedChecksum {
+    /// Extracts the requested checksum, at most one checksum value may be provided
+    #[tracing::instrument(level = "trace")]
+    pub fn from_input(
+        algorithm: Option<&ChecksumAlgorithm>,
+        crc32: Option<&String>,
+        crc32c: Option<&String>,
+        sha1: Option<&String>,
+        sha256: Option<&String>,
+    ) -> S3Result<Option<Self>> {
+        let provided = [
+            (ContentChecksumAlgorithm::Crc32, crc32),
+            (ContentChecksumAlgorithm::Crc32c, crc32c),
+            (ContentChecksumAlgorithm::Sha1, sha1),
+            (ContentChecksumAlgorithm::Sha256, sha256),
+        ]
+        .into_iter()
+        .filter_map(|(algorithm, value)| Some((algorithm, value?.clone())))
+        .collect::<Vec<_>>();
+        if provided.len() > 1 {
+            error!("Multiple checksums provided");
+            return Err(s3_error!(
+                InvalidRequest,
+                "Expecting a single x-amz-checksum- header"
+            ));
+        }
+        let algorithm = match algorithm {
+            Some(algorithm) => Some(
+                ContentChecksumAlgorithm::from_name(algorithm.as_str()).ok_or_else(|| {
+                    error!(
+                        algorithm = algorithm.as_str(),
+                        "Unsupported checksum algorithm"
+                    );
+                    s3_error!(InvalidRequest, "Unsupported checksum algorithm")
+                })?,
+            ),
+            None => None,
+        };
+        match (algorithm, provided.into_iter().next()) {
+            (Some(algorithm), Some((provided, _))) if algorithm != provided => {
+                error!(?algorithm, ?provided, "Checksum algorithm mismatch");
+                Err(s3_error!(
+                    InvalidRequest,
+                    "Value for x-amz-sdk-checksum-algorithm does not match the provided checksum"
+                ))
+            }
+            (_, Some((algorithm, expected))) => Ok(Some(Self {
+                algorithm,
+                expected: Some(expected),
+            })),
+            (Some(algorithm), None) => Ok(Some(Self {
+                algorithm,
+                expected: None,
+            })),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Compares the calculated checksum with the expected value
+    pub fn verify(&self, calculated: &str) -> S3Result<ContentChecksum> {
+        if let Some(expected) = &self.expected {
+            if expected != calculated {
+                error!(expected, calculated, "Checksum mismatch");
+                return Err(s3_error!(
+                    BadDigest,
+                    "The {} you specified did not match the calculated checksum",
+                    self.algorithm.header_name()
+                ));
+            }
+        }
+        Ok(ContentChecksum {
+            algorithm: self.algorithm,
+            value: calculated.to_string(),
+        })
+    }
+}
+
+/// MD5 digest of the payload sent by the client via Content-MD5
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentMd5 {
+    /// Hex encoded, as calculated by the HashingTransformer
+    pub expected: String,
+}
+
+impl ContentMd5 {
+    /// Parses the base64 encoded header value, malformed digests are rejected before any data is written
+    #[tracing::instrument(level = "trace")]
+    pub fn from_input(content_md5: Option<&String>) -> S3Result<Option<Self>> {
+        let Some(content_md5) = content_md5 else {
+            return Ok(None);
+        };
+        match general_purpose::STANDARD.decode(content_md5.trim()) {
+            Ok(digest) if digest.len() == 16 => Ok(Some(Self {
+                expected: hex::encode(digest),
+            })),
+            _ => {
+                error!(content_md5, "Invalid Content-MD5");
+                Err(s3_error!(
+                    InvalidDigest,
+                    "The Content-MD5 you specified is not valid"
+                ))
+            }
+        }
+    }
+
+    /// Compares the calculated hex digest with the expected value
+    pub fn verify(&self, calculated: &str) -> S3Result<()> {
+        if self.expected != calculated {
+            error!(expected = self.expected, calculated, "Content-MD5 mismatch");
+            return Err(s3_error!(
+                BadDigest,
+                "The Content-MD5 you specified did not match what we received"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Converts a hex digest (as returned by the HashingTransformer) into the base64 checksum format
+pub fn hex_to_checksum(hex_digest: &str) -> Result<String> {
+    Ok(general_purpose::STANDARD.encode(hex::decode(hex_digest)?))
+}
+
+/// MD5 of zero bytes, the ETag of empty objects
+pub const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+/// SHA256 of zero bytes
+pub const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Checksum of zero bytes, empty uploads are not passed through the ChecksumTransformer
+pub fn empty_checksum(algorithm: ContentChecksumAlgorithm) -> String {
+    ChecksumHasher::new(algorithm).finalize()
+}
+
+/// Checks whether the client hashes of an upload match the stored hex digests of the key.
+/// At least one hash must be provided, checksums that are not stored (e.g. crc32) never match.
+pub fn is_identical_upload(
+    content_md5: Option<&ContentMd5>,
+    requested: Option<&RequestedChecksum>,
+    stored_md5: Option<&str>,
+    stored_sha256: Option<&str>,
+) -> bool {
+    let mut verified = false;
+    if let Some(content_md5) = content_md5 {
+        if stored_md5 != Some(content_md5.expected.as_str()) {
+            return false;
+        }
+        verified = true;
+    }
+    if let Some(requested) = requested {
+        if requested.algorithm != ContentChecksumAlgorithm::Sha256 {
+            return false;
+        }
+        let Some(stored) = stored_sha256.and_then(|sha256| hex_to_checksum(sha256).ok()) else {
+            return false;
+        };
+        if let Some(expected) = &requested.expected {
+            if expected != &stored {
+                return false;
+            }
+            verified = true;
+        }
+    }
+    verified
+}
+
+/// Composite checksum of a multipart upload: checksum of the concatenated raw part
+/// checksums followed by `-<number of parts>`, the part checksums are added in order
+pub struct CompositeChecksum {
+    algorithm: ContentChecksumAlgorithm,
+    hasher: ChecksumHasher,
+    parts: usize,
+}
+
+impl CompositeChecksum {
+    pub fn new(algorithm: ContentChecksumAlgorithm) -> Self {
+        CompositeChecksum {
+            algorithm,
+            hasher: ChecksumHasher::new(algorithm),
+            parts: 0,
+        }
+    }
+
+    pub fn algorithm(&self) -> ContentChecksumAlgorithm {
+        self.algorithm
+    }
+
+    /// Adds the base64 encoded checksum of the next part
+    pub fn update(&mut self, part_checksum: &str) -> Result<()> {
+        self.hasher
+            .update(&general_purpose::STANDARD.decode(part_checksum)?);
+        self.parts += 1;
+        Ok(())
+    }
+
+    pub fn finalize(self) -> String {
+        format!("{}-{}", self.hasher.finalize(), self.parts)
+    }
+}
+
+enum ChecksumHasher {
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+}
+
+impl ChecksumHasher {
+    fn new(algorithm: ContentChecksumAlgorithm) -> Self {
+        match algorithm {
+            ContentChecksumAlgorithm::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+            ContentChecksumAlgorithm::Crc32c => Self::Crc32c(0),
+            ContentChecksumAlgorithm::Sha1 => Self::Sha1(sha1::Sha1::new()),
+            ContentChecksumAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(hasher) => hasher.update(data),
+            Self::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Self::Sha1(hasher) => Digest::update(hasher, data),
+            Self::Sha256(hasher) => Digest::update(hasher, data),
+        }
+    }
+
+    /// Base64 encoded big-endian checksum
+    fn finalize(self) -> String {
+        let bytes = match self {
+            Self::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            Self::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+            Self::Sha1(hasher) => hasher.finalize().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+        };
+        general_purpose::STANDARD.encode(bytes)
+    }
+}
+
+/// Calculates a checksum of the passing data, the base64 encoded result
+/// is sent to the backchannel once the stream is finished
+pub struct ChecksumTransformer {
+    hasher: Option<ChecksumHasher>,
+    back_channel: Sender<String>,
+    notifier: Option<Arc<Notifier>>,
+    msg_receiver: Option<Receiver<Message>>,
+    idx: Option<usize>,
+}
+
+impl ChecksumTransformer {
+    #[tracing::instrument(level = "trace")]
+    pub fn new_with_backchannel(algorithm: ContentChecksumAlgorithm) -> (Self, Receiver<String>) {
+        let (sender, receiver) = async_channel::bounded(1);
+        (
+            Self {
+                hasher: Some(ChecksumHasher::new(algorithm)),
+                back_channel: sender,
+                notifier: None,
+                msg_receiver: None,
+                idx: None,
+            },
+            receiver,
+        )
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn process_messages(&mut self) -> Result<bool> {
+        if let Some(rx) = &self.msg_receiver {
+            loop {
+                match rx.try_recv() {
+                    Ok(Message::Finished) => return Ok(true),
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Closed) => {
+                        error!("Message receiver closed");
+                        return Err(anyhow!("Message receiver closed"));
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transformer for ChecksumTransformer {
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn initialize(&mut self, idx: usize) -> (TransformerType, Sender<Message>) {
+        self.idx = Some(idx);
+        let (sx, rx) = async_channel::bounded(10);
+        self.msg_receiver = Some(rx);
+        (TransformerType::Unspecified, sx)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, buf))]
+    async fn process_bytes(&mut self, buf: &mut BytesMut) -> Result<()> {
+        let finished = self.process_messages()?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(buf);
+        }
+        if finished {
+            if let Some(hasher) = self.hasher.take() {
+                self.back_channel.try_send(hasher.finalize())?;
+            }
+            if let Some(notifier) = &self.notifier {
+                notifier.send_next(
+                    self.idx.ok_or_else(|| anyhow!("Missing idx"))?,
+                    Message::Finished,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, notifier))]
+    #[inline]
+    async fn set_notifier(&mut self, notifier: Arc<Notifier>) -> Result<()> {
+        self.notifier = Some(notifier);
+        Ok(())
+    }
+}
+
+/// Verifies the SHA256 of the passing (plaintext) data against the stored hash
+///
+/// The latest chunk is held back until the next one arrives. On a mismatch the final
+/// chunk is never released and the stream fails, a response with a known Content-Length
+/// therefore can not complete successfully.
+pub struct Sha256VerifyTransformer {
+    hasher: Option<sha2::Sha256>,
+    expected: String,
+    held: BytesMut,
+    notifier: Option<Arc<Notifier>>,
+    msg_receiver: Option<Receiver<Message>>,
+    idx: Option<usize>,
+}
+
+impl Sha256VerifyTransformer {
+    /// Expects the hex encoded hash
+    #[tracing::instrument(level = "trace")]
+    pub fn new(expected: String) -> Self {
+        Self {
+            hasher: Some(sha2::Sha256::new()),
+            expected: expected.to_ascii_lowercase(),
+            held: BytesMut::new(),
+            notifier: None,
+            msg_receiver: None,
+            idx: None,
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn process_messages(&mut self) -> Result<bool> {
+        if let Some(rx) = &self.msg_receiver {
+            loop {
+                match rx.try_recv() {
+                    Ok(Message::Finished) => return Ok(true),
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Closed) => {
+                        error!("Message receiver closed");
+                        return Err(anyhow!("Message receiver closed"));
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transformer for Sha256VerifyTransformer {
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn initialize(&mut self, idx: usize) -> (TransformerType, Sender<Message>) {
+        self.idx = Some(idx);
+        let (sx, rx) = async_channel::bounded(10);
+        self.msg_receiver = Some(rx);
+        (TransformerType::Unspecified, sx)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, buf))]
+    async fn process_bytes(&mut self, buf: &mut BytesMut) -> Result<()> {
+        let finished = self.process_messages()?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            Digest::update(hasher, &buf[..]);
+        }
+        // Release the previous chunk, hold back the current one
+        std::mem::swap(buf, &mut self.held);
+        if finished {
+            if let Some(hasher) = self.hasher.take() {
+                let calculated = hex::encode(hasher.finalize());
+                if calculated != self.expected {
+                    error!(
+                        expected = self.expected,
+                        calculated, "SHA256 of the streamed data does not match the stored hash"
+                    );
+                    buf.clear();
+                    self.held.clear();
+                    return Err(anyhow!("SHA256 mismatch"));
+                }
+            }
+            buf.extend_from_slice(&self.held);
+            self.held.clear();
+            if let Some(notifier) = &self.notifier {
+                notifier.send_next(
+                    self.idx.ok_or_else(|| anyhow!("Missing idx"))?,
+                    Message::Finished,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, notifier))]
+    #[inline]
+    async fn set_notifier(&mut self, notifier: Arc<Notifier>) -> Result<()> {
+        self.notifier = Some(notifier);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use s3s::S3ErrorCode;
+
+    #[test]
+    fn test_content_md5() {
+        assert_eq!(ContentMd5::from_input(None).unwrap(), None);
+        // md5("hello world")
+        let header = "XrY7u+Ae7tCTyyK7j1rNww==".to_string();
+        let content_md5 = ContentMd5::from_input(Some(&header)).unwrap().unwrap();
+        assert_eq!(content_md5.expected, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert!(content_md5
+            .verify("5eb63bbbe01eeed093cb22bb8f5acdc3")
+            .is_ok());
+        assert_eq!(
+            content_md5
+                .verify("d41d8cd98f00b204e9800998ecf8427e")
+                .unwrap_err()
+                .code(),
+            &S3ErrorCode::BadDigest
+        );
+
+        for invalid in ["not base64!", "aGVsbG8="] {
+            assert_eq!(
+                ContentMd5::from_input(Some(&invalid.to_string()))
+                    .unwrap_err()
+                    .code(),
+                &S3ErrorCode::InvalidDigest
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(hex::encode(md5::Md5::digest(b"")), EMPTY_MD5);
+        assert_eq!(hex::encode(sha2::Sha256::digest(b"")), EMPTY_SHA256);
+        assert_eq!(
+            empty_checksum(ContentChecksumAlgorithm::Sha256),
+            hex_to_checksum(EMPTY_SHA256).unwrap()
+        );
+        assert_eq!(empty_checksum(ContentChecksumAlgorithm::Crc32), "AAAAAA==");
+        assert_eq!(empty_checksum(ContentChecksumAlgorithm::Crc32c), "AAAAAA==");
+    }
+
+    #[test]
+    fn test_identical_upload() {
+        // md5 and sha256 of "hello world"
+        let md5 = "5eb63bbbe01eeed093cb22bb8f5acdc3";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let content_md5 = ContentMd5::from_input(Some(&"XrY7u+Ae7tCTyyK7j1rNww==".to_string()))
+            .unwrap()
+            .unwrap();
+        let checksum = |algorithm, expected: Option<&str>| RequestedChecksum {
+            algorithm,
+            expected: expected.map(str::to_string),
+        };
+        let sha256_checksum = hex_to_checksum(sha256).unwrap();
+
+        // Hash match
+        assert!(is_identical_upload(
+            Some(&content_md5),
+            None,
+            Some(md5),
+            Some(sha256)
+        ));
+        assert!(is_identical_upload(
+            None,
+            Some(&checksum(
+                ContentChecksumAlgorithm::Sha256,
+                Some(&sha256_checksum)
+            )),
+            Some(md5),
+            Some(sha256)
+        ));
+
+        // Hash mismatch, the overwrite proceeds
+        assert!(!is_identical_upload(
+            Some(&content_md5),
+            None,
+            Some(EMPTY_MD5),
+            Some(EMPTY_SHA256)
+        ));
+        assert!(!is_identical_upload(
+            Some(&content_md5),
+            Some(&checksum(
+                ContentChecksumAlgorithm::Sha256,
+                Some(&empty_checksum(ContentChecksumAlgorithm::Sha256))
+            )),
+            Some(md5),
+            Some(sha256)
+        ));
+        // Checksums that are not stored can not be compared
+        assert!(!is_identical_upload(
+            Some(&content_md5),
+            Some(&checksum(ContentChecksumAlgorithm::Crc32, Some("DUoRhQ=="))),
+            Some(md5),
+            Some(sha256)
+        ));
+
+        // No client hash, no deduplication
+        assert!(!is_identical_upload(None, None, Some(md5), Some(sha256)));
+        assert!(!is_identical_upload(
+            None,
+            Some(&checksum(ContentChecksumAlgorithm::Sha256, None)),
+            Some(md5),
+            Some(sha256)
+        ));
+    }
+}
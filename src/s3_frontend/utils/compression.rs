@@ -0,0 +1,236 @@
+use crate::config::CompressionFilter;
+use bytes::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+use s3s::dto::StreamingBlob;
+
+/// Content types of already compressed data
+const COMPRESSED_TYPES: &[&str] = &[
+    "application/gzip",
+    "application/x-gzip",
+    "application/zip",
+    "application/zstd",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/vnd.apache.parquet",
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "audio/*",
+    "video/*",
+];
+
+/// Magic numbers of already compressed data
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    &[0x1f, 0x8b],                         // gzip, BGZF (bam, tabix)
+    &[0x28, 0xb5, 0x2f, 0xfd],             // zstd
+    b"BZh",                                // bzip2
+    &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00], // xz
+    &[0x50, 0x4b, 0x03, 0x04],             // zip
+    &[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c], // 7z
+    &[0xff, 0xd8, 0xff],                   // jpeg
+    &[0x89, 0x50, 0x4e, 0x47],             // png
+    b"GIF8",                               // gif
+    b"CRAM",                               // cram
+    b"PAR1",                               // parquet
+];
+
+/// Whether an upload is compressed, see `CompressionFilter`
+///
+/// `head` are the first bytes of the upload, an empty slice skips the sniffing.
+pub fn should_compress(
+    filter: Option<&CompressionFilter>,
+    key: &str,
+    content_type: Option<&str>,
+    head: &[u8],
+) -> bool {
+    if let Some(filter) = filter {
+        if filter
+            .deny
+            .iter()
+            .any(|e| matches_entry(e, key, content_type))
+        {
+            return false;
+        }
+        if filter
+            .allow
+            .iter()
+            .any(|e| matches_entry(e, key, content_type))
+        {
+            return true;
+        }
+    }
+    if COMPRESSED_TYPES
+        .iter()
+        .any(|t| content_type.is_some_and(|c| matches_content_type(t, c)))
+    {
+        return false;
+    }
+    !is_compressed_data(head)
+}
+
+fn matches_entry(entry: &str, key: &str, content_type: Option<&str>) -> bool {
+    if entry.starts_with('.') {
+        return key
+            .to_ascii_lowercase()
+            .ends_with(&entry.to_ascii_lowercase());
+    }
+    content_type.is_some_and(|c| matches_content_type(entry, c))
+}
+
+/// Compares the essence of the content type (without parameters), `type/*` matches all subtypes
+fn matches_content_type(pattern: &str, content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(main_type) => essence
+            .split_once('/')
+            .is_some_and(|(main, _)| main == main_type),
+        None => essence == pattern,
+    }
+}
+
+fn is_compressed_data(head: &[u8]) -> bool {
+    COMPRESSED_MAGIC.iter().any(|magic| head.starts_with(magic))
+        // webp, mp4 / mov
+        || (head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP"))
+        || head.get(4..8) == Some(b"ftyp")
+}
+
+/// Reads the first chunk of a body for sniffing, the returned body still contains it
+pub async fn peek_body(mut body: StreamingBlob) -> (Bytes, StreamingBlob) {
+    let first = match body.next().await {
+        Some(Ok(first)) => first,
+        Some(Err(e)) => {
+            let err = std::io::Error::new(std::io::ErrorKind::Other, e);
+            return (
+                Bytes::new(),
+                StreamingBlob::wrap(stream::iter([Err::<Bytes, _>(err)])),
+            );
+        }
+        None => return (Bytes::new(), body),
+    };
+    let rest = body.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+    (
+        first.clone(),
+        StreamingBlob::wrap(stream::iter([Ok(first)]).chain(rest)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compressible text and incompressible (already compressed) fixtures
+    fn fixtures() -> (Vec<u8>, Vec<u8>) {
+        let text = (0..2000)
+            .map(|i| format!("chr1\t{}\t.\tA\tG\t50\tPASS\t.\n", 10_000 + i * 7))
+            .collect::<String>()
+            .into_bytes();
+        let compressed = zstd::encode_all(&text[..], 19).unwrap();
+        (text, compressed)
+    }
+
+    #[test]
+    fn test_sniffing() {
+        let (_, compressed) = fixtures();
+        // Detected without extension or content type
+        assert!(!should_compress(None, "calls", None, &compressed));
+        assert!(!should_compress(
+            None,
+            "reads.bam",
+            None,
+            &[0x1f, 0x8b, 0x08, 0x04]
+        ));
+        assert!(!should_compress(
+            None,
+            "image",
+            None,
+            &[0xff, 0xd8, 0xff, 0xe0]
+        ));
+        assert!(!should_compress(None, "movie", None, b"\0\0\0\x18ftypmp42"));
+        // Empty heads can not be sniffed
+        assert!(should_compress(None, "empty", None, &[]));
+    }
+
+    #[test]
+    fn test_content_types() {
+        assert!(!should_compress(None, "a", Some("image/jpeg"), b"abc"));
+        assert!(!should_compress(None, "a", Some("Video/MP4"), b"abc"));
+        assert!(!should_compress(
+            None,
+            "a",
+            Some("application/gzip; charset=binary"),
+            b"abc"
+        ));
+        assert!(should_compress(None, "a", Some("text/plain"), b"abc"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let (text, compressed) = fixtures();
+        let filter = CompressionFilter {
+            allow: vec!["application/gzip".to_string(), ".VCF".to_string()],
+            deny: vec!["text/*".to_string(), ".bam".to_string()],
+        };
+        // Deny takes precedence
+        assert!(!should_compress(
+            Some(&filter),
+            "calls.vcf",
+            Some("text/plain"),
+            &text
+        ));
+        assert!(!should_compress(Some(&filter), "reads.BAM", None, &text));
+        // Allowed entries are compressed regardless of the data
+        assert!(should_compress(
+            Some(&filter),
+            "calls.gz",
+            Some("application/gzip"),
+            &compressed
+        ));
+        assert!(should_compress(
+            Some(&filter),
+            "calls.vcf",
+            None,
+            &compressed
+        ));
+        assert!(should_compress(Some(&filter), "other", None, &text));
+    }
+
+    #[tokio::test]
+    async fn test_peek_body() {
+        let (text, _) = fixtures();
+        let chunks = text
+            .chunks(1000)
+            .map(|c| Ok::<_, std::io::Error>(Bytes::copy_from_slice(c)))
+            .collect::<Vec<_>>();
+        let body = StreamingBlob::wrap(stream::iter(chunks));
+        let (head, body) = peek_body(body).await;
+        assert_eq!(&head[..], &text[..1000]);
+        let data = body.try_collect::<Vec<_>>().await.unwrap().concat();
+        assert_eq!(data, text);
+
+        let (head, body) = peek_body(StreamingBlob::wrap(stream::iter(Vec::<
+            Result<Bytes, std::io::Error>,
+        >::new())))
+        .await;
+        assert!(head.is_empty());
+        assert!(body.try_collect::<Vec<_>>().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fixtures() {
+        // Compressing the compressed fixture again only grows it, the sniffing skips it
+        let (text, compressed) = fixtures();
+        assert!(compressed.len() < text.len() / 4);
+        assert!(zstd::encode_all(&compressed[..], 0).unwrap().len() >= compressed.len());
+        assert!(should_compress(None, "calls.vcf", None, &text));
+        assert!(!should_compress(None, "calls.vcf.zst", None, &compressed));
+    }
+}
@@ -0,0 +1,188 @@
+use dashmap::DashSet;
+use s3s::dto::Timestamp;
+use s3s::{s3_error, S3ErrorCode, S3Result};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tracing::debug;
+
+/// Conditional request headers of GetObject and HeadObject, or the
+/// `x-amz-copy-source-if-*` headers of CopyObject
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<Timestamp>,
+    pub if_unmodified_since: Option<Timestamp>,
+}
+
+/// Checks an etag against a list of entity tags (`"a", W/"b"` or `*`)
+fn etag_matches(etag: &str, header: &str) -> bool {
+    let etag = etag.trim_matches('"');
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/").trim_matches('"') == etag)
+}
+
+impl Preconditions {
+    pub fn is_empty(&self) -> bool {
+        self.if_match.is_none()
+            && self.if_none_match.is_none()
+            && self.if_modified_since.is_none()
+            && self.if_unmodified_since.is_none()
+    }
+
+    /// Evaluates the preconditions with the precedence documented by AWS (RFC 7232):
+    ///
+    /// - If-Match takes precedence over If-Unmodified-Since (412 Precondition Failed)
+    /// - If-None-Match takes precedence over If-Modified-Since (304 Not Modified)
+    ///
+    /// HTTP dates only have second precision, the last modified timestamp is truncated accordingly.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn evaluate(&self, etag: &str, last_modified: Option<OffsetDateTime>) -> S3Result<()> {
+        let last_modified = last_modified.map(|t| t.unix_timestamp());
+        let since = |ts: &Timestamp| OffsetDateTime::from(ts.clone()).unix_timestamp();
+
+        match (&self.if_match, &self.if_unmodified_since) {
+            (Some(if_match), _) if !etag_matches(etag, if_match) => {
+                debug!(etag, if_match, "If-Match failed");
+                return Err(s3_error!(
+                    PreconditionFailed,
+                    "If-Match precondition failed"
+                ));
+            }
+            (None, Some(unmodified_since)) => {
+                if let Some(last_modified) = last_modified {
+                    if last_modified > since(unmodified_since) {
+                        debug!(etag, "If-Unmodified-Since failed");
+                        return Err(s3_error!(
+                            PreconditionFailed,
+                            "If-Unmodified-Since precondition failed"
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        match (&self.if_none_match, &self.if_modified_since) {
+            (Some(if_none_match), _) => {
+                if etag_matches(etag, if_none_match) {
+                    return Err(s3_error!(NotModified, "Not modified"));
+                }
+            }
+            (None, Some(modified_since)) => {
+                if let Some(last_modified) = last_modified {
+                    if last_modified <= since(modified_since) {
+                        return Err(s3_error!(NotModified, "Not modified"));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Evaluates the preconditions of a copy source, a copy has nothing to answer with
+    /// 304 Not Modified: every failed condition is a 412 Precondition Failed
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn evaluate_copy_source(
+        &self,
+        etag: &str,
+        last_modified: Option<OffsetDateTime>,
+    ) -> S3Result<()> {
+        self.evaluate(etag, last_modified).map_err(|e| {
+            if *e.code() == S3ErrorCode::NotModified {
+                debug!(etag, "Copy source precondition failed");
+                s3_error!(PreconditionFailed, "Copy source precondition failed")
+            } else {
+                e
+            }
+        })
+    }
+}
+
+/// Conditional writes (`If-Match` / `If-None-Match: *`) for compare-and-swap style uploads
+#[derive(Debug, Default, Clone)]
+pub struct WritePreconditions {
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+}
+
+impl WritePreconditions {
+    pub fn from_headers(headers: &http::HeaderMap) -> Self {
+        let header = |name: http::HeaderName| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        Self {
+            if_match: header(http::header::IF_MATCH),
+            if_none_match: header(http::header::IF_NONE_MATCH),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.if_match.is_none() && self.if_none_match.is_none()
+    }
+
+    /// Evaluates the preconditions against the etag of the current object,
+    /// `None` if no (finished) object exists under the key
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn evaluate(&self, current_etag: Option<&str>) -> S3Result<()> {
+        if let Some(if_none_match) = &self.if_none_match {
+            if if_none_match.trim() != "*" {
+                return Err(s3_error!(
+                    NotImplemented,
+                    "Only If-None-Match: * is supported for writes"
+                ));
+            }
+            if current_etag.is_some() {
+                debug!(current_etag, "If-None-Match failed, object exists");
+                return Err(s3_error!(
+                    PreconditionFailed,
+                    "If-None-Match precondition failed, object already exists"
+                ));
+            }
+        }
+        if let Some(if_match) = &self.if_match {
+            match current_etag {
+                Some(etag) if etag_matches(etag, if_match) => {}
+                _ => {
+                    debug!(current_etag, if_match, "If-Match failed");
+                    return Err(s3_error!(
+                        PreconditionFailed,
+                        "If-Match precondition failed"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Marks a key as being conditionally written until dropped
+///
+/// Conditional writes of the same key are serialized, a second conditional write
+/// fails instead of racing against the first one. Unconditional writes are not
+/// affected and keep last-writer-wins semantics.
+pub struct WriteLock {
+    keys: Arc<DashSet<String>>,
+    key: String,
+}
+
+impl WriteLock {
+    pub fn acquire(keys: &Arc<DashSet<String>>, key: String) -> Option<Self> {
+        keys.insert(key.clone()).then(|| Self {
+            keys: keys.clone(),
+            key,
+        })
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        self.keys.remove(&self.key);
+    }
+}
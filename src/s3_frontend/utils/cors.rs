@@ -0,0 +1,200 @@
+use crate::caching::cache::Cache;
+use crate::helpers::split_s3_path;
+use crate::structs::ObjectType;
+use http::{HeaderValue, StatusCode};
+use s3s::Body;
+use std::sync::Arc;
+use tracing::{debug, error, trace};
+
+/// Answers a CORS preflight (OPTIONS) request with the CORS configuration of the bucket
+///
+/// Preflights never carry credentials, the response only depends on the configured
+/// rules of the project: 200 with the allow headers if a rule matches the origin,
+/// method and requested headers, 403 otherwise.
+#[tracing::instrument(level = "trace", skip(cache, req))]
+pub async fn handle_preflight(
+    cache: Arc<Cache>,
+    base_domain: &str,
+    req: &hyper::Request<hyper::Body>,
+) -> hyper::Response<Body> {
+    let headers = req.headers();
+    let (Some(origin), Some(method)) = (
+        header_str(headers.get(hyper::header::ORIGIN)),
+        header_str(headers.get(hyper::header::ACCESS_CONTROL_REQUEST_METHOD)),
+    ) else {
+        error!("Preflight without Origin or Access-Control-Request-Method");
+        return empty_response(StatusCode::BAD_REQUEST);
+    };
+    let requested_headers = header_str(headers.get(hyper::header::ACCESS_CONTROL_REQUEST_HEADERS))
+        .map(|h| {
+            h.split(',')
+                .map(|e| e.trim().to_string())
+                .filter(|e| !e.is_empty())
+                .collect::<Vec<_>>()
+        });
+
+    let Some(bucket) = split_s3_path(req, base_domain).0 else {
+        debug!("Preflight without bucket");
+        return empty_response(StatusCode::FORBIDDEN);
+    };
+    let Some(project) = cache.get_full_resource_by_path(&bucket).await else {
+        debug!(bucket, "Preflight for unknown bucket");
+        return empty_response(StatusCode::FORBIDDEN);
+    };
+    if project.object_type != ObjectType::Project {
+        return empty_response(StatusCode::FORBIDDEN);
+    }
+
+    let Some(cors_headers) = cache.get_cors_config(&project.id).and_then(|config| {
+        config.into_headers(origin.to_string(), method.to_string(), requested_headers)
+    }) else {
+        debug!(
+            bucket,
+            origin, method, "Preflight not allowed by CORS configuration"
+        );
+        return empty_response(StatusCode::FORBIDDEN);
+    };
+    trace!(?cors_headers);
+
+    let mut builder = hyper::Response::builder().status(StatusCode::OK);
+    for (key, value) in cors_headers {
+        builder = builder.header(key, value);
+    }
+    builder
+        .header(hyper::header::VARY, "Origin")
+        .body(Body::empty())
+        .unwrap_or_else(|_| empty_response(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+fn header_str(value: Option<&HeaderValue>) -> Option<&str> {
+    value?.to_str().ok()
+}
+
+fn empty_response(status: StatusCode) -> hyper::Response<Body> {
+    let mut response = hyper::Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::caching::policies::CORS_KEY;
+    use crate::structs::Object;
+    use aruna_rust_api::api::storage::models::v2::{KeyValue, KeyValueVariant};
+    use diesel_ulid::DieselUlid;
+
+    const CORS: &str = r#"[{"allowed_methods": ["PUT"], "allowed_origins": ["https://app.example.org"], "allowed_headers": ["content-type"], "max_age_seconds": 600}]"#;
+
+    async fn cache() -> Arc<Cache> {
+        let (cache, _receiver) = test_cache(None).await;
+        let project = |name: &str, key_values| Object {
+            id: DieselUlid::generate(),
+            name: name.to_string(),
+            object_type: ObjectType::Project,
+            key_values,
+            ..Default::default()
+        };
+        let cors = vec![KeyValue {
+            key: CORS_KEY.to_string(),
+            value: CORS.to_string(),
+            variant: KeyValueVariant::Label as i32,
+        }];
+        cache.upsert_object(project("bucket", cors)).await.unwrap();
+        cache.upsert_object(project("other", vec![])).await.unwrap();
+        cache
+    }
+
+    fn preflight(host: &str, path: &str, origin: &str) -> hyper::Request<hyper::Body> {
+        hyper::Request::builder()
+            .method(http::Method::OPTIONS)
+            .uri(path)
+            .header(hyper::header::HOST, host)
+            .header(hyper::header::ORIGIN, origin)
+            .header(hyper::header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .header(
+                hyper::header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "Content-Type",
+            )
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_preflight_addressing() {
+        let cache = cache().await;
+        const ORIGIN: &str = "https://app.example.org";
+        // Virtual-hosted and path-style, the base domain with and without the port
+        for (base_domain, host, path) in [
+            (
+                "s3.example.org",
+                "bucket.s3.example.org:1337",
+                "/dir/file.txt",
+            ),
+            (
+                "s3.example.org:1337",
+                "bucket.s3.example.org:1337",
+                "/dir/file.txt",
+            ),
+            (
+                "s3.example.org",
+                "s3.example.org:1337",
+                "/bucket/dir/file.txt",
+            ),
+            (
+                "s3.example.org:1337",
+                "s3.example.org:1337",
+                "/bucket/dir/file.txt",
+            ),
+            ("s3.example.org", "s3.example.org", "/bucket"),
+        ] {
+            let response =
+                handle_preflight(cache.clone(), base_domain, &preflight(host, path, ORIGIN)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{host}{path}");
+            let headers = response.headers();
+            assert_eq!(
+                headers.get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                Some(&HeaderValue::from_static(ORIGIN))
+            );
+            assert_eq!(
+                headers.get(hyper::header::ACCESS_CONTROL_MAX_AGE),
+                Some(&HeaderValue::from_static("600"))
+            );
+        }
+
+        // Without a matching rule, bucket or the required headers
+        for (host, path, origin) in [
+            (
+                "bucket.s3.example.org",
+                "/file.txt",
+                "https://evil.example.org",
+            ),
+            (
+                "s3.example.org",
+                "/bucket/file.txt",
+                "https://evil.example.org",
+            ),
+            ("other.s3.example.org", "/file.txt", ORIGIN),
+            ("s3.example.org", "/other/file.txt", ORIGIN),
+            ("missing.s3.example.org", "/file.txt", ORIGIN),
+            ("s3.example.org", "/", ORIGIN),
+        ] {
+            let response = handle_preflight(
+                cache.clone(),
+                "s3.example.org",
+                &preflight(host, path, origin),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{host}{path}");
+            assert!(response
+                .headers()
+                .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none());
+        }
+        let mut request = preflight("s3.example.org", "/bucket/file.txt", ORIGIN);
+        request.headers_mut().remove(hyper::header::ORIGIN);
+        let response = handle_preflight(cache, "s3.example.org", &request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,202 @@
+use super::aws_chunked::payload_content_length;
+use super::object_key::url_decode;
+use super::rate_limit::extract_access_key;
+use super::upload_limits::{check_declared, check_quota, object_limit, part_limit, project_limits};
+use crate::caching::cache::Cache;
+use crate::helpers::split_s3_path;
+use crate::s3_frontend::auth::AuthProvider;
+use http::{Method, Request};
+use s3s::path::S3Path;
+use s3s::{s3_error, S3Result};
+use std::sync::Arc;
+use tracing::{debug, error};
+
+/// Whether the client waits for `100 Continue` before it sends the body
+pub fn expects_continue<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(http::header::EXPECT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+}
+
+/// Bucket and key of a request, the key is decoded like s3s does
+pub fn s3_path<B>(req: &Request<B>, base_domain: &str) -> S3Result<S3Path> {
+    match split_s3_path(req, base_domain) {
+        (None, _) => Ok(S3Path::root()),
+        (Some(bucket), None) => Ok(S3Path::bucket(&bucket)),
+        (Some(bucket), Some(key)) => {
+            let key = url_decode(&key).ok_or_else(|| {
+                error!(key, "Invalid percent-encoding");
+                s3_error!(InvalidURI, "Invalid percent-encoding of the key")
+            })?;
+            Ok(S3Path::object(&bucket, &key))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Upload {
+    Object,
+    Part,
+}
+
+impl Upload {
+    /// PutObject and UploadPart, the uploads with bodies of unbounded size
+    fn from_request<B>(req: &Request<B>, path: &S3Path) -> Option<Self> {
+        if req.method() != Method::PUT || !matches!(path, S3Path::Object { .. }) {
+            return None;
+        }
+        let params = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|param| param.split('=').next())
+            .filter(|name| !name.is_empty() && *name != "x-id")
+            .collect::<Vec<_>>();
+        match params.as_slice() {
+            [] if !req.headers().contains_key("x-amz-copy-source") => Some(Upload::Object),
+            [_, _] if params.contains(&"partNumber") && params.contains(&"uploadId") => {
+                Some(Upload::Part)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Access and size checks of uploads that wait for `100 Continue`
+///
+/// hyper only answers `100 Continue` once the body is polled. These checks run
+/// before the request is passed to s3s, which may read the body before it checks the
+/// access (e.g. to verify a signed payload), so rejected clients abort without sending
+/// the body. The signature is verified by s3s afterwards, the checks can only reject.
+pub struct UploadPrecheck {
+    auth: AuthProvider,
+    cache: Arc<Cache>,
+}
+
+impl UploadPrecheck {
+    pub fn new(auth: AuthProvider, cache: Arc<Cache>) -> Self {
+        UploadPrecheck { auth, cache }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, req))]
+    pub async fn check<B>(&self, req: &Request<B>, base_domain: &str) -> S3Result<()> {
+        let path = s3_path(req, base_domain)?;
+        let credentials = match extract_access_key(req) {
+            Some(access_key) => Some(self.auth.credentials(&access_key).await?),
+            None => None,
+        };
+        let Some(result) = self
+            .auth
+            .authorize(
+                credentials.as_ref(),
+                req.method(),
+                &path,
+                req.uri().query(),
+                req.headers(),
+            )
+            .await?
+        else {
+            return Ok(());
+        };
+
+        let Some(upload) = Upload::from_request(req, &path) else {
+            return Ok(());
+        };
+        let content_length = req
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let Some(declared) = payload_content_length(req.headers(), content_length) else {
+            // Rejected by the handler without reading the body
+            return Ok(());
+        };
+        let project_id = result
+            .objects_state
+            .resource_states()
+            .and_then(|states| states.get_project())
+            .map(|project| project.id);
+        let limits = project_limits(&self.cache, project_id.as_ref());
+        match upload {
+            Upload::Object => {
+                check_declared("Object", declared as u64, object_limit(&limits))?;
+                if let Some(project_id) = project_id {
+                    check_quota(
+                        &self.cache,
+                        &[project_id],
+                        declared,
+                        req.headers(),
+                        &result.user_state,
+                    )?;
+                }
+            }
+            Upload::Part => check_declared("Part", declared as u64, part_limit(&limits))?,
+        }
+        debug!(?upload, declared, "Upload passed the precheck");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, uri: &str) -> Request<()> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::HOST, "localhost:1337")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_expects_continue() {
+        let mut req = request(Method::PUT, "/bucket/key");
+        assert!(!expects_continue(&req));
+        req.headers_mut()
+            .insert(http::header::EXPECT, "100-Continue".parse().unwrap());
+        assert!(expects_continue(&req));
+    }
+
+    #[test]
+    fn test_s3_path() {
+        let path = |uri| s3_path(&request(Method::PUT, uri), "localhost:1337");
+        assert_eq!(path("/").unwrap(), S3Path::root());
+        assert_eq!(path("/bucket").unwrap(), S3Path::bucket("bucket"));
+        assert_eq!(
+            path("/bucket/dir/with%20space+plus").unwrap(),
+            S3Path::object("bucket", "dir/with space+plus")
+        );
+        assert!(path("/bucket/100%").is_err());
+    }
+
+    #[test]
+    fn test_upload() {
+        let upload = |method, uri| {
+            let req = request(method, uri);
+            let path = s3_path(&req, "localhost:1337").unwrap();
+            Upload::from_request(&req, &path)
+        };
+        assert_eq!(upload(Method::PUT, "/bucket/key"), Some(Upload::Object));
+        assert_eq!(
+            upload(Method::PUT, "/bucket/key?x-id=PutObject"),
+            Some(Upload::Object)
+        );
+        assert_eq!(
+            upload(Method::PUT, "/bucket/key?partNumber=1&uploadId=abc"),
+            Some(Upload::Part)
+        );
+        assert_eq!(upload(Method::PUT, "/bucket/key?tagging"), None);
+        assert_eq!(upload(Method::PUT, "/bucket"), None);
+        assert_eq!(upload(Method::POST, "/bucket/key?uploads"), None);
+
+        let mut req = request(Method::PUT, "/bucket/key");
+        req.headers_mut()
+            .insert("x-amz-copy-source", "other/key".parse().unwrap());
+        let path = s3_path(&req, "localhost:1337").unwrap();
+        assert_eq!(Upload::from_request(&req, &path), None);
+    }
+}
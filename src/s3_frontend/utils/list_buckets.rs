@@ -0,0 +1,98 @@
+use crate::structs::{DbPermissionLevel, Object, ObjectType};
+use aruna_rust_api::api::storage::models::v2::{DataClass, Status};
+use diesel_ulid::DieselUlid;
+use s3s::dto::{Bucket, Timestamp};
+use std::collections::HashMap;
+
+/// Projects listed by ListBuckets, in the order of `projects` (sorted by name)
+///
+/// A project is listed if the access key has at least read permission on the project
+/// itself, or if it is public and public projects are listed. Permissions on collections
+/// or datasets below a project do not list the project.
+pub fn accessible_buckets(
+    projects: Vec<Object>,
+    permissions: &HashMap<DieselUlid, DbPermissionLevel>,
+    include_public: bool,
+) -> Vec<Bucket> {
+    projects
+        .into_iter()
+        .filter(|project| {
+            project.object_type == ObjectType::Project
+                && project.object_status != Status::Deleted
+                && (permissions
+                    .get(&project.id)
+                    .is_some_and(|perm| perm >= &DbPermissionLevel::Read)
+                    || (include_public && project.data_class == DataClass::Public))
+        })
+        .map(|project| Bucket {
+            creation_date: project
+                .created_at
+                .and_then(|t| {
+                    time::OffsetDateTime::from_unix_timestamp(t.and_utc().timestamp()).ok()
+                })
+                .map(Timestamp::from),
+            name: Some(project.name),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str, data_class: DataClass) -> Object {
+        Object {
+            id: DieselUlid::generate(),
+            name: name.to_string(),
+            object_type: ObjectType::Project,
+            object_status: Status::Available,
+            data_class,
+            created_at: chrono::DateTime::from_timestamp(1_700_000_000, 0).map(|t| t.naive_utc()),
+            ..Default::default()
+        }
+    }
+
+    fn names(buckets: &[Bucket]) -> Vec<&str> {
+        buckets.iter().filter_map(|b| b.name.as_deref()).collect()
+    }
+
+    #[test]
+    fn test_accessible_buckets() {
+        let shared = project("a-shared", DataClass::Private);
+        let alice_ws = project("b-alice-workspace", DataClass::Workspace);
+        let bob_only = project("c-bob", DataClass::Private);
+        let public = project("d-public", DataClass::Public);
+        let projects = vec![
+            shared.clone(),
+            alice_ws.clone(),
+            bob_only.clone(),
+            public.clone(),
+        ];
+
+        let alice = HashMap::from([
+            (shared.id, DbPermissionLevel::Read),
+            (alice_ws.id, DbPermissionLevel::Admin),
+        ]);
+        let bob = HashMap::from([
+            (shared.id, DbPermissionLevel::Write),
+            (bob_only.id, DbPermissionLevel::Read),
+            // No access despite the explicit entry
+            (alice_ws.id, DbPermissionLevel::None),
+        ]);
+
+        let listed = accessible_buckets(projects.clone(), &alice, false);
+        assert_eq!(names(&listed), vec!["a-shared", "b-alice-workspace"]);
+        assert!(listed.iter().all(|b| b.creation_date.is_some()));
+
+        assert_eq!(
+            names(&accessible_buckets(projects.clone(), &bob, false)),
+            vec!["a-shared", "c-bob"]
+        );
+
+        // Public projects are only added if enabled, workspaces of others never
+        assert_eq!(
+            names(&accessible_buckets(projects, &bob, true)),
+            vec!["a-shared", "c-bob", "d-public"]
+        );
+    }
+}
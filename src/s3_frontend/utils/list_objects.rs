@@ -1,40 +1,149 @@
 use crate::caching::cache::Cache;
+use crate::s3_frontend::utils::bucket_name::key_to_path;
+use crate::s3_frontend::utils::object_key::url_encode;
 use crate::structs::{Object, ObjectLocation, ObjectType};
 use anyhow::Result;
 use aruna_rust_api::api::storage::models::v2::DataClass;
 use base64::engine::general_purpose;
 use base64::Engine;
-use chrono::NaiveDateTime;
 use diesel_ulid::DieselUlid;
+use http::HeaderValue;
 use s3s::s3_error;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use std::sync::Arc;
+use tracing::{error, warn};
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, PartialOrd, Ord)]
 pub struct Contents {
     pub key: String,
-    pub etag: DieselUlid,
+    pub etag: String,
     pub size: i64,
-    pub storage_class: DataClass,
-    pub created_at: Option<NaiveDateTime>,
+    pub storage_class: Option<String>,
+    pub last_modified: Option<time::OffsetDateTime>,
 }
 impl From<(&String, &(Object, Option<ObjectLocation>))> for Contents {
     #[tracing::instrument(level = "trace", skip(value))]
     fn from(value: (&String, &(Object, Option<ObjectLocation>))) -> Self {
         Contents {
             key: value.0.clone(),
-            etag: value.1 .0.id,
-            size: match &value.1 .1 {
-                Some(s) => s.raw_content_len,
-                None => 0,
-            },
-            storage_class: value.1 .0.data_class,
-            created_at: value.1 .0.created_at,
+            etag: value.1 .0.get_etag(value.1 .1.as_ref()),
+            size: value.1 .0.get_raw_size(value.1 .1.as_ref()),
+            storage_class: value.1 .1.as_ref().and_then(|l| l.storage_class.clone()),
+            last_modified: value.1 .0.get_last_modified(),
         }
     }
 }
 
-#[tracing::instrument(level = "trace", skip(cache, delimiter, prefix, start_at, max_keys))]
+/// Classification of a path relative to the listed prefix and delimiter
+#[derive(Debug, PartialEq, Eq)]
+enum PathEntry {
+    /// Sorted after every path with the prefix, the listing is complete
+    Beyond,
+    /// Rolled up into the common prefix (terminated with the delimiter)
+    CommonPrefix(String),
+    /// Listed as content if it is an object
+    Key,
+}
+
+/// A single page of a listing, paths have to be added in sorted order
+///
+/// Common prefixes and contents both count towards `max_keys`. A full page stops at the
+/// first path of the next entry, the continuation token therefore never points into
+/// an already returned common prefix.
+struct ListPage<'a> {
+    prefix: &'a str,
+    delimiter: Option<&'a str>,
+    max_keys: usize,
+    keys: BTreeSet<Contents>,
+    common_prefixes: BTreeSet<String>,
+    next_token: Option<String>,
+}
+
+impl<'a> ListPage<'a> {
+    fn new(prefix: &'a str, delimiter: Option<&'a str>, max_keys: usize) -> Self {
+        Self {
+            prefix,
+            delimiter: delimiter.filter(|d| !d.is_empty()),
+            max_keys,
+            keys: BTreeSet::default(),
+            common_prefixes: BTreeSet::default(),
+            next_token: None,
+        }
+    }
+
+    fn classify(&self, path: &str) -> PathEntry {
+        let Some(stripped) = path.strip_prefix(self.prefix) else {
+            return PathEntry::Beyond;
+        };
+        match self
+            .delimiter
+            .and_then(|d| stripped.find(d).map(|idx| idx + d.len()))
+        {
+            Some(end) => PathEntry::CommonPrefix(format!("{}{}", self.prefix, &stripped[..end])),
+            None => PathEntry::Key,
+        }
+    }
+
+    fn is_full(&mut self, path: &str) -> bool {
+        if self.keys.len() + self.common_prefixes.len() < self.max_keys {
+            return false;
+        }
+        self.next_token = Some(general_purpose::STANDARD_NO_PAD.encode(path));
+        true
+    }
+
+    /// Adds the common prefix of a path, false if the page is full
+    fn add_prefix(&mut self, path: &str, common_prefix: String) -> bool {
+        if self.common_prefixes.contains(&common_prefix) {
+            return true;
+        }
+        if self.is_full(path) {
+            return false;
+        }
+        self.common_prefixes.insert(common_prefix);
+        true
+    }
+
+    /// Empty collections and datasets (e.g. created by directory markers) have no paths
+    /// below them, with the `/` delimiter they are listed as common prefix
+    fn empty_directory(&self, path: &str, object: &Object, public_only: bool) -> Option<String> {
+        let is_directory = matches!(
+            object.object_type,
+            ObjectType::Collection | ObjectType::Dataset
+        );
+        let is_empty = object.children.as_ref().map_or(true, |c| c.is_empty());
+        (self.delimiter == Some("/")
+            && is_directory
+            && is_empty
+            && (!public_only || object.data_class == DataClass::Public))
+            .then(|| format!("{path}/"))
+    }
+
+    /// Adds an object, false if the page is full
+    fn add_key(&mut self, path: &str, contents: Contents) -> bool {
+        if self.is_full(path) {
+            return false;
+        }
+        self.keys.insert(contents);
+        true
+    }
+
+    fn finish(self) -> (BTreeSet<Contents>, BTreeSet<String>, Option<String>) {
+        (self.keys, self.common_prefixes, self.next_token)
+    }
+}
+
+/// Only objects are listed as contents, collections and datasets are implicit prefixes
+fn is_listed(object: &Object, public_only: bool) -> bool {
+    object.object_type == ObjectType::Object
+        && (!public_only || object.data_class == DataClass::Public)
+}
+
+#[tracing::instrument(
+    level = "trace",
+    skip(cache, delimiter, prefix, start_at, max_keys, public_only)
+)]
 pub async fn list_response(
     cache: &Arc<Cache>,
     delimiter: &Option<String>,
@@ -42,115 +151,661 @@ pub async fn list_response(
     bucket_name: &str,
     start_at: &str,
     max_keys: usize,
+    public_only: bool,
 ) -> Result<(BTreeSet<Contents>, BTreeSet<String>, Option<String>)> {
-    let mut keys: BTreeSet<Contents> = BTreeSet::default();
-    let mut common_prefixes: BTreeSet<String> = BTreeSet::default();
-    let mut new_continuation_token: Option<String> = None;
-
-    match (delimiter.clone(), prefix.clone()) {
-        (Some(delimiter), Some(prefix)) => {
-            for (path, id) in cache.get_path_range(bucket_name, start_at) {
-                // Breaks with next path to start at after max_keys is reached
-                let num_keys = keys.len() + common_prefixes.len();
-                if num_keys == max_keys {
-                    new_continuation_token = Some(general_purpose::STANDARD_NO_PAD.encode(path));
-                    break;
-                }
+    let prefix = prefix.as_deref().unwrap_or_default();
+    let mut page = ListPage::new(prefix, delimiter.as_deref(), max_keys);
 
-                if let Some(stripped_path) = path.strip_prefix(&prefix) {
-                    if let Some((common_prefix, _)) = stripped_path.split_once(&delimiter) {
-                        common_prefixes.insert(format!(
-                            "{}{}",
-                            [prefix.to_string(), common_prefix.to_string()].join(""),
-                            delimiter
-                        ));
-                    } else {
-                        let object_with_location = cache
-                            .get_resource_cloned(&id, false)
-                            .await
-                            .map_err(|_| s3_error!(NoSuchKey, "No key found for path"))?;
-
-                        if object_with_location.0.object_type != ObjectType::Object {
-                            continue;
-                        }
-                        keys.insert((&path, &object_with_location).into());
-                    };
-                } else {
-                    continue;
-                };
-            }
-        }
-        (Some(delimiter), None) => {
-            for (path, id) in cache.get_path_range(bucket_name, start_at) {
-                // Breaks with next path to start at after max_keys is reached
-                let num_keys = keys.len() + common_prefixes.len();
-                if num_keys == max_keys {
-                    new_continuation_token = Some(general_purpose::STANDARD_NO_PAD.encode(path));
-                    break;
-                }
-
-                if let Some((common_prefix, _)) = path.split_once(&delimiter) {
-                    // Collect common prefix with delimiter at its end
-                    common_prefixes.insert(format!("{}{}", common_prefix, delimiter));
-                } else {
-                    // If None split -> Entry
-                    let object_with_location = cache
+    // Paths with the prefix are a contiguous range starting at the prefix
+    let start_at = if start_at < prefix { prefix } else { start_at };
+    for (path, id) in cache.get_path_range(bucket_name, start_at) {
+        match page.classify(&path) {
+            PathEntry::Beyond => break,
+            PathEntry::CommonPrefix(common_prefix) => {
+                // Anonymous listings do not reveal the names of private subtrees, the
+                // prefix is listed with the first visible path below it
+                if public_only && !page.common_prefixes.contains(&common_prefix) {
+                    let (object, _) = cache
                         .get_resource_cloned(&id, false)
                         .await
                         .map_err(|_| s3_error!(NoSuchKey, "No key found for path"))?;
-
-                    if object_with_location.0.object_type != ObjectType::Object {
+                    if !is_listed(&object, true)
+                        && page.empty_directory(&path, &object, true).is_none()
+                    {
                         continue;
                     }
-                    keys.insert((&path, &object_with_location).into());
-                };
+                }
+                if !page.add_prefix(&path, common_prefix) {
+                    break;
+                }
             }
-        }
-        (None, Some(prefix)) => {
-            for (path, id) in cache.get_path_range(bucket_name, start_at) {
-                // Breaks with next path to start at after max_keys is reached
-                let num_keys = keys.len() + common_prefixes.len();
-                if num_keys == max_keys {
-                    new_continuation_token = Some(general_purpose::STANDARD_NO_PAD.encode(path));
+            PathEntry::Key => {
+                let object_with_location = cache
+                    .get_resource_cloned(&id, false)
+                    .await
+                    .map_err(|_| s3_error!(NoSuchKey, "No key found for path"))?;
+                if let Some(directory) =
+                    page.empty_directory(&path, &object_with_location.0, public_only)
+                {
+                    if !page.add_prefix(&path, directory) {
+                        break;
+                    }
+                    continue;
+                }
+                if !is_listed(&object_with_location.0, public_only) {
+                    continue;
+                }
+                if !page.add_key(&path, (&path, &object_with_location).into()) {
                     break;
                 }
+            }
+        }
+    }
 
-                if path.strip_prefix(&prefix).is_some() {
-                    let object_with_location = cache
-                        .get_resource_cloned(&id, false)
-                        .await
-                        .map_err(|_| s3_error!(NoSuchKey, "No key found for path"))?;
+    Ok(page.finish())
+}
 
-                    if object_with_location.0.object_type != ObjectType::Object {
-                        continue;
-                    }
-                    keys.insert((&path, &object_with_location).into());
-                } else {
-                    continue;
+/// First path after the `marker` of ListObjects (v1), markers are exclusive
+///
+/// A marker ending with the delimiter is a returned common prefix, all paths below it
+/// were rolled up into that prefix and are skipped as well.
+pub fn marker_start(marker: &str, delimiter: Option<&str>) -> String {
+    match delimiter.filter(|d| !d.is_empty()) {
+        Some(d) if !marker.is_empty() && marker.ends_with(d) => format!("{marker}{}", char::MAX),
+        _ if marker.is_empty() => String::new(),
+        _ => format!("{marker}\0"),
+    }
+}
+
+/// Greatest listed key or common prefix of a page, the `NextMarker` of ListObjects (v1)
+pub fn last_entry(keys: &BTreeSet<Contents>, common_prefixes: &BTreeSet<String>) -> Option<String> {
+    let last_key = keys.last().map(|contents| contents.key.as_str());
+    last_key
+        .max(common_prefixes.last().map(String::as_str))
+        .map(str::to_string)
+}
+
+/// Hierarchy metadata of a listed key or common prefix, returned as JSON array in the
+/// `x-aruna-extended-listing` header if requested with `x-aruna-extended-listing: true`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListingEntry {
+    /// Url encoded key or common prefix, header values are restricted to ASCII
+    pub key: String,
+    pub id: DieselUlid,
+    /// `Object`, `Dataset` or `Collection`
+    pub resource_type: String,
+    pub data_class: String,
+    /// Number of revisions known to the proxy, the listed object is the latest
+    pub revision: usize,
+}
+
+impl ListingEntry {
+    fn new(key: &str, object: &Object, revision: usize) -> Self {
+        ListingEntry {
+            key: url_encode(key),
+            id: object.id,
+            resource_type: format!("{:?}", object.object_type),
+            data_class: object.data_class.as_str_name().to_string(),
+            revision,
+        }
+    }
+}
+
+/// Upper bound of a serialized `x-aruna-*` listing extension, common proxies and
+/// clients reject larger response headers
+pub const MAX_EXTENSION_HEADER_SIZE: usize = 16 * 1024;
+
+/// Header value of an opt-in listing extension as JSON. Extensions that exceed
+/// `MAX_EXTENSION_HEADER_SIZE` or are no valid header value are omitted, they never
+/// fail the listing itself.
+pub fn extension_header(name: &str, value: &impl Serialize) -> Option<HeaderValue> {
+    let json = serde_json::to_string(value)
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string(), header = name);
+        })
+        .ok()?;
+    if json.len() > MAX_EXTENSION_HEADER_SIZE {
+        warn!(
+            header = name,
+            size = json.len(),
+            "Listing extension exceeds the header size limit, omitted"
+        );
+        return None;
+    }
+    HeaderValue::from_str(&json)
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string(), header = name);
+        })
+        .ok()
+}
+
+/// Metadata of the contents and common prefixes of a listed page, prefixes that are
+/// not a collection or dataset (e.g. with other delimiters) are skipped
+#[tracing::instrument(level = "trace", skip(cache, keys, common_prefixes))]
+pub async fn extended_listing(
+    cache: &Arc<Cache>,
+    bucket_name: &str,
+    delimiter: Option<&str>,
+    keys: &BTreeSet<Contents>,
+    common_prefixes: &BTreeSet<String>,
+) -> Vec<ListingEntry> {
+    let mut entries = Vec::with_capacity(keys.len() + common_prefixes.len());
+    for contents in keys {
+        let Some(id) = cache.get_path(&key_to_path(bucket_name, &contents.key)) else {
+            continue;
+        };
+        let Ok((object, _)) = cache.get_resource_cloned(&id, true).await else {
+            continue;
+        };
+        let revision = cache.get_revisions(&id).await.len().max(1);
+        entries.push(ListingEntry::new(&contents.key, &object, revision));
+    }
+    for common_prefix in common_prefixes {
+        let path = key_to_path(
+            bucket_name,
+            common_prefix.trim_end_matches(delimiter.unwrap_or("/")),
+        );
+        let Some(id) = cache.get_path(&path) else {
+            continue;
+        };
+        let Ok((object, _)) = cache.get_resource_cloned(&id, true).await else {
+            continue;
+        };
+        if object.object_type == ObjectType::Object {
+            continue;
+        }
+        entries.push(ListingEntry::new(common_prefix, &object, 1));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::replication::replication_handler::ReplicationMessage;
+    use crate::s3_frontend::utils::bucket_name::strip_duplicated_bucket;
+    use crate::structs::TypedRelation;
+    use async_channel::Receiver;
+
+    /// Bucket relative paths of a project with two and three level deep hierarchies
+    const HIERARCHY: &[(&str, ObjectType)] = &[
+        ("coll", ObjectType::Collection),
+        ("coll/ds", ObjectType::Dataset),
+        ("coll/ds/a.txt", ObjectType::Object),
+        ("coll/ds/b.txt", ObjectType::Object),
+        ("coll/ds/sub/c.txt", ObjectType::Object),
+        ("coll/d.txt", ObjectType::Object),
+        ("coll2", ObjectType::Collection),
+        ("coll2/e.txt", ObjectType::Object),
+        ("f.txt", ObjectType::Object),
+        ("g-h-i.txt", ObjectType::Object),
+    ];
+
+    /// Cache with the project `bucket` and the given paths below it, parents have to be
+    /// listed before their children. Segments below a dataset are part of the object name.
+    async fn project(
+        bucket: &str,
+        paths: &[(&str, ObjectType)],
+    ) -> (Arc<Cache>, Receiver<ReplicationMessage>) {
+        let (cache, receiver) = test_cache(None).await;
+        let project = Object::initialize_now(bucket.to_string(), ObjectType::Project, None);
+        let mut objects: Vec<(&str, Object)> = Vec::new();
+        for (path, object_type) in paths {
+            let parent = objects
+                .iter_mut()
+                .filter(|(parent, object)| {
+                    object.object_type != ObjectType::Object
+                        && path.starts_with(&format!("{parent}/"))
+                })
+                .max_by_key(|(parent, _)| parent.len());
+            let (name, relation) = match &parent {
+                Some((parent_path, parent)) => (
+                    &path[parent_path.len() + 1..],
+                    match parent.object_type {
+                        ObjectType::Collection => TypedRelation::Collection(parent.id),
+                        _ => TypedRelation::Dataset(parent.id),
+                    },
+                ),
+                None => (*path, TypedRelation::Project(project.id)),
+            };
+            let object =
+                Object::initialize_now(name.to_string(), object_type.clone(), Some(relation));
+            if let Some((_, parent)) = parent {
+                let child = match object_type {
+                    ObjectType::Collection => TypedRelation::Collection(object.id),
+                    ObjectType::Dataset => TypedRelation::Dataset(object.id),
+                    _ => TypedRelation::Object(object.id),
                 };
+                parent
+                    .children
+                    .get_or_insert_with(Default::default)
+                    .insert(child);
             }
+            objects.push((*path, object));
         }
-        (None, None) => {
-            for (path, id) in cache.get_path_range(bucket_name, start_at) {
-                // Breaks with next path to start at after max_keys is reached
-                let num_keys = keys.len() + common_prefixes.len();
-                if num_keys == max_keys {
-                    new_continuation_token = Some(general_purpose::STANDARD_NO_PAD.encode(path));
-                    break;
-                }
+        cache.upsert_object(project).await.unwrap();
+        for (_, object) in objects {
+            cache.upsert_object(object).await.unwrap();
+        }
+        (cache, receiver)
+    }
 
-                let object_with_location = cache
-                    .get_resource_cloned(&id, false)
-                    .await
-                    .map_err(|_| s3_error!(NoSuchKey, "No key found for path"))?;
+    async fn list(
+        cache: &Arc<Cache>,
+        prefix: &str,
+        delimiter: Option<&str>,
+        start_at: &str,
+        max_keys: usize,
+    ) -> (Vec<String>, Vec<String>, Option<String>) {
+        let (keys, prefixes, token) = list_response(
+            cache,
+            &delimiter.map(str::to_string),
+            &Some(prefix.to_string()),
+            "bucket",
+            start_at,
+            max_keys,
+            false,
+        )
+        .await
+        .unwrap();
+        let token = token.map(|t| {
+            String::from_utf8(general_purpose::STANDARD_NO_PAD.decode(t).unwrap()).unwrap()
+        });
+        (
+            keys.into_iter().map(|k| k.key).collect(),
+            prefixes.into_iter().collect(),
+            token,
+        )
+    }
 
-                if object_with_location.0.object_type != ObjectType::Object {
-                    continue;
+    /// Follows the continuation tokens and collects all pages
+    async fn list_all(
+        cache: &Arc<Cache>,
+        prefix: &str,
+        delimiter: Option<&str>,
+        max_keys: usize,
+    ) -> (Vec<String>, Vec<String>) {
+        let (mut all_keys, mut all_prefixes) = (Vec::new(), Vec::new());
+        let mut start_at = String::new();
+        loop {
+            let (keys, prefixes, token) = list(cache, prefix, delimiter, &start_at, max_keys).await;
+            assert!(keys.len() + prefixes.len() <= max_keys);
+            all_keys.extend(keys);
+            all_prefixes.extend(prefixes);
+            match token {
+                Some(token) => start_at = token,
+                None => return (all_keys, all_prefixes),
+            }
+        }
+    }
+
+    /// Follows the markers of ListObjects (v1) and collects all pages
+    async fn list_all_v1(
+        cache: &Arc<Cache>,
+        prefix: &str,
+        delimiter: Option<&str>,
+        max_keys: usize,
+    ) -> (Vec<String>, Vec<String>) {
+        let (mut all_keys, mut all_prefixes) = (Vec::new(), Vec::new());
+        let mut marker = String::new();
+        loop {
+            let start_at = marker_start(&marker, delimiter);
+            let (keys, prefixes, token) = list_response(
+                cache,
+                &delimiter.map(str::to_string),
+                &Some(prefix.to_string()),
+                "bucket",
+                &start_at,
+                max_keys,
+                false,
+            )
+            .await
+            .unwrap();
+            assert!(keys.len() + prefixes.len() <= max_keys);
+            let next_marker = last_entry(&keys, &prefixes);
+            all_keys.extend(keys.into_iter().map(|k| k.key));
+            all_prefixes.extend(prefixes);
+            match (token, next_marker) {
+                (Some(_), Some(next_marker)) => marker = next_marker,
+                _ => return (all_keys, all_prefixes),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn delimiter_rolls_up_hierarchy() {
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, token) = list(&cache, "", Some("/"), "", 1000).await;
+        assert_eq!(keys, vec!["f.txt", "g-h-i.txt"]);
+        assert_eq!(prefixes, vec!["coll/", "coll2/"]);
+        assert!(token.is_none());
+    }
+
+    #[tokio::test]
+    async fn delimiter_two_levels_deep() {
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, _) = list(&cache, "coll/", Some("/"), "", 1000).await;
+        assert_eq!(keys, vec!["coll/d.txt"]);
+        assert_eq!(prefixes, vec!["coll/ds/"]);
+    }
+
+    #[tokio::test]
+    async fn delimiter_three_levels_deep() {
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, _) = list(&cache, "coll/ds/", Some("/"), "", 1000).await;
+        assert_eq!(keys, vec!["coll/ds/a.txt", "coll/ds/b.txt"]);
+        assert_eq!(prefixes, vec!["coll/ds/sub/"]);
+    }
+
+    #[tokio::test]
+    async fn without_delimiter_only_objects_are_listed() {
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, _) = list(&cache, "coll", None, "", 1000).await;
+        assert_eq!(
+            keys,
+            vec![
+                "coll/d.txt",
+                "coll/ds/a.txt",
+                "coll/ds/b.txt",
+                "coll/ds/sub/c.txt",
+                "coll2/e.txt"
+            ]
+        );
+        assert!(prefixes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn other_delimiters() {
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, _) = list(&cache, "", Some("-"), "", 1000).await;
+        assert!(keys.contains(&"coll/ds/a.txt".to_string()));
+        assert!(!keys.contains(&"g-h-i.txt".to_string()));
+        assert_eq!(prefixes, vec!["g-"]);
+
+        let (keys, prefixes, _) = list(&cache, "g-", Some("-"), "", 1000).await;
+        assert!(keys.is_empty());
+        assert_eq!(prefixes, vec!["g-h-"]);
+    }
+
+    #[tokio::test]
+    async fn pagination_counts_prefixes_and_keys() {
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, token) = list(&cache, "", Some("/"), "", 1).await;
+        assert!(keys.is_empty());
+        assert_eq!(prefixes, vec!["coll/"]);
+        assert_eq!(token.as_deref(), Some("coll2/e.txt"));
+
+        for max_keys in 1..5 {
+            let (keys, prefixes) = list_all(&cache, "", Some("/"), max_keys).await;
+            assert_eq!(keys, vec!["f.txt", "g-h-i.txt"]);
+            assert_eq!(prefixes, vec!["coll/", "coll2/"]);
+
+            let (keys, prefixes) = list_all(&cache, "coll/ds/", Some("/"), max_keys).await;
+            assert_eq!(keys, vec!["coll/ds/a.txt", "coll/ds/b.txt"]);
+            assert_eq!(prefixes, vec!["coll/ds/sub/"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn marker_is_exclusive() {
+        assert_eq!(marker_start("", Some("/")), "");
+        assert_eq!(marker_start("f.txt", Some("/")), "f.txt\0");
+        assert_eq!(marker_start("coll/", None), "coll/\0");
+        assert_eq!(
+            marker_start("coll/", Some("/")),
+            format!("coll/{}", char::MAX)
+        );
+
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        let (keys, prefixes, _) = list(
+            &cache,
+            "",
+            Some("/"),
+            &marker_start("coll/", Some("/")),
+            1000,
+        )
+        .await;
+        assert_eq!(keys, vec!["f.txt", "g-h-i.txt"]);
+        assert_eq!(prefixes, vec!["coll2/"]);
+
+        let (keys, _, _) = list(
+            &cache,
+            "coll/ds/",
+            None,
+            &marker_start("coll/ds/a.txt", None),
+            1000,
+        )
+        .await;
+        assert_eq!(keys, vec!["coll/ds/b.txt", "coll/ds/sub/c.txt"]);
+    }
+
+    #[tokio::test]
+    async fn v1_and_v2_pagination_list_the_same_entries() {
+        let (empty, _empty_receiver) = project("bucket", &[]).await;
+        let (cache, _receiver) = project("bucket", HIERARCHY).await;
+        for cache in [&empty, &cache] {
+            for prefix in ["", "coll", "coll/", "coll/ds/", "g-", "missing/"] {
+                for delimiter in [None, Some("/"), Some("-")] {
+                    let expected = list_all(cache, prefix, delimiter, 1000).await;
+                    for max_keys in 1..6 {
+                        let v2 = list_all(cache, prefix, delimiter, max_keys).await;
+                        let v1 = list_all_v1(cache, prefix, delimiter, max_keys).await;
+                        assert_eq!(v2, expected, "v2 {prefix:?} {delimiter:?} {max_keys}");
+                        assert_eq!(v1, expected, "v1 {prefix:?} {delimiter:?} {max_keys}");
+                    }
                 }
-                keys.insert((&path, &object_with_location).into());
+            }
+        }
+        assert_eq!(
+            list_all_v1(&empty, "", Some("/"), 1).await,
+            (vec![], vec![])
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_directories_are_prefixes() {
+        let mut paths = HIERARCHY.to_vec();
+        paths.extend([
+            ("empty", ObjectType::Collection),
+            ("coll2/empty-ds", ObjectType::Dataset),
+        ]);
+        let (cache, _receiver) = project("bucket", &paths).await;
+        let (keys, prefixes, _) = list(&cache, "", Some("/"), "", 1000).await;
+        assert_eq!(keys, vec!["f.txt", "g-h-i.txt"]);
+        assert_eq!(prefixes, vec!["coll/", "coll2/", "empty/"]);
+
+        let (keys, prefixes, _) = list(&cache, "coll2/", Some("/"), "", 1000).await;
+        assert_eq!(keys, vec!["coll2/e.txt"]);
+        assert_eq!(prefixes, vec!["coll2/empty-ds/"]);
+
+        // Without delimiter only objects are listed
+        let (keys, prefixes, _) = list(&cache, "", None, "", 1000).await;
+        assert!(!keys.iter().any(|key| key.starts_with("empty")));
+        assert!(prefixes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keys_round_trip_at_every_depth() {
+        let bucket = "my-little-project";
+        let (cache, _receiver) = project(bucket, HIERARCHY).await;
+
+        for prefix in ["", "coll/", "coll/ds/", "coll/ds/sub/"] {
+            let (keys, _, _) = list_response(
+                &cache,
+                &None,
+                &Some(prefix.to_string()),
+                bucket,
+                "",
+                1000,
+                false,
+            )
+            .await
+            .unwrap();
+            assert!(!keys.is_empty());
+            for key in keys.into_iter().map(|k| k.key) {
+                // Listed keys never repeat the project name and download by the same path
+                assert!(!key.starts_with(bucket));
+                assert!(cache.get_path(&key_to_path(bucket, &key)).is_some());
+                // The duplicated form does not resolve, but hints at the listed key
+                let duplicated = format!("{bucket}/{key}");
+                assert!(cache.get_path(&key_to_path(bucket, &duplicated)).is_none());
+                assert_eq!(
+                    strip_duplicated_bucket(bucket, &duplicated),
+                    Some(key.as_str())
+                );
             }
         }
     }
 
-    Ok((keys, common_prefixes, new_continuation_token))
+    #[tokio::test]
+    async fn extended_listing_matches_cache() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let dataset = Object::initialize_now(
+            "ds".to_string(),
+            ObjectType::Dataset,
+            Some(TypedRelation::Project(project.id)),
+        );
+        let mut object = Object::initialize_now(
+            "a b.txt".to_string(),
+            ObjectType::Object,
+            Some(TypedRelation::Dataset(dataset.id)),
+        );
+        object.data_class = DataClass::Public;
+        for resource in [&project, &dataset, &object] {
+            cache.upsert_object(resource.clone()).await.unwrap();
+        }
+
+        let (keys, common_prefixes, _) =
+            list_response(&cache, &None, &None, "bucket", "", 1000, false)
+                .await
+                .unwrap();
+        let (_, with_delimiter, _) = list_response(
+            &cache,
+            &Some("/".to_string()),
+            &None,
+            "bucket",
+            "",
+            1000,
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(common_prefixes.is_empty());
+
+        let entries = extended_listing(&cache, "bucket", Some("/"), &keys, &with_delimiter).await;
+        // Transferred as JSON header value
+        let header = serde_json::to_string(&entries).unwrap();
+        assert!(header.is_ascii());
+        let parsed: Vec<ListingEntry> = serde_json::from_str(&header).unwrap();
+        assert_eq!(parsed.len(), 2);
+
+        assert_eq!(parsed[0].key, "ds/a%20b.txt");
+        assert_eq!(parsed[0].id, object.id);
+        assert_eq!(Some(parsed[0].id), cache.get_path("bucket/ds/a b.txt"));
+        assert_eq!(parsed[0].resource_type, "Object");
+        assert_eq!(parsed[0].data_class, "PUBLIC");
+        assert_eq!(parsed[0].revision, 1);
+
+        assert_eq!(parsed[1].key, "ds/");
+        assert_eq!(Some(parsed[1].id), cache.get_path("bucket/ds"));
+        assert_eq!(parsed[1].resource_type, "Dataset");
+    }
+
+    #[tokio::test]
+    async fn anonymous_listings_hide_private_prefixes() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        cache.upsert_object(project.clone()).await.unwrap();
+        // Collection name -> data classes of the objects below it
+        for (name, classes) in [
+            ("public", vec![DataClass::Public]),
+            ("private", vec![DataClass::Private]),
+            ("mixed", vec![DataClass::Private, DataClass::Public]),
+        ] {
+            let mut collection = Object::initialize_now(
+                name.to_string(),
+                ObjectType::Collection,
+                Some(TypedRelation::Project(project.id)),
+            );
+            collection.data_class = DataClass::Public;
+            let objects = classes
+                .into_iter()
+                .enumerate()
+                .map(|(idx, class)| {
+                    let mut object = Object::initialize_now(
+                        format!("{idx}.txt"),
+                        ObjectType::Object,
+                        Some(TypedRelation::Collection(collection.id)),
+                    );
+                    object.data_class = class;
+                    object
+                })
+                .collect::<Vec<_>>();
+            collection.children = Some(
+                objects
+                    .iter()
+                    .map(|object| TypedRelation::Object(object.id))
+                    .collect(),
+            );
+            cache.upsert_object(collection).await.unwrap();
+            for object in objects {
+                cache.upsert_object(object).await.unwrap();
+            }
+        }
+        // Empty public collections are visible as directory marker
+        let mut empty = Object::initialize_now(
+            "empty".to_string(),
+            ObjectType::Collection,
+            Some(TypedRelation::Project(project.id)),
+        );
+        empty.data_class = DataClass::Public;
+        cache.upsert_object(empty).await.unwrap();
+
+        let delimiter = Some("/".to_string());
+        let prefixes = |public_only, max_keys| {
+            let cache = cache.clone();
+            let delimiter = delimiter.clone();
+            async move {
+                let mut all = Vec::new();
+                let mut start_at = String::new();
+                loop {
+                    let (_, prefixes, token) = list_response(
+                        &cache,
+                        &delimiter,
+                        &None,
+                        "bucket",
+                        &start_at,
+                        max_keys,
+                        public_only,
+                    )
+                    .await
+                    .unwrap();
+                    all.extend(prefixes);
+                    match token {
+                        Some(token) => {
+                            start_at = String::from_utf8(
+                                general_purpose::STANDARD_NO_PAD.decode(token).unwrap(),
+                            )
+                            .unwrap()
+                        }
+                        None => return all,
+                    }
+                }
+            }
+        };
+        assert_eq!(
+            prefixes(false, 1000).await,
+            vec!["empty/", "mixed/", "private/", "public/"]
+        );
+        for max_keys in [1, 2, 1000] {
+            assert_eq!(
+                prefixes(true, max_keys).await,
+                vec!["empty/", "mixed/", "public/"]
+            );
+        }
+    }
 }
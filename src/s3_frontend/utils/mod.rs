@@ -1,5 +1,24 @@
+pub mod acl;
+pub mod aws_chunked;
+pub mod bandwidth;
+pub mod bucket_name;
 pub mod buffered_s3_sink;
+pub mod checksum;
+pub mod compression;
+pub mod conditional;
+pub mod cors;
 pub mod debug_transformer;
+pub mod expect_continue;
+pub mod list_buckets;
 pub mod list_objects;
+pub mod multipart;
+pub mod object_key;
 pub mod ranges;
+pub mod rate_limit;
 pub mod replication_sink;
+pub mod response_overrides;
+pub mod select;
+pub mod sse_c;
+pub mod tls;
+pub mod tree;
+pub mod upload_limits;
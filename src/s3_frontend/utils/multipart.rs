@@ -0,0 +1,443 @@
+use super::checksum::CompositeChecksum;
+use crate::structs::{ContentChecksum, PartETag, UploadPart};
+use bytes::{Bytes, BytesMut};
+use http::{Method, Request};
+use hyper::body::HttpBody;
+use md5::{Digest, Md5};
+use s3s::dto::CompletedPart;
+use s3s::{s3_error, S3Result};
+use tracing::error;
+
+/// Maximum number of parts of a multipart upload
+pub const MAX_PARTS: usize = 10_000;
+/// Minimum size of every part except the last one
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// Upper bound of a CompleteMultipartUpload body, a part with etag and all
+/// checksums takes less than 1 KiB
+pub const MAX_COMPLETE_BODY_SIZE: usize = MAX_PARTS * 1024;
+
+/// Whether the request is a CompleteMultipartUpload (`POST /<key>?uploadId=...`)
+pub fn is_complete_multipart_upload<B>(req: &Request<B>) -> bool {
+    req.method() == Method::POST
+        && req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .any(|param| param.split('=').next() == Some("uploadId"))
+}
+
+/// Reads the body of a CompleteMultipartUpload, oversized bodies and part lists are
+/// rejected before s3s deserializes the complete list
+#[tracing::instrument(level = "trace", skip(body))]
+pub async fn read_complete_body(mut body: hyper::Body) -> S3Result<Bytes> {
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            s3_error!(IncompleteBody, "Unable to read the request body")
+        })?;
+        if buffer.len() + chunk.len() > MAX_COMPLETE_BODY_SIZE {
+            error!(error = "CompleteMultipartUpload body too large");
+            return Err(s3_error!(
+                MaxMessageLengthExceeded,
+                "The part list exceeds {} bytes",
+                MAX_COMPLETE_BODY_SIZE
+            ));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    let part_count = count_parts(&buffer);
+    if part_count > MAX_PARTS {
+        error!(error = "Too many parts", parts = part_count);
+        return Err(s3_error!(
+            InvalidArgument,
+            "Number of parts exceeds the limit of {}",
+            MAX_PARTS
+        ));
+    }
+    Ok(buffer.freeze())
+}
+
+/// Number of `<Part>` elements of a CompleteMultipartUpload body
+fn count_parts(body: &[u8]) -> usize {
+    body.windows(6)
+        .filter(|tag| {
+            tag.starts_with(b"<Part")
+                && matches!(tag[5], b'>' | b'/' | b' ' | b'\t' | b'\r' | b'\n')
+        })
+        .count()
+}
+
+/// Requested parts of a CompleteMultipartUpload matched with the stored part records
+#[derive(Debug)]
+pub struct CompletedParts {
+    /// Backend etags of the parts, the backend needs the full list
+    pub backend_parts: Vec<PartETag>,
+    /// Stored parts that are not part of the object
+    pub unreferenced: Vec<u64>,
+    pub raw_size: u64,
+    pub disk_size: u64,
+    /// md5-of-md5s-<partcount>, only if all parts have a known md5
+    pub etag: Option<String>,
+    /// Composite checksum, only if all parts carry a checksum of the same algorithm
+    pub checksum: Option<ContentChecksum>,
+}
+
+/// State of the composite checksum while the parts are merged
+enum Composite {
+    Empty,
+    Running(CompositeChecksum),
+    Unavailable,
+}
+
+/// Matches the requested parts with the stored records (both sorted by part number)
+/// in a single pass
+///
+/// Hashes and checksums are calculated incrementally, apart from the part list for
+/// the backend only a single part record is held at a time.
+#[tracing::instrument(level = "trace", skip(requested, stored))]
+pub fn merge_parts<I>(requested: I, stored: &[UploadPart]) -> S3Result<CompletedParts>
+where
+    I: IntoIterator<Item = CompletedPart>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let requested = requested.into_iter();
+    // Reject oversized part lists before doing any further work
+    let part_count = requested.len();
+    if part_count > MAX_PARTS {
+        error!(error = "Too many parts", parts = part_count);
+        return Err(s3_error!(
+            InvalidArgument,
+            "Number of parts exceeds the limit of {}",
+            MAX_PARTS
+        ));
+    }
+
+    let mut stored = stored.iter().peekable();
+    let mut unreferenced = Vec::new();
+    let mut backend_parts = Vec::with_capacity(part_count);
+    let mut md5s = Some(Md5::new());
+    let mut composite = Composite::Empty;
+    let mut previous = None;
+    let (mut raw_size, mut disk_size) = (0, 0);
+    for (idx, part) in requested.enumerate() {
+        let part_number = part.part_number.ok_or_else(|| {
+            error!(error = "part_number must be specified");
+            s3_error!(InvalidPart, "part_number must be specified")
+        })?;
+        let etag = part.e_tag.as_deref().ok_or_else(|| {
+            error!(error = "etag must be specified");
+            s3_error!(InvalidPart, "etag must be specified")
+        })?;
+        // Parts must be listed in strictly ascending order
+        if previous.is_some_and(|previous| previous >= part_number) {
+            error!(error = "Parts must be in ascending order", part_number);
+            return Err(s3_error!(
+                InvalidPartOrder,
+                "Part {} is not in ascending order",
+                part_number
+            ));
+        }
+        previous = Some(part_number);
+
+        let requested_number = part_number as u64;
+        while let Some(record) = stored.next_if(|p| p.part_number < requested_number) {
+            unreferenced.push(record.part_number);
+        }
+        // Re-uploaded parts are stored multiple times, the latest one counts
+        let mut matched = None;
+        while let Some(record) = stored.next_if(|p| p.part_number == requested_number) {
+            matched = Some(record);
+        }
+        let Some(record) = matched else {
+            error!(error = "Part not found", part_number = requested_number);
+            return Err(s3_error!(
+                InvalidPart,
+                "Part {} was not uploaded",
+                requested_number
+            ));
+        };
+        if idx + 1 != part_count && record.raw_size < MIN_PART_SIZE {
+            error!(error = "Part too small", part_number = requested_number);
+            return Err(s3_error!(
+                EntityTooSmall,
+                "Part {} is smaller than the minimum part size of {} bytes",
+                requested_number,
+                MIN_PART_SIZE
+            ));
+        }
+        // Parts uploaded before md5 etags were stored are matched by their backend etag
+        let expected_etag = if record.md5.is_empty() {
+            record.etag.trim_matches('"')
+        } else {
+            &record.md5
+        };
+        if etag.trim_matches('"') != expected_etag {
+            error!(error = "Part etag mismatch", part_number = requested_number);
+            return Err(s3_error!(
+                InvalidPart,
+                "ETag of part {} does not match",
+                requested_number
+            ));
+        }
+        if record.md5.is_empty() {
+            md5s = None;
+        } else {
+            let md5 = hex::decode(&record.md5).map_err(|_| {
+                error!(error = "Invalid stored part md5");
+                s3_error!(InternalError, "Invalid stored part md5")
+            })?;
+            if let Some(md5s) = md5s.as_mut() {
+                md5s.update(md5);
+            }
+        }
+        let requested_checksum = [
+            part.checksum_crc32,
+            part.checksum_crc32c,
+            part.checksum_sha1,
+            part.checksum_sha256,
+        ]
+        .into_iter()
+        .flatten()
+        .next();
+        if let Some(requested_checksum) = requested_checksum {
+            if record.checksum.as_ref().map(|c| &c.value) != Some(&requested_checksum) {
+                error!(
+                    error = "Part checksum mismatch",
+                    part_number = requested_number
+                );
+                return Err(s3_error!(
+                    InvalidPart,
+                    "Checksum of part {} does not match",
+                    requested_number
+                ));
+            }
+        }
+        composite = match (composite, &record.checksum) {
+            (Composite::Empty, Some(checksum)) => {
+                let mut composite = CompositeChecksum::new(checksum.algorithm);
+                update_composite(&mut composite, checksum)?;
+                Composite::Running(composite)
+            }
+            (Composite::Running(mut composite), Some(checksum))
+                if composite.algorithm() == checksum.algorithm =>
+            {
+                update_composite(&mut composite, checksum)?;
+                Composite::Running(composite)
+            }
+            _ => Composite::Unavailable,
+        };
+
+        backend_parts.push(PartETag {
+            part_number,
+            etag: record.etag.clone(),
+        });
+        raw_size += record.raw_size;
+        disk_size += record.size;
+    }
+    unreferenced.extend(stored.map(|p| p.part_number));
+
+    let etag = md5s.map(|md5s| format!("{}-{}", hex::encode(md5s.finalize()), part_count));
+    let checksum = match composite {
+        Composite::Running(composite) => Some(ContentChecksum {
+            algorithm: composite.algorithm(),
+            value: composite.finalize(),
+        }),
+        _ => None,
+    };
+    Ok(CompletedParts {
+        backend_parts,
+        unreferenced,
+        raw_size,
+        disk_size,
+        etag,
+        checksum,
+    })
+}
+
+fn update_composite(composite: &mut CompositeChecksum, checksum: &ContentChecksum) -> S3Result<()> {
+    composite.update(&checksum.value).map_err(|e| {
+        error!(error = ?e, msg = e.to_string());
+        s3_error!(InternalError, "Unable to calculate checksum")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::ContentChecksumAlgorithm;
+    use diesel_ulid::DieselUlid;
+    use s3s::S3ErrorCode;
+
+    fn record(part_number: u64, raw_size: u64, md5: String) -> UploadPart {
+        UploadPart {
+            id: DieselUlid::generate(),
+            object_id: DieselUlid::generate(),
+            upload_id: "upload".to_string(),
+            part_number,
+            raw_size,
+            size: raw_size + 28,
+            etag: format!("backend-{part_number}"),
+            md5,
+            checksum: None,
+        }
+    }
+
+    fn requested(part_number: i32, etag: &str) -> CompletedPart {
+        CompletedPart {
+            part_number: Some(part_number),
+            e_tag: Some(format!("\"{etag}\"")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_max_parts() {
+        // Fixture of the maximum number of parts, a re-upload and an unreferenced part
+        let md5 = |part_number: u64| hex::encode(Md5::digest(part_number.to_be_bytes()));
+        let mut stored = (1..=MAX_PARTS as u64 + 1)
+            .map(|number| record(number, MIN_PART_SIZE, md5(number)))
+            .collect::<Vec<_>>();
+        stored.insert(0, record(1, MIN_PART_SIZE, "0".repeat(32)));
+        let parts = (1..=MAX_PARTS as i32)
+            .map(|number| requested(number, &md5(number as u64)))
+            .collect::<Vec<_>>();
+
+        let completed = merge_parts(parts, &stored).unwrap();
+
+        assert_eq!(completed.backend_parts.len(), MAX_PARTS);
+        assert_eq!(completed.backend_parts[0].etag, "backend-1");
+        assert_eq!(completed.unreferenced, vec![MAX_PARTS as u64 + 1]);
+        assert_eq!(completed.raw_size, MAX_PARTS as u64 * MIN_PART_SIZE);
+        assert_eq!(completed.disk_size, MAX_PARTS as u64 * (MIN_PART_SIZE + 28));
+        let mut md5s = Md5::new();
+        for number in 1..=MAX_PARTS as u64 {
+            md5s.update(hex::decode(md5(number)).unwrap());
+        }
+        assert_eq!(
+            completed.etag,
+            Some(format!("{}-{}", hex::encode(md5s.finalize()), MAX_PARTS))
+        );
+        assert_eq!(completed.checksum, None);
+
+        let too_many = (1..=MAX_PARTS as i32 + 1)
+            .map(|number| requested(number, "etag"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            merge_parts(too_many, &stored).unwrap_err().code(),
+            &S3ErrorCode::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn test_merge_rejects_invalid_parts() {
+        let stored = vec![
+            record(1, MIN_PART_SIZE, String::new()),
+            record(2, 1, String::new()),
+            record(3, 1, String::new()),
+        ];
+        let error = |parts: Vec<CompletedPart>| merge_parts(parts, &stored).unwrap_err();
+        assert_eq!(
+            error(vec![requested(1, "backend-1"), requested(1, "backend-1")]).code(),
+            &S3ErrorCode::InvalidPartOrder
+        );
+        assert_eq!(
+            error(vec![requested(4, "backend-4")]).code(),
+            &S3ErrorCode::InvalidPart
+        );
+        assert_eq!(
+            error(vec![requested(2, "backend-2"), requested(3, "backend-3")]).code(),
+            &S3ErrorCode::EntityTooSmall
+        );
+
+        // Legacy parts without md5 have no multipart etag
+        let completed = merge_parts(
+            vec![requested(1, "backend-1"), requested(3, "backend-3")],
+            &stored,
+        )
+        .unwrap();
+        assert_eq!(completed.etag, None);
+        assert_eq!(completed.unreferenced, vec![2]);
+    }
+
+    #[test]
+    fn test_merge_legacy_parts_by_backend_etag() {
+        let stored = vec![
+            record(1, MIN_PART_SIZE, String::new()),
+            record(2, 1, hex::encode(Md5::digest(b"part"))),
+        ];
+        let md5 = hex::encode(Md5::digest(b"part"));
+        let completed =
+            merge_parts(vec![requested(1, "backend-1"), requested(2, &md5)], &stored).unwrap();
+        assert_eq!(completed.backend_parts[0].etag, "backend-1");
+        assert_eq!(completed.etag, None);
+
+        // Neither another backend etag nor an md5 match a legacy part
+        for etag in ["backend-2", md5.as_str()] {
+            assert_eq!(
+                merge_parts(vec![requested(1, etag), requested(2, &md5)], &stored)
+                    .unwrap_err()
+                    .code(),
+                &S3ErrorCode::InvalidPart
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_complete_body_is_bounded() {
+        let body = |parts: usize| {
+            let parts = (1..=parts)
+                .map(|number| {
+                    format!("<Part><PartNumber>{number}</PartNumber><ETag>\"etag\"</ETag></Part>")
+                })
+                .collect::<String>();
+            format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>")
+        };
+        let read = |body: String| read_complete_body(hyper::Body::from(body));
+
+        let accepted = body(MAX_PARTS);
+        assert_eq!(read(accepted.clone()).await.unwrap(), accepted);
+        assert_eq!(
+            read(body(MAX_PARTS + 1)).await.unwrap_err().code(),
+            &S3ErrorCode::InvalidArgument
+        );
+        assert_eq!(
+            read(" ".repeat(MAX_COMPLETE_BODY_SIZE + 1))
+                .await
+                .unwrap_err()
+                .code(),
+            &S3ErrorCode::MaxMessageLengthExceeded
+        );
+    }
+
+    #[test]
+    fn test_merge_composite_checksum() {
+        let checksum = |algorithm, value: &str| {
+            Some(ContentChecksum {
+                algorithm,
+                value: value.to_string(),
+            })
+        };
+        let mut stored = vec![
+            record(1, MIN_PART_SIZE, String::new()),
+            record(2, 1, String::new()),
+        ];
+        stored[0].checksum = checksum(ContentChecksumAlgorithm::Crc32, "AAAAAA==");
+        stored[1].checksum = checksum(ContentChecksumAlgorithm::Crc32, "AAAAAQ==");
+        let parts = || vec![requested(1, "backend-1"), requested(2, "backend-2")];
+        let completed = merge_parts(parts(), &stored).unwrap();
+        let mut expected = CompositeChecksum::new(ContentChecksumAlgorithm::Crc32);
+        expected.update("AAAAAA==").unwrap();
+        expected.update("AAAAAQ==").unwrap();
+        assert_eq!(
+            completed.checksum,
+            checksum(ContentChecksumAlgorithm::Crc32, &expected.finalize())
+        );
+        assert!(completed.checksum.unwrap().value.ends_with("-2"));
+
+        // Mixed algorithms have no composite checksum
+        stored[1].checksum = checksum(ContentChecksumAlgorithm::Crc32c, "AAAAAQ==");
+        assert_eq!(merge_parts(parts(), &stored).unwrap().checksum, None);
+    }
+}
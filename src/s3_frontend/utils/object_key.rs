@@ -0,0 +1,198 @@
+use s3s::dto::EncodingType;
+use s3s::{s3_error, S3Error};
+use tracing::error;
+
+/// Longest key accepted by S3, in bytes of the UTF-8 encoding
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// Rejects keys that cannot be stored or listed safely
+///
+/// Keys are split at `/` into cache paths and backend locations, relative (`.`, `..`)
+/// and empty segments would address other resources than the key itself.
+/// A trailing `/` (folder markers of S3 clients) is allowed.
+pub fn validate_key(key: &str) -> Result<(), S3Error> {
+    if key.len() > MAX_KEY_LENGTH {
+        error!(len = key.len(), "Object key too long");
+        return Err(s3_error!(
+            InvalidArgument,
+            "Object key exceeds {MAX_KEY_LENGTH} bytes at byte {MAX_KEY_LENGTH}"
+        ));
+    }
+    if let Some((pos, c)) = key.char_indices().find(|(_, c)| c.is_control()) {
+        error!(pos, "Control character in object key");
+        return Err(s3_error!(
+            InvalidArgument,
+            "Object key contains the control character U+{:04X} at byte {pos}",
+            c as u32
+        ));
+    }
+    let segments = key.split('/').count();
+    let mut pos = 0;
+    for (idx, segment) in key.split('/').enumerate() {
+        match segment {
+            "." | ".." => {
+                error!(pos, "Relative segment in object key");
+                return Err(s3_error!(
+                    InvalidURI,
+                    "Object key contains the relative segment '{segment}' at byte {pos}"
+                ));
+            }
+            "" if idx + 1 < segments => {
+                error!(pos, "Empty segment in object key");
+                return Err(s3_error!(
+                    InvalidURI,
+                    "Object key contains an empty segment (repeated or leading '/') at byte {pos}"
+                ));
+            }
+            _ => {}
+        }
+        pos += segment.len() + 1;
+    }
+    Ok(())
+}
+
+/// `encoding-type=url` of list requests
+pub fn is_url_encoding(encoding_type: Option<&EncodingType>) -> bool {
+    encoding_type.is_some_and(|e| e.as_str() == EncodingType::URL)
+}
+
+/// Percent-encodes a key for list responses with `encoding-type=url`
+///
+/// Only unreserved characters and `/` are kept, keys with characters that are not
+/// allowed in XML 1.0 round-trip this way.
+pub fn url_encode(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decodes a percent-encoded request path, None for invalid escapes or UTF-8
+pub fn url_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'%' => {
+                let hex = bytes
+                    .get(idx + 1..idx + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+                decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+                idx += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes a key, prefix or marker of a list response if requested
+pub fn encode_listed(value: String, url_encoding: bool) -> String {
+    if url_encoding {
+        url_encode(&value)
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use s3s::S3ErrorCode;
+
+    fn rejected(key: &str) -> (S3ErrorCode, String) {
+        let err = validate_key(key).unwrap_err();
+        (
+            err.code().clone(),
+            err.message().unwrap_or_default().to_string(),
+        )
+    }
+
+    #[test]
+    fn test_valid_keys() {
+        for key in [
+            "a.txt",
+            "coll/ds/file.txt",
+            "folder/",
+            "with space/file name.txt",
+            "a+b=c.txt",
+            "100%/done%20.txt",
+            "emoji/🦀🚀.txt",
+            "umlaut/Ärger über Öl.csv",
+            ".hidden/..dots../file.",
+        ] {
+            assert!(validate_key(key).is_ok(), "{key}");
+        }
+        assert!(validate_key(&"ü".repeat(MAX_KEY_LENGTH / 2)).is_ok());
+    }
+
+    #[test]
+    fn test_rejected_keys() {
+        let (code, message) = rejected("dir/line\nbreak");
+        assert_eq!(code, S3ErrorCode::InvalidArgument);
+        assert!(message.contains("U+000A at byte 8"), "{message}");
+
+        let (code, message) = rejected("nul\0");
+        assert_eq!(code, S3ErrorCode::InvalidArgument);
+        assert!(message.contains("U+0000 at byte 3"), "{message}");
+
+        // Positions are byte offsets of the UTF-8 encoding
+        let (_, message) = rejected("🦀\u{7f}");
+        assert!(message.contains("U+007F at byte 4"), "{message}");
+
+        let (code, message) = rejected("coll/../other/file");
+        assert_eq!(code, S3ErrorCode::InvalidURI);
+        assert!(message.contains("'..' at byte 5"), "{message}");
+        assert_eq!(rejected("./file").0, S3ErrorCode::InvalidURI);
+        assert_eq!(rejected("coll/..").0, S3ErrorCode::InvalidURI);
+
+        let (code, message) = rejected("coll//file");
+        assert_eq!(code, S3ErrorCode::InvalidURI);
+        assert!(message.contains("at byte 5"), "{message}");
+        assert_eq!(rejected("/file").0, S3ErrorCode::InvalidURI);
+
+        let (code, _) = rejected(&"ü".repeat(MAX_KEY_LENGTH / 2 + 1));
+        assert_eq!(code, S3ErrorCode::InvalidArgument);
+    }
+
+    #[test]
+    fn test_url_encode() {
+        assert_eq!(url_encode("coll/file-1_a.txt~"), "coll/file-1_a.txt~");
+        assert_eq!(url_encode("with space+plus%"), "with%20space%2Bplus%25");
+        assert_eq!(url_encode("🦀"), "%F0%9F%A6%80");
+        assert_eq!(url_encode("a&b<c>"), "a%26b%3Cc%3E");
+        assert_eq!(encode_listed("a b".to_string(), false), "a b");
+        assert_eq!(encode_listed("a b".to_string(), true), "a%20b");
+    }
+
+    #[test]
+    fn test_url_decode() {
+        for key in ["coll/file-1_a.txt~", "with space+plus%", "🦀", "a&b<c>"] {
+            assert_eq!(url_decode(&url_encode(key)).as_deref(), Some(key));
+        }
+        // Paths are not form encoded
+        assert_eq!(url_decode("a+b").as_deref(), Some("a+b"));
+        assert_eq!(url_decode("100%"), None);
+        assert_eq!(url_decode("%zz"), None);
+        assert_eq!(url_decode("%+1"), None);
+        assert_eq!(url_decode("%FF"), None);
+    }
+
+    #[test]
+    fn test_is_url_encoding() {
+        assert!(is_url_encoding(Some(&EncodingType::from_static(
+            EncodingType::URL
+        ))));
+        assert!(!is_url_encoding(None));
+    }
+}
@@ -21,7 +21,9 @@ pub fn calculate_ranges(
     let Some(range) = input_range else {
         return Ok((None, None, content_length, None));
     };
-    let aruna_range = aruna_range_from_s3range(range, content_length);
+    let Some(aruna_range) = aruna_range_from_s3range(range, content_length) else {
+        return Err(RangeNotSatisfiable.into());
+    };
     if location.is_pithos() {
         let Some(footer) = footer else {
             return Err(anyhow!("Footer not found"));
@@ -86,26 +88,99 @@ pub fn calculate_content_length_from_range(range: pithos_lib::helpers::structs::
     range.to - range.from // Note: -1 bytes-ranges are inclusive
 }
 
+/// The requested range starts behind the end of the object (416 Range Not Satisfiable)
+#[derive(Debug)]
+pub struct RangeNotSatisfiable;
+
+impl std::fmt::Display for RangeNotSatisfiable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Requested range not satisfiable")
+    }
+}
+
+impl std::error::Error for RangeNotSatisfiable {}
+
+/// Converts an S3 range into an exclusive byte range of the object
+///
+/// Ranges reaching past the end of the object and suffixes longer than the object
+/// are clamped to the object size, returns None if no byte of the object is selected.
 #[tracing::instrument(level = "trace", skip(range_string, content_length))]
-pub fn aruna_range_from_s3range(range_string: S3Range, content_length: u64) -> ArunaRange {
+pub fn aruna_range_from_s3range(range_string: S3Range, content_length: u64) -> Option<ArunaRange> {
     match range_string {
-        Int { first, last } => match last {
-            Some(val) => ArunaRange {
-                from: first,
-                to: if val > content_length {
-                    content_length
-                } else {
-                    val + 1
-                },
+        Int { first, .. } if first >= content_length => None,
+        Int { first, last } => Some(ArunaRange {
+            from: first,
+            to: match last {
+                Some(val) if val < content_length => val + 1,
+                _ => content_length,
             },
-            None => ArunaRange {
-                from: first,
-                to: content_length,
-            },
-        },
-        Suffix { length } => ArunaRange {
-            from: content_length - length,
+        }),
+        Suffix { length } if length == 0 || content_length == 0 => None,
+        Suffix { length } => Some(ArunaRange {
+            from: content_length.saturating_sub(length),
             to: content_length,
-        },
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffix_larger_than_object() {
+        let range = aruna_range_from_s3range(Suffix { length: 1048576 }, 100_000).unwrap();
+        assert_eq!(range.from, 0);
+        assert_eq!(range.to, 100_000);
+
+        let range = aruna_range_from_s3range(Suffix { length: 10 }, 100).unwrap();
+        assert_eq!(range.from, 90);
+        assert_eq!(range.to, 100);
+    }
+
+    #[test]
+    fn last_beyond_eof() {
+        let range = aruna_range_from_s3range(
+            Int {
+                first: 10,
+                last: Some(100),
+            },
+            100,
+        )
+        .unwrap();
+        assert_eq!(range.from, 10);
+        assert_eq!(range.to, 100);
+
+        let range = aruna_range_from_s3range(
+            Int {
+                first: 10,
+                last: Some(99),
+            },
+            100,
+        )
+        .unwrap();
+        assert_eq!(range.to, 100);
+
+        assert!(aruna_range_from_s3range(
+            Int {
+                first: 100,
+                last: None
+            },
+            100
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn zero_length_object() {
+        assert!(aruna_range_from_s3range(
+            Int {
+                first: 0,
+                last: None
+            },
+            0
+        )
+        .is_none());
+        assert!(aruna_range_from_s3range(Suffix { length: 10 }, 0).is_none());
     }
 }
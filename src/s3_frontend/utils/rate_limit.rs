@@ -0,0 +1,246 @@
+use crate::config::{RateLimit, RateLimits};
+use crate::helpers::split_s3_path;
+use dashmap::DashMap;
+use http::Method;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Buckets that were not used for this long are full again and can be dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    Read,
+    Write,
+    Listing,
+}
+
+impl RequestClass {
+    /// GET/HEAD on a bucket (or the root) is a listing, on a key a read
+    pub fn from_request<B>(req: &http::Request<B>, base_domain: &str) -> Self {
+        match *req.method() {
+            Method::GET | Method::HEAD | Method::OPTIONS => match split_s3_path(req, base_domain) {
+                (_, Some(_)) => RequestClass::Read,
+                _ => RequestClass::Listing,
+            },
+            _ => RequestClass::Write,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestClass::Read => "read",
+            RequestClass::Write => "write",
+            RequestClass::Listing => "listing",
+        }
+    }
+}
+
+/// Who is limited, known access keys for signed requests and the source ip otherwise
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Principal {
+    AccessKey(String),
+    Anonymous(Option<IpAddr>),
+}
+
+impl Principal {
+    /// The signature is verified later on, unknown access keys are limited by their
+    /// source ip like unsigned requests. Otherwise every made up key would get a bucket
+    /// of its own.
+    pub fn from_request<B>(
+        req: &http::Request<B>,
+        remote_ip: Option<IpAddr>,
+        is_known: impl Fn(&str) -> bool,
+    ) -> Self {
+        match extract_access_key(req) {
+            Some(access_key) if is_known(&access_key) => Principal::AccessKey(access_key),
+            _ => Principal::Anonymous(remote_ip),
+        }
+    }
+
+    /// Log field, access keys or the source ip
+    pub fn label(&self) -> String {
+        match self {
+            Principal::AccessKey(key) => key.clone(),
+            Principal::Anonymous(Some(ip)) => ip.to_string(),
+            Principal::Anonymous(None) => "anonymous".to_string(),
+        }
+    }
+}
+
+/// Access key of a V4/V2 signed request (header or presigned url)
+pub fn extract_access_key<B>(req: &http::Request<B>) -> Option<String> {
+    if let Some(auth) = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+    {
+        // AWS4-HMAC-SHA256 Credential=<key>/<scope>, ... or AWS <key>:<signature>
+        if let Some((_, credential)) = auth.split_once("Credential=") {
+            return credential.split('/').next().map(|k| k.to_string());
+        }
+        if let Some(v2) = auth.strip_prefix("AWS ") {
+            return v2.split(':').next().map(|k| k.trim().to_string());
+        }
+    }
+    req.uri().query()?.split('&').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        match key {
+            "X-Amz-Credential" => value
+                .split("%2F")
+                .next()
+                .and_then(|k| k.split('/').next())
+                .map(|k| k.to_string()),
+            "AWSAccessKeyId" => Some(value.to_string()),
+            _ => None,
+        }
+    })
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket rate limiter per principal and request class
+///
+/// Every check only touches a single DashMap shard, idle buckets are removed by `prune`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: DashMap<(Principal, RequestClass), TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: DashMap::new(),
+        }
+    }
+
+    fn limit(&self, class: RequestClass) -> RateLimit {
+        match class {
+            RequestClass::Read => self.limits.reads,
+            RequestClass::Write => self.limits.writes,
+            RequestClass::Listing => self.limits.listings,
+        }
+    }
+
+    /// Takes a token, returns the time until the next token is available if the bucket is empty
+    pub fn check(&self, principal: &Principal, class: RequestClass) -> Result<(), Duration> {
+        let limit = self.limit(class);
+        if limit.requests_per_second <= 0.0 {
+            return Ok(());
+        }
+        let burst = limit.burst.max(1.0);
+        let now = Instant::now();
+        let mut bucket = self
+            .buckets
+            .entry((principal.clone(), class))
+            .or_insert_with(|| TokenBucket {
+                tokens: burst,
+                last_refill: now,
+            });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.requests_per_second).min(burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.requests_per_second,
+            ))
+        }
+    }
+
+    /// Removes buckets that were idle long enough to be full again
+    pub fn prune(&self) {
+        self.buckets
+            .retain(|_, bucket| bucket.last_refill.elapsed() < IDLE_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: &str = "AKIAKNOWN";
+
+    fn signed(access_key: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri("/bucket/key")
+            .header(
+                http::header::AUTHORIZATION,
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={access_key}/20240101/us-east-1/s3/aws4_request, SignedHeaders=host, Signature=00"
+                ),
+            )
+            .body(())
+            .unwrap()
+    }
+
+    fn principal(req: &http::Request<()>, ip: &str) -> Principal {
+        Principal::from_request(req, Some(ip.parse().unwrap()), |key| key == KNOWN)
+    }
+
+    #[test]
+    fn test_extract_access_key() {
+        assert_eq!(extract_access_key(&signed(KNOWN)).as_deref(), Some(KNOWN));
+        let v2 = http::Request::builder()
+            .header(http::header::AUTHORIZATION, "AWS AKIAV2:signature")
+            .body(())
+            .unwrap();
+        assert_eq!(extract_access_key(&v2).as_deref(), Some("AKIAV2"));
+        let presigned = http::Request::builder()
+            .uri("/bucket/key?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential=AKIAURL%2F20240101%2Fus-east-1%2Fs3%2Faws4_request")
+            .body(())
+            .unwrap();
+        assert_eq!(extract_access_key(&presigned).as_deref(), Some("AKIAURL"));
+        let unsigned = http::Request::builder().body(()).unwrap();
+        assert_eq!(extract_access_key(&unsigned), None);
+    }
+
+    #[test]
+    fn test_unknown_keys_limited_by_ip() {
+        let limiter = RateLimiter::new(RateLimits {
+            reads: RateLimit {
+                requests_per_second: 0.001,
+                burst: 2.0,
+            },
+            ..Default::default()
+        });
+        assert_eq!(
+            principal(&signed(KNOWN), "10.0.0.1"),
+            Principal::AccessKey(KNOWN.to_string())
+        );
+
+        // Made up keys share the bucket of their source ip
+        for (idx, key) in ["FAKE1", "FAKE2", "FAKE3"].iter().enumerate() {
+            let principal = principal(&signed(key), "10.0.0.1");
+            assert_eq!(
+                principal,
+                Principal::Anonymous(Some("10.0.0.1".parse().unwrap()))
+            );
+            assert_eq!(
+                limiter.check(&principal, RequestClass::Read).is_ok(),
+                idx < 2
+            );
+        }
+        let unsigned = http::Request::builder().body(()).unwrap();
+        assert!(limiter
+            .check(&principal(&unsigned, "10.0.0.1"), RequestClass::Read)
+            .is_err());
+
+        // Other sources and known keys are limited independently
+        assert!(limiter
+            .check(&principal(&signed("FAKE4"), "10.0.0.2"), RequestClass::Read)
+            .is_ok());
+        assert!(limiter
+            .check(&principal(&signed(KNOWN), "10.0.0.1"), RequestClass::Read)
+            .is_ok());
+        assert_eq!(limiter.buckets.len(), 3);
+    }
+}
@@ -9,16 +9,20 @@ use async_channel::Receiver;
 use async_channel::Sender;
 use async_channel::TryRecvError;
 use bytes::{BufMut, BytesMut};
-use md5::{Digest, Md5};
 use pithos_lib::helpers::notifications::Notifier;
 use pithos_lib::transformer::TransformerType;
 use pithos_lib::transformer::{Sink, Transformer};
 use tokio::sync::mpsc::Sender as TokioSender;
 use tracing::error;
 
+use crate::replication::checksum::ChecksumAlgorithm;
+use crate::replication::chunk_size::{ChunkSize, BLOCK_SIZE};
+
 pub struct ReplicationSink {
     object_id: String,
+    maximum_blocks: usize,
     maximum_chunks: usize,
+    chunk_size: ChunkSize,
     chunk_counter: usize, // One chunk contains multiple blocks
     sender: TokioSender<Result<PullReplicationResponse, tonic::Status>>,
     error_recv: async_channel::Receiver<Option<(i64, String)>>,
@@ -29,6 +33,8 @@ pub struct ReplicationSink {
     notifier: Option<Arc<Notifier>>,
     msg_receiver: Option<Receiver<pithos_lib::helpers::notifications::Message>>,
     idx: Option<usize>,
+    checksum_algorithm: ChecksumAlgorithm,
+    total_size: Option<u64>,
 }
 
 impl Sink for ReplicationSink {}
@@ -37,26 +43,39 @@ impl ReplicationSink {
     #[tracing::instrument(level = "trace", skip())]
     pub fn new(
         object_id: String,
-        chunks: usize,
+        blocks: usize,
         sender: TokioSender<Result<PullReplicationResponse, tonic::Status>>,
         error_recv: async_channel::Receiver<Option<(i64, String)>>,
+        checksum_algorithm: ChecksumAlgorithm,
+        chunk_size: ChunkSize,
     ) -> ReplicationSink {
         ReplicationSink {
-            maximum_chunks: chunks,
+            maximum_blocks: blocks,
+            maximum_chunks: chunk_size.chunk_count(blocks),
+            chunk_size,
             object_id,
             sender,
             error_recv,
             chunk_counter: 0,
-            buffer: BytesMut::with_capacity((1024 * 1024 * 5) + 128),
+            buffer: BytesMut::with_capacity(chunk_size.bytes() + 128),
             is_finished: false,
             bytes_counter: 0,
             bytes_start: 0,
             notifier: None,
             msg_receiver: None,
             idx: None,
+            checksum_algorithm,
+            total_size: None,
         }
     }
 
+    /// Data that is passed through unchanged (e.g. customer encrypted ciphertext) has
+    /// no footer, the last chunk is only sent once all remaining bytes are buffered
+    pub fn with_total_size(mut self, total_size: u64) -> Self {
+        self.total_size = Some(total_size);
+        self
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     fn process_messages(&mut self) -> Result<bool> {
         if let Some(rx) = &self.msg_receiver {
@@ -83,8 +102,12 @@ impl ReplicationSink {
             return Ok(false);
         }
 
+        // Data chunks contain whole blocks, the last chunk contains the remaining data (footer)
         let len = if self.chunk_counter < self.maximum_chunks {
-            65536 + 28
+            let sent_blocks = self.chunk_counter * self.chunk_size.blocks();
+            (self.maximum_blocks - sent_blocks).min(self.chunk_size.blocks()) * BLOCK_SIZE
+        } else if let Some(total_size) = self.total_size {
+            total_size.saturating_sub(self.bytes_start) as usize
         } else {
             self.buffer.len()
         };
@@ -95,21 +118,15 @@ impl ReplicationSink {
 
         let data = self.buffer.split_to(len).to_vec();
 
-        // create a Md5 hasher instance
-        let mut hasher = Md5::new();
-        // process input message
-        hasher.update(&data);
-
-        // acquire hash digest in the form of GenericArray,
-        // which in this case is equivalent to [u8; 16]
-        let result = hasher.finalize();
+        // Checksum with the algorithm negotiated with the receiving proxy
+        let checksum = self.checksum_algorithm.checksum(&data);
 
         let message = PullReplicationResponse {
             message: Some(Message::Chunk(Chunk {
                 object_id: self.object_id.clone(),
                 chunk_idx: (self.chunk_counter as i64),
                 data,
-                checksum: hex::encode(result),
+                checksum,
             })),
         };
 
@@ -166,10 +183,11 @@ impl Transformer for ReplicationSink {
         let finished = self.process_messages()?;
 
         if finished && !self.buffer.is_empty() && !self.is_finished {
-            self.create_and_send_message().await.map_err(|e| {
+            // The last read can contain whole blocks in front of the remaining data
+            while self.create_and_send_message().await.map_err(|e| {
                 error!(error = ?e, msg = e.to_string());
                 tonic::Status::unauthenticated(e.to_string())
-            })?;
+            })? {}
             self.is_finished = true;
 
             if let Some(notifier) = &self.notifier {
@@ -0,0 +1,146 @@
+use crate::structs::UserState;
+use mime_guess::mime;
+use s3s::dto::{GetObjectOutput, HeadObjectOutput, Timestamp};
+use s3s::{s3_error, S3Result};
+use tracing::error;
+
+/// `response-*` query parameters of GetObject and HeadObject
+///
+/// The overrides only change the headers of a single response, the stored object
+/// (content type, metadata) is never altered.
+#[derive(Debug, Default)]
+pub struct ResponseOverrides {
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub content_type: Option<String>,
+    pub expires: Option<Timestamp>,
+}
+
+impl ResponseOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.cache_control.is_none()
+            && self.content_disposition.is_none()
+            && self.content_encoding.is_none()
+            && self.content_language.is_none()
+            && self.content_type.is_none()
+            && self.expires.is_none()
+    }
+
+    /// Like AWS, anonymous requests must not override response headers
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn validate(&self, user_state: &UserState) -> S3Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if matches!(user_state, UserState::Anonymous) {
+            error!(error = "Response header overrides on anonymous request");
+            return Err(s3_error!(
+                InvalidRequest,
+                "Request specific response headers cannot be used for anonymous GET requests."
+            ));
+        }
+        self.mime()?;
+        Ok(())
+    }
+
+    fn mime(&self) -> S3Result<Option<mime::Mime>> {
+        self.content_type
+            .as_ref()
+            .map(|content_type| {
+                content_type.parse().map_err(|_| {
+                    error!(content_type, "Invalid response-content-type");
+                    s3_error!(InvalidArgument, "Invalid response-content-type")
+                })
+            })
+            .transpose()
+    }
+
+    pub fn apply_get(self, output: &mut GetObjectOutput) -> S3Result<()> {
+        if let Some(mime) = self.mime()? {
+            output.content_type = Some(mime);
+        }
+        output.cache_control = self.cache_control.or(output.cache_control.take());
+        output.content_disposition = self
+            .content_disposition
+            .or(output.content_disposition.take());
+        output.content_encoding = self.content_encoding.or(output.content_encoding.take());
+        output.content_language = self.content_language.or(output.content_language.take());
+        output.expires = self.expires.or(output.expires.take());
+        Ok(())
+    }
+
+    pub fn apply_head(self, output: &mut HeadObjectOutput) -> S3Result<()> {
+        if let Some(mime) = self.mime()? {
+            output.content_type = Some(mime);
+        }
+        output.cache_control = self.cache_control.or(output.cache_control.take());
+        output.content_disposition = self
+            .content_disposition
+            .or(output.content_disposition.take());
+        output.content_encoding = self.content_encoding.or(output.content_encoding.take());
+        output.content_language = self.content_language.or(output.content_language.take());
+        output.expires = self.expires.or(output.expires.take());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel_ulid::DieselUlid;
+    use s3s::S3ErrorCode;
+
+    fn overrides() -> ResponseOverrides {
+        ResponseOverrides {
+            content_disposition: Some("attachment; filename=\"report.csv\"".to_string()),
+            content_type: Some("text/csv".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let user = UserState::Personal {
+            user_id: DieselUlid::generate(),
+        };
+        assert!(ResponseOverrides::default()
+            .validate(&UserState::Anonymous)
+            .is_ok());
+        assert!(overrides().validate(&user).is_ok());
+        assert_eq!(
+            overrides()
+                .validate(&UserState::Anonymous)
+                .unwrap_err()
+                .code(),
+            &S3ErrorCode::InvalidRequest
+        );
+        let invalid = ResponseOverrides {
+            content_type: Some("not a mime".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid.validate(&user).unwrap_err().code(),
+            &S3ErrorCode::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut output = GetObjectOutput {
+            content_type: Some(mime::APPLICATION_OCTET_STREAM),
+            content_language: Some("en".to_string()),
+            ..Default::default()
+        };
+        overrides().apply_get(&mut output).unwrap();
+        assert_eq!(output.content_type, Some(mime::TEXT_CSV));
+        assert_eq!(
+            output.content_disposition.as_deref(),
+            Some("attachment; filename=\"report.csv\"")
+        );
+        // Not overridden
+        assert_eq!(output.content_language.as_deref(), Some("en"));
+        assert_eq!(output.cache_control, None);
+    }
+}
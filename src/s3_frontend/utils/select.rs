@@ -0,0 +1,1216 @@
+use hyper::StatusCode;
+use s3s::dto::{InputSerialization, OutputSerialization, SelectObjectContentRequest};
+use s3s::{S3Error, S3ErrorCode};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Errors of the supported SelectObjectContent subset
+#[derive(Debug, PartialEq, Eq)]
+pub enum SelectError {
+    /// Valid (S3 Select) SQL or options that are not supported by the proxy
+    Unsupported(String),
+    /// Malformed expression
+    Parse(String),
+    CsvParsing(String),
+    JsonParsing(String),
+    /// A single record is larger than `MAX_RECORD_SIZE`
+    OverMaxRecordSize,
+}
+
+impl From<SelectError> for S3Error {
+    fn from(error: SelectError) -> Self {
+        let (code, msg) = match error {
+            SelectError::Unsupported(msg) => ("UnsupportedSqlOperation", msg),
+            SelectError::Parse(msg) => ("ParseUnexpectedToken", msg),
+            SelectError::CsvParsing(msg) => ("CSVParsingError", msg),
+            SelectError::JsonParsing(msg) => ("JSONParsingError", msg),
+            SelectError::OverMaxRecordSize => (
+                "OverMaxRecordSize",
+                format!("A record exceeds the maximum size of {MAX_RECORD_SIZE} bytes"),
+            ),
+        };
+        let mut error = S3Error::with_message(S3ErrorCode::Custom(code.into()), msg);
+        error.set_status_code(StatusCode::BAD_REQUEST);
+        error
+    }
+}
+
+/// Same limit as AWS: records are buffered until their delimiter shows up
+pub const MAX_RECORD_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    QuotedIdent(String),
+    Str(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 15] = [
+    "<=", ">=", "<>", "!=", "=", "<", ">", "*", ",", ".", "(", ")", "[", "]", ";",
+];
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, SelectError> {
+    let mut tokens = Vec::new();
+    let mut rest = sql.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (Token::Ident(rest[..len].to_string()), len)
+        } else if c == '"' || c == '\'' {
+            // Quotes are escaped by doubling them
+            let mut value = String::new();
+            let mut chars = rest.char_indices().skip(1).peekable();
+            let mut end = None;
+            while let Some((idx, next)) = chars.next() {
+                if next == c {
+                    if matches!(chars.peek(), Some((_, n)) if *n == c) {
+                        value.push(c);
+                        chars.next();
+                    } else {
+                        end = Some(idx + 1);
+                        break;
+                    }
+                } else {
+                    value.push(next);
+                }
+            }
+            let len = end.ok_or_else(|| SelectError::Parse("Unterminated quote".to_string()))?;
+            if c == '"' {
+                (Token::QuotedIdent(value), len)
+            } else {
+                (Token::Str(value), len)
+            }
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let len = rest[1..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .map(|len| len + 1)
+                .unwrap_or(rest.len());
+            (Token::Number(rest[..len].to_string()), len)
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            (Token::Symbol(symbol), symbol.len())
+        } else {
+            return Err(SelectError::Parse(format!("Unexpected character: {c}")));
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// A (possibly quoted) identifier, unquoted identifiers match case-insensitively
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Ident {
+    name: String,
+    quoted: bool,
+}
+
+impl Ident {
+    fn matches(&self, name: &str) -> bool {
+        if self.quoted {
+            self.name == name
+        } else {
+            self.name.eq_ignore_ascii_case(name)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Column {
+    /// Zero based position of `_1`, `_2`, ...
+    Position(usize),
+    /// Column name or path into a JSON record
+    Name(Vec<Ident>),
+}
+
+impl Column {
+    fn output_name(&self) -> String {
+        match self {
+            Column::Position(idx) => format!("_{}", idx + 1),
+            Column::Name(path) => path
+                .last()
+                .map(|ident| ident.name.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl CompareOp {
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "=" => Some(CompareOp::Eq),
+            "!=" | "<>" => Some(CompareOp::NotEq),
+            "<" => Some(CompareOp::Lt),
+            "<=" => Some(CompareOp::LtEq),
+            ">" => Some(CompareOp::Gt),
+            ">=" => Some(CompareOp::GtEq),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::NotEq => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::LtEq => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::GtEq => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    column: Column,
+    op: CompareOp,
+    value: Literal,
+}
+
+impl Condition {
+    /// Numbers are compared numerically (CSV fields are parsed), strings lexicographically,
+    /// missing values and type mismatches never match
+    fn matches(&self, value: Option<&Value>) -> bool {
+        let ordering = match (&self.value, value) {
+            (Literal::Number(expected), Some(Value::Number(n))) => {
+                n.as_f64().and_then(|n| n.partial_cmp(expected))
+            }
+            (Literal::Number(expected), Some(Value::String(s))) => s
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .and_then(|n| n.partial_cmp(expected)),
+            (Literal::Str(expected), Some(Value::String(s))) => Some(s.as_str().cmp(expected)),
+            _ => None,
+        };
+        ordering.is_some_and(|ordering| self.op.holds(ordering))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Projection {
+    All,
+    Columns(Vec<Column>),
+}
+
+/// `SELECT <* | columns> FROM S3Object [[AS] alias] [WHERE <comparisons joined by AND>] [LIMIT n]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectQuery {
+    projection: Projection,
+    conditions: Vec<Condition>,
+    limit: Option<u64>,
+}
+
+/// Keywords that start clauses or operators outside of the supported subset
+const UNSUPPORTED_KEYWORDS: [&str; 16] = [
+    "OR", "NOT", "LIKE", "IN", "BETWEEN", "IS", "GROUP", "ORDER", "HAVING", "JOIN", "UNION",
+    "DISTINCT", "CASE", "CAST", "OFFSET", "AS",
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SelectError> {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("expected {keyword}")))
+        }
+    }
+
+    /// Error for the current token, keywords of unsupported features are reported as such
+    fn unexpected(&self, context: &str) -> SelectError {
+        match self.peek() {
+            Some(Token::Ident(ident))
+                if UNSUPPORTED_KEYWORDS
+                    .iter()
+                    .any(|k| k.eq_ignore_ascii_case(ident)) =>
+            {
+                SelectError::Unsupported(format!("{} is not supported", ident.to_uppercase()))
+            }
+            Some(Token::Symbol("(")) => {
+                SelectError::Unsupported("Functions and parentheses are not supported".to_string())
+            }
+            Some(Token::Symbol("[")) => {
+                SelectError::Unsupported("JSON path expressions are not supported".to_string())
+            }
+            Some(token) => SelectError::Parse(format!("Unexpected token {token:?}, {context}")),
+            None => SelectError::Parse(format!("Unexpected end of expression, {context}")),
+        }
+    }
+
+    fn ident(&mut self) -> Result<Ident, SelectError> {
+        match self.peek() {
+            Some(Token::Ident(name))
+                if !UNSUPPORTED_KEYWORDS
+                    .iter()
+                    .any(|k| k.eq_ignore_ascii_case(name)) =>
+            {
+                let ident = Ident {
+                    name: name.clone(),
+                    quoted: false,
+                };
+                self.pos += 1;
+                // Function calls (aggregates, CAST, ...) are identifiers followed by (
+                if self.is_symbol("(") {
+                    return Err(SelectError::Unsupported(format!(
+                        "Function {} is not supported",
+                        ident.name.to_uppercase()
+                    )));
+                }
+                Ok(ident)
+            }
+            Some(Token::QuotedIdent(name)) => {
+                let ident = Ident {
+                    name: name.clone(),
+                    quoted: true,
+                };
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => Err(self.unexpected("expected a column")),
+        }
+    }
+
+    /// Dotted column reference, the table alias is resolved later
+    fn column_path(&mut self) -> Result<Vec<Ident>, SelectError> {
+        let mut path = vec![self.ident()?];
+        while self.is_symbol(".") {
+            self.pos += 1;
+            path.push(self.ident()?);
+        }
+        Ok(path)
+    }
+
+    fn literal(&mut self) -> Result<Literal, SelectError> {
+        match self.next() {
+            Some(Token::Str(value)) => Ok(Literal::Str(value)),
+            Some(Token::Number(value)) => value
+                .parse::<f64>()
+                .map(Literal::Number)
+                .map_err(|_| SelectError::Parse(format!("Invalid number: {value}"))),
+            Some(Token::Ident(_)) | Some(Token::QuotedIdent(_)) => Err(SelectError::Unsupported(
+                "Comparisons are only supported against string or number literals".to_string(),
+            )),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("expected a literal"))
+            }
+        }
+    }
+}
+
+/// Resolves `alias.column`, unqualified `_N` columns address CSV fields by position
+fn resolve_column(mut path: Vec<Ident>, aliases: &[Ident]) -> Column {
+    if path.len() > 1 && aliases.iter().any(|alias| path[0].matches(&alias.name)) {
+        path.remove(0);
+    }
+    if let [ident] = path.as_slice() {
+        if !ident.quoted {
+            if let Some(idx) = ident
+                .name
+                .strip_prefix('_')
+                .and_then(|idx| idx.parse::<usize>().ok())
+                .filter(|idx| *idx > 0)
+            {
+                return Column::Position(idx - 1);
+            }
+        }
+    }
+    Column::Name(path)
+}
+
+impl SelectQuery {
+    pub fn parse(sql: &str) -> Result<Self, SelectError> {
+        let mut parser = Parser {
+            tokens: tokenize(sql)?,
+            pos: 0,
+        };
+
+        parser.expect_keyword("SELECT")?;
+        let mut paths = Vec::new();
+        let mut all = false;
+        loop {
+            if parser.is_symbol("*") {
+                parser.pos += 1;
+                all = true;
+            } else if matches!(
+                parser.tokens.get(parser.pos..parser.pos + 3),
+                Some([
+                    Token::Ident(_) | Token::QuotedIdent(_),
+                    Token::Symbol("."),
+                    Token::Symbol("*")
+                ])
+            ) {
+                // alias.*
+                parser.pos += 3;
+                all = true;
+            } else {
+                paths.push(parser.column_path()?);
+            }
+            if parser.is_symbol(",") {
+                parser.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if all && !paths.is_empty() {
+            return Err(SelectError::Unsupported(
+                "* can not be combined with other columns".to_string(),
+            ));
+        }
+
+        parser.expect_keyword("FROM")?;
+        let table = parser.ident()?;
+        if !table.matches("S3Object") {
+            return Err(SelectError::Parse(format!(
+                "Unknown table {}, expected S3Object",
+                table.name
+            )));
+        }
+        let mut aliases = vec![table];
+        if parser.is_keyword("AS") {
+            parser.pos += 1;
+        }
+        if matches!(
+            parser.peek(),
+            Some(Token::Ident(_)) | Some(Token::QuotedIdent(_))
+        ) && !parser.is_keyword("WHERE")
+            && !parser.is_keyword("LIMIT")
+        {
+            aliases.push(parser.ident()?);
+        }
+
+        let mut conditions = Vec::new();
+        if parser.is_keyword("WHERE") {
+            parser.pos += 1;
+            loop {
+                let column = resolve_column(parser.column_path()?, &aliases);
+                let op = match parser.peek() {
+                    Some(Token::Symbol(symbol)) => CompareOp::from_symbol(symbol),
+                    _ => None,
+                }
+                .ok_or_else(|| parser.unexpected("expected a comparison operator"))?;
+                parser.pos += 1;
+                let value = parser.literal()?;
+                conditions.push(Condition { column, op, value });
+                if parser.is_keyword("AND") {
+                    parser.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        let mut limit = None;
+        if parser.is_keyword("LIMIT") {
+            parser.pos += 1;
+            limit = match parser.next() {
+                Some(Token::Number(value)) => Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| SelectError::Parse(format!("Invalid limit: {value}")))?,
+                ),
+                _ => return Err(SelectError::Parse("Expected a limit".to_string())),
+            };
+        }
+        if parser.is_symbol(";") {
+            parser.pos += 1;
+        }
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("expected end of expression"));
+        }
+
+        let projection = if all {
+            Projection::All
+        } else {
+            Projection::Columns(
+                paths
+                    .into_iter()
+                    .map(|path| resolve_column(path, &aliases))
+                    .collect(),
+            )
+        };
+        Ok(SelectQuery {
+            projection,
+            conditions,
+            limit,
+        })
+    }
+
+    fn columns(&self) -> impl Iterator<Item = &Column> {
+        let projected = match &self.projection {
+            Projection::All => None,
+            Projection::Columns(columns) => Some(columns.iter()),
+        };
+        projected
+            .into_iter()
+            .flatten()
+            .chain(self.conditions.iter().map(|c| &c.column))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderInfo {
+    None,
+    Ignore,
+    Use,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvInputFormat {
+    pub field_delimiter: char,
+    pub quote: char,
+    pub quote_escape: char,
+    pub record_delimiter: u8,
+    pub comments: Option<char>,
+    pub header: HeaderInfo,
+    pub allow_quoted_record_delimiter: bool,
+}
+
+impl Default for CsvInputFormat {
+    fn default() -> Self {
+        CsvInputFormat {
+            field_delimiter: ',',
+            quote: '"',
+            quote_escape: '"',
+            record_delimiter: b'\n',
+            comments: None,
+            header: HeaderInfo::None,
+            allow_quoted_record_delimiter: false,
+        }
+    }
+}
+
+impl CsvInputFormat {
+    fn split(&self, line: &str) -> Vec<String> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                if (c == self.quote_escape || c == self.quote) && chars.peek() == Some(&self.quote)
+                {
+                    field.push(self.quote);
+                    chars.next();
+                } else if c == self.quote {
+                    in_quotes = false;
+                } else {
+                    field.push(c);
+                }
+            } else if c == self.quote {
+                in_quotes = true;
+            } else if c == self.field_delimiter {
+                fields.push(std::mem::take(&mut field));
+            } else {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+        fields
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv(CsvInputFormat),
+    JsonLines,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv {
+        field_delimiter: String,
+        quote: char,
+        quote_escape: char,
+        record_delimiter: String,
+        always_quote: bool,
+    },
+    Json {
+        record_delimiter: String,
+    },
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Csv {
+            field_delimiter: ",".to_string(),
+            quote: '"',
+            quote_escape: '"',
+            record_delimiter: "\n".to_string(),
+            always_quote: false,
+        }
+    }
+}
+
+/// A single character option, `\r\n` is accepted as record delimiter and treated as `\n`
+fn single_char(value: Option<&String>, default: char, option: &str) -> Result<char, SelectError> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        (None, _) => Ok(default),
+        _ if value == "\r\n" => Ok('\n'),
+        _ => Err(SelectError::Unsupported(format!(
+            "Only single character values are supported for {option}"
+        ))),
+    }
+}
+
+impl InputFormat {
+    pub fn from_serialization(input: &InputSerialization) -> Result<Self, SelectError> {
+        if let Some(compression) = &input.compression_type {
+            if compression.as_str() != "NONE" {
+                return Err(SelectError::Unsupported(format!(
+                    "CompressionType {} is not supported",
+                    compression.as_str()
+                )));
+            }
+        }
+        if input.parquet.is_some() {
+            return Err(SelectError::Unsupported(
+                "Parquet input is not supported".to_string(),
+            ));
+        }
+        match (&input.csv, &input.json) {
+            (Some(csv), None) => {
+                let record_delimiter =
+                    single_char(csv.record_delimiter.as_ref(), '\n', "RecordDelimiter")?;
+                let quote = single_char(csv.quote_character.as_ref(), '"', "QuoteCharacter")?;
+                if !record_delimiter.is_ascii() || !quote.is_ascii() {
+                    return Err(SelectError::Unsupported(
+                        "Only ASCII record delimiters and quote characters are supported"
+                            .to_string(),
+                    ));
+                }
+                let header = match csv.file_header_info.as_ref().map(|h| h.as_str()) {
+                    None | Some("NONE") => HeaderInfo::None,
+                    Some("IGNORE") => HeaderInfo::Ignore,
+                    Some("USE") => HeaderInfo::Use,
+                    Some(other) => {
+                        return Err(SelectError::Parse(format!(
+                            "Invalid FileHeaderInfo: {other}"
+                        )))
+                    }
+                };
+                Ok(InputFormat::Csv(CsvInputFormat {
+                    field_delimiter: single_char(
+                        csv.field_delimiter.as_ref(),
+                        ',',
+                        "FieldDelimiter",
+                    )?,
+                    quote,
+                    quote_escape: single_char(
+                        csv.quote_escape_character.as_ref(),
+                        '"',
+                        "QuoteEscapeCharacter",
+                    )?,
+                    record_delimiter: record_delimiter as u8,
+                    comments: csv
+                        .comments
+                        .as_ref()
+                        .map(|c| single_char(Some(c), '#', "Comments"))
+                        .transpose()?,
+                    header,
+                    allow_quoted_record_delimiter: csv
+                        .allow_quoted_record_delimiter
+                        .unwrap_or_default(),
+                }))
+            }
+            (None, Some(json)) => match json.type_.as_ref().map(|t| t.as_str()) {
+                None | Some("LINES") => Ok(InputFormat::JsonLines),
+                Some(other) => Err(SelectError::Unsupported(format!(
+                    "JSON Type {other} is not supported, supported: LINES"
+                ))),
+            },
+            _ => Err(SelectError::Parse(
+                "Exactly one of CSV or JSON input serialization is required".to_string(),
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn from_serialization(output: &OutputSerialization) -> Result<Self, SelectError> {
+        match (&output.csv, &output.json) {
+            (Some(csv), None) => Ok(OutputFormat::Csv {
+                field_delimiter: csv
+                    .field_delimiter
+                    .clone()
+                    .unwrap_or_else(|| ",".to_string()),
+                quote: single_char(csv.quote_character.as_ref(), '"', "QuoteCharacter")?,
+                quote_escape: single_char(
+                    csv.quote_escape_character.as_ref(),
+                    '"',
+                    "QuoteEscapeCharacter",
+                )?,
+                record_delimiter: csv
+                    .record_delimiter
+                    .clone()
+                    .unwrap_or_else(|| "\n".to_string()),
+                always_quote: csv
+                    .quote_fields
+                    .as_ref()
+                    .is_some_and(|q| q.as_str() == "ALWAYS"),
+            }),
+            (None, Some(json)) => Ok(OutputFormat::Json {
+                record_delimiter: json
+                    .record_delimiter
+                    .clone()
+                    .unwrap_or_else(|| "\n".to_string()),
+            }),
+            _ => Err(SelectError::Parse(
+                "Exactly one of CSV or JSON output serialization is required".to_string(),
+            )),
+        }
+    }
+
+    fn write(&self, record: Vec<(String, Value)>, output: &mut Vec<u8>) {
+        match self {
+            OutputFormat::Csv {
+                field_delimiter,
+                quote,
+                quote_escape,
+                record_delimiter,
+                always_quote,
+            } => {
+                let fields = record
+                    .into_iter()
+                    .map(|(_, value)| {
+                        let text = match value {
+                            Value::Null => String::new(),
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        };
+                        let needs_quotes = *always_quote
+                            || text.contains(field_delimiter.as_str())
+                            || text.contains(*quote)
+                            || text.contains(record_delimiter.as_str())
+                            || text.contains(|c: char| c == '\n' || c == '\r');
+                        if needs_quotes {
+                            let escaped = text.replace(*quote, &format!("{quote_escape}{quote}"));
+                            format!("{quote}{escaped}{quote}")
+                        } else {
+                            text
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                output.extend_from_slice(fields.join(field_delimiter).as_bytes());
+                output.extend_from_slice(record_delimiter.as_bytes());
+            }
+            OutputFormat::Json { record_delimiter } => {
+                // Serialized by hand to keep the column order
+                let fields = record
+                    .into_iter()
+                    .map(|(key, value)| format!("{}:{}", Value::String(key), value))
+                    .collect::<Vec<_>>();
+                output.extend_from_slice(format!("{{{}}}", fields.join(",")).as_bytes());
+                output.extend_from_slice(record_delimiter.as_bytes());
+            }
+        }
+    }
+}
+
+enum Record {
+    Csv(Vec<String>),
+    Json(Value),
+}
+
+/// Evaluates a query on the plaintext object data
+///
+/// Data is fed in arbitrary chunks, only complete records are evaluated.
+/// The returned bytes are the serialized matching records.
+pub struct SelectExecutor {
+    query: SelectQuery,
+    input: InputFormat,
+    output: OutputFormat,
+    // Incomplete trailing record of the last chunk
+    buffer: Vec<u8>,
+    // Bytes of `buffer` that were already scanned and the quote state at that point
+    scanned: usize,
+    in_quotes: bool,
+    header: Option<Vec<String>>,
+    header_pending: bool,
+    matched: u64,
+    pub bytes_scanned: u64,
+    pub bytes_returned: u64,
+}
+
+impl SelectExecutor {
+    pub fn new(request: &SelectObjectContentRequest) -> Result<Self, SelectError> {
+        if request.expression_type.as_str() != "SQL" {
+            return Err(SelectError::Unsupported(format!(
+                "ExpressionType {} is not supported",
+                request.expression_type.as_str()
+            )));
+        }
+        if request.scan_range.is_some() {
+            return Err(SelectError::Unsupported(
+                "ScanRange is not supported".to_string(),
+            ));
+        }
+        SelectExecutor::with_formats(
+            SelectQuery::parse(&request.expression)?,
+            InputFormat::from_serialization(&request.input_serialization)?,
+            OutputFormat::from_serialization(&request.output_serialization)?,
+        )
+    }
+
+    pub fn with_formats(
+        query: SelectQuery,
+        input: InputFormat,
+        output: OutputFormat,
+    ) -> Result<Self, SelectError> {
+        for column in query.columns() {
+            match (&input, column) {
+                (InputFormat::Csv(csv), Column::Name(path)) => {
+                    if path.len() > 1 {
+                        return Err(SelectError::Unsupported(
+                            "Nested paths are not supported for CSV".to_string(),
+                        ));
+                    }
+                    if csv.header != HeaderInfo::Use {
+                        return Err(SelectError::Parse(format!(
+                            "Column {} requires FileHeaderInfo USE",
+                            column.output_name()
+                        )));
+                    }
+                }
+                (InputFormat::JsonLines, Column::Position(_)) => {
+                    return Err(SelectError::Unsupported(
+                        "Positional columns are not supported for JSON".to_string(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let header_pending = matches!(
+            &input,
+            InputFormat::Csv(CsvInputFormat { header, .. }) if *header != HeaderInfo::None
+        );
+        Ok(SelectExecutor {
+            query,
+            input,
+            output,
+            buffer: Vec::new(),
+            scanned: 0,
+            in_quotes: false,
+            header: None,
+            header_pending,
+            matched: 0,
+            bytes_scanned: 0,
+            bytes_returned: 0,
+        })
+    }
+
+    /// True if the limit is reached, no further data has to be read
+    pub fn is_done(&self) -> bool {
+        self.query.limit.is_some_and(|limit| self.matched >= limit)
+    }
+
+    pub fn process(&mut self, chunk: &[u8]) -> Result<Vec<u8>, SelectError> {
+        self.bytes_scanned += chunk.len() as u64;
+        self.buffer.extend_from_slice(chunk);
+        let (record_delimiter, quote) = match &self.input {
+            InputFormat::Csv(csv) => (
+                csv.record_delimiter,
+                csv.allow_quoted_record_delimiter.then_some(csv.quote as u8),
+            ),
+            InputFormat::JsonLines => (b'\n', None),
+        };
+
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut output = Vec::new();
+        let mut start = 0;
+        for idx in self.scanned..buffer.len() {
+            let byte = buffer[idx];
+            if Some(byte) == quote {
+                self.in_quotes = !self.in_quotes;
+            } else if byte == record_delimiter && !self.in_quotes {
+                self.handle_record(&buffer[start..idx], &mut output)?;
+                start = idx + 1;
+                if self.is_done() {
+                    self.scanned = 0;
+                    return Ok(self.returned(output));
+                }
+            }
+        }
+        if buffer.len() - start > MAX_RECORD_SIZE {
+            return Err(SelectError::OverMaxRecordSize);
+        }
+        self.buffer = buffer[start..].to_vec();
+        self.scanned = self.buffer.len();
+        Ok(self.returned(output))
+    }
+
+    /// Evaluates the last record if the data does not end with a record delimiter
+    pub fn finish(&mut self) -> Result<Vec<u8>, SelectError> {
+        let mut output = Vec::new();
+        let buffer = std::mem::take(&mut self.buffer);
+        self.scanned = 0;
+        self.in_quotes = false;
+        if !buffer.is_empty() && !self.is_done() {
+            self.handle_record(&buffer, &mut output)?;
+        }
+        Ok(self.returned(output))
+    }
+
+    fn returned(&mut self, output: Vec<u8>) -> Vec<u8> {
+        self.bytes_returned += output.len() as u64;
+        output
+    }
+
+    fn handle_record(&mut self, raw: &[u8], output: &mut Vec<u8>) -> Result<(), SelectError> {
+        let line = std::str::from_utf8(raw).map_err(|_| match self.input {
+            InputFormat::Csv(_) => SelectError::CsvParsing("Invalid UTF-8".to_string()),
+            InputFormat::JsonLines => SelectError::JsonParsing("Invalid UTF-8".to_string()),
+        })?;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+
+        let record = match &self.input {
+            InputFormat::Csv(csv) => {
+                if csv.comments.is_some_and(|c| line.starts_with(c)) {
+                    return Ok(());
+                }
+                let fields = csv.split(line);
+                let use_header = csv.header == HeaderInfo::Use;
+                if self.header_pending {
+                    self.header_pending = false;
+                    if use_header {
+                        self.set_header(fields)?;
+                    }
+                    return Ok(());
+                }
+                Record::Csv(fields)
+            }
+            InputFormat::JsonLines => Record::Json(
+                serde_json::from_str(line).map_err(|e| SelectError::JsonParsing(e.to_string()))?,
+            ),
+        };
+
+        if self
+            .query
+            .conditions
+            .iter()
+            .all(|condition| condition.matches(self.lookup(&record, &condition.column).as_ref()))
+        {
+            self.matched += 1;
+            self.output.write(self.project(record), output);
+        }
+        Ok(())
+    }
+
+    fn set_header(&mut self, header: Vec<String>) -> Result<(), SelectError> {
+        for column in self.query.columns() {
+            if let Column::Name(path) = column {
+                if !header.iter().any(|name| path[0].matches(name)) {
+                    return Err(SelectError::Parse(format!(
+                        "Unknown column: {}",
+                        path[0].name
+                    )));
+                }
+            }
+        }
+        self.header = Some(header);
+        Ok(())
+    }
+
+    fn lookup(&self, record: &Record, column: &Column) -> Option<Value> {
+        match (record, column) {
+            (Record::Csv(fields), Column::Position(idx)) => {
+                fields.get(*idx).cloned().map(Value::String)
+            }
+            (Record::Csv(fields), Column::Name(path)) => {
+                let idx = self
+                    .header
+                    .as_ref()?
+                    .iter()
+                    .position(|name| path[0].matches(name))?;
+                fields.get(idx).cloned().map(Value::String)
+            }
+            (Record::Json(value), Column::Name(path)) => {
+                let mut current = value;
+                for ident in path {
+                    current = current
+                        .as_object()?
+                        .iter()
+                        .find(|(key, _)| ident.matches(key))?
+                        .1;
+                }
+                Some(current.clone())
+            }
+            (Record::Json(_), Column::Position(_)) => None,
+        }
+    }
+
+    fn project(&self, record: Record) -> Vec<(String, Value)> {
+        match &self.query.projection {
+            Projection::Columns(columns) => columns
+                .iter()
+                .map(|column| {
+                    (
+                        column.output_name(),
+                        self.lookup(&record, column).unwrap_or(Value::Null),
+                    )
+                })
+                .collect(),
+            Projection::All => match record {
+                Record::Csv(fields) => fields
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, field)| {
+                        let name = self
+                            .header
+                            .as_ref()
+                            .and_then(|header| header.get(idx).cloned())
+                            .unwrap_or_else(|| format!("_{}", idx + 1));
+                        (name, Value::String(field))
+                    })
+                    .collect(),
+                Record::Json(Value::Object(object)) => object.into_iter().collect(),
+                Record::Json(value) => vec![("_1".to_string(), value)],
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_with_header() -> InputFormat {
+        InputFormat::Csv(CsvInputFormat {
+            header: HeaderInfo::Use,
+            ..Default::default()
+        })
+    }
+
+    fn run(sql: &str, input: InputFormat, output: OutputFormat, chunks: &[&str]) -> String {
+        let mut executor =
+            SelectExecutor::with_formats(SelectQuery::parse(sql).unwrap(), input, output).unwrap();
+        let mut result = Vec::new();
+        for chunk in chunks {
+            result.extend(executor.process(chunk.as_bytes()).unwrap());
+            if executor.is_done() {
+                break;
+            }
+        }
+        result.extend(executor.finish().unwrap());
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = SelectQuery::parse(
+            "select s.name, _2 from S3Object s where s.age >= 18 and city = 'Gießen' limit 5;",
+        )
+        .unwrap();
+        assert_eq!(query.limit, Some(5));
+        assert_eq!(query.conditions.len(), 2);
+        assert_eq!(
+            query.projection,
+            Projection::Columns(vec![
+                Column::Name(vec![Ident {
+                    name: "name".to_string(),
+                    quoted: false
+                }]),
+                Column::Position(1),
+            ])
+        );
+        assert_eq!(
+            SelectQuery::parse("SELECT s.* FROM S3Object AS s").unwrap(),
+            SelectQuery {
+                projection: Projection::All,
+                conditions: vec![],
+                limit: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        for sql in [
+            "SELECT COUNT(*) FROM S3Object",
+            "SELECT * FROM S3Object WHERE a = 1 OR b = 2",
+            "SELECT * FROM S3Object s WHERE s.a LIKE 'x%'",
+            "SELECT * FROM S3Object ORDER BY a",
+            "SELECT * FROM S3Object[*].a",
+            "SELECT a AS b FROM S3Object",
+            "SELECT * FROM S3Object WHERE a = b",
+            "SELECT * FROM S3Object WHERE (a = 1)",
+        ] {
+            assert!(
+                matches!(SelectQuery::parse(sql), Err(SelectError::Unsupported(_))),
+                "{sql}"
+            );
+        }
+        for sql in [
+            "SELECT",
+            "SELECT * FROM table",
+            "SELECT * FROM S3Object LIMIT x",
+        ] {
+            assert!(
+                matches!(SelectQuery::parse(sql), Err(SelectError::Parse(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let data = [
+            "name,age,city\nalice,30,\"Berlin, DE\"\nbob,1",
+            "7,Gießen\r\ncarol,45,Paris",
+        ];
+        assert_eq!(
+            run(
+                "SELECT s.name, s.city FROM S3Object s WHERE s.age > 18",
+                csv_with_header(),
+                OutputFormat::default(),
+                &data
+            ),
+            "alice,\"Berlin, DE\"\ncarol,Paris\n"
+        );
+        assert_eq!(
+            run(
+                "SELECT _1 FROM S3Object WHERE _3 = 'Gießen'",
+                InputFormat::Csv(CsvInputFormat {
+                    header: HeaderInfo::Ignore,
+                    ..Default::default()
+                }),
+                OutputFormat::Json {
+                    record_delimiter: "\n".to_string()
+                },
+                &data
+            ),
+            "{\"_1\":\"bob\"}\n"
+        );
+        assert_eq!(
+            run(
+                "SELECT * FROM S3Object LIMIT 1",
+                csv_with_header(),
+                OutputFormat::Json {
+                    record_delimiter: "\n".to_string()
+                },
+                &data
+            ),
+            "{\"name\":\"alice\",\"age\":\"30\",\"city\":\"Berlin, DE\"}\n"
+        );
+        // Column names need a header
+        assert!(SelectExecutor::with_formats(
+            SelectQuery::parse("SELECT name FROM S3Object").unwrap(),
+            InputFormat::Csv(CsvInputFormat::default()),
+            OutputFormat::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_csv_chunk_boundaries() {
+        // Quoted record delimiter split across chunks
+        let data = ["name,note\nalice,\"line one", "\nline two\"\nbob,plain\n"];
+        assert_eq!(
+            run(
+                "SELECT s.name FROM S3Object s WHERE s.note = 'plain'",
+                InputFormat::Csv(CsvInputFormat {
+                    header: HeaderInfo::Use,
+                    allow_quoted_record_delimiter: true,
+                    ..Default::default()
+                }),
+                OutputFormat::default(),
+                &data
+            ),
+            "bob\n"
+        );
+
+        let mut executor = SelectExecutor::with_formats(
+            SelectQuery::parse("SELECT * FROM S3Object").unwrap(),
+            InputFormat::Csv(CsvInputFormat::default()),
+            OutputFormat::default(),
+        )
+        .unwrap();
+        let chunk = vec![b'a'; MAX_RECORD_SIZE / 2 + 1];
+        assert!(executor.process(&chunk).is_ok());
+        assert!(matches!(
+            executor.process(&chunk),
+            Err(SelectError::OverMaxRecordSize)
+        ));
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let data = [
+            "{\"id\": 1, \"meta\": {\"kind\": \"a\"}}\n{\"id\": 2, \"meta\": {\"ki",
+            "nd\": \"b\"}}\n{\"id\": 3}",
+        ];
+        assert_eq!(
+            run(
+                "SELECT s.id FROM S3Object s WHERE s.meta.kind != 'a'",
+                InputFormat::JsonLines,
+                OutputFormat::Json {
+                    record_delimiter: "\n".to_string()
+                },
+                &data
+            ),
+            "{\"id\":2}\n"
+        );
+        assert_eq!(
+            run(
+                "SELECT s.id, s.meta.kind FROM S3Object s WHERE s.id >= 2",
+                InputFormat::JsonLines,
+                OutputFormat::default(),
+                &data
+            ),
+            "2,b\n3,\n"
+        );
+    }
+}
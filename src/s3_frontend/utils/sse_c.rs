@@ -0,0 +1,225 @@
+use crate::structs::{CustomerKeyHash, ObjectLocation};
+use base64::engine::general_purpose;
+use base64::Engine;
+use http::{HeaderMap, HeaderValue};
+use md5::{Digest, Md5};
+use s3s::{s3_error, S3Result};
+use tracing::error;
+
+pub const SSE_C_ALGORITHM: &str = "x-amz-server-side-encryption-customer-algorithm";
+pub const SSE_C_KEY: &str = "x-amz-server-side-encryption-customer-key";
+pub const SSE_C_KEY_MD5: &str = "x-amz-server-side-encryption-customer-key-md5";
+
+/// The only algorithm accepted by S3, the data itself is encrypted with ChaCha20
+const ALGORITHM: &str = "AES256";
+
+/// Encryption key provided by the client (SSE-C), only kept for the request
+#[derive(Clone, PartialEq, Eq)]
+pub struct CustomerKey {
+    key: [u8; 32],
+    key_md5: String,
+}
+
+impl std::fmt::Debug for CustomerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomerKey")
+            .field("key_md5", &self.key_md5)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CustomerKey {
+    /// Parses the x-amz-server-side-encryption-customer-* headers, all three are
+    /// required if one of them is present
+    #[tracing::instrument(level = "trace", skip(headers))]
+    pub fn from_headers(headers: &HeaderMap<HeaderValue>) -> S3Result<Option<Self>> {
+        let get = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().unwrap_or_default().to_string())
+        };
+        let (algorithm, key, key_md5) = match (
+            get(SSE_C_ALGORITHM),
+            get(SSE_C_KEY),
+            get(SSE_C_KEY_MD5),
+        ) {
+            (None, None, None) => return Ok(None),
+            (Some(algorithm), Some(key), Some(key_md5)) => (algorithm, key, key_md5),
+            (None, ..) => {
+                error!(error = "Missing SSE-C algorithm");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Requests specifying Server Side Encryption with Customer provided keys must provide a valid encryption algorithm."
+                ));
+            }
+            (_, None, _) => {
+                error!(error = "Missing SSE-C key");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Requests specifying Server Side Encryption with Customer provided keys must provide an appropriate secret key."
+                ));
+            }
+            (_, _, None) => {
+                error!(error = "Missing SSE-C key md5");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Requests specifying Server Side Encryption with Customer provided keys must provide the client calculated MD5 of the secret key."
+                ));
+            }
+        };
+        if algorithm != ALGORITHM {
+            error!(algorithm, error = "Unsupported SSE-C algorithm");
+            return Err(s3_error!(
+                InvalidArgument,
+                "The encryption request you specified is not valid. The valid value is AES256."
+            ));
+        }
+        let key: [u8; 32] = general_purpose::STANDARD
+            .decode(&key)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| {
+                error!(error = "Invalid SSE-C key");
+                s3_error!(
+                    InvalidArgument,
+                    "The secret key was invalid for the specified algorithm."
+                )
+            })?;
+        let calculated = general_purpose::STANDARD.encode(Md5::digest(key));
+        if calculated != key_md5 {
+            error!(error = "SSE-C key md5 mismatch");
+            return Err(s3_error!(
+                InvalidArgument,
+                "The calculated MD5 hash of the key did not match the hash that was provided."
+            ));
+        }
+        Ok(Some(CustomerKey { key, key_md5 }))
+    }
+
+    pub fn key(&self) -> [u8; 32] {
+        self.key
+    }
+
+    pub fn hash(&self) -> CustomerKeyHash {
+        CustomerKeyHash::new(&self.key)
+    }
+
+    /// Response headers confirming the customer key, the key itself is never returned
+    pub fn add_to_headers(&self, headers: &mut HeaderMap<HeaderValue>) {
+        headers.insert(SSE_C_ALGORITHM, HeaderValue::from_static(ALGORITHM));
+        if let Ok(key_md5) = HeaderValue::from_str(&self.key_md5) {
+            headers.insert(SSE_C_KEY_MD5, key_md5);
+        }
+    }
+}
+
+/// Checks the provided key against the location, returns the location with the key of
+/// the request for customer encrypted data
+#[tracing::instrument(level = "trace", skip(location))]
+pub fn unlock_location(
+    location: &ObjectLocation,
+    provided: Option<&CustomerKey>,
+) -> S3Result<ObjectLocation> {
+    match (&location.customer_key, provided) {
+        (None, None) => Ok(location.clone()),
+        (None, Some(_)) => {
+            error!(error = "SSE-C headers for unencrypted object");
+            Err(s3_error!(
+                InvalidRequest,
+                "The encryption parameters are not applicable to this object."
+            ))
+        }
+        (Some(_), None) => {
+            error!(error = "Missing SSE-C headers");
+            Err(s3_error!(
+                InvalidRequest,
+                "The object was stored using a form of Server Side Encryption. The correct parameters must be provided to retrieve the object."
+            ))
+        }
+        (Some(stored), Some(provided)) => {
+            if !stored.matches(&provided.key) {
+                error!(error = "SSE-C key mismatch");
+                return Err(s3_error!(
+                    AccessDenied,
+                    "The provided encryption key does not match the key the object was stored with."
+                ));
+            }
+            let mut location = location.clone();
+            location.file_format = location.file_format.with_key(provided.key);
+            Ok(location)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::FileFormat;
+    use s3s::S3ErrorCode;
+
+    fn headers(algorithm: &str, key: &[u8], key_md5: Option<String>) -> HeaderMap<HeaderValue> {
+        let mut headers = HeaderMap::new();
+        headers.insert(SSE_C_ALGORITHM, HeaderValue::from_str(algorithm).unwrap());
+        headers.insert(
+            SSE_C_KEY,
+            HeaderValue::from_str(&general_purpose::STANDARD.encode(key)).unwrap(),
+        );
+        let key_md5 = key_md5.unwrap_or_else(|| general_purpose::STANDARD.encode(Md5::digest(key)));
+        headers.insert(SSE_C_KEY_MD5, HeaderValue::from_str(&key_md5).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_from_headers() {
+        assert!(CustomerKey::from_headers(&HeaderMap::new())
+            .unwrap()
+            .is_none());
+        let key = CustomerKey::from_headers(&headers("AES256", &[7u8; 32], None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.key(), [7u8; 32]);
+
+        let err = CustomerKey::from_headers(&headers("AES256", &[7u8; 32], Some("AAAA".into())))
+            .unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidArgument);
+        let err = CustomerKey::from_headers(&headers("AES256", &[7u8; 16], None)).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidArgument);
+        let err = CustomerKey::from_headers(&headers("aws:kms", &[7u8; 32], None)).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidArgument);
+
+        let mut partial = headers("AES256", &[7u8; 32], None);
+        partial.remove(SSE_C_KEY_MD5);
+        assert!(CustomerKey::from_headers(&partial).is_err());
+    }
+
+    #[test]
+    fn test_unlock_location() {
+        let key = CustomerKey::from_headers(&headers("AES256", &[7u8; 32], None))
+            .unwrap()
+            .unwrap();
+        let other = CustomerKey::from_headers(&headers("AES256", &[8u8; 32], None))
+            .unwrap()
+            .unwrap();
+        let location = ObjectLocation {
+            file_format: FileFormat::customer_encrypted(false),
+            customer_key: Some(key.hash()),
+            ..Default::default()
+        };
+        // Only a salted hash is stored
+        assert!(!serde_json::to_string(&location)
+            .unwrap()
+            .contains(&general_purpose::STANDARD.encode([7u8; 32])));
+
+        let unlocked = unlock_location(&location, Some(&key)).unwrap();
+        assert_eq!(unlocked.get_encryption_key(), Some([7u8; 32]));
+        let err = unlock_location(&location, Some(&other)).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::AccessDenied);
+        let err = unlock_location(&location, None).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidRequest);
+
+        let plain = ObjectLocation::default();
+        assert!(unlock_location(&plain, None).is_ok());
+        let err = unlock_location(&plain, Some(&key)).unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidRequest);
+    }
+}
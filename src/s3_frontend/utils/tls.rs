@@ -0,0 +1,163 @@
+use crate::config::Tls;
+use crate::shutdown::Shutdown;
+use anyhow::{anyhow, bail, Result};
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrStream;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, error, info, warn};
+
+/// Interval in which the certificate files are checked for changes
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Slow or stalled handshakes are dropped after this duration
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections that know the address of the client
+pub trait RemoteAddr {
+    fn remote_ip(&self) -> Option<IpAddr>;
+}
+
+impl RemoteAddr for AddrStream {
+    fn remote_ip(&self) -> Option<IpAddr> {
+        Some(self.remote_addr().ip())
+    }
+}
+
+impl RemoteAddr for TlsStream<TcpStream> {
+    fn remote_ip(&self) -> Option<IpAddr> {
+        self.get_ref().0.peer_addr().ok().map(|addr| addr.ip())
+    }
+}
+
+/// Server config that is rebuilt when the certificate, key or client CA files change
+///
+/// New connections use the latest config, established connections keep theirs.
+pub struct ReloadingTlsConfig {
+    tls: Tls,
+    current: RwLock<(Arc<ServerConfig>, Option<SystemTime>)>,
+}
+
+impl ReloadingTlsConfig {
+    #[tracing::instrument(level = "trace")]
+    pub fn new(tls: Tls) -> Result<Self> {
+        let modified = last_modified(&tls);
+        let config = load_server_config(&tls).map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })?;
+        Ok(Self {
+            tls,
+            current: RwLock::new((config, modified)),
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        let current = self.current.read().unwrap_or_else(|e| e.into_inner());
+        TlsAcceptor::from(current.0.clone())
+    }
+
+    /// Reloads the files if one of them changed, on errors (e.g. a partially
+    /// written renewal) the previous config stays active and the reload is retried
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn reload_if_changed(&self) {
+        let modified = last_modified(&self.tls);
+        if self.current.read().unwrap_or_else(|e| e.into_inner()).1 == modified {
+            return;
+        }
+        match load_server_config(&self.tls) {
+            Ok(config) => {
+                *self.current.write().unwrap_or_else(|e| e.into_inner()) = (config, modified);
+                info!(cert = self.tls.cert, "Reloaded TLS certificate");
+            }
+            Err(e) => {
+                error!(error = ?e, msg = "Unable to reload TLS certificate, keeping the previous one");
+            }
+        }
+    }
+}
+
+/// Latest modification of the configured files
+fn last_modified(tls: &Tls) -> Option<SystemTime> {
+    [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+fn load_server_config(tls: &Tls) -> Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&tls.cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", tls.cert)
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&tls.key)?))?
+        .ok_or_else(|| anyhow!("No private key found in {}", tls.key))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match &tls.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(client_ca)?)) {
+                roots.add(cert?)?;
+            }
+            builder
+                .with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Accepts TLS connections until the shutdown is triggered
+///
+/// Handshakes run concurrently, a failed handshake only drops its own connection.
+#[tracing::instrument(level = "trace", skip(listener, config, shutdown))]
+pub fn tls_incoming(
+    listener: TcpListener,
+    config: Arc<ReloadingTlsConfig>,
+    shutdown: Shutdown,
+) -> impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error> {
+    let (sender, receiver) = mpsc::channel::<io::Result<TlsStream<TcpStream>>>(128);
+    crate::spawn(async move {
+        loop {
+            let (stream, remote) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // e.g. too many open files, retry after a short pause
+                        warn!(error = ?e, msg = e.to_string());
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+                _ = shutdown.signaled() => return,
+            };
+            let acceptor = config.acceptor();
+            let sender = sender.clone();
+            crate::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => debug!(%remote, error = ?e, "TLS handshake failed"),
+                    Err(_) => debug!(%remote, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    hyper::server::accept::from_stream(ReceiverStream::new(receiver))
+}
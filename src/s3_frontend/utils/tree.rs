@@ -0,0 +1,293 @@
+//! Folder tree of the portal, the rollup counters of a hierarchy node and its children
+//!
+//! `GET /.aruna/tree/<bucket>[/<prefix>]` with an aruna token (`Authorization: Bearer`)
+//! returns the node as JSON. The counters follow the same rules as the
+//! `x-aruna-prefix-stats` listing extension: stats are only returned for nodes the
+//! caller can read completely, children without any read access are not listed.
+use super::object_key::url_decode;
+use crate::auth::auth_helpers::get_token_from_md;
+use crate::caching::cache::Cache;
+use crate::helpers::split_s3_path;
+use crate::structs::{DbPermissionLevel, PrefixStats, TypedId};
+use diesel_ulid::DieselUlid;
+use http::{Method, StatusCode};
+use s3s::Body;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::metadata::MetadataMap;
+use tracing::{debug, error};
+
+/// Reserved bucket of the endpoint, bucket names can not start with a dot
+const TREE_BUCKET: &str = ".aruna";
+const TREE_PREFIX: &str = "tree/";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeNode {
+    /// Bucket and key of the node without trailing delimiter
+    pub path: String,
+    pub id: DieselUlid,
+    /// `Project`, `Collection`, `Dataset` or `Object`
+    pub resource_type: String,
+    /// Omitted if the caller can only read a part of the subtree
+    pub stats: Option<PrefixStats>,
+    pub children: Vec<TreeChild>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeChild {
+    pub name: String,
+    pub id: DieselUlid,
+    pub resource_type: String,
+    pub stats: Option<PrefixStats>,
+}
+
+/// Path of the requested node if the request addresses the tree endpoint
+pub fn tree_path<B>(req: &http::Request<B>, base_domain: &str) -> Option<String> {
+    if req.method() != Method::GET {
+        return None;
+    }
+    match split_s3_path(req, base_domain) {
+        (Some(bucket), Some(key)) if bucket == TREE_BUCKET => key
+            .strip_prefix(TREE_PREFIX)
+            .and_then(|path| url_decode(path.trim_end_matches('/'))),
+        _ => None,
+    }
+}
+
+/// Answers a tree request, `path` is the result of `tree_path`
+#[tracing::instrument(level = "trace", skip(cache, req))]
+pub async fn handle_tree(
+    cache: Arc<Cache>,
+    path: &str,
+    req: &hyper::Request<hyper::Body>,
+) -> hyper::Response<Body> {
+    let Some(permissions) = authenticate(&cache, req).await else {
+        return empty_response(StatusCode::UNAUTHORIZED);
+    };
+    // Unknown nodes and nodes without any readable entry are not distinguished
+    let Some(node) = tree_node(&cache, path, &permissions).await else {
+        debug!(path, "Tree node not found or not readable");
+        return empty_response(StatusCode::NOT_FOUND);
+    };
+    let body = match serde_json::to_vec(&node) {
+        Ok(body) => body,
+        Err(e) => {
+            error!(error = ?e, msg = e.to_string());
+            return empty_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap_or_else(|_| empty_response(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// Permissions of the user (or token) of the aruna token in the request
+async fn authenticate(
+    cache: &Cache,
+    req: &hyper::Request<hyper::Body>,
+) -> Option<HashMap<DieselUlid, DbPermissionLevel>> {
+    let token = get_token_from_md(&MetadataMap::from_headers(req.headers().clone()))
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })
+        .ok()?;
+    let (user_id, token_id, pk) = cache
+        .auth
+        .read()
+        .await
+        .as_ref()?
+        .check_permissions(&token)
+        .await
+        .map_err(|e| {
+            error!(error = ?e, msg = e.to_string());
+            e
+        })
+        .ok()?;
+    if pk.is_proxy {
+        error!(error = "Proxy token is not allowed");
+        return None;
+    }
+    let access_key = token_id.unwrap_or_else(|| user_id.to_string());
+    cache
+        .get_key_perms(&access_key)
+        .await
+        .map(|key| key.permissions)
+}
+
+/// Node of the path with its children, None if the path is unknown or the caller
+/// can not read anything below it
+pub async fn tree_node(
+    cache: &Cache,
+    path: &str,
+    permissions: &HashMap<DieselUlid, DbPermissionLevel>,
+) -> Option<TreeNode> {
+    let id = cache.get_path(path)?;
+    let (object, _) = cache.get_resource_cloned(&id, true).await.ok()?;
+    let mut children = Vec::new();
+    for (name, child) in cache.get_children(&id).await.unwrap_or_default() {
+        let child_id = child.get_id();
+        if !cache.grants_subtree_read(&child_id, permissions).await {
+            continue;
+        }
+        children.push(TreeChild {
+            name,
+            id: child_id,
+            resource_type: resource_type(&child),
+            stats: cache.get_visible_prefix_stats(&child_id, permissions).await,
+        });
+    }
+    let stats = cache.get_visible_prefix_stats(&id, permissions).await;
+    if stats.is_none() && children.is_empty() {
+        return None;
+    }
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    Some(TreeNode {
+        path: path.to_string(),
+        id,
+        resource_type: format!("{:?}", object.object_type),
+        stats,
+        children,
+    })
+}
+
+fn resource_type(typed_id: &TypedId) -> String {
+    match typed_id {
+        TypedId::Project(_) => "Project",
+        TypedId::Collection(_) => "Collection",
+        TypedId::Dataset(_) => "Dataset",
+        TypedId::Object(_) => "Object",
+        TypedId::Unknown(_) => "Unknown",
+    }
+    .to_string()
+}
+
+fn empty_response(status: StatusCode) -> hyper::Response<Body> {
+    let mut response = hyper::Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caching::cache::test_cache;
+    use crate::structs::{Object, ObjectLocation, ObjectType, TypedRelation};
+
+    #[test]
+    fn test_tree_path() {
+        let request = |method: Method, host: &str, uri: &str| {
+            http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::HOST, host)
+                .body(())
+                .unwrap()
+        };
+        let path = |method, host, uri| tree_path(&request(method, host, uri), "localhost:1337");
+        assert_eq!(
+            path(
+                Method::GET,
+                "localhost:1337",
+                "/.aruna/tree/bucket/my%20dir/"
+            ),
+            Some("bucket/my dir".to_string())
+        );
+        assert_eq!(
+            path(Method::GET, "localhost:1337", "/.aruna/tree/bucket"),
+            Some("bucket".to_string())
+        );
+        assert_eq!(
+            path(Method::PUT, "localhost:1337", "/.aruna/tree/bucket"),
+            None
+        );
+        // Keys of virtual-hosted buckets are not affected
+        assert_eq!(
+            path(Method::GET, "bucket.localhost:1337", "/.aruna/tree/bucket"),
+            None
+        );
+        assert_eq!(path(Method::GET, "localhost:1337", "/bucket/tree/x"), None);
+    }
+
+    #[tokio::test]
+    async fn test_tree_node_permissions() {
+        let (cache, _receiver) = test_cache(None).await;
+
+        let mut project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let mut collections = Vec::new();
+        let mut objects = Vec::new();
+        for name in ["a", "b"] {
+            let mut collection = Object::initialize_now(
+                name.to_string(),
+                ObjectType::Collection,
+                Some(TypedRelation::Project(project.id)),
+            );
+            let object = Object::initialize_now(
+                "file.txt".to_string(),
+                ObjectType::Object,
+                Some(TypedRelation::Collection(collection.id)),
+            );
+            collection.children = Some([TypedRelation::Object(object.id)].into());
+            collections.push(collection);
+            objects.push(object);
+        }
+        project.children = Some(
+            collections
+                .iter()
+                .map(|c| TypedRelation::Collection(c.id))
+                .collect(),
+        );
+        cache.upsert_object(project.clone()).await.unwrap();
+        for resource in collections.iter().chain(objects.iter()) {
+            cache.upsert_object(resource.clone()).await.unwrap();
+        }
+        for (object, size) in objects.iter().zip([10, 20]) {
+            let location = ObjectLocation {
+                id: DieselUlid::generate(),
+                raw_content_len: size,
+                ..Default::default()
+            };
+            cache
+                .add_location_with_binding(object.id, location)
+                .await
+                .unwrap();
+        }
+
+        // Read on the project, everything is visible
+        let all = HashMap::from([(project.id, DbPermissionLevel::Read)]);
+        let node = tree_node(&cache, "bucket", &all).await.unwrap();
+        assert_eq!(
+            node.stats,
+            Some(PrefixStats {
+                object_count: 2,
+                total_size: 30
+            })
+        );
+        assert_eq!(
+            node.children
+                .iter()
+                .map(|c| (c.name.as_str(), c.stats.map(|s| s.total_size)))
+                .collect::<Vec<_>>(),
+            vec![("a", Some(10)), ("b", Some(20))]
+        );
+
+        // Read on one collection, the project counters would leak the other one
+        let partial = HashMap::from([(collections[0].id, DbPermissionLevel::Read)]);
+        let node = tree_node(&cache, "bucket", &partial).await.unwrap();
+        assert_eq!(node.stats, None);
+        assert_eq!(node.children.len(), 1);
+        assert_eq!(node.children[0].name, "a");
+        assert_eq!(node.children[0].stats.map(|s| s.object_count), Some(1));
+        let node = tree_node(&cache, "bucket/a", &partial).await.unwrap();
+        assert_eq!(node.children[0].stats.map(|s| s.total_size), Some(10));
+        assert!(tree_node(&cache, "bucket/b", &partial).await.is_none());
+
+        // Below read, nothing is visible
+        let none = HashMap::from([(project.id, DbPermissionLevel::None)]);
+        assert!(tree_node(&cache, "bucket", &none).await.is_none());
+        assert!(tree_node(&cache, "unknown", &all).await.is_none());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::caching::cache::Cache;
+use crate::config::UploadLimits;
+use crate::s3_frontend::utils::buffered_s3_sink::PartSizer;
+use crate::structs::UserState;
+use crate::CONFIG;
+use diesel_ulid::DieselUlid;
+use futures::{StreamExt, TryStreamExt};
+use http::HeaderMap;
+use s3s::dto::StreamingBlob;
+use s3s::{s3_error, S3Error};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::{debug, error};
+
+/// Stricter one of two limits, 0 disables a limit
+fn min_limit(a: u64, b: u64) -> u64 {
+    match (a, b) {
+        (0, limit) | (limit, 0) => limit,
+        (a, b) => a.min(b),
+    }
+}
+
+/// Largest body of a PutObject, the buffered bytes bound the size of the backend parts
+pub fn object_limit(limits: &UploadLimits) -> u64 {
+    let buffered = match limits.max_inflight_bytes {
+        0 => 0,
+        max => PartSizer::new(None)
+            .with_max_part_size(max)
+            .max_object_size(),
+    };
+    min_limit(limits.max_object_size, buffered)
+}
+
+/// Largest body of an UploadPart, parts are buffered completely
+pub fn part_limit(limits: &UploadLimits) -> u64 {
+    min_limit(limits.max_part_size, limits.max_inflight_bytes)
+}
+
+/// Rejects declared sizes above the limit before any data is read
+pub fn check_declared(what: &str, len: u64, limit: u64) -> Result<(), S3Error> {
+    if limit > 0 && len > limit {
+        error!(what, len, limit, "Declared size exceeds the limit");
+        return Err(s3_error!(
+            EntityTooLarge,
+            "{what} of {len} bytes exceeds the limit of {limit} bytes"
+        ));
+    }
+    Ok(())
+}
+
+/// Upload size limits of the proxy config with the overrides of the project
+pub fn project_limits(cache: &Cache, project_id: Option<&DieselUlid>) -> UploadLimits {
+    let defaults = CONFIG
+        .frontend
+        .as_ref()
+        .map(|frontend| frontend.upload_limits)
+        .unwrap_or_default();
+    match project_id.and_then(|id| cache.get_upload_limits(id)) {
+        Some(policy) => policy.apply(defaults),
+        None => defaults,
+    }
+}
+
+/// Rejects uploads of `additional` bytes that would exceed the quota of a project,
+/// proxy admins may bypass the check with `x-aruna-quota-override: true`
+#[tracing::instrument(level = "trace", skip(cache, headers, user_state))]
+pub fn check_quota(
+    cache: &Cache,
+    project_ids: &[DieselUlid],
+    additional: i64,
+    headers: &HeaderMap,
+    user_state: &UserState,
+) -> Result<(), S3Error> {
+    let bypass = headers
+        .get("x-aruna-quota-override")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"));
+    if bypass {
+        let user_id = user_state.get_user_id();
+        if !user_id.is_some_and(|id| CONFIG.proxy.admin_ids.contains(&id)) {
+            error!(?user_id, error = "Quota override without admin rights");
+            return Err(s3_error!(
+                AccessDenied,
+                "Quota override requires proxy admin rights"
+            ));
+        }
+        debug!(
+            ?user_id,
+            ?project_ids,
+            "Quota check bypassed by proxy admin"
+        );
+        return Ok(());
+    }
+    for project_id in project_ids {
+        if let Some(quota) = cache.get_quota(project_id) {
+            quota.check(project_id, &cache.get_bucket_usage(project_id), additional)?;
+        }
+    }
+    Ok(())
+}
+
+/// Tracks whether a body was aborted by [`limit_body`]
+#[derive(Debug, Clone)]
+pub struct BodyLimit {
+    limit: u64,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl BodyLimit {
+    /// EntityTooLarge if the body was aborted, to be checked after a failed upload
+    pub fn check(&self) -> Result<(), S3Error> {
+        if self.exceeded.load(Ordering::Relaxed) {
+            error!(limit = self.limit, "Request body exceeds the limit");
+            return Err(s3_error!(
+                EntityTooLarge,
+                "Request body exceeds the limit of {} bytes",
+                self.limit
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Fails the body stream as soon as more than `limit` bytes were received, bodies of
+/// chunked uploads are not known in advance. 0 returns the body as is.
+pub fn limit_body(body: StreamingBlob, limit: u64) -> (StreamingBlob, BodyLimit) {
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let body_limit = BodyLimit {
+        limit,
+        exceeded: exceeded.clone(),
+    };
+    if limit == 0 {
+        return (body, body_limit);
+    }
+    let mut received = 0u64;
+    let stream = body
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        .map(move |chunk| {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+            if received > limit {
+                exceeded.store(true, Ordering::Relaxed);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Request body exceeds the limit of {limit} bytes"),
+                ));
+            }
+            Ok(chunk)
+        });
+    (StreamingBlob::wrap(stream), body_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::UploadLimitsPolicy;
+    use bytes::Bytes;
+    use futures::stream;
+    use s3s::S3ErrorCode;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn body(chunks: &[usize]) -> StreamingBlob {
+        StreamingBlob::wrap(stream::iter(
+            chunks
+                .iter()
+                .map(|len| Ok::<_, std::io::Error>(Bytes::from(vec![0u8; *len])))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut limits = UploadLimits::default();
+        assert_eq!(object_limit(&limits), 0);
+        assert_eq!(part_limit(&limits), 5 * 1024 * MIB);
+
+        limits.max_object_size = 100 * MIB;
+        limits.max_inflight_bytes = 8 * MIB;
+        assert_eq!(object_limit(&limits), 100 * MIB);
+        assert_eq!(part_limit(&limits), 8 * MIB);
+
+        // Buffered parts bound the size of a single upload
+        limits.max_object_size = 0;
+        assert_eq!(object_limit(&limits), 8 * MIB * 9_500);
+    }
+
+    #[test]
+    fn test_project_overrides() {
+        let policy: UploadLimitsPolicy =
+            serde_json::from_str(r#"{"max_object_size": 1024, "max_part_size": 0}"#).unwrap();
+        let limits = policy.apply(UploadLimits {
+            max_inflight_bytes: 8 * MIB,
+            ..Default::default()
+        });
+        assert_eq!(limits.max_object_size, 1024);
+        assert_eq!(limits.max_multipart_size, 0);
+        // Disabled part size, the buffered bytes still apply
+        assert_eq!(part_limit(&limits), 8 * MIB);
+    }
+
+    #[test]
+    fn test_declared_length() {
+        assert!(check_declared("Object", 100, 0).is_ok());
+        assert!(check_declared("Object", 100, 100).is_ok());
+        let err = check_declared("Object", 101, 100).unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::EntityTooLarge);
+        assert_eq!(
+            err.message(),
+            Some("Object of 101 bytes exceeds the limit of 100 bytes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body_over_limit() {
+        let (limited, limit) = limit_body(body(&[40, 40, 40, 40]), 100);
+        let chunks = limited.collect::<Vec<_>>().await;
+        // The stream fails with the chunk that exceeds the limit
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks[..2].iter().all(|c| c.is_ok()));
+        assert!(chunks[2].is_err());
+        let err = limit.check().unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::EntityTooLarge);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body_within_limit() {
+        let (limited, limit) = limit_body(body(&[40, 40, 20]), 100);
+        let chunks = limited.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), 100);
+        assert!(limit.check().is_ok());
+
+        let (unlimited, limit) = limit_body(body(&[1000]), 0);
+        assert_eq!(unlimited.try_collect::<Vec<_>>().await.unwrap().len(), 1);
+        assert!(limit.check().is_ok());
+    }
+}
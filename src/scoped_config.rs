@@ -0,0 +1,284 @@
+//! Config of the dataproxy the current task belongs to
+//!
+//! Multiple dataproxies can be embedded into one process, every one of them runs its
+//! tasks (and the requests of its servers) with its own config and the state that belongs
+//! to the dataproxy instead of the process: the measured clock skew, the metrics and the
+//! webhook clients. The config is only available within a dataproxy, callers outside of
+//! its tasks use `DataProxy::scope`.
+use crate::auth::clock::{ClockSkew, SystemClock};
+use crate::caching::webhooks::WebhookClients;
+use crate::config::Config;
+use crate::metrics::Metrics;
+use anyhow::Result;
+use futures_core::future::BoxFuture;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+
+tokio::task_local! {
+    static SCOPE: &'static Scope;
+}
+
+/// Config of a dataproxy and the state kept per dataproxy
+pub(crate) struct Scope {
+    pub config: Config,
+    pub state: ScopedState,
+}
+
+/// Clock skew, metrics and webhook clients of a dataproxy
+pub(crate) struct ScopedState {
+    pub clock: ClockSkew,
+    pub metrics: Metrics,
+    pub webhook_clients: WebhookClients,
+}
+
+impl ScopedState {
+    fn new() -> Result<Self> {
+        Ok(ScopedState {
+            clock: ClockSkew::new(Box::new(SystemClock)),
+            metrics: Metrics::new()?,
+            webhook_clients: WebhookClients::new()?,
+        })
+    }
+}
+
+/// Config of the current dataproxy, dereferences to the config of the scope of the task
+pub struct CurrentConfig;
+
+pub(crate) static CONFIG: CurrentConfig = CurrentConfig;
+
+impl Deref for CurrentConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &current_scope().config
+    }
+}
+
+/// Validates the config of a dataproxy and creates its state
+///
+/// The scope lives as long as the process, every dataproxy is built once.
+pub(crate) fn init(mut config: Config) -> Result<&'static Scope> {
+    config.validate()?;
+    Ok(Box::leak(Box::new(Scope {
+        config,
+        state: ScopedState::new()?,
+    })))
+}
+
+/// Scope of the current task, None outside of a dataproxy
+pub(crate) fn current() -> Option<&'static Scope> {
+    SCOPE.try_with(|scope| *scope).ok()
+}
+
+/// Scope of the current task, the config is never read outside of a dataproxy
+pub(crate) fn current_scope() -> &'static Scope {
+    match current() {
+        Some(scope) => scope,
+        #[cfg(test)]
+        None => test_scope(),
+        #[cfg(not(test))]
+        None => panic!("Dataproxy config used outside of a dataproxy, see DataProxy::scope"),
+    }
+}
+
+/// State of the current dataproxy
+///
+/// Calls of the public API outside of a dataproxy, e.g. pre-populating its cache, record
+/// into a detached state that is not served by any dataproxy.
+pub(crate) fn state() -> &'static ScopedState {
+    match current() {
+        Some(scope) => &scope.state,
+        #[cfg(test)]
+        None => &test_scope().state,
+        #[cfg(not(test))]
+        None => {
+            static DETACHED: OnceLock<ScopedState> = OnceLock::new();
+            DETACHED.get_or_init(|| ScopedState::new().expect("valid detached state"))
+        }
+    }
+}
+
+/// Unit tests run outside of a dataproxy with the config of the repository
+#[cfg(test)]
+fn test_scope() -> &'static Scope {
+    static TEST_SCOPE: OnceLock<&'static Scope> = OnceLock::new();
+    TEST_SCOPE.get_or_init(|| {
+        let mut config: Config =
+            toml::from_str(include_str!("../config.toml")).expect("valid test config");
+        config.source = Some("config.toml".to_string());
+        init(config).expect("valid test config")
+    })
+}
+
+/// Runs the future within the scope of a dataproxy, e.g. one of its tasks
+pub(crate) async fn scope<F: Future>(scope: &'static Scope, future: F) -> F::Output {
+    SCOPE.scope(scope, future).await
+}
+
+/// Runs a synchronous function within the scope of a dataproxy
+pub(crate) fn sync_scope<R>(scope: &'static Scope, f: impl FnOnce() -> R) -> R {
+    SCOPE.sync_scope(scope, f)
+}
+
+/// Keeps the scope of the current task for a spawned task
+pub(crate) fn in_current_config<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let current = current();
+    async move {
+        match current {
+            Some(current) => scope(current, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// `tokio::spawn` for tasks of the current dataproxy
+pub(crate) fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(in_current_config(future))
+}
+
+/// Tower layer for the gRPC server: calls are handled within the scope of the dataproxy
+/// that created the layer, hyper drives every connection in a task of its own
+#[derive(Clone)]
+pub struct ConfigLayer {
+    scope: &'static Scope,
+}
+
+impl ConfigLayer {
+    pub fn current() -> Self {
+        ConfigLayer {
+            scope: current_scope(),
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for ConfigLayer {
+    type Service = ConfigService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConfigService {
+            inner,
+            scope: self.scope,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConfigService<S> {
+    inner: S,
+    scope: &'static Scope,
+}
+
+impl<S, R> tower::Service<R> for ConfigService<S>
+where
+    S: tower::Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let current = self.scope;
+        let future = sync_scope(current, || self.inner.call(req));
+        Box::pin(scope(current, future))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::clock::{ARUNA_ORIGIN, CLOCK_SKEW};
+    use crate::metrics::S3_THROTTLED;
+    use std::time::{Duration, SystemTime};
+
+    fn config(endpoint_id: &str) -> &'static Scope {
+        let config = format!(
+            r#"
+            rules = []
+
+            [proxy]
+            endpoint_id = "{endpoint_id}"
+            private_key = "MC4CAQAwBQYDK2VwBCIEIM/FI+bYw+auSKGyGqeISRIEjofvZV/lbK7QL1wkuCey"
+            public_key = "MCowBQYDK2VwAyEAnouQBh4GHPCD/k85VIzPyCdOijVg2qlzt2TELwTMy4c="
+            serial = 1337
+            enable_ingest = false
+            admin_ids = []
+            grpc_server = "127.0.0.1:0"
+            remote_synced = false
+
+            [backend.filesystem]
+            root_path = "/tmp/scoped_config"
+            encryption = false
+            compression = false
+            backend_scheme = "s3://{{{{PROJECT_NAME}}}}/{{{{OBJECT_NAME}}}}"
+            "#
+        );
+        init(toml::from_str(&config).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scopes_are_independent() {
+        assert!(current().is_none());
+        let first = config("01H81W0ZMB54YEP5711Q2BK46V");
+        let second = config("01HVTJ6ZVWRVMP2S07F8N8V8YE");
+
+        let spawned = |config| {
+            scope(config, async {
+                spawn(async { CONFIG.proxy.endpoint_id }).await.unwrap()
+            })
+        };
+        let (a, b) = tokio::join!(spawned(first), spawned(second));
+        assert_eq!(a, first.config.proxy.endpoint_id);
+        assert_eq!(b, second.config.proxy.endpoint_id);
+        assert_ne!(a, b);
+        assert!(current().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_state_is_kept_per_scope() {
+        let first = config("01H81W0ZMB54YEP5711Q2BK46V");
+        let second = config("01HVTJ6ZVWRVMP2S07F8N8V8YE");
+
+        scope(first, async {
+            spawn(async {
+                CLOCK_SKEW.record(SystemTime::now() + Duration::from_secs(120), ARUNA_ORIGIN);
+                S3_THROTTLED.with_label_values(&["put"]).inc();
+            })
+            .await
+            .unwrap()
+        })
+        .await;
+
+        assert!(first.state.clock.origin_skew(ARUNA_ORIGIN).is_some());
+        assert_eq!(
+            first
+                .state
+                .metrics
+                .s3_throttled
+                .with_label_values(&["put"])
+                .get(),
+            1
+        );
+        assert_eq!(second.state.clock.origin_skew(ARUNA_ORIGIN), None);
+        assert_eq!(
+            second
+                .state
+                .metrics
+                .s3_throttled
+                .with_label_values(&["put"])
+                .get(),
+            0
+        );
+    }
+}
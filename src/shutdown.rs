@@ -0,0 +1,155 @@
+use crate::caching::cache::Cache;
+use crate::data_backends::storage_backend::StorageBackend;
+use crate::CONFIG;
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// Shutdown signal shared by the servers and background tasks of a dataproxy
+///
+/// After the signal is triggered the servers stop accepting new connections,
+/// in-flight requests and the current replication batch get the configured
+/// grace period to finish before they are dropped.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Starts the shutdown of all tasks
+    pub fn trigger(&self) {
+        info!("shutdown triggered");
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once the shutdown was triggered
+    pub fn signaled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.sender.subscribe();
+        async move {
+            // Sender is owned by self, an error means the signal can never fire
+            if receiver.wait_for(|triggered| *triggered).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Runs the task until it finishes or the grace period after the shutdown signal expired
+    pub async fn with_grace<F>(&self, name: &str, task: F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let signaled = self.signaled();
+        let grace = Duration::from_secs(CONFIG.proxy.shutdown_grace_period);
+        tokio::select! {
+            result = task => result,
+            _ = async {
+                signaled.await;
+                tokio::time::sleep(grace).await;
+            } => {
+                warn!(task = name, "grace period expired, dropping in-flight work");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Releases the multipart uploads that are still in flight after the servers stopped
+///
+/// With persistence the upload, its location and parts survive the restart and the
+/// client can continue the upload afterwards. Without persistence the records are lost
+/// on exit, the backend uploads are aborted and their locations dropped instead of
+/// leaving orphans behind. Returns the number of aborted uploads.
+pub async fn release_uploads(cache: &Cache, backend: &dyn StorageBackend) -> usize {
+    let uploads = cache.all_multipart_uploads();
+    if cache.is_persistent().await {
+        if !uploads.is_empty() {
+            info!(
+                uploads = uploads.len(),
+                "keeping persisted multipart uploads"
+            );
+        }
+        return 0;
+    }
+    let mut aborted = 0;
+    for upload in uploads {
+        let object_id = cache.get_path(&format!("{}/{}", upload.bucket, upload.key));
+        let location = match object_id {
+            Some(object_id) => cache
+                .get_location(&object_id)
+                .await
+                .filter(|l| l.upload_id.as_ref() == Some(&upload.upload_id))
+                .map(|location| (object_id, location)),
+            None => None,
+        };
+        if let Some((object_id, location)) = location {
+            if let Err(e) = backend
+                .abort_multipart_upload(location, upload.upload_id.clone())
+                .await
+            {
+                error!(error = ?e, upload_id = upload.upload_id, msg = "Unable to abort upload");
+            }
+            if let Err(e) = cache.remove_location(object_id).await {
+                error!(error = ?e, msg = e.to_string());
+            }
+        }
+        if let Err(e) = cache
+            .delete_parts_by_upload_id(upload.upload_id.clone())
+            .await
+        {
+            error!(error = ?e, msg = e.to_string());
+        }
+        if let Err(e) = cache.remove_multipart_upload(&upload.upload_id).await {
+            error!(error = ?e, msg = e.to_string());
+        }
+        aborted += 1;
+    }
+    if aborted > 0 {
+        warn!(uploads = aborted, "aborted in-flight multipart uploads");
+    }
+    aborted
+}
+
+/// Waits for SIGTERM or SIGINT (Ctrl+C)
+pub async fn wait_for_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .map_err(|e| {
+                error!(error = ?e, msg = e.to_string());
+                e
+            })?;
+        tokio::select! {
+            _ = sigterm.recv() => info!("received SIGTERM"),
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                info!("received SIGINT");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        info!("received SIGINT");
+    }
+    Ok(())
+}
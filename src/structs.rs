@@ -4,8 +4,8 @@ use aruna_rust_api::api::storage::models::v2::generic_resource::Resource;
 use aruna_rust_api::api::storage::models::v2::permission::ResourceId;
 use aruna_rust_api::api::storage::models::v2::Pubkey;
 use aruna_rust_api::api::storage::models::v2::{
-    relation::Relation, DataClass, InternalRelationVariant, KeyValue, Object as GrpcObject,
-    PermissionLevel, Project, RelationDirection, Status, User as GrpcUser,
+    relation::Relation, DataClass, InternalRelationVariant, KeyValue, KeyValueVariant,
+    Object as GrpcObject, PermissionLevel, Project, RelationDirection, Status, User as GrpcUser,
 };
 use aruna_rust_api::api::storage::models::v2::{Collection, DataEndpoint};
 use aruna_rust_api::api::storage::models::v2::{Dataset, ResourceVariant};
@@ -20,20 +20,32 @@ use aruna_rust_api::api::storage::services::v2::CreateProjectRequest;
 use aruna_rust_api::api::storage::services::v2::UpdateObjectRequest;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use diesel_ulid::DieselUlid;
-use http::{HeaderValue, Method};
+use http::{HeaderValue, Method, StatusCode};
 use pithos_lib::helpers::structs::{EncryptionKey, FileContext};
 use rand::RngCore;
 use s3s::dto::CreateBucketInput;
+use s3s::dto::{
+    BucketLifecycleConfiguration, ExpirationStatus, GetBucketLifecycleConfigurationOutput,
+    LifecycleExpiration, LifecycleRule, LifecycleRuleFilter,
+};
 use s3s::dto::{CORSRule as S3SCORSRule, GetBucketCorsOutput};
-use s3s::{s3_error, S3Error};
+use s3s::dto::{
+    DefaultRetention, GetObjectRetentionOutput, ObjectLockConfiguration, ObjectLockEnabled,
+    ObjectLockRetention, ObjectLockRetentionMode, ObjectLockRule,
+};
+use s3s::{s3_error, S3Error, S3ErrorCode};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     str::FromStr,
 };
-use tracing::{debug, error};
+use tracing::error;
 
 use crate::auth::auth::AuthHandler;
+use crate::bundler::bundle_helper::BundleMember;
+use crate::caching::policies::RETENTION_KEY;
+use crate::config::{BandwidthLimits, ShardingScheme, UploadLimits};
+use crate::error::ProxyError;
 use crate::helpers::IntoOption;
 use crate::CONFIG;
 
@@ -52,11 +64,54 @@ pub struct Bundle {
     pub ids: Vec<DieselUlid>,
     pub expires_at: Option<DateTime<Utc>>,
     pub once: bool,
+    // Path prefixes (relative to the bundled resources) of included / excluded objects
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // Downloadable without credentials, READ was verified on creation
+    #[serde(default)]
+    pub public: bool,
+    // Prepend a manifest and SHA256SUMS to the archive
+    #[serde(default)]
+    pub manifest: bool,
 }
 impl Bundle {
     pub fn is_default(&self) -> bool {
         self.id == DieselUlid::default()
     }
+
+    /// True if the object path (relative to the bundled resource) is part of the bundle
+    pub fn selects(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        (self.include.is_empty() || self.include.iter().any(|p| path.starts_with(p.as_str())))
+            && !self.exclude.iter().any(|p| path.starts_with(p.as_str()))
+    }
+
+    /// Removes unselected objects and directories without selected objects
+    pub fn filter_levels(
+        &self,
+        levels: Vec<(String, BundleMember)>,
+    ) -> Vec<(String, BundleMember)> {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return levels;
+        }
+        let objects = levels
+            .iter()
+            .filter(|(name, _)| !name.ends_with('/') && self.selects(name))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        levels
+            .into_iter()
+            .filter(|(name, _)| {
+                if name.ends_with('/') {
+                    objects.iter().any(|o| o.starts_with(name.as_str()))
+                } else {
+                    self.selects(name)
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -206,6 +261,34 @@ impl FileFormat {
         }
     }
 
+    /// Format of data encrypted with a customer provided key (SSE-C), the stored
+    /// key is a placeholder and replaced by the key of the request
+    pub fn customer_encrypted(compressed: bool) -> Self {
+        if compressed {
+            FileFormat::RawEncryptedCompressed([0u8; 32])
+        } else {
+            FileFormat::RawEncrypted([0u8; 32])
+        }
+    }
+
+    /// Same format without compression, pithos files keep their (chunk wise) compression
+    pub fn without_compression(&self) -> Self {
+        match self {
+            FileFormat::RawCompressed => FileFormat::Raw,
+            FileFormat::RawEncryptedCompressed(key) => FileFormat::RawEncrypted(*key),
+            other => other.clone(),
+        }
+    }
+
+    pub fn with_key(&self, key: [u8; 32]) -> Self {
+        match self {
+            FileFormat::RawEncrypted(_) => FileFormat::RawEncrypted(key),
+            FileFormat::RawEncryptedCompressed(_) => FileFormat::RawEncryptedCompressed(key),
+            FileFormat::Pithos(_) => FileFormat::Pithos(key),
+            other => other.clone(),
+        }
+    }
+
     pub fn get_encryption_key_as_enc_key(&self) -> EncryptionKey {
         match self {
             FileFormat::RawEncrypted(key)
@@ -228,13 +311,198 @@ pub struct ObjectLocation {
     pub disk_hash: Option<String>,
     pub is_temporary: bool,
     pub ref_count: u32, // Number of objects that reference this location
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub user_metadata: HashMap<String, String>, // x-amz-meta-* provided on upload
+    #[serde(default)]
+    pub raw_hashes: HashMap<String, String>, // Hashes of the raw content, e.g. "sha256"
+    #[serde(default)]
+    pub etag: Option<String>, // Unquoted S3 ETag, md5 or md5-of-md5s-<partcount> for multipart
+    #[serde(default)]
+    pub checksum: Option<ContentChecksum>, // Client requested checksum (x-amz-checksum-*)
+    #[serde(default)]
+    pub sharding: ShardingScheme, // Scheme the bucket / key were derived with
+    #[serde(default)]
+    pub backend: Option<String>, // Named backend storing the data, None for the main backend
+    #[serde(default)]
+    pub corrupt: bool, // Stored data does not match the disk hash, see the integrity scrubber
+    #[serde(default)]
+    pub storage_class: Option<String>, // S3 storage class, None for the default class of the backend
+    #[serde(default)]
+    pub restore: Option<RestoreStatus>, // Requested restore of data in an archive storage class
+    #[serde(default)]
+    pub customer_key: Option<CustomerKeyHash>, // Data is encrypted with a customer provided key (SSE-C)
+    #[serde(default)]
+    pub repaired_at: Option<i64>, // Footer or disk metadata was reconstructed, see the footer repair
+}
+
+/// Salted hash of a customer provided encryption key, the key itself is never stored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomerKeyHash {
+    pub salt: String,
+    pub hash: String,
+}
+
+impl CustomerKeyHash {
+    pub fn new(key: &[u8; 32]) -> Self {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let salt = hex::encode(salt);
+        let hash = Self::calculate(&salt, key);
+        CustomerKeyHash { salt, hash }
+    }
+
+    pub fn matches(&self, key: &[u8; 32]) -> bool {
+        Self::calculate(&self.salt, key) == self.hash
+    }
+
+    fn calculate(salt: &str, key: &[u8; 32]) -> String {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(salt.as_bytes());
+        hasher.update(key);
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Storage classes whose data has to be restored before it can be read
+pub const ARCHIVE_STORAGE_CLASSES: [&str; 2] = ["GLACIER", "DEEP_ARCHIVE"];
+
+/// Storage classes accepted on uploads
+pub const STORAGE_CLASSES: [&str; 8] = [
+    "STANDARD",
+    "REDUCED_REDUNDANCY",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER_IR",
+    "GLACIER",
+    "DEEP_ARCHIVE",
+];
+
+/// Restore of an archived object, reported as `x-amz-restore`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RestoreStatus {
+    pub ongoing: bool,
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+impl RestoreStatus {
+    /// `ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT"`
+    pub fn to_header(&self) -> String {
+        match self.expiry {
+            Some(expiry) if !self.ongoing => format!(
+                "ongoing-request=\"false\", expiry-date=\"{}\"",
+                expiry.format("%a, %d %b %Y %H:%M:%S GMT")
+            ),
+            _ => format!("ongoing-request=\"{}\"", self.ongoing),
+        }
+    }
+
+    /// Parses the `x-amz-restore` value returned by the backend
+    pub fn from_header(value: &str) -> Option<Self> {
+        let field = |name: &str| {
+            let (_, rest) = value.split_once(&format!("{name}=\""))?;
+            rest.split_once('"').map(|(value, _)| value)
+        };
+        let ongoing = field("ongoing-request")?.eq_ignore_ascii_case("true");
+        let expiry = field("expiry-date")
+            .and_then(|date| DateTime::parse_from_rfc2822(&date.replace("GMT", "+0000")).ok())
+            .map(|date| date.with_timezone(&Utc));
+        Some(RestoreStatus { ongoing, expiry })
+    }
+
+    /// The restored copy can be read
+    pub fn is_available(&self, now: DateTime<Utc>) -> bool {
+        !self.ongoing && self.expiry.map(|expiry| expiry > now).unwrap_or(true)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentChecksumAlgorithm {
+    Crc32,
+    Crc32c,
+    Sha1,
+    Sha256,
+}
+
+impl ContentChecksumAlgorithm {
+    /// Parses the algorithm names used by S3 (CRC32, CRC32C, SHA1, SHA256)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "CRC32" => Some(Self::Crc32),
+            "CRC32C" => Some(Self::Crc32c),
+            "SHA1" => Some(Self::Sha1),
+            "SHA256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn header_name(&self) -> &'static str {
+        match self {
+            Self::Crc32 => "x-amz-checksum-crc32",
+            Self::Crc32c => "x-amz-checksum-crc32c",
+            Self::Sha1 => "x-amz-checksum-sha1",
+            Self::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+}
+
+/// Base64 encoded checksum of the raw content, composite checksums
+/// of multipart uploads end with `-<number of parts>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContentChecksum {
+    pub algorithm: ContentChecksumAlgorithm,
+    pub value: String,
+}
+
+impl ContentChecksum {
+    /// Value as (crc32, crc32c, sha1, sha256) for the S3 checksum fields
+    pub fn into_fields(
+        self,
+    ) -> (
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    ) {
+        let value = Some(self.value);
+        match self.algorithm {
+            ContentChecksumAlgorithm::Crc32 => (value, None, None, None),
+            ContentChecksumAlgorithm::Crc32c => (None, value, None, None),
+            ContentChecksumAlgorithm::Sha1 => (None, None, value, None),
+            ContentChecksumAlgorithm::Sha256 => (None, None, None, value),
+        }
+    }
 }
 
 impl ObjectLocation {
+    /// User metadata extended by the raw content hashes, returned as x-amz-meta-*
+    pub fn get_metadata(&self) -> Option<HashMap<String, String>> {
+        let mut metadata = self.user_metadata.clone();
+        // Locations of older versions stored missing hashes as empty strings
+        metadata.extend(
+            self.raw_hashes
+                .iter()
+                .filter(|(_, hash)| !hash.is_empty())
+                .map(|(name, hash)| (name.clone(), hash.clone())),
+        );
+        if metadata.is_empty() {
+            None
+        } else {
+            Some(metadata)
+        }
+    }
+
     pub fn get_encryption_key(&self) -> Option<[u8; 32]> {
         self.file_format.get_encryption_key()
     }
 
+    pub fn is_customer_encrypted(&self) -> bool {
+        self.customer_key.is_some()
+    }
+
     pub fn count_blocks(&self) -> usize {
         match &self.file_format {
             FileFormat::RawCompressed | FileFormat::Raw => {
@@ -261,6 +529,19 @@ impl ObjectLocation {
             _ => false,
         }
     }
+
+    /// Stored in an archive storage class without an available restored copy
+    pub fn is_archived(&self, now: DateTime<Utc>) -> bool {
+        self.storage_class
+            .as_deref()
+            .map(|class| ARCHIVE_STORAGE_CLASSES.contains(&class))
+            .unwrap_or(false)
+            && !self
+                .restore
+                .as_ref()
+                .map(|restore| restore.is_available(now))
+                .unwrap_or(false)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
@@ -285,6 +566,8 @@ pub struct Object {
     pub data_class: DataClass,
     pub object_type: ObjectType,
     pub hashes: HashMap<String, String>,
+    #[serde(default)]
+    pub content_len: i64, // Raw size reported by the server
     pub metadata_license: String,
     pub data_license: String,
     pub dynamic: bool,
@@ -326,6 +609,35 @@ impl Object {
         }
     }
 
+    /// Logical size of the object, a local location takes precedence over
+    /// the size reported by the server
+    pub fn get_raw_size(&self, location: Option<&ObjectLocation>) -> i64 {
+        location
+            .map(|l| l.raw_content_len)
+            .filter(|len| *len > 0)
+            .unwrap_or(self.content_len)
+    }
+
+    /// Last modification as reported by the server, objects are immutable and every
+    /// update creates a new revision with its own timestamp
+    ///
+    /// Falls back to the creation time encoded in the id if the server did not send one.
+    pub fn get_last_modified(&self) -> Option<time::OffsetDateTime> {
+        let seconds = self
+            .created_at
+            .map(|t| t.and_utc().timestamp())
+            .unwrap_or_else(|| (self.id.timestamp() / 1000) as i64);
+        time::OffsetDateTime::from_unix_timestamp(seconds).ok()
+    }
+
+    /// Quoted S3 ETag, objects uploaded without a stored md5 fall back to their id
+    pub fn get_etag(&self, location: Option<&ObjectLocation>) -> String {
+        location
+            .and_then(|l| l.etag.as_ref().or_else(|| l.raw_hashes.get("md5")))
+            .map(|etag| format!("\"{etag}\""))
+            .unwrap_or_else(|| self.id.to_string())
+    }
+
     pub fn get_file_context(
         &self,
         location: Option<ObjectLocation>,
@@ -408,6 +720,19 @@ impl User {
 
         (to_update, to_delete)
     }
+
+    /// Current permissions of an access key (user id or token id) of this user,
+    /// `None` if the token does not exist (anymore)
+    pub fn get_key_permissions(
+        &self,
+        access_key: &str,
+    ) -> Option<HashMap<DieselUlid, DbPermissionLevel>> {
+        if self.user_id.to_string() == access_key {
+            return Some(self.personal_permissions.clone());
+        }
+        let token_id = DieselUlid::from_str(access_key).ok()?;
+        self.tokens.get(&token_id).cloned()
+    }
 }
 
 pub fn perm_convert(perms: Vec<Permission>) -> HashMap<DieselUlid, DbPermissionLevel> {
@@ -509,6 +834,35 @@ pub struct AccessKeyPermissions {
     pub secret: String,
     pub is_service_account: bool,
     pub permissions: HashMap<DieselUlid, DbPermissionLevel>,
+    // Temporary credentials minted with GetSessionToken
+    #[serde(default)]
+    pub session: Option<SessionInfo>,
+}
+
+impl AccessKeyPermissions {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|session| session.expires_at <= now)
+    }
+
+    /// Key that is validated against the server, the minting key for sessions
+    pub fn validated_key(&self) -> &str {
+        self.session
+            .as_ref()
+            .map(|session| session.parent_key.as_str())
+            .unwrap_or(self.access_key.as_str())
+    }
+}
+
+/// Temporary credentials derived from the access key of the minting user. Scoped
+/// sessions only hold the scope permission, unscoped sessions the permissions of
+/// the minting key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub parent_key: String,
+    pub expires_at: DateTime<Utc>,
+    pub scope: Option<(DieselUlid, DbPermissionLevel)>,
 }
 
 // TODO! ENDPOINTS
@@ -544,15 +898,28 @@ pub struct PubKey {
     pub id: i16,
     pub key: String,
     pub is_proxy: bool,
+    /// Endpoint a proxy key belongs to, parsed from the key location
+    #[serde(default)]
+    pub endpoint_id: Option<DieselUlid>,
 }
 
 impl From<Pubkey> for PubKey {
     #[tracing::instrument(level = "trace", skip(value))]
     fn from(value: Pubkey) -> Self {
+        let is_proxy = value.location.contains("proxy");
+        let endpoint_id = is_proxy
+            .then(|| {
+                value
+                    .location
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .find_map(|part| DieselUlid::from_str(part).ok())
+            })
+            .flatten();
         Self {
             id: value.id as i16,
             key: value.key,
-            is_proxy: value.location.contains("proxy"),
+            is_proxy,
+            endpoint_id,
         }
     }
 }
@@ -777,6 +1144,7 @@ impl TryFrom<Project> for Object {
             data_class: value.data_class(),
             object_type: ObjectType::Project,
             hashes: HashMap::default(),
+            content_len: 0,
             metadata_license: value.metadata_license_tag,
             data_license: value.default_data_license_tag,
             dynamic: value.dynamic,
@@ -849,6 +1217,7 @@ impl TryFrom<Collection> for Object {
             data_class: value.data_class(),
             object_type: ObjectType::Collection,
             hashes: HashMap::default(),
+            content_len: 0,
             metadata_license: value.metadata_license_tag,
             data_license: value.default_data_license_tag,
             dynamic: value.dynamic,
@@ -921,6 +1290,7 @@ impl TryFrom<Dataset> for Object {
             data_class: value.data_class(),
             object_type: ObjectType::Dataset,
             hashes: HashMap::default(),
+            content_len: 0,
             metadata_license: value.metadata_license_tag,
             data_license: value.default_data_license_tag,
             dynamic: value.dynamic,
@@ -993,6 +1363,7 @@ impl TryFrom<GrpcObject> for Object {
             data_class: value.data_class(),
             object_type: ObjectType::Object,
             hashes: HashMap::default(),
+            content_len: value.content_len,
             metadata_license: value.metadata_license_tag,
             data_license: value.data_license_tag,
             dynamic: value.dynamic,
@@ -1172,53 +1543,140 @@ impl Object {
         Ok(())
     }
 
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub fn project_get_headers(
+    /// Another endpoint that holds the data of this object, fully synced endpoints first
+    #[tracing::instrument(level = "trace", skip(self, ep_id))]
+    pub fn owning_endpoint(&self, ep_id: &DieselUlid) -> Option<DieselUlid> {
+        let candidates = self
+            .endpoints
+            .iter()
+            .filter(|ep| &ep.id != ep_id && ep.status != Some(SyncStatus::Error));
+        candidates
+            .clone()
+            .find(|ep| ep.status == Some(SyncStatus::Finished))
+            .or_else(|| candidates.clone().next())
+            .map(|ep| ep.id)
+    }
+
+    /// Sync status of this endpoint as reported via `x-aruna-endpoint-status`
+    pub fn endpoint_status(&self, ep_id: &DieselUlid) -> &'static str {
+        match self.endpoints.iter().find(|ep| &ep.id == ep_id) {
+            None => "unassigned",
+            Some(ep) => match ep.status {
+                None => "unknown",
+                Some(SyncStatus::Waiting) => "waiting",
+                Some(SyncStatus::Running) => "running",
+                Some(SyncStatus::Finished) => "finished",
+                Some(SyncStatus::Error) => "error",
+            },
+        }
+    }
+
+    /// State of the data of this object on the given endpoint
+    #[tracing::instrument(level = "trace", skip(self, location, ep_id))]
+    pub fn staging_status(
         &self,
-        request_method: &http::Method,
-        header: &http::HeaderMap<HeaderValue>,
-    ) -> Option<HashMap<String, String>> {
-        if self.object_type != ObjectType::Project {
-            error!("Invalid object type");
-            return None;
+        location: Option<&ObjectLocation>,
+        ep_id: &DieselUlid,
+    ) -> StagingStatus {
+        if let Some(location) = location {
+            return if location.corrupt {
+                StagingStatus::Error
+            } else if self.object_status == Status::Initializing {
+                // Stored, but the server was not notified yet
+                StagingStatus::Staging
+            } else {
+                StagingStatus::Available
+            };
+        }
+        if self.object_status == Status::Initializing {
+            return StagingStatus::Staging;
+        }
+        match self.endpoints.iter().find(|ep| &ep.id == ep_id) {
+            Some(ep) => match ep.status {
+                None | Some(SyncStatus::Waiting) | Some(SyncStatus::Running) => {
+                    StagingStatus::ReplicationPending
+                }
+                // Finished without data is as broken as a failed replication
+                Some(SyncStatus::Finished) | Some(SyncStatus::Error) => StagingStatus::Error,
+            },
+            None if self.owning_endpoint(ep_id).is_some() => StagingStatus::ReplicationPending,
+            None => StagingStatus::Error,
         }
+    }
 
-        let request_origin = header.get(hyper::header::ORIGIN)?.to_str().ok()?;
+    /// Error for an object that is known but has no data on this endpoint:
+    /// 409 if the data is expected to arrive (upload finalization or replication),
+    /// a redirect if another endpoint holds the data, NoSuchKey otherwise
+    #[tracing::instrument(level = "trace", skip(self, ep_id))]
+    pub fn missing_location(&self, ep_id: &DieselUlid) -> S3Error {
+        let assigned = self.endpoints.iter().any(|ep| &ep.id == ep_id);
+        match self.staging_status(None, ep_id) {
+            status @ StagingStatus::Staging => return status.not_ready(&self.id),
+            status @ StagingStatus::ReplicationPending if assigned => {
+                return status.not_ready(&self.id)
+            }
+            _ => {}
+        }
+        match self.owning_endpoint(ep_id) {
+            Some(endpoint) => {
+                error!(object = ?self.id, ?endpoint, "Object stored on another endpoint");
+                s3_error!(
+                    PermanentRedirect,
+                    "Object {} is stored on endpoint {}",
+                    self.id,
+                    endpoint
+                )
+            }
+            None => {
+                error!(object = ?self.id, "Object has no data");
+                ProxyError::NotAvailable(format!("No data found for object {}", self.id)).into()
+            }
+        }
+    }
+}
 
-        let request_headers = header
-            .get(hyper::header::ACCESS_CONTROL_REQUEST_HEADERS)
-            .map(|x| {
-                x.to_str()
-                    .unwrap_or("")
-                    .split(',')
-                    .map(|e| e.trim().to_string())
-                    .collect::<Vec<String>>()
-            });
+/// State of the data of an object on this proxy, reported via `x-aruna-object-status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StagingStatus {
+    /// Uploaded or uploading, but not yet finished on the server
+    Staging,
+    Available,
+    /// The data is expected to be replicated to this endpoint
+    ReplicationPending,
+    /// Replication failed or the stored data is corrupt
+    Error,
+}
 
-        let key = self
-            .key_values
-            .iter()
-            .find_map(|e| {
-                if e.key == "app.aruna-storage.org/cors" {
-                    match serde_json::from_str::<CORSConfiguration>(&e.value) {
-                        Ok(config) => Some(config.into_headers(
-                            request_origin.to_string(),
-                            request_method.to_string(),
-                            request_headers.clone(),
-                        )),
-                        _ => {
-                            error!("Invalid cors config");
-                            None
-                        }
-                    }
-                } else {
-                    debug!("No cors config");
-                    None
-                }
-            })
-            .flatten();
+impl StagingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StagingStatus::Staging => "staging",
+            StagingStatus::Available => "available",
+            StagingStatus::ReplicationPending => "replication-pending",
+            StagingStatus::Error => "error",
+        }
+    }
 
-        key
+    /// 409 with a machine readable code for data that is not available yet
+    pub fn not_ready(&self, object_id: &DieselUlid) -> S3Error {
+        let code = match self {
+            StagingStatus::Staging => "ObjectStaging",
+            _ => "ReplicationPending",
+        };
+        error!(
+            ?object_id,
+            status = self.as_str(),
+            "Object data is not available yet"
+        );
+        let mut error = S3Error::with_message(
+            S3ErrorCode::Custom(code.into()),
+            format!(
+                "Data of object {object_id} is not available yet ({})",
+                self.as_str()
+            ),
+        );
+        error.set_status_code(StatusCode::CONFLICT);
+        error
     }
 }
 
@@ -1259,7 +1717,7 @@ impl ResourceState {
         }
     }
 
-    pub fn _as_name(&self) -> Option<String> {
+    pub fn as_name(&self) -> Option<String> {
         match self {
             ResourceState::Missing { name, .. } => Some(name.clone()),
             ResourceState::Found { object } => Some(object.name.clone()),
@@ -1275,6 +1733,40 @@ pub enum NewOrExistingObject {
     None,
 }
 
+/// Hierarchy levels of a key including the bucket: project, collection, dataset and object
+pub const MAX_HIERARCHY_DEPTH: usize = 4;
+
+/// Splits an object key into its path segments, empty segments (leading, trailing
+/// or duplicate slashes) can not be mapped to resources
+pub fn key_segments(key: &str) -> Result<Vec<&str>, S3Error> {
+    let segments = key.split('/').collect::<Vec<_>>();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        error!(key, "Key contains an empty path segment");
+        return Err(s3_error!(
+            InvalidArgument,
+            "key {key} contains an empty path segment, leading, trailing and duplicate '/' are not supported"
+        ));
+    }
+    Ok(segments)
+}
+
+/// Fails if the key does not end with the object name at segment `name_idx`,
+/// i.e. the key is deeper than the hierarchy it was resolved into
+pub fn check_key_depth(segments: usize, name_idx: usize) -> Result<(), S3Error> {
+    if segments > name_idx + 1 {
+        // The bucket is part of the hierarchy
+        let depth = segments + 1;
+        let max_depth = name_idx + 2;
+        error!(depth, max_depth, "Key exceeds maximum hierarchy depth");
+        return Err(s3_error!(
+            InvalidArgument,
+            "key depth {depth} exceeds maximum hierarchy depth {max_depth}, segments beyond {} must be part of the object name",
+            name_idx + 1
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct ResourceStates {
     objects: [ResourceState; 4],
@@ -1305,18 +1797,23 @@ impl ResourceStates {
             self.objects[0].is_missing()
         };
 
+        // Directory markers have no object, the directory itself may be missing
+        let object = match self.objects[3] {
+            ResourceState::None => self.objects[2].is_missing(),
+            _ => self.objects[3].is_missing(),
+        };
         match (
             project,
             self.objects[1].is_missing(),
             self.objects[2].is_missing(),
-            self.objects[3].is_missing(),
+            object,
         ) {
             (false, true, true, true)
             | (false, false, true, true)
             | (false, false, false, true)
             | (false, false, false, false) => {}
             _ => {
-                bail!("Invalid resource state")
+                bail!("existing resources can not be children of missing parents")
             }
         }
         Ok(())
@@ -1462,36 +1959,54 @@ impl ResourceStates {
         Ok(())
     }
 
+    /// Sets the missing resource in the slot of its variant
+    pub fn set_missing_variant(&mut self, name: String, variant: ResourceVariant) -> Result<()> {
+        let idx = match variant {
+            ResourceVariant::Project => 0,
+            ResourceVariant::Collection => 1,
+            ResourceVariant::Dataset => 2,
+            ResourceVariant::Object => 3,
+            _ => bail!("Invalid resource variant"),
+        };
+        self.objects[idx] = ResourceState::new_missing(name, variant);
+        Ok(())
+    }
+
+    fn not_found(&self, idx: usize, label: &str, variant: fn(String) -> ProxyError) -> S3Error {
+        let message = match self.objects[idx].as_name() {
+            Some(name) => format!("{label} {name} not found"),
+            None => format!("{label} not found"),
+        };
+        error!(message);
+        variant(message).into()
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn require_project(&self) -> Result<&Object, S3Error> {
-        self.objects[0].as_ref().ok_or_else(|| {
-            error!("Project not found");
-            s3_error!(NoSuchKey, "Project not found")
-        })
+        self.objects[0]
+            .as_ref()
+            .ok_or_else(|| self.not_found(0, "Project", ProxyError::BucketNotFound))
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn require_collection(&self) -> Result<&Object, S3Error> {
-        self.objects[1].as_ref().ok_or_else(|| {
-            error!("Collection not found");
-            s3_error!(NoSuchKey, "Collection not found")
-        })
+        self.objects[1]
+            .as_ref()
+            .ok_or_else(|| self.not_found(1, "Collection", ProxyError::NotFound))
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn require_dataset(&self) -> Result<&Object, S3Error> {
-        self.objects[2].as_ref().ok_or_else(|| {
-            error!("Dataset not found");
-            s3_error!(NoSuchKey, "Dataset not found")
-        })
+        self.objects[2]
+            .as_ref()
+            .ok_or_else(|| self.not_found(2, "Dataset", ProxyError::NotFound))
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn require_object(&self) -> Result<&Object, S3Error> {
-        self.objects[3].as_ref().ok_or_else(|| {
-            error!("Object not found");
-            s3_error!(NoSuchKey, "Object not found")
-        })
+        self.objects[3]
+            .as_ref()
+            .ok_or_else(|| self.not_found(3, "Object", ProxyError::NotFound))
     }
 
     #[tracing::instrument(level = "trace", skip(self, key_info, perm))]
@@ -1540,22 +2055,11 @@ impl ResourceStates {
         ]
     }
 
-    pub fn into_new_or_existing(
+    /// Collection and dataset of the key, missing ones are initialized
+    fn new_or_existing_parents(
         &self,
-    ) -> Result<
-        (
-            NewOrExistingObject,
-            NewOrExistingObject,
-            NewOrExistingObject,
-            NewOrExistingObject,
-            [Option<(DieselUlid, String)>; 4],
-        ),
-        S3Error,
-    > {
-        let project = self.require_project()?;
-
-        let project_tag = Some((project.id, project.name.clone()));
-
+        project: &Object,
+    ) -> Result<(NewOrExistingObject, NewOrExistingObject), S3Error> {
         let collection = match &self.objects[1] {
             ResourceState::None => NewOrExistingObject::None,
             ResourceState::Found { object } => NewOrExistingObject::Existing(object.clone()),
@@ -1570,14 +2074,6 @@ impl ResourceStates {
             }
         };
 
-        let collection_tag = match collection {
-            NewOrExistingObject::Existing(ref collection)
-            | NewOrExistingObject::Missing(ref collection) => {
-                Some((collection.id, collection.name.clone()))
-            }
-            _ => None,
-        };
-
         let dataset = match &self.objects[2] {
             ResourceState::None => NewOrExistingObject::None,
             ResourceState::Found { object } => NewOrExistingObject::Existing(object.clone()),
@@ -1597,6 +2093,50 @@ impl ResourceStates {
             }
         };
 
+        Ok((collection, dataset))
+    }
+
+    /// Collection and dataset of a directory marker, markers below a dataset have neither
+    pub fn into_directory_marker(
+        &self,
+    ) -> Result<(NewOrExistingObject, NewOrExistingObject), S3Error> {
+        let project = self.require_project()?;
+        if !matches!(self.objects[3], ResourceState::None) {
+            error!("Directory marker refers to an object");
+            return Err(s3_error!(
+                InvalidArgument,
+                "Directory markers can not refer to objects"
+            ));
+        }
+        self.new_or_existing_parents(project)
+    }
+
+    pub fn into_new_or_existing(
+        &self,
+    ) -> Result<
+        (
+            NewOrExistingObject,
+            NewOrExistingObject,
+            NewOrExistingObject,
+            NewOrExistingObject,
+            [Option<(DieselUlid, String)>; 4],
+        ),
+        S3Error,
+    > {
+        let project = self.require_project()?;
+
+        let project_tag = Some((project.id, project.name.clone()));
+
+        let (collection, dataset) = self.new_or_existing_parents(project)?;
+
+        let collection_tag = match collection {
+            NewOrExistingObject::Existing(ref collection)
+            | NewOrExistingObject::Missing(ref collection) => {
+                Some((collection.id, collection.name.clone()))
+            }
+            _ => None,
+        };
+
         let dataset_tag = match dataset {
             NewOrExistingObject::Existing(ref dataset)
             | NewOrExistingObject::Missing(ref dataset) => Some((dataset.id, dataset.name.clone())),
@@ -1604,7 +2144,19 @@ impl ResourceStates {
         };
 
         let object = match &self.objects[3] {
-            ResourceState::None => NewOrExistingObject::None,
+            // The key names an existing collection or dataset
+            ResourceState::None => {
+                let (label, name) = match (self.objects[2].as_ref(), self.objects[1].as_ref()) {
+                    (Some(dataset), _) => ("dataset", dataset.name.clone()),
+                    (None, Some(collection)) => ("collection", collection.name.clone()),
+                    (None, None) => ("project", project.name.clone()),
+                };
+                error!(label, name, "Key refers to an existing resource");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "key refers to existing {label} {name}, objects can not replace a {label}"
+                ));
+            }
             ResourceState::Found { object } => NewOrExistingObject::Existing(object.clone()),
             ResourceState::Missing { .. } => {
                 let relation = match dataset {
@@ -1644,12 +2196,26 @@ impl ResourceStates {
     }
 }
 
+/// Resources of an authorized request, one variant per S3 path kind
 #[derive(Debug, Clone)]
 pub enum ObjectsState {
-    Regular {
+    /// Requests without a bucket (ListBuckets)
+    Root,
+    /// Bucket requests, the project is missing for CreateBucket
+    Bucket {
+        states: ResourceStates,
+    },
+    /// Bucket relative keys, the location is None for new objects and objects without data
+    Object {
+        states: ResourceStates,
+        location: Option<ObjectLocation>,
+    },
+    /// Objects addressed by their id (`objects/<id>`)
+    SpecialObject {
         states: ResourceStates,
         location: Option<ObjectLocation>,
     },
+    /// Files inside of object packages
     Objects {
         root: Object,
         filename: String,
@@ -1660,18 +2226,15 @@ pub enum ObjectsState {
     },
 }
 
-impl Default for ObjectsState {
-    fn default() -> Self {
-        Self::Regular {
-            states: ResourceStates::default(),
-            location: None,
-        }
-    }
-}
-
 impl ObjectsState {
-    pub fn new_regular(states: ResourceStates, location: Option<ObjectLocation>) -> Self {
-        Self::Regular { states, location }
+    pub fn new_bucket(states: ResourceStates) -> Self {
+        Self::Bucket { states }
+    }
+    pub fn new_object(states: ResourceStates, location: Option<ObjectLocation>) -> Self {
+        Self::Object { states, location }
+    }
+    pub fn new_special_object(states: ResourceStates, location: Option<ObjectLocation>) -> Self {
+        Self::SpecialObject { states, location }
     }
     pub fn new_objects(root: Object, filename: String) -> Self {
         Self::Objects { root, filename }
@@ -1679,9 +2242,21 @@ impl ObjectsState {
     pub fn new_bundle(bundle: Bundle, filename: String) -> Self {
         Self::Bundle { bundle, filename }
     }
+
+    /// Resolved hierarchy of bucket and object requests
+    pub fn resource_states(&self) -> Option<&ResourceStates> {
+        match self {
+            ObjectsState::Bucket { states }
+            | ObjectsState::Object { states, .. }
+            | ObjectsState::SpecialObject { states, .. } => Some(states),
+            ObjectsState::Root | ObjectsState::Objects { .. } | ObjectsState::Bundle { .. } => None,
+        }
+    }
+
     pub fn extract_object(&self) -> Result<(Object, Option<ObjectLocation>), S3Error> {
         match self {
-            ObjectsState::Regular { states, location } => {
+            ObjectsState::Object { states, location }
+            | ObjectsState::SpecialObject { states, location } => {
                 Ok((states.require_object()?.clone(), location.clone()))
             }
             _ => Err(s3_error!(InvalidRequest, "Object not found")),
@@ -1689,18 +2264,46 @@ impl ObjectsState {
     }
 
     pub fn try_slice(&self) -> Result<[Option<(DieselUlid, String)>; 4], S3Error> {
-        match self {
-            ObjectsState::Regular { states, .. } => Ok(states.as_slice()),
-            _ => Err(s3_error!(InvalidRequest, "Object not found")),
+        match self.resource_states() {
+            Some(states) => Ok(states.as_slice()),
+            None => Err(s3_error!(InvalidRequest, "Object not found")),
         }
     }
 
+    /// States and location of object requests
     pub fn require_regular(self) -> Result<(ResourceStates, Option<ObjectLocation>), S3Error> {
         match self {
-            ObjectsState::Regular { states, location } => Ok((states, location)),
-            _ => Err(s3_error!(InvalidRequest, "Object not found")),
+            ObjectsState::Object { states, location }
+            | ObjectsState::SpecialObject { states, location } => Ok((states, location)),
+            _ => {
+                error!("Request is not an object request");
+                Err(s3_error!(InvalidRequest, "Object not found"))
+            }
+        }
+    }
+
+    /// States of bucket requests
+    pub fn require_bucket(self) -> Result<ResourceStates, S3Error> {
+        match self {
+            ObjectsState::Bucket { states } => Ok(states),
+            _ => {
+                error!("Request is not a bucket request");
+                Err(s3_error!(InvalidRequest, "Bucket not found"))
+            }
         }
     }
+
+    /// Object and location of reads, objects without a location on this proxy
+    /// fail with NotAvailable (see `Object::missing_location`)
+    pub fn require_data(
+        self,
+        ep_id: &DieselUlid,
+    ) -> Result<(ResourceStates, Object, ObjectLocation), S3Error> {
+        let (states, location) = self.require_regular()?;
+        let object = states.require_object()?.clone();
+        let location = location.ok_or_else(|| object.missing_location(ep_id))?;
+        Ok((states, object, location))
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -1776,7 +2379,10 @@ impl Into<UserState> for Option<AccessKeyPermissions> {
     }
 }
 
-#[derive(Default, Clone)]
+/// Authorized request as inserted into the request extensions by the auth provider
+///
+/// There is no default, the path kind is always explicit in the `objects_state`.
+#[derive(Clone)]
 pub struct CheckAccessResult {
     pub objects_state: ObjectsState,
     pub user_state: UserState,
@@ -1784,6 +2390,11 @@ pub struct CheckAccessResult {
 }
 
 impl CheckAccessResult {
+    /// Requests without a bucket carry no resources and cors headers
+    pub fn root(user_state: UserState) -> Self {
+        Self::new(ObjectsState::Root, user_state, None)
+    }
+
     #[tracing::instrument(level = "trace", skip(objects_state, user_state, headers))]
     pub fn new(
         objects_state: ObjectsState,
@@ -1862,55 +2473,237 @@ impl From<CORSConfiguration> for GetBucketCorsOutput {
 }
 
 impl CORSConfiguration {
-    #[tracing::instrument]
-    pub fn into_headers(
-        self,
-        origin: String,
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn get_headers(
+        &self,
+        request_method: &http::Method,
+        header: &http::HeaderMap<HeaderValue>,
+    ) -> Option<HashMap<String, String>> {
+        let request_origin = header.get(hyper::header::ORIGIN)?.to_str().ok()?;
+
+        let request_headers = header
+            .get(hyper::header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .map(|x| {
+                x.to_str()
+                    .unwrap_or("")
+                    .split(',')
+                    .map(|e| e.trim().to_string())
+                    .collect::<Vec<String>>()
+            });
+
+        self.into_headers(
+            request_origin.to_string(),
+            request_method.to_string(),
+            request_headers,
+        )
+    }
+
+    /// Headers of the first rule that allows the origin, method and requested headers
+    ///
+    /// Origins and allowed headers may contain a single `*` wildcard, header names are
+    /// compared case-insensitive.
+    #[tracing::instrument]
+    pub fn into_headers(
+        &self,
+        origin: String,
         method: String,
         header: Option<Vec<String>>,
     ) -> Option<HashMap<String, String>> {
-        for cors_rule in self.0 {
-            if cors_rule.allowed_origins.contains(&origin)
-                && cors_rule.allowed_methods.contains(&method)
-            {
-                let mut headers = HashMap::new();
-                if !cors_rule.allowed_origins.is_empty() {
-                    headers.insert(
-                        "Access-Control-Allow-Origin".to_string(),
-                        cors_rule.allowed_origins.join(", "),
-                    );
-                }
-                if !cors_rule.allowed_methods.is_empty() {
-                    headers.insert(
-                        "Access-Control-Allow-Methods".to_string(),
-                        cors_rule.allowed_methods.join(", "),
-                    );
+        for cors_rule in self.0.iter() {
+            let origin_allowed = cors_rule
+                .allowed_origins
+                .iter()
+                .any(|allowed| wildcard_match(allowed, &origin));
+            let method_allowed = cors_rule
+                .allowed_methods
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&method));
+            let headers_allowed = header.iter().flatten().all(|requested| {
+                cors_rule.allowed_headers.iter().flatten().any(|allowed| {
+                    wildcard_match(
+                        &allowed.to_ascii_lowercase(),
+                        &requested.to_ascii_lowercase(),
+                    )
+                })
+            });
+            if !(origin_allowed && method_allowed && headers_allowed) {
+                continue;
+            }
+
+            let mut headers = HashMap::new();
+            // Only a single origin is valid, wildcard rules allow any origin
+            let allow_origin = if cors_rule.allowed_origins.iter().any(|o| o == "*") {
+                "*".to_string()
+            } else {
+                origin.clone()
+            };
+            headers.insert("Access-Control-Allow-Origin".to_string(), allow_origin);
+            headers.insert(
+                "Access-Control-Allow-Methods".to_string(),
+                cors_rule.allowed_methods.join(", "),
+            );
+            if let Some(requested) = header.as_ref().filter(|h| !h.is_empty()) {
+                headers.insert(
+                    "Access-Control-Allow-Headers".to_string(),
+                    requested.join(", "),
+                );
+            }
+            if let Some(head) = &cors_rule.expose_headers {
+                headers.insert("Access-Control-Expose-Headers".to_string(), head.join(", "));
+            }
+            if cors_rule.max_age_seconds > 0 {
+                headers.insert(
+                    "Access-Control-Max-Age".to_string(),
+                    cors_rule.max_age_seconds.to_string(),
+                );
+            }
+            return Some(headers);
+        }
+        None
+    }
+}
+
+/// Expiration rules of a bucket, stored as project label like the cors configuration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LifecycleConfiguration(pub Vec<ExpirationRule>);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExpirationRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub prefix: String,
+    pub enabled: bool,
+    pub expiration: Expiration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Expiration {
+    Days(i32),
+    Date(DateTime<Utc>),
+}
+
+impl ExpirationRule {
+    /// True if an object with this key and last modification is expired at `now`
+    pub fn is_expired(&self, key: &str, last_modified: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        if !self.enabled || !key.starts_with(&self.prefix) {
+            return false;
+        }
+        match self.expiration {
+            Expiration::Days(days) => last_modified + chrono::Duration::days(days as i64) <= now,
+            Expiration::Date(date) => date <= now,
+        }
+    }
+}
+
+impl LifecycleConfiguration {
+    pub fn is_expired(&self, key: &str, last_modified: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.0
+            .iter()
+            .any(|rule| rule.is_expired(key, last_modified, now))
+    }
+}
+
+impl TryFrom<BucketLifecycleConfiguration> for LifecycleConfiguration {
+    type Error = S3Error;
+
+    fn try_from(value: BucketLifecycleConfiguration) -> Result<Self, Self::Error> {
+        let mut rules = Vec::with_capacity(value.rules.len());
+        for rule in value.rules {
+            let prefix = match rule.filter {
+                Some(LifecycleRuleFilter::Prefix(prefix)) => prefix,
+                Some(_) => {
+                    error!("Unsupported lifecycle filter");
+                    return Err(s3_error!(
+                        NotImplemented,
+                        "Only prefix filters are supported"
+                    ));
                 }
-                if let Some(head) = cors_rule.allowed_headers {
-                    headers.insert("Access-Control-Allow-Headers".to_string(), head.join(", "));
+                None => rule.prefix.unwrap_or_default(),
+            };
+            let expiration = match rule.expiration {
+                Some(LifecycleExpiration {
+                    days: Some(days), ..
+                }) if days > 0 => Expiration::Days(days),
+                Some(LifecycleExpiration {
+                    date: Some(date), ..
+                }) => {
+                    let date = time::OffsetDateTime::from(date);
+                    Expiration::Date(
+                        DateTime::from_timestamp(date.unix_timestamp(), 0).ok_or_else(|| {
+                            error!("Invalid expiration date");
+                            s3_error!(InvalidArgument, "Invalid expiration date")
+                        })?,
+                    )
                 }
-                if let Some(head) = cors_rule.expose_headers {
-                    headers.insert("Access-Control-Expose-Headers".to_string(), head.join(", "));
+                _ => {
+                    error!("Lifecycle rule without expiration");
+                    return Err(s3_error!(
+                        NotImplemented,
+                        "Only rules with an expiration in days or at a date are supported"
+                    ));
                 }
+            };
+            rules.push(ExpirationRule {
+                id: rule.id,
+                prefix,
+                enabled: rule.status.as_str() == ExpirationStatus::ENABLED,
+                expiration,
+            });
+        }
+        Ok(LifecycleConfiguration(rules))
+    }
+}
 
-                if cors_rule.max_age_seconds == 0 {
-                    headers.insert(
-                        "Access-Control-Max-Age".to_string(),
-                        cors_rule.max_age_seconds.to_string(),
-                    );
-                }
+impl From<LifecycleConfiguration> for GetBucketLifecycleConfigurationOutput {
+    fn from(val: LifecycleConfiguration) -> Self {
+        let rules = val
+            .0
+            .into_iter()
+            .map(|rule| LifecycleRule {
+                abort_incomplete_multipart_upload: None,
+                id: rule.id,
+                filter: Some(LifecycleRuleFilter::Prefix(rule.prefix)),
+                noncurrent_version_expiration: None,
+                noncurrent_version_transitions: None,
+                prefix: None,
+                transitions: None,
+                status: ExpirationStatus::from_static(if rule.enabled {
+                    ExpirationStatus::ENABLED
+                } else {
+                    ExpirationStatus::DISABLED
+                }),
+                expiration: Some(match rule.expiration {
+                    Expiration::Days(days) => LifecycleExpiration {
+                        days: Some(days),
+                        ..Default::default()
+                    },
+                    Expiration::Date(date) => LifecycleExpiration {
+                        date: time::OffsetDateTime::from_unix_timestamp(date.timestamp())
+                            .ok()
+                            .map(|d| d.into()),
+                        ..Default::default()
+                    },
+                }),
+            })
+            .collect::<Vec<_>>();
+        GetBucketLifecycleConfigurationOutput {
+            rules: Some(rules),
+            ..Default::default()
+        }
+    }
+}
 
-                if let Some(expected_headers) = header {
-                    for header in expected_headers {
-                        if !headers.contains_key(&header) {
-                            return None;
-                        }
-                    }
-                }
-                return Some(headers);
-            }
+/// Matches a value against a pattern with at most one `*` wildcard
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            value.len() >= prefix.len() + suffix.len()
+                && value.starts_with(prefix)
+                && value.ends_with(suffix)
         }
-        None
+        None => pattern == value,
     }
 }
 
@@ -1947,6 +2740,587 @@ impl From<&Object> for TypedId {
     }
 }
 
+/// In-progress multipart upload
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultipartUploadInfo {
+    pub upload_id: String,
+    pub bucket: String,
+    pub key: String,
+    pub initiated: DateTime<Utc>,
+    pub initiator: Option<DieselUlid>,
+}
+
+/// Aggregated object count and raw size below a hierarchy node
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefixStats {
+    pub object_count: i64,
+    pub total_size: i64,
+}
+
+/// Storage consumed by a set of objects
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageStats {
+    pub object_count: i64,
+    /// Sum of the uploaded sizes
+    pub raw_size: i64,
+    /// Sum of the stored sizes after compression / encryption
+    pub disk_size: i64,
+}
+
+impl UsageStats {
+    fn apply(&mut self, usage: &ObjectUsage, sign: i64) {
+        self.object_count += sign;
+        self.raw_size += sign * usage.raw_size;
+        self.disk_size += sign * usage.disk_size;
+    }
+}
+
+/// Contribution of a single object to the usage of its buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectUsage {
+    pub data_class: DataClass,
+    pub raw_size: i64,
+    pub disk_size: i64,
+}
+
+/// Storage consumption of a bucket, in total and by data class
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BucketUsage {
+    #[serde(flatten)]
+    pub total: UsageStats,
+    pub data_classes: BTreeMap<String, UsageStats>,
+}
+
+impl BucketUsage {
+    pub fn add(&mut self, usage: &ObjectUsage) {
+        self.apply(usage, 1)
+    }
+
+    pub fn remove(&mut self, usage: &ObjectUsage) {
+        self.apply(usage, -1)
+    }
+
+    fn apply(&mut self, usage: &ObjectUsage, sign: i64) {
+        self.total.apply(usage, sign);
+        let key = usage.data_class.as_str_name().to_string();
+        let class = self.data_classes.entry(key.clone()).or_default();
+        class.apply(usage, sign);
+        if class.object_count == 0 {
+            self.data_classes.remove(&key);
+        }
+    }
+}
+
+/// Download bandwidth of a project, set via the `app.aruna-storage.org/bandwidth` attribute
+///
+/// Unset fields keep the limits of the proxy config, 0 disables a limit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct BandwidthPolicy {
+    #[serde(default)]
+    pub per_key_bytes_per_second: Option<u64>,
+    #[serde(default)]
+    pub per_project_bytes_per_second: Option<u64>,
+}
+
+impl BandwidthPolicy {
+    pub fn apply(&self, defaults: BandwidthLimits) -> BandwidthLimits {
+        BandwidthLimits {
+            per_key_bytes_per_second: self
+                .per_key_bytes_per_second
+                .unwrap_or(defaults.per_key_bytes_per_second),
+            per_project_bytes_per_second: self
+                .per_project_bytes_per_second
+                .unwrap_or(defaults.per_project_bytes_per_second),
+            burst_bytes: defaults.burst_bytes,
+        }
+    }
+}
+
+/// Upload sizes of a project, set via the `app.aruna-storage.org/upload-limits` attribute
+///
+/// Unset fields keep the limits of the proxy config, 0 disables a limit. The buffered
+/// bytes per upload bound the memory of the proxy and can not be overridden.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct UploadLimitsPolicy {
+    #[serde(default)]
+    pub max_object_size: Option<u64>,
+    #[serde(default)]
+    pub max_part_size: Option<u64>,
+    #[serde(default)]
+    pub max_multipart_size: Option<u64>,
+}
+
+impl UploadLimitsPolicy {
+    pub fn apply(&self, defaults: UploadLimits) -> UploadLimits {
+        UploadLimits {
+            max_object_size: self.max_object_size.unwrap_or(defaults.max_object_size),
+            max_part_size: self.max_part_size.unwrap_or(defaults.max_part_size),
+            max_multipart_size: self
+                .max_multipart_size
+                .unwrap_or(defaults.max_multipart_size),
+            max_inflight_bytes: defaults.max_inflight_bytes,
+        }
+    }
+}
+
+/// Access a bucket policy statement applies to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Read,
+    Write,
+}
+
+/// Denies access to keys of a project, evaluated after permissions and rules
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PolicyStatement {
+    /// User ids, `anonymous` for requests without access key or `*` for everyone
+    pub principals: Vec<String>,
+    pub actions: Vec<PolicyAction>,
+    /// Key prefix relative to the bucket, empty for the whole bucket
+    #[serde(default)]
+    pub prefix: String,
+}
+
+impl PolicyStatement {
+    fn matches(&self, user_id: Option<&str>, action: PolicyAction, key: &str) -> bool {
+        self.actions.contains(&action)
+            && key.starts_with(&self.prefix)
+            && self.principals.iter().any(|p| match user_id {
+                _ if p == "*" => true,
+                Some(user_id) => p == user_id,
+                None => p == "anonymous",
+            })
+    }
+}
+
+/// Bucket policy of a project, set via the `app.aruna-storage.org/bucket-policy` attribute
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct BucketPolicy {
+    #[serde(default)]
+    pub deny: Vec<PolicyStatement>,
+}
+
+impl BucketPolicy {
+    /// 403 AccessDenied if a statement denies the access to the key
+    pub fn check(
+        &self,
+        user_id: Option<&DieselUlid>,
+        action: PolicyAction,
+        key: &str,
+    ) -> Result<(), S3Error> {
+        let user_id = user_id.map(|id| id.to_string());
+        if let Some(statement) = self
+            .deny
+            .iter()
+            .find(|s| s.matches(user_id.as_deref(), action, key))
+        {
+            error!(
+                ?user_id,
+                ?action,
+                key,
+                prefix = statement.prefix,
+                "Denied by bucket policy"
+            );
+            return Err(s3_error!(AccessDenied, "Denied by bucket policy"));
+        }
+        Ok(())
+    }
+}
+
+/// Ingest validation of a project, set via the `app.aruna-storage.org/validation` attribute
+///
+/// Empty lists allow everything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ValidationSpec {
+    #[serde(default)]
+    pub content_types: Vec<String>,
+    /// File extensions without the leading dot
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// User metadata (`x-amz-meta-*`) every upload has to provide
+    #[serde(default)]
+    pub required_metadata: Vec<String>,
+    #[serde(default)]
+    pub max_key_length: Option<usize>,
+}
+
+impl ValidationSpec {
+    /// 400 InvalidArgument if the upload does not match the spec
+    pub fn validate(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+        metadata: Option<&HashMap<String, String>>,
+    ) -> Result<(), S3Error> {
+        if let Some(max) = self.max_key_length {
+            if key.len() > max {
+                error!(key, max, "Key exceeds validation spec");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Key is longer than {max} characters"
+                ));
+            }
+        }
+        if !self.extensions.is_empty() {
+            let extension = key.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+            if !self
+                .extensions
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(extension))
+            {
+                error!(key, "Extension not allowed by validation spec");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "File extension is not allowed, expected one of: {}",
+                    self.extensions.join(", ")
+                ));
+            }
+        }
+        if !self.content_types.is_empty() {
+            // Parameters like charset are not part of the validation
+            let content_type = content_type
+                .and_then(|ct| ct.split(';').next())
+                .map(str::trim)
+                .unwrap_or_default();
+            if !self
+                .content_types
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(content_type))
+            {
+                error!(
+                    key,
+                    content_type, "Content type not allowed by validation spec"
+                );
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Content type {content_type} is not allowed"
+                ));
+            }
+        }
+        if let Some(missing) = self
+            .required_metadata
+            .iter()
+            .find(|name| !metadata.is_some_and(|m| m.contains_key(name.as_str())))
+        {
+            error!(key, missing, "Missing metadata required by validation spec");
+            return Err(s3_error!(
+                InvalidArgument,
+                "Missing required metadata x-amz-meta-{missing}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Handling of uploads to existing keys
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionMode {
+    /// A new revision of the object is created
+    #[default]
+    Overwrite,
+    /// Existing objects can not be overwritten
+    Reject,
+}
+
+/// Collision handling of a project, set via the `app.aruna-storage.org/collision` attribute
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CollisionPolicy {
+    #[serde(default)]
+    pub mode: CollisionMode,
+}
+
+impl CollisionPolicy {
+    /// 412 PreconditionFailed for uploads to existing keys in reject mode
+    pub fn check(&self, key: &str, exists: bool) -> Result<(), S3Error> {
+        if exists && self.mode == CollisionMode::Reject {
+            error!(key, "Upload rejected by collision policy");
+            return Err(s3_error!(
+                PreconditionFailed,
+                "Object already exists and the project does not allow overwrites"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Replication targets of a project, set via the `app.aruna-storage.org/replication` attribute
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReplicationPolicy {
+    /// Endpoints that may pull replicas, empty allows every endpoint
+    #[serde(default)]
+    pub allowed_endpoints: Vec<DieselUlid>,
+}
+
+impl ReplicationPolicy {
+    pub fn allows(&self, endpoint_id: &DieselUlid) -> bool {
+        self.allowed_endpoints.is_empty() || self.allowed_endpoints.contains(endpoint_id)
+    }
+}
+
+/// Storage quota of a project, set via the `app.aruna-storage.org/quota` attribute
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QuotaPolicy {
+    /// Maximum sum of the uploaded (raw) sizes in bytes
+    pub max_bytes: i64,
+}
+
+impl QuotaPolicy {
+    /// 403 QuotaExceeded if `additional` bytes do not fit next to the current usage
+    pub fn check(
+        &self,
+        project_id: &DieselUlid,
+        usage: &BucketUsage,
+        additional: i64,
+    ) -> Result<(), S3Error> {
+        let requested = usage.total.raw_size.saturating_add(additional.max(0));
+        if requested <= self.max_bytes {
+            return Ok(());
+        }
+        error!(
+            ?project_id,
+            used = usage.total.raw_size,
+            additional,
+            quota = self.max_bytes,
+            "Upload exceeds project quota"
+        );
+        let mut error = S3Error::with_message(
+            S3ErrorCode::Custom("QuotaExceeded".into()),
+            format!(
+                "Upload of {additional} bytes exceeds the quota of project {project_id} ({} of {} bytes used)",
+                usage.total.raw_size, self.max_bytes
+            ),
+        );
+        error.set_status_code(StatusCode::FORBIDDEN);
+        Err(error)
+    }
+}
+
+/// Object lock mode, only COMPLIANCE is supported
+///
+/// Locked objects can not be deleted or overwritten by anyone (including admins)
+/// until the retention expires.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionMode {
+    #[serde(rename = "COMPLIANCE")]
+    Compliance,
+}
+
+impl RetentionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetentionMode::Compliance => ObjectLockRetentionMode::COMPLIANCE,
+        }
+    }
+}
+
+impl FromStr for RetentionMode {
+    type Err = S3Error;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            ObjectLockRetentionMode::COMPLIANCE => Ok(RetentionMode::Compliance),
+            other => {
+                error!(mode = other, "Unsupported object lock mode");
+                Err(s3_error!(
+                    InvalidArgument,
+                    "Unsupported object lock mode {other}, only COMPLIANCE is supported"
+                ))
+            }
+        }
+    }
+}
+
+/// Default retention of new objects in a project, set via the `app.aruna-storage.org/object-lock` attribute
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObjectLockPolicy {
+    pub mode: RetentionMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub years: Option<i32>,
+}
+
+impl ObjectLockPolicy {
+    /// Retention of an object uploaded at `now` without explicit lock headers
+    pub fn default_retention(&self, now: DateTime<Utc>) -> Option<ObjectRetention> {
+        let retain_until = match (self.days, self.years) {
+            (Some(days), _) if days > 0 => now + chrono::Duration::days(days as i64),
+            (_, Some(years)) if years > 0 => {
+                now.checked_add_months(chrono::Months::new(years as u32 * 12))?
+            }
+            _ => return None,
+        };
+        Some(ObjectRetention {
+            mode: self.mode,
+            retain_until,
+        })
+    }
+}
+
+impl TryFrom<ObjectLockConfiguration> for ObjectLockPolicy {
+    type Error = S3Error;
+
+    fn try_from(value: ObjectLockConfiguration) -> Result<Self, Self::Error> {
+        let Some(retention) = value.rule.and_then(|rule| rule.default_retention) else {
+            error!("Object lock configuration without default retention");
+            return Err(s3_error!(
+                MalformedXML,
+                "Missing default retention of the object lock rule"
+            ));
+        };
+        let mode = RetentionMode::from_str(
+            retention
+                .mode
+                .as_ref()
+                .map(|mode| mode.as_str())
+                .unwrap_or_default(),
+        )?;
+        match (retention.days, retention.years) {
+            (Some(days), None) if days > 0 => {}
+            (None, Some(years)) if years > 0 => {}
+            _ => {
+                error!(?retention, "Invalid default retention period");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "Default retention requires either positive Days or Years"
+                ));
+            }
+        }
+        Ok(ObjectLockPolicy {
+            mode,
+            days: retention.days,
+            years: retention.years,
+        })
+    }
+}
+
+impl From<ObjectLockPolicy> for ObjectLockConfiguration {
+    fn from(val: ObjectLockPolicy) -> Self {
+        ObjectLockConfiguration {
+            object_lock_enabled: Some(ObjectLockEnabled::from_static(ObjectLockEnabled::ENABLED)),
+            rule: Some(ObjectLockRule {
+                default_retention: Some(DefaultRetention {
+                    days: val.days,
+                    mode: Some(ObjectLockRetentionMode::from_static(val.mode.as_str())),
+                    years: val.years,
+                }),
+            }),
+        }
+    }
+}
+
+/// Retention of a single object, stored as object label `app.aruna-storage.org/retention`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObjectRetention {
+    pub mode: RetentionMode,
+    pub retain_until: DateTime<Utc>,
+}
+
+impl ObjectRetention {
+    /// Parses the `x-amz-object-lock-*` headers of an upload, mode and date must be set together
+    pub fn from_headers(
+        mode: Option<&str>,
+        retain_until: Option<s3s::dto::Timestamp>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Self>, S3Error> {
+        let (mode, retain_until) = match (mode, retain_until) {
+            (None, None) => return Ok(None),
+            (Some(mode), Some(retain_until)) => (mode, retain_until),
+            _ => {
+                error!("Incomplete object lock headers");
+                return Err(s3_error!(
+                    InvalidArgument,
+                    "x-amz-object-lock-mode and x-amz-object-lock-retain-until-date must be set together"
+                ));
+            }
+        };
+        let mode = RetentionMode::from_str(mode)?;
+        let retain_until =
+            DateTime::from_timestamp(time::OffsetDateTime::from(retain_until).unix_timestamp(), 0)
+                .ok_or_else(|| {
+                    error!("Invalid retain until date");
+                    s3_error!(InvalidArgument, "Invalid retain until date")
+                })?;
+        if retain_until <= now {
+            error!(%retain_until, "Retain until date in the past");
+            return Err(s3_error!(
+                InvalidArgument,
+                "The retain until date must be in the future"
+            ));
+        }
+        Ok(Some(ObjectRetention { mode, retain_until }))
+    }
+
+    /// Retention stored in the labels of the object, invalid values are ignored
+    pub fn from_object(object: &Object) -> Option<Self> {
+        let kv = object
+            .key_values
+            .iter()
+            .find(|kv| kv.key == RETENTION_KEY)?;
+        serde_json::from_str(&kv.value)
+            .map_err(|e| {
+                error!(error = ?e, object_id = ?object.id, msg = "Invalid object retention");
+                e
+            })
+            .ok()
+    }
+
+    pub fn to_json(&self) -> Result<String, S3Error> {
+        serde_json::to_string(self).map_err(|e| {
+            error!(error = ?e, msg = "Unable to serialize object retention");
+            s3_error!(InternalError, "Unable to serialize object retention")
+        })
+    }
+
+    /// Object label of the retention, see [`Self::from_object`]
+    pub fn to_label(&self) -> Result<KeyValue, S3Error> {
+        Ok(KeyValue {
+            key: RETENTION_KEY.to_string(),
+            value: self.to_json()?,
+            variant: KeyValueVariant::Label as i32,
+        })
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        now < self.retain_until
+    }
+
+    /// 403 AccessDenied while the retention is active, used for deletions and overwrites
+    pub fn check_mutation(
+        &self,
+        object_id: &DieselUlid,
+        now: DateTime<Utc>,
+    ) -> Result<(), S3Error> {
+        if !self.is_active(now) {
+            return Ok(());
+        }
+        error!(?object_id, retain_until = %self.retain_until, "Object is locked");
+        Err(s3_error!(
+            AccessDenied,
+            "Object {object_id} is locked in {} mode until {}",
+            self.mode.as_str(),
+            self.retain_until.to_rfc3339()
+        ))
+    }
+}
+
+impl From<ObjectRetention> for GetObjectRetentionOutput {
+    fn from(val: ObjectRetention) -> Self {
+        GetObjectRetentionOutput {
+            retention: Some(ObjectLockRetention {
+                mode: Some(ObjectLockRetentionMode::from_static(val.mode.as_str())),
+                retain_until_date: time::OffsetDateTime::from_unix_timestamp(
+                    val.retain_until.timestamp(),
+                )
+                .ok()
+                .map(|d| d.into()),
+            }),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct UploadPart {
     pub id: DieselUlid,
@@ -1955,10 +3329,636 @@ pub struct UploadPart {
     pub part_number: u64,
     pub raw_size: u64,
     pub size: u64,
+    #[serde(default)]
+    pub etag: String, // Backend etag
+    #[serde(default)]
+    pub md5: String, // Md5 of the raw part content, returned as part etag
+    #[serde(default)]
+    pub checksum: Option<ContentChecksum>,
+}
+
+/// Delay after the given number of failed deliveries (5s doubling, max 1h)
+fn retry_backoff(attempts: u32) -> chrono::Duration {
+    chrono::Duration::seconds(
+        5i64.saturating_mul(1i64 << attempts.saturating_sub(1).min(20))
+            .min(3600),
+    )
+}
+
+/// Object event of a webhook notification
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebhookEvent {
+    /// S3 event name without the `s3:` prefix, e.g. `ObjectCreated:Put`
+    pub event_name: String,
+    pub event_time: DateTime<Utc>,
+    pub request_id: Option<String>,
+    pub bucket: String,
+    pub key: String,
+    pub size: i64,
+    pub etag: String,
+    pub version_id: String,
+}
+
+/// Webhook event that is queued for (re)delivery
+///
+/// Url and secret are resolved on every attempt, deliveries of projects without
+/// a webhook are dropped.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PendingWebhook {
+    pub id: DieselUlid,
+    pub project_id: DieselUlid,
+    pub event: WebhookEvent,
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// Attempts are exhausted, kept until it is requeued by an admin
+    #[serde(default)]
+    pub dead_letter: bool,
+}
+
+impl PendingWebhook {
+    pub fn new(project_id: DieselUlid, event: WebhookEvent) -> Self {
+        PendingWebhook {
+            id: DieselUlid::generate(),
+            project_id,
+            event,
+            attempts: 0,
+            next_attempt: Utc::now(),
+            last_error: None,
+            dead_letter: false,
+        }
+    }
+
+    /// Same backoff as for server notifications, moved to the dead letters
+    /// after `max_attempts`
+    pub fn schedule_retry(&mut self, error: String, max_attempts: u32) {
+        self.attempts += 1;
+        self.next_attempt = Utc::now() + retry_backoff(self.attempts);
+        self.last_error = Some(error);
+        self.dead_letter = self.attempts >= max_attempts;
+    }
+}
+
+/// FinishObjectStaging call that could not be delivered to the Aruna server yet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingNotification {
+    pub id: DieselUlid, // Object id, local id if the object still has to be created
+    pub content_len: i64,
+    pub hashes: HashMap<String, String>,
+    // Used to sign a fresh impersonating token for each attempt
+    pub user_id: DieselUlid,
+    pub access_key: Option<String>,
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// Object whose CreateObject failed, created before the staging is finished
+    #[serde(default)]
+    pub create: Option<Object>,
+}
+
+impl PendingNotification {
+    /// Returns None for anonymous users, their notifications can not be replayed
+    pub fn new(
+        id: DieselUlid,
+        content_len: i64,
+        hashes: HashMap<String, String>,
+        user_state: &UserState,
+    ) -> Option<Self> {
+        let access_key = match user_state {
+            UserState::Token { access_key, .. } => Some(access_key.clone()),
+            _ => None,
+        };
+        Some(PendingNotification {
+            id,
+            content_len,
+            hashes,
+            user_id: user_state.get_user_id()?,
+            access_key,
+            attempts: 0,
+            next_attempt: Utc::now(),
+            last_error: None,
+            create: None,
+        })
+    }
+
+    /// Finish notification of the created object that replaces the local one
+    pub fn created(self, object_id: DieselUlid) -> Self {
+        PendingNotification {
+            id: object_id,
+            attempts: 0,
+            next_attempt: Utc::now(),
+            last_error: None,
+            create: None,
+            ..self
+        }
+    }
+
+    /// Schedules the next attempt with exponential backoff
+    pub fn schedule_retry(&mut self, error: String) {
+        self.attempts += 1;
+        self.next_attempt = Utc::now() + retry_backoff(self.attempts);
+        self.last_error = Some(error);
+    }
+
+    pub fn get_hashes(&self) -> Vec<Hash> {
+        self.hashes
+            .iter()
+            .map(|(k, v)| Hash {
+                alg: match k.as_str() {
+                    "MD5" => 2,
+                    "SHA256" => 1,
+                    _ => 0,
+                },
+                hash: v.to_string(),
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn test_resource_strings_cmp() {}
+
+    /// Project and object of a regular key
+    fn object_states() -> ResourceStates {
+        let mut states = ResourceStates::default();
+        states.set_project(Object::initialize_now(
+            "project".to_string(),
+            ObjectType::Project,
+            None,
+        ));
+        let mut object = Object::initialize_now("file".to_string(), ObjectType::Object, None);
+        object.object_status = Status::Available;
+        states.set_object(object);
+        states
+    }
+
+    #[test]
+    fn test_objects_state_kinds() {
+        let ep_id = DieselUlid::generate();
+
+        // Root requests have no resources
+        let root = CheckAccessResult::root(UserState::Anonymous);
+        assert!(root.objects_state.resource_states().is_none());
+        assert!(root.objects_state.clone().require_bucket().is_err());
+        assert!(root.objects_state.require_regular().is_err());
+
+        // Bucket requests are not object requests
+        let bucket = ObjectsState::new_bucket(object_states());
+        assert!(bucket.resource_states().is_some());
+        assert!(bucket.try_slice().is_ok());
+        assert!(bucket.extract_object().is_err());
+        assert!(bucket.clone().require_regular().is_err());
+        assert!(bucket.clone().require_data(&ep_id).is_err());
+        assert!(bucket.require_bucket().is_ok());
+
+        // Objects without location are explicitly not available
+        for state in [
+            ObjectsState::new_object(object_states(), None),
+            ObjectsState::new_special_object(object_states(), None),
+        ] {
+            assert!(state.clone().require_bucket().is_err());
+            let (object, location) = state.extract_object().unwrap();
+            assert_eq!(object.name, "file");
+            assert!(location.is_none());
+            let err = state.require_data(&ep_id).unwrap_err();
+            assert_eq!(
+                *err.code(),
+                S3ErrorCode::Custom("ObjectNotAvailable".into())
+            );
+        }
+        let (_, object, location) =
+            ObjectsState::new_object(object_states(), Some(ObjectLocation::default()))
+                .require_data(&ep_id)
+                .unwrap();
+        assert_eq!(object.name, "file");
+        assert_eq!(location, ObjectLocation::default());
+
+        // Packages and bundles carry no hierarchy
+        let package = ObjectsState::new_objects(
+            Object::initialize_now("file".to_string(), ObjectType::Object, None),
+            "file.txt".to_string(),
+        );
+        assert!(package.resource_states().is_none());
+        assert!(package.try_slice().is_err());
+        assert!(package.require_regular().is_err());
+    }
+
+    #[test]
+    fn test_key_segments() {
+        assert_eq!(key_segments("file").unwrap(), vec!["file"]);
+        assert_eq!(key_segments("a/b/c").unwrap(), vec!["a", "b", "c"]);
+        // Trailing, leading and duplicate slashes
+        for key in ["dir/", "/file", "a//b", "a/b/c/d/e/"] {
+            let err = key_segments(key).unwrap_err();
+            assert_eq!(*err.code(), S3ErrorCode::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_key_depth() {
+        // Keys with 1-6 segments resolved into the full hierarchy (object name at index 2)
+        for segments in 1..=3 {
+            assert!(check_key_depth(segments, (segments - 1).min(2)).is_ok());
+        }
+        for segments in 4..=6 {
+            let err = check_key_depth(segments, 2).unwrap_err();
+            assert_eq!(*err.code(), S3ErrorCode::InvalidArgument);
+            assert_eq!(
+                err.message(),
+                Some(
+                    format!(
+                        "key depth {} exceeds maximum hierarchy depth 4, segments beyond 3 must be part of the object name",
+                        segments + 1
+                    )
+                    .as_str()
+                )
+            );
+        }
+        // An existing dataset directly below the project ends the hierarchy earlier
+        assert!(check_key_depth(2, 1).is_ok());
+        assert!(check_key_depth(3, 1).is_err());
+    }
+
+    #[test]
+    fn test_key_replaces_collection() {
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let collection = Object::initialize_now(
+            "col".to_string(),
+            ObjectType::Collection,
+            Some(TypedRelation::Project(project.id)),
+        );
+        let mut states = ResourceStates::new();
+        states.set_project(project);
+        states.set_collection(collection);
+        assert!(states.validate(false).is_ok());
+        let err = states.into_new_or_existing().unwrap_err();
+        assert_eq!(*err.code(), S3ErrorCode::InvalidArgument);
+        assert_eq!(
+            err.message(),
+            Some("key refers to existing collection col, objects can not replace a collection")
+        );
+
+        // Existing resources below missing parents
+        let mut states = ResourceStates::new();
+        states.set_project(Object::initialize_now(
+            "bucket".to_string(),
+            ObjectType::Project,
+            None,
+        ));
+        states
+            .set_missing_variant("col".to_string(), ResourceVariant::Collection)
+            .unwrap();
+        states.set_object(Object::initialize_now(
+            "file".to_string(),
+            ObjectType::Object,
+            None,
+        ));
+        assert!(states.validate(false).is_err());
+    }
+
+    #[test]
+    fn test_directory_marker() {
+        let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+        let mut states = ResourceStates::new();
+        states.set_project(project);
+        states
+            .set_missing_variant("dir".to_string(), ResourceVariant::Dataset)
+            .unwrap();
+        assert!(states.validate(false).is_ok());
+        let (collection, dataset) = states.into_directory_marker().unwrap();
+        assert!(matches!(collection, NewOrExistingObject::None));
+        assert!(matches!(dataset, NewOrExistingObject::Missing(ds) if ds.name == "dir"));
+        // Objects can not be written as directories
+        assert!(states.into_new_or_existing().is_err());
+    }
+
+    #[test]
+    fn test_owning_endpoint() {
+        let own = DieselUlid::generate();
+        let running = DieselUlid::generate();
+        let finished = DieselUlid::generate();
+        let failed = DieselUlid::generate();
+        let endpoint = |id, status| Endpoint {
+            id,
+            variant: SyncVariant::FullSync,
+            status,
+        };
+        let mut object = Object {
+            endpoints: vec![
+                endpoint(own, Some(SyncStatus::Finished)),
+                endpoint(failed, Some(SyncStatus::Error)),
+                endpoint(running, Some(SyncStatus::Running)),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(object.owning_endpoint(&own), Some(running));
+
+        object
+            .endpoints
+            .push(endpoint(finished, Some(SyncStatus::Finished)));
+        assert_eq!(object.owning_endpoint(&own), Some(finished));
+
+        object
+            .endpoints
+            .retain(|ep| ep.id == own || ep.id == failed);
+        assert_eq!(object.owning_endpoint(&own), None);
+    }
+
+    #[test]
+    fn test_staging_status() {
+        let own = DieselUlid::generate();
+        let other = DieselUlid::generate();
+        let endpoint = |id, status| Endpoint {
+            id,
+            variant: SyncVariant::FullSync,
+            status,
+        };
+        let mut object = Object {
+            object_status: Status::Initializing,
+            endpoints: vec![endpoint(own, Some(SyncStatus::Waiting))],
+            ..Default::default()
+        };
+        let location = ObjectLocation::default();
+        assert_eq!(
+            object.staging_status(Some(&location), &own),
+            StagingStatus::Staging
+        );
+
+        object.object_status = Status::Available;
+        assert_eq!(
+            object.staging_status(Some(&location), &own),
+            StagingStatus::Available
+        );
+        assert_eq!(
+            object.staging_status(None, &own),
+            StagingStatus::ReplicationPending
+        );
+        assert_eq!(object.endpoint_status(&own), "waiting");
+        assert_eq!(
+            object.missing_location(&own).status_code(),
+            Some(StatusCode::CONFLICT)
+        );
+
+        object.endpoints = vec![
+            endpoint(own, Some(SyncStatus::Error)),
+            endpoint(other, Some(SyncStatus::Finished)),
+        ];
+        assert_eq!(object.staging_status(None, &own), StagingStatus::Error);
+        assert_eq!(
+            object.endpoint_status(&DieselUlid::generate()),
+            "unassigned"
+        );
+        let corrupt = ObjectLocation {
+            corrupt: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            object.staging_status(Some(&corrupt), &own),
+            StagingStatus::Error
+        );
+    }
+
+    #[test]
+    fn test_quota_policy() {
+        let project_id = DieselUlid::generate();
+        let quota = QuotaPolicy { max_bytes: 100 };
+        let mut usage = BucketUsage::default();
+        usage.add(&ObjectUsage {
+            data_class: DataClass::Private,
+            raw_size: 60,
+            disk_size: 70,
+        });
+        assert!(quota.check(&project_id, &usage, 40).is_ok());
+        let err = quota.check(&project_id, &usage, 41).unwrap_err();
+        assert_eq!(err.status_code(), Some(StatusCode::FORBIDDEN));
+        assert_eq!(err.code().as_str(), "QuotaExceeded");
+    }
+
+    #[test]
+    fn test_object_retention() {
+        let now = Utc::now();
+        let until = |date: DateTime<Utc>| {
+            Some(
+                time::OffsetDateTime::from_unix_timestamp(date.timestamp())
+                    .unwrap()
+                    .into(),
+            )
+        };
+        let retention = ObjectRetention::from_headers(
+            Some("COMPLIANCE"),
+            until(now + chrono::Duration::days(1)),
+            now,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(ObjectRetention::from_headers(None, None, now)
+            .unwrap()
+            .is_none());
+        // Incomplete, unsupported mode or past date
+        assert!(ObjectRetention::from_headers(Some("COMPLIANCE"), None, now).is_err());
+        assert!(ObjectRetention::from_headers(
+            Some("GOVERNANCE"),
+            until(now + chrono::Duration::days(1)),
+            now
+        )
+        .is_err());
+        assert!(ObjectRetention::from_headers(
+            Some("COMPLIANCE"),
+            until(now - chrono::Duration::days(1)),
+            now
+        )
+        .is_err());
+
+        // Stored as object label
+        let object = Object {
+            key_values: vec![retention.to_label().unwrap()],
+            ..Default::default()
+        };
+        let stored = ObjectRetention::from_object(&object).unwrap();
+        assert_eq!(stored, retention);
+        let err = stored.check_mutation(&object.id, now).unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::AccessDenied);
+        assert!(stored
+            .check_mutation(&object.id, now + chrono::Duration::days(2))
+            .is_ok());
+        assert!(ObjectRetention::from_object(&Object::default()).is_none());
+    }
+
+    #[test]
+    fn test_object_lock_policy() {
+        let now = Utc::now();
+        let policy: ObjectLockPolicy =
+            serde_json::from_str(r#"{"mode":"COMPLIANCE","days":30}"#).unwrap();
+        assert_eq!(
+            policy.default_retention(now).unwrap().retain_until,
+            now + chrono::Duration::days(30)
+        );
+        let config = ObjectLockConfiguration::from(policy.clone());
+        assert_eq!(ObjectLockPolicy::try_from(config).unwrap(), policy);
+
+        let both = ObjectLockConfiguration::from(ObjectLockPolicy {
+            mode: RetentionMode::Compliance,
+            days: Some(1),
+            years: Some(1),
+        });
+        assert!(ObjectLockPolicy::try_from(both).is_err());
+    }
+
+    #[test]
+    fn test_restore_status() {
+        let restored = RestoreStatus::from_header(
+            "ongoing-request=\"false\", expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\"",
+        )
+        .unwrap();
+        let expiry = DateTime::parse_from_rfc3339("2012-12-21T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            restored,
+            RestoreStatus {
+                ongoing: false,
+                expiry: Some(expiry),
+            }
+        );
+        assert_eq!(
+            RestoreStatus::from_header(&restored.to_header()),
+            Some(restored.clone())
+        );
+        let ongoing = RestoreStatus::from_header("ongoing-request=\"true\"").unwrap();
+        assert_eq!(ongoing.to_header(), "ongoing-request=\"true\"");
+        assert!(RestoreStatus::from_header("invalid").is_none());
+
+        // Archived data is readable while the restored copy is available
+        let mut location = ObjectLocation {
+            storage_class: Some("GLACIER".to_string()),
+            ..Default::default()
+        };
+        let before = expiry - chrono::Duration::days(1);
+        assert!(location.is_archived(before));
+        location.restore = Some(ongoing);
+        assert!(location.is_archived(before));
+        location.restore = Some(restored);
+        assert!(!location.is_archived(before));
+        assert!(location.is_archived(expiry));
+        location.storage_class = Some("STANDARD_IA".to_string());
+        assert!(!location.is_archived(expiry));
+    }
+
+    #[test]
+    fn test_last_modified_from_server() {
+        let updated = DateTime::parse_from_rfc3339("2023-05-04T10:20:30Z")
+            .unwrap()
+            .timestamp();
+        let grpc_object = GrpcObject {
+            id: DieselUlid::generate().to_string(),
+            name: "file".to_string(),
+            created_by: DieselUlid::generate().to_string(),
+            created_at: Some(prost_wkt_types::Timestamp {
+                seconds: updated,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        let object = Object::try_from(grpc_object.clone()).unwrap();
+        assert_eq!(
+            object.get_last_modified().map(|t| t.unix_timestamp()),
+            Some(updated)
+        );
+        // A rebuilt cache reports the same timestamp, both from the server and the persistence
+        let rebuilt = Object::try_from(grpc_object).unwrap();
+        assert_eq!(rebuilt.get_last_modified(), object.get_last_modified());
+        let reloaded = reload(object.clone());
+        assert_eq!(reloaded.created_at, object.created_at);
+        assert_eq!(reloaded.get_last_modified(), object.get_last_modified());
+
+        // Without a server timestamp the creation time of the id is used
+        let mut object = Object::initialize_now("file".to_string(), ObjectType::Object, None);
+        object.created_at = None;
+        assert_eq!(
+            object.get_last_modified().map(|t| t.unix_timestamp()),
+            Some((object.id.timestamp() / 1000) as i64)
+        );
+        assert_eq!(
+            reload(object.clone()).get_last_modified(),
+            object.get_last_modified()
+        );
+    }
+
+    /// Writes an object like the persistence does (JSONB column) and reads it back
+    /// like the cache does on startup
+    fn reload(object: Object) -> Object {
+        use crate::database::persistence::GenericBytes;
+        use tokio_postgres::types::{FromSql, Json, ToSql, Type};
+
+        let stored: GenericBytes<DieselUlid, Object> = object.try_into().unwrap();
+        let mut column = bytes::BytesMut::new();
+        stored.data.to_sql(&Type::JSONB, &mut column).unwrap();
+        Object::try_from(GenericBytes {
+            id: stored.id,
+            data: Json::<Object>::from_sql(&Type::JSONB, &column).unwrap(),
+            table: stored.table,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_metadata_omits_missing_hashes() {
+        let mut location = ObjectLocation {
+            user_metadata: HashMap::from_iter([("origin".to_string(), "lab".to_string())]),
+            raw_hashes: HashMap::from_iter([
+                ("sha256".to_string(), "abc".to_string()),
+                ("md5".to_string(), String::new()),
+            ]),
+            ..Default::default()
+        };
+        let metadata = location.get_metadata().unwrap();
+        assert_eq!(metadata.get("origin").map(String::as_str), Some("lab"));
+        assert_eq!(metadata.get("sha256").map(String::as_str), Some("abc"));
+        assert!(!metadata.contains_key("md5"));
+
+        location.user_metadata.clear();
+        location.raw_hashes = HashMap::from_iter([("md5".to_string(), String::new())]);
+        assert_eq!(location.get_metadata(), None);
+    }
+
+    #[test]
+    fn test_pending_creation() {
+        let local = Object::initialize_now("file".to_string(), ObjectType::Object, None);
+        let mut notification = PendingNotification {
+            id: local.id,
+            content_len: 3,
+            hashes: HashMap::from_iter([("MD5".to_string(), "abc".to_string())]),
+            user_id: DieselUlid::generate(),
+            access_key: None,
+            attempts: 0,
+            next_attempt: Utc::now(),
+            last_error: None,
+            create: Some(local.clone()),
+        };
+        notification.schedule_retry("unavailable".to_string());
+
+        // Persisted notifications keep the object that still has to be created
+        let stored = serde_json::to_string(&notification).unwrap();
+        let loaded: PendingNotification = serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded.create.as_ref().map(|o| o.id), Some(local.id));
+
+        // Notifications stored before creations were queued only finish objects
+        let mut legacy = serde_json::to_value(&notification).unwrap();
+        legacy.as_object_mut().unwrap().remove("create");
+        let legacy: PendingNotification = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.create, None);
+
+        let created = DieselUlid::generate();
+        let finish = loaded.created(created);
+        assert_eq!(finish.id, created);
+        assert_eq!(finish.create, None);
+        assert_eq!(finish.attempts, 0);
+        assert_eq!(finish.content_len, 3);
+        assert_eq!(finish.hashes, notification.hashes);
+        assert_eq!(finish.user_id, notification.user_id);
+    }
 }
@@ -0,0 +1,267 @@
+//! Export of request traces to an OpenTelemetry collector and propagation of the
+//! trace context (`traceparent`) over gRPC metadata
+use crate::config::Config;
+use anyhow::Result;
+use diesel_ulid::DieselUlid;
+use http::HeaderMap;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{
+    Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId,
+};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, ShouldSample};
+use opentelemetry_sdk::Resource;
+use std::future::Future;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Request header of admins to export the trace of a request regardless of the rate
+pub const DEBUG_TRACE_HEADER: &str = "x-aruna-debug-trace";
+/// Field of request spans that forces the export of the trace
+pub const DEBUG_TRACE_FIELD: &str = "debug_trace";
+
+tokio::task_local! {
+    static REQUEST_SPAN: Span;
+}
+
+/// Layer exporting the spans via OTLP if `[telemetry]` is configured, the tracer
+/// needs a tokio runtime
+pub fn otlp_layer<S>(config: &Config) -> Result<Option<impl Layer<S>>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Some(config) = &config.telemetry else {
+        return Ok(None);
+    };
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&config.otlp_endpoint),
+        )
+        .with_trace_config(
+            opentelemetry_sdk::trace::config()
+                .with_sampler(DebugSampler::new(config.sample_rate))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    let filter = config.filter.parse::<Targets>()?;
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(filter),
+    ))
+}
+
+/// Exports the spans that are not exported yet
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Runs the future of a request with its root span, see [`record_user`]
+pub async fn scope<F: Future>(span: Span, future: F) -> F::Output {
+    REQUEST_SPAN.scope(span, future).await
+}
+
+/// Adds the authenticated user to the root span of the current request
+pub fn record_user(user_id: &DieselUlid) {
+    let _ = REQUEST_SPAN.try_with(|span| {
+        span.record("user_id", tracing::field::display(user_id));
+    });
+}
+
+/// Whether the request asks for an exported trace, only honored for admins
+pub fn debug_requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(DEBUG_TRACE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+/// Forwards the trace context of the current span, e.g. to a peer proxy
+pub fn inject(md: &mut MetadataMap) {
+    inject_context(&Span::current().context(), md);
+}
+
+/// Continues the trace of the caller in the span, if the headers contain one
+pub fn set_parent(span: &Span, headers: &HeaderMap) {
+    let cx = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if cx.span().span_context().is_valid() {
+        span.set_parent(cx);
+    }
+}
+
+fn inject_context(cx: &Context, md: &mut MetadataMap) {
+    TraceContextPropagator::new().inject_context(cx, &mut MetadataInjector(md));
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            AsciiMetadataKey::from_bytes(key.as_bytes()),
+            AsciiMetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Head-based sampling: traces of callers follow their decision, new traces are sampled
+/// by the rate unless the root span has the [`DEBUG_TRACE_FIELD`]
+#[derive(Debug, Clone)]
+struct DebugSampler(Sampler);
+
+impl DebugSampler {
+    fn new(rate: f64) -> Self {
+        DebugSampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            rate,
+        ))))
+    }
+}
+
+impl ShouldSample for DebugSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let forced = attributes.iter().any(|kv| {
+            kv.key.as_str() == DEBUG_TRACE_FIELD && matches!(kv.value, Value::Bool(true))
+        });
+        if forced {
+            return SamplingResult {
+                decision: SamplingDecision::RecordAndSample,
+                attributes: Vec::new(),
+                trace_state: parent_context
+                    .map(|cx| cx.span().span_context().trace_state().clone())
+                    .unwrap_or_default(),
+            };
+        }
+        self.0
+            .should_sample(parent_context, trace_id, name, span_kind, attributes, links)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceState};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    fn remote(flags: TraceFlags) -> Context {
+        Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_hex(TRACE_ID).unwrap(),
+            SpanId::from_hex(SPAN_ID).unwrap(),
+            flags,
+            true,
+            TraceState::default(),
+        ))
+    }
+
+    fn decision(
+        sampler: &DebugSampler,
+        parent: Option<&Context>,
+        attributes: &[KeyValue],
+    ) -> SamplingDecision {
+        sampler
+            .should_sample(
+                parent,
+                TraceId::from_hex(TRACE_ID).unwrap(),
+                "s3_request",
+                &SpanKind::Internal,
+                attributes,
+                &[],
+            )
+            .decision
+    }
+
+    #[test]
+    fn test_sampling() {
+        let never = DebugSampler::new(0.0);
+        assert_eq!(decision(&never, None, &[]), SamplingDecision::Drop);
+        assert_eq!(
+            decision(&never, None, &[KeyValue::new(DEBUG_TRACE_FIELD, false)]),
+            SamplingDecision::Drop
+        );
+        // Admin debug requests are always exported
+        assert_eq!(
+            decision(&never, None, &[KeyValue::new(DEBUG_TRACE_FIELD, true)]),
+            SamplingDecision::RecordAndSample
+        );
+        // Continued traces follow the caller
+        assert_eq!(
+            decision(&never, Some(&remote(TraceFlags::SAMPLED)), &[]),
+            SamplingDecision::RecordAndSample
+        );
+        let always = DebugSampler::new(1.0);
+        assert_eq!(
+            decision(&always, Some(&remote(TraceFlags::default())), &[]),
+            SamplingDecision::Drop
+        );
+        assert_eq!(
+            decision(&always, None, &[]),
+            SamplingDecision::RecordAndSample
+        );
+    }
+
+    #[test]
+    fn test_propagation() {
+        let mut md = MetadataMap::new();
+        inject_context(&remote(TraceFlags::SAMPLED), &mut md);
+        assert_eq!(
+            md.get("traceparent").unwrap().to_str().unwrap(),
+            format!("00-{TRACE_ID}-{SPAN_ID}-01")
+        );
+
+        let headers = md.into_headers();
+        let cx = TraceContextPropagator::new().extract(&HeaderExtractor(&headers));
+        let span = cx.span();
+        assert_eq!(span.span_context().trace_id().to_string(), TRACE_ID);
+        assert!(span.span_context().is_sampled());
+
+        // Requests without trace context start a new trace
+        let mut md = MetadataMap::new();
+        inject_context(&Context::new(), &mut md);
+        assert!(md.is_empty());
+    }
+
+    #[test]
+    fn test_debug_header() {
+        let mut headers = HeaderMap::new();
+        assert!(!debug_requested(&headers));
+        headers.insert(DEBUG_TRACE_HEADER, "false".parse().unwrap());
+        assert!(!debug_requested(&headers));
+        headers.insert(DEBUG_TRACE_HEADER, "true".parse().unwrap());
+        assert!(debug_requested(&headers));
+    }
+}
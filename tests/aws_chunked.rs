@@ -0,0 +1,241 @@
+//! Uploads in the aws-chunked framing of the AWS SDKs are stored without the framing
+//! and read back byte-identically
+//!
+//! Trailing checksums (`x-amz-trailer`) are not compared with the payload: the checksum
+//! of the `x-amz-sdk-checksum-algorithm` is calculated over the decoded payload and
+//! returned in the PutObject response instead.
+mod common;
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::{start_proxy_with_access, Access};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const REGION: &str = "us-east-1";
+/// Chunk size of the Java SDK v2
+const CHUNK_SIZE: usize = 128 * 1024;
+/// Default of the Java SDK v2: unsigned chunks and a CRC32 trailer
+const UNSIGNED_TRAILER: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+/// Every chunk is signed, chained to the signature of the request
+const SIGNED_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+
+/// A few chunks of text, the last one is shorter than `CHUNK_SIZE`
+fn payload() -> Vec<u8> {
+    (0..3000)
+        .flat_map(|i| format!("{i:06} The quick brown fox jumps over the lazy dog\n").into_bytes())
+        .collect()
+}
+
+fn crc32(data: &[u8]) -> String {
+    STANDARD.encode(crc32fast::hash(data).to_be_bytes())
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// SigV4 signing key and scope of the current day
+struct Signer {
+    timestamp: String,
+    scope: String,
+    key: Vec<u8>,
+}
+
+impl Signer {
+    fn new(secret: &str) -> Self {
+        let now = chrono::Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        let mut key = format!("AWS4{secret}").into_bytes();
+        for part in [date.as_str(), REGION, "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part);
+        }
+        Signer {
+            timestamp: now.format("%Y%m%dT%H%M%SZ").to_string(),
+            scope: format!("{date}/{REGION}/s3/aws4_request"),
+            key,
+        }
+    }
+
+    fn sign(&self, string_to_sign: &str) -> String {
+        hex::encode(hmac_sha256(&self.key, string_to_sign))
+    }
+
+    /// Signature of a PutObject with the `headers` (sorted by name), the content
+    /// sha256 is the streaming mode instead of the payload hash
+    fn sign_request(&self, path: &str, headers: &[(&str, String)], content_sha256: &str) -> String {
+        let canonical_headers = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{value}\n"))
+            .collect::<String>();
+        let canonical_request = format!(
+            "PUT\n{path}\n\n{canonical_headers}\n{}\n{content_sha256}",
+            signed_header_names(headers)
+        );
+        self.sign(&format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            self.timestamp,
+            self.scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        ))
+    }
+}
+
+fn signed_header_names(headers: &[(&str, String)]) -> String {
+    headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// `<hex-size>\r\n<data>\r\n` per chunk, a final empty chunk and the CRC32 trailer
+fn frame_unsigned(payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::new();
+    for chunk in payload.chunks(CHUNK_SIZE) {
+        framed.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        framed.extend_from_slice(chunk);
+        framed.extend_from_slice(b"\r\n");
+    }
+    framed.extend_from_slice(
+        format!("0\r\nx-amz-checksum-crc32:{}\r\n\r\n", crc32(payload)).as_bytes(),
+    );
+    framed
+}
+
+/// `<hex-size>;chunk-signature=<sig>\r\n<data>\r\n` per chunk and a final empty chunk,
+/// each signature covers the chunk and the previous signature (`seed` for the first)
+fn frame_signed(payload: &[u8], signer: &Signer, seed: &str) -> Vec<u8> {
+    let empty_hash = hex::encode(Sha256::digest(b""));
+    let mut previous = seed.to_string();
+    let mut framed = Vec::new();
+    for chunk in payload.chunks(CHUNK_SIZE).chain([&[][..]]) {
+        previous = signer.sign(&format!(
+            "AWS4-HMAC-SHA256-PAYLOAD\n{}\n{}\n{previous}\n{empty_hash}\n{}",
+            signer.timestamp,
+            signer.scope,
+            hex::encode(Sha256::digest(chunk))
+        ));
+        framed.extend_from_slice(
+            format!("{:x};chunk-signature={previous}\r\n", chunk.len()).as_bytes(),
+        );
+        framed.extend_from_slice(chunk);
+        framed.extend_from_slice(b"\r\n");
+    }
+    framed
+}
+
+/// Sends a signed PutObject, `headers` are signed in addition to host and date
+async fn put(
+    access: &Access,
+    key: &str,
+    mut headers: Vec<(&str, String)>,
+    frame: impl FnOnce(&Signer, &str) -> Vec<u8>,
+) -> Result<reqwest::Response> {
+    let signer = Signer::new(&access.secret);
+    let path = format!("/bucket/{key}");
+    headers.push(("host", access.address.clone()));
+    headers.push(("x-amz-date", signer.timestamp.clone()));
+    headers.sort();
+    let content_sha256 = headers
+        .iter()
+        .find(|(name, _)| *name == "x-amz-content-sha256")
+        .map(|(_, value)| value.clone())
+        .expect("streaming mode is signed");
+    let signature = signer.sign_request(&path, &headers, &content_sha256);
+
+    let mut request = reqwest::Client::new()
+        .put(format!("http://{}{path}", access.address))
+        .header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={signature}",
+                access.access_key,
+                signer.scope,
+                signed_header_names(&headers)
+            ),
+        )
+        .header("content-type", "text/plain");
+    for (name, value) in &headers {
+        if *name != "host" {
+            request = request.header(*name, value);
+        }
+    }
+    Ok(request.body(frame(&signer, &signature)).send().await?)
+}
+
+async fn get(client: &aws_sdk_s3::Client, key: &str) -> Result<Vec<u8>> {
+    let output = client.get_object().bucket("bucket").key(key).send().await?;
+    Ok(output.body.collect().await?.into_bytes().to_vec())
+}
+
+#[tokio::test]
+async fn test_java_sdk_upload_round_trip() -> Result<()> {
+    let (client, access) = start_proxy_with_access("01J4B7WQ3E9R5T2Y8V6K1M0PAS").await?;
+    let payload = payload();
+    let headers = vec![
+        ("content-encoding", "aws-chunked".to_string()),
+        ("x-amz-content-sha256", UNSIGNED_TRAILER.to_string()),
+        ("x-amz-decoded-content-length", payload.len().to_string()),
+        ("x-amz-sdk-checksum-algorithm", "CRC32".to_string()),
+        ("x-amz-trailer", "x-amz-checksum-crc32".to_string()),
+    ];
+
+    let response = put(&access, "java.txt", headers, |_, _| {
+        frame_unsigned(&payload)
+    })
+    .await?;
+    assert!(response.status().is_success());
+    // Calculated over the decoded payload, identical to the trailer the client sent
+    assert_eq!(
+        response
+            .headers()
+            .get("x-amz-checksum-crc32")
+            .and_then(|value| value.to_str().ok()),
+        Some(crc32(&payload).as_str())
+    );
+
+    let head = client
+        .head_object()
+        .bucket("bucket")
+        .key("java.txt")
+        .send()
+        .await?;
+    assert_eq!(head.content_length(), Some(payload.len() as i64));
+    assert_eq!(get(&client, "java.txt").await?, payload);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_signed_chunks_round_trip() -> Result<()> {
+    let (client, access) = start_proxy_with_access("01J4B7WQ3E9R5T2Y8V6K1M0PAT").await?;
+    let payload = payload();
+    let headers = || {
+        vec![
+            ("content-encoding", "aws-chunked".to_string()),
+            ("x-amz-content-sha256", SIGNED_PAYLOAD.to_string()),
+            ("x-amz-decoded-content-length", payload.len().to_string()),
+        ]
+    };
+
+    let response = put(&access, "signed.txt", headers(), |signer, seed| {
+        frame_signed(&payload, signer, seed)
+    })
+    .await?;
+    assert!(response.status().is_success());
+    assert_eq!(get(&client, "signed.txt").await?, payload);
+
+    // Data that does not match its chunk signature is rejected
+    let response = put(&access, "tampered.txt", headers(), |signer, seed| {
+        let mut framed = frame_signed(&payload, signer, seed);
+        let last = framed.len() - 200;
+        framed[last] ^= 1;
+        framed
+    })
+    .await?;
+    assert!(!response.status().is_success());
+    Ok(())
+}
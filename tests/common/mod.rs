@@ -0,0 +1,338 @@
+//! Embedded dataproxy with a filesystem (or in-memory) backend and a user, shared by the
+//! integration tests
+#![allow(dead_code)]
+use anyhow::Result;
+use aos_data_proxy::{
+    Cache, Config, DataProxy, DataProxyBuilder, MemoryBackend, Object, ObjectType, TypedRelation,
+};
+use aruna_rust_api::api::storage::models::v2::permission::ResourceId;
+use aruna_rust_api::api::storage::models::v2::{
+    DataClass, Permission, PermissionLevel, User as GrpcUser, UserAttributes,
+};
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use diesel_ulid::DieselUlid;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// Storage format of the filesystem backend
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Storage {
+    pub encryption: bool,
+    pub compression: bool,
+}
+
+/// Key handling of the S3 frontend, see `frontend.flatten_keys`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Keys {
+    pub flatten: bool,
+}
+
+/// Address of the S3 frontend and the credentials of the user, for raw requests
+#[derive(Debug, Clone)]
+pub struct Access {
+    pub address: String,
+    pub access_key: String,
+    pub secret: String,
+}
+
+fn config(
+    endpoint_id: &str,
+    address: &str,
+    public_address: Option<&str>,
+    root: &Path,
+    storage: Storage,
+    keys: Keys,
+) -> Result<Config> {
+    let listeners = match public_address {
+        Some(public_address) => format!(
+            r#"
+            [[frontend.listeners]]
+            server = "{public_address}"
+            hostname = "{public_address}"
+            profile = "public_read_only"
+            "#
+        ),
+        None => String::new(),
+    };
+    Ok(toml::from_str(&format!(
+        r#"
+        rules = []
+
+        [proxy]
+        endpoint_id = "{endpoint_id}"
+        private_key = "MC4CAQAwBQYDK2VwBCIEIM/FI+bYw+auSKGyGqeISRIEjofvZV/lbK7QL1wkuCey"
+        public_key = "MCowBQYDK2VwAyEAnouQBh4GHPCD/k85VIzPyCdOijVg2qlzt2TELwTMy4c="
+        serial = 1337
+        enable_ingest = false
+        admin_ids = []
+        grpc_server = "127.0.0.1:0"
+        remote_synced = false
+
+        [frontend]
+        server = "{address}"
+        hostname = "{address}"
+        flatten_keys = {}
+        {listeners}
+
+        [backend.filesystem]
+        root_path = "{}"
+        encryption = {}
+        compression = {}
+        backend_scheme = "s3://{{{{PROJECT_NAME}}}}/{{{{OBJECT_NAME}}}}"
+        "#,
+        keys.flatten,
+        root.display(),
+        storage.encryption,
+        storage.compression,
+    ))?)
+}
+
+fn free_address() -> Result<String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.to_string())
+}
+
+/// Starts the S3 frontend of a proxy with a user that owns the project `bucket` and the
+/// initializing object `bucket/file.txt`, returns a client with the credentials of the user
+pub async fn start_proxy(endpoint_id: &str, storage: Storage) -> Result<aws_sdk_s3::Client> {
+    start(endpoint_id, storage, &[("file.txt", DataClass::Private)]).await
+}
+
+/// Like `start_proxy` with the given in-memory backend instead of the filesystem backend
+pub async fn start_proxy_in_memory(
+    endpoint_id: &str,
+    backend: MemoryBackend,
+) -> Result<aws_sdk_s3::Client> {
+    let (proxy, addresses, client, _) = build(
+        endpoint_id,
+        Storage::default(),
+        Keys::default(),
+        &[("file.txt", DataClass::Private)],
+        None,
+        Some(backend),
+    )
+    .await?;
+    let s3 = proxy.into_tasks().s3.expect("frontend configured");
+    tokio::spawn(s3);
+    wait_for(&addresses).await;
+    Ok(client)
+}
+
+/// Like `start_proxy` with the given initializing objects instead of `bucket/file.txt`
+pub async fn start_proxy_with_keys(endpoint_id: &str, keys: &[&str]) -> Result<aws_sdk_s3::Client> {
+    let keys = keys
+        .iter()
+        .map(|key| (*key, DataClass::Private))
+        .collect::<Vec<_>>();
+    start(endpoint_id, Storage::default(), &keys).await
+}
+
+/// Like `start_proxy` with an additional `public_read_only` listener and the initializing
+/// public object `bucket/public.txt`, returns the client, the cache and the address of
+/// the listener
+pub async fn start_proxy_with_public_listener(
+    endpoint_id: &str,
+) -> Result<(aws_sdk_s3::Client, Arc<Cache>, String)> {
+    let public_address = free_address()?;
+    let (proxy, addresses, client, _) = build(
+        endpoint_id,
+        Storage::default(),
+        Keys::default(),
+        &[
+            ("file.txt", DataClass::Private),
+            ("public.txt", DataClass::Public),
+        ],
+        Some(&public_address),
+        None,
+    )
+    .await?;
+    let cache = proxy.cache();
+    let s3 = proxy.into_tasks().s3.expect("frontend configured");
+    tokio::spawn(s3);
+    wait_for(&addresses).await;
+    Ok((client, cache, public_address))
+}
+
+/// Like `start_proxy` with the given key handling, returns the cache to set up the
+/// hierarchy of the bucket
+pub async fn start_proxy_with_cache(
+    endpoint_id: &str,
+    keys: Keys,
+) -> Result<(aws_sdk_s3::Client, Arc<Cache>)> {
+    let (proxy, addresses, client, _) = build(
+        endpoint_id,
+        Storage::default(),
+        keys,
+        &[("file.txt", DataClass::Private)],
+        None,
+        None,
+    )
+    .await?;
+    let cache = proxy.cache();
+    let s3 = proxy.into_tasks().s3.expect("frontend configured");
+    tokio::spawn(s3);
+    wait_for(&addresses).await;
+    Ok((client, cache))
+}
+
+/// Like `start_proxy` and additionally returns the credentials of the user
+pub async fn start_proxy_with_access(endpoint_id: &str) -> Result<(aws_sdk_s3::Client, Access)> {
+    let (proxy, addresses, client, access) = build(
+        endpoint_id,
+        Storage::default(),
+        Keys::default(),
+        &[("file.txt", DataClass::Private)],
+        None,
+        None,
+    )
+    .await?;
+    let s3 = proxy.into_tasks().s3.expect("frontend configured");
+    tokio::spawn(s3);
+    wait_for(&addresses).await;
+    Ok((client, access))
+}
+
+/// Runs all tasks of a proxy like the binary does until SIGTERM/SIGINT, returns the
+/// client of the user, the cache and the handle of `DataProxy::run`
+pub async fn run_proxy(
+    endpoint_id: &str,
+) -> Result<(aws_sdk_s3::Client, Arc<Cache>, JoinHandle<Result<()>>)> {
+    let (proxy, addresses, client, _) = build(
+        endpoint_id,
+        Storage::default(),
+        Keys::default(),
+        &[("file.txt", DataClass::Private)],
+        None,
+        None,
+    )
+    .await?;
+    let cache = proxy.cache();
+    let handle = tokio::spawn(proxy.run());
+    wait_for(&addresses).await;
+    Ok((client, cache, handle))
+}
+
+async fn start(
+    endpoint_id: &str,
+    storage: Storage,
+    keys: &[(&str, DataClass)],
+) -> Result<aws_sdk_s3::Client> {
+    let (proxy, addresses, client, _) =
+        build(endpoint_id, storage, Keys::default(), keys, None, None).await?;
+    let s3 = proxy.into_tasks().s3.expect("frontend configured");
+    tokio::spawn(s3);
+    wait_for(&addresses).await;
+    Ok(client)
+}
+
+async fn wait_for(addresses: &[String]) {
+    for address in addresses {
+        while TcpStream::connect(address).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+/// Proxy with the resources and the user of the tests, its tasks are not started yet
+async fn build(
+    endpoint_id: &str,
+    storage: Storage,
+    key_handling: Keys,
+    keys: &[(&str, DataClass)],
+    public_address: Option<&str>,
+    backend: Option<MemoryBackend>,
+) -> Result<(DataProxy, Vec<String>, aws_sdk_s3::Client, Access)> {
+    let address = free_address()?;
+    let root = std::env::temp_dir().join(format!("embedded_{endpoint_id}"));
+    let mut builder = DataProxyBuilder::new().config(config(
+        endpoint_id,
+        &address,
+        public_address,
+        &root,
+        storage,
+        key_handling,
+    )?);
+    if let Some(backend) = backend {
+        builder = builder.backend(Box::new(backend));
+    }
+    let proxy = builder.build().await?;
+
+    // Resources and users are usually synced from the server
+    let cache = proxy.cache();
+    let mut project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+    let objects = keys
+        .iter()
+        .map(|(key, data_class)| {
+            let mut object = Object::initialize_now(
+                key.to_string(),
+                ObjectType::Object,
+                Some(TypedRelation::Project(project.id)),
+            );
+            object.data_class = *data_class;
+            object
+        })
+        .collect::<Vec<_>>();
+    project.children = Some(
+        objects
+            .iter()
+            .map(|object| TypedRelation::Object(object.id))
+            .collect(),
+    );
+    cache.upsert_object(project.clone()).await?;
+    for object in objects {
+        cache.upsert_object(object).await?;
+    }
+
+    let user_id = DieselUlid::generate();
+    cache
+        .clone()
+        .upsert_user(GrpcUser {
+            id: user_id.to_string(),
+            attributes: Some(UserAttributes {
+                personal_permissions: vec![Permission {
+                    permission_level: PermissionLevel::Admin as i32,
+                    resource_id: Some(ResourceId::ProjectId(project.id.to_string())),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await?;
+    let (access_key, secret) = cache
+        .create_or_update_secret(&user_id.to_string(), &user_id)
+        .await?;
+
+    let addresses = [Some(address.as_str()), public_address]
+        .into_iter()
+        .flatten()
+        .map(str::to_string)
+        .collect();
+
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .endpoint_url(format!("http://{address}"))
+        .credentials_provider(Credentials::new(
+            access_key.clone(),
+            secret.clone(),
+            None,
+            None,
+            "test",
+        ))
+        .force_path_style(true)
+        .build();
+    let access = Access {
+        address,
+        access_key,
+        secret,
+    };
+    Ok((
+        proxy,
+        addresses,
+        aws_sdk_s3::Client::from_conf(config),
+        access,
+    ))
+}
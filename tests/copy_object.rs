@@ -0,0 +1,59 @@
+//! CopyObject with the `x-amz-copy-source-if-*` preconditions of the source
+mod common;
+
+use anyhow::Result;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use common::start_proxy_with_keys;
+
+#[tokio::test]
+async fn test_copy_source_preconditions() -> Result<()> {
+    let client =
+        start_proxy_with_keys("01J2FD0XK3W8QH6E2N7M5VZ4RB", &["source.txt", "copy.txt"]).await?;
+    let etag = client
+        .put_object()
+        .bucket("bucket")
+        .key("source.txt")
+        .content_type("text/plain")
+        .body(ByteStream::from_static(b"copied data"))
+        .send()
+        .await?
+        .e_tag
+        .expect("etag of the upload");
+    let copy = || {
+        client
+            .copy_object()
+            .bucket("bucket")
+            .key("copy.txt")
+            .copy_source("bucket/source.txt")
+    };
+
+    // Failed conditions of the source are answered with 412, never 304
+    let error = copy()
+        .copy_source_if_match("\"0123456789abcdef0123456789abcdef\"")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Some("PreconditionFailed"));
+    let error = copy()
+        .copy_source_if_none_match(&etag)
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Some("PreconditionFailed"));
+
+    let output = copy().copy_source_if_match(&etag).send().await?;
+    assert!(output.copy_object_result.is_some());
+    let copied = client
+        .get_object()
+        .bucket("bucket")
+        .key("copy.txt")
+        .send()
+        .await?;
+    assert_eq!(copied.content_type(), Some("text/plain"));
+    assert_eq!(
+        copied.body.collect().await?.into_bytes().as_ref(),
+        b"copied data"
+    );
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! Credentials of the DataproxyUserService against a stub Aruna server: created via gRPC,
+//! used via S3, revoked via gRPC and denied on the next S3 request
+use anyhow::Result;
+use aos_data_proxy::{Config, DataProxyBuilder, Object, ObjectType};
+use aruna_rust_api::api::dataproxy::services::v2::dataproxy_user_service_client::DataproxyUserServiceClient;
+use aruna_rust_api::api::dataproxy::services::v2::{
+    GetCredentialsRequest, RevokeCredentialsRequest,
+};
+use aruna_rust_api::api::storage::models::v2::permission::ResourceId;
+use aruna_rust_api::api::storage::models::v2::{
+    Permission, PermissionLevel, Pubkey, User as GrpcUser, UserAttributes,
+};
+use aruna_rust_api::api::storage::services::v2::{
+    GetPubkeysRequest, GetPubkeysResponse, GetUserRedactedRequest, GetUserRedactedResponse,
+};
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use diesel_ulid::DieselUlid;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, BoxFuture};
+use tonic::metadata::MetadataValue;
+
+/// Key id of the server key, the key pair in `tests/test.pem` and `tests/test.pub`
+const SERVER_KID: i32 = 1;
+
+/// Serves the requests the proxy sends to the Aruna server on startup and while
+/// authenticating users, everything else is unimplemented
+#[derive(Clone)]
+struct StubServer {
+    user: GrpcUser,
+}
+
+struct GetPubkeysSvc;
+
+impl tonic::server::UnaryService<GetPubkeysRequest> for GetPubkeysSvc {
+    type Response = GetPubkeysResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, _request: tonic::Request<GetPubkeysRequest>) -> Self::Future {
+        let key = include_str!("test.pub")
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        Box::pin(async move {
+            Ok(tonic::Response::new(GetPubkeysResponse {
+                pubkeys: vec![Pubkey {
+                    id: SERVER_KID,
+                    key,
+                    location: "aruna".to_string(),
+                }],
+            }))
+        })
+    }
+}
+
+struct GetUserRedactedSvc(GrpcUser);
+
+impl tonic::server::UnaryService<GetUserRedactedRequest> for GetUserRedactedSvc {
+    type Response = GetUserRedactedResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<GetUserRedactedRequest>) -> Self::Future {
+        let user = self.0.clone();
+        Box::pin(async move {
+            if request.into_inner().user_id != user.id {
+                return Err(tonic::Status::not_found("Unknown user"));
+            }
+            Ok(tonic::Response::new(GetUserRedactedResponse {
+                user: Some(user),
+            }))
+        })
+    }
+}
+
+impl tower::Service<http::Request<hyper::Body>> for StubServer {
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<hyper::Body>) -> Self::Future {
+        let user = self.user.clone();
+        Box::pin(async move {
+            Ok(match req.uri().path() {
+                "/aruna.api.storage.services.v2.StorageStatusService/GetPubkeys" => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetPubkeysSvc, req)
+                        .await
+                }
+                "/aruna.api.storage.services.v2.UserService/GetUserRedacted" => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(GetUserRedactedSvc(user), req)
+                        .await
+                }
+                _ => tonic::Status::unimplemented("Not part of the stub").to_http(),
+            })
+        })
+    }
+}
+
+async fn start_stub(address: &str, user: GrpcUser) -> Result<()> {
+    let stub = StubServer { user };
+    let server = hyper::Server::bind(&address.parse()?)
+        .http2_only(true)
+        .serve(hyper::service::make_service_fn(move |_| {
+            let stub = stub.clone();
+            async move { Ok::<_, Infallible>(stub) }
+        }));
+    tokio::spawn(server);
+    wait_for(address).await;
+    Ok(())
+}
+
+fn config(address: &str, grpc_address: &str, aruna_url: &str) -> Result<Config> {
+    let root = std::env::temp_dir().join("credentials");
+    Ok(toml::from_str(&format!(
+        r#"
+        rules = []
+
+        [proxy]
+        endpoint_id = "01J4C2N8D5F7G9H3K6M1P0Q4RS"
+        private_key = "MC4CAQAwBQYDK2VwBCIEIM/FI+bYw+auSKGyGqeISRIEjofvZV/lbK7QL1wkuCey"
+        public_key = "MCowBQYDK2VwAyEAnouQBh4GHPCD/k85VIzPyCdOijVg2qlzt2TELwTMy4c="
+        serial = 1337
+        enable_ingest = false
+        admin_ids = []
+        aruna_url = "{aruna_url}"
+        grpc_server = "{grpc_address}"
+        remote_synced = false
+
+        [frontend]
+        server = "{address}"
+        hostname = "{address}"
+
+        [backend.filesystem]
+        root_path = "{}"
+        encryption = false
+        compression = false
+        backend_scheme = "s3://{{{{PROJECT_NAME}}}}/{{{{OBJECT_NAME}}}}"
+        "#,
+        root.display()
+    ))?)
+}
+
+fn free_address() -> Result<String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.to_string())
+}
+
+async fn wait_for(address: &str) {
+    while TcpStream::connect(address).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Aruna token of the user, signed with the server key
+fn token(user_id: DieselUlid) -> Result<String> {
+    let key = EncodingKey::from_ed_pem(include_bytes!("test.pem"))?;
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(SERVER_KID.to_string());
+    let claims = serde_json::json!({
+        "iss": "aruna",
+        "sub": user_id.to_string(),
+        "exp": chrono::Utc::now().timestamp() + 600,
+        "aud": "aruna",
+    });
+    Ok(jsonwebtoken::encode(&header, &claims, &key)?)
+}
+
+fn s3_client(address: &str, access_key: String, secret_key: String) -> aws_sdk_s3::Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .endpoint_url(format!("http://{address}"))
+        .credentials_provider(Credentials::new(access_key, secret_key, None, None, "test"))
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+#[tokio::test]
+async fn test_create_use_revoke_credentials() -> Result<()> {
+    let (address, grpc_address, stub_address) = (free_address()?, free_address()?, free_address()?);
+
+    let project = Object::initialize_now("bucket".to_string(), ObjectType::Project, None);
+    let user_id = DieselUlid::generate();
+    start_stub(
+        &stub_address,
+        GrpcUser {
+            id: user_id.to_string(),
+            attributes: Some(UserAttributes {
+                personal_permissions: vec![Permission {
+                    permission_level: PermissionLevel::Admin as i32,
+                    resource_id: Some(ResourceId::ProjectId(project.id.to_string())),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let proxy = DataProxyBuilder::new()
+        .config(config(
+            &address,
+            &grpc_address,
+            &format!("http://{stub_address}"),
+        )?)
+        .build()
+        .await?;
+    // Resources are usually synced from the server
+    proxy.cache().upsert_object(project).await?;
+    let tasks = proxy.into_tasks();
+    tokio::spawn(tasks.s3.expect("frontend configured"));
+    tokio::spawn(tasks.grpc);
+    wait_for(&address).await;
+    wait_for(&grpc_address).await;
+
+    let mut user_service =
+        DataproxyUserServiceClient::connect(format!("http://{grpc_address}")).await?;
+    let authorization = MetadataValue::try_from(format!("Bearer {}", token(user_id)?))?;
+
+    // Create
+    let mut request = tonic::Request::new(GetCredentialsRequest {});
+    request
+        .metadata_mut()
+        .insert("authorization", authorization.clone());
+    let credentials = user_service.get_credentials(request).await?.into_inner();
+    assert!(!credentials.secret_key.is_empty());
+    let client = s3_client(&address, credentials.access_key, credentials.secret_key);
+
+    // Use
+    let listed = client.list_objects_v2().bucket("bucket").send().await?;
+    assert_eq!(listed.name(), Some("bucket"));
+
+    // Revoke
+    let mut request = tonic::Request::new(RevokeCredentialsRequest {});
+    request
+        .metadata_mut()
+        .insert("authorization", authorization);
+    user_service.revoke_credentials(request).await?;
+
+    // Denied on the next request, without waiting for any cache expiry
+    let error = client
+        .list_objects_v2()
+        .bucket("bucket")
+        .send()
+        .await
+        .expect_err("revoked credentials are denied");
+    assert_eq!(
+        error
+            .raw_response()
+            .map(|response| response.status().as_u16()),
+        Some(403)
+    );
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! Two dataproxies embedded into the same process, each serves signed S3 requests with
+//! its own config and in-memory backend
+mod common;
+
+use anyhow::Result;
+use aos_data_proxy::MemoryBackend;
+use aws_sdk_s3::primitives::ByteStream;
+use common::start_proxy_in_memory;
+
+async fn round_trip(client: &aws_sdk_s3::Client, data: &'static [u8]) -> Result<Vec<u8>> {
+    client
+        .put_object()
+        .bucket("bucket")
+        .key("file.txt")
+        .body(ByteStream::from_static(data))
+        .send()
+        .await?;
+    let output = client
+        .get_object()
+        .bucket("bucket")
+        .key("file.txt")
+        .send()
+        .await?;
+    Ok(output.body.collect().await?.into_bytes().to_vec())
+}
+
+#[tokio::test]
+async fn test_embedded_proxies_round_trip() -> Result<()> {
+    let (first_backend, second_backend) = (MemoryBackend::new(), MemoryBackend::new());
+    let first = start_proxy_in_memory("01H81W0ZMB54YEP5711Q2BK46V", first_backend.clone()).await?;
+    let second =
+        start_proxy_in_memory("01HVTJ6ZVWRVMP2S07F8N8V8YE", second_backend.clone()).await?;
+
+    let (a, b) = tokio::join!(
+        round_trip(&first, b"stored by the first proxy"),
+        round_trip(&second, b"stored by the second proxy")
+    );
+    assert_eq!(a?, b"stored by the first proxy");
+    assert_eq!(b?, b"stored by the second proxy");
+
+    // Every proxy only wrote to its own backend
+    assert_eq!(first_backend.object_count(), 1);
+    assert_eq!(second_backend.object_count(), 1);
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Uploads with `Expect: 100-continue` and an invalid credential are rejected before the
+//! proxy asks for the body
+use anyhow::Result;
+use aos_data_proxy::{BackendOperation, Config, DataProxyBuilder, MemoryBackend};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const BODY_LEN: usize = 256 * 1024 * 1024;
+const CHUNK: usize = 1024 * 1024;
+/// Upper bound of the bytes accepted by the socket buffers of client and server
+const MAX_ACCEPTED: usize = 32 * 1024 * 1024;
+
+fn config(address: &str) -> Result<Config> {
+    let root = std::env::temp_dir().join("expect_continue");
+    Ok(toml::from_str(&format!(
+        r#"
+        rules = []
+
+        [proxy]
+        endpoint_id = "01H81W0ZMB54YEP5711Q2BK46V"
+        private_key = "MC4CAQAwBQYDK2VwBCIEIM/FI+bYw+auSKGyGqeISRIEjofvZV/lbK7QL1wkuCey"
+        public_key = "MCowBQYDK2VwAyEAnouQBh4GHPCD/k85VIzPyCdOijVg2qlzt2TELwTMy4c="
+        serial = 1337
+        enable_ingest = false
+        admin_ids = []
+        grpc_server = "127.0.0.1:0"
+        remote_synced = false
+
+        [frontend]
+        server = "{address}"
+        hostname = "{address}"
+
+        [backend.filesystem]
+        root_path = "{}"
+        encryption = false
+        compression = false
+        backend_scheme = "s3://{{{{PROJECT_NAME}}}}/{{{{OBJECT_NAME}}}}"
+        "#,
+        root.display()
+    ))?)
+}
+
+fn free_address() -> Result<String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.to_string())
+}
+
+/// Reads the response head, fails if the proxy waits for the body instead
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buf)).await??;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).to_string())
+}
+
+#[tokio::test]
+async fn test_rejected_upload_is_not_read() -> Result<()> {
+    let address = free_address()?;
+    let backend = MemoryBackend::new();
+    let proxy = DataProxyBuilder::new()
+        .config(config(&address)?)
+        .backend(Box::new(backend.clone()))
+        .build()
+        .await?;
+    let s3 = proxy.into_tasks().s3.expect("frontend configured");
+    tokio::spawn(s3);
+
+    let mut stream = loop {
+        match TcpStream::connect(&address).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    };
+
+    // Signed payload, s3s would read the whole body to verify the signature
+    let request = format!(
+        "PUT /bucket/large.bin HTTP/1.1\r\n\
+         Host: {address}\r\n\
+         Authorization: AWS4-HMAC-SHA256 Credential=UNKNOWNACCESSKEY/20240101/us-east-1/s3/aws4_request, \
+         SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={sig}\r\n\
+         x-amz-date: 20240101T000000Z\r\n\
+         x-amz-content-sha256: {hash}\r\n\
+         Content-Length: {BODY_LEN}\r\n\
+         Expect: 100-continue\r\n\r\n",
+        sig = "0".repeat(64),
+        hash = "1".repeat(64),
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Answered without a single body byte
+    let head = read_head(&mut stream).await?;
+    assert!(!head.contains("100 Continue"), "{head}");
+    assert!(head.starts_with("HTTP/1.1 403"), "{head}");
+
+    // Clients that send the body anyway only fill the socket buffers
+    let chunk = vec![0u8; CHUNK];
+    let mut accepted = 0;
+    while accepted < BODY_LEN {
+        match tokio::time::timeout(Duration::from_secs(2), stream.write_all(&chunk)).await {
+            Ok(Ok(())) => accepted += CHUNK,
+            _ => break,
+        }
+    }
+    assert!(accepted < MAX_ACCEPTED, "{accepted} bytes were accepted");
+
+    // Rejected uploads do not touch the backend
+    for operation in [
+        BackendOperation::InitializeLocation,
+        BackendOperation::CreateBucket,
+        BackendOperation::Put,
+        BackendOperation::InitMultipart,
+        BackendOperation::UploadPart,
+    ] {
+        assert_eq!(backend.calls(operation), 0, "{operation:?}");
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Keys with multiple segments round-trip through PutObject, ListObjectsV2 and
+//! GetObject, auto-created parents are removed with their last object
+mod common;
+
+use anyhow::Result;
+use aos_data_proxy::{Cache, Object, ObjectType, TypedRelation};
+use aws_sdk_s3::primitives::ByteStream;
+use common::{start_proxy_with_cache, Keys};
+
+/// Initializing object below `parent`, usually synced from the server
+async fn initialize(
+    cache: &Cache,
+    name: &str,
+    object_type: ObjectType,
+    parent: TypedRelation,
+) -> Result<Object> {
+    let object = Object::initialize_now(name.to_string(), object_type, Some(parent));
+    cache.upsert_object(object.clone()).await?;
+    Ok(object)
+}
+
+async fn round_trip(client: &aws_sdk_s3::Client, key: &str, data: &'static [u8]) -> Result<()> {
+    client
+        .put_object()
+        .bucket("bucket")
+        .key(key)
+        .body(ByteStream::from_static(data))
+        .send()
+        .await?;
+
+    let listed = client.list_objects_v2().bucket("bucket").send().await?;
+    let keys = listed
+        .contents()
+        .iter()
+        .filter_map(|object| object.key())
+        .collect::<Vec<_>>();
+    assert!(keys.contains(&key), "{key} not in {keys:?}");
+
+    let output = client.get_object().bucket("bucket").key(key).send().await?;
+    assert_eq!(output.body.collect().await?.into_bytes().as_ref(), data);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keys_below_dataset() -> Result<()> {
+    let (client, cache) =
+        start_proxy_with_cache("01J3Q9V6ZK2M8T4R7N1W5X0HBD", Keys::default()).await?;
+    let project = cache.get_path("bucket").expect("bucket");
+    let collection = initialize(
+        &cache,
+        "col",
+        ObjectType::Collection,
+        TypedRelation::Project(project),
+    )
+    .await?;
+    let dataset = initialize(
+        &cache,
+        "ds",
+        ObjectType::Dataset,
+        TypedRelation::Collection(collection.id),
+    )
+    .await?;
+    // Segments below the dataset stay part of the object name
+    let object = initialize(
+        &cache,
+        "sub/dir/file.txt",
+        ObjectType::Object,
+        TypedRelation::Dataset(dataset.id),
+    )
+    .await?;
+
+    round_trip(&client, "col/ds/sub/dir/file.txt", b"nested").await?;
+    assert_eq!(
+        cache.get_path("bucket/col/ds/sub/dir/file.txt"),
+        Some(object.id)
+    );
+    let listed = client
+        .list_objects_v2()
+        .bucket("bucket")
+        .prefix("col/ds/sub/")
+        .delimiter("/")
+        .send()
+        .await?;
+    let prefixes = listed
+        .common_prefixes()
+        .iter()
+        .filter_map(|prefix| prefix.prefix())
+        .collect::<Vec<_>>();
+    assert_eq!(prefixes, vec!["col/ds/sub/dir/"]);
+
+    // The auto-created collection and dataset are removed with their last object
+    client
+        .delete_object()
+        .bucket("bucket")
+        .key("col/ds/sub/dir/file.txt")
+        .send()
+        .await?;
+    assert_eq!(cache.get_path("bucket/col/ds"), None);
+    assert_eq!(cache.get_path("bucket/col"), None);
+    assert_eq!(cache.get_path("bucket"), Some(project));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_flattened_keys() -> Result<()> {
+    let (client, cache) =
+        start_proxy_with_cache("01J3Q9W2B7C4F8H1K5M9P3S6VY", Keys { flatten: true }).await?;
+    let project = cache.get_path("bucket").expect("bucket");
+    // Missing segments are not created as collection/dataset but kept in the name
+    let object = initialize(
+        &cache,
+        "a/b/c.txt",
+        ObjectType::Object,
+        TypedRelation::Project(project),
+    )
+    .await?;
+
+    round_trip(&client, "a/b/c.txt", b"flattened").await?;
+    assert_eq!(cache.get_path("bucket/a/b/c.txt"), Some(object.id));
+    assert_eq!(cache.get_path("bucket/a"), None);
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Listings and HeadObject report the size of the uploaded data, not the size of the
+//! compressed and encrypted data on disk
+mod common;
+
+use anyhow::Result;
+use aws_sdk_s3::primitives::ByteStream;
+use common::{start_proxy, Storage};
+
+/// Compressible data over multiple pithos chunks
+const LEN: usize = 3 * 1024 * 1024 + 17;
+
+#[tokio::test]
+async fn test_listed_size_of_transformed_upload() -> Result<()> {
+    let client = start_proxy(
+        "01J2F6V7E4R2AXWZ5S0G8P3M1Q",
+        Storage {
+            encryption: true,
+            compression: true,
+        },
+    )
+    .await?;
+
+    let data = (0..LEN).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+    client
+        .put_object()
+        .bucket("bucket")
+        .key("file.txt")
+        .body(ByteStream::from(data.clone()))
+        .send()
+        .await?;
+
+    let listed = client.list_objects_v2().bucket("bucket").send().await?;
+    let objects = listed.contents();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].key(), Some("file.txt"));
+    assert_eq!(objects[0].size(), Some(LEN as i64));
+
+    let head = client
+        .head_object()
+        .bucket("bucket")
+        .key("file.txt")
+        .send()
+        .await?;
+    assert_eq!(head.content_length(), Some(LEN as i64));
+
+    // The stored data is transformed, the download is not
+    let output = client
+        .get_object()
+        .bucket("bucket")
+        .key("file.txt")
+        .send()
+        .await?;
+    assert_eq!(output.body.collect().await?.into_bytes().as_ref(), &data[..]);
+    Ok(())
+}
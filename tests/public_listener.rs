@@ -0,0 +1,82 @@
+//! Policy matrix of a `public_read_only` listener next to the regular frontend
+mod common;
+
+use anyhow::Result;
+use aruna_rust_api::api::storage::models::v2::{KeyValue, KeyValueVariant};
+use aws_sdk_s3::primitives::ByteStream;
+use common::start_proxy_with_public_listener;
+use reqwest::StatusCode;
+
+const CACHE_CONTROL: &str = "public, max-age=60";
+
+#[tokio::test]
+async fn test_public_read_only_listener() -> Result<()> {
+    let (client, cache, public_address) =
+        start_proxy_with_public_listener("01J2FB4ZQ7M3N8V0K6T5R1X9WD").await?;
+    // Project attributes are usually synced from the server
+    let mut project = cache
+        .get_full_resource_by_path("bucket")
+        .await
+        .expect("bucket");
+    project.key_values.push(KeyValue {
+        key: "app.aruna-storage.org/cache-control".to_string(),
+        value: CACHE_CONTROL.to_string(),
+        variant: KeyValueVariant::Label as i32,
+    });
+    cache.upsert_object(project).await?;
+    for (key, data) in [("file.txt", "private data"), ("public.txt", "public data")] {
+        client
+            .put_object()
+            .bucket("bucket")
+            .key(key)
+            .body(ByteStream::from_static(data.as_bytes()))
+            .send()
+            .await?;
+    }
+
+    let public = reqwest::Client::new();
+    let url = |key: &str| format!("http://{public_address}/bucket/{key}");
+
+    // Writes are rejected, even for existing keys
+    let response = public
+        .put(url("public.txt"))
+        .body("overwritten")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let response = public.delete(url("public.txt")).send().await?;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    // Private objects are hidden
+    let response = public.get(url("file.txt")).send().await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!response.text().await?.contains("private data"));
+
+    // Public objects are served with the cache headers of the project
+    let response = public.get(url("public.txt")).send().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("cache-control")
+            .map(|value| value.to_str())
+            .transpose()?,
+        Some(CACHE_CONTROL)
+    );
+    assert_eq!(response.text().await?, "public data");
+
+    // The regular frontend still serves the unchanged data to the owner, without
+    // the cache headers of the public listener
+    let output = client
+        .get_object()
+        .bucket("bucket")
+        .key("public.txt")
+        .send()
+        .await?;
+    assert_eq!(output.cache_control(), None);
+    assert_eq!(
+        output.body.collect().await?.into_bytes().as_ref(),
+        b"public data"
+    );
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! SIGTERM stops a running proxy without leaving in-flight multipart uploads behind
+#![cfg(unix)]
+mod common;
+
+use anyhow::Result;
+use aws_sdk_s3::primitives::ByteStream;
+use common::run_proxy;
+use std::process::Command;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+#[tokio::test]
+async fn test_sigterm_aborts_inflight_uploads() -> Result<()> {
+    // Keeps the test process alive, the proxy listens for the same signal
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let (client, cache, proxy) = run_proxy("01J3H5C1Q8W0Y6N2T4D7R9K3MZ").await?;
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket("bucket")
+        .key("file.txt")
+        .send()
+        .await?;
+    let upload_id = upload.upload_id().expect("upload id").to_string();
+    client
+        .upload_part()
+        .bucket("bucket")
+        .key("file.txt")
+        .upload_id(&upload_id)
+        .part_number(1)
+        .body(ByteStream::from_static(b"never completed"))
+        .send()
+        .await?;
+
+    let object_id = cache.get_path("bucket/file.txt").expect("object");
+    let location = cache
+        .get_location(&object_id)
+        .await
+        .expect("upload location");
+    assert_eq!(location.upload_id.as_deref(), Some(upload_id.as_str()));
+
+    let status = Command::new("kill")
+        .args(["-TERM", &std::process::id().to_string()])
+        .status()?;
+    assert!(status.success());
+    sigterm.recv().await;
+
+    tokio::time::timeout(Duration::from_secs(30), proxy).await???;
+    // Without persistence the upload cannot be continued, nothing of it is left
+    assert!(cache.get_location(&object_id).await.is_none());
+    assert!(cache.all_multipart_uploads().is_empty());
+    assert!(cache.get_parts(&upload_id).is_empty());
+    Ok(())
+}